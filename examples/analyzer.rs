@@ -0,0 +1,67 @@
+//! Captures both directions of a serial link and writes them to standard output.
+//!
+//! Connect one port to each direction of the link, e.g. with an RS-232 tap cable, and run:
+//!
+//! ```text
+//! cargo run --example analyzer /dev/ttyUSB0 /dev/ttyUSB1 9600 pcapng > link.pcapng
+//! ```
+//!
+//! The first port captures the data transmitted by the device being analyzed and the second
+//! captures the data it receives. The format is `text` (the default), `json`, `hexdump`, or `pcapng`.
+
+extern crate serial;
+
+use std::env;
+use std::io;
+
+use serial::prelude::*;
+use serial::analyzer::{Analyzer,CaptureWriter,Event,Format};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 3 {
+        eprintln!("usage: {} <tx port> <rx port> [baud] [text|json|hexdump|pcapng]", args[0]);
+        return;
+    }
+
+    let baud: usize = args.get(3).map_or(9600, |baud| baud.parse().unwrap());
+
+    let format = match args.get(4).map(String::as_str) {
+        None | Some("text") => Format::Text,
+        Some("json") => Format::JsonLines,
+        Some("hexdump") => Format::HexDump,
+        Some("pcapng") => Format::Pcapng,
+        Some(format) => {
+            eprintln!("unknown format: {}", format);
+            return;
+        }
+    };
+
+    let mut options = serial::OpenOptions::new();
+    options.access(serial::Access::ReadOnly).dtr(false).rts(false);
+
+    let open = |name: &str| {
+        let mut port = options.open(name).unwrap();
+        port.configure(&serial::PortSettings::standard(baud)).unwrap();
+        port
+    };
+
+    let analyzer = Analyzer::new(open(&args[1]), open(&args[2]), Default::default());
+
+    let stdout = io::stdout();
+    let mut capture = CaptureWriter::new(stdout.lock(), format).unwrap();
+
+    for event in analyzer.receiver().iter() {
+        match event {
+            Event::Data(record) => {
+                capture.write_record(&record).unwrap();
+                capture.flush().unwrap();
+            },
+            Event::Stopped(direction, err) => {
+                eprintln!("{} stopped: {}", direction, err);
+                break;
+            }
+        }
+    }
+}
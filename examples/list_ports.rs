@@ -0,0 +1,52 @@
+//! Lists the serial ports available on the system.
+//!
+//! ```text
+//! cargo run --example list_ports [--all] [--json]
+//! ```
+//!
+//! `--all` includes built-in UARTs and pseudo-terminals, and `--json` prints the ports as a JSON
+//! array for consumption by scripts, e.g., `cargo run --example list_ports -- --json | jq`.
+
+extern crate serial;
+
+use std::env;
+
+use serial::enumerate::{self,EnumerateOptions,PortType};
+
+fn main() {
+    let mut options = EnumerateOptions::new();
+    let mut json = false;
+
+    for arg in env::args().skip(1) {
+        match &arg[..] {
+            "--all" => { options.platform(true).pseudo(true); },
+            "--json" => json = true,
+            _ => {
+                eprintln!("usage: list_ports [--all] [--json]");
+                return;
+            }
+        }
+    }
+
+    let ports = match options.available_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return;
+        }
+    };
+
+    if json {
+        println!("{}", enumerate::to_json(&ports));
+        return;
+    }
+
+    for port in ports {
+        match port.port_type {
+            PortType::Usb(ref usb) => {
+                println!("{} (USB {:04x}:{:04x} {})", port.path.display(), usb.vid, usb.pid, usb.product.as_ref().map_or("", String::as_str));
+            },
+            ref port_type => println!("{} ({:?})", port.path.display(), port_type)
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Compares the CPU time spent reading a fast stream with and without read batching.
+//!
+//! Wire the port's TX to its RX and run, e.g.:
+//!
+//! ```text
+//! cargo run --release --example read_batching /dev/ttyUSB0 3000000
+//! ```
+//!
+//! The port is fed continuously from a second thread while the main thread reads for five seconds
+//! in each mode and reports the number of reads and the CPU time used by the process.
+
+extern crate libc;
+extern crate serial;
+
+#[cfg(unix)]
+fn main() {
+    use std::env;
+    use std::io::prelude::*;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool,Ordering};
+    use std::thread;
+    use std::time::{Duration,Instant};
+
+    use serial::prelude::*;
+    use serial::posix::TTYPort;
+
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        eprintln!("usage: {} <port> [baud]", args[0]);
+        return;
+    }
+
+    let baud: usize = args.get(2).map_or(3_000_000, |baud| baud.parse().unwrap());
+
+    let mut port = TTYPort::open(Path::new(&args[1])).unwrap();
+    port.configure(&serial::PortSettings::standard(baud)).unwrap();
+    port.set_timeout(Duration::from_secs(1)).unwrap();
+
+    for &(name, size, delay) in &[("unbatched", 0, Duration::from_millis(0)), ("batched", 4096, Duration::from_millis(1))] {
+        port.set_read_batching(size, delay);
+        port.discard_input().unwrap();
+
+        let stop = AtomicBool::new(false);
+        let port = &port;
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let data = vec![0x55; 4096];
+                let mut writer = port;
+
+                while !stop.load(Ordering::Relaxed) {
+                    writer.write_all(&data).unwrap();
+                }
+            });
+
+            let mut reader = port;
+            let mut buf = vec![0; 4096];
+            let mut reads = 0u64;
+            let mut bytes = 0u64;
+
+            let start = Instant::now();
+            let cpu_start = cpu_time();
+
+            while start.elapsed() < Duration::from_secs(5) {
+                bytes += reader.read(&mut buf).unwrap() as u64;
+                reads += 1;
+            }
+
+            let cpu = cpu_time() - cpu_start;
+            stop.store(true, Ordering::Relaxed);
+
+            println!("{:>9}: {} bytes in {} reads ({:.1} bytes/read), {:.3} s CPU", name, bytes, reads, bytes as f64 / reads as f64, cpu.as_secs_f64());
+        });
+    }
+
+    fn cpu_time() -> Duration {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+
+        let user = Duration::new(usage.ru_utime.tv_sec as u64, usage.ru_utime.tv_usec as u32 * 1000);
+        let system = Duration::new(usage.ru_stime.tv_sec as u64, usage.ru_stime.tv_usec as u32 * 1000);
+
+        user + system
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("read batching is only available on POSIX systems");
+}
@@ -3,7 +3,6 @@ extern crate serial;
 use std::env;
 use std::time::Duration;
 
-use std::io::prelude::*;
 use serial::prelude::*;
 
 const SETTINGS: serial::PortSettings = serial::PortSettings {
@@ -24,16 +23,16 @@ fn main() {
 }
 
 fn interact<T: SerialPort>(port: &mut T) -> serial::Result<()> {
-    try!(port.configure(&SETTINGS));
-    try!(port.set_timeout(Duration::from_secs(1)));
+    port.configure(&SETTINGS)?;
+    port.set_timeout(Duration::from_secs(1))?;
 
     let mut buf: Vec<u8> = (0..255).collect();
 
     println!("writing bytes");
-    try!(port.write(&buf[..]));
+    port.write_all(&buf[..])?;
 
     println!("reading bytes");
-    try!(port.read(&mut buf[..]));
+    port.read_exact(&mut buf[..])?;
 
     Ok(())
 }
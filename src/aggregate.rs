@@ -0,0 +1,264 @@
+//! Merging the data received from many ports into one stream.
+//!
+//! An `Aggregator` reads from each of its ports on a background thread and delivers the received
+//! data as events tagged with the port's ID. This suits data loggers on multi-drop links and test
+//! rigs that monitor a rack of devices:
+//!
+//! ```no_run
+//! use serial::aggregate::{Aggregator,Event};
+//!
+//! let mut aggregator = Aggregator::new(Default::default());
+//! aggregator.add("left", serial::open("/dev/ttyUSB0").unwrap());
+//! aggregator.add("right", serial::open("/dev/ttyUSB1").unwrap());
+//!
+//! for event in aggregator.receiver().iter() {
+//!     match event {
+//!         Event::Data(id, data) => println!("{}: {:?}", id, data),
+//!         Event::Stopped(id, err) => println!("{} stopped: {}", id, err)
+//!     }
+//! }
+//! ```
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,SyncSender,TrySendError};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::shutdown;
+
+/// An event delivered by an `Aggregator`.
+#[derive(Debug)]
+pub enum Event<I> {
+    /// Data was received from the port with the given ID.
+    Data(I, Vec<u8>),
+
+    /// Reading from the port with the given ID failed, so its thread has stopped.
+    ///
+    /// The port is returned by `Aggregator::close()`.
+    Stopped(I, ::Error)
+}
+
+/// Options for an `Aggregator`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct AggregateOptions {
+    /// Maximum number of bytes delivered in each event.
+    pub chunk_size: usize,
+
+    /// Maximum number of events buffered before reading stops until the receiver catches up.
+    pub capacity: usize,
+
+    /// Maximum time that a thread waits for data before checking whether it should stop.
+    ///
+    /// This bounds the latency of closing the aggregator.
+    pub poll_interval: Duration
+}
+
+impl Default for AggregateOptions {
+    fn default() -> Self {
+        AggregateOptions {
+            chunk_size: 4096,
+            capacity: 256,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// Reads from many ports concurrently and delivers their data through a single receiver.
+///
+/// Each port is read on its own thread. The threads are stopped when the aggregator is closed or
+/// dropped.
+pub struct Aggregator<I, P> {
+    options: AggregateOptions,
+    sender: SyncSender<Event<I>>,
+    receiver: Receiver<Event<I>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<(I, JoinHandle<P>)>
+}
+
+impl<I, P> Aggregator<I, P>
+    where I: Clone + Send + 'static,
+          P: SerialPort + Send + 'static
+{
+    /// Creates an aggregator with no ports.
+    pub fn new(options: AggregateOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(options.capacity);
+
+        Aggregator {
+            options,
+            sender,
+            receiver,
+            stop: Arc::new(AtomicBool::new(false)),
+            threads: Vec::new()
+        }
+    }
+
+    /// Starts reading from `port`, whose events are tagged with `id`.
+    pub fn add(&mut self, id: I, port: P) {
+        let thread = {
+            let id = id.clone();
+            let sender = self.sender.clone();
+            let stop = self.stop.clone();
+            let options = self.options;
+
+            thread::spawn(move || run(id, port, &sender, &stop, &options))
+        };
+
+        self.threads.push((id, thread));
+    }
+
+    /// Returns the number of ports.
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Returns `true` if no ports have been added.
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// Returns the receiver for events from all ports.
+    ///
+    /// The receiver stays connected until the aggregator is closed, even if every port has
+    /// stopped.
+    pub fn receiver(&self) -> &Receiver<Event<I>> {
+        &self.receiver
+    }
+
+    /// Stops all threads and returns the ports with their IDs, in the order they were added.
+    ///
+    /// Events that haven't been received are discarded.
+    ///
+    /// ## Errors
+    ///
+    /// A port's result is an error of kind `Io(Other)` if its thread panicked.
+    pub fn close(mut self) -> Vec<(I, ::Result<P>)> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        // unblocks threads waiting for room in the channel
+        while self.receiver.try_recv().is_ok() {}
+
+        self.threads.drain(..).map(|(id, thread)| (id, shutdown::join_until(thread, None, "I/O thread"))).collect()
+    }
+
+    /// Stops all threads like `close()`, waiting at most `timeout` for them to stop.
+    ///
+    /// ## Errors
+    ///
+    /// A port's result is an error of kind `Io(TimedOut)` if its thread didn't stop in time, or of kind
+    /// `Io(Other)` if its thread panicked.
+    pub fn shutdown(mut self, timeout: Duration) -> Vec<(I, ::Result<P>)> {
+        let deadline = Instant::now() + timeout;
+        self.stop.store(true, Ordering::Relaxed);
+
+        // unblocks threads waiting for room in the channel
+        while self.receiver.try_recv().is_ok() {}
+
+        self.threads.drain(..).map(|(id, thread)| (id, shutdown::join_until(thread, Some(deadline), "I/O thread"))).collect()
+    }
+}
+
+impl<I, P> Drop for Aggregator<I, P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn run<I: Clone, P: SerialPort>(id: I, mut port: P, sender: &SyncSender<Event<I>>, stop: &AtomicBool, options: &AggregateOptions) -> P {
+    let mut buf = vec![0; options.chunk_size.max(1)];
+
+    while !stop.load(Ordering::Relaxed) {
+        let len = match port.read_with_timeout(&mut buf, options.poll_interval) {
+            Ok(0) => continue,
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                deliver(Event::Stopped(id, ::Error::from(err)), sender, stop, options);
+                break;
+            }
+        };
+
+        deliver(Event::Data(id.clone(), buf[..len].to_vec()), sender, stop, options);
+    }
+
+    port
+}
+
+/// Sends an event, waiting while the channel is full unless the aggregator is stopped.
+fn deliver<I>(mut event: Event<I>, sender: &SyncSender<Event<I>>, stop: &AtomicBool, options: &AggregateOptions) {
+    loop {
+        match sender.try_send(event) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+            Err(TrySendError::Full(rejected)) => {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                event = rejected;
+                thread::sleep(options.poll_interval);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{Aggregator,Event};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn aggregator_tags_data_with_port_id() {
+        let mut a = ScriptedDevice::new();
+        a.push_input(b"one");
+
+        let mut b = ScriptedDevice::new();
+        b.push_input(b"two");
+
+        let mut aggregator = Aggregator::new(Default::default());
+        aggregator.add('a', a);
+        aggregator.add('b', b);
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match aggregator.receiver().recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::Data(id, data) => received.push((id, data)),
+                Event::Stopped(id, err) => panic!("{} stopped: {}", id, err)
+            }
+        }
+        received.sort();
+
+        assert_eq!(received, vec![('a', b"one".to_vec()), ('b', b"two".to_vec())]);
+
+        let ports = aggregator.close();
+        assert_eq!(ports.len(), 2);
+        assert!(ports.iter().all(|(_, port)| port.is_ok()));
+    }
+
+    #[test]
+    fn aggregator_reports_stopped_port() {
+        let mut device = ScriptedDevice::new();
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let mut aggregator = Aggregator::new(Default::default());
+        aggregator.add(7, device);
+
+        match aggregator.receiver().recv_timeout(Duration::from_secs(5)).unwrap() {
+            Event::Stopped(id, err) => {
+                assert_eq!(id, 7);
+                assert!(err.is_disconnection());
+            },
+            Event::Data(..) => panic!("unexpected data")
+        }
+
+        let (id, port) = aggregator.close().pop().unwrap();
+        assert_eq!(id, 7);
+        assert!(port.is_ok());
+    }
+}
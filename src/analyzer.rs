@@ -0,0 +1,687 @@
+//! Capturing both directions of a serial link as a protocol analyzer.
+//!
+//! An `Analyzer` reads from two ports, each tapping one direction of a link, and delivers the
+//! received data as timestamped records labelled with their direction. A `CaptureWriter` writes
+//! the records as text, JSON lines, a hex dump, or a pcapng file that can be opened in Wireshark:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use serial::analyzer::{Analyzer,CaptureWriter,Event,Format};
+//!
+//! let mut options = serial::OpenOptions::new();
+//! options.access(serial::Access::ReadOnly).dtr(false).rts(false);
+//!
+//! let tx = options.open("/dev/ttyUSB0").unwrap();
+//! let rx = options.open("/dev/ttyUSB1").unwrap();
+//!
+//! let analyzer = Analyzer::new(tx, rx, Default::default());
+//! let mut capture = CaptureWriter::new(File::create("link.pcapng").unwrap(), Format::Pcapng).unwrap();
+//!
+//! for event in analyzer.receiver().iter() {
+//!     match event {
+//!         Event::Data(record) => capture.write_record(&record).unwrap(),
+//!         Event::Stopped(direction, err) => println!("{} stopped: {}", direction, err)
+//!     }
+//! }
+//! ```
+//!
+//! Each record is timestamped as soon as the read that received it returns, so the timestamps are
+//! only as precise as the port's read latency. Records are delivered in the order they were read,
+//! which may differ from the order of their timestamps by up to that latency.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,SyncSender,TrySendError};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
+
+use ::SerialPort;
+use ::shutdown;
+use ::hexdump::HexDump;
+
+pub use ::hexdump::Direction;
+
+/// Data received from one direction of a link.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Record {
+    /// The time the data was read.
+    pub timestamp: SystemTime,
+
+    /// The direction the data was sent in.
+    pub direction: Direction,
+
+    /// The data.
+    pub data: Vec<u8>
+}
+
+/// An event delivered by an `Analyzer`.
+#[derive(Debug)]
+pub enum Event {
+    /// Data was received.
+    Data(Record),
+
+    /// Reading the given direction failed, so its thread has stopped.
+    Stopped(Direction, ::Error)
+}
+
+/// Options for an `Analyzer`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct AnalyzerOptions {
+    /// Maximum number of bytes in each record.
+    pub chunk_size: usize,
+
+    /// Maximum number of events buffered before reading stops until the receiver catches up.
+    pub capacity: usize,
+
+    /// Maximum time that a thread waits for data before checking whether it should stop.
+    pub poll_interval: Duration
+}
+
+impl Default for AnalyzerOptions {
+    fn default() -> Self {
+        AnalyzerOptions {
+            chunk_size: 4096,
+            capacity: 1024,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// Reads both directions of a link and merges them into one stream of records.
+///
+/// Each port is read on its own thread. The threads are stopped when the analyzer is closed or
+/// dropped.
+pub struct Analyzer<P> {
+    receiver: Receiver<Event>,
+    stop: Arc<AtomicBool>,
+    tx: Option<JoinHandle<P>>,
+    rx: Option<JoinHandle<P>>
+}
+
+impl<P: SerialPort + Send + 'static> Analyzer<P> {
+    /// Starts reading `tx`, which taps the data transmitted by the device being analyzed, and
+    /// `rx`, which taps the data it receives.
+    pub fn new(tx: P, rx: P, options: AnalyzerOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(options.capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let spawn = |direction, port| {
+            let sender = sender.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || run(direction, port, &sender, &stop, &options))
+        };
+
+        let tx = spawn(Direction::Tx, tx);
+        let rx = spawn(Direction::Rx, rx);
+
+        Analyzer {
+            receiver,
+            stop,
+            tx: Some(tx),
+            rx: Some(rx)
+        }
+    }
+
+    /// Returns the receiver for records from both directions.
+    ///
+    /// The receiver stays connected until the analyzer is closed, even if both ports have
+    /// stopped.
+    pub fn receiver(&self) -> &Receiver<Event> {
+        &self.receiver
+    }
+
+    /// Stops both threads and returns the TX and RX ports.
+    ///
+    /// Events that haven't been received are discarded.
+    ///
+    /// ## Errors
+    ///
+    /// A port's result is an error of kind `Io(Other)` if its thread panicked.
+    pub fn close(mut self) -> (::Result<P>, ::Result<P>) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        // unblocks threads waiting for room in the channel
+        while self.receiver.try_recv().is_ok() {}
+
+        let join = |thread: JoinHandle<P>| shutdown::join_until(thread, None, "I/O thread");
+
+        (join(self.tx.take().unwrap()), join(self.rx.take().unwrap()))
+    }
+
+    /// Stops both threads like `close()`, waiting at most `timeout` for them to stop.
+    ///
+    /// ## Errors
+    ///
+    /// A port's result is an error of kind `Io(TimedOut)` if its thread didn't stop in time, or of kind
+    /// `Io(Other)` if its thread panicked.
+    pub fn shutdown(mut self, timeout: Duration) -> (::Result<P>, ::Result<P>) {
+        let deadline = Instant::now() + timeout;
+        self.stop.store(true, Ordering::Relaxed);
+
+        // unblocks threads waiting for room in the channel
+        while self.receiver.try_recv().is_ok() {}
+
+        let join = |thread: JoinHandle<P>| shutdown::join_until(thread, Some(deadline), "I/O thread");
+        (join(self.tx.take().unwrap()), join(self.rx.take().unwrap()))
+    }
+}
+
+impl<P> Drop for Analyzer<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn run<P: SerialPort>(direction: Direction, mut port: P, sender: &SyncSender<Event>, stop: &AtomicBool, options: &AnalyzerOptions) -> P {
+    let mut buf = vec![0; options.chunk_size.max(1)];
+
+    while !stop.load(Ordering::Relaxed) {
+        let len = match port.read_with_timeout(&mut buf, options.poll_interval) {
+            Ok(0) => continue,
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                deliver(Event::Stopped(direction, ::Error::from(err)), sender, stop, options);
+                break;
+            }
+        };
+
+        let record = Record {
+            timestamp: SystemTime::now(),
+            direction,
+            data: buf[..len].to_vec()
+        };
+
+        deliver(Event::Data(record), sender, stop, options);
+    }
+
+    port
+}
+
+/// Sends an event, waiting while the channel is full unless the analyzer is stopped.
+fn deliver(mut event: Event, sender: &SyncSender<Event>, stop: &AtomicBool, options: &AnalyzerOptions) {
+    loop {
+        match sender.try_send(event) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+            Err(TrySendError::Full(rejected)) => {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                event = rejected;
+                thread::sleep(options.poll_interval);
+            }
+        }
+    }
+}
+
+/// The format of a capture.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Format {
+    /// One line per record with the timestamp, direction, data in hex, and printable data:
+    ///
+    /// ```text
+    /// 1700000000.123456 TX 48 69 0d 0a  |Hi..|
+    /// ```
+    Text,
+
+    /// One JSON object per line with the timestamp in seconds, the direction, and the data in hex:
+    ///
+    /// ```text
+    /// {"time":1700000000.123456,"direction":"tx","data":"48690d0a"}
+    /// ```
+    JsonLines,
+
+    /// A hex dump with offset, hex, and ASCII columns, as formatted by `hexdump::HexDump`. Each
+    /// record starts with its timestamp and an arrow for its direction:
+    ///
+    /// ```text
+    /// 1700000000.123456 -> 00000000  48 69 0d 0a                                       |Hi..|
+    /// ```
+    HexDump,
+
+    /// A pcapng file with one interface per direction.
+    ///
+    /// The interfaces use the `LINKTYPE_USER0` link type, as there's none for raw serial data.
+    /// Interface 0 carries TX data and interface 1 carries RX data, and each packet's flags mark
+    /// it as outbound or inbound accordingly. Timestamps have microsecond resolution.
+    Pcapng
+}
+
+/// The `LINKTYPE_USER0` link type of pcapng captures.
+pub const LINKTYPE_USER0: u16 = 147;
+
+/// Writes records to a capture file.
+pub struct CaptureWriter<W: io::Write> {
+    writer: W,
+    format: Format,
+    dump: HexDump
+}
+
+impl<W: io::Write> CaptureWriter<W> {
+    /// Starts a capture in the given format.
+    ///
+    /// For pcapng captures, the section header and interface descriptions are written
+    /// immediately.
+    pub fn new(mut writer: W, format: Format) -> io::Result<Self> {
+        if format == Format::Pcapng {
+            // section header block
+            let mut block = Vec::with_capacity(28);
+            block.extend_from_slice(&0x0a0d_0d0a_u32.to_le_bytes());
+            block.extend_from_slice(&28_u32.to_le_bytes());
+            block.extend_from_slice(&0x1a2b_3c4d_u32.to_le_bytes());
+            block.extend_from_slice(&1_u16.to_le_bytes());
+            block.extend_from_slice(&0_u16.to_le_bytes());
+            block.extend_from_slice(&(-1_i64).to_le_bytes());
+            block.extend_from_slice(&28_u32.to_le_bytes());
+
+            // interface description blocks, one for each direction
+            for _ in 0..2 {
+                block.extend_from_slice(&1_u32.to_le_bytes());
+                block.extend_from_slice(&20_u32.to_le_bytes());
+                block.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+                block.extend_from_slice(&0_u16.to_le_bytes());
+                block.extend_from_slice(&0_u32.to_le_bytes());
+                block.extend_from_slice(&20_u32.to_le_bytes());
+            }
+
+            writer.write_all(&block)?;
+        }
+
+        Ok(CaptureWriter { writer, format, dump: HexDump::new() })
+    }
+
+    /// Returns the format of the capture.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Writes a record to the capture.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let timestamp = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        match self.format {
+            Format::Text => {
+                let mut line = format!("{}.{:06} {}", timestamp.as_secs(), timestamp.subsec_micros(), record.direction);
+
+                for byte in &record.data {
+                    line.push_str(&format!(" {:02x}", byte));
+                }
+
+                line.push_str("  |");
+                line.extend(record.data.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+                line.push_str("|\n");
+
+                self.writer.write_all(line.as_bytes())
+            },
+            Format::HexDump => {
+                let lines = self.dump.format(Some(record.timestamp), Some(record.direction), &record.data);
+                self.writer.write_all(lines.as_bytes())
+            },
+            Format::JsonLines => {
+                let direction = match record.direction {
+                    Direction::Tx => "tx",
+                    Direction::Rx => "rx"
+                };
+
+                let mut line = format!("{{\"time\":{}.{:06},\"direction\":\"{}\",\"data\":\"", timestamp.as_secs(), timestamp.subsec_micros(), direction);
+
+                for byte in &record.data {
+                    line.push_str(&format!("{:02x}", byte));
+                }
+
+                line.push_str("\"}\n");
+
+                self.writer.write_all(line.as_bytes())
+            },
+            Format::Pcapng => {
+                let (interface, flags) = match record.direction {
+                    Direction::Tx => (0_u32, 2_u32),
+                    Direction::Rx => (1_u32, 1_u32)
+                };
+
+                let micros = timestamp.as_secs() * 1_000_000 + u64::from(timestamp.subsec_micros());
+                let padding = (4 - record.data.len() % 4) % 4;
+                let length = (44 + record.data.len() + padding) as u32;
+
+                // enhanced packet block with an epb_flags option
+                let mut block = Vec::with_capacity(length as usize);
+                block.extend_from_slice(&6_u32.to_le_bytes());
+                block.extend_from_slice(&length.to_le_bytes());
+                block.extend_from_slice(&interface.to_le_bytes());
+                block.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+                block.extend_from_slice(&(micros as u32).to_le_bytes());
+                block.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+                block.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+                block.extend_from_slice(&record.data);
+                block.extend_from_slice(&[0; 3][..padding]);
+                block.extend_from_slice(&2_u16.to_le_bytes());
+                block.extend_from_slice(&4_u16.to_le_bytes());
+                block.extend_from_slice(&flags.to_le_bytes());
+                block.extend_from_slice(&[0; 4]);
+                block.extend_from_slice(&length.to_le_bytes());
+
+                self.writer.write_all(&block)
+            }
+        }
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+
+/// Reads records from a capture file written by `CaptureWriter`.
+///
+/// Text, JSON lines, and pcapng captures can be read. Hex dumps can't, since they don't mark where
+/// each record ends.
+pub struct CaptureReader<R: io::BufRead> {
+    reader: R,
+    format: Format,
+    line: usize
+}
+
+impl<R: io::BufRead> CaptureReader<R> {
+    /// Starts reading a capture in the given format.
+    ///
+    /// For pcapng captures, the section header is read and checked immediately.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` for `Format::HexDump`, or of kind
+    /// `InvalidData` if a pcapng capture doesn't start with a little-endian section header.
+    pub fn new(mut reader: R, format: Format) -> io::Result<Self> {
+        match format {
+            Format::HexDump => return Err(io::Error::new(io::ErrorKind::InvalidInput, "hex dump captures can't be read")),
+            Format::Pcapng => {
+                let mut header = [0; 12];
+                reader.read_exact(&mut header)?;
+
+                if header[..4] != [0x0a, 0x0d, 0x0d, 0x0a] || header[8..] != 0x1a2b_3c4d_u32.to_le_bytes() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "not a little-endian pcapng capture"));
+                }
+
+                let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+                io::copy(&mut <&mut R as io::Read>::take(&mut reader, length.saturating_sub(12) as u64), &mut io::sink())?;
+            },
+            _ => ()
+        }
+
+        Ok(CaptureReader { reader, format, line: 0 })
+    }
+
+    /// Returns the format of the capture.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Reads the next record, or returns `None` at the end of the capture.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidData` if the capture is malformed.
+    pub fn read_record(&mut self) -> io::Result<Option<Record>> {
+        if self.format == Format::Pcapng {
+            return self.read_packet();
+        }
+
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            self.line += 1;
+
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let record = match self.format {
+            Format::Text => parse_text(line.trim_end()),
+            _ => parse_json(line.trim())
+        };
+
+        record.map(Some).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: malformed record", self.line)))
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let mut header = [0; 8];
+
+            // a capture may end at any block boundary
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            self.reader.read_exact(&mut header)?;
+
+            let kind = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+            if length < 12 || length % 4 != 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed pcapng block"));
+            }
+
+            let mut body = vec![0; length - 8];
+            self.reader.read_exact(&mut body)?;
+
+            // skip blocks other than enhanced packet blocks
+            if kind != 6 {
+                continue;
+            }
+
+            let word = |offset: usize| body.get(offset..offset + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+            let (interface, high, low, captured) = match (word(0), word(4), word(8), word(12)) {
+                (Some(interface), Some(high), Some(low), Some(captured)) => (interface, high, low, captured as usize),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed pcapng block"))
+            };
+
+            let data = match body.get(20..20 + captured) {
+                Some(data) => data.to_vec(),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed pcapng block"))
+            };
+
+            let micros = (u64::from(high) << 32) | u64::from(low);
+            let direction = if interface == 0 { Direction::Tx } else { Direction::Rx };
+
+            return Ok(Some(Record { timestamp: UNIX_EPOCH + Duration::from_micros(micros), direction, data }));
+        }
+    }
+}
+
+impl<R: io::BufRead> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        self.read_record().transpose()
+    }
+}
+
+/// Parses a timestamp of the form `seconds.micros`.
+fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let (secs, micros) = s.split_once('.')?;
+
+    if micros.len() != 6 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::new(secs.parse().ok()?, micros.parse::<u32>().ok()? * 1000))
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len()).step_by(2).map(|i| s.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+/// Parses a line of `Format::Text`.
+fn parse_text(line: &str) -> Option<Record> {
+    // the hex column never contains '|', so the first separator ends it
+    let (fields, _) = line.split_once("  |")?;
+    let mut fields = fields.split(' ');
+
+    let timestamp = parse_timestamp(fields.next()?)?;
+
+    let direction = match fields.next()? {
+        "TX" => Direction::Tx,
+        "RX" => Direction::Rx,
+        _ => return None
+    };
+
+    let data = fields.map(|byte| if byte.len() == 2 { u8::from_str_radix(byte, 16).ok() } else { None }).collect::<Option<_>>()?;
+
+    Some(Record { timestamp, direction, data })
+}
+
+/// Parses a line of `Format::JsonLines`.
+fn parse_json(line: &str) -> Option<Record> {
+    let rest = line.strip_prefix("{\"time\":")?;
+    let (time, rest) = rest.split_once(",\"direction\":\"")?;
+    let (direction, rest) = rest.split_once("\",\"data\":\"")?;
+    let data = rest.strip_suffix("\"}")?;
+
+    let direction = match direction {
+        "tx" => Direction::Tx,
+        "rx" => Direction::Rx,
+        _ => return None
+    };
+
+    Some(Record { timestamp: parse_timestamp(time)?, direction, data: parse_hex(data)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::{Duration,UNIX_EPOCH};
+
+    use super::{Analyzer,CaptureReader,CaptureWriter,Direction,Event,Format,Record};
+    use ::testing::ScriptedDevice;
+
+    fn record(direction: Direction, data: &[u8]) -> Record {
+        Record {
+            timestamp: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_000),
+            direction,
+            data: data.to_vec()
+        }
+    }
+
+    #[test]
+    fn analyzer_labels_data_with_direction() {
+        let mut tx = ScriptedDevice::new();
+        tx.push_input(b"request");
+
+        let mut rx = ScriptedDevice::new();
+        rx.push_input(b"response");
+
+        let analyzer = Analyzer::new(tx, rx, Default::default());
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match analyzer.receiver().recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::Data(record) => received.push((record.direction, record.data)),
+                Event::Stopped(direction, err) => panic!("{} stopped: {}", direction, err)
+            }
+        }
+        received.sort_by_key(|&(direction, _)| direction == Direction::Rx);
+
+        assert_eq!(received, vec![(Direction::Tx, b"request".to_vec()), (Direction::Rx, b"response".to_vec())]);
+
+        let (tx, rx) = analyzer.close();
+        assert!(tx.is_ok() && rx.is_ok());
+    }
+
+    #[test]
+    fn capture_writes_text() {
+        let mut capture = CaptureWriter::new(Vec::new(), Format::Text).unwrap();
+        capture.write_record(&record(Direction::Tx, b"Hi\r\n")).unwrap();
+
+        assert_eq!(String::from_utf8(capture.into_inner()).unwrap(), "1700000000.123456 TX 48 69 0d 0a  |Hi..|\n");
+    }
+
+    #[test]
+    fn capture_writes_hex_dump() {
+        let mut capture = CaptureWriter::new(Vec::new(), Format::HexDump).unwrap();
+        capture.write_record(&record(Direction::Tx, b"Hi\r\n")).unwrap();
+        capture.write_record(&record(Direction::Tx, b"!")).unwrap();
+
+        let dump = String::from_utf8(capture.into_inner()).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert!(lines[0].starts_with("1700000000.123456 -> 00000000  48 69 0d 0a "));
+        assert!(lines[0].ends_with(" |Hi..|"));
+        assert!(lines[1].starts_with("1700000000.123456 -> 00000004  21 "));
+    }
+
+    #[test]
+    fn capture_writes_json_lines() {
+        let mut capture = CaptureWriter::new(Vec::new(), Format::JsonLines).unwrap();
+        capture.write_record(&record(Direction::Rx, b"Hi\r\n")).unwrap();
+
+        assert_eq!(String::from_utf8(capture.into_inner()).unwrap(), "{\"time\":1700000000.123456,\"direction\":\"rx\",\"data\":\"48690d0a\"}\n");
+    }
+
+    #[test]
+    fn capture_writes_pcapng_blocks() {
+        let mut capture = CaptureWriter::new(Vec::new(), Format::Pcapng).unwrap();
+        capture.write_record(&record(Direction::Rx, b"abcde")).unwrap();
+
+        let bytes = capture.into_inner();
+        let header = 28 + 2 * 20;
+        assert_eq!(bytes.len(), header + 52);
+        assert_eq!(&bytes[..4], &[0x0a, 0x0d, 0x0d, 0x0a]);
+
+        let packet = &bytes[header..];
+        let word = |offset: usize| u32::from_le_bytes([packet[offset], packet[offset + 1], packet[offset + 2], packet[offset + 3]]);
+
+        assert_eq!(word(0), 6);
+        assert_eq!(word(4), 52);
+        assert_eq!(word(8), 1);
+        assert_eq!((u64::from(word(12)) << 32) | u64::from(word(16)), 1_700_000_000_123_456);
+        assert_eq!(word(20), 5);
+        assert_eq!(&packet[28..33], b"abcde");
+        assert_eq!(word(40), 1);
+        assert_eq!(word(48), 52);
+    }
+    #[test]
+    fn capture_reader_reads_written_records() {
+        let records = vec![record(Direction::Tx, b"Hi  |\r\n"), record(Direction::Rx, b""), record(Direction::Rx, b"abcde")];
+
+        for &format in &[Format::Text, Format::JsonLines, Format::Pcapng] {
+            let mut capture = CaptureWriter::new(Vec::new(), format).unwrap();
+
+            for record in &records {
+                capture.write_record(record).unwrap();
+            }
+
+            let bytes = capture.into_inner();
+            let read: Vec<Record> = CaptureReader::new(&bytes[..], format).unwrap().collect::<Result<_, _>>().unwrap();
+            assert_eq!(read, records, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn capture_reader_rejects_malformed_captures() {
+        let mut reader = CaptureReader::new(&b"1700000000.123456 TX 4  |.|\n"[..], Format::Text).unwrap();
+        assert_eq!(reader.read_record().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert!(CaptureReader::new(&b""[..], Format::HexDump).is_err());
+        assert!(CaptureReader::new(&[0; 12][..], Format::Pcapng).is_err());
+    }
+}
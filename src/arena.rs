@@ -0,0 +1,330 @@
+//! Capturing received data directly into caller-provided buffers.
+//!
+//! `capture()` moves a port to a background thread that reads straight into a pool of large
+//! buffers registered by the caller. Each buffer is handed back as a `Segment` once it's full, or
+//! once it has held data for `max_latency`, and is returned to the pool with `Pool::recycle()`
+//! when the caller is done with it. No data is copied or allocated after the buffers are
+//! registered, which suits loggers that sustain several megabytes per second:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::arena;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let buffers = (0..4).map(|_| vec![0; 4 << 20]).collect();
+//! let capture = arena::capture(port, buffers, Default::default());
+//!
+//! let mut log = std::fs::File::create("capture.bin").unwrap();
+//!
+//! for segment in capture.segments.iter() {
+//!     log.write_all(&segment).unwrap();
+//!     capture.pool.recycle(segment);
+//! }
+//! ```
+//!
+//! When every buffer is held by the caller, the thread stops reading until one is recycled.
+//! Unread data accumulates in the operating system's buffer in the meantime, where it's subject
+//! to flow control or may be lost if the buffer overflows.
+
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,RecvTimeoutError,Sender};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::shutdown;
+
+/// Options for capturing into caller-provided buffers.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct ArenaOptions {
+    /// Maximum time that received data is held in a partially filled buffer before the buffer is
+    /// handed back.
+    pub max_latency: Duration,
+
+    /// Maximum time to wait for received data or a free buffer before checking whether to stop.
+    pub poll_interval: Duration
+}
+
+impl Default for ArenaOptions {
+    fn default() -> Self {
+        ArenaOptions {
+            max_latency: Duration::from_millis(100),
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// A buffer filled with received data.
+///
+/// A segment dereferences to the data it holds. The whole buffer, including any unfilled space,
+/// is returned by `into_inner()`.
+#[derive(Debug)]
+pub struct Segment {
+    buf: Vec<u8>,
+    len: usize
+}
+
+impl Segment {
+    /// Returns the buffer that holds the segment's data.
+    ///
+    /// The buffer has the length it had when it was registered. Only the first `len()` bytes
+    /// hold received data.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Deref for Segment {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for Segment {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// A handle for giving buffers to a capture thread.
+///
+/// `Pool` can be cloned to recycle segments from other threads.
+#[derive(Debug,Clone)]
+pub struct Pool {
+    sender: Sender<Vec<u8>>
+}
+
+impl Pool {
+    /// Gives the capture thread another buffer to fill.
+    ///
+    /// The buffer is filled up to its length, so it should be allocated with e.g.
+    /// `vec![0; size]`. Empty buffers are ignored.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(BrokenPipe)` if the thread has stopped.
+    pub fn register(&self, buffer: Vec<u8>) -> ::Result<()> {
+        match self.sender.send(buffer) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(::Error::new(::ErrorKind::Io(io::ErrorKind::BrokenPipe), "capture thread has stopped"))
+        }
+    }
+
+    /// Returns a segment's buffer to the pool to be filled again.
+    ///
+    /// Segments returned after the thread has stopped are dropped.
+    pub fn recycle(&self, segment: Segment) {
+        let _ = self.register(segment.into_inner());
+    }
+}
+
+/// A port being captured into caller-provided buffers.
+pub struct Capture<P> {
+    /// Receives filled segments in the order their data was received.
+    ///
+    /// The receiver is disconnected when the thread stops, after the last partially filled
+    /// segment has been delivered.
+    pub segments: Receiver<Segment>,
+
+    /// Returns buffers to the capture thread.
+    pub pool: Pool,
+
+    /// Controls the capture thread.
+    pub worker: Worker<P>
+}
+
+/// A handle to a capture thread.
+pub struct Worker<P> {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<::Result<P>>>
+}
+
+impl<P> Worker<P> {
+    /// Stops the capture thread and returns the port.
+    ///
+    /// Data that was already received is delivered in a final segment.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if it stopped because of an I/O
+    /// error.
+    pub fn close(mut self) -> ::Result<P> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "capture thread").and_then(|result| result)
+    }
+
+    /// Stops the thread like `close()`, waiting at most `timeout` for it to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the thread didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<P> {
+        let deadline = Instant::now() + timeout;
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "capture thread").and_then(|result| result)
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Moves a port to a thread that captures received data into `buffers`.
+///
+/// More buffers can be added later with `Pool::register()`.
+pub fn capture<P>(port: P, buffers: Vec<Vec<u8>>, options: ArenaOptions) -> Capture<P>
+    where P: SerialPort + Send + 'static
+{
+    let (free_tx, free_rx) = mpsc::channel();
+    let (filled_tx, filled_rx) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    for buffer in buffers {
+        let _ = free_tx.send(buffer);
+    }
+
+    let thread = {
+        let stop = stop.clone();
+        thread::spawn(move || run(port, &free_rx, &filled_tx, &stop, &options))
+    };
+
+    Capture {
+        segments: filled_rx,
+        pool: Pool { sender: free_tx },
+        worker: Worker { stop, thread: Some(thread) }
+    }
+}
+
+fn run<P: SerialPort>(mut port: P, free: &Receiver<Vec<u8>>, filled: &Sender<Segment>, stop: &AtomicBool, options: &ArenaOptions) -> ::Result<P> {
+    let mut current: Option<Segment> = None;
+    let mut deadline = Instant::now();
+
+    let result = loop {
+        if stop.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+
+        let segment = match current {
+            Some(ref mut segment) => segment,
+            None => {
+                match free.recv_timeout(options.poll_interval) {
+                    Ok(buf) => if !buf.is_empty() { current = Some(Segment { buf, len: 0 }) },
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break Ok(())
+                }
+
+                continue;
+            }
+        };
+
+        let timeout = if segment.len == 0 {
+            options.poll_interval
+        }
+        else {
+            deadline.saturating_duration_since(Instant::now()).min(options.poll_interval)
+        };
+
+        match port.read_with_timeout(&mut segment.buf[segment.len..], timeout) {
+            Ok(n) => {
+                // the latency is measured from the first byte in the segment
+                if segment.len == 0 {
+                    deadline = Instant::now() + options.max_latency;
+                }
+
+                segment.len += n;
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => break Err(::Error::from(err))
+        }
+
+        let full = segment.len == segment.buf.len();
+        let expired = segment.len > 0 && Instant::now() >= deadline;
+
+        if (full || expired) && filled.send(current.take().unwrap()).is_err() {
+            break Ok(());
+        }
+    };
+
+    if let Some(segment) = current {
+        if segment.len > 0 {
+            let _ = filled.send(segment);
+        }
+    }
+
+    result.map(|()| port)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{capture,ArenaOptions};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn capture_fills_buffers_across_reads() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"abc");
+        device.push_input(b"defgh");
+        device.push_input(b"ij");
+
+        let capture = capture(device, vec![vec![0; 4], vec![0; 4]], Default::default());
+
+        let first = capture.segments.recv().unwrap();
+        let second = capture.segments.recv().unwrap();
+
+        assert_eq!(&*first, b"abcd");
+        assert_eq!(&*second, b"efgh");
+
+        let ptr = first.as_ptr();
+        capture.pool.recycle(first);
+
+        let third = capture.segments.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(&*third, b"ij");
+        assert_eq!(third.as_ptr(), ptr);
+
+        capture.worker.close().unwrap();
+    }
+
+    #[test]
+    fn capture_delivers_partial_buffer_after_max_latency() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello");
+
+        let options = ArenaOptions { max_latency: Duration::from_millis(20), ..Default::default() };
+        let capture = capture(device, vec![vec![0; 1024]], options);
+
+        let segment = capture.segments.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(&*segment, b"hello");
+        assert_eq!(segment.into_inner().len(), 1024);
+
+        capture.worker.close().unwrap();
+    }
+
+    #[test]
+    fn capture_delivers_received_data_when_stopped_by_error() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"last");
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let options = ArenaOptions { max_latency: Duration::from_secs(60), ..Default::default() };
+        let capture = capture(device, vec![vec![0; 1024]], options);
+
+        assert_eq!(&*capture.segments.recv().unwrap(), b"last");
+        assert!(capture.segments.recv().is_err());
+        assert!(capture.worker.close().err().unwrap().is_disconnection());
+        assert!(capture.pool.register(vec![0; 16]).is_err());
+    }
+}
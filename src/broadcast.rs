@@ -0,0 +1,195 @@
+//! Writing the same data to several ports.
+//!
+//! A `Broadcast` fans every write out to a set of ports, which is useful for updating the firmware
+//! of several identical devices at once or for driving mirrored displays:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::broadcast::Broadcast;
+//!
+//! let mut displays = Broadcast::new();
+//! displays.push(serial::open("/dev/ttyUSB0").unwrap());
+//! displays.push(serial::open("/dev/ttyUSB1").unwrap());
+//!
+//! displays.write_all(b"\x0cHello\r\n").unwrap();
+//!
+//! for (index, err) in displays.errors() {
+//!     println!("display {} failed: {}", index, err);
+//! }
+//! ```
+//!
+//! A port that fails is remembered with its error and skipped by later writes, so that one
+//! unplugged device doesn't interrupt the others. Writes fail only when every port has failed.
+
+use std::io;
+
+/// A writer that writes everything to each of a set of ports.
+///
+/// Ports are identified by their index, in the order they were added.
+pub struct Broadcast<P> {
+    ports: Vec<P>,
+    errors: Vec<Option<::Error>>
+}
+
+impl<P: io::Write> Broadcast<P> {
+    /// Creates a broadcast with no ports.
+    pub fn new() -> Self {
+        Broadcast {
+            ports: Vec::new(),
+            errors: Vec::new()
+        }
+    }
+
+    /// Adds a port and returns its index.
+    pub fn push(&mut self, port: P) -> usize {
+        self.ports.push(port);
+        self.errors.push(None);
+        self.ports.len() - 1
+    }
+
+    /// Returns the number of ports, including ports that have failed.
+    pub fn len(&self) -> usize {
+        self.ports.len()
+    }
+
+    /// Returns `true` if no ports have been added.
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// Returns the number of ports that haven't failed.
+    pub fn healthy(&self) -> usize {
+        self.errors.iter().filter(|err| err.is_none()).count()
+    }
+
+    /// Returns the error that caused the port at `index` to fail, if it has failed.
+    pub fn error(&self, index: usize) -> Option<&::Error> {
+        self.errors.get(index).and_then(Option::as_ref)
+    }
+
+    /// Returns the index and error of each port that has failed.
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &::Error)> {
+        self.errors.iter().enumerate().filter_map(|(index, err)| err.as_ref().map(|err| (index, err)))
+    }
+
+    /// Forgets all errors, so that failed ports are written to again.
+    pub fn clear_errors(&mut self) {
+        for err in &mut self.errors {
+            *err = None;
+        }
+    }
+
+    /// Returns a reference to the ports.
+    pub fn get_ref(&self) -> &[P] {
+        &self.ports
+    }
+
+    /// Returns a mutable reference to the ports, e.g., to configure them.
+    pub fn get_mut(&mut self) -> &mut [P] {
+        &mut self.ports
+    }
+
+    /// Returns the ports.
+    pub fn into_inner(self) -> Vec<P> {
+        self.ports
+    }
+
+    /// Calls `f` on each port that hasn't failed. A port for which `f` fails is marked as failed.
+    fn each<F: FnMut(&mut P) -> io::Result<()>>(&mut self, operation: &'static str, mut f: F) -> io::Result<()> {
+        for (port, err) in self.ports.iter_mut().zip(self.errors.iter_mut()) {
+            if err.is_none() {
+                if let Err(e) = f(port) {
+                    *err = Some(::Error::from(e).with_operation(operation));
+                }
+            }
+        }
+
+        if self.healthy() == 0 {
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), "every port has failed").with_operation(operation).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: io::Write> Default for Broadcast<P> {
+    fn default() -> Self {
+        Broadcast::new()
+    }
+}
+
+/// Each write is written in full to every port that hasn't failed, so a successful write always
+/// writes all of `buf`.
+///
+/// ## Errors
+///
+/// A write fails only if no port is left that hasn't failed, including when there are no ports.
+impl<P: io::Write> io::Write for Broadcast<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.each("write", |port| port.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.each("flush", |port| port.flush())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::prelude::*;
+
+    use super::Broadcast;
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn broadcast_writes_to_every_port() {
+        let mut broadcast = Broadcast::new();
+        broadcast.push(ScriptedDevice::new());
+        broadcast.push(ScriptedDevice::new());
+
+        broadcast.write_all(b"data").unwrap();
+        broadcast.flush().unwrap();
+
+        for port in broadcast.get_ref() {
+            assert_eq!(port.output(), b"data");
+        }
+    }
+
+    #[test]
+    fn broadcast_skips_failed_ports() {
+        let mut broadcast = Broadcast::new();
+        broadcast.push(ScriptedDevice::new());
+        let failing = broadcast.push(ScriptedDevice::new());
+        broadcast.get_mut()[failing].write_error = Some(io::ErrorKind::BrokenPipe);
+
+        broadcast.write_all(b"one").unwrap();
+        broadcast.get_mut()[failing].write_error = None;
+        broadcast.write_all(b"two").unwrap();
+
+        assert_eq!(broadcast.healthy(), 1);
+        assert_eq!(broadcast.get_ref()[0].output(), b"onetwo");
+        assert_eq!(broadcast.get_ref()[failing].output(), b"");
+
+        let errors: Vec<usize> = broadcast.errors().map(|(index, _)| index).collect();
+        assert_eq!(errors, vec![failing]);
+        assert!(broadcast.error(failing).unwrap().is_disconnection());
+        assert_eq!(broadcast.error(failing).unwrap().operation(), Some("write"));
+
+        broadcast.clear_errors();
+        broadcast.write_all(b"three").unwrap();
+        assert_eq!(broadcast.get_ref()[failing].output(), b"three");
+    }
+
+    #[test]
+    fn broadcast_fails_when_every_port_has_failed() {
+        let mut broadcast = Broadcast::new();
+        broadcast.push(ScriptedDevice::new());
+        broadcast.get_mut()[0].write_error = Some(io::ErrorKind::BrokenPipe);
+
+        assert!(broadcast.write_all(b"data").is_err());
+        assert!(Broadcast::<ScriptedDevice>::new().write(b"data").is_err());
+    }
+}
@@ -0,0 +1,398 @@
+//! Buffered reading with lookahead.
+//!
+//! `BufferedPort` reads from a port through a buffer that can be inspected without consuming it.
+//! This allows a protocol to be detected from the first bytes of a message, e.g., to tell NMEA
+//! sentences from UBX and RTCM frames that are sent over the same port:
+//!
+//! ```no_run
+//! use serial::buffered::BufferedPort;
+//!
+//! let mut port = BufferedPort::new(serial::open("/dev/ttyACM0").unwrap());
+//! let mut header = [0; 2];
+//!
+//! let len = port.peek(&mut header).unwrap();
+//!
+//! match &header[..len] {
+//!     [b'$', ..] => println!("NMEA"),
+//!     [0xb5, 0x62] => println!("UBX"),
+//!     [0xd3, ..] => println!("RTCM"),
+//!     _ => println!("unknown")
+//! }
+//! ```
+//!
+//! Data that was consumed can be pushed back with `unread()`, so that it's read again by the
+//! parser that handles the detected protocol.
+//!
+//! `read_until()` reads delimited messages with a limit on their length and on the time spent
+//! waiting for them, so that noise on a line without delimiters can't exhaust memory.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration,Instant};
+
+use ::{SerialDevice,SerialPort};
+
+/// The reason `BufferedPort::read_until()` stopped reading.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Stop {
+    /// The delimiter was read. It's the last byte of the data.
+    Delimiter,
+
+    /// The maximum length was read without finding the delimiter.
+    MaxLength,
+
+    /// The timeout elapsed before the delimiter was read.
+    Timeout
+}
+
+const DEFAULT_CAPACITY: usize = 256;
+
+/// A port wrapper that buffers received data.
+///
+/// `BufferedPort` implements `std::io::BufRead`. It also implements `SerialDevice` if the wrapped
+/// port does, in which case `discard_input()` discards the buffered data as well.
+pub struct BufferedPort<P> {
+    port: P,
+    buffer: VecDeque<u8>,
+    capacity: usize
+}
+
+impl<P> BufferedPort<P> {
+    /// Wraps a port with a buffer of the default capacity.
+    pub fn new(port: P) -> Self {
+        BufferedPort::with_capacity(DEFAULT_CAPACITY, port)
+    }
+
+    /// Wraps a port, reading up to `capacity` bytes at a time.
+    pub fn with_capacity(capacity: usize, port: P) -> Self {
+        BufferedPort {
+            port,
+            buffer: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1)
+        }
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    ///
+    /// Reading directly from the port bypasses the buffered data.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    ///
+    /// Buffered data is discarded.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Returns the number of bytes that have been received but not yet read.
+    pub fn available(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Pushes data back onto the front of the buffer.
+    ///
+    /// The data is returned by the following reads before any data that was already buffered.
+    /// The buffer grows as needed to hold the data.
+    pub fn unread(&mut self, data: &[u8]) {
+        for &byte in data.iter().rev() {
+            self.buffer.push_front(byte);
+        }
+    }
+
+    /// Copies buffered data into `buf` without consuming it.
+    fn copy_buffered(&self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.buffer.len());
+
+        for (dst, &src) in buf.iter_mut().zip(self.buffer.iter()) {
+            *dst = src;
+        }
+
+        len
+    }
+}
+
+impl<P: io::Read> BufferedPort<P> {
+    /// Copies data into `buf` without consuming it.
+    ///
+    /// If fewer bytes than `buf.len()` are buffered, one read from the port is attempted with the
+    /// port's timeout. Returns the number of bytes copied, which may be less than `buf.len()`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if no data is buffered and reading from the port failed,
+    /// including an error of kind `TimedOut` if no data was received.
+    pub fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.len() < buf.len() {
+            match self.fill() {
+                Ok(_) => (),
+                Err(_) if !self.buffer.is_empty() => (),
+                Err(err) => return Err(err)
+            }
+        }
+
+        Ok(self.copy_buffered(buf))
+    }
+
+    /// Reads once from the port and appends the data to the buffer.
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut buf = vec![0; self.capacity];
+
+        loop {
+            match self.port.read(&mut buf) {
+                Ok(len) => {
+                    self.buffer.extend(&buf[..len]);
+                    return Ok(len);
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+    }
+}
+
+impl<P: SerialPort> BufferedPort<P> {
+    /// Reads until `delimiter`, until `max` bytes have been read, or until `timeout` elapses,
+    /// whichever comes first.
+    ///
+    /// Returns the data that was read, including the delimiter if it was found, and the reason
+    /// for stopping. Data received after the delimiter stays buffered for the next read. A timeout
+    /// isn't an error: the data received until then is returned with `Stop::Timeout`.
+    ///
+    /// This method shadows `std::io::BufRead::read_until()`, which has no limits and can still be
+    /// called as `BufRead::read_until(&mut port, delimiter, &mut buf)`.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use serial::buffered::{BufferedPort,Stop};
+    ///
+    /// let mut port = BufferedPort::new(serial::open("/dev/ttyUSB0").unwrap());
+    ///
+    /// match port.read_until(b'\n', 82, Duration::from_secs(2)).unwrap() {
+    ///     (sentence, Stop::Delimiter) => println!("{}", String::from_utf8_lossy(&sentence)),
+    ///     (_, Stop::MaxLength) => println!("sentence is too long"),
+    ///     (_, Stop::Timeout) => println!("no sentence received")
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if reading from the port failed for another reason than a
+    /// timeout, or an error of kind `UnexpectedEof` if the port reached end of file. Data that was
+    /// read before the error stays buffered.
+    pub fn read_until(&mut self, delimiter: u8, max: usize, timeout: Duration) -> io::Result<(Vec<u8>, Stop)> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0; self.capacity];
+        let mut scanned = 0;
+
+        loop {
+            let limit = self.buffer.len().min(max);
+
+            if let Some(end) = self.buffer.range(scanned..limit).position(|&byte| byte == delimiter) {
+                return Ok((self.take(scanned + end + 1), Stop::Delimiter));
+            }
+
+            if self.buffer.len() >= max {
+                return Ok((self.take(max), Stop::MaxLength));
+            }
+
+            scanned = limit;
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::ZERO {
+                let len = self.buffer.len();
+                return Ok((self.take(len), Stop::Timeout));
+            }
+
+            match self.port.read_with_timeout(&mut buf, remaining) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of file")),
+                Ok(len) => self.buffer.extend(&buf[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => {
+                    let len = self.buffer.len();
+                    return Ok((self.take(len), Stop::Timeout));
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    /// Removes `len` bytes from the front of the buffer.
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        self.buffer.drain(..len).collect()
+    }
+}
+
+impl<P: io::Read> io::Read for BufferedPort<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            return self.port.read(buf);
+        }
+
+        let len = self.copy_buffered(buf);
+        self.buffer.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<P: io::Read> io::BufRead for BufferedPort<P> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.buffer.is_empty() {
+            self.fill()?;
+        }
+
+        Ok(self.buffer.make_contiguous())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.buffer.len());
+        self.buffer.drain(..amt);
+    }
+}
+
+impl<P: io::Write> io::Write for BufferedPort<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: SerialDevice> SerialDevice for BufferedPort<P> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, write_settings);
+
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            return SerialDevice::read_with_timeout(&mut self.port, buf, timeout);
+        }
+
+        io::Read::read(self, buf)
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        self.buffer.clear();
+        SerialDevice::discard_input(&mut self.port)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::prelude::*;
+
+    use std::time::Duration;
+
+    use super::{BufferedPort,Stop};
+    use ::prelude::*;
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn peek_does_not_consume_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"$GPGGA");
+
+        let mut port = BufferedPort::new(device);
+        let mut header = [0; 2];
+        let mut buf = [0; 8];
+
+        assert_eq!(port.peek(&mut header).unwrap(), 2);
+        assert_eq!(&header, b"$G");
+        assert_eq!(port.read(&mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"$GPGGA");
+    }
+
+    #[test]
+    fn peek_returns_partial_data_on_timeout() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"\xb5");
+
+        let mut port = BufferedPort::new(device);
+        let mut header = [0; 2];
+
+        assert_eq!(port.peek(&mut header).unwrap(), 1);
+        assert_eq!(port.peek(&mut header).unwrap(), 1);
+
+        port.read_exact(&mut header[..1]).unwrap();
+        assert_eq!(port.peek(&mut header).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn unread_pushes_data_to_front() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"cd");
+
+        let mut port = BufferedPort::new(device);
+        let mut buf = [0; 1];
+
+        port.peek(&mut buf).unwrap();
+        port.unread(b"ab");
+
+        let mut data = Vec::new();
+        port.get_mut().read_error = Some(io::ErrorKind::UnexpectedEof);
+        assert!(port.read_to_end(&mut data).is_err());
+        assert_eq!(data, b"abcd");
+        assert_eq!(port.available(), 0);
+    }
+
+    #[test]
+    fn discard_input_clears_buffer() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"stale");
+
+        let mut port = BufferedPort::new(device);
+        let mut buf = [0; 8];
+
+        port.peek(&mut buf).unwrap();
+        port.discard_input().unwrap();
+
+        assert_eq!(port.available(), 0);
+        assert_eq!(port.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn read_until_stops_at_delimiter_and_keeps_rest() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"$GP");
+        device.push_input(b"GGA\r\n$GPRMC");
+
+        let mut port = BufferedPort::new(device);
+
+        assert_eq!(port.read_until(b'\n', 82, Duration::from_secs(1)).unwrap(), (b"$GPGGA\r\n".to_vec(), Stop::Delimiter));
+        assert_eq!(port.available(), 6);
+    }
+
+    #[test]
+    fn read_until_stops_at_max_length() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"noise without end");
+
+        let mut port = BufferedPort::new(device);
+
+        assert_eq!(port.read_until(b'\n', 5, Duration::from_secs(1)).unwrap(), (b"noise".to_vec(), Stop::MaxLength));
+        assert_eq!(port.read_until(b'w', 3, Duration::from_secs(1)).unwrap(), (b" w".to_vec(), Stop::Delimiter));
+    }
+
+    #[test]
+    fn read_until_reports_timeout_with_partial_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"par");
+
+        let mut port = BufferedPort::new(device);
+
+        assert_eq!(port.read_until(b'\n', 82, Duration::from_millis(10)).unwrap(), (b"par".to_vec(), Stop::Timeout));
+        assert_eq!(port.read_until(b'\n', 82, Duration::ZERO).unwrap(), (Vec::new(), Stop::Timeout));
+    }
+}
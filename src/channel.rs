@@ -0,0 +1,372 @@
+//! Message-passing access to a serial port from a background thread.
+//!
+//! `SerialPort::into_channel()` moves a port to a managed I/O thread. Received data is delivered
+//! in chunks through a `Receiver<Vec<u8>>`, and data is transmitted by sending it to a `Writer`.
+//! This suits GUI applications and actor systems that prefer message passing to blocking reads.
+//!
+//! ```no_run
+//! use serial::prelude::*;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let channel = port.into_channel(Default::default());
+//!
+//! channel.writer.send(b"AT\r".to_vec()).unwrap();
+//!
+//! for chunk in channel.receiver.iter() {
+//!     println!("{:?}", chunk);
+//! }
+//! ```
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use std::sync::mpsc::{self,Receiver,Sender,SyncSender,TrySendError};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::shutdown::{self,Drain};
+
+/// Policy for received data when the channel's receiver falls behind.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Backpressure {
+    /// Stop reading from the port until the receiver catches up.
+    ///
+    /// Unread data accumulates in the operating system's buffer, where it's subject to flow
+    /// control or may be lost if the buffer overflows.
+    Block,
+
+    /// Discard received chunks that don't fit in the channel.
+    ///
+    /// The number of discarded chunks is reported by `Worker::dropped()`.
+    Drop
+}
+
+/// Options for a port's background I/O thread.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct ChannelOptions {
+    /// Maximum number of bytes delivered in each chunk.
+    pub chunk_size: usize,
+
+    /// Maximum number of chunks buffered in the channel before applying backpressure.
+    pub capacity: usize,
+
+    /// Policy applied when the channel is full.
+    pub backpressure: Backpressure,
+
+    /// Maximum time to wait for received data before servicing pending writes.
+    ///
+    /// This bounds the latency of writes and of stopping the thread.
+    pub poll_interval: Duration
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        ChannelOptions {
+            chunk_size: 4096,
+            capacity: 64,
+            backpressure: Backpressure::Block,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// A serial port owned by a background I/O thread.
+///
+/// The thread is stopped when the `Worker` is closed, shut down, or dropped.
+pub struct Channel<P> {
+    /// Receives chunks of data read from the port.
+    ///
+    /// The receiver is disconnected when the thread stops.
+    pub receiver: Receiver<Vec<u8>>,
+
+    /// Sends data to be written to the port.
+    pub writer: Writer,
+
+    /// Controls the background thread.
+    pub worker: Worker<P>
+}
+
+/// A handle for sending data to be written by a background I/O thread.
+///
+/// Data is written in the order it's sent. Writes are performed asynchronously, so an error
+/// writing to the port stops the thread.
+#[derive(Debug,Clone)]
+pub struct Writer {
+    sender: Sender<Vec<u8>>,
+    drain: Arc<Drain>
+}
+
+impl Writer {
+    pub(crate) fn new(sender: Sender<Vec<u8>>, drain: Arc<Drain>) -> Self {
+        Writer { sender, drain }
+    }
+
+    /// Queues data to be written to the port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(BrokenPipe)` if the thread has stopped.
+    pub fn send(&self, data: Vec<u8>) -> ::Result<()> {
+        let len = data.len();
+        self.drain.queued(len);
+
+        match self.sender.send(data) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                self.drain.written(len);
+                Err(::Error::new(::ErrorKind::Io(io::ErrorKind::BrokenPipe), "I/O thread has stopped"))
+            }
+        }
+    }
+}
+
+impl io::Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf.to_vec())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A handle to a background I/O thread.
+pub struct Worker<P> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<::Result<P>>>
+}
+
+struct Shared {
+    stop: AtomicBool,
+    dropped: AtomicUsize,
+    drain: Arc<Drain>
+}
+
+impl<P> Worker<P> {
+    /// Returns the number of received chunks that were discarded by `Backpressure::Drop`.
+    pub fn dropped(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stops the thread and returns the port.
+    ///
+    /// Data that was queued for writing but not yet written is discarded.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if it stopped because of an I/O
+    /// error.
+    pub fn close(mut self) -> ::Result<P> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "I/O thread").and_then(|result| result)
+    }
+
+    /// Writes the queued data, then stops the thread and returns the port.
+    ///
+    /// Writing stops when `timeout` has passed. Data that was queued but not written by then is
+    /// counted in `Shutdown::unsent`. Nothing more is read from the port once the thread starts
+    /// writing the queued data.
+    ///
+    /// ## Errors
+    ///
+    /// `Shutdown::result` is an error of kind `Io(TimedOut)` if the thread didn't stop in time, or
+    /// the error that stopped the thread, if it stopped because of an I/O error.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Shutdown<P> {
+        let deadline = Instant::now() + timeout;
+        self.shared.drain.request(deadline);
+
+        let result = shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "I/O thread").and_then(|result| result);
+        ::Shutdown { result, unsent: self.shared.drain.unsent() }
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Moves a port to a background I/O thread.
+///
+/// This is the implementation of `SerialPort::into_channel()`.
+pub fn spawn<P>(port: P, options: ChannelOptions) -> Channel<P>
+    where P: SerialPort + Send + 'static
+{
+    let (data_tx, data_rx) = mpsc::sync_channel(options.capacity);
+    let (write_tx, write_rx) = mpsc::channel();
+
+    let shared = Arc::new(Shared {
+        stop: AtomicBool::new(false),
+        dropped: AtomicUsize::new(0),
+        drain: Arc::new(Drain::new())
+    });
+
+    let thread = {
+        let shared = shared.clone();
+        thread::spawn(move || run(port, &shared, &write_rx, &data_tx, &options))
+    };
+
+    Channel {
+        receiver: data_rx,
+        writer: Writer::new(write_tx, shared.drain.clone()),
+        worker: Worker { shared, thread: Some(thread) }
+    }
+}
+
+fn run<P: SerialPort>(mut port: P, shared: &Shared, writes: &Receiver<Vec<u8>>, data: &SyncSender<Vec<u8>>, options: &ChannelOptions) -> ::Result<P> {
+    let mut buf = vec![0; options.chunk_size.max(1)];
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        while !shared.drain.expired() {
+            let pending = match writes.try_recv() {
+                Ok(pending) => pending,
+                Err(_) => break
+            };
+
+            port.write_all(&pending)?;
+            port.flush()?;
+            shared.drain.written(pending.len());
+        }
+
+        if shared.drain.deadline().is_some() {
+            break;
+        }
+
+        let len = match port.read_with_timeout(&mut buf, options.poll_interval) {
+            Ok(0) => continue,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(::Error::from(err))
+        };
+
+        deliver(buf[..len].to_vec(), shared, data, options);
+    }
+
+    Ok(port)
+}
+
+fn deliver(mut chunk: Vec<u8>, shared: &Shared, data: &SyncSender<Vec<u8>>, options: &ChannelOptions) {
+    loop {
+        match data.try_send(chunk) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+            Err(TrySendError::Full(rejected)) => {
+                if options.backpressure == Backpressure::Drop {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+
+                if shared.stop.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                chunk = rejected;
+                thread::sleep(options.poll_interval);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::thread;
+    use std::time::{Duration,Instant};
+
+    use super::{Backpressure,ChannelOptions};
+    use ::SerialPort;
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn channel_delivers_received_chunks() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello");
+        device.push_input(b" world");
+
+        let channel = device.into_channel(Default::default());
+
+        assert_eq!(channel.receiver.recv().unwrap(), b"hello");
+        assert_eq!(channel.receiver.recv().unwrap(), b" world");
+        channel.worker.close().unwrap();
+    }
+
+    #[test]
+    fn channel_splits_chunks_larger_than_chunk_size() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"abcdef");
+
+        let options = ChannelOptions { chunk_size: 4, ..Default::default() };
+        let channel = device.into_channel(options);
+
+        assert_eq!(channel.receiver.recv().unwrap(), b"abcd");
+        assert_eq!(channel.receiver.recv().unwrap(), b"ef");
+        channel.worker.close().unwrap();
+    }
+
+    #[test]
+    fn channel_writes_data_from_writer() {
+        let mut device = ScriptedDevice::new();
+        device.echo = true;
+
+        let channel = device.into_channel(Default::default());
+        channel.writer.send(b"AT\r".to_vec()).unwrap();
+
+        assert_eq!(channel.receiver.recv().unwrap(), b"AT\r");
+
+        let device = channel.worker.close().unwrap();
+        assert_eq!(device.events, vec![Event::Write(b"AT\r".to_vec()), Event::Flush]);
+    }
+
+    #[test]
+    fn channel_drops_chunks_when_full() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"1");
+        device.push_input(b"2");
+        device.push_input(b"3");
+
+        let options = ChannelOptions { capacity: 1, backpressure: Backpressure::Drop, ..Default::default() };
+        let channel = device.into_channel(options);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while channel.worker.dropped() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(channel.worker.dropped(), 2);
+        assert_eq!(channel.receiver.recv().unwrap(), b"1");
+        channel.worker.close().unwrap();
+    }
+
+    #[test]
+    fn channel_shutdown_writes_queued_data() {
+        let mut device = ScriptedDevice::new();
+        device.write_delay = Duration::from_millis(10);
+
+        let channel = device.into_channel(Default::default());
+        channel.writer.send(b"AT\r".to_vec()).unwrap();
+        channel.writer.send(b"ATZ\r".to_vec()).unwrap();
+
+        let shutdown = channel.worker.shutdown(Duration::from_secs(5));
+        assert_eq!(shutdown.unsent, 0);
+        assert_eq!(shutdown.result.ok().unwrap().output(), b"AT\rATZ\r");
+    }
+
+    #[test]
+    fn channel_shutdown_reports_unsent_data() {
+        let mut device = ScriptedDevice::new();
+        device.write_delay = Duration::from_millis(200);
+
+        let channel = device.into_channel(Default::default());
+        channel.writer.send(b"AT\r".to_vec()).unwrap();
+        channel.writer.send(b"ATZ\r".to_vec()).unwrap();
+
+        let shutdown = channel.worker.shutdown(Duration::from_millis(50));
+        assert_eq!(shutdown.unsent, 7);
+        assert_eq!(shutdown.result.err().unwrap().kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+}
@@ -0,0 +1,469 @@
+//! COBS framing with CRC-protected typed payloads.
+//!
+//! Consistent Overhead Byte Stuffing (COBS) removes all zero bytes from a frame, so that a single
+//! zero byte can delimit frames. It's a popular choice for links between a host and firmware,
+//! because a receiver can always resynchronize at the next zero byte.
+//!
+//! `CobsCodec` appends a CRC-16 to each frame before encoding it. `TypedPort` combines the codec
+//! with a `Payload` type to send and receive structured messages:
+//!
+//! ```no_run
+//! use serial::codec::cobs::{Payload,TypedPort};
+//!
+//! struct Reading {
+//!     channel: u8,
+//!     value: u16
+//! }
+//!
+//! impl Payload for Reading {
+//!     fn encode_payload(&self, buf: &mut Vec<u8>) -> serial::Result<()> {
+//!         buf.push(self.channel);
+//!         buf.extend_from_slice(&self.value.to_le_bytes());
+//!         Ok(())
+//!     }
+//!
+//!     fn decode_payload(bytes: &[u8]) -> serial::Result<Self> {
+//!         match bytes {
+//!             &[channel, lo, hi] => Ok(Reading { channel, value: u16::from_le_bytes([lo, hi]) }),
+//!             _ => Err(serial::Error::new(serial::ErrorKind::Io(std::io::ErrorKind::InvalidData), "invalid reading"))
+//!         }
+//!     }
+//! }
+//!
+//! let port = serial::open("/dev/ttyACM0").unwrap();
+//! let mut port: TypedPort<_, Reading> = TypedPort::new(port);
+//!
+//! let reading = port.recv().unwrap();
+//! println!("{} = {}", reading.channel, reading.value);
+//! ```
+//!
+//! `Payload` is the serialization hook. It can be implemented by hand as above. With the `serde`
+//! feature, any type that implements `Serialize` and `DeserializeOwned` can be wrapped in
+//! `Postcard`, which serializes it in the compact [postcard](https://docs.rs/postcard) format:
+//!
+//! ```no_run
+//! # #[cfg(feature = "serde")]
+//! # fn main() {
+//! use serial::codec::cobs::{Postcard,TypedPort};
+//!
+//! // (channel, value), or any type that derives Serialize and Deserialize
+//! let port = serial::open("/dev/ttyACM0").unwrap();
+//! let mut port: TypedPort<_, Postcard<(u8, u16)>> = TypedPort::new(port);
+//!
+//! port.send(&Postcard((1, 512))).unwrap();
+//! # }
+//! # #[cfg(not(feature = "serde"))]
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "serde")]
+extern crate postcard;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+use std::io;
+use std::marker::PhantomData;
+
+use ::SerialPort;
+use super::{Decoder,Encoder,Framed};
+
+/// A type that can be sent as the payload of a COBS frame.
+pub trait Payload: Sized {
+    /// Appends the serialized payload to `buf`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the value can't be serialized.
+    fn encode_payload(&self, buf: &mut Vec<u8>) -> ::Result<()>;
+
+    /// Deserializes a payload.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if `bytes` isn't a valid payload.
+    fn decode_payload(bytes: &[u8]) -> ::Result<Self>;
+}
+
+impl Payload for Vec<u8> {
+    fn encode_payload(&self, buf: &mut Vec<u8>) -> ::Result<()> {
+        buf.extend_from_slice(self);
+        Ok(())
+    }
+
+    fn decode_payload(bytes: &[u8]) -> ::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// A payload that's serialized in the postcard format.
+///
+/// Decoding fails if the frame contains bytes after the serialized value.
+#[cfg(feature = "serde")]
+#[derive(Debug,Clone,Default,PartialEq,Eq,Hash)]
+pub struct Postcard<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: self::serde::Serialize + self::serde::de::DeserializeOwned> Payload for Postcard<T> {
+    fn encode_payload(&self, buf: &mut Vec<u8>) -> ::Result<()> {
+        let encoded = self::postcard::to_extend(&self.0, Vec::new()).map_err(|err| {
+            ::Error::new(::ErrorKind::InvalidInput, err.to_string()).with_operation("serialize payload")
+        })?;
+
+        buf.extend_from_slice(&encoded);
+        Ok(())
+    }
+
+    fn decode_payload(bytes: &[u8]) -> ::Result<Self> {
+        match self::postcard::take_from_bytes(bytes) {
+            Ok((value, &[])) => Ok(Postcard(value)),
+            Ok(_) => Err(::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), "trailing bytes after payload").with_operation("deserialize payload")),
+            Err(err) => Err(::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), err.to_string()).with_operation("deserialize payload"))
+        }
+    }
+}
+
+/// Computes the CRC-16/CCITT-FALSE of `data`.
+///
+/// The CRC is transmitted with its low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+/// Appends the COBS encoding of `data` to `buf`, without the delimiter.
+pub fn encode(data: &[u8], buf: &mut Vec<u8>) {
+    let mut code_index = buf.len();
+    buf.push(0);
+
+    let mut code = 1;
+
+    for (i, &byte) in data.iter().enumerate() {
+        if byte != 0 {
+            buf.push(byte);
+            code += 1;
+        }
+
+        // a full block only needs a new block header if more data follows
+        if byte == 0 || (code == 0xff && i + 1 < data.len()) {
+            buf[code_index] = code;
+            code_index = buf.len();
+            buf.push(0);
+            code = 1;
+        }
+    }
+
+    buf[code_index] = code;
+}
+
+/// Decodes COBS-encoded data without the delimiter.
+///
+/// Returns `None` if `data` isn't valid COBS.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+
+    while i < data.len() {
+        let code = data[i] as usize;
+
+        if code == 0 || i + code > data.len() {
+            return None;
+        }
+
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+
+    Some(out)
+}
+
+/// A codec for zero-delimited COBS frames with a CRC-16.
+///
+/// Frames that fail to decode or whose CRC doesn't match are removed from the buffer and reported
+/// as errors of kind `Io(InvalidData)`. Frames longer than the maximum length are discarded as they
+/// arrive.
+#[derive(Debug,Clone)]
+pub struct CobsCodec {
+    max_length: usize,
+    discarding: bool
+}
+
+impl CobsCodec {
+    /// Creates a COBS codec that accepts encoded frames of up to 1024 bytes.
+    pub fn new() -> Self {
+        CobsCodec::with_max_length(1024)
+    }
+
+    /// Creates a COBS codec that accepts encoded frames of up to `max_length` bytes.
+    pub fn with_max_length(max_length: usize) -> Self {
+        CobsCodec {
+            max_length,
+            discarding: false
+        }
+    }
+}
+
+impl Default for CobsCodec {
+    fn default() -> Self {
+        CobsCodec::new()
+    }
+}
+
+impl Decoder for CobsCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Vec<u8>>> {
+        loop {
+            let end = match buf.iter().position(|&b| b == 0) {
+                Some(end) => end,
+                None => {
+                    if self.discarding || buf.len() > self.max_length {
+                        buf.clear();
+                        self.discarding = true;
+                    }
+
+                    return Ok(None);
+                }
+            };
+
+            let frame: Vec<u8> = buf.drain(..end + 1).collect();
+
+            if self.discarding {
+                self.discarding = false;
+                return Err(invalid_data("COBS frame exceeds maximum length"));
+            }
+
+            // consecutive delimiters are used to flush a receiver's buffer
+            if end == 0 {
+                continue;
+            }
+
+            if end > self.max_length {
+                return Err(invalid_data("COBS frame exceeds maximum length"));
+            }
+
+            let mut data = decode(&frame[..end]).filter(|data| data.len() >= 2).ok_or_else(|| invalid_data("invalid COBS frame"))?;
+
+            let crc_start = data.len() - 2;
+            let crc = data[crc_start] as u16 | (data[crc_start + 1] as u16) << 8;
+            data.truncate(crc_start);
+
+            if crc16(&data) != crc {
+                return Err(invalid_data("COBS frame CRC mismatch"));
+            }
+
+            return Ok(Some(data));
+        }
+    }
+}
+
+impl Encoder for CobsCodec {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, data: &Vec<u8>, buf: &mut Vec<u8>) -> ::Result<()> {
+        let crc = crc16(data);
+
+        let mut frame = Vec::with_capacity(data.len() + 2);
+        frame.extend_from_slice(data);
+        frame.push(crc as u8);
+        frame.push((crc >> 8) as u8);
+
+        encode(&frame, buf);
+        buf.push(0);
+        Ok(())
+    }
+}
+
+/// A serial port that sends and receives typed messages in COBS frames.
+pub struct TypedPort<P, T> {
+    framed: Framed<P, CobsCodec>,
+    payload: PhantomData<fn(T) -> T>
+}
+
+impl<P: SerialPort, T: Payload> TypedPort<P, T> {
+    /// Creates a typed port that sends and receives messages of type `T` on `port`.
+    pub fn new(port: P) -> Self {
+        TypedPort::with_codec(port, CobsCodec::new())
+    }
+
+    /// Creates a typed port with a configured codec, e.g., to allow longer frames.
+    pub fn with_codec(port: P, codec: CobsCodec) -> Self {
+        TypedPort {
+            framed: Framed::new(port, codec),
+            payload: PhantomData
+        }
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        self.framed.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        self.framed.get_mut()
+    }
+
+    /// Returns the underlying port.
+    ///
+    /// Bytes that have been received but not yet decoded are discarded.
+    pub fn into_inner(self) -> P {
+        self.framed.into_parts().0
+    }
+
+    /// Serializes and sends a message.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error returned by `Payload::encode_payload()`.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn send(&mut self, message: &T) -> ::Result<()> {
+        let mut payload = Vec::new();
+        message.encode_payload(&mut payload)?;
+
+        self.framed.write_frame(&payload)
+    }
+
+    /// Receives and deserializes the next message.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(InvalidData)` if a corrupt frame was received. The next call continues with the
+    ///   following frame.
+    /// * Any error returned by `Payload::decode_payload()`.
+    /// * Any error returned by `Framed::read_frame()`.
+    pub fn recv(&mut self) -> ::Result<T> {
+        let payload = self.framed.read_frame()?;
+        T::decode_payload(&payload)
+    }
+}
+
+fn invalid_data(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("decode COBS frame")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{crc16,decode,encode,CobsCodec,TypedPort};
+    use super::super::{Decoder,Encoder};
+    use ::testing::ScriptedDevice;
+
+    fn cobs(data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode(data, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn cobs_matches_reference_encodings() {
+        assert_eq!(cobs(&[]), vec![0x01]);
+        assert_eq!(cobs(&[0x00]), vec![0x01, 0x01]);
+        assert_eq!(cobs(&[0x11, 0x22, 0x00, 0x33]), vec![0x03, 0x11, 0x22, 0x02, 0x33]);
+        assert_eq!(cobs(&[0x11, 0x00, 0x00, 0x00]), vec![0x02, 0x11, 0x01, 0x01, 0x01]);
+
+        let block: Vec<u8> = (1..255).collect();
+        assert_eq!(cobs(&block)[0], 0xff);
+        assert_eq!(cobs(&block).len(), 255);
+    }
+
+    #[test]
+    fn cobs_round_trips_long_runs() {
+        for &len in &[253, 254, 255, 256, 600] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 255 + 1) as u8).collect();
+            let encoded = cobs(&data);
+
+            assert!(!encoded.contains(&0));
+            assert_eq!(decode(&encoded), Some(data));
+        }
+    }
+
+    #[test]
+    fn crc16_matches_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn codec_round_trips_and_rejects_corruption() {
+        let mut codec = CobsCodec::new();
+        let mut buf = vec![0];
+
+        codec.encode(&vec![1, 0, 2], &mut buf).unwrap();
+        let corrupt = buf.len();
+        codec.encode(&vec![3], &mut buf).unwrap();
+        buf[corrupt] ^= 0x40;
+        codec.encode(&vec![], &mut buf).unwrap();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec![1, 0, 2]));
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec![]));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn codec_discards_overlong_frame() {
+        let mut codec = CobsCodec::with_max_length(4);
+        let mut buf = vec![9; 6];
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&[9, 0]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        codec.encode(&vec![7], &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec![7]));
+    }
+
+    #[test]
+    fn typed_port_sends_and_receives_payloads() {
+        let mut frame = Vec::new();
+        CobsCodec::new().encode(&vec![0xaa, 0x00], &mut frame).unwrap();
+
+        let mut device = ScriptedDevice::new();
+        device.push_input(&frame);
+
+        let mut port: TypedPort<_, Vec<u8>> = TypedPort::new(device);
+        assert_eq!(port.recv().unwrap(), vec![0xaa, 0x00]);
+
+        port.send(&vec![0xaa, 0x00]).unwrap();
+        assert_eq!(port.get_ref().output(), frame);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn typed_port_serializes_serde_payloads() {
+        use super::Postcard;
+
+        let mut port: TypedPort<_, Postcard<(u8, String)>> = TypedPort::new(ScriptedDevice::new());
+        port.send(&Postcard((7, "on".to_string()))).unwrap();
+
+        let mut frame = Vec::new();
+        CobsCodec::new().encode(&vec![7, 2, b'o', b'n'], &mut frame).unwrap();
+        assert_eq!(port.get_ref().output(), frame);
+
+        port.get_mut().push_input(&frame);
+        assert_eq!(port.recv().unwrap(), Postcard((7, "on".to_string())));
+
+        frame.clear();
+        CobsCodec::new().encode(&vec![7, 2, b'o', b'n', 0xff], &mut frame).unwrap();
+        port.get_mut().push_input(&frame);
+        assert_eq!(port.recv().unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,298 @@
+//! DNP3 link-layer framing.
+//!
+//! A DNP3 link-layer frame starts with the sync bytes `0x05 0x64`, followed by a length, a control
+//! byte, and the destination and source addresses. The header is protected by a CRC, and the user
+//! data that follows is split into blocks of up to 16 bytes, each protected by its own CRC.
+//!
+//! `Dnp3Codec` delimits and validates these frames without interpreting the transport or
+//! application layers.
+
+use std::io;
+
+use super::{Decoder,Encoder};
+
+/// Sync bytes at the start of every frame.
+const SYNC: [u8; 2] = [0x05, 0x64];
+
+/// Length of the header, including its CRC.
+const HEADER_LEN: usize = 10;
+
+/// Maximum number of user data bytes in a block.
+const BLOCK_LEN: usize = 16;
+
+/// Maximum number of user data bytes in a frame.
+pub const MAX_DATA_LEN: usize = 250;
+
+/// A DNP3 link-layer frame.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Dnp3Frame {
+    /// The control byte, which holds the direction, primary, and function code bits.
+    pub control: u8,
+
+    /// The destination address.
+    pub destination: u16,
+
+    /// The source address.
+    pub source: u16,
+
+    /// The user data, without the block CRCs.
+    pub data: Vec<u8>
+}
+
+impl Dnp3Frame {
+    /// Returns `true` if the frame was sent by a master station (DIR bit).
+    pub fn is_from_master(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    /// Returns `true` if the frame was sent by the primary station of a transaction (PRM bit).
+    pub fn is_primary(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    /// Returns the link-layer function code.
+    pub fn function_code(&self) -> u8 {
+        self.control & 0x0f
+    }
+}
+
+/// Computes the DNP3 CRC-16 of `data`.
+///
+/// The CRC is transmitted with its low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for &byte in data {
+        crc ^= byte as u16;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xa6bc } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+/// A codec for DNP3 link-layer frames.
+///
+/// The decoder searches for the sync bytes and validates the header CRC, so it resynchronizes
+/// after noise or a partial frame. A frame whose header is valid but whose user data fails a block
+/// CRC is removed from the buffer and reported as an error of kind `Io(InvalidData)`.
+#[derive(Debug,Default,Clone)]
+pub struct Dnp3Codec {
+    _private: ()
+}
+
+impl Dnp3Codec {
+    /// Creates a DNP3 codec.
+    pub fn new() -> Self {
+        Dnp3Codec::default()
+    }
+}
+
+impl Decoder for Dnp3Codec {
+    type Item = Dnp3Frame;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Dnp3Frame>> {
+        loop {
+            match buf.windows(2).position(|window| window == SYNC) {
+                Some(start) => {
+                    buf.drain(..start);
+                },
+                None => {
+                    // keep a trailing first sync byte, since the second may not have arrived yet
+                    let keep = if buf.last() == Some(&SYNC[0]) { 1 } else { 0 };
+                    let len = buf.len();
+                    buf.drain(..len - keep);
+                    return Ok(None);
+                }
+            }
+
+            if buf.len() < HEADER_LEN {
+                return Ok(None);
+            }
+
+            // a header that fails its CRC is most likely a false sync in other data
+            if buf[2] < 5 || crc16(&buf[..8]) != read_u16(&buf[8..10]) {
+                buf.drain(..1);
+                continue;
+            }
+
+            let data_len = buf[2] as usize - 5;
+            let frame_len = HEADER_LEN + data_len + 2 * data_len.div_ceil(BLOCK_LEN);
+
+            if buf.len() < frame_len {
+                return Ok(None);
+            }
+
+            let bytes: Vec<u8> = buf.drain(..frame_len).collect();
+            let mut data = Vec::with_capacity(data_len);
+
+            for block in bytes[HEADER_LEN..].chunks(BLOCK_LEN + 2) {
+                let (block_data, crc) = block.split_at(block.len() - 2);
+
+                if crc16(block_data) != read_u16(crc) {
+                    return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), "DNP3 data block CRC mismatch").with_operation("decode DNP3 frame"));
+                }
+
+                data.extend_from_slice(block_data);
+            }
+
+            return Ok(Some(Dnp3Frame {
+                control: bytes[3],
+                destination: read_u16(&bytes[4..6]),
+                source: read_u16(&bytes[6..8]),
+                data
+            }));
+        }
+    }
+}
+
+impl Encoder for Dnp3Codec {
+    type Item = Dnp3Frame;
+
+    fn encode(&mut self, frame: &Dnp3Frame, buf: &mut Vec<u8>) -> ::Result<()> {
+        if frame.data.len() > MAX_DATA_LEN {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "DNP3 user data exceeds 250 bytes").with_operation("encode DNP3 frame"));
+        }
+
+        let start = buf.len();
+        buf.extend_from_slice(&SYNC);
+        buf.push((frame.data.len() + 5) as u8);
+        buf.push(frame.control);
+        write_u16(buf, frame.destination);
+        write_u16(buf, frame.source);
+
+        let crc = crc16(&buf[start..]);
+        write_u16(buf, crc);
+
+        for block in frame.data.chunks(BLOCK_LEN) {
+            buf.extend_from_slice(block);
+            write_u16(buf, crc16(block));
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn write_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push(value as u8);
+    buf.push((value >> 8) as u8);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{crc16,Dnp3Codec,Dnp3Frame};
+    use super::super::{Decoder,Encoder,Framed};
+    use ::testing::ScriptedDevice;
+
+    fn frame(len: usize) -> Dnp3Frame {
+        Dnp3Frame {
+            control: 0xc4,
+            destination: 1,
+            source: 1024,
+            data: (0..len as u8).collect()
+        }
+    }
+
+    fn encode(frame: &Dnp3Frame) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Dnp3Codec::new().encode(frame, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn crc16_matches_check_value() {
+        assert_eq!(crc16(b"123456789"), 0xea82);
+    }
+
+    #[test]
+    fn encode_matches_reference_header() {
+        // a request link status frame from master 1 to outstation 1024
+        let frame = Dnp3Frame { control: 0xc9, destination: 1024, source: 1, data: vec![] };
+        let bytes = encode(&frame);
+
+        assert_eq!(&bytes[..8], &[0x05, 0x64, 0x05, 0xc9, 0x00, 0x04, 0x01, 0x00]);
+        assert_eq!(bytes.len(), 10);
+        assert!(frame.is_from_master());
+        assert!(frame.is_primary());
+        assert_eq!(frame.function_code(), 9);
+    }
+
+    #[test]
+    fn decode_round_trips_frames_with_multiple_blocks() {
+        for &len in &[0, 1, 16, 17, 250] {
+            let mut buf = encode(&frame(len));
+            assert_eq!(buf.len(), 10 + len + 2 * len.div_ceil(16));
+
+            assert_eq!(Dnp3Codec::new().decode(&mut buf).unwrap(), Some(frame(len)));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_waits_for_complete_frame() {
+        let bytes = encode(&frame(20));
+        let mut codec = Dnp3Codec::new();
+        let mut buf = Vec::new();
+
+        for &byte in &bytes[..bytes.len() - 1] {
+            buf.push(byte);
+            assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        }
+
+        buf.push(bytes[bytes.len() - 1]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame(20)));
+    }
+
+    #[test]
+    fn decode_resynchronizes_after_noise_and_false_sync() {
+        let mut buf = vec![0xff, 0x05, 0x64, 0x05, 0x00, 0x05];
+        buf.extend(encode(&frame(3)));
+
+        assert_eq!(Dnp3Codec::new().decode(&mut buf).unwrap(), Some(frame(3)));
+    }
+
+    #[test]
+    fn decode_reports_corrupt_data_block_and_continues() {
+        let mut buf = encode(&frame(4));
+        buf[11] ^= 0x01;
+        buf.extend(encode(&frame(2)));
+
+        let mut codec = Dnp3Codec::new();
+        let err = codec.decode(&mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame(2)));
+    }
+
+    #[test]
+    fn encode_rejects_oversized_data() {
+        let mut buf = Vec::new();
+        let err = Dnp3Codec::new().encode(&frame(251), &mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn framed_reads_and_writes_frames() {
+        let bytes = encode(&frame(30));
+
+        let mut device = ScriptedDevice::new();
+        device.push_input(&bytes[..7]);
+        device.push_input(&bytes[7..]);
+
+        let mut framed = Framed::new(device, Dnp3Codec::new());
+        assert_eq!(framed.read_frame().unwrap(), frame(30));
+
+        framed.write_frame(&frame(1)).unwrap();
+        assert_eq!(framed.get_ref().output(), encode(&frame(1)));
+    }
+}
@@ -0,0 +1,400 @@
+//! Intel HEX records.
+//!
+//! `IntelHexCodec` reads and writes the records of an Intel HEX file one line at a time, so that
+//! an image can be streamed to or from a monitor ROM or EPROM programmer without holding the whole
+//! file. `RecordBuilder` splits a memory image into records, inserting extended linear address
+//! records as needed, and `Loader` resolves the absolute address of the data in received records.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::codec::{self,Framed};
+//! use serial::codec::ihex::{IntelHexCodec,RecordBuilder};
+//!
+//! let image = std::fs::read("firmware.bin").unwrap();
+//!
+//! let mut builder = RecordBuilder::new(16);
+//! let mut records = builder.data(0x0800_0000, &image);
+//! records.extend(builder.finish(None));
+//!
+//! // wait for the programmer's prompt after each record
+//! let mut framed = Framed::new(serial::open("/dev/ttyUSB0").unwrap(), IntelHexCodec::new());
+//! framed.write_frames(records, |port, _| codec::expect(port, b">", Duration::from_secs(1))).unwrap();
+//! ```
+
+use std::fmt;
+use std::io;
+
+use super::{Decoder,Encoder};
+
+/// A data record.
+pub const DATA: u8 = 0x00;
+
+/// An end of file record.
+pub const END_OF_FILE: u8 = 0x01;
+
+/// An extended segment address record, whose data is bits 4–19 of the base address.
+pub const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+
+/// A start segment address record, whose data is the initial CS:IP register values.
+pub const START_SEGMENT_ADDRESS: u8 = 0x03;
+
+/// An extended linear address record, whose data is bits 16–31 of the base address.
+pub const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+
+/// A start linear address record, whose data is the 32-bit entry point.
+pub const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// A record of an Intel HEX file.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Record {
+    /// The record type, e.g. `DATA` or `END_OF_FILE`.
+    pub record_type: u8,
+
+    /// The 16-bit address offset of a data record. It's zero for other record types.
+    pub offset: u16,
+
+    /// The data of the record. At most 255 bytes can be encoded.
+    pub data: Vec<u8>
+}
+
+impl Record {
+    /// Creates a data record.
+    pub fn data(offset: u16, data: &[u8]) -> Self {
+        Record { record_type: DATA, offset, data: data.to_vec() }
+    }
+
+    /// Creates an end of file record.
+    pub fn end_of_file() -> Self {
+        Record { record_type: END_OF_FILE, offset: 0, data: Vec::new() }
+    }
+
+    /// Creates an extended linear address record for a base address of `upper << 16`.
+    pub fn extended_linear_address(upper: u16) -> Self {
+        Record { record_type: EXTENDED_LINEAR_ADDRESS, offset: 0, data: upper.to_be_bytes().to_vec() }
+    }
+
+    /// Creates a start linear address record.
+    pub fn start_linear_address(address: u32) -> Self {
+        Record { record_type: START_LINEAR_ADDRESS, offset: 0, data: address.to_be_bytes().to_vec() }
+    }
+
+    /// Parses a record from a line of text, including the leading `:`.
+    ///
+    /// Whitespace around the record is ignored.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if the line isn't a well-formed record, if its
+    /// checksum doesn't match, or if a record other than a data record has the wrong length.
+    pub fn parse(line: &[u8]) -> ::Result<Self> {
+        let line = super::trim(line);
+
+        if line.first() != Some(&b':') {
+            return Err(invalid_record("missing start code"));
+        }
+
+        let bytes = super::parse_hex(&line[1..]).ok_or_else(|| invalid_record("invalid hex digits"))?;
+
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+            return Err(invalid_record("wrong record length"));
+        }
+
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(invalid_record("checksum mismatch"));
+        }
+
+        let record = Record {
+            record_type: bytes[3],
+            offset: u16::from_be_bytes([bytes[1], bytes[2]]),
+            data: bytes[4..bytes.len() - 1].to_vec()
+        };
+
+        let expected = match record.record_type {
+            DATA => None,
+            END_OF_FILE => Some(0),
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => Some(2),
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => Some(4),
+            _ => return Err(invalid_record("unknown record type"))
+        };
+
+        if expected.is_some_and(|len| record.data.len() != len) {
+            return Err(invalid_record("wrong record length"));
+        }
+
+        Ok(record)
+    }
+
+    fn checksum(&self) -> u8 {
+        let sum = self.data.iter().fold(self.data.len() as u8, |sum, &b| sum.wrapping_add(b))
+            .wrapping_add((self.offset >> 8) as u8)
+            .wrapping_add(self.offset as u8)
+            .wrapping_add(self.record_type);
+
+        sum.wrapping_neg()
+    }
+}
+
+impl fmt::Display for Record {
+    /// Formats the record as a line of text with uppercase hex digits, without a line terminator.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":{:02X}{:04X}{:02X}", self.data.len(), self.offset, self.record_type)?;
+
+        for byte in &self.data {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        write!(f, "{:02X}", self.checksum())
+    }
+}
+
+/// Splits a memory image into Intel HEX records.
+///
+/// Data records don't cross 64 KiB boundaries. An extended linear address record is emitted
+/// before the first data record and whenever the upper 16 bits of the address change.
+#[derive(Debug,Clone)]
+pub struct RecordBuilder {
+    record_size: usize,
+    upper: Option<u16>
+}
+
+impl RecordBuilder {
+    /// Creates a builder that puts up to `record_size` bytes in each data record.
+    ///
+    /// The record size is limited to 1–255 bytes. 16 and 32 are the most common sizes.
+    pub fn new(record_size: usize) -> Self {
+        RecordBuilder {
+            record_size: record_size.clamp(1, 255),
+            upper: None
+        }
+    }
+
+    /// Returns the records for `data` at `address`.
+    ///
+    /// Consecutive calls can continue an image in pieces, e.g. as it's read from a file. Data
+    /// that extends past the end of the 32-bit address space wraps around to address zero.
+    pub fn data(&mut self, address: u32, data: &[u8]) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut address = address;
+        let mut data = data;
+
+        while !data.is_empty() {
+            let upper = (address >> 16) as u16;
+            let offset = address as u16;
+
+            if self.upper != Some(upper) {
+                records.push(Record::extended_linear_address(upper));
+                self.upper = Some(upper);
+            }
+
+            let room = 0x1_0000 - offset as usize;
+            let len = data.len().min(self.record_size).min(room);
+
+            records.push(Record::data(offset, &data[..len]));
+
+            address = address.wrapping_add(len as u32);
+            data = &data[len..];
+        }
+
+        records
+    }
+
+    /// Returns the records that end the file: a start linear address record if `start` is given,
+    /// followed by the end of file record.
+    pub fn finish(&mut self, start: Option<u32>) -> Vec<Record> {
+        self.upper = None;
+
+        start.map(Record::start_linear_address).into_iter().chain(Some(Record::end_of_file())).collect()
+    }
+}
+
+/// Resolves the absolute addresses of data in a sequence of Intel HEX records.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Loader {
+    base: u32,
+    start: Option<u32>,
+    finished: bool
+}
+
+impl Loader {
+    /// Creates a loader with a base address of zero.
+    pub fn new() -> Self {
+        Loader::default()
+    }
+
+    /// Processes a record.
+    ///
+    /// Returns the absolute address and the data of a data record, and `None` for other record
+    /// types, which update the base address or start address. Addresses wrap around within the
+    /// 32-bit address space.
+    pub fn load<'a>(&mut self, record: &'a Record) -> Option<(u32, &'a [u8])> {
+        let word = |data: &[u8]| data.iter().fold(0u32, |word, &b| (word << 8) | u32::from(b));
+
+        match record.record_type {
+            DATA => return Some((self.base.wrapping_add(u32::from(record.offset)), &record.data)),
+            END_OF_FILE => self.finished = true,
+            EXTENDED_SEGMENT_ADDRESS => self.base = word(&record.data) << 4,
+            EXTENDED_LINEAR_ADDRESS => self.base = word(&record.data) << 16,
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => self.start = Some(word(&record.data)),
+            _ => ()
+        }
+
+        None
+    }
+
+    /// Returns the start address, if a start address record has been loaded.
+    ///
+    /// For a start segment address record, the CS register value is in the upper 16 bits and the
+    /// IP register value is in the lower 16 bits.
+    pub fn start_address(&self) -> Option<u32> {
+        self.start
+    }
+
+    /// Returns `true` if the end of file record has been loaded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// A codec for the records of an Intel HEX file, one per line.
+///
+/// The decoder ignores anything before a record's `:` start code, such as prompts echoed by a
+/// monitor ROM. The encoder terminates each record with `\r\n` by default.
+#[derive(Debug,Clone)]
+pub struct IntelHexCodec {
+    terminator: &'static str
+}
+
+impl IntelHexCodec {
+    /// Creates an Intel HEX codec.
+    pub fn new() -> Self {
+        IntelHexCodec { terminator: "\r\n" }
+    }
+
+    /// Sets the line terminator appended by the encoder.
+    pub fn set_terminator(&mut self, terminator: &'static str) {
+        self.terminator = terminator;
+    }
+}
+
+impl Default for IntelHexCodec {
+    fn default() -> Self {
+        IntelHexCodec::new()
+    }
+}
+
+impl Decoder for IntelHexCodec {
+    type Item = Record;
+
+    /// Decodes the next record.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if a line that starts with `:` isn't a valid
+    /// record. The line is discarded, and decoding continues with the next line.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Record>> {
+        super::decode_line(buf, b':', Record::parse)
+    }
+}
+
+impl Encoder for IntelHexCodec {
+    type Item = Record;
+
+    /// Encodes a record as a line of text.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the record holds more than 255 bytes.
+    fn encode(&mut self, record: &Record, buf: &mut Vec<u8>) -> ::Result<()> {
+        if record.data.len() > 255 {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "record is too long").with_operation("encode Intel HEX record"));
+        }
+
+        buf.extend_from_slice(record.to_string().as_bytes());
+        buf.extend_from_slice(self.terminator.as_bytes());
+        Ok(())
+    }
+}
+
+fn invalid_record(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("decode Intel HEX record")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{IntelHexCodec,Loader,Record,RecordBuilder,DATA,EXTENDED_LINEAR_ADDRESS};
+    use super::super::{expect,Decoder,Encoder,Framed};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn record_round_trips_through_text() {
+        let line = b":10010000214601360121470136007EFE09D2190140\r\n";
+        let record = Record::parse(line).unwrap();
+
+        assert_eq!(record.record_type, DATA);
+        assert_eq!(record.offset, 0x0100);
+        assert_eq!(record.data.len(), 16);
+        assert_eq!(record.to_string(), ":10010000214601360121470136007EFE09D2190140");
+        assert_eq!(Record::end_of_file().to_string(), ":00000001FF");
+    }
+
+    #[test]
+    fn record_rejects_bad_checksum() {
+        let err = Record::parse(b":10010000214601360121470136007EFE09D2190141").unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn builder_splits_records_at_64k_boundaries() {
+        let mut builder = RecordBuilder::new(16);
+        let mut records = builder.data(0x0001_fff8, &[0xaa; 24]);
+        records.extend(builder.finish(Some(0x0002_0000)));
+
+        let types: Vec<u8> = records.iter().map(|record| record.record_type).collect();
+        assert_eq!(types, vec![EXTENDED_LINEAR_ADDRESS, DATA, EXTENDED_LINEAR_ADDRESS, DATA, 0x05, 0x01]);
+        assert_eq!(records[1].data.len(), 8);
+
+        let mut loader = Loader::new();
+        let loaded: Vec<(u32, usize)> = records.iter().filter_map(|record| loader.load(record)).map(|(address, data)| (address, data.len())).collect();
+
+        assert_eq!(loaded, vec![(0x0001_fff8, 8), (0x0002_0000, 16)]);
+        assert_eq!(loader.start_address(), Some(0x0002_0000));
+        assert!(loader.is_finished());
+    }
+
+    #[test]
+    fn encode_rejects_oversized_record() {
+        let mut buf = Vec::new();
+        let err = IntelHexCodec::new().encode(&Record::data(0, &[0; 256]), &mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_skips_prompts_between_records() {
+        let mut codec = IntelHexCodec::new();
+        let mut buf = b"> :00000001FF\r\n> :0000".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Record::end_of_file()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn write_frames_waits_for_handshake_after_each_record() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"*");
+
+        let mut framed = Framed::new(device, IntelHexCodec::new());
+        let records = vec![Record::data(0, b"ab"), Record::end_of_file()];
+
+        let err = framed.write_frames(records, |port, _| expect(port, b"*", Duration::from_millis(10))).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+
+        let output = framed.get_ref().output();
+        assert_eq!(output, b":0200000061623B\r\n:00000001FF\r\n".to_vec());
+    }
+}
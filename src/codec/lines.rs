@@ -0,0 +1,200 @@
+//! Line-delimited text.
+
+use std::io;
+
+use super::{Decoder,Encoder};
+
+/// A codec for lines of text terminated by `\n` or `\r\n`.
+///
+/// Decoded lines don't include the line terminator. Invalid UTF-8 is replaced with `U+FFFD`, since
+/// devices that print text over a serial port rarely guarantee a valid encoding. The encoder
+/// terminates each line with `\r\n` by default.
+#[derive(Debug,Clone)]
+pub struct LineCodec {
+    max_length: usize,
+    terminator: &'static str,
+    discarding: bool
+}
+
+impl LineCodec {
+    /// Creates a line codec that accepts lines of up to 1024 bytes.
+    pub fn new() -> Self {
+        LineCodec::with_max_length(1024)
+    }
+
+    /// Creates a line codec that accepts lines of up to `max_length` bytes.
+    pub fn with_max_length(max_length: usize) -> Self {
+        LineCodec {
+            max_length,
+            terminator: "\r\n",
+            discarding: false
+        }
+    }
+
+    /// Returns the maximum length of a line.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Sets the line terminator appended by the encoder.
+    pub fn set_terminator(&mut self, terminator: &'static str) {
+        self.terminator = terminator;
+    }
+}
+
+impl Default for LineCodec {
+    fn default() -> Self {
+        LineCodec::new()
+    }
+}
+
+impl Decoder for LineCodec {
+    type Item = String;
+
+    /// Decodes the next line.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if a line exceeds the maximum length. The rest
+    /// of the line is discarded as it arrives, and decoding continues with the next line.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<String>> {
+        loop {
+            let end = buf.iter().position(|&b| b == b'\n');
+
+            if self.discarding {
+                match end {
+                    Some(end) => {
+                        buf.drain(..end + 1);
+                        self.discarding = false;
+                        continue;
+                    },
+                    None => {
+                        buf.clear();
+                        return Ok(None);
+                    }
+                }
+            }
+
+            let end = match end {
+                Some(end) => end,
+                None if buf.len() > self.max_length => {
+                    buf.clear();
+                    self.discarding = true;
+                    return Err(too_long());
+                },
+                None => return Ok(None)
+            };
+
+            let mut line: Vec<u8> = buf.drain(..end + 1).collect();
+            line.pop();
+
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            if line.len() > self.max_length {
+                return Err(too_long());
+            }
+
+            return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+        }
+    }
+}
+
+impl Encoder for LineCodec {
+    type Item = String;
+
+    fn encode(&mut self, line: &String, buf: &mut Vec<u8>) -> ::Result<()> {
+        if line.contains('\n') {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "line contains a line feed").with_operation("encode line"));
+        }
+
+        buf.extend_from_slice(line.as_bytes());
+        buf.extend_from_slice(self.terminator.as_bytes());
+        Ok(())
+    }
+}
+
+fn too_long() -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), "line exceeds maximum length").with_operation("decode line")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::LineCodec;
+    use super::super::{Decoder,Encoder,Framed};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn decode_splits_lines_and_strips_terminators() {
+        let mut codec = LineCodec::new();
+        let mut buf = b"one\r\ntwo\nthr".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("one".to_string()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("two".to_string()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"ee\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("three".to_string()));
+    }
+
+    #[test]
+    fn decode_discards_overlong_line() {
+        let mut codec = LineCodec::with_max_length(4);
+        let mut buf = b"abcdef".to_vec();
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+
+        buf.extend_from_slice(b"gh\nok\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("ok".to_string()));
+    }
+
+    #[test]
+    fn encode_appends_terminator() {
+        let mut codec = LineCodec::new();
+        let mut buf = Vec::new();
+
+        codec.encode(&"AT".to_string(), &mut buf).unwrap();
+        codec.set_terminator("\r");
+        codec.encode(&"ATZ".to_string(), &mut buf).unwrap();
+
+        assert_eq!(buf, b"AT\r\nATZ\r".to_vec());
+        assert_eq!(codec.encode(&"a\nb".to_string(), &mut buf).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn frames_skips_corrupt_frames_and_ends_on_fatal_error() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"toolong
+ok
+");
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let mut framed = Framed::new(device, LineCodec::with_max_length(4));
+        let mut frames = framed.frames();
+
+        assert_eq!(frames.next().unwrap().unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+        assert_eq!(frames.next().unwrap().unwrap(), "ok");
+        assert!(frames.next().unwrap().is_err());
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn framed_decodes_fed_data_without_reading() {
+        let mut device = ScriptedDevice::new();
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let mut framed = Framed::new(device, LineCodec::new());
+        framed.feed(b"one\ntw");
+
+        assert_eq!(framed.decode_buffered().unwrap(), Some("one".to_string()));
+        assert_eq!(framed.decode_buffered().unwrap(), None);
+
+        framed.feed(b"o\n");
+        assert_eq!(framed.decode_buffered().unwrap(), Some("two".to_string()));
+    }
+}
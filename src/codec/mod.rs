@@ -0,0 +1,358 @@
+//! Framing codecs that delimit messages in a serial byte stream.
+//!
+//! A codec splits received bytes into frames with a `Decoder` and serializes frames with an
+//! `Encoder`. `Framed` combines a port with a codec to read and write whole frames:
+//!
+//! ```no_run
+//! use serial::codec::Framed;
+//! use serial::codec::dnp3::Dnp3Codec;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let mut framed = Framed::new(port, Dnp3Codec::new());
+//!
+//! loop {
+//!     let frame = framed.read_frame().unwrap();
+//!     println!("{} -> {}: {:?}", frame.source, frame.destination, frame.data);
+//! }
+//! ```
+
+use std::io;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+
+pub mod cobs;
+pub mod dnp3;
+pub mod ihex;
+pub mod lines;
+pub mod plotter;
+pub mod srec;
+
+/// Splits a byte stream into frames.
+pub trait Decoder {
+    /// The type of decoded frames.
+    type Item;
+
+    /// Decodes a frame from the start of `buf`.
+    ///
+    /// If `buf` contains a complete frame, the frame's bytes are removed from `buf` and the frame
+    /// is returned. If it doesn't contain a complete frame yet, `None` is returned and `buf` may
+    /// be left unchanged until more data has been appended. Bytes that can't be part of a frame
+    /// should be removed so that decoding resynchronizes with the stream.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if a corrupt frame was found. The corrupt frame should be removed from
+    /// `buf`, so that decoding can continue with the next frame.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Self::Item>>;
+}
+
+/// Serializes frames into bytes.
+pub trait Encoder {
+    /// The type of frames to encode.
+    type Item;
+
+    /// Appends the encoding of `item` to `buf`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the frame can't be encoded.
+    fn encode(&mut self, item: &Self::Item, buf: &mut Vec<u8>) -> ::Result<()>;
+}
+
+/// A serial port that reads and writes frames with a codec.
+pub struct Framed<P, C> {
+    port: P,
+    codec: C,
+    buffer: Vec<u8>
+}
+
+impl<P: SerialPort, C> Framed<P, C> {
+    /// Combines a port with a codec.
+    pub fn new(port: P, codec: C) -> Self {
+        Framed {
+            port,
+            codec,
+            buffer: Vec::new()
+        }
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns a mutable reference to the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Returns the bytes that have been received but not yet decoded.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Appends bytes that were read from the port by other means to the read buffer.
+    ///
+    /// Together with `decode_buffered()`, this lets an event loop that reads from the port itself,
+    /// e.g., with `TTYPort::try_read()` after the port is reported readable, decode frames without
+    /// blocking in `read_frame()`.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decodes the next frame from the bytes already in the read buffer, without reading from the
+    /// port.
+    ///
+    /// Returns `None` if the buffer doesn't contain a complete frame.
+    ///
+    /// ## Errors
+    ///
+    /// Returns any error returned by the decoder for a corrupt frame.
+    pub fn decode_buffered(&mut self) -> ::Result<Option<C::Item>> where C: Decoder {
+        self.codec.decode(&mut self.buffer)
+    }
+
+    /// Returns the underlying port and codec.
+    ///
+    /// Bytes that have been received but not yet decoded are discarded.
+    pub fn into_parts(self) -> (P, C) {
+        (self.port, self.codec)
+    }
+
+    /// Reads the next frame.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no data was received within the port's timeout.
+    /// * `Io(UnexpectedEof)` if the port reached end of file.
+    /// * Any error returned by the decoder for a corrupt frame.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn read_frame(&mut self) -> ::Result<C::Item> where C: Decoder {
+        let mut buf = [0; 256];
+
+        loop {
+            if let Some(item) = self.codec.decode(&mut self.buffer)? {
+                return Ok(item);
+            }
+
+            let len = match self.port.read(&mut buf) {
+                Ok(len) => len,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(::Error::from(err))
+            };
+
+            if len == 0 {
+                return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::UnexpectedEof), "end of file").with_operation("read frame"));
+            }
+
+            self.buffer.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    /// Returns an iterator over received frames.
+    ///
+    /// Timeouts and corrupt frames are yielded as errors without ending the iteration. Any other
+    /// error is yielded once and ends it, and end of file ends it without an error.
+    ///
+    /// ```no_run
+    /// use serial::codec::Framed;
+    /// use serial::codec::lines::LineCodec;
+    ///
+    /// let port = serial::open("/dev/ttyUSB0").unwrap();
+    /// let mut framed = Framed::new(port, LineCodec::new());
+    ///
+    /// for line in framed.frames().filter_map(Result::ok) {
+    ///     println!("{}", line);
+    /// }
+    /// ```
+    pub fn frames(&mut self) -> Frames<'_, P, C> where C: Decoder {
+        Frames {
+            framed: self,
+            done: false
+        }
+    }
+
+    /// Encodes and writes a frame.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error returned by the encoder.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn write_frame(&mut self, item: &C::Item) -> ::Result<()> where C: Encoder {
+        let mut buf = Vec::new();
+        self.codec.encode(item, &mut buf)?;
+
+        self.port.write_all(&buf)?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// Encodes and writes frames one at a time, calling `handshake` after each frame is written.
+    ///
+    /// The handshake can wait for a device to acknowledge each frame before the next one is sent,
+    /// e.g. with [`expect()`](fn.expect.html), as monitor ROMs and EPROM programmers often require.
+    /// Returns the number of frames written.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the first error from writing a frame or from `handshake`, after which no more
+    /// frames are written.
+    pub fn write_frames<I, F>(&mut self, items: I, mut handshake: F) -> ::Result<usize>
+        where C: Encoder,
+              I: IntoIterator<Item = C::Item>,
+              F: FnMut(&mut P, &C::Item) -> ::Result<()>
+    {
+        let mut count = 0;
+
+        for item in items {
+            self.write_frame(&item)?;
+            handshake(&mut self.port, &item)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Reads from a port until `reply` is received.
+///
+/// Data is read one byte at a time, so nothing after the reply is consumed. Data received before
+/// the reply is discarded.
+///
+/// ## Errors
+///
+/// * `Io(TimedOut)` if the reply wasn't received within `timeout`.
+/// * `Io(UnexpectedEof)` if the port reached end of file.
+/// * `NoDevice` if the device was disconnected.
+/// * `Io` for any other type of I/O error.
+pub fn expect<P: SerialPort + ?Sized>(port: &mut P, reply: &[u8], timeout: Duration) -> ::Result<()> {
+    let mut received = Vec::with_capacity(reply.len());
+
+    if expect_until(port, reply, &mut received, Instant::now() + timeout)? {
+        Ok(())
+    }
+    else {
+        Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "reply not received").with_operation("expect"))
+    }
+}
+
+/// Reads from a port until `reply` is received or `deadline` passes.
+///
+/// `received` holds the last bytes read, so that a reply that's split across calls is matched.
+/// Returns `false` if the deadline passed.
+pub(crate) fn expect_until<P>(port: &mut P, reply: &[u8], received: &mut Vec<u8>, deadline: Instant) -> ::Result<bool>
+    where P: SerialPort + ?Sized
+{
+    let mut byte = [0];
+
+    while !received.ends_with(reply) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining == Duration::from_secs(0) {
+            return Ok(false);
+        }
+
+        match port.read_with_timeout(&mut byte, remaining) {
+            Ok(0) => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::UnexpectedEof), "end of file").with_operation("expect")),
+            Ok(_) => {
+                if received.len() >= reply.len() {
+                    received.remove(0);
+                }
+
+                received.push(byte[0]);
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(::Error::from(err).with_operation("expect"))
+        }
+    }
+
+    Ok(true)
+}
+
+/// Decodes the line that starts at the first `start_code` in `buf` with `parse`.
+///
+/// Anything before the start code is discarded. The line, including its terminator, is removed
+/// from `buf` whether or not it parses.
+fn decode_line<T, F>(buf: &mut Vec<u8>, start_code: u8, parse: F) -> ::Result<Option<T>>
+    where F: FnOnce(&[u8]) -> ::Result<T>
+{
+    match buf.iter().position(|&b| b == start_code) {
+        Some(start) => {
+            buf.drain(..start);
+        },
+        None => {
+            buf.clear();
+            return Ok(None);
+        }
+    }
+
+    let end = match buf.iter().position(|&b| b == b'\n') {
+        Some(end) => end,
+        None => return Ok(None)
+    };
+
+    let line: Vec<u8> = buf.drain(..end + 1).collect();
+    parse(&line).map(Some)
+}
+
+/// Removes ASCII whitespace from both ends of `line`.
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(line.len());
+    let end = line.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |end| end + 1);
+
+    &line[start..end]
+}
+
+/// Parses pairs of hex digits into bytes.
+fn parse_hex(digits: &[u8]) -> Option<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+
+    digits.chunks(2).map(|pair| {
+        let digit = |b: u8| (b as char).to_digit(16);
+        Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+    }).collect()
+}
+
+/// An iterator over received frames, created by `Framed::frames()`.
+pub struct Frames<'a, P: 'a, C: 'a> {
+    framed: &'a mut Framed<P, C>,
+    done: bool
+}
+
+impl<'a, P: SerialPort, C: Decoder> Iterator for Frames<'a, P, C> {
+    type Item = ::Result<C::Item>;
+
+    fn next(&mut self) -> Option<::Result<C::Item>> {
+        if self.done {
+            return None;
+        }
+
+        match self.framed.read_frame() {
+            Ok(item) => Some(Ok(item)),
+            Err(ref err) if err.kind() == ::ErrorKind::Io(io::ErrorKind::UnexpectedEof) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = !err.is_transient() && err.kind() != ::ErrorKind::Io(io::ErrorKind::InvalidData);
+                Some(Err(err))
+            }
+        }
+    }
+}
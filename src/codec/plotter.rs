@@ -0,0 +1,171 @@
+//! Numeric telemetry in the Arduino serial plotter format.
+//!
+//! Each line holds one sample with a value for every channel. Values are separated by commas,
+//! spaces, or tabs, and may be labeled as `label:value`:
+//!
+//! ```text
+//! temperature:21.5,humidity:40
+//! 21.6 41
+//! ```
+//!
+//! A line that contains only labels, such as `temperature,humidity`, names the channels of the
+//! following lines. Unlabeled channels are named after their position, starting with `1`.
+
+use std::io;
+
+use super::Decoder;
+use super::lines::LineCodec;
+
+/// A channel's value in a sample.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Channel {
+    /// The channel's label.
+    pub label: String,
+
+    /// The channel's value.
+    pub value: f64
+}
+
+/// A sample of all channels received in one line.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Sample {
+    /// The channels in the order they were received.
+    pub channels: Vec<Channel>
+}
+
+impl Sample {
+    /// Returns the value of the channel with the given label.
+    pub fn get(&self, label: &str) -> Option<f64> {
+        self.channels.iter().find(|channel| channel.label == label).map(|channel| channel.value)
+    }
+
+    /// Returns the values of all channels in order.
+    pub fn values(&self) -> Vec<f64> {
+        self.channels.iter().map(|channel| channel.value).collect()
+    }
+}
+
+/// A codec that decodes lines of plotter output into samples.
+///
+/// Empty lines and header lines are consumed without producing a sample. A line that mixes
+/// numbers with other text is removed from the buffer and reported as an error of kind
+/// `Io(InvalidData)`.
+#[derive(Debug,Clone,Default)]
+pub struct PlotterCodec {
+    lines: LineCodec,
+    labels: Vec<String>
+}
+
+impl PlotterCodec {
+    /// Creates a plotter codec.
+    pub fn new() -> Self {
+        PlotterCodec::default()
+    }
+
+    /// Returns the labels set by the last header line.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Sets the labels of unlabeled channels, as if a header line had been received.
+    pub fn set_labels<I: IntoIterator<Item = S>, S: Into<String>>(&mut self, labels: I) {
+        self.labels = labels.into_iter().map(Into::into).collect();
+    }
+
+    fn parse(&mut self, line: &str) -> ::Result<Option<Sample>> {
+        let tokens: Vec<&str> = line.split(&[',', ' ', '\t'][..]).filter(|token| !token.is_empty()).collect();
+
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        if tokens.iter().all(|token| !token.contains(':') && token.parse::<f64>().is_err()) {
+            self.set_labels(tokens);
+            return Ok(None);
+        }
+
+        let mut channels = Vec::with_capacity(tokens.len());
+
+        for (i, token) in tokens.into_iter().enumerate() {
+            let (label, value) = match token.rfind(':') {
+                Some(colon) => (token[..colon].to_string(), &token[colon + 1..]),
+                None => (self.labels.get(i).cloned().unwrap_or_else(|| (i + 1).to_string()), token)
+            };
+
+            let value = value.parse().map_err(|_| {
+                ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), format!("invalid plotter value: {:?}", token)).with_operation("decode sample")
+            })?;
+
+            channels.push(Channel { label, value });
+        }
+
+        Ok(Some(Sample { channels }))
+    }
+}
+
+impl Decoder for PlotterCodec {
+    type Item = Sample;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Sample>> {
+        while let Some(line) = self.lines.decode(buf)? {
+            if let Some(sample) = self.parse(&line)? {
+                return Ok(Some(sample));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::PlotterCodec;
+    use super::super::Decoder;
+
+    #[test]
+    fn decode_unlabeled_values() {
+        let mut codec = PlotterCodec::new();
+        let mut buf = b"1.5 -2\t3e2\r\n".to_vec();
+
+        let sample = codec.decode(&mut buf).unwrap().unwrap();
+
+        assert_eq!(sample.values(), vec![1.5, -2.0, 300.0]);
+        assert_eq!(sample.get("2"), Some(-2.0));
+    }
+
+    #[test]
+    fn decode_labeled_values() {
+        let mut codec = PlotterCodec::new();
+        let mut buf = b"temperature:21.5,humidity:40\n".to_vec();
+
+        let sample = codec.decode(&mut buf).unwrap().unwrap();
+
+        assert_eq!(sample.get("temperature"), Some(21.5));
+        assert_eq!(sample.get("humidity"), Some(40.0));
+    }
+
+    #[test]
+    fn decode_applies_header_labels() {
+        let mut codec = PlotterCodec::new();
+        let mut buf = b"\nx,y\n1,2\n3\n".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().get("y"), Some(2.0));
+        assert_eq!(codec.labels(), &["x".to_string(), "y".to_string()]);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().get("x"), Some(3.0));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn decode_rejects_mixed_line_and_continues() {
+        let mut codec = PlotterCodec::new();
+        let mut buf = b"1,abc\n2\n".to_vec();
+
+        let err = codec.decode(&mut buf).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().values(), vec![2.0]);
+    }
+}
@@ -0,0 +1,370 @@
+//! Motorola S-records.
+//!
+//! `SRecordCodec` reads and writes S-records one line at a time, so that an image can be streamed
+//! to or from a monitor ROM or EPROM programmer without holding the whole file. `RecordBuilder`
+//! splits a memory image into data records and adds the count and termination records:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::codec::{self,Framed};
+//! use serial::codec::srec::{AddressWidth,RecordBuilder,SRecordCodec};
+//!
+//! let image = std::fs::read("monitor.bin").unwrap();
+//!
+//! let mut builder = RecordBuilder::new(AddressWidth::Bits24, 32);
+//! let mut records = builder.data(0x00_4000, &image).unwrap();
+//! records.extend(builder.finish(Some(0x00_4000)));
+//!
+//! // wait for the monitor to acknowledge each record
+//! let mut framed = Framed::new(serial::open("/dev/ttyUSB0").unwrap(), SRecordCodec::new());
+//! framed.write_frames(records, |port, _| codec::expect(port, b"\x06", Duration::from_secs(1))).unwrap();
+//! ```
+
+use std::fmt;
+use std::io;
+
+use super::{Decoder,Encoder};
+
+/// The width of the addresses in data records.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum AddressWidth {
+    /// 16-bit addresses in S1 records, terminated by an S9 record.
+    Bits16,
+
+    /// 24-bit addresses in S2 records, terminated by an S8 record.
+    Bits24,
+
+    /// 32-bit addresses in S3 records, terminated by an S7 record.
+    Bits32
+}
+
+impl AddressWidth {
+    fn len(self) -> usize {
+        match self {
+            AddressWidth::Bits16 => 2,
+            AddressWidth::Bits24 => 3,
+            AddressWidth::Bits32 => 4
+        }
+    }
+
+    fn max(self) -> u64 {
+        (1 << (8 * self.len())) - 1
+    }
+}
+
+/// A Motorola S-record.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Record {
+    /// The record type from 0 to 9, e.g. 1 for an S1 record.
+    pub record_type: u8,
+
+    /// The address field of the record.
+    ///
+    /// This is the load address of a data record, the number of data records in a count record,
+    /// and the entry point in a termination record.
+    pub address: u32,
+
+    /// The data of the record.
+    pub data: Vec<u8>
+}
+
+impl Record {
+    /// Creates an S0 header record.
+    pub fn header(data: &[u8]) -> Self {
+        Record { record_type: 0, address: 0, data: data.to_vec() }
+    }
+
+    /// Creates a data record with addresses of the given width.
+    pub fn data(width: AddressWidth, address: u32, data: &[u8]) -> Self {
+        let record_type = match width {
+            AddressWidth::Bits16 => 1,
+            AddressWidth::Bits24 => 2,
+            AddressWidth::Bits32 => 3
+        };
+
+        Record { record_type, address, data: data.to_vec() }
+    }
+
+    /// Creates a count record: S5 for up to 65535 data records, S6 otherwise.
+    pub fn count(count: u32) -> Self {
+        Record { record_type: if count <= 0xffff { 5 } else { 6 }, address: count, data: Vec::new() }
+    }
+
+    /// Creates the termination record that matches data records with addresses of the given
+    /// width.
+    pub fn termination(width: AddressWidth, start: u32) -> Self {
+        let record_type = match width {
+            AddressWidth::Bits16 => 9,
+            AddressWidth::Bits24 => 8,
+            AddressWidth::Bits32 => 7
+        };
+
+        Record { record_type, address: start, data: Vec::new() }
+    }
+
+    /// Returns `true` for S1, S2, and S3 data records.
+    pub fn is_data(&self) -> bool {
+        (1..=3).contains(&self.record_type)
+    }
+
+    /// Parses a record from a line of text, including the leading `S`.
+    ///
+    /// Whitespace around the record is ignored.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if the line isn't a well-formed record or if its
+    /// checksum doesn't match.
+    pub fn parse(line: &[u8]) -> ::Result<Self> {
+        let line = super::trim(line);
+
+        if line.len() < 2 || line[0] != b'S' {
+            return Err(invalid_record("missing start code"));
+        }
+
+        let record_type = match (line[1] as char).to_digit(10) {
+            Some(record_type) => record_type as u8,
+            None => return Err(invalid_record("invalid record type"))
+        };
+
+        let address_len = address_len(record_type).ok_or_else(|| invalid_record("invalid record type"))?;
+        let bytes = super::parse_hex(&line[2..]).ok_or_else(|| invalid_record("invalid hex digits"))?;
+
+        if bytes.len() < 2 + address_len || bytes.len() != 1 + bytes[0] as usize {
+            return Err(invalid_record("wrong record length"));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+
+        if !body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != checksum[0] {
+            return Err(invalid_record("checksum mismatch"));
+        }
+
+        Ok(Record {
+            record_type,
+            address: body[1..1 + address_len].iter().fold(0u32, |address, &b| (address << 8) | u32::from(b)),
+            data: body[1 + address_len..].to_vec()
+        })
+    }
+
+    fn address_bytes(&self) -> Vec<u8> {
+        let len = address_len(self.record_type).unwrap_or(2);
+        self.address.to_be_bytes()[4 - len..].to_vec()
+    }
+}
+
+impl fmt::Display for Record {
+    /// Formats the record as a line of text with uppercase hex digits, without a line terminator.
+    ///
+    /// Address bits that don't fit in the record type's address field are dropped.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let address = self.address_bytes();
+        let count = (address.len() + self.data.len() + 1) as u8;
+
+        write!(f, "S{}{:02X}", self.record_type, count)?;
+
+        let mut sum = count;
+
+        for byte in address.iter().chain(&self.data) {
+            write!(f, "{:02X}", byte)?;
+            sum = sum.wrapping_add(*byte);
+        }
+
+        write!(f, "{:02X}", !sum)
+    }
+}
+
+/// Returns the length of the address field of a record type.
+fn address_len(record_type: u8) -> Option<usize> {
+    match record_type {
+        0 | 1 | 5 | 9 => Some(2),
+        2 | 6 | 8 => Some(3),
+        3 | 7 => Some(4),
+        _ => None
+    }
+}
+
+/// Splits a memory image into S-records.
+#[derive(Debug,Clone)]
+pub struct RecordBuilder {
+    width: AddressWidth,
+    record_size: usize,
+    count: u32
+}
+
+impl RecordBuilder {
+    /// Creates a builder that puts up to `record_size` bytes in each data record.
+    ///
+    /// The record size is limited to what fits in a record with the given address width, which is
+    /// 252 bytes for 16-bit addresses. 16 and 32 are the most common sizes.
+    pub fn new(width: AddressWidth, record_size: usize) -> Self {
+        RecordBuilder {
+            width,
+            record_size: record_size.clamp(1, 254 - width.len()),
+            count: 0
+        }
+    }
+
+    /// Returns the data records for `data` at `address`.
+    ///
+    /// Consecutive calls can continue an image in pieces, e.g. as it's read from a file.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the data extends past the largest address
+    /// that fits in the address width.
+    pub fn data(&mut self, address: u32, data: &[u8]) -> ::Result<Vec<Record>> {
+        if !data.is_empty() && u64::from(address) + data.len() as u64 - 1 > self.width.max() {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "address exceeds address width").with_operation("encode S-record"));
+        }
+
+        let records: Vec<Record> = data.chunks(self.record_size).enumerate().map(|(i, chunk)| {
+            Record::data(self.width, address + (i * self.record_size) as u32, chunk)
+        }).collect();
+
+        self.count += records.len() as u32;
+        Ok(records)
+    }
+
+    /// Returns the records that end the file: a count of the data records returned so far,
+    /// followed by the termination record with the entry point `start`, or zero if it's not
+    /// given.
+    pub fn finish(&mut self, start: Option<u32>) -> Vec<Record> {
+        let count = self.count;
+        self.count = 0;
+
+        vec![Record::count(count), Record::termination(self.width, start.unwrap_or(0))]
+    }
+}
+
+/// A codec for S-records, one per line.
+///
+/// The decoder ignores anything before a record's `S` start code, such as prompts echoed by a
+/// monitor ROM. The encoder terminates each record with `\r\n` by default.
+#[derive(Debug,Clone)]
+pub struct SRecordCodec {
+    terminator: &'static str
+}
+
+impl SRecordCodec {
+    /// Creates an S-record codec.
+    pub fn new() -> Self {
+        SRecordCodec { terminator: "\r\n" }
+    }
+
+    /// Sets the line terminator appended by the encoder.
+    pub fn set_terminator(&mut self, terminator: &'static str) {
+        self.terminator = terminator;
+    }
+}
+
+impl Default for SRecordCodec {
+    fn default() -> Self {
+        SRecordCodec::new()
+    }
+}
+
+impl Decoder for SRecordCodec {
+    type Item = Record;
+
+    /// Decodes the next record.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if a line that starts with `S` isn't a valid
+    /// record. The line is discarded, and decoding continues with the next line.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Record>> {
+        super::decode_line(buf, b'S', Record::parse)
+    }
+}
+
+impl Encoder for SRecordCodec {
+    type Item = Record;
+
+    /// Encodes a record as a line of text.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the record type is invalid or if the record
+    /// is too long.
+    fn encode(&mut self, record: &Record, buf: &mut Vec<u8>) -> ::Result<()> {
+        let fits = address_len(record.record_type).is_some_and(|len| len + record.data.len() < 255);
+
+        if !fits {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "invalid S-record").with_operation("encode S-record"));
+        }
+
+        buf.extend_from_slice(record.to_string().as_bytes());
+        buf.extend_from_slice(self.terminator.as_bytes());
+        Ok(())
+    }
+}
+
+fn invalid_record(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("decode S-record")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{AddressWidth,Record,RecordBuilder,SRecordCodec};
+    use super::super::{Decoder,Encoder};
+
+    #[test]
+    fn record_round_trips_through_text() {
+        let line = b"S1137AF00A0A0D0000000000000000000000000061\r\n";
+        let record = Record::parse(line).unwrap();
+
+        assert_eq!(record.record_type, 1);
+        assert_eq!(record.address, 0x7af0);
+        assert_eq!(record.data.len(), 16);
+        assert_eq!(record.to_string(), "S1137AF00A0A0D0000000000000000000000000061");
+        assert_eq!(Record::termination(AddressWidth::Bits16, 0).to_string(), "S9030000FC");
+    }
+
+    #[test]
+    fn record_rejects_bad_checksum() {
+        let err = Record::parse(b"S1137AF00A0A0D0000000000000000000000000062").unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn builder_counts_data_records() {
+        let mut builder = RecordBuilder::new(AddressWidth::Bits24, 16);
+        let mut records = builder.data(0x01_0000, &[0x11; 40]).unwrap();
+        records.extend(builder.finish(Some(0x01_0000)));
+
+        let types: Vec<u8> = records.iter().map(|record| record.record_type).collect();
+        assert_eq!(types, vec![2, 2, 2, 5, 8]);
+        assert_eq!(records[2].address, 0x01_0020);
+        assert_eq!(records[2].data.len(), 8);
+        assert_eq!(records[3].address, 3);
+        assert_eq!(records[4].address, 0x01_0000);
+
+        assert!(builder.data(0xffff, &[0; 2]).is_ok());
+        assert_eq!(RecordBuilder::new(AddressWidth::Bits16, 16).data(0xffff, &[0; 2]).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn codec_round_trips_records() {
+        let mut codec = SRecordCodec::new();
+        let record = Record::data(AddressWidth::Bits32, 0x8000_0000, b"boot");
+
+        let mut buf = b"Loading...\r\n".to_vec();
+        codec.encode(&record, &mut buf).unwrap();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(record));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn codec_rejects_oversized_record() {
+        let mut buf = Vec::new();
+        let record = Record::data(AddressWidth::Bits32, 0, &[0; 251]);
+
+        assert_eq!(SRecordCodec::new().encode(&record, &mut buf).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+}
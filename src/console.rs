@@ -0,0 +1,159 @@
+//! Helpers for driving the serial consoles of bootloaders and kernels.
+//!
+//! `interrupt_autoboot()` sends a key repeatedly until a bootloader such as U-Boot prints its
+//! prompt, so that a board can be stopped before it boots. `sysrq()` sends a break followed by a
+//! command key, which Linux interprets as a magic SysRq request:
+//!
+//! ```no_run
+//! use serial::console::{self,AutobootOptions};
+//!
+//! let mut port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! // power cycle the board, then stop U-Boot at its prompt
+//! console::interrupt_autoboot(&mut port, b"=> ", &AutobootOptions::default()).unwrap();
+//!
+//! // later, ask the running kernel to dump its tasks
+//! console::sysrq(&mut port, b't').unwrap();
+//! ```
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::codec;
+
+/// The duration of the breaks sent by `send_break()` and `sysrq()`.
+///
+/// This is the break length of `tcsendbreak()` on Linux, which consoles reliably recognize at any
+/// common baud rate.
+pub const BREAK_DURATION: Duration = Duration::from_millis(250);
+
+/// Options for interrupting a bootloader's automatic boot.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct AutobootOptions {
+    /// The keys sent to interrupt the boot.
+    ///
+    /// U-Boot stops on any key by default, but may be configured to require a stop string.
+    pub keys: Vec<u8>,
+
+    /// Time between sending the keys.
+    pub interval: Duration,
+
+    /// Maximum time to wait for the prompt.
+    pub timeout: Duration
+}
+
+impl Default for AutobootOptions {
+    fn default() -> Self {
+        AutobootOptions {
+            keys: b" ".to_vec(),
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_secs(30)
+        }
+    }
+}
+
+/// Sends a break of `BREAK_DURATION`.
+pub fn send_break<P: SerialPort + ?Sized>(port: &mut P) -> ::Result<()> {
+    port.send_break(BREAK_DURATION)
+}
+
+/// Sends a magic SysRq command to a Linux serial console.
+///
+/// The kernel treats a key received within five seconds after a break as a SysRq command, e.g.,
+/// `b'b'` to reboot or `b't'` to show the running tasks. The console must have been enabled with
+/// `sysrq_always_enabled` or `/proc/sys/kernel/sysrq`.
+///
+/// ## Errors
+///
+/// This function returns an error if the break or the key could not be sent.
+pub fn sysrq<P: SerialPort + ?Sized>(port: &mut P, command: u8) -> ::Result<()> {
+    send_break(port)?;
+    io::Write::write_all(port, &[command])?;
+    io::Write::flush(port)?;
+
+    Ok(())
+}
+
+/// Sends `options.keys` every `options.interval` until `prompt` is received.
+///
+/// Data received before the prompt, such as the bootloader's banner, is discarded. Nothing after
+/// the prompt is consumed.
+///
+/// ## Errors
+///
+/// * `Io(TimedOut)` if the prompt wasn't received within `options.timeout`.
+/// * `Io(UnexpectedEof)` if the port reached end of file.
+/// * `NoDevice` if the device was disconnected.
+/// * `Io` for any other type of I/O error.
+pub fn interrupt_autoboot<P>(port: &mut P, prompt: &[u8], options: &AutobootOptions) -> ::Result<()>
+    where P: SerialPort + ?Sized
+{
+    let deadline = Instant::now() + options.timeout;
+    let mut received = Vec::with_capacity(prompt.len());
+
+    loop {
+        io::Write::write_all(port, &options.keys)?;
+        io::Write::flush(port)?;
+
+        let next = (Instant::now() + options.interval).min(deadline);
+
+        if codec::expect_until(port, prompt, &mut received, next)? {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            let err = ::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "prompt not received");
+            return Err(err.with_operation("interrupt autoboot"));
+        }
+
+        // the interval may not have passed if the port's reads return early
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{interrupt_autoboot,sysrq,AutobootOptions,BREAK_DURATION};
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn sysrq_sends_break_then_command() {
+        let mut device = ScriptedDevice::new();
+        sysrq(&mut device, b'b').unwrap();
+
+        assert_eq!(device.events, vec![Event::Break(BREAK_DURATION), Event::Write(b"b".to_vec()), Event::Flush]);
+    }
+
+    #[test]
+    fn interrupt_autoboot_sends_keys_until_prompt() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"Hit any key to stop autoboot:  3 ");
+        device.push_input(b"\r\n=");
+        device.push_input(b"> tail");
+
+        let options = AutobootOptions { interval: Duration::from_millis(1), ..Default::default() };
+        interrupt_autoboot(&mut device, b"=> ", &options).unwrap();
+
+        assert!(device.output().starts_with(b" "));
+        assert!(device.output().iter().all(|&b| b == b' '));
+        assert_eq!(device.input.pop_front().unwrap(), b"tail");
+    }
+
+    #[test]
+    fn interrupt_autoboot_times_out() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"Starting kernel ...");
+
+        let options = AutobootOptions { keys: b"stop".to_vec(), interval: Duration::from_millis(1), timeout: Duration::from_millis(20) };
+        let err = interrupt_autoboot(&mut device, b"=> ", &options).err().unwrap();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+        assert!(device.output().starts_with(b"stopstop"));
+    }
+}
@@ -0,0 +1,356 @@
+//! Dialing modems for PPP links.
+//!
+//! `dial()` initializes a modem with AT commands, dials a number, and waits for `CONNECT`. The
+//! port is then carrying the raw link, ready for a PPP implementation. `run()` also watches the CD
+//! (Carrier Detect) signal while the link is up, and redials when carrier is lost:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::dialup::{self,DialOptions};
+//!
+//! let mut port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! dialup::run(&mut port, "*99#", &DialOptions::default(), |link| {
+//!     // hand the link to a PPP implementation, which reads and writes HDLC-like frames
+//!     let mut frame = [0; 1500];
+//!
+//!     loop {
+//!         let len = link.read(&mut frame)?;
+//!         println!("{:?}", &frame[..len]);
+//!     }
+//! }).unwrap();
+//! ```
+//!
+//! The default initialization string includes `&C1`, so that the modem reports carrier on CD, and
+//! `&D2`, so that it hangs up when DTR is dropped. Without them, lost carrier can't be detected.
+//!
+//! A PPP daemon that opens the device itself, such as `pppd`, can be started after `dial()`
+//! returns, as long as it's told not to reset the modem, e.g., with pppd's `nodetach` and without
+//! a `connect` script.
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+
+/// Options for dialing a modem.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct DialOptions {
+    /// AT commands sent before dialing, each of which must be answered with `OK`.
+    pub init: Vec<String>,
+
+    /// Maximum time to wait for `OK` after each initialization command.
+    pub command_timeout: Duration,
+
+    /// Maximum time to wait for `CONNECT` after dialing.
+    pub connect_timeout: Duration,
+
+    /// Number of times a number is dialed before giving up, including the first try.
+    pub attempts: u32,
+
+    /// Delay before dialing again after a failed call or lost carrier.
+    pub redial_delay: Duration,
+
+    /// Time that DTR is dropped to hang up.
+    pub hangup_time: Duration,
+
+    /// Interval at which CD is checked while the link is up.
+    pub poll_interval: Duration
+}
+
+impl Default for DialOptions {
+    fn default() -> Self {
+        DialOptions {
+            init: vec!["ATZ".to_string(), "ATE0V1&C1&D2".to_string()],
+            command_timeout: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(60),
+            attempts: 3,
+            redial_delay: Duration::from_secs(5),
+            hangup_time: Duration::from_millis(500),
+            poll_interval: Duration::from_millis(100)
+        }
+    }
+}
+
+/// Initializes the modem on `port` and dials `number`.
+///
+/// `number` is appended to `ATD`, so it may include dial modifiers, e.g., `T5551234` or `*99#`
+/// for a cellular data call. Returns the connect message, e.g., `CONNECT 115200`. Nothing after
+/// the connect message is read from the port.
+///
+/// ## Errors
+///
+/// * `Io(Other)` if the modem rejected an initialization command.
+/// * `Io(ConnectionRefused)` if the call failed, e.g., with `BUSY` or `NO CARRIER`. The error's
+///   description is the modem's result.
+/// * `Io(TimedOut)` if the modem didn't respond in time.
+/// * `NoDevice` if the device was disconnected.
+/// * `Io` for any other type of I/O error.
+pub fn dial<P: SerialPort + ?Sized>(port: &mut P, number: &str, options: &DialOptions) -> ::Result<String> {
+    port.discard_input()?;
+
+    for command in &options.init {
+        send(port, command)?;
+
+        loop {
+            match &*read_result(port, command, options.command_timeout)? {
+                "OK" => break,
+                "ERROR" => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), format!("modem rejected {}", command)).with_operation("initialize modem")),
+                _ => ()
+            }
+        }
+    }
+
+    let command = format!("ATD{}", number);
+    send(port, &command)?;
+
+    loop {
+        let result = read_result(port, &command, options.connect_timeout)?;
+
+        if result.starts_with("CONNECT") {
+            return Ok(result);
+        }
+
+        if ["NO CARRIER", "BUSY", "NO DIALTONE", "NO DIAL TONE", "NO ANSWER", "ERROR"].contains(&&*result) {
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::ConnectionRefused), result).with_operation("dial"));
+        }
+    }
+}
+
+/// Hangs up by dropping DTR for `options.hangup_time`.
+///
+/// This requires the modem to be configured with `&D2`, as the default initialization does.
+pub fn hangup<P: SerialPort + ?Sized>(port: &mut P, options: &DialOptions) -> ::Result<()> {
+    port.set_dtr(false)?;
+    thread::sleep(options.hangup_time);
+    port.set_dtr(true)
+}
+
+/// Dials `number` and runs `session` on the link, redialing whenever carrier is lost.
+///
+/// Each call is tried up to `options.attempts` times. `session` is given a `Link`, whose reads and
+/// writes fail with `Io(ConnectionAborted)` once carrier is lost. When `session` returns after
+/// carrier was lost, the modem is hung up and the number is dialed again. When it returns while
+/// the link is still up, the modem is hung up and its result is returned.
+///
+/// ## Errors
+///
+/// This function returns the error of the last try if dialing failed `options.attempts` times in a
+/// row, or the error returned by `session` while the link was up.
+pub fn run<P, F>(port: &mut P, number: &str, options: &DialOptions, mut session: F) -> ::Result<()>
+    where P: SerialPort + ?Sized,
+          F: FnMut(&mut Link<'_, P>) -> io::Result<()>
+{
+    loop {
+        let mut attempt = 1;
+
+        while let Err(err) = dial(port, number, options) {
+            if attempt >= options.attempts || err.kind() == ::ErrorKind::NoDevice {
+                return Err(err);
+            }
+
+            attempt += 1;
+            hangup(port, options)?;
+            thread::sleep(options.redial_delay);
+        }
+
+        let mut link = Link { port: &mut *port, poll_interval: options.poll_interval, carrier_lost: false };
+        let result = session(&mut link);
+        let carrier_lost = link.carrier_lost;
+
+        hangup(port, options)?;
+
+        if !carrier_lost {
+            return result.map_err(::Error::from);
+        }
+
+        thread::sleep(options.redial_delay);
+    }
+}
+
+fn send<P: SerialPort + ?Sized>(port: &mut P, command: &str) -> ::Result<()> {
+    port.write_all(command.as_bytes())?;
+    port.write_all(b"\r")?;
+    port.flush()?;
+    Ok(())
+}
+
+/// Reads lines until one that isn't empty or the echo of `command`.
+///
+/// Lines end with `\n`, so that nothing after the `\r\n` of a connect message is read.
+fn read_result<P: SerialPort + ?Sized>(port: &mut P, command: &str, timeout: Duration) -> ::Result<String> {
+    let deadline = Instant::now() + timeout;
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining == Duration::from_secs(0) {
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), format!("no response to {}", command)).with_operation("dial"));
+        }
+
+        match port.read_with_timeout(&mut byte, remaining) {
+            Ok(0) => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::UnexpectedEof), "end of file").with_operation("dial")),
+            Ok(_) if byte[0] == b'\n' => {
+                let result = String::from_utf8_lossy(&line).trim().to_string();
+                line.clear();
+
+                if !result.is_empty() && result != command {
+                    return Ok(result);
+                }
+            },
+            Ok(_) => line.push(byte[0]),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(::Error::from(err).with_operation("dial"))
+        }
+    }
+}
+
+/// A connected modem link, which fails once carrier is lost.
+///
+/// Reads wait at most the port's timeout, checking CD at the poll interval.
+pub struct Link<'a, P: ?Sized + 'a> {
+    port: &'a mut P,
+    poll_interval: Duration,
+    carrier_lost: bool
+}
+
+impl<'a, P: SerialPort + ?Sized> Link<'a, P> {
+    /// Returns a mutable reference to the port.
+    pub fn get_mut(&mut self) -> &mut P {
+        self.port
+    }
+
+    /// Returns `true` if carrier has been lost.
+    pub fn carrier_lost(&self) -> bool {
+        self.carrier_lost
+    }
+
+    fn check_carrier(&mut self) -> io::Result<()> {
+        if !self.carrier_lost && !self.port.read_cd()? {
+            self.carrier_lost = true;
+        }
+
+        if self.carrier_lost {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "carrier lost"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, P: SerialPort + ?Sized> io::Read for Link<'a, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.port.timeout();
+
+        loop {
+            self.check_carrier()?;
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::from_secs(0) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
+            }
+
+            match self.port.read_with_timeout(buf, remaining.min(self.poll_interval)) {
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+                result => return result
+            }
+        }
+    }
+}
+
+impl<'a, P: SerialPort + ?Sized> io::Write for Link<'a, P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_carrier()?;
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::Duration;
+
+    use super::{dial,run,DialOptions};
+    use ::testing::{Event,ScriptedDevice};
+
+    fn options() -> DialOptions {
+        DialOptions {
+            command_timeout: Duration::from_millis(50),
+            connect_timeout: Duration::from_millis(50),
+            redial_delay: Duration::from_secs(0),
+            hangup_time: Duration::from_secs(0),
+            poll_interval: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    fn push_call(device: &mut ScriptedDevice, result: &[u8]) {
+        device.push_input(b"ATZ\r\r\nOK\r\n");
+        device.push_input(b"\r\nOK\r\n");
+        device.push_input(result);
+    }
+
+    #[test]
+    fn dial_initializes_and_waits_for_connect() {
+        let mut device = ScriptedDevice::new();
+        push_call(&mut device, b"\r\nCONNECT 115200\r\n~\x7d");
+
+        assert_eq!(dial(&mut device, "*99#", &options()).unwrap(), "CONNECT 115200");
+        assert_eq!(device.output(), b"ATZ\rATE0V1&C1&D2\rATD*99#\r");
+        assert_eq!(device.input.pop_front().unwrap(), b"~\x7d");
+    }
+
+    #[test]
+    fn dial_reports_failed_calls() {
+        let mut device = ScriptedDevice::new();
+        push_call(&mut device, b"\r\nBUSY\r\n");
+
+        let err = dial(&mut device, "5551234", &options()).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::ConnectionRefused));
+        assert_eq!(err.to_string(), "dial: BUSY");
+
+        device.push_input(b"\r\nERROR\r\n");
+        assert_eq!(dial(&mut device, "5551234", &options()).unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::Other));
+        assert_eq!(dial(&mut device, "5551234", &options()).unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn run_redials_when_carrier_is_lost() {
+        let mut device = ScriptedDevice::new();
+        device.cd = true;
+        push_call(&mut device, b"\r\nNO CARRIER\r\n");
+        push_call(&mut device, b"\r\nCONNECT\r\n");
+
+        let mut sessions = 0;
+
+        run(&mut device, "*99#", &options(), |link| {
+            sessions += 1;
+
+            if sessions == 1 {
+                link.write_all(b"~")?;
+                link.get_mut().cd = false;
+                push_call(link.get_mut(), b"\r\nCONNECT\r\n");
+
+                assert_eq!(link.read(&mut [0; 16]).unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+                assert!(link.carrier_lost());
+                link.get_mut().cd = true;
+            }
+
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(sessions, 2);
+        assert_eq!(device.events.iter().filter(|&event| *event == Event::Dtr(false)).count(), 3);
+    }
+}
@@ -0,0 +1,311 @@
+//! Discovery of the serial ports available on the system.
+//!
+//! ```no_run
+//! for port in serial::enumerate::available_ports().unwrap() {
+//!     println!("{} ({:?})", port.path.display(), port.port_type);
+//! }
+//! ```
+//!
+//! On Linux, ports are discovered by reading sysfs directly, so no system libraries such as libudev
+//! are required. This works in static musl builds and in containers where `/sys` is mounted.
+//!
+//! By default, only USB adapters and expansion cards are listed. UARTs that are built into the
+//! system, such as a Raspberry Pi's `/dev/ttyAMA0`, and pseudo-terminals can be included with
+//! `EnumerateOptions`:
+//!
+//! ```no_run
+//! let ports = serial::enumerate::EnumerateOptions::new().platform(true).available_ports().unwrap();
+//! ```
+//!
+//! Tools built on this crate can print the ports as JSON with `to_json()`, so that shell scripts
+//! and programs in other languages can consume them:
+//!
+//! ```no_run
+//! let ports = serial::enumerate::available_ports().unwrap();
+//! println!("{}", serial::enumerate::to_json(&ports));
+//! ```
+
+use std::fmt::Write;
+use std::path::{Path,PathBuf};
+
+#[cfg(target_os = "linux")]
+mod sysfs;
+
+/// Information about a serial port found by `available_ports()`.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct PortInfo {
+    /// The path of the device, which can be passed to `serial::open()`.
+    ///
+    /// Symlinks are resolved, so each device is listed once under its canonical path.
+    pub path: PathBuf,
+
+    /// Symlinks that refer to the same device, sorted by path.
+    ///
+    /// On Linux, these include the persistent names that udev creates in `/dev/serial/by-id` and
+    /// `/dev/serial/by-path`, and links such as `/dev/serial0` on a Raspberry Pi.
+    pub aliases: Vec<PathBuf>,
+
+    /// The hardware that provides the port.
+    pub port_type: PortType,
+
+    /// The name of the kernel driver bound to the device, if known.
+    pub driver: Option<String>
+}
+
+impl PortInfo {
+    /// Returns the port as a JSON object.
+    ///
+    /// The object has the members `path`, `aliases`, `type` (`"usb"`, `"pci"`, `"platform"`, or
+    /// `"pseudo"`), `driver`, and `usb`. For USB adapters, `usb` is an object with the members of
+    /// `UsbInfo`, and otherwise it's `null`. Members that aren't known are `null`. Paths that
+    /// aren't valid Unicode are converted lossily.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use serial::enumerate::{PortInfo,PortType};
+    ///
+    /// let port = PortInfo { path: PathBuf::from("/dev/ttyS0"), aliases: vec![], port_type: PortType::Platform, driver: None };
+    ///
+    /// assert_eq!(port.to_json(), r#"{"path":"/dev/ttyS0","aliases":[],"type":"platform","driver":null,"usb":null}"#);
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push_str("{\"path\":");
+        json_path(json, &self.path);
+        json.push_str(",\"aliases\":[");
+
+        for (i, alias) in self.aliases.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json_path(json, alias);
+        }
+
+        let port_type = match self.port_type {
+            PortType::Usb(_) => "usb",
+            PortType::Pci => "pci",
+            PortType::Platform => "platform",
+            PortType::Pseudo => "pseudo"
+        };
+
+        let _ = write!(json, "],\"type\":\"{}\",\"driver\":", port_type);
+        json_option(json, self.driver.as_ref());
+        json.push_str(",\"usb\":");
+
+        match self.port_type {
+            PortType::Usb(ref usb) => {
+                let _ = write!(json, "{{\"vid\":{},\"pid\":{},\"serial_number\":", usb.vid, usb.pid);
+                json_option(json, usb.serial_number.as_ref());
+                json.push_str(",\"manufacturer\":");
+                json_option(json, usb.manufacturer.as_ref());
+                json.push_str(",\"product\":");
+                json_option(json, usb.product.as_ref());
+                json.push_str(",\"interface\":");
+
+                match usb.interface {
+                    Some(interface) => { let _ = write!(json, "{}", interface); },
+                    None => json.push_str("null")
+                }
+
+                json.push('}');
+            },
+            _ => json.push_str("null")
+        }
+
+        json.push('}');
+    }
+}
+
+/// Returns a list of ports as a JSON array of the objects described by `PortInfo::to_json()`.
+pub fn to_json(ports: &[PortInfo]) -> String {
+    let mut json = String::from("[");
+
+    for (i, port) in ports.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+
+        port.write_json(&mut json);
+    }
+
+    json.push(']');
+    json
+}
+
+fn json_path(json: &mut String, path: &Path) {
+    json_string(json, &path.to_string_lossy());
+}
+
+fn json_option(json: &mut String, s: Option<&String>) {
+    match s {
+        Some(s) => json_string(json, s),
+        None => json.push_str("null")
+    }
+}
+
+fn json_string(json: &mut String, s: &str) {
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => { let _ = write!(json, "\\u{:04x}", c as u32); },
+            c => json.push(c)
+        }
+    }
+
+    json.push('"');
+}
+
+/// The type of hardware that provides a serial port.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum PortType {
+    /// A USB serial adapter.
+    Usb(UsbInfo),
+
+    /// A serial port on a PCI or PCI Express card.
+    Pci,
+
+    /// A UART that's built into the system, such as `/dev/ttyS0` on a PC or `/dev/ttyAMA0` on a
+    /// Raspberry Pi.
+    ///
+    /// Only listed by `EnumerateOptions::platform()`.
+    Platform,
+
+    /// The slave side of a pseudo-terminal, such as `/dev/pts/3`.
+    ///
+    /// Only listed by `EnumerateOptions::pseudo()`.
+    Pseudo
+}
+
+/// Information about a USB serial adapter.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct UsbInfo {
+    /// USB vendor ID.
+    pub vid: u16,
+
+    /// USB product ID.
+    pub pid: u16,
+
+    /// The device's serial number, if it reports one.
+    pub serial_number: Option<String>,
+
+    /// The device's manufacturer string, if it reports one.
+    pub manufacturer: Option<String>,
+
+    /// The device's product string, if it reports one.
+    pub product: Option<String>,
+
+    /// The number of the USB interface that provides the port, for devices with multiple ports.
+    pub interface: Option<u8>
+}
+
+/// Options for choosing which types of ports are listed.
+///
+/// With no options set, the same ports are listed as by `available_ports()`.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct EnumerateOptions {
+    platform: bool,
+    pseudo: bool
+}
+
+impl EnumerateOptions {
+    /// Creates a set of options that lists USB adapters and expansion cards.
+    pub fn new() -> Self {
+        EnumerateOptions::default()
+    }
+
+    /// Sets whether UARTs that are built into the system are listed.
+    ///
+    /// Drivers such as Linux's 8250 driver register a fixed number of ports whether or not the
+    /// hardware exists. Only ports for which sysfs reports a detected UART are listed.
+    pub fn platform(&mut self, platform: bool) -> &mut Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Sets whether pseudo-terminals are listed.
+    ///
+    /// This includes the slave side of every open pseudo-terminal, such as those of terminal
+    /// emulators and tools like `socat` that create virtual serial port pairs.
+    pub fn pseudo(&mut self, pseudo: bool) -> &mut Self {
+        self.pseudo = pseudo;
+        self
+    }
+
+    /// Returns the serial ports available on the system that match these options, sorted by path.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `available_ports()`.
+    pub fn available_ports(&self) -> ::Result<Vec<PortInfo>> {
+        #[cfg(target_os = "linux")]
+        {
+            sysfs::available_ports(self)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err(::Error::new(::ErrorKind::Io(::std::io::ErrorKind::Unsupported), "port enumeration is not supported on this platform").with_operation("enumerate ports"))
+        }
+    }
+}
+
+/// Returns the serial ports available on the system, sorted by path.
+///
+/// USB adapters and expansion cards are listed. Use `EnumerateOptions` to list other types of
+/// ports.
+///
+/// ## Errors
+///
+/// This function returns an error if the available ports could not be determined:
+///
+/// * `Io(Unsupported)` if enumeration isn't supported on the current platform.
+/// * `Io` for any other type of I/O error.
+pub fn available_ports() -> ::Result<Vec<PortInfo>> {
+    EnumerateOptions::new().available_ports()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{to_json,PortInfo,PortType,UsbInfo};
+
+    #[test]
+    fn to_json_lists_usb_details() {
+        let usb = UsbInfo {
+            vid: 0x0403,
+            pid: 0x6001,
+            serial_number: Some("A6008isP".to_string()),
+            manufacturer: Some("FTDI".to_string()),
+            product: Some("FT232R \"USB\" UART\u{1}".to_string()),
+            interface: Some(0)
+        };
+
+        let port = PortInfo {
+            path: PathBuf::from("/dev/ttyUSB0"),
+            aliases: vec![PathBuf::from("/dev/serial/by-id/usb-FTDI-if00"), PathBuf::from("/dev/serial0")],
+            port_type: PortType::Usb(usb),
+            driver: Some("ftdi_sio".to_string())
+        };
+
+        assert_eq!(to_json(&[port]), concat!(
+            r#"[{"path":"/dev/ttyUSB0","aliases":["/dev/serial/by-id/usb-FTDI-if00","/dev/serial0"],"type":"usb","driver":"ftdi_sio","#,
+            r#""usb":{"vid":1027,"pid":24577,"serial_number":"A6008isP","manufacturer":"FTDI","product":"FT232R \"USB\" UART\u0001","interface":0}}]"#
+        ));
+        assert_eq!(to_json(&[]), "[]");
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{EnumerateOptions,PortInfo,PortType,UsbInfo};
+
+pub fn available_ports(options: &EnumerateOptions) -> ::Result<Vec<PortInfo>> {
+    match scan(Path::new("/sys"), Path::new("/dev"), options) {
+        Ok(ports) => Ok(ports),
+        Err(err) => Err(::Error::from(err).with_operation("enumerate ports"))
+    }
+}
+
+/// Scans the TTY class of a sysfs tree for serial ports backed by hardware.
+///
+/// Pseudo-terminals aren't in sysfs, so they're found in `dev/pts`.
+fn scan(sys: &Path, dev: &Path, options: &EnumerateOptions) -> io::Result<Vec<PortInfo>> {
+    let sys = fs::canonicalize(sys)?;
+    let mut ports = Vec::new();
+
+    for entry in fs::read_dir(sys.join("class/tty"))? {
+        let entry = entry?;
+
+        // virtual terminals and PTYs have no device link
+        let device = match fs::canonicalize(entry.path().join("device")) {
+            Ok(device) => device,
+            Err(_) => continue
+        };
+
+        let port_type = match port_type(&sys, &device) {
+            Some(port_type) => port_type,
+            None if options.platform && has_uart(&entry.path()) => PortType::Platform,
+            None => continue
+        };
+
+        ports.push(PortInfo {
+            path: dev.join(entry.file_name()),
+            port_type,
+            driver: link_name(&device.join("driver")),
+            aliases: Vec::new()
+        });
+    }
+
+    if options.pseudo {
+        ports.extend(pseudo_terminals(&dev.join("pts"))?);
+    }
+
+    for port in &mut ports {
+        if let Ok(path) = fs::canonicalize(&port.path) {
+            port.path = path;
+        }
+    }
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    ports.dedup_by(|a, b| a.path == b.path);
+
+    add_aliases(&mut ports, dev);
+    Ok(ports)
+}
+
+/// Adds symlinks in `dev`, `dev/serial/by-id`, and `dev/serial/by-path` to the aliases of the
+/// ports they point to. `ports` must be sorted by path.
+fn add_aliases(ports: &mut [PortInfo], dev: &Path) {
+    for dir in &[dev.to_path_buf(), dev.join("serial/by-id"), dev.join("serial/by-path")] {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.flatten() {
+            // skips links into /proc, such as /dev/stdin, which can point to a pseudo-terminal
+            match fs::read_link(entry.path()) {
+                Ok(ref target) if !target.starts_with("/proc") => (),
+                _ => continue
+            }
+
+            let target = match fs::canonicalize(entry.path()) {
+                Ok(target) => target,
+                Err(_) => continue
+            };
+
+            if let Ok(i) = ports.binary_search_by(|port| port.path.cmp(&target)) {
+                ports[i].aliases.push(entry.path());
+            }
+        }
+    }
+
+    for port in ports {
+        port.aliases.sort();
+    }
+}
+
+/// Returns `true` if the serial core reports a detected UART for a TTY.
+///
+/// Ports without hardware have a `type` of 0 (`PORT_UNKNOWN`).
+fn has_uart(tty: &Path) -> bool {
+    read_attribute(tty, "type").is_some_and(|port_type| port_type != "0")
+}
+
+fn pseudo_terminals(pts: &Path) -> io::Result<Vec<PortInfo>> {
+    let entries = match fs::read_dir(pts) {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err)
+    };
+
+    let mut ports = Vec::new();
+
+    for entry in entries {
+        let entry = entry?;
+
+        // skips ptmx, which is the multiplexer for creating new pseudo-terminals
+        if entry.file_name().to_str().is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit())) {
+            ports.push(PortInfo {
+                path: pts.join(entry.file_name()),
+                port_type: PortType::Pseudo,
+                driver: None,
+                aliases: Vec::new()
+            });
+        }
+    }
+
+    Ok(ports)
+}
+
+fn port_type(sys: &Path, device: &Path) -> Option<PortType> {
+    let mut interface = None;
+
+    for dir in device.ancestors().take_while(|dir| dir.starts_with(sys)) {
+        if interface.is_none() {
+            interface = read_attribute(dir, "bInterfaceNumber").and_then(|s| u8::from_str_radix(&s, 16).ok());
+        }
+
+        if let Some(vid) = read_hex(dir, "idVendor") {
+            return Some(PortType::Usb(UsbInfo {
+                vid,
+                pid: read_hex(dir, "idProduct").unwrap_or(0),
+                serial_number: read_attribute(dir, "serial"),
+                manufacturer: read_attribute(dir, "manufacturer"),
+                product: read_attribute(dir, "product"),
+                interface
+            }));
+        }
+
+        if link_name(&dir.join("subsystem")).as_deref() == Some("pci") {
+            return Some(PortType::Pci);
+        }
+    }
+
+    None
+}
+
+fn read_attribute(dir: &Path, name: &str) -> Option<String> {
+    match fs::read_to_string(dir.join(name)) {
+        Ok(value) => Some(value.trim().to_owned()).filter(|value| !value.is_empty()),
+        Err(_) => None
+    }
+}
+
+fn read_hex(dir: &Path, name: &str) -> Option<u16> {
+    read_attribute(dir, name).and_then(|value| u16::from_str_radix(&value, 16).ok())
+}
+
+fn link_name(path: &Path) -> Option<String> {
+    fs::read_link(path).ok()
+        .and_then(|target| target.file_name().map(|name| name.to_string_lossy().into_owned()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path,PathBuf};
+
+    use super::scan;
+    use super::super::{EnumerateOptions,PortType,UsbInfo};
+
+    /// A fake sysfs tree in a temporary directory, which is removed when dropped.
+    struct FakeSysfs {
+        root: PathBuf
+    }
+
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = ::std::env::temp_dir().join(format!("serial-sysfs-{}-{}", name, ::std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("class/tty")).unwrap();
+            fs::create_dir_all(root.join("bus")).unwrap();
+
+            FakeSysfs { root }
+        }
+
+        /// Creates a device directory with the given attributes and subsystem.
+        fn device(&self, path: &str, subsystem: Option<&str>, attributes: &[(&str, &str)]) -> PathBuf {
+            let dir = self.root.join(path);
+            fs::create_dir_all(&dir).unwrap();
+
+            for &(name, value) in attributes {
+                fs::write(dir.join(name), format!("{}\n", value)).unwrap();
+            }
+
+            if let Some(subsystem) = subsystem {
+                let target = self.root.join("bus").join(subsystem);
+                fs::create_dir_all(&target).unwrap();
+                ::std::os::unix::fs::symlink(&target, dir.join("subsystem")).unwrap();
+            }
+
+            dir
+        }
+
+        /// Adds a TTY class entry, optionally linked to a device with a driver.
+        fn tty(&self, name: &str, device: Option<&Path>, driver: Option<&str>) -> PathBuf {
+            let dir = self.root.join("class/tty").join(name);
+            fs::create_dir_all(&dir).unwrap();
+
+            if let Some(device) = device {
+                ::std::os::unix::fs::symlink(device, dir.join("device")).unwrap();
+
+                if let Some(driver) = driver {
+                    let target = self.root.join("bus/drivers").join(driver);
+                    fs::create_dir_all(&target).unwrap();
+                    let _ = ::std::os::unix::fs::symlink(&target, device.join("driver"));
+                }
+            }
+
+            dir
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn scan_finds_usb_serial_adapter() {
+        let sysfs = FakeSysfs::new("usb-serial");
+
+        sysfs.device("devices/pci0000:00/usb1/1-1", Some("usb"), &[
+            ("idVendor", "0403"),
+            ("idProduct", "6001"),
+            ("serial", "A12345"),
+            ("manufacturer", "FTDI"),
+            ("product", "FT232R USB UART")
+        ]);
+        sysfs.device("devices/pci0000:00/usb1/1-1/1-1:1.0", Some("usb"), &[("bInterfaceNumber", "00")]);
+        let port = sysfs.device("devices/pci0000:00/usb1/1-1/1-1:1.0/ttyUSB0", Some("usb-serial"), &[]);
+        sysfs.tty("ttyUSB0", Some(&port), Some("ftdi_sio"));
+
+        let ports = scan(&sysfs.root, Path::new("/dev"), &EnumerateOptions::new()).unwrap();
+
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].path, Path::new("/dev/ttyUSB0"));
+        assert_eq!(ports[0].driver, Some("ftdi_sio".to_owned()));
+        assert_eq!(ports[0].port_type, PortType::Usb(UsbInfo {
+            vid: 0x0403,
+            pid: 0x6001,
+            serial_number: Some("A12345".to_owned()),
+            manufacturer: Some("FTDI".to_owned()),
+            product: Some("FT232R USB UART".to_owned()),
+            interface: Some(0)
+        }));
+    }
+
+    #[test]
+    fn scan_finds_pci_serial_card() {
+        let sysfs = FakeSysfs::new("pci");
+
+        let card = sysfs.device("devices/pci0000:00/0000:00:1c.0", Some("pci"), &[]);
+        sysfs.tty("ttyS4", Some(&card), Some("serial"));
+
+        let ports = scan(&sysfs.root, Path::new("/dev"), &EnumerateOptions::new()).unwrap();
+
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].path, Path::new("/dev/ttyS4"));
+        assert_eq!(ports[0].port_type, PortType::Pci);
+    }
+
+    #[test]
+    fn scan_skips_virtual_and_platform_ttys() {
+        let sysfs = FakeSysfs::new("virtual");
+
+        let platform = sysfs.device("devices/platform/serial8250/tty/ttyS0", Some("platform"), &[]);
+        sysfs.tty("ttyS0", Some(&platform), Some("serial8250"));
+        sysfs.tty("tty0", None, None);
+        sysfs.tty("ptmx", None, None);
+
+        assert_eq!(scan(&sysfs.root, Path::new("/dev"), &EnumerateOptions::new()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn scan_finds_detected_platform_uarts_when_asked() {
+        let sysfs = FakeSysfs::new("platform");
+
+        let amba = sysfs.device("devices/platform/soc/fe201000.serial", Some("amba"), &[]);
+        fs::write(sysfs.tty("ttyAMA0", Some(&amba), Some("uart-pl011")).join("type"), "15\n").unwrap();
+
+        let serial8250 = sysfs.device("devices/platform/serial8250", Some("platform"), &[]);
+        fs::write(sysfs.tty("ttyS0", Some(&serial8250), Some("serial8250")).join("type"), "4\n").unwrap();
+        fs::write(sysfs.tty("ttyS1", Some(&serial8250), None).join("type"), "0\n").unwrap();
+
+        assert_eq!(scan(&sysfs.root, Path::new("/dev"), &EnumerateOptions::new()).unwrap(), vec![]);
+
+        let ports = scan(&sysfs.root, Path::new("/dev"), EnumerateOptions::new().platform(true)).unwrap();
+        let paths: Vec<&Path> = ports.iter().map(|port| port.path.as_path()).collect();
+
+        assert_eq!(paths, vec![Path::new("/dev/ttyAMA0"), Path::new("/dev/ttyS0")]);
+        assert!(ports.iter().all(|port| port.port_type == PortType::Platform));
+        assert_eq!(ports[0].driver, Some("uart-pl011".to_owned()));
+    }
+
+    #[test]
+    fn scan_finds_pseudo_terminals_when_asked() {
+        let sysfs = FakeSysfs::new("pts");
+        let dev = sysfs.root.join("dev");
+
+        fs::create_dir_all(dev.join("pts")).unwrap();
+        for name in &["0", "12", "ptmx"] {
+            fs::write(dev.join("pts").join(name), "").unwrap();
+        }
+
+        assert_eq!(scan(&sysfs.root, &dev, &EnumerateOptions::new()).unwrap(), vec![]);
+
+        let ports = scan(&sysfs.root, &dev, EnumerateOptions::new().pseudo(true)).unwrap();
+        let paths: Vec<PathBuf> = ports.iter().map(|port| port.path.clone()).collect();
+
+        assert_eq!(paths, vec![dev.join("pts/0"), dev.join("pts/12")]);
+        assert_eq!(ports[0].port_type, PortType::Pseudo);
+    }
+
+    #[test]
+    fn scan_lists_symlinks_as_aliases() {
+        let sysfs = FakeSysfs::new("aliases");
+        let dev = sysfs.root.join("dev");
+
+        let port = sysfs.device("devices/pci0000:00/0000:00:1c.0", Some("pci"), &[]);
+        sysfs.tty("ttyS4", Some(&port), None);
+
+        fs::create_dir_all(dev.join("serial/by-id")).unwrap();
+        fs::create_dir_all(dev.join("serial/by-path")).unwrap();
+        fs::write(dev.join("ttyS4"), "").unwrap();
+        ::std::os::unix::fs::symlink("../../ttyS4", dev.join("serial/by-id/pci-card-port0")).unwrap();
+        ::std::os::unix::fs::symlink("../../ttyS4", dev.join("serial/by-path/pci-0000:00:1c.0")).unwrap();
+        ::std::os::unix::fs::symlink("ttyS4", dev.join("serial1")).unwrap();
+        ::std::os::unix::fs::symlink("/proc/self/fd/0", dev.join("stdin")).unwrap();
+
+        let ports = scan(&sysfs.root, &dev, &EnumerateOptions::new()).unwrap();
+
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].path, fs::canonicalize(dev.join("ttyS4")).unwrap());
+        assert_eq!(ports[0].aliases, vec![
+            dev.join("serial/by-id/pci-card-port0"),
+            dev.join("serial/by-path/pci-0000:00:1c.0"),
+            dev.join("serial1")
+        ]);
+    }
+}
@@ -0,0 +1,399 @@
+//! Helpers for ESC/POS receipt printers.
+//!
+//! ESC/POS is the command set used by most receipt printers. `Printer` writes the most common
+//! commands to a serial port:
+//!
+//! ```no_run
+//! use serial::ModemSignal;
+//! use serial::escpos::{Align,Barcode,Cut,Printer};
+//!
+//! let port = serial::open("/dev/ttyS0").unwrap();
+//!
+//! let mut printer = Printer::new(port);
+//! printer.set_busy_signal(Some(ModemSignal::Dsr));
+//!
+//! printer.init().unwrap();
+//! printer.align(Align::Center).unwrap();
+//! printer.bold(true).unwrap();
+//! printer.line("RECEIPT").unwrap();
+//! printer.bold(false).unwrap();
+//! printer.barcode(Barcode::Code128, b"{B12345").unwrap();
+//! printer.feed(3).unwrap();
+//! printer.cut(Cut::Partial).unwrap();
+//! ```
+//!
+//! Receipt printers have small input buffers and signal when they're busy, e.g., while the paper
+//! is being cut. Serial printers usually signal this with DSR (wired to the printer's DTR) or with
+//! XON/XOFF, which can be enabled with `FlowControl::FlowSoftware`. When a busy signal is set,
+//! `Printer` writes data in small chunks and waits for the printer to become ready before each
+//! chunk.
+
+use std::cmp;
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::{ModemSignal,SerialPort};
+
+const ESC: u8 = 0x1b;
+const GS: u8 = 0x1d;
+const LF: u8 = 0x0a;
+
+/// Horizontal alignment of printed text.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Align {
+    /// Align to the left margin.
+    Left,
+
+    /// Center between the margins.
+    Center,
+
+    /// Align to the right margin.
+    Right
+}
+
+/// Type of paper cut.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Cut {
+    /// Cut the paper completely.
+    Full,
+
+    /// Leave the paper attached at one point.
+    Partial
+}
+
+/// Barcode symbology.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Barcode {
+    /// UPC-A, with 11 or 12 digits.
+    UpcA,
+
+    /// EAN-13, with 12 or 13 digits.
+    Ean13,
+
+    /// EAN-8, with 7 or 8 digits.
+    Ean8,
+
+    /// Code 39.
+    Code39,
+
+    /// ITF (interleaved 2 of 5), with an even number of digits.
+    Itf,
+
+    /// Code 128. The data must start with a code set selector, e.g., `{B`.
+    Code128
+}
+
+impl Barcode {
+    fn code(self) -> u8 {
+        match self {
+            Barcode::UpcA => 65,
+            Barcode::Ean13 => 67,
+            Barcode::Ean8 => 68,
+            Barcode::Code39 => 69,
+            Barcode::Itf => 70,
+            Barcode::Code128 => 73
+        }
+    }
+}
+
+/// An ESC/POS printer connected to a serial port.
+pub struct Printer<P> {
+    port: P,
+    busy_signal: Option<ModemSignal>,
+    busy_timeout: Duration,
+    chunk_size: usize
+}
+
+impl<P: SerialPort> Printer<P> {
+    /// Creates a printer that writes to `port`.
+    ///
+    /// No busy signal is checked by default.
+    pub fn new(port: P) -> Self {
+        Printer {
+            port,
+            busy_signal: None,
+            busy_timeout: Duration::from_secs(10),
+            chunk_size: 64
+        }
+    }
+
+    /// Sets the modem status signal that the printer deasserts while it's busy.
+    ///
+    /// `None` writes without checking whether the printer is ready, which is appropriate when
+    /// flow control is handled by the serial port's settings.
+    pub fn set_busy_signal(&mut self, signal: Option<ModemSignal>) {
+        self.busy_signal = signal;
+    }
+
+    /// Sets the maximum time to wait for a busy printer to become ready.
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
+    }
+
+    /// Sets the number of bytes written between checks of the busy signal.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = cmp::max(chunk_size, 1);
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Resets the printer's formatting to its defaults (`ESC @`).
+    pub fn init(&mut self) -> ::Result<()> {
+        self.write_raw(&[ESC, b'@'])
+    }
+
+    /// Prints text without a line feed.
+    ///
+    /// Printers only support their selected code page, so characters outside of ASCII are printed
+    /// as `?`. Use `write_raw()` to print text in other code pages.
+    pub fn text(&mut self, text: &str) -> ::Result<()> {
+        let bytes: Vec<u8> = text.chars().map(|c| if c.is_ascii() { c as u8 } else { b'?' }).collect();
+        self.write_raw(&bytes)
+    }
+
+    /// Prints text followed by a line feed.
+    pub fn line(&mut self, text: &str) -> ::Result<()> {
+        self.text(text)?;
+        self.write_raw(&[LF])
+    }
+
+    /// Enables or disables bold text (`ESC E`).
+    pub fn bold(&mut self, enable: bool) -> ::Result<()> {
+        self.write_raw(&[ESC, b'E', enable as u8])
+    }
+
+    /// Enables or disables underlined text (`ESC -`).
+    pub fn underline(&mut self, enable: bool) -> ::Result<()> {
+        self.write_raw(&[ESC, b'-', enable as u8])
+    }
+
+    /// Sets the alignment of the following lines (`ESC a`).
+    pub fn align(&mut self, align: Align) -> ::Result<()> {
+        let n = match align {
+            Align::Left => 0,
+            Align::Center => 1,
+            Align::Right => 2
+        };
+
+        self.write_raw(&[ESC, b'a', n])
+    }
+
+    /// Sets the character size as multiples of the normal width and height (`GS !`).
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if either multiple isn't between 1 and 8.
+    pub fn size(&mut self, width: u8, height: u8) -> ::Result<()> {
+        if !(1..=8).contains(&width) || !(1..=8).contains(&height) {
+            return Err(invalid_input("character size must be between 1 and 8"));
+        }
+
+        self.write_raw(&[GS, b'!', (width - 1) << 4 | (height - 1)])
+    }
+
+    /// Feeds the paper by `lines` lines (`ESC d`).
+    pub fn feed(&mut self, lines: u8) -> ::Result<()> {
+        self.write_raw(&[ESC, b'd', lines])
+    }
+
+    /// Cuts the paper (`GS V`).
+    pub fn cut(&mut self, cut: Cut) -> ::Result<()> {
+        let m = match cut {
+            Cut::Full => 0,
+            Cut::Partial => 1
+        };
+
+        self.write_raw(&[GS, b'V', m])
+    }
+
+    /// Prints a barcode (`GS k`) with the human-readable text below it.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `data` is empty or longer than 255 bytes.
+    pub fn barcode(&mut self, barcode: Barcode, data: &[u8]) -> ::Result<()> {
+        if data.is_empty() || data.len() > 255 {
+            return Err(invalid_input("barcode data must be between 1 and 255 bytes"));
+        }
+
+        let mut command = vec![GS, b'H', 2, GS, b'k', barcode.code(), data.len() as u8];
+        command.extend_from_slice(data);
+
+        self.write_raw(&command)
+    }
+
+    /// Prints a monochrome raster image (`GS v 0`).
+    ///
+    /// The image is `width` pixels wide and `bitmap.len() / ((width + 7) / 8)` pixels high. Each
+    /// row is packed into bytes with the leftmost pixel in the most significant bit, and set bits
+    /// are printed black.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the bitmap doesn't contain whole rows or the
+    /// image is too large.
+    pub fn raster(&mut self, width: usize, bitmap: &[u8]) -> ::Result<()> {
+        let row_len = width.div_ceil(8);
+
+        if row_len == 0 || bitmap.len() % row_len != 0 {
+            return Err(invalid_input("bitmap must contain whole rows"));
+        }
+
+        let height = bitmap.len() / row_len;
+
+        if row_len > 0xffff || height > 0xffff {
+            return Err(invalid_input("image is too large"));
+        }
+
+        let mut command = vec![GS, b'v', b'0', 0, row_len as u8, (row_len >> 8) as u8, height as u8, (height >> 8) as u8];
+        command.extend_from_slice(bitmap);
+
+        self.write_raw(&command)
+    }
+
+    /// Writes raw bytes to the printer, waiting for it to become ready if a busy signal is set.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if the printer stayed busy for longer than the busy timeout.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> ::Result<()> {
+        let signal = match self.busy_signal {
+            Some(signal) => signal,
+            None => {
+                self.port.write_all(bytes)?;
+                return Ok(());
+            }
+        };
+
+        for chunk in bytes.chunks(self.chunk_size) {
+            self.wait_ready(signal)?;
+            self.port.write_all(chunk)?;
+            self.port.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn wait_ready(&mut self, signal: ModemSignal) -> ::Result<()> {
+        let deadline = Instant::now() + self.busy_timeout;
+
+        loop {
+            let ready = match signal {
+                ModemSignal::Cts => self.port.read_cts()?,
+                ModemSignal::Dsr => self.port.read_dsr()?,
+                ModemSignal::Ri => self.port.read_ri()?,
+                ModemSignal::Cd => self.port.read_cd()?
+            };
+
+            if ready {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "printer is busy").with_operation("print"));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn invalid_input(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, description).with_operation("print")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{Align,Barcode,Cut,Printer};
+    use ::ModemSignal;
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn printer_writes_formatting_commands() {
+        let mut printer = Printer::new(ScriptedDevice::new());
+
+        printer.init().unwrap();
+        printer.align(Align::Center).unwrap();
+        printer.bold(true).unwrap();
+        printer.size(2, 1).unwrap();
+        printer.line("Café").unwrap();
+        printer.feed(3).unwrap();
+        printer.cut(Cut::Partial).unwrap();
+
+        assert_eq!(printer.get_ref().output(), b"\x1b@\x1ba\x01\x1bE\x01\x1d!\x10Caf?\n\x1bd\x03\x1dV\x01".to_vec());
+    }
+
+    #[test]
+    fn printer_writes_barcode_with_length() {
+        let mut printer = Printer::new(ScriptedDevice::new());
+
+        printer.barcode(Barcode::Ean13, b"401234567890").unwrap();
+
+        assert_eq!(printer.get_ref().output(), b"\x1dH\x02\x1dkC\x0c401234567890".to_vec());
+        assert_eq!(printer.barcode(Barcode::Ean13, b"").unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(printer.size(0, 1).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn printer_writes_raster_header() {
+        let mut printer = Printer::new(ScriptedDevice::new());
+
+        printer.raster(12, &[0xff, 0xf0, 0x80, 0x10]).unwrap();
+
+        assert_eq!(printer.get_ref().output(), vec![0x1d, b'v', b'0', 0, 2, 0, 2, 0, 0xff, 0xf0, 0x80, 0x10]);
+        assert_eq!(printer.raster(12, &[0xff, 0xf0, 0x80]).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn printer_writes_in_chunks_when_ready() {
+        let mut device = ScriptedDevice::new();
+        device.dsr = true;
+
+        let mut printer = Printer::new(device);
+        printer.set_busy_signal(Some(ModemSignal::Dsr));
+        printer.set_chunk_size(4);
+        printer.text("abcdefghij").unwrap();
+
+        assert_eq!(printer.get_ref().events, vec![
+            Event::Write(b"abcd".to_vec()),
+            Event::Flush,
+            Event::Write(b"efgh".to_vec()),
+            Event::Flush,
+            Event::Write(b"ij".to_vec()),
+            Event::Flush
+        ]);
+    }
+
+    #[test]
+    fn printer_times_out_while_busy() {
+        let mut printer = Printer::new(ScriptedDevice::new());
+        printer.set_busy_signal(Some(ModemSignal::Cts));
+        printer.set_busy_timeout(Duration::from_millis(5));
+
+        let err = printer.init().unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+        assert_eq!(printer.get_ref().output(), vec![]);
+    }
+}
@@ -0,0 +1,461 @@
+//! Event-driven access to a serial port.
+//!
+//! An `Events` builder registers callbacks for received data, errors, and changes of modem status
+//! signals, and then moves the port to a background thread that waits for these events and invokes
+//! the callbacks.
+//!
+//! ```no_run
+//! use serial::events::Events;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let handle = Events::new(port)
+//!     .on_data(|data| println!("received {:?}", data))
+//!     .on_error(|err| println!("error: {}", err))
+//!     .on_modem_signal(|signal, level| println!("{:?} is now {}", signal, level))
+//!     .start();
+//!
+//! handle.writer().send(b"AT\r".to_vec()).unwrap();
+//! ```
+//!
+//! The thread can also watch for a device that has gone silent. `on_idle()` registers a callback
+//! for when no data has been received for a given period, `idle_timeout()` stops the thread with
+//! an error instead, and `keep_alive()` sends a frame whenever the link has been quiet:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::events::Events;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let handle = Events::new(port)
+//!     .keep_alive(Duration::from_secs(5), b"PING\r".to_vec())
+//!     .on_idle(Duration::from_secs(30), |idle| println!("sensor silent for {:?}", idle))
+//!     .start();
+//! ```
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::{ModemSignal,SerialPort};
+use ::channel::Writer;
+use ::shutdown::{self,Drain};
+
+type DataCallback = Box<dyn FnMut(&[u8]) + Send>;
+type ErrorCallback = Box<dyn FnMut(::Error) + Send>;
+type ModemSignalCallback = Box<dyn FnMut(ModemSignal, bool) + Send>;
+type IdleCallback = Box<dyn FnMut(Duration) + Send>;
+
+/// A builder for event-driven access to a serial port.
+pub struct Events<P> {
+    port: P,
+    chunk_size: usize,
+    poll_interval: Duration,
+    on_data: Option<DataCallback>,
+    on_error: Option<ErrorCallback>,
+    on_modem_signal: Option<ModemSignalCallback>,
+    on_idle: Option<(Duration, IdleCallback)>,
+    idle_timeout: Option<Duration>,
+    keep_alive: Option<(Duration, Vec<u8>)>
+}
+
+impl<P: SerialPort + Send + 'static> Events<P> {
+    /// Creates a builder for the given port without any callbacks.
+    pub fn new(port: P) -> Self {
+        Events {
+            port,
+            chunk_size: 4096,
+            poll_interval: Duration::from_millis(10),
+            on_data: None,
+            on_error: None,
+            on_modem_signal: None,
+            on_idle: None,
+            idle_timeout: None,
+            keep_alive: None
+        }
+    }
+
+    /// Sets the maximum number of bytes passed to each call of the data callback.
+    ///
+    /// The default is 4096 bytes.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the interval at which modem status signals and pending writes are serviced.
+    ///
+    /// The default is 10 milliseconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Registers a callback that's called with data received from the port.
+    pub fn on_data<F>(mut self, callback: F) -> Self
+        where F: FnMut(&[u8]) + Send + 'static
+    {
+        self.on_data = Some(Box::new(callback));
+        self
+    }
+
+    /// Registers a callback that's called with the error that stops the thread.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+        where F: FnMut(::Error) + Send + 'static
+    {
+        self.on_error = Some(Box::new(callback));
+        self
+    }
+
+    /// Registers a callback that's called when a modem status signal changes.
+    ///
+    /// The callback receives the signal that changed and its new level. Modem status signals are
+    /// only polled if this callback is registered.
+    pub fn on_modem_signal<F>(mut self, callback: F) -> Self
+        where F: FnMut(ModemSignal, bool) + Send + 'static
+    {
+        self.on_modem_signal = Some(Box::new(callback));
+        self
+    }
+
+    /// Registers a callback that's called when no data has been received for `period`.
+    ///
+    /// The callback receives the time since data was last received, or since the thread started.
+    /// It's called once for each silent period, and again only after data has been received.
+    pub fn on_idle<F>(mut self, period: Duration, callback: F) -> Self
+        where F: FnMut(Duration) + Send + 'static
+    {
+        self.on_idle = Some((period, Box::new(callback)));
+        self
+    }
+
+    /// Stops the thread if no data has been received for `timeout`.
+    ///
+    /// The error callback receives an error of kind `Io(TimedOut)`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Writes `frame` to the port whenever nothing has been received or sent as a keep-alive for
+    /// `interval`.
+    ///
+    /// This keeps devices that expect regular traffic awake, and prompts a response that resets
+    /// the idle period of `on_idle()` and `idle_timeout()`.
+    pub fn keep_alive(mut self, interval: Duration, frame: Vec<u8>) -> Self {
+        self.keep_alive = Some((interval, frame));
+        self
+    }
+
+    /// Starts the background thread.
+    ///
+    /// The callbacks are invoked from the background thread. The thread runs until the returned
+    /// handle is closed, shut down, or dropped, or until an error occurs.
+    pub fn start(self) -> EventHandle<P> {
+        let (write_tx, write_rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let drain = Arc::new(Drain::new());
+
+        let thread = {
+            let stop = stop.clone();
+            let drain = drain.clone();
+            thread::spawn(move || self.run(&stop, &drain, &write_rx))
+        };
+
+        EventHandle {
+            writer: Writer::new(write_tx, drain.clone()),
+            stop,
+            drain,
+            thread: Some(thread)
+        }
+    }
+
+    fn run(mut self, stop: &AtomicBool, drain: &Drain, writes: &Receiver<Vec<u8>>) -> P {
+        if let Err(err) = self.poll(stop, drain, writes) {
+            if let Some(ref mut on_error) = self.on_error {
+                on_error(err);
+            }
+        }
+
+        self.port
+    }
+
+    fn poll(&mut self, stop: &AtomicBool, drain: &Drain, writes: &Receiver<Vec<u8>>) -> ::Result<()> {
+        let mut buf = vec![0; self.chunk_size.max(1)];
+
+        let mut signals = match self.on_modem_signal {
+            Some(_) => Some(SignalState::read(&mut self.port)?),
+            None => None
+        };
+
+        let mut last_data = Instant::now();
+        let mut last_keep_alive = last_data;
+        let mut idle_reported = false;
+
+        while !stop.load(Ordering::Relaxed) {
+            while !drain.expired() {
+                let pending = match writes.try_recv() {
+                    Ok(pending) => pending,
+                    Err(_) => break
+                };
+
+                self.port.write_all(&pending)?;
+                self.port.flush()?;
+                drain.written(pending.len());
+            }
+
+            if drain.deadline().is_some() {
+                break;
+            }
+
+            match self.port.read_with_timeout(&mut buf, self.poll_interval) {
+                Ok(0) => (),
+                Ok(n) => {
+                    last_data = Instant::now();
+                    idle_reported = false;
+
+                    if let Some(ref mut on_data) = self.on_data {
+                        on_data(&buf[..n]);
+                    }
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(::Error::from(err))
+            }
+
+            let idle = last_data.elapsed();
+
+            if let Some((period, ref mut on_idle)) = self.on_idle {
+                if !idle_reported && idle >= period {
+                    idle_reported = true;
+                    on_idle(idle);
+                }
+            }
+
+            if let Some(timeout) = self.idle_timeout {
+                if idle >= timeout {
+                    return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "no data received within the idle timeout").with_operation("read"));
+                }
+            }
+
+            if let Some((interval, ref frame)) = self.keep_alive {
+                if idle >= interval && last_keep_alive.elapsed() >= interval {
+                    self.port.write_all(frame)?;
+                    self.port.flush()?;
+                    last_keep_alive = Instant::now();
+                }
+            }
+
+            if let (Some(previous), Some(on_modem_signal)) = (signals.as_mut(), self.on_modem_signal.as_mut()) {
+                let current = SignalState::read(&mut self.port)?;
+
+                for (signal, level) in previous.changes(&current) {
+                    on_modem_signal(signal, level);
+                }
+
+                *previous = current;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A handle to the background thread started by `Events::start()`.
+pub struct EventHandle<P> {
+    writer: Writer,
+    stop: Arc<AtomicBool>,
+    drain: Arc<Drain>,
+    thread: Option<JoinHandle<P>>
+}
+
+impl<P> EventHandle<P> {
+    /// Returns a handle for writing to the port.
+    pub fn writer(&self) -> Writer {
+        self.writer.clone()
+    }
+
+    /// Stops the thread and returns the port.
+    ///
+    /// Data that was queued for writing but not yet written is discarded.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if one of the callbacks panicked.
+    pub fn close(mut self) -> ::Result<P> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "event thread")
+    }
+
+    /// Writes the queued data, then stops the thread and returns the port.
+    ///
+    /// Writing stops when `timeout` has passed. Data that was queued but not written by then is
+    /// counted in `Shutdown::unsent`. Nothing more is read from the port once the thread starts
+    /// writing the queued data.
+    ///
+    /// ## Errors
+    ///
+    /// `Shutdown::result` is an error of kind `Io(TimedOut)` if the thread didn't stop in time, or
+    /// an error if one of the callbacks panicked.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Shutdown<P> {
+        let deadline = Instant::now() + timeout;
+        self.drain.request(deadline);
+
+        let result = shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "event thread");
+        ::Shutdown { result, unsent: self.drain.unsent() }
+    }
+}
+
+impl<P> Drop for EventHandle<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The levels of all modem status signals.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+struct SignalState {
+    cts: bool,
+    dsr: bool,
+    ri: bool,
+    cd: bool
+}
+
+impl SignalState {
+    fn read<P: SerialPort + ?Sized>(port: &mut P) -> ::Result<Self> {
+        Ok(SignalState {
+            cts: port.read_cts()?,
+            dsr: port.read_dsr()?,
+            ri: port.read_ri()?,
+            cd: port.read_cd()?
+        })
+    }
+
+    /// Returns the signals whose levels differ in `other`, along with their new levels.
+    fn changes(&self, other: &SignalState) -> Vec<(ModemSignal, bool)> {
+        let pairs = [
+            (ModemSignal::Cts, self.cts, other.cts),
+            (ModemSignal::Dsr, self.dsr, other.dsr),
+            (ModemSignal::Ri, self.ri, other.ri),
+            (ModemSignal::Cd, self.cd, other.cd)
+        ];
+
+        pairs.iter()
+            .filter(|&&(_, old, new)| old != new)
+            .map(|&(signal, _, new)| (signal, new))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Events,SignalState};
+    use ::ModemSignal;
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn events_calls_data_callback() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello");
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Events::new(device).on_data(move |data| tx.send(data.to_vec()).unwrap()).start();
+
+        assert_eq!(rx.recv().unwrap(), b"hello");
+        handle.close().unwrap();
+    }
+
+    #[test]
+    fn events_writes_data_from_writer() {
+        let mut device = ScriptedDevice::new();
+        device.echo = true;
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Events::new(device).on_data(move |data| tx.send(data.to_vec()).unwrap()).start();
+        handle.writer().send(b"AT\r".to_vec()).unwrap();
+
+        assert_eq!(rx.recv().unwrap(), b"AT\r");
+
+        let device = handle.close().unwrap();
+        assert_eq!(device.events, vec![Event::Write(b"AT\r".to_vec()), Event::Flush]);
+    }
+
+    #[test]
+    fn events_calls_error_callback_and_stops() {
+        let mut device = ScriptedDevice::new();
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Events::new(device).on_error(move |err| tx.send(err.kind()).unwrap()).start();
+
+        assert_eq!(rx.recv().unwrap(), ::ErrorKind::Io(io::ErrorKind::BrokenPipe));
+        handle.close().unwrap();
+    }
+
+    #[test]
+    fn events_calls_idle_callback_once_per_silence() {
+        let device = ScriptedDevice::new();
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Events::new(device)
+            .poll_interval(Duration::from_millis(1))
+            .on_idle(Duration::from_millis(20), move |idle| tx.send(idle).unwrap())
+            .start();
+
+        assert!(rx.recv().unwrap() >= Duration::from_millis(20));
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        handle.close().unwrap();
+    }
+
+    #[test]
+    fn events_stops_after_idle_timeout() {
+        let device = ScriptedDevice::new();
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Events::new(device)
+            .poll_interval(Duration::from_millis(1))
+            .idle_timeout(Duration::from_millis(10))
+            .on_error(move |err| tx.send(err.kind()).unwrap())
+            .start();
+
+        assert_eq!(rx.recv().unwrap(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+        handle.close().unwrap();
+    }
+
+    #[test]
+    fn events_sends_keep_alive_when_quiet() {
+        let device = ScriptedDevice::new();
+
+        let handle = Events::new(device)
+            .poll_interval(Duration::from_millis(1))
+            .keep_alive(Duration::from_millis(10), b"PING".to_vec())
+            .start();
+
+        thread::sleep(Duration::from_millis(100));
+
+        let device = handle.close().unwrap();
+        let pings = device.events.iter().filter(|&event| *event == Event::Write(b"PING".to_vec())).count();
+
+        assert!((2..=20).contains(&pings), "{} keep-alives", pings);
+    }
+
+    #[test]
+    fn signal_state_reports_changed_signals() {
+        let old = SignalState { cts: false, dsr: true, ri: false, cd: true };
+        let new = SignalState { cts: true, dsr: true, ri: false, cd: false };
+
+        assert_eq!(old.changes(&new), vec![(ModemSignal::Cts, true), (ModemSignal::Cd, false)]);
+        assert_eq!(new.changes(&new), vec![]);
+    }
+}
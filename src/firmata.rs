@@ -0,0 +1,548 @@
+//! A client for the Firmata protocol.
+//!
+//! Firmata is a protocol for controlling microcontrollers from a host computer. Boards running a
+//! stock Firmata firmware, such as the `StandardFirmata` sketch for Arduino, can be controlled
+//! without writing any firmware:
+//!
+//! ```no_run
+//! use serial::firmata::{Firmata,PinMode};
+//!
+//! let mut port = serial::open("/dev/ttyACM0").unwrap();
+//! serial::SerialPort::configure(&mut port, &serial::PortSettings::standard(57600)).unwrap();
+//!
+//! let mut board = Firmata::new(port);
+//! board.set_pin_mode(13, PinMode::Output).unwrap();
+//! board.digital_write(13, true).unwrap();
+//!
+//! board.report_analog(0, true).unwrap();
+//! loop {
+//!     board.read_message().unwrap();
+//!     println!("A0 = {}", board.analog_read(0));
+//! }
+//! ```
+//!
+//! Input values are reported by the board asynchronously. `read_message()` or `poll()` must be
+//! called regularly to receive them, after which `digital_read()` and `analog_read()` return the
+//! last reported values.
+
+use std::io;
+
+use ::SerialPort;
+use ::codec::{Decoder,Encoder,Framed};
+
+const DIGITAL_MESSAGE: u8 = 0x90;
+const ANALOG_MESSAGE: u8 = 0xe0;
+const REPORT_ANALOG: u8 = 0xc0;
+const REPORT_DIGITAL: u8 = 0xd0;
+const SET_PIN_MODE: u8 = 0xf4;
+const PROTOCOL_VERSION: u8 = 0xf9;
+const SYSTEM_RESET: u8 = 0xff;
+const START_SYSEX: u8 = 0xf0;
+const END_SYSEX: u8 = 0xf7;
+
+/// Sysex command to report the firmware name and version.
+pub const REPORT_FIRMWARE: u8 = 0x79;
+
+/// Sysex command to send a string.
+pub const STRING_DATA: u8 = 0x71;
+
+/// Sysex command to set the sampling interval.
+pub const SAMPLING_INTERVAL: u8 = 0x7a;
+
+/// Sysex command to write analog values to pins above 15.
+pub const EXTENDED_ANALOG: u8 = 0x6f;
+
+/// Number of pins for which input values are tracked.
+const MAX_PINS: usize = 128;
+
+/// Mode of a pin.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum PinMode {
+    /// Digital input.
+    Input,
+
+    /// Digital output.
+    Output,
+
+    /// Analog input.
+    Analog,
+
+    /// PWM output.
+    Pwm,
+
+    /// Servo output.
+    Servo,
+
+    /// I2C pin.
+    I2c,
+
+    /// Digital input with the internal pull-up resistor enabled.
+    InputPullup,
+
+    /// Any other mode, by its number.
+    Other(u8)
+}
+
+impl PinMode {
+    fn code(self) -> u8 {
+        match self {
+            PinMode::Input => 0x00,
+            PinMode::Output => 0x01,
+            PinMode::Analog => 0x02,
+            PinMode::Pwm => 0x03,
+            PinMode::Servo => 0x04,
+            PinMode::I2c => 0x06,
+            PinMode::InputPullup => 0x0b,
+            PinMode::Other(code) => code
+        }
+    }
+}
+
+/// A message exchanged with a Firmata board.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Message {
+    /// The values of the eight pins of a digital port.
+    DigitalPort {
+        /// The port number. Port `n` contains pins `8 * n` to `8 * n + 7`.
+        port: u8,
+
+        /// The pin values, with the lowest-numbered pin in the least significant bit.
+        value: u8
+    },
+
+    /// The value of an analog channel.
+    Analog {
+        /// The analog channel, e.g., 0 for `A0`.
+        channel: u8,
+
+        /// The 14-bit value.
+        value: u16
+    },
+
+    /// The version of the Firmata protocol.
+    ProtocolVersion {
+        /// Major version.
+        major: u8,
+
+        /// Minor version.
+        minor: u8
+    },
+
+    /// A system exclusive message.
+    Sysex {
+        /// The sysex command.
+        command: u8,
+
+        /// The 7-bit data bytes.
+        data: Vec<u8>
+    }
+}
+
+/// A codec for Firmata messages.
+///
+/// Data bytes that aren't part of a message are skipped, and a message that's interrupted by the
+/// start of another message is discarded.
+#[derive(Debug,Default,Clone)]
+pub struct FirmataCodec {
+    _private: ()
+}
+
+impl FirmataCodec {
+    /// Creates a Firmata codec.
+    pub fn new() -> Self {
+        FirmataCodec::default()
+    }
+}
+
+impl Decoder for FirmataCodec {
+    type Item = Message;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Message>> {
+        loop {
+            // skip data bytes that don't follow a command
+            let start = buf.iter().position(|&byte| byte & 0x80 != 0).unwrap_or(buf.len());
+            buf.drain(..start);
+
+            let command = match buf.first() {
+                Some(&command) => command,
+                None => return Ok(None)
+            };
+
+            let len = match command {
+                START_SYSEX => match buf.iter().position(|&byte| byte == END_SYSEX) {
+                    Some(end) => end + 1,
+                    None => buf.len() + 1
+                },
+                _ if command & 0xf0 == DIGITAL_MESSAGE || command & 0xf0 == ANALOG_MESSAGE || command == PROTOCOL_VERSION => 3,
+                _ => {
+                    // a command that's only sent to the board
+                    buf.drain(..1);
+                    continue;
+                }
+            };
+
+            // a message is discarded if another one starts before it's complete
+            let body_end = ::std::cmp::min(if command == START_SYSEX { len - 1 } else { len }, buf.len());
+            if let Some(next) = buf[1..body_end].iter().position(|&byte| byte & 0x80 != 0) {
+                buf.drain(..next + 1);
+                continue;
+            }
+
+            if buf.len() < len {
+                return Ok(None);
+            }
+
+            let bytes: Vec<u8> = buf.drain(..len).collect();
+
+            let message = match command {
+                START_SYSEX if bytes.len() < 3 => continue,
+                START_SYSEX => Message::Sysex { command: bytes[1], data: bytes[2..bytes.len() - 1].to_vec() },
+                PROTOCOL_VERSION => Message::ProtocolVersion { major: bytes[1], minor: bytes[2] },
+                _ if command & 0xf0 == DIGITAL_MESSAGE => Message::DigitalPort { port: command & 0x0f, value: bytes[1] | bytes[2] << 7 },
+                _ => Message::Analog { channel: command & 0x0f, value: bytes[1] as u16 | (bytes[2] as u16) << 7 }
+            };
+
+            return Ok(Some(message));
+        }
+    }
+}
+
+impl Encoder for FirmataCodec {
+    type Item = Message;
+
+    fn encode(&mut self, message: &Message, buf: &mut Vec<u8>) -> ::Result<()> {
+        match *message {
+            Message::DigitalPort { port, value } => {
+                if port > 15 {
+                    return Err(invalid_input("digital port out of range"));
+                }
+
+                buf.extend_from_slice(&[DIGITAL_MESSAGE | port, value & 0x7f, value >> 7]);
+            },
+            Message::Analog { channel, value } => {
+                if channel > 15 || value > 0x3fff {
+                    return Err(invalid_input("analog channel or value out of range"));
+                }
+
+                buf.extend_from_slice(&[ANALOG_MESSAGE | channel, value as u8 & 0x7f, (value >> 7) as u8]);
+            },
+            Message::ProtocolVersion { major, minor } => {
+                buf.extend_from_slice(&[PROTOCOL_VERSION, major & 0x7f, minor & 0x7f]);
+            },
+            Message::Sysex { command, ref data } => {
+                if command & 0x80 != 0 || data.iter().any(|&byte| byte & 0x80 != 0) {
+                    return Err(invalid_input("sysex bytes must be 7-bit"));
+                }
+
+                buf.push(START_SYSEX);
+                buf.push(command);
+                buf.extend_from_slice(data);
+                buf.push(END_SYSEX);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A Firmata board connected to a serial port.
+pub struct Firmata<P> {
+    framed: Framed<P, FirmataCodec>,
+    digital_in: [u8; MAX_PINS / 8],
+    digital_out: [u8; MAX_PINS / 8],
+    analog_in: [u16; 16],
+    firmware: Option<(u8, u8, String)>
+}
+
+impl<P: SerialPort> Firmata<P> {
+    /// Creates a client for a board connected to `port`.
+    ///
+    /// Boards running `StandardFirmata` communicate at 57600 baud. Many boards reset when the port
+    /// is opened and need about two seconds before they respond.
+    pub fn new(port: P) -> Self {
+        Firmata {
+            framed: Framed::new(port, FirmataCodec::new()),
+            digital_in: [0; MAX_PINS / 8],
+            digital_out: [0; MAX_PINS / 8],
+            analog_in: [0; 16],
+            firmware: None
+        }
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        self.framed.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        self.framed.get_mut()
+    }
+
+    /// Sets the mode of a pin.
+    pub fn set_pin_mode(&mut self, pin: u8, mode: PinMode) -> ::Result<()> {
+        self.write(&[SET_PIN_MODE, check_pin(pin)?, mode.code() & 0x7f])
+    }
+
+    /// Sets the value of a digital output pin.
+    ///
+    /// The value is sent as a digital port message, which is supported by all versions of
+    /// `StandardFirmata`. The other pins of the port are written with the last values set by this
+    /// client.
+    pub fn digital_write(&mut self, pin: u8, level: bool) -> ::Result<()> {
+        let pin = check_pin(pin)?;
+        let port = pin / 8;
+        let mask = 1 << (pin % 8);
+
+        if level {
+            self.digital_out[port as usize] |= mask;
+        }
+        else {
+            self.digital_out[port as usize] &= !mask;
+        }
+
+        let value = self.digital_out[port as usize];
+        self.framed.write_frame(&Message::DigitalPort { port, value })
+    }
+
+    /// Writes an analog (PWM or servo) value to a pin.
+    pub fn analog_write(&mut self, pin: u8, value: u16) -> ::Result<()> {
+        let pin = check_pin(pin)?;
+
+        if pin < 16 && value <= 0x3fff {
+            return self.framed.write_frame(&Message::Analog { channel: pin, value });
+        }
+
+        let mut data = vec![pin];
+        let mut value = value;
+
+        loop {
+            data.push(value as u8 & 0x7f);
+            value >>= 7;
+
+            if value == 0 {
+                break;
+            }
+        }
+
+        self.send_sysex(EXTENDED_ANALOG, &data)
+    }
+
+    /// Enables or disables reporting of an analog channel, e.g., 0 for `A0`.
+    pub fn report_analog(&mut self, channel: u8, enable: bool) -> ::Result<()> {
+        if channel > 15 {
+            return Err(invalid_input("analog channel out of range"));
+        }
+
+        self.write(&[REPORT_ANALOG | channel, enable as u8])
+    }
+
+    /// Enables or disables reporting of the digital port that contains `pin`.
+    ///
+    /// The board reports the values of all eight pins of the port whenever one of them changes.
+    pub fn report_digital(&mut self, pin: u8, enable: bool) -> ::Result<()> {
+        let port = check_pin(pin)? / 8;
+        self.write(&[REPORT_DIGITAL | port, enable as u8])
+    }
+
+    /// Sets the interval at which the board samples and reports analog inputs.
+    pub fn set_sampling_interval(&mut self, milliseconds: u16) -> ::Result<()> {
+        self.send_sysex(SAMPLING_INTERVAL, &[milliseconds as u8 & 0x7f, (milliseconds >> 7) as u8 & 0x7f])
+    }
+
+    /// Asks the board to report its firmware name and version.
+    ///
+    /// The response is received by `read_message()` or `poll()`, after which it's returned by
+    /// `firmware()`.
+    pub fn query_firmware(&mut self) -> ::Result<()> {
+        self.send_sysex(REPORT_FIRMWARE, &[])
+    }
+
+    /// Resets the board's pin modes and reporting to their defaults.
+    pub fn reset(&mut self) -> ::Result<()> {
+        self.digital_out = [0; MAX_PINS / 8];
+        self.write(&[SYSTEM_RESET])
+    }
+
+    /// Sends a system exclusive message. All bytes of `data` must be 7-bit values.
+    pub fn send_sysex(&mut self, command: u8, data: &[u8]) -> ::Result<()> {
+        self.framed.write_frame(&Message::Sysex { command, data: data.to_vec() })
+    }
+
+    /// Returns the last reported value of a digital input pin.
+    pub fn digital_read(&self, pin: u8) -> bool {
+        match self.digital_in.get(pin as usize / 8) {
+            Some(&value) => value & (1 << (pin % 8)) != 0,
+            None => false
+        }
+    }
+
+    /// Returns the last reported value of an analog channel, e.g., 0 for `A0`.
+    pub fn analog_read(&self, channel: u8) -> u16 {
+        self.analog_in.get(channel as usize).cloned().unwrap_or(0)
+    }
+
+    /// Returns the firmware's major version, minor version, and name, once reported.
+    pub fn firmware(&self) -> Option<(u8, u8, &str)> {
+        self.firmware.as_ref().map(|&(major, minor, ref name)| (major, minor, &name[..]))
+    }
+
+    /// Reads the next message from the board and updates the reported input values.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no message was received within the port's timeout.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn read_message(&mut self) -> ::Result<Message> {
+        let message = self.framed.read_frame()?;
+
+        match message {
+            Message::DigitalPort { port, value } => {
+                self.digital_in[port as usize] = value;
+            },
+            Message::Analog { channel, value } => {
+                self.analog_in[channel as usize] = value;
+            },
+            Message::Sysex { command: REPORT_FIRMWARE, ref data } if data.len() >= 2 => {
+                self.firmware = Some((data[0], data[1], decode_string(&data[2..])));
+            },
+            _ => ()
+        }
+
+        Ok(message)
+    }
+
+    /// Reads the next message if one is received within the port's timeout.
+    ///
+    /// Returns `None` instead of an error if the read times out.
+    pub fn poll(&mut self) -> ::Result<Option<Message>> {
+        match self.read_message() {
+            Ok(message) => Ok(Some(message)),
+            Err(ref err) if err.kind() == ::ErrorKind::Io(io::ErrorKind::TimedOut) => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> ::Result<()> {
+        let port = self.framed.get_mut();
+        port.write_all(bytes)?;
+        port.flush()?;
+        Ok(())
+    }
+}
+
+/// Decodes a string sent as pairs of 7-bit bytes, least significant bits first.
+pub fn decode_string(data: &[u8]) -> String {
+    data.chunks(2).map(|pair| (pair[0] as u32 | (pair.get(1).cloned().unwrap_or(0) as u32) << 7) as u8 as char).collect()
+}
+
+fn check_pin(pin: u8) -> ::Result<u8> {
+    if (pin as usize) < MAX_PINS {
+        Ok(pin)
+    }
+    else {
+        Err(invalid_input("pin out of range"))
+    }
+}
+
+fn invalid_input(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, description).with_operation("firmata")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Firmata,FirmataCodec,Message,PinMode};
+    use ::codec::{Decoder,Encoder};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn codec_decodes_messages_and_skips_stray_bytes() {
+        let mut buf = vec![0x12, 0x91, 0x05, 0x01, 0xe2, 0x7f, 0x07, 0xf9, 0x02, 0x05, 0xf0, 0x71, 0x48, 0x00, 0xf7];
+        let mut codec = FirmataCodec::new();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Message::DigitalPort { port: 1, value: 0x85 }));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Message::Analog { channel: 2, value: 1023 }));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Message::ProtocolVersion { major: 2, minor: 5 }));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Message::Sysex { command: 0x71, data: vec![0x48, 0x00] }));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn codec_waits_for_complete_sysex() {
+        let mut buf = vec![0xf0, 0x79, 0x02];
+        let mut codec = FirmataCodec::new();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(&[0x05, 0xf7]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Message::Sysex { command: 0x79, data: vec![0x02, 0x05] }));
+    }
+
+    #[test]
+    fn codec_discards_interrupted_message() {
+        let mut buf = vec![0xe0, 0x10, 0x90, 0x01, 0x00];
+
+        assert_eq!(FirmataCodec::new().decode(&mut buf).unwrap(), Some(Message::DigitalPort { port: 0, value: 1 }));
+    }
+
+    #[test]
+    fn codec_rejects_messages_out_of_range() {
+        let mut codec = FirmataCodec::new();
+        let mut buf = Vec::new();
+
+        let messages = [
+            Message::DigitalPort { port: 16, value: 0 },
+            Message::Analog { channel: 0, value: 0x4000 },
+            Message::Sysex { command: 0x71, data: vec![0x80] }
+        ];
+
+        for message in &messages {
+            assert_eq!(codec.encode(message, &mut buf).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        }
+
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn firmata_writes_pin_commands() {
+        let mut board = Firmata::new(ScriptedDevice::new());
+
+        board.set_pin_mode(13, PinMode::Output).unwrap();
+        board.digital_write(13, true).unwrap();
+        board.digital_write(9, true).unwrap();
+        board.analog_write(3, 200).unwrap();
+        board.report_analog(0, true).unwrap();
+
+        assert_eq!(board.digital_write(200, true).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+
+        assert_eq!(board.get_ref().output(), vec![
+            0xf4, 13, 0x01,
+            0x91, 0x20, 0x00,
+            0x91, 0x22, 0x00,
+            0xe3, 0x48, 0x01,
+            0xc0, 0x01
+        ]);
+    }
+
+    #[test]
+    fn firmata_tracks_reported_inputs() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&[0x90, 0x04, 0x00, 0xe1, 0x00, 0x04]);
+        device.push_input(&[0xf0, 0x79, 0x02, 0x05, b'S', 0x00, b'F', 0x00, 0xf7]);
+
+        let mut board = Firmata::new(device);
+        board.read_message().unwrap();
+        board.read_message().unwrap();
+        board.read_message().unwrap();
+
+        assert!(board.digital_read(2));
+        assert!(!board.digital_read(3));
+        assert_eq!(board.analog_read(1), 512);
+        assert_eq!(board.firmware(), Some((2, 5, "SF")));
+        assert_eq!(board.poll().unwrap(), None);
+    }
+}
@@ -0,0 +1,431 @@
+//! A gateway between Modbus TCP clients and a Modbus serial line.
+//!
+//! A `Gateway` accepts Modbus TCP connections and forwards each request to a unit on an RTU or
+//! ASCII bus, one request at a time, then sends the unit's response back to the TCP client:
+//!
+//! ```no_run
+//! use std::net::TcpListener;
+//! use serial::gateway::Gateway;
+//! use serial::modbus::{Client,Framing};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let mut gateway = Gateway::new(Client::new(port, Framing::Rtu));
+//!
+//! // TCP unit ID 1 is the meter at address 17
+//! gateway.route(1, 17);
+//!
+//! let worker = gateway.serve(TcpListener::bind("0.0.0.0:502").unwrap(), Default::default()).unwrap();
+//! ```
+//!
+//! Requests from all connections wait in a queue for the bus. Failures on the serial side are
+//! translated to the Modbus gateway exceptions, so that TCP clients see a protocol error instead of
+//! a dropped connection:
+//!
+//! * 0x06 (Server Device Busy) if the queue is full.
+//! * 0x0A (Gateway Path Unavailable) if the unit ID has no route or the serial port failed.
+//! * 0x0B (Gateway Target Device Failed to Respond) if the unit didn't respond in time or its
+//!   response was corrupt or to another function.
+//!
+//! Exception responses from the units themselves are passed through unchanged.
+
+use std::io::{self,Read,Write};
+use std::net::{TcpListener,TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{self,Receiver,RecvTimeoutError,Sender,SyncSender,TrySendError};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::modbus::Client;
+use ::shutdown;
+
+const SERVER_DEVICE_BUSY: u8 = 0x06;
+const GATEWAY_PATH_UNAVAILABLE: u8 = 0x0a;
+const GATEWAY_TARGET_FAILED: u8 = 0x0b;
+
+/// Length of the Modbus TCP header, including the unit ID.
+const MBAP_LEN: usize = 7;
+
+/// Highest address of a unit on a serial line.
+const MAX_ADDRESS: u8 = 247;
+
+/// Options for a gateway's background threads.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct GatewayOptions {
+    /// Maximum number of requests waiting for the bus.
+    ///
+    /// Requests that arrive while the queue is full are rejected with exception 0x06.
+    pub queue_len: usize,
+
+    /// Maximum time to wait for a connection or request before checking whether to stop.
+    pub poll_interval: Duration
+}
+
+impl Default for GatewayOptions {
+    fn default() -> Self {
+        GatewayOptions {
+            queue_len: 32,
+            poll_interval: Duration::from_millis(100)
+        }
+    }
+}
+
+/// Forwards Modbus TCP requests to units on a serial line.
+pub struct Gateway<P> {
+    client: Client<P>,
+    routes: Vec<(u8, u8)>,
+    default_address: Option<u8>
+}
+
+impl<P: SerialPort> Gateway<P> {
+    /// Creates a gateway that forwards requests with `client`.
+    ///
+    /// The client's timeout bounds how long each request waits for a response. Without routes,
+    /// the unit ID of a request is used as the unit's address.
+    pub fn new(client: Client<P>) -> Self {
+        Gateway {
+            client,
+            routes: Vec::new(),
+            default_address: None
+        }
+    }
+
+    /// Forwards requests for TCP unit ID `unit` to the unit at `address`.
+    ///
+    /// Once a route has been added, requests for unit IDs without a route are forwarded to the
+    /// default address, if any, or rejected with exception 0x0A.
+    pub fn route(&mut self, unit: u8, address: u8) -> &mut Self {
+        self.routes.retain(|&(routed, _)| routed != unit);
+        self.routes.push((unit, address));
+        self
+    }
+
+    /// Sets the address that requests are forwarded to if their unit ID has no route.
+    ///
+    /// This serves clients that address a single device with unit ID 0 or 255. Without routes, it
+    /// applies to unit IDs 248 to 255, which can't be serial addresses.
+    pub fn set_default_address(&mut self, address: Option<u8>) {
+        self.default_address = address;
+    }
+
+    /// Returns a reference to the client.
+    pub fn get_ref(&self) -> &Client<P> {
+        &self.client
+    }
+
+    /// Returns a mutable reference to the client.
+    pub fn get_mut(&mut self) -> &mut Client<P> {
+        &mut self.client
+    }
+
+    /// Returns the client.
+    pub fn into_inner(self) -> Client<P> {
+        self.client
+    }
+
+    /// Returns the serial address for TCP unit ID `unit`, if it's routed.
+    pub fn address(&self, unit: u8) -> Option<u8> {
+        if let Some(&(_, address)) = self.routes.iter().find(|&&(routed, _)| routed == unit) {
+            return Some(address);
+        }
+
+        if self.routes.is_empty() && unit <= MAX_ADDRESS {
+            return Some(unit);
+        }
+
+        self.default_address
+    }
+
+    /// Forwards a request PDU for TCP unit ID `unit` and returns the response PDU.
+    ///
+    /// Errors are translated to exception responses as described in the [module
+    /// documentation](index.html). Requests to address 0 are broadcast, which units don't respond
+    /// to, so `None` is returned.
+    pub fn forward(&mut self, unit: u8, pdu: &[u8]) -> Option<Vec<u8>> {
+        let function = pdu.first().cloned().unwrap_or(0);
+
+        let address = match self.address(unit) {
+            Some(address) => address,
+            None => return Some(vec![function | 0x80, GATEWAY_PATH_UNAVAILABLE])
+        };
+
+        match self.client.exchange(address, pdu) {
+            Ok(_) if address == 0 => None,
+            Ok(response) => match response.first() {
+                Some(&code) if code == function || code == function | 0x80 => Some(response),
+                _ => Some(vec![function | 0x80, GATEWAY_TARGET_FAILED])
+            },
+            Err(err) => {
+                let exception = match err.kind() {
+                    ::ErrorKind::Io(io::ErrorKind::TimedOut) | ::ErrorKind::Io(io::ErrorKind::InvalidData) => GATEWAY_TARGET_FAILED,
+                    _ => GATEWAY_PATH_UNAVAILABLE
+                };
+
+                Some(vec![function | 0x80, exception])
+            }
+        }
+    }
+
+    /// Serves Modbus TCP clients that connect to `listener` from background threads.
+    ///
+    /// Each connection is served by its own thread, and requests are forwarded by a thread that
+    /// owns the serial port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the listener couldn't be made non-blocking.
+    pub fn serve(self, listener: TcpListener, options: GatewayOptions) -> ::Result<Worker<P>>
+        where P: Send + 'static
+    {
+        listener.set_nonblocking(true)?;
+
+        let (request_tx, request_rx) = mpsc::sync_channel(options.queue_len);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let bus = {
+            let stop = stop.clone();
+            thread::spawn(move || run_bus(self, &request_rx, &stop, &options))
+        };
+
+        let accept = {
+            let stop = stop.clone();
+            thread::spawn(move || run_accept(&listener, &request_tx, &stop, &options))
+        };
+
+        Ok(Worker { stop, bus: Some(bus), accept: Some(accept) })
+    }
+}
+
+/// A handle to a gateway's background threads.
+///
+/// The threads are stopped when the `Worker` is closed, shut down, or dropped. Connections are
+/// closed within the poll interval after the threads are stopped.
+pub struct Worker<P> {
+    stop: Arc<AtomicBool>,
+    bus: Option<JoinHandle<P>>,
+    accept: Option<JoinHandle<()>>
+}
+
+impl<P> Worker<P> {
+    /// Stops the threads and returns the serial port.
+    ///
+    /// Requests that are waiting for the bus are discarded.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(Other)` if a thread panicked.
+    pub fn close(mut self) -> ::Result<P> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        let accepted = shutdown::join_until(self.accept.take().unwrap(), None, "accept thread");
+        let port = shutdown::join_until(self.bus.take().unwrap(), None, "bus thread")?;
+
+        accepted.map(|()| port)
+    }
+
+    /// Stops the threads like `close()`, waiting at most `timeout` for them to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the threads didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<P> {
+        let deadline = Instant::now() + timeout;
+        self.stop.store(true, Ordering::Relaxed);
+
+        let accepted = shutdown::join_until(self.accept.take().unwrap(), Some(deadline), "accept thread");
+        let port = shutdown::join_until(self.bus.take().unwrap(), Some(deadline), "bus thread")?;
+
+        accepted.map(|()| port)
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A request waiting for the bus.
+struct Request {
+    unit: u8,
+    pdu: Vec<u8>,
+    response: Sender<Option<Vec<u8>>>
+}
+
+fn run_bus<P: SerialPort>(mut gateway: Gateway<P>, requests: &Receiver<Request>, stop: &AtomicBool, options: &GatewayOptions) -> P {
+    while !stop.load(Ordering::Relaxed) {
+        match requests.recv_timeout(options.poll_interval) {
+            Ok(request) => {
+                let response = gateway.forward(request.unit, &request.pdu);
+                let _ = request.response.send(response);
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+    }
+
+    gateway.client.into_inner()
+}
+
+fn run_accept(listener: &TcpListener, requests: &SyncSender<Request>, stop: &Arc<AtomicBool>, options: &GatewayOptions) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let requests = requests.clone();
+                let stop = stop.clone();
+                let options = *options;
+
+                thread::spawn(move || {
+                    let _ = serve_connection(stream, &requests, &stop, &options);
+                });
+            },
+            // includes running out of file descriptors, which may resolve when clients disconnect
+            Err(_) => thread::sleep(options.poll_interval)
+        }
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, requests: &SyncSender<Request>, stop: &AtomicBool, options: &GatewayOptions) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(options.poll_interval))?;
+    stream.set_nodelay(true)?;
+
+    let mut header = [0; MBAP_LEN];
+
+    while read_exact_until_stop(&mut stream, &mut header, stop)? {
+        let protocol = (header[2] as u16) << 8 | header[3] as u16;
+        let len = ((header[4] as usize) << 8 | header[5] as usize).saturating_sub(1);
+
+        // not a Modbus TCP client, so there's no way to respond
+        if protocol != 0 || len == 0 || len > 253 {
+            return Ok(());
+        }
+
+        let mut pdu = vec![0; len];
+
+        if !read_exact_until_stop(&mut stream, &mut pdu, stop)? {
+            return Ok(());
+        }
+
+        let (response_tx, response_rx) = mpsc::channel();
+        let request = Request { unit: header[6], pdu, response: response_tx };
+
+        let response = match requests.try_send(request) {
+            Ok(()) => match response_rx.recv() {
+                Ok(response) => response,
+                Err(_) => return Ok(())
+            },
+            Err(TrySendError::Full(request)) => Some(vec![request.pdu[0] | 0x80, SERVER_DEVICE_BUSY]),
+            Err(TrySendError::Disconnected(_)) => return Ok(())
+        };
+
+        if let Some(response) = response {
+            let len = response.len() + 1;
+            let mut adu = Vec::with_capacity(MBAP_LEN + response.len());
+
+            adu.extend_from_slice(&[header[0], header[1], 0, 0, (len >> 8) as u8, len as u8, header[6]]);
+            adu.extend_from_slice(&response);
+            stream.write_all(&adu)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills `buf` from `stream`, returning `false` if the connection was closed or the gateway is
+/// stopping.
+fn read_exact_until_stop(stream: &mut TcpStream, buf: &mut [u8], stop: &AtomicBool) -> io::Result<bool> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err)
+        }
+    }
+
+    Ok(true)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read,Write};
+    use std::net::{TcpListener,TcpStream};
+    use std::time::Duration;
+
+    use super::Gateway;
+    use ::modbus::{encode,Client,Framing};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn forward_routes_units_and_passes_exceptions() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode(Framing::Rtu, 17, &[0x03, 0x02, 0x12, 0x34]));
+
+        let mut gateway = Gateway::new(Client::new(device, Framing::Rtu));
+        gateway.route(1, 17);
+
+        assert_eq!(gateway.forward(1, &[0x03, 0x00, 0x10, 0x00, 0x01]), Some(vec![0x03, 0x02, 0x12, 0x34]));
+
+        gateway.get_mut().get_mut().push_input(&encode(Framing::Rtu, 17, &[0x83, 0x02]));
+        assert_eq!(gateway.forward(1, &[0x03, 0xff, 0xff, 0x00, 0x01]), Some(vec![0x83, 0x02]));
+        assert_eq!(gateway.forward(2, &[0x03, 0x00, 0x10, 0x00, 0x01]), Some(vec![0x83, 0x0a]));
+        assert!(gateway.get_ref().get_ref().output().starts_with(&encode(Framing::Rtu, 17, &[0x03, 0x00, 0x10, 0x00, 0x01])));
+    }
+
+    #[test]
+    fn forward_rejects_response_to_another_function() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode(Framing::Rtu, 1, &[0x04, 0x02, 0x12, 0x34]));
+
+        let mut gateway = Gateway::new(Client::new(device, Framing::Rtu));
+
+        assert_eq!(gateway.forward(1, &[0x03, 0x00, 0x10, 0x00, 0x01]), Some(vec![0x83, 0x0b]));
+    }
+
+    #[test]
+    fn forward_translates_timeouts_and_broadcasts() {
+        let mut client = Client::new(ScriptedDevice::new(), Framing::Rtu);
+        client.set_timeout(Duration::from_millis(10));
+
+        let mut gateway = Gateway::new(client);
+
+        assert_eq!(gateway.forward(5, &[0x06, 0x00, 0x01, 0x00, 0x03]), Some(vec![0x86, 0x0b]));
+        assert_eq!(gateway.forward(0, &[0x06, 0x00, 0x01, 0x00, 0x03]), None);
+        assert_eq!(gateway.forward(255, &[0x06, 0x00, 0x01, 0x00, 0x03]), Some(vec![0x86, 0x0a]));
+
+        gateway.set_default_address(Some(0));
+        assert_eq!(gateway.forward(255, &[0x06, 0x00, 0x01, 0x00, 0x03]), None);
+    }
+
+    #[test]
+    fn serve_answers_tcp_clients() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode(Framing::Rtu, 1, &[0x03, 0x02, 0x12, 0x34]));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = Gateway::new(Client::new(device, Framing::Rtu)).serve(listener, Default::default()).unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&[0x12, 0x34, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x10, 0x00, 0x01]).unwrap();
+
+        let mut response = [0; 11];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(response, [0x12, 0x34, 0x00, 0x00, 0x00, 0x05, 0x01, 0x03, 0x02, 0x12, 0x34]);
+
+        let device = worker.close().unwrap();
+        assert_eq!(device.output(), encode(Framing::Rtu, 1, &[0x03, 0x00, 0x10, 0x00, 0x01]));
+    }
+}
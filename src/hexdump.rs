@@ -0,0 +1,208 @@
+//! Formatting traffic as classic hex dumps.
+//!
+//! `HexDump` formats data as lines of offset, hex, and ASCII columns. Data captured from a link can
+//! be labelled with a timestamp and an arrow for the direction it was sent in, and the offsets of
+//! each direction continue from one call to the next:
+//!
+//! ```
+//! use serial::hexdump::{Direction,HexDump};
+//!
+//! let mut dump = HexDump::new();
+//! print!("{}", dump.format(None, Some(Direction::Tx), b"AT+CSQ\r"));
+//! print!("{}", dump.format(None, Some(Direction::Rx), b"+CSQ: 23,99\r\n"));
+//! ```
+//!
+//! This prints:
+//!
+//! ```text
+//! -> 00000000  41 54 2b 43 53 51 0d                              |AT+CSQ.|
+//! <- 00000000  2b 43 53 51 3a 20 32 33  2c 39 39 0d 0a           |+CSQ: 23,99..|
+//! ```
+//!
+//! Timestamps are printed as seconds since the Unix epoch with microsecond precision. Continuation
+//! lines of the same data are indented to line up with the first.
+
+use std::fmt;
+use std::time::{SystemTime,UNIX_EPOCH};
+
+/// The direction that data was sent in, from the point of view of the device being analyzed.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Direction {
+    /// Data transmitted by the device.
+    Tx,
+
+    /// Data received by the device.
+    Rx
+}
+
+impl Direction {
+    /// Returns the arrow that marks data sent in this direction: `->` for TX and `<-` for RX.
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Direction::Tx => "->",
+            Direction::Rx => "<-"
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Tx => f.write_str("TX"),
+            Direction::Rx => f.write_str("RX")
+        }
+    }
+}
+
+/// A hex dump formatter that tracks the offset of each direction.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct HexDump {
+    width: usize,
+    offsets: [u64; 2]
+}
+
+impl HexDump {
+    /// Creates a formatter with 16 bytes per line.
+    pub fn new() -> Self {
+        HexDump::with_width(16)
+    }
+
+    /// Creates a formatter with the given number of bytes per line.
+    ///
+    /// A width of zero is treated as one. Lines wider than 8 bytes have an extra space after every
+    /// eighth byte.
+    pub fn with_width(width: usize) -> Self {
+        HexDump {
+            width: width.max(1),
+            offsets: [0; 2]
+        }
+    }
+
+    /// Returns the number of bytes per line.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the offset at which the next data sent in `direction` will be shown.
+    ///
+    /// Data without a direction shares the offset of `Direction::Tx`.
+    pub fn offset(&self, direction: Option<Direction>) -> u64 {
+        self.offsets[index(direction)]
+    }
+
+    /// Resets the offsets of both directions to zero.
+    pub fn reset(&mut self) {
+        self.offsets = [0; 2];
+    }
+
+    /// Formats `data` as one or more lines, each ending with a newline.
+    ///
+    /// The first line is prefixed with `timestamp` and the arrow for `direction` if they're
+    /// given. Empty data produces no lines.
+    pub fn format(&mut self, timestamp: Option<SystemTime>, direction: Option<Direction>, data: &[u8]) -> String {
+        let mut prefix = String::new();
+
+        if let Some(timestamp) = timestamp {
+            let timestamp = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+            prefix.push_str(&format!("{}.{:06} ", timestamp.as_secs(), timestamp.subsec_micros()));
+        }
+
+        if let Some(direction) = direction {
+            prefix.push_str(direction.arrow());
+            prefix.push(' ');
+        }
+
+        let indent = " ".repeat(prefix.len());
+        let offset = &mut self.offsets[index(direction)];
+        let mut output = String::new();
+
+        for (i, line) in data.chunks(self.width).enumerate() {
+            output.push_str(if i == 0 { &prefix } else { &indent });
+            output.push_str(&format!("{:08x}  ", offset));
+
+            for column in 0..self.width {
+                if column > 0 && column % 8 == 0 {
+                    output.push(' ');
+                }
+
+                match line.get(column) {
+                    Some(byte) => output.push_str(&format!("{:02x} ", byte)),
+                    None => output.push_str("   ")
+                }
+            }
+
+            output.push_str(" |");
+            output.extend(line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+            output.push_str("|\n");
+
+            *offset += line.len() as u64;
+        }
+
+        output
+    }
+}
+
+impl Default for HexDump {
+    fn default() -> Self {
+        HexDump::new()
+    }
+}
+
+/// Formats `data` as a hex dump starting at offset zero, without timestamps or arrows.
+pub fn dump(data: &[u8]) -> String {
+    HexDump::new().format(None, None, data)
+}
+
+fn index(direction: Option<Direction>) -> usize {
+    match direction {
+        Some(Direction::Rx) => 1,
+        Some(Direction::Tx) | None => 0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration,UNIX_EPOCH};
+
+    use super::{dump,Direction,HexDump};
+
+    #[test]
+    fn dump_formats_offset_hex_and_ascii_columns() {
+        let expected = concat!(
+            "00000000  30 31 32 33 34 35 36 37  38 39 41 42 43 44 45 46  |0123456789ABCDEF|\n",
+            "00000010  00 7f 20                                          |.. |\n"
+        );
+
+        assert_eq!(dump(b"0123456789ABCDEF\x00\x7f "), expected);
+        assert_eq!(dump(b""), "");
+    }
+
+    #[test]
+    fn hex_dump_tracks_offsets_for_each_direction() {
+        let mut dump = HexDump::with_width(4);
+
+        assert_eq!(dump.format(None, Some(Direction::Tx), b"abcdef"), "-> 00000000  61 62 63 64  |abcd|\n   00000004  65 66        |ef|\n");
+        assert_eq!(dump.format(None, Some(Direction::Rx), b"OK"), "<- 00000000  4f 4b        |OK|\n");
+        assert_eq!(dump.format(None, Some(Direction::Tx), b"g"), "-> 00000006  67           |g|\n");
+
+        assert_eq!(dump.offset(Some(Direction::Tx)), 7);
+        assert_eq!(dump.offset(Some(Direction::Rx)), 2);
+
+        dump.reset();
+        assert_eq!(dump.offset(Some(Direction::Tx)), 0);
+    }
+
+    #[test]
+    fn hex_dump_prefixes_timestamp() {
+        let timestamp = UNIX_EPOCH + Duration::new(1_700_000_000, 5_000);
+        let mut dump = HexDump::with_width(2);
+
+        let expected = concat!(
+            "1700000000.000005 <- 00000000  41 42  |AB|\n",
+            "                     00000002  43     |C|\n"
+        );
+
+        assert_eq!(dump.format(Some(timestamp), Some(Direction::Rx), b"ABC"), expected);
+    }
+}
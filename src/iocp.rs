@@ -0,0 +1,451 @@
+//! Serving many COM ports through an I/O completion port (Windows only).
+//!
+//! A `Driver` opens each port for overlapped I/O and binds it to a single I/O completion port.
+//! Reads are kept pending on every port, and their completions are dispatched to a handler
+//! registered for each port by whichever thread calls `run()`. This is the high-scale counterpart
+//! of the [`poller`](../poller/index.html) module, which polls each port's receive queue in turn:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::iocp::{Driver,Event,Writer};
+//!
+//! let mut driver = Driver::new().unwrap();
+//! let settings = serial::PortSettings::standard(115200);
+//!
+//! for name in &["COM3", "COM4", "COM5"] {
+//!     driver.add(name, &settings, move |event: Event, writer: &mut Writer| match event {
+//!         // echo received data back to the port
+//!         Event::Received(data) => writer.write(writer.token(), data).unwrap(),
+//!         Event::Written(len) => println!("{}: wrote {} bytes", name, len),
+//!         Event::Failed(err) => println!("{}: {}", name, err)
+//!     }).unwrap();
+//! }
+//!
+//! loop {
+//!     driver.run(Duration::from_secs(1)).unwrap();
+//! }
+//! ```
+//!
+//! Handlers queue writes to any port through the `Writer` they're called with. A port stops being
+//! read after a read fails. The ports are closed when they're removed or the driver is dropped.
+
+use std::ffi::OsStr;
+use std::mem;
+use std::ptr;
+use std::time::{Duration,Instant};
+
+use ::windows::ffi::*;
+use ::{PortSettings,SerialPortSettings};
+
+const WAIT_TIMEOUT: DWORD = 258;
+
+/// How long dropping a driver waits for its cancelled operations to complete.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Identifies a port added to a `Driver`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Token(pub usize);
+
+/// An event dispatched to a port's handler.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Data was received.
+    Received(&'a [u8]),
+
+    /// A write queued by `Driver::write()` or `Writer::write()` completed with the given number of bytes written.
+    Written(usize),
+
+    /// A read or write failed.
+    ///
+    /// When a read fails, the port isn't read from again.
+    Failed(::Error)
+}
+
+/// An overlapped operation, which is owned by the kernel while it's pending.
+///
+/// The `OVERLAPPED` structure is the first field, so that a pointer to it returned by
+/// `GetQueuedCompletionStatus()` is also a pointer to the operation.
+#[repr(C)]
+struct Operation {
+    overlapped: OVERLAPPED,
+    read: bool,
+    buffer: Vec<u8>
+}
+
+impl Operation {
+    fn new(read: bool, buffer: Vec<u8>) -> Box<Self> {
+        Box::new(Operation {
+            overlapped: unsafe { mem::zeroed() },
+            read,
+            buffer
+        })
+    }
+}
+
+type Handler = Box<dyn FnMut(Event, &mut Writer) + Send>;
+
+struct Entry {
+    token: Token,
+    handle: HANDLE,
+    device: String,
+    handler: Handler
+}
+
+/// Queues writes from a port's handler while the driver is dispatching events.
+pub struct Writer<'a> {
+    driver: &'a mut Driver,
+    token: Token
+}
+
+impl<'a> Writer<'a> {
+    /// Returns the token of the port whose event is being handled.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// Queues `data` to be written to a port, which may be any port added to the driver.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `Driver::write()`.
+    pub fn write(&mut self, token: Token, data: &[u8]) -> ::Result<()> {
+        self.driver.write(token, data)
+    }
+}
+
+/// Dispatches the completions of reads and writes on many ports from one I/O completion port.
+pub struct Driver {
+    port: HANDLE,
+    entries: Vec<Entry>,
+    next_token: usize,
+    buffer_size: usize,
+    pending: usize
+}
+
+// The completion port and the port handles may be used from any thread.
+unsafe impl Send for Driver {}
+
+impl Driver {
+    /// Creates a driver with no ports.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io` if the completion port can't be created.
+    pub fn new() -> ::Result<Self> {
+        let port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, ptr::null_mut(), 0, 1) };
+
+        if port.is_null() {
+            return Err(::windows::error::last_os_error().with_operation("create completion port"));
+        }
+
+        Ok(Driver {
+            port,
+            entries: Vec::new(),
+            next_token: 0,
+            buffer_size: 4096,
+            pending: 0
+        })
+    }
+
+    /// Sets the size of the buffer used for each read, which bounds the data in each
+    /// `Event::Received`. Applies to ports added afterwards. The default is 4096 bytes.
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.buffer_size = size.max(1);
+    }
+
+    /// Opens a COM port for overlapped I/O, configures it, and starts reading from it.
+    ///
+    /// `port` is a name as accepted by `COMPort::open()`. The handler is called from `run()` with
+    /// every event for the port and a `Writer` for queueing writes in response.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `COMPort::open()` and `SerialPort::configure()`.
+    pub fn add<T, F>(&mut self, port: &T, settings: &PortSettings, handler: F) -> ::Result<Token>
+        where T: AsRef<OsStr> + ?Sized,
+              F: FnMut(Event, &mut Writer) + Send + 'static
+    {
+        let device = port.as_ref().to_string_lossy().into_owned();
+
+        let name = match ::windows::device_path(port.as_ref()) {
+            Some(name) => name,
+            None => return Err(::Error::new(::ErrorKind::InvalidInput, "invalid COM port name").with_device(device).with_operation("open"))
+        };
+
+        let handle = unsafe {
+            CreateFileW(name.as_ptr(), GENERIC_READ | GENERIC_WRITE, 0, ptr::null_mut(), OPEN_EXISTING, FILE_ATTRIBUTE_NORMAL | FILE_FLAG_OVERLAPPED, 0 as HANDLE)
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(::windows::error::last_open_error().with_device(device).with_operation("open"));
+        }
+
+        if let Err(err) = Self::setup(handle, self.port, self.next_token, settings) {
+            unsafe { CloseHandle(handle) };
+            return Err(err.with_device(device));
+        }
+
+        let token = Token(self.next_token);
+        self.next_token += 1;
+
+        self.entries.push(Entry {
+            token,
+            handle,
+            device,
+            handler: Box::new(handler)
+        });
+
+        let index = self.entries.len() - 1;
+        self.start_read(index);
+
+        Ok(token)
+    }
+
+    fn setup(handle: HANDLE, port: HANDLE, key: usize, settings: &PortSettings) -> ::Result<()> {
+        let mut dcb = DCB::new();
+
+        if unsafe { GetCommState(handle, &mut dcb) } == 0 {
+            return Err(::windows::error::last_os_error().with_operation("read settings"));
+        }
+
+        let mut device_settings = ::windows::COMSettings::from_raw(dcb);
+        device_settings.set_baud_rate(settings.baud_rate)?;
+        device_settings.set_char_size(settings.char_size);
+        device_settings.set_parity(settings.parity);
+        device_settings.set_stop_bits(settings.stop_bits);
+        device_settings.set_flow_control(settings.flow_control);
+
+        if unsafe { SetCommState(handle, device_settings.as_raw()) } == 0 {
+            return Err(::windows::error::last_os_error().with_operation("write settings"));
+        }
+
+        // a read completes as soon as any data has been received
+        let timeouts = COMMTIMEOUTS {
+            ReadIntervalTimeout: DWORD::MAX,
+            ReadTotalTimeoutMultiplier: DWORD::MAX,
+            ReadTotalTimeoutConstant: DWORD::MAX - 1,
+            WriteTotalTimeoutMultiplier: 0,
+            WriteTotalTimeoutConstant: 0
+        };
+
+        if unsafe { SetCommTimeouts(handle, &timeouts) } == 0 {
+            return Err(::windows::error::last_os_error().with_operation("set timeout"));
+        }
+
+        if unsafe { CreateIoCompletionPort(handle, port, key, 0) }.is_null() {
+            return Err(::windows::error::last_os_error().with_operation("bind completion port"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of ports.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no ports have been added.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Queues `data` to be written to a port.
+    ///
+    /// The write's completion is dispatched to the port's handler as `Event::Written`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidInput` if the token doesn't identify a port.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn write(&mut self, token: Token, data: &[u8]) -> ::Result<()> {
+        let index = self.index(token)?;
+        let mut operation = Operation::new(false, data.to_vec());
+
+        let entry = &mut self.entries[index];
+        let ok = unsafe {
+            WriteFile(entry.handle, operation.buffer.as_ptr() as LPVOID, operation.buffer.len() as DWORD, ptr::null_mut(), &mut operation.overlapped)
+        };
+
+        if ok == 0 && unsafe { GetLastError() } != ERROR_IO_PENDING {
+            return Err(::disconnected(::windows::error::last_os_error()).with_device(entry.device.clone()).with_operation("write"));
+        }
+
+        // the operation is freed by run(), since its completion is queued even if the write
+        // completed immediately
+        mem::forget(operation);
+        self.pending += 1;
+
+        Ok(())
+    }
+
+    /// Stops reading from a port and closes it.
+    ///
+    /// Events for operations that were pending on the port aren't dispatched. Returns `false` if
+    /// the token doesn't identify a port.
+    pub fn remove(&mut self, token: Token) -> bool {
+        match self.index(token) {
+            Ok(index) => {
+                let entry = self.entries.remove(index);
+
+                // pending operations complete with ERROR_OPERATION_ABORTED and are freed by run()
+                unsafe {
+                    CancelIoEx(entry.handle, ptr::null_mut());
+                    CloseHandle(entry.handle);
+                }
+
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    /// Waits until at least one operation completes or the timeout expires, and dispatches the
+    /// completions that are ready to the ports' handlers.
+    ///
+    /// Returns the number of events that were dispatched.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io` if waiting on the completion port fails.
+    pub fn run(&mut self, timeout: Duration) -> ::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut dispatched = 0;
+        let mut wait = timeout;
+
+        loop {
+            let mut len: DWORD = 0;
+            let mut key: ULONG_PTR = 0;
+            let mut overlapped: LPOVERLAPPED = ptr::null_mut();
+
+            let milliseconds = wait.as_millis().min(DWORD::MAX as u128 - 1) as DWORD;
+            let ok = unsafe { GetQueuedCompletionStatus(self.port, &mut len, &mut key, &mut overlapped, milliseconds) };
+
+            if overlapped.is_null() {
+                let errno = unsafe { GetLastError() };
+
+                if ok == 0 && errno != WAIT_TIMEOUT {
+                    return Err(::windows::error::last_os_error().with_operation("wait for completions"));
+                }
+
+                return Ok(dispatched);
+            }
+
+            let operation = unsafe { Box::from_raw(overlapped as *mut Operation) };
+            self.pending -= 1;
+
+            let result = if ok != 0 { Ok(len as usize) } else { Err(::windows::error::last_os_error()) };
+
+            if self.complete(Token(key), operation, result) {
+                dispatched += 1;
+            }
+
+            // collect the completions that are already queued without waiting again
+            wait = if dispatched > 0 { Duration::from_secs(0) } else { deadline.saturating_duration_since(Instant::now()) };
+        }
+    }
+
+    /// Dispatches a completed operation. Returns `false` if the port has been removed.
+    fn complete(&mut self, token: Token, mut operation: Box<Operation>, result: ::Result<usize>) -> bool {
+        let index = match self.index(token) {
+            Ok(index) => index,
+            Err(_) => return false
+        };
+
+        match result {
+            Ok(len) if operation.read => {
+                self.dispatch(index, Event::Received(&operation.buffer[..len]));
+
+                operation.overlapped = unsafe { mem::zeroed() };
+                self.restart_read(index, operation);
+            },
+            Ok(len) => self.dispatch(index, Event::Written(len)),
+            Err(err) => {
+                let err = ::disconnected(err).with_device(self.entries[index].device.clone()).with_operation(if operation.read { "read" } else { "write" });
+                self.dispatch(index, Event::Failed(err));
+            }
+        }
+
+        true
+    }
+
+    /// Calls a port's handler with an event.
+    ///
+    /// The handler is taken out of its entry while it runs, so that its `Writer` can borrow the
+    /// driver. Writers can't add or remove ports, so `index` stays valid.
+    fn dispatch(&mut self, index: usize, event: Event) {
+        let token = self.entries[index].token;
+        let mut handler = mem::replace(&mut self.entries[index].handler, Box::new(ignore));
+
+        handler(event, &mut Writer { driver: self, token });
+
+        self.entries[index].handler = handler;
+    }
+
+    fn start_read(&mut self, index: usize) {
+        let operation = Operation::new(true, vec![0; self.buffer_size]);
+        self.restart_read(index, operation);
+    }
+
+    fn restart_read(&mut self, index: usize, mut operation: Box<Operation>) {
+        let entry = &mut self.entries[index];
+
+        let ok = unsafe {
+            ReadFile(entry.handle, operation.buffer.as_mut_ptr() as LPVOID, operation.buffer.len() as DWORD, ptr::null_mut(), &mut operation.overlapped)
+        };
+
+        if ok == 0 && unsafe { GetLastError() } != ERROR_IO_PENDING {
+            let err = ::disconnected(::windows::error::last_os_error()).with_device(entry.device.clone()).with_operation("read");
+            self.dispatch(index, Event::Failed(err));
+            return;
+        }
+
+        mem::forget(operation);
+        self.pending += 1;
+    }
+
+    fn index(&self, token: Token) -> ::Result<usize> {
+        match self.entries.iter().position(|entry| entry.token == token) {
+            Some(index) => Ok(index),
+            None => Err(::Error::new(::ErrorKind::InvalidInput, "no such port"))
+        }
+    }
+}
+
+fn ignore(_: Event, _: &mut Writer) {}
+
+/// Pending operations are cancelled, and their completions are drained from the completion port
+/// so that the operations can be freed. Events aren't dispatched for them. An operation that
+/// hasn't completed within a second of being cancelled is leaked, since the kernel may still
+/// write to its buffer.
+impl Drop for Driver {
+    fn drop(&mut self) {
+        for entry in &self.entries {
+            unsafe { CancelIoEx(entry.handle, ptr::null_mut()) };
+        }
+
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+
+        while self.pending > 0 {
+            let mut len: DWORD = 0;
+            let mut key: ULONG_PTR = 0;
+            let mut overlapped: LPOVERLAPPED = ptr::null_mut();
+
+            let milliseconds = deadline.saturating_duration_since(Instant::now()).as_millis() as DWORD;
+            unsafe { GetQueuedCompletionStatus(self.port, &mut len, &mut key, &mut overlapped, milliseconds) };
+
+            if overlapped.is_null() {
+                break;
+            }
+
+            drop(unsafe { Box::from_raw(overlapped as *mut Operation) });
+            self.pending -= 1;
+        }
+
+        for entry in &self.entries {
+            unsafe { CloseHandle(entry.handle) };
+        }
+
+        unsafe { CloseHandle(self.port) };
+    }
+}
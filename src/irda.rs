@@ -0,0 +1,499 @@
+//! IrDA SIR dongles attached to serial ports.
+//!
+//! A SIR (Serial Infrared) dongle converts the UART signal of a serial port to infrared pulses at
+//! up to 115,200 baud. Linux used to drive these dongles with the `irtty-sir` line discipline, but
+//! the kernel's IrDA stack was removed in Linux 4.17, so devices that still speak IrDA, such as
+//! legacy meters and medical instruments, have to be served from user space.
+//!
+//! An `IrdaPort` wraps the port that a dongle is attached to. It powers the dongle, switches the
+//! dongle's speed along with the port's baud rate, rejects settings that SIR doesn't support, and
+//! discards the echo of transmitted data. It's a `SerialPort` itself, so it can be configured and
+//! used like any other port. `SirCodec` frames data with the IrLAP asynchronous wrapper:
+//!
+//! ```no_run
+//! use serial::prelude::*;
+//! use serial::codec::Framed;
+//! use serial::irda::{IrdaPort,SirCodec,Tekram};
+//!
+//! let port = serial::open("/dev/ttyS0").unwrap();
+//! let mut irda = IrdaPort::new(port, Tekram).unwrap();
+//!
+//! // IrDA devices are discovered at 9600 baud before negotiating a higher speed
+//! irda.reconfigure(|settings| settings.set_baud_rate(serial::Baud9600)).unwrap();
+//!
+//! let mut framed = Framed::new(irda, SirCodec::new());
+//! framed.write_frame(&vec![0xff, 0x3f, 0x01]).unwrap();
+//! ```
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use ::{BaudRate,SerialDevice,SerialPort,SerialPortSettings};
+use ::codec::{Decoder,Encoder};
+
+/// The baud rates supported by SIR.
+pub const SIR_SPEEDS: [usize; 6] = [2400, 9600, 19200, 38400, 57600, 115200];
+
+const BOF: u8 = 0xc0;
+const EOF: u8 = 0xc1;
+const CE: u8 = 0x7d;
+const XBOF: u8 = 0xff;
+
+/// A strategy for controlling a type of SIR dongle.
+pub trait Dongle {
+    /// Powers the dongle.
+    ///
+    /// The default implementation asserts DTR and RTS, which power most dongles.
+    fn power_on<P: SerialPort + ?Sized>(&mut self, port: &mut P) -> ::Result<()> {
+        port.set_dtr(true)?;
+        port.set_rts(true)
+    }
+
+    /// Switches the dongle to `speed`.
+    ///
+    /// This is called before the port's baud rate is changed to `speed`.
+    fn set_speed<P: SerialPort + ?Sized>(&mut self, port: &mut P, speed: usize) -> ::Result<()>;
+
+    /// Returns `true` if the dongle supports `speed`.
+    ///
+    /// The default implementation accepts the speeds in `SIR_SPEEDS`.
+    fn supports(&self, speed: usize) -> bool {
+        SIR_SPEEDS.contains(&speed)
+    }
+}
+
+/// A dongle that follows the port's baud rate without being configured.
+///
+/// This suits dongles that only contain an infrared transceiver and its pulse shaping.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq)]
+pub struct Generic;
+
+impl Dongle for Generic {
+    fn set_speed<P: SerialPort + ?Sized>(&mut self, _: &mut P, _: usize) -> ::Result<()> {
+        Ok(())
+    }
+}
+
+/// A Tekram IR-210B dongle, or a compatible dongle such as the IRMate 210.
+///
+/// The dongle's speed is set by sending it a control byte at 9600 baud while RTS is cleared.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq)]
+pub struct Tekram;
+
+impl Tekram {
+    const DELAY: Duration = Duration::from_millis(50);
+
+    /// Selects the shorter 1.6 µs pulse width.
+    const PULSE_WIDTH: u8 = 0x10;
+}
+
+impl Dongle for Tekram {
+    fn set_speed<P: SerialPort + ?Sized>(&mut self, port: &mut P, speed: usize) -> ::Result<()> {
+        let code = match speed {
+            2400 => 0x08,
+            9600 => 0x04,
+            19200 => 0x03,
+            38400 => 0x02,
+            57600 => 0x01,
+            115200 => 0x00,
+            _ => return Err(unsupported_speed(speed))
+        };
+
+        port.reconfigure_dyn(&mut |settings| settings.set_baud_rate(BaudRate::Baud9600))?;
+
+        port.set_dtr(true)?;
+        port.set_rts(false)?;
+        thread::sleep(Tekram::DELAY);
+
+        port.write_all(&[code | Tekram::PULSE_WIDTH])?;
+        port.flush()?;
+        thread::sleep(Tekram::DELAY);
+
+        port.set_rts(true)
+    }
+}
+
+fn unsupported_speed(speed: usize) -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, format!("{} baud is not supported by the dongle", speed)).with_operation("set IrDA speed")
+}
+
+/// A serial port with a SIR dongle attached.
+///
+/// Settings are restricted to the dongle's speeds with 8 data bits, no parity, one stop bit, and
+/// no flow control, since the dongle's power and control signals share the RTS and DTR lines.
+/// Changing the baud rate also switches the dongle's speed.
+///
+/// Infrared transceivers usually receive their own transmissions, so the input buffer is
+/// discarded after each write by default.
+pub struct IrdaPort<P, D> {
+    port: P,
+    dongle: D,
+    speed: Option<usize>,
+    discard_echo: bool
+}
+
+impl<P: SerialPort, D: Dongle> IrdaPort<P, D> {
+    /// Wraps the port that a dongle is attached to, and powers and configures the dongle at 9600
+    /// baud.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the port couldn't be configured.
+    pub fn new(mut port: P, mut dongle: D) -> ::Result<Self> {
+        dongle.power_on(&mut port)?;
+        dongle.set_speed(&mut port, 9600)?;
+
+        port.reconfigure_dyn(&mut |settings| {
+            settings.set_baud_rate(BaudRate::Baud9600)?;
+            settings.set_char_size(::Bits8);
+            settings.set_parity(::ParityNone);
+            settings.set_stop_bits(::Stop1);
+            settings.set_flow_control(::FlowNone);
+            Ok(())
+        })?;
+
+        Ok(IrdaPort {
+            port,
+            dongle,
+            speed: Some(9600),
+            discard_echo: true
+        })
+    }
+
+    /// Returns whether the input buffer is discarded after each write.
+    pub fn discards_echo(&self) -> bool {
+        self.discard_echo
+    }
+
+    /// Sets whether the input buffer is discarded after each write.
+    ///
+    /// Disable this for dongles that blind their receiver while transmitting, so that a response
+    /// that starts right after a write isn't lost.
+    pub fn set_discard_echo(&mut self, discard_echo: bool) {
+        self.discard_echo = discard_echo;
+    }
+
+    /// Returns a reference to the dongle.
+    pub fn dongle(&self) -> &D {
+        &self.dongle
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
+
+impl<P: SerialPort, D: Dongle> io::Read for IrdaPort<P, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl<P: SerialPort, D: Dongle> io::Write for IrdaPort<P, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write_all(buf)?;
+
+        if self.discard_echo {
+            self.port.flush()?;
+            self.port.discard_input()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: SerialDevice, D: Dongle> SerialDevice for IrdaPort<P, D> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, read_with_timeout, discard_input);
+
+    /// Checks that `settings` are supported by SIR, switches the dongle's speed if the baud rate
+    /// changed, and applies `settings` to the port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if the dongle doesn't support the
+    /// baud rate, or if the settings aren't 8N1 without flow control.
+    fn write_settings(&mut self, settings: &P::Settings) -> ::Result<()> {
+        let speed = settings.baud_rate().map(|baud_rate| baud_rate.speed()).unwrap_or(0);
+
+        if !self.dongle.supports(speed) {
+            return Err(unsupported_speed(speed));
+        }
+
+        if settings.char_size() != Some(::Bits8) || settings.parity() != Some(::ParityNone) || settings.stop_bits() != Some(::Stop1) || settings.flow_control() != Some(::FlowNone) {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "SIR requires 8N1 without flow control").with_operation("write settings"));
+        }
+
+        if self.speed != Some(speed) {
+            // the dongle's speed is unknown until it's switched successfully
+            self.speed = None;
+            self.dongle.set_speed(&mut self.port, speed)?;
+        }
+
+        self.port.write_settings(settings)?;
+        self.speed = Some(speed);
+        Ok(())
+    }
+}
+
+/// Computes the IrLAP frame check sequence (CRC-16/X-25) of `data`.
+///
+/// The FCS is transmitted with its low byte first.
+pub fn fcs(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+
+    for &byte in data {
+        crc ^= byte as u16;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+/// A codec for IrLAP frames in the SIR asynchronous wrapper.
+///
+/// Frames start with a BOF (`0xC0`) and end with an EOF (`0xC1`) after a 16-bit FCS. Frame data
+/// doesn't include the FCS.
+#[derive(Debug,Clone)]
+pub struct SirCodec {
+    extra_bofs: usize,
+    max_length: usize
+}
+
+impl SirCodec {
+    /// Creates a codec that sends 10 extra BOFs and accepts frames of up to 2048 bytes.
+    pub fn new() -> Self {
+        SirCodec {
+            extra_bofs: 10,
+            max_length: 2048
+        }
+    }
+
+    /// Sets the number of extra BOFs sent before each frame.
+    ///
+    /// IrLAP negotiates the number of extra BOFs a receiver needs to recover between frames.
+    pub fn set_extra_bofs(&mut self, extra_bofs: usize) {
+        self.extra_bofs = extra_bofs;
+    }
+}
+
+impl Default for SirCodec {
+    fn default() -> Self {
+        SirCodec::new()
+    }
+}
+
+impl Decoder for SirCodec {
+    type Item = Vec<u8>;
+
+    /// Decodes the next frame.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if a frame's FCS doesn't match, a frame was
+    /// aborted, or a frame exceeds the maximum length.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Vec<u8>>> {
+        // data before a BOF can't be part of a frame
+        let start = match buf.iter().rposition(|&b| b == BOF) {
+            Some(start) => start,
+            None => {
+                buf.clear();
+                return Ok(None);
+            }
+        };
+
+        let start = match buf[..start].iter().position(|&b| b == EOF) {
+            // an earlier frame is complete, so decode it first
+            Some(end) => match buf[..end].iter().rposition(|&b| b == BOF) {
+                Some(start) => start,
+                None => {
+                    buf.drain(..end + 1);
+                    return self.decode(buf);
+                }
+            },
+            None => start
+        };
+
+        buf.drain(..start);
+
+        let end = match buf.iter().position(|&b| b == EOF) {
+            Some(end) => end,
+            None if buf.len() > 2 * (self.max_length + 2) => {
+                buf.clear();
+                return Err(invalid_frame("frame exceeds maximum length"));
+            },
+            None => return Ok(None)
+        };
+
+        let wrapped: Vec<u8> = buf.drain(..end + 1).collect();
+        let mut frame = Vec::with_capacity(end);
+        let mut escaped = false;
+
+        for &byte in &wrapped[1..end] {
+            if escaped {
+                frame.push(byte ^ 0x20);
+                escaped = false;
+            }
+            else if byte == CE {
+                escaped = true;
+            }
+            else {
+                frame.push(byte);
+            }
+        }
+
+        if escaped {
+            return Err(invalid_frame("frame aborted"));
+        }
+
+        if frame.len() < 2 || frame.len() > self.max_length + 2 {
+            return Err(invalid_frame("invalid frame length"));
+        }
+
+        let data_len = frame.len() - 2;
+
+        if fcs(&frame[..data_len]) != (frame[data_len] as u16 | (frame[data_len + 1] as u16) << 8) {
+            return Err(invalid_frame("FCS mismatch"));
+        }
+
+        frame.truncate(data_len);
+        Ok(Some(frame))
+    }
+}
+
+impl Encoder for SirCodec {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, frame: &Vec<u8>, buf: &mut Vec<u8>) -> ::Result<()> {
+        if frame.len() > self.max_length {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "frame exceeds maximum length").with_operation("encode frame"));
+        }
+
+        let fcs = fcs(frame);
+
+        buf.extend((0..self.extra_bofs).map(|_| XBOF));
+        buf.push(BOF);
+
+        for &byte in frame.iter().chain(&[fcs as u8, (fcs >> 8) as u8]) {
+            if byte == BOF || byte == EOF || byte == CE {
+                buf.push(CE);
+                buf.push(byte ^ 0x20);
+            }
+            else {
+                buf.push(byte);
+            }
+        }
+
+        buf.push(EOF);
+        Ok(())
+    }
+}
+
+fn invalid_frame(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("decode frame")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use super::{fcs,Generic,IrdaPort,SirCodec,Tekram};
+    use ::{SerialDevice,SerialPort,SerialPortSettings};
+    use ::codec::{Decoder,Encoder};
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn fcs_matches_check_value() {
+        assert_eq!(fcs(b"123456789"), 0x906e);
+    }
+
+    #[test]
+    fn codec_round_trips_escaped_frames() {
+        let mut codec = SirCodec::new();
+        codec.set_extra_bofs(2);
+
+        let frame = vec![0xff, 0xc0, 0x7d, 0xc1, 0x01];
+        let mut buf = b"noise".to_vec();
+        codec.encode(&frame, &mut buf).unwrap();
+
+        assert_eq!(&buf[5..10], &[0xff, 0xff, 0xc0, 0xff, 0x7d]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn codec_rejects_corrupt_and_aborted_frames() {
+        let mut codec = SirCodec::new();
+        let mut buf = Vec::new();
+        codec.encode(&vec![0x01, 0x02], &mut buf).unwrap();
+
+        let mut corrupt = buf.clone();
+        corrupt[12] ^= 0x01;
+        assert!(codec.decode(&mut corrupt).is_err());
+
+        let mut aborted = vec![0xc0, 0x01, 0x7d, 0xc1];
+        aborted.extend_from_slice(&buf);
+        assert!(codec.decode(&mut aborted).is_err());
+        assert_eq!(codec.decode(&mut aborted).unwrap(), Some(vec![0x01, 0x02]));
+    }
+
+    #[test]
+    fn codec_rejects_oversized_frames() {
+        let mut codec = SirCodec::new();
+        let mut buf = Vec::new();
+
+        assert_eq!(codec.encode(&vec![0; 2049], &mut buf).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn irda_port_powers_dongle_and_discards_echo() {
+        let mut irda = IrdaPort::new(ScriptedDevice::new(), Generic).unwrap();
+        assert!(irda.get_ref().dtr && irda.get_ref().rts);
+        assert_eq!(irda.read_settings().unwrap().baud_rate(), Some(::Baud9600));
+
+        irda.write_all(b"ping").unwrap();
+        assert!(irda.get_ref().events.ends_with(&[Event::Write(b"ping".to_vec()), Event::Flush, Event::DiscardInput]));
+    }
+
+    #[test]
+    fn irda_port_rejects_unsupported_settings() {
+        let mut irda = IrdaPort::new(ScriptedDevice::new(), Generic).unwrap();
+
+        assert_eq!(irda.reconfigure(|settings| settings.set_baud_rate(::Baud4800)).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(irda.reconfigure(|settings| { settings.set_parity(::ParityEven); Ok(()) }).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(irda.get_ref().settings.baud_rate(), Some(::Baud9600));
+    }
+
+    #[test]
+    fn tekram_switches_speed_with_control_byte() {
+        let mut irda = IrdaPort::new(ScriptedDevice::new(), Tekram).unwrap();
+        irda.get_mut().events.clear();
+
+        irda.reconfigure(|settings| settings.set_baud_rate(::Baud115200)).unwrap();
+
+        assert_eq!(irda.get_ref().events, vec![Event::Dtr(true), Event::Rts(false), Event::Write(vec![0x10]), Event::Flush, Event::Rts(true)]);
+        assert_eq!(irda.get_ref().settings.baud_rate(), Some(::Baud115200));
+    }
+}
@@ -0,0 +1,151 @@
+//! Blocking iterators over data received from a port.
+//!
+//! Iterators make simple tools, such as loggers, a `for` loop:
+//!
+//! ```no_run
+//! use serial::prelude::*;
+//!
+//! let mut port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! for line in port.lines(b'\n') {
+//!     match line {
+//!         Ok(line) => println!("{}", String::from_utf8_lossy(&line)),
+//!         Err(ref err) if err.is_transient() => continue,
+//!         Err(err) => panic!("{}", err)
+//!     }
+//! }
+//! ```
+//!
+//! A timeout is yielded as an error of kind `Io(TimedOut)`, after which iteration continues. Any
+//! other error is yielded once and ends the iteration. See also `codec::Framed::frames()`.
+
+use std::io;
+use std::mem;
+
+/// An iterator over delimited lines, created by `SerialPort::lines()`.
+///
+/// Lines are yielded without the delimiter. When the port reaches end of file, data that was
+/// received after the last delimiter is yielded as a final line.
+pub struct Lines<R> {
+    reader: R,
+    delimiter: u8,
+    buffer: Vec<u8>,
+    done: bool
+}
+
+impl<R: io::Read> Lines<R> {
+    /// Creates an iterator over lines read from `reader`.
+    pub fn new(reader: R, delimiter: u8) -> Self {
+        Lines {
+            reader,
+            delimiter,
+            buffer: Vec::new(),
+            done: false
+        }
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Data that has been received but not yet yielded is discarded.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: io::Read> Iterator for Lines<R> {
+    type Item = ::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<::Result<Vec<u8>>> {
+        let mut buf = [0; 256];
+
+        while !self.done {
+            if let Some(end) = self.buffer.iter().position(|&byte| byte == self.delimiter) {
+                let mut line: Vec<u8> = self.buffer.drain(..end + 1).collect();
+                line.pop();
+                return Some(Ok(line));
+            }
+
+            match self.reader.read(&mut buf) {
+                Ok(0) => {
+                    self.done = true;
+
+                    if !self.buffer.is_empty() {
+                        return Some(Ok(mem::take(&mut self.buffer)));
+                    }
+                },
+                Ok(len) => self.buffer.extend_from_slice(&buf[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    let err = ::Error::from(err);
+                    self.done = !err.is_transient();
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use ::prelude::*;
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn lines_splits_at_delimiter() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"one;two;");
+
+        let lines: Vec<Vec<u8>> = device.lines(b';').take(2).map(Result::unwrap).collect();
+
+        assert_eq!(lines, vec![b"one".to_vec(), b"two".to_vec()]);
+    }
+
+    #[test]
+    fn lines_continues_after_timeout() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"par");
+
+        let mut lines = device.lines(b'\n');
+
+        let err = lines.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+
+        lines.get_mut().push_input(b"tial\n");
+        assert_eq!(lines.next().unwrap().unwrap(), b"partial");
+    }
+
+    #[test]
+    fn lines_ends_after_fatal_error() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"ok\n");
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let mut lines = device.lines(b'\n');
+
+        assert_eq!(lines.next().unwrap().unwrap(), b"ok");
+        assert!(lines.next().unwrap().unwrap_err().is_disconnection());
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn lines_yields_final_line_at_end_of_file() {
+        let lines: Vec<Vec<u8>> = super::Lines::new(&b"a\nb"[..], b'\n').map(Result::unwrap).collect();
+
+        assert_eq!(lines, vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+}
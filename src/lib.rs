@@ -1,8 +1,10 @@
+use std::cmp::Ordering;
 use std::default::Default;
 use std::error::Error as StdError;
 use std::ffi::OsStr;
 use std::fmt;
 use std::io;
+use std::str::FromStr;
 use std::time::Duration;
 
 
@@ -23,12 +25,148 @@ pub mod prelude {
     pub use ::{SerialPort,SerialPortSettings};
 }
 
+#[macro_use]
+mod macros;
+
 #[cfg(unix)]
 pub mod posix;
 
 #[cfg(windows)]
 pub mod windows;
 
+pub mod buffered;
+
+pub mod iter;
+
+mod shutdown;
+pub use shutdown::Shutdown;
+
+#[cfg(feature = "aggregate")]
+pub mod aggregate;
+
+#[cfg(feature = "analyzer")]
+pub mod analyzer;
+
+#[cfg(feature = "arena")]
+pub mod arena;
+
+#[cfg(feature = "broadcast")]
+pub mod broadcast;
+
+#[cfg(feature = "channel")]
+pub mod channel;
+
+#[cfg(feature = "codec")]
+pub mod codec;
+
+#[cfg(feature = "console")]
+pub mod console;
+
+#[cfg(feature = "dialup")]
+pub mod dialup;
+
+#[cfg(feature = "enumerate")]
+pub mod enumerate;
+
+#[cfg(feature = "escpos")]
+pub mod escpos;
+
+#[cfg(feature = "events")]
+pub mod events;
+
+#[cfg(feature = "firmata")]
+pub mod firmata;
+
+#[cfg(feature = "gateway")]
+pub mod gateway;
+
+#[cfg(feature = "hexdump")]
+pub mod hexdump;
+
+#[cfg(all(feature = "iocp", windows))]
+pub mod iocp;
+
+#[cfg(feature = "irda")]
+pub mod irda;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+
+#[cfg(feature = "monitor")]
+pub mod monitor;
+
+#[cfg(feature = "modbus")]
+pub mod modbus;
+
+#[cfg(feature = "nmea")]
+pub mod nmea;
+
+#[cfg(feature = "paced")]
+pub mod paced;
+
+#[cfg(feature = "poller")]
+pub mod poller;
+
+#[cfg(feature = "profile")]
+pub mod profile;
+
+#[cfg(feature = "replay")]
+pub mod replay;
+
+#[cfg(feature = "responder")]
+pub mod responder;
+
+#[cfg(feature = "ring")]
+pub mod ring;
+
+#[cfg(feature = "rs485")]
+pub mod rs485;
+
+#[cfg(feature = "scale")]
+pub mod scale;
+
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+
+#[cfg(feature = "selftest")]
+pub mod selftest;
+
+#[cfg(feature = "serialport")]
+pub mod serialport;
+
+#[cfg(feature = "session")]
+pub mod session;
+
+#[cfg(feature = "stk500")]
+pub mod stk500;
+
+#[cfg(feature = "testkit")]
+pub mod testkit;
+
+#[cfg(feature = "throttle")]
+pub mod throttle;
+
+#[cfg(feature = "tools")]
+pub mod tools;
+
+#[cfg(feature = "transaction")]
+pub mod transaction;
+
+#[cfg(feature = "trigger")]
+pub mod trigger;
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub mod uring;
+
+#[cfg(feature = "url")]
+pub mod url;
+
+#[cfg(test)]
+mod testing;
+
 
 /// A type for results generated by interacting with serial ports.
 ///
@@ -37,15 +175,24 @@ pub type Result<T> = std::result::Result<T,::Error>;
 
 /// Categories of errors that can occur when interacting with serial ports.
 ///
-/// This list is intended to grow over time and it is not recommended to exhaustively match against it.
-#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+/// This list is intended to grow over time, so matching against it requires a wildcard arm.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// The device is not available.
     ///
-    /// This could indicate that the device is in use by another process or was disconnected while
-    /// performing I/O.
+    /// This could indicate that the device doesn't exist or was disconnected while performing I/O.
     NoDevice,
 
+    /// The device is in use by another process.
+    Busy,
+
+    /// The process doesn't have permission to access the device.
+    ///
+    /// On Unix systems, this usually means that the user isn't a member of the group that owns the
+    /// device file, e.g., `dialout` or `uucp`.
+    PermissionDenied,
+
     /// A parameter was incorrect.
     InvalidInput,
 
@@ -56,41 +203,152 @@ pub enum ErrorKind {
 }
 
 /// An error type for serial port operations.
+///
+/// Besides its `ErrorKind` and description, an error can carry the name of the device and the
+/// operation that failed as well as the underlying error that caused it, which is available
+/// through `std::error::Error::source()`.
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
-    description: String
+    description: String,
+    device: Option<String>,
+    operation: Option<&'static str>,
+    source: Option<Box<dyn StdError + Send + Sync>>
 }
 
 impl Error {
     pub fn new<T: Into<String>>(kind: ErrorKind, description: T) -> Self {
         Error {
-            kind: kind,
-            description: description.into()
+            kind,
+            description: description.into(),
+            device: None,
+            operation: None,
+            source: None
         }
     }
 
+    /// Attaches the name of the device that caused the error.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let error = serial::Error::new(serial::ErrorKind::NoDevice, "No such file or directory")
+    ///     .with_device("/dev/ttyUSB0")
+    ///     .with_operation("open");
+    ///
+    /// assert_eq!(error.to_string(), "/dev/ttyUSB0: open: No such file or directory");
+    /// ```
+    pub fn with_device<T: Into<String>>(mut self, device: T) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+
+    /// Attaches the name of the operation that failed, e.g., `"read"` or `"open"`.
+    pub fn with_operation(mut self, operation: &'static str) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Attaches the underlying error that caused this error.
+    pub fn with_source<E: Into<Box<dyn StdError + Send + Sync>>>(mut self, source: E) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
     /// Returns the corresponding `ErrorKind` for this error.
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// Returns the name of the device that caused the error, if known.
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_ref().map(|device| &device[..])
+    }
+
+    /// Returns the name of the operation that failed, if known.
+    pub fn operation(&self) -> Option<&str> {
+        self.operation
+    }
+
+    /// Returns the OS error code that caused this error, if any.
+    ///
+    /// This is `errno` on Unix and the value of `GetLastError()` on Windows.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self.source {
+            Some(ref source) => source.downcast_ref::<io::Error>().and_then(io::Error::raw_os_error),
+            None => None
+        }
+    }
+
+    /// Returns `true` if the operation may succeed when retried on the same port.
+    ///
+    /// This is the case for interrupted system calls, timeouts, and operations that would block.
+    ///
+    /// ```
+    /// use std::io;
+    ///
+    /// let error = serial::Error::new(serial::ErrorKind::Io(io::ErrorKind::TimedOut), "Operation timed out");
+    /// assert!(error.is_transient());
+    /// ```
+    pub fn is_transient(&self) -> bool {
+        matches!(self.kind, ErrorKind::Io(io::ErrorKind::Interrupted) | ErrorKind::Io(io::ErrorKind::TimedOut) | ErrorKind::Io(io::ErrorKind::WouldBlock))
+    }
+
+    /// Returns `true` if the error indicates that the device was disconnected.
+    ///
+    /// A disconnected port won't recover, so it should be closed and reopened once the device
+    /// reappears. Besides errors of kind `NoDevice`, this recognizes errors that platforms report
+    /// for unplugged devices, such as `EIO` on Unix and `ERROR_DEVICE_NOT_CONNECTED` on Windows.
+    pub fn is_disconnection(&self) -> bool {
+        match self.kind {
+            ErrorKind::NoDevice | ErrorKind::Io(io::ErrorKind::BrokenPipe) => true,
+            _ => self.raw_os_error().is_some_and(is_disconnection_os_error)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_disconnection_os_error(errno: i32) -> bool {
+    posix::error::is_disconnection(errno)
+}
+
+#[cfg(windows)]
+fn is_disconnection_os_error(errno: i32) -> bool {
+    windows::error::is_disconnection(errno)
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        if let Some(ref device) = self.device {
+            write!(fmt, "{}: ", device)?;
+        }
+
+        if let Some(operation) = self.operation {
+            write!(fmt, "{}: ", operation)?;
+        }
+
         fmt.write_str(&self.description)
     }
 }
 
 impl StdError for Error {
-    fn description(&self) -> &str {
-        &self.description
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.source {
+            Some(ref source) => Some(&**source),
+            None => None
+        }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(io_error: io::Error) -> Error {
-        Error::new(ErrorKind::Io(io_error.kind()), format!("{}", io_error))
+        // unwrap errors that were converted from `serial::Error` to preserve their context
+        if io_error.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = io_error.into_inner().unwrap();
+            return *inner.downcast::<Error>().unwrap();
+        }
+
+        Error::new(ErrorKind::Io(io_error.kind()), io_error.to_string()).with_source(io_error)
     }
 }
 
@@ -98,11 +356,13 @@ impl From<Error> for io::Error {
     fn from(error: Error) -> io::Error {
         let kind = match error.kind {
             ErrorKind::NoDevice => io::ErrorKind::NotFound,
+            ErrorKind::Busy => io::ErrorKind::ResourceBusy,
+            ErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
             ErrorKind::InvalidInput => io::ErrorKind::InvalidInput,
             ErrorKind::Io(kind) => kind
         };
 
-        io::Error::new(kind, error.description)
+        io::Error::new(kind, error)
     }
 }
 
@@ -124,8 +384,9 @@ pub type SystemPort = windows::COMPort;
 ///
 /// This function returns an error if the device could not be opened and initialized:
 ///
-/// * `NoDevice` if the device could not be opened. This could indicate that the device is
-///   already in use.
+/// * `NoDevice` if the device doesn't exist or could not be opened.
+/// * `Busy` if the device is already in use by another process.
+/// * `PermissionDenied` if the process lacks permission to open the device.
 /// * `InvalidInput` if `port` is not a valid device name.
 /// * `Io` for any other error while opening or initializing the device.
 ///
@@ -163,8 +424,9 @@ pub fn open<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<SystemPort> {
 ///
 /// This function returns an error if the device could not be opened and initialized:
 ///
-/// * `NoDevice` if the device could not be opened. This could indicate that the device is
-///   already in use.
+/// * `NoDevice` if the device doesn't exist or could not be opened.
+/// * `Busy` if the device is already in use by another process.
+/// * `PermissionDenied` if the process lacks permission to open the device.
 /// * `InvalidInput` if `port` is not a valid device name.
 /// * `Io` for any other error while opening or initializing the device.
 ///
@@ -191,6 +453,284 @@ pub fn open<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<SystemPort> {
     windows::COMPort::open(port)
 }
 
+/// Opens a native serial port as a boxed trait object.
+///
+/// This is useful for applications that choose between native ports and other transports at
+/// runtime. The argument is interpreted as by [`serial::open()`](fn.open.html), which returns the
+/// same errors.
+///
+/// ```no_run
+/// use serial::prelude::*;
+///
+/// let mut port = serial::open_boxed("/dev/ttyUSB0").unwrap();
+///
+/// port.reconfigure(|settings| settings.set_baud_rate(serial::Baud115200)).unwrap();
+/// ```
+pub fn open_boxed<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<Box<dyn SerialPort + Send>> {
+    Ok(Box::new(open(port)?))
+}
+
+#[cfg(feature = "url")]
+pub use url::open_url;
+
+/// The access mode that a port is opened with.
+///
+/// More modes may be added in the future, so matching against it requires a wildcard arm.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Access {
+    /// The port can be read from and written to.
+    #[default]
+    ReadWrite,
+
+    /// The port can only be read from, e.g., to monitor a link passively.
+    ///
+    /// Writes fail with an error of kind `Io`.
+    ReadOnly,
+
+    /// The port can only be written to, e.g., for a transmit-only beacon.
+    ///
+    /// Reads fail with an error of kind `Io`.
+    WriteOnly
+}
+
+/// Options for opening a native serial port.
+///
+/// This builder can be used to control how a port is initialized when it's opened. Options that
+/// aren't set leave the corresponding state as the operating system and driver initialize it.
+///
+/// ```no_run
+/// // open an Arduino without resetting it
+/// let port = serial::OpenOptions::new().dtr(false).open("/dev/ttyACM0").unwrap();
+/// ```
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct OpenOptions {
+    dtr: Option<bool>,
+    rts: Option<bool>,
+    wait_for_carrier: bool,
+    restore_on_close: bool,
+    preserve_settings: bool,
+    access: Access,
+
+    #[cfg(unix)]
+    hang_up_on_close: Option<bool>,
+
+    #[cfg(unix)]
+    controlling_terminal: bool,
+
+    #[cfg(unix)]
+    close_on_exec: bool,
+
+    #[cfg(windows)]
+    shared: bool
+}
+
+impl OpenOptions {
+    /// Creates a set of options with nothing set.
+    pub fn new() -> Self {
+        OpenOptions::default()
+    }
+
+    /// Sets the state of the DTR (Data Terminal Ready) control signal when the port is opened.
+    ///
+    /// Setting `false` avoids asserting DTR on devices that are reset or powered by it, such as
+    /// Arduinos and DTR-powered sensors. On Windows, this sets `fDtrControl` to
+    /// `DTR_CONTROL_ENABLE` or `DTR_CONTROL_DISABLE` before the port is used.
+    ///
+    /// ## Portability
+    ///
+    /// Some POSIX systems, including Linux, assert DTR and RTS when a TTY device is opened. On those
+    /// systems the signal is cleared immediately after opening, which may leave a brief pulse. The
+    /// pulse is avoided if the signal was left deasserted when the port was last closed, which
+    /// requires disabling hang-up on close (`stty -hupcl`).
+    pub fn dtr(&mut self, level: bool) -> &mut Self {
+        self.dtr = Some(level);
+        self
+    }
+
+    /// Sets the state of the RTS (Request To Send) control signal when the port is opened.
+    ///
+    /// On Windows, this sets `fRtsControl` to `RTS_CONTROL_ENABLE` or `RTS_CONTROL_DISABLE` before
+    /// the port is used. The same portability caveat applies as for [`dtr()`](#method.dtr).
+    pub fn rts(&mut self, level: bool) -> &mut Self {
+        self.rts = Some(level);
+        self
+    }
+
+    /// Sets whether opening the port blocks until the CD (Carrier Detect) signal is asserted.
+    ///
+    /// This is the traditional way for dial-in applications to wait for an incoming connection. On
+    /// POSIX systems, it also enables modem control (see `TTYSettings::set_modem_control()`), so
+    /// that the port is hung up when carrier is lost. The default is `false`.
+    pub fn wait_for_carrier(&mut self, wait_for_carrier: bool) -> &mut Self {
+        self.wait_for_carrier = wait_for_carrier;
+        self
+    }
+
+    /// Sets whether DTR and RTS are lowered when the port is closed.
+    ///
+    /// Setting `false` keeps devices that are reset or powered by DTR running after the port is
+    /// closed. See `TTYSettings::set_hang_up_on_close()` for details. This option is only available
+    /// on POSIX systems.
+    #[cfg(unix)]
+    pub fn hang_up_on_close(&mut self, hang_up_on_close: bool) -> &mut Self {
+        self.hang_up_on_close = Some(hang_up_on_close);
+        self
+    }
+
+    /// Sets whether the port's original state is restored when it's closed.
+    ///
+    /// The device's state, including its settings and the levels of DTR and RTS, is captured with
+    /// `SerialDevice::snapshot()` before it's initialized, and restored when the port is closed
+    /// or dropped. This leaves a console that was configured by another program exactly as it was
+    /// found. Closing the port explicitly with `close()` reports errors while restoring it. The
+    /// default is `false`.
+    pub fn restore_on_close(&mut self, restore_on_close: bool) -> &mut Self {
+        self.restore_on_close = restore_on_close;
+        self
+    }
+
+    /// Sets whether the port is opened without changing its current settings.
+    ///
+    /// Ports are normally initialized for binary access when they're opened, which disables
+    /// canonical mode, echo, and the translation of line endings on POSIX systems. Setting `true`
+    /// attaches to the port as it was left by another program or a bootloader instead, e.g., to
+    /// monitor a console that's already configured. Options that are set explicitly, such as the
+    /// levels of DTR and RTS, are still applied. The default is `false`.
+    ///
+    /// ## Portability
+    ///
+    /// On Windows, the port's `DCB` isn't initialized when opening, so this option has no effect.
+    /// On POSIX systems, a port that's left in canonical mode only becomes readable once a
+    /// complete line has been received.
+    pub fn preserve_settings(&mut self, preserve_settings: bool) -> &mut Self {
+        self.preserve_settings = preserve_settings;
+        self
+    }
+
+    /// Sets the access mode that the port is opened with. The default is `Access::ReadWrite`.
+    ///
+    /// Control signals can be set and settings can be changed regardless of the access mode.
+    pub fn access(&mut self, access: Access) -> &mut Self {
+        self.access = access;
+        self
+    }
+
+    /// Sets whether the port may become the controlling terminal of the calling process.
+    ///
+    /// Ports are normally opened with `O_NOCTTY`, so that a process without a controlling
+    /// terminal, such as a daemon, doesn't acquire the port as one. Setting `true` omits
+    /// `O_NOCTTY`, which is only useful for programs such as `getty` that run a session on the
+    /// port. The default is `false`. This option is only available on POSIX systems.
+    #[cfg(unix)]
+    pub fn controlling_terminal(&mut self, controlling_terminal: bool) -> &mut Self {
+        self.controlling_terminal = controlling_terminal;
+        self
+    }
+
+    /// Sets whether the port is closed in child processes created with `exec()` (`O_CLOEXEC`).
+    ///
+    /// The default is `false`, so child processes inherit the open port. This option is only
+    /// available on POSIX systems.
+    #[cfg(unix)]
+    pub fn close_on_exec(&mut self, close_on_exec: bool) -> &mut Self {
+        self.close_on_exec = close_on_exec;
+        self
+    }
+
+    /// Sets whether other handles may open the port while it's open (`FILE_SHARE_READ` and
+    /// `FILE_SHARE_WRITE`).
+    ///
+    /// Most COM port drivers reject shared access, in which case opening the port fails. The
+    /// default is `false`. This option is only available on Windows.
+    #[cfg(windows)]
+    pub fn shared(&mut self, shared: bool) -> &mut Self {
+        self.shared = shared;
+        self
+    }
+
+    /// Returns the access mode that the port is opened with.
+    pub fn access_mode(&self) -> Access {
+        self.access
+    }
+
+    /// Returns whether the port may become the controlling terminal of the calling process.
+    #[cfg(unix)]
+    pub fn acquires_controlling_terminal(&self) -> bool {
+        self.controlling_terminal
+    }
+
+    /// Returns whether the port is closed in child processes created with `exec()`.
+    #[cfg(unix)]
+    pub fn closes_on_exec(&self) -> bool {
+        self.close_on_exec
+    }
+
+    /// Returns whether other handles may open the port while it's open.
+    #[cfg(windows)]
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Returns the state of the DTR control signal to set when the port is opened, if any.
+    pub fn dtr_level(&self) -> Option<bool> {
+        self.dtr
+    }
+
+    /// Returns the state of the RTS control signal to set when the port is opened, if any.
+    pub fn rts_level(&self) -> Option<bool> {
+        self.rts
+    }
+
+    /// Returns whether opening the port blocks until carrier is detected.
+    pub fn waits_for_carrier(&self) -> bool {
+        self.wait_for_carrier
+    }
+
+    /// Returns whether the port's original state is restored when it's closed.
+    pub fn restores_on_close(&self) -> bool {
+        self.restore_on_close
+    }
+
+    /// Returns whether the port is opened without changing its current settings.
+    pub fn preserves_settings(&self) -> bool {
+        self.preserve_settings
+    }
+
+    /// Returns whether DTR and RTS are lowered when the port is closed, if set.
+    #[cfg(unix)]
+    pub fn hangs_up_on_close(&self) -> Option<bool> {
+        self.hang_up_on_close
+    }
+
+    /// Opens a native serial port with these options.
+    ///
+    /// The argument is interpreted as by [`serial::open()`](fn.open.html).
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as [`serial::open()`](fn.open.html). Failing to set the
+    /// initial state of a control signal is reported as an error of kind `Io`.
+    #[cfg(unix)]
+    pub fn open<T: AsRef<OsStr> + ?Sized>(&self, port: &T) -> ::Result<SystemPort> {
+        use std::path::Path;
+        posix::TTYPort::open_with_options(Path::new(port), self)
+    }
+
+    /// Opens a native serial port with these options.
+    ///
+    /// The argument is interpreted as by [`serial::open()`](fn.open.html).
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as [`serial::open()`](fn.open.html). Failing to set the
+    /// initial state of a control signal is reported as an error of kind `Io`.
+    #[cfg(windows)]
+    pub fn open<T: AsRef<OsStr> + ?Sized>(&self, port: &T) -> ::Result<SystemPort> {
+        windows::COMPort::open_with_options(port, self)
+    }
+}
+
 
 /// Serial port baud rates.
 ///
@@ -200,7 +740,22 @@ pub fn open<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<SystemPort> {
 /// that are widely-supported on many systems. While non-standard baud rates can be set with
 /// `BaudOther`, their behavior is system-dependent. Some systems may not support arbitrary baud
 /// rates. Using the standard baud rates is more likely to result in portable applications.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+///
+/// ## Conversions
+///
+/// A `BaudRate` can be created from an integer with `from_speed()` or parsed from a string, and
+/// baud rates are ordered by speed:
+///
+/// ```
+/// use serial::BaudRate;
+///
+/// let baud_rate: BaudRate = "115200".parse().unwrap();
+///
+/// assert_eq!(baud_rate, BaudRate::Baud115200);
+/// assert!(BaudRate::Baud9600 < BaudRate::BaudOther(14400));
+/// ```
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
 pub enum BaudRate {
     /** 110 baud. */     Baud110,
     /** 300 baud. */     Baud300,
@@ -286,8 +841,55 @@ impl BaudRate {
     }
 }
 
+impl From<usize> for BaudRate {
+    fn from(speed: usize) -> BaudRate {
+        BaudRate::from_speed(speed)
+    }
+}
+
+impl From<BaudRate> for usize {
+    fn from(baud_rate: BaudRate) -> usize {
+        baud_rate.speed()
+    }
+}
+
+impl PartialOrd for BaudRate {
+    fn partial_cmp(&self, other: &BaudRate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BaudRate {
+    /// Orders baud rates by speed.
+    ///
+    /// A standard variant sorts before a `BaudOther` with the same speed, so that the ordering
+    /// remains consistent with equality.
+    fn cmp(&self, other: &BaudRate) -> Ordering {
+        let is_other = |baud_rate: &BaudRate| matches!(*baud_rate, BaudRate::BaudOther(_));
+
+        self.speed().cmp(&other.speed()).then_with(|| is_other(self).cmp(&is_other(other)))
+    }
+}
+
+impl FromStr for BaudRate {
+    type Err = ::Error;
+
+    /// Parses a baud rate from a decimal integer, e.g., `"115200"`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an `InvalidInput` error if the string is not a positive integer.
+    fn from_str(s: &str) -> ::Result<BaudRate> {
+        match s.trim().parse::<usize>() {
+            Ok(speed) if speed > 0 => Ok(BaudRate::from_speed(speed)),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("invalid baud rate: {:?}", s)))
+        }
+    }
+}
+
 /// Number of bits per character.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+#[non_exhaustive]
 pub enum CharSize {
     /** 5 bits per character. */ Bits5,
     /** 6 bits per character. */ Bits6,
@@ -304,7 +906,8 @@ pub enum CharSize {
 ///
 /// Parity checking is disabled by setting `ParityNone`, in which case parity bits are not
 /// transmitted.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
 pub enum Parity {
     /// No parity bit.
     ParityNone,
@@ -319,7 +922,8 @@ pub enum Parity {
 /// Number of stop bits.
 ///
 /// Stop bits are transmitted after every character.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+#[non_exhaustive]
 pub enum StopBits {
     /// One stop bit.
     Stop1,
@@ -329,7 +933,8 @@ pub enum StopBits {
 }
 
 /// Flow control modes.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
 pub enum FlowControl {
     /// No flow control.
     FlowNone,
@@ -341,6 +946,69 @@ pub enum FlowControl {
     FlowHardware
 }
 
+/// Modem status signals that are inputs to a serial port.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum ModemSignal {
+    /// CTS (Clear To Send) signal.
+    Cts,
+
+    /// DSR (Data Set Ready) signal.
+    Dsr,
+
+    /// RI (Ring Indicator) signal.
+    Ri,
+
+    /// CD (Carrier Detect) signal.
+    Cd
+}
+
+/// Whether a port should favor low latency or throughput, set with `set_latency_hint()`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum LatencyHint {
+    /// Deliver received data as soon as possible, at the cost of more interrupts and USB
+    /// transfers.
+    Low,
+
+    /// Deliver received data in larger chunks, which reduces overhead for high-rate streams.
+    Throughput
+}
+
+/// The state of a serial port captured by `SerialDevice::snapshot()`.
+///
+/// A snapshot holds the device's native settings, so restoring it also restores settings that
+/// aren't covered by `PortSettings`, such as line discipline flags or the Windows `DCB`.
+#[derive(Debug,Clone)]
+pub struct Snapshot<S> {
+    settings: S,
+    timeout: Duration,
+    rts: Option<bool>,
+    dtr: Option<bool>
+}
+
+impl<S> Snapshot<S> {
+    /// Returns the captured settings.
+    pub fn settings(&self) -> &S {
+        &self.settings
+    }
+
+    /// Returns the captured timeout.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the captured state of the RTS control signal, if the device can report it.
+    pub fn rts(&self) -> Option<bool> {
+        self.rts
+    }
+
+    /// Returns the captured state of the DTR control signal, if the device can report it.
+    pub fn dtr(&self) -> Option<bool> {
+        self.dtr
+    }
+}
+
 /// A trait for implementing serial devices.
 ///
 /// This trait is meant to be used to implement new serial port devices. To use a serial port
@@ -413,6 +1081,60 @@ pub trait SerialDevice: io::Read+io::Write {
     /// Sets the timeout for future I/O operations.
     fn set_timeout(&mut self, timeout: Duration) -> ::Result<()>;
 
+    /// Reads from the device using a one-off timeout.
+    ///
+    /// This behaves like `std::io::Read::read()`, except that `timeout` is used in place of the
+    /// device's current timeout. The timeout returned by `timeout()` is not affected.
+    ///
+    /// The default implementation temporarily replaces the device's timeout with `set_timeout()`
+    /// and restores it after reading. Implementations should override it if the device can apply
+    /// a timeout to a single read. `COMPort` uses the default implementation, so on Windows each
+    /// call changes the port's `COMMTIMEOUTS` twice.
+    ///
+    /// If data was read but the previous timeout couldn't be restored, the data is returned and
+    /// the device is left with `timeout`, which `timeout()` reports. The error from restoring the
+    /// timeout is returned only when no data was read and the read itself didn't fail.
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let previous = self.timeout();
+
+        self.set_timeout(timeout)?;
+        let result = self.read(buf);
+        let restored = self.set_timeout(previous);
+
+        // data that has been read can't be put back, so it takes precedence over a failure to
+        // restore the timeout
+        match result {
+            Ok(0) | Err(_) => restored.map_err(io::Error::from).and(result),
+            Ok(n) => Ok(n)
+        }
+    }
+
+    /// Discards any data that has been received by the device but not yet read.
+    ///
+    /// The default implementation reads and discards data until a read times out after one
+    /// millisecond. Implementations should override it if the device can discard its input buffer
+    /// directly.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the input buffer could not be discarded:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn discard_input(&mut self) -> ::Result<()> {
+        let mut buf = [0; 256];
+
+        loop {
+            match self.read_with_timeout(&mut buf, Duration::from_millis(1)) {
+                Ok(0) => return Ok(()),
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => return Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(::Error::from(err))
+            }
+        }
+    }
+
     /// Sets the state of the RTS (Request To Send) control signal.
     ///
     /// Setting a value of `true` asserts the RTS control signal. `false` clears the signal.
@@ -490,7 +1212,194 @@ pub trait SerialDevice: io::Read+io::Write {
     /// * `NoDevice` if the device was disconnected.
     /// * `Io` for any other type of I/O error.
     fn read_cd(&mut self) -> ::Result<bool>;
-}
+
+    /// Reads the state of the RTS (Request To Send) control signal that's output by the device.
+    ///
+    /// The default implementation returns an error of kind `Io(Unsupported)`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the state of the RTS control signal could not be read:
+    ///
+    /// * `Io(Unsupported)` if the device can't report the state of its outputs.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn read_rts(&mut self) -> ::Result<bool> {
+        Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), "reading output signals is not supported").with_operation("read control signal"))
+    }
+
+    /// Reads the state of the DTR (Data Terminal Ready) control signal that's output by the device.
+    ///
+    /// The default implementation returns an error of kind `Io(Unsupported)`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `read_rts()`.
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), "reading output signals is not supported").with_operation("read control signal"))
+    }
+
+    /// Holds the device's transmit line in the break condition for the given duration.
+    ///
+    /// The default implementation returns an error of kind `Io(Unsupported)`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the break could not be sent:
+    ///
+    /// * `Io(Unsupported)` if the device can't send breaks.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        let _ = duration;
+        Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), "sending breaks is not supported").with_operation("send break"))
+    }
+
+    /// Tells the device whether to favor low latency or throughput when delivering received
+    /// data.
+    ///
+    /// The default implementation ignores the hint.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the device couldn't be reconfigured:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn set_latency_hint(&mut self, hint: LatencyHint) -> ::Result<()> {
+        let _ = hint;
+        Ok(())
+    }
+
+    /// Captures the device's settings, timeout, and the states of its RTS and DTR signals.
+    ///
+    /// The states of RTS and DTR are left out of the snapshot if the device can't report them.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the device's state could not be read:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn snapshot(&mut self) -> ::Result<Snapshot<Self::Settings>> {
+        let unsupported = |result: ::Result<bool>| match result {
+            Ok(level) => Ok(Some(level)),
+            Err(ref err) if err.kind() == ::ErrorKind::Io(io::ErrorKind::Unsupported) => Ok(None),
+            Err(err) => Err(err)
+        };
+
+        Ok(Snapshot {
+            settings: self.read_settings()?,
+            timeout: self.timeout(),
+            rts: unsupported(self.read_rts())?,
+            dtr: unsupported(self.read_dtr())?
+        })
+    }
+
+    /// Restores the state captured by `snapshot()`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the state could not be restored:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `InvalidInput` if a setting is not compatible with the underlying hardware.
+    /// * `Io` for any other type of I/O error.
+    fn restore(&mut self, snapshot: &Snapshot<Self::Settings>) -> ::Result<()> {
+        self.write_settings(&snapshot.settings)?;
+        self.set_timeout(snapshot.timeout)?;
+
+        if let Some(level) = snapshot.rts {
+            self.set_rts(level)?;
+        }
+
+        if let Some(level) = snapshot.dtr {
+            self.set_dtr(level)?;
+        }
+
+        Ok(())
+    }
+
+    /// Verifies that the device still exists.
+    ///
+    /// The default implementation reads the device's settings, which fails for devices that were
+    /// unplugged on most platforms. Implementations should override it if the device can be
+    /// checked more reliably.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the device couldn't be checked:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn check(&mut self) -> ::Result<()> {
+        match self.read_settings() {
+            Ok(_) => Ok(()),
+            Err(err) => Err(disconnected(err))
+        }
+    }
+
+    /// Waits until one of the given modem status signals changes state.
+    ///
+    /// Returns the signal that changed. This function blocks until a change occurs, regardless of
+    /// the device's timeout.
+    ///
+    /// The default implementation polls the signals every ten milliseconds, so changes that are
+    /// shorter than that may be missed. Implementations should override it if the device can
+    /// notify of signal changes.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if waiting for the signals failed:
+    ///
+    /// * `InvalidInput` if `signals` is empty.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn wait_signal_change(&mut self, signals: &[ModemSignal]) -> ::Result<ModemSignal> {
+        if signals.is_empty() {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "no modem signals to wait for").with_operation("wait for signal change"));
+        }
+
+        let mut initial = Vec::with_capacity(signals.len());
+        for &signal in signals {
+            initial.push(read_signal(self, signal)?);
+        }
+
+        loop {
+            std::thread::sleep(Duration::from_millis(10));
+
+            for (&signal, &level) in signals.iter().zip(&initial) {
+                if read_signal(self, signal)? != level {
+                    return Ok(signal);
+                }
+            }
+        }
+    }
+}
+
+fn read_signal<T: SerialDevice + ?Sized>(device: &mut T, signal: ModemSignal) -> ::Result<bool> {
+    match signal {
+        ModemSignal::Cts => device.read_cts(),
+        ModemSignal::Dsr => device.read_dsr(),
+        ModemSignal::Ri => device.read_ri(),
+        ModemSignal::Cd => device.read_cd()
+    }
+}
+
+/// Reports errors that indicate a disconnection as `NoDevice`.
+///
+/// Other errors are returned unchanged.
+fn disconnected(err: ::Error) -> ::Error {
+    if !err.is_disconnection() || err.kind() == ErrorKind::NoDevice {
+        return err;
+    }
+
+    let mut disconnected = Error::new(ErrorKind::NoDevice, "device was disconnected");
+    disconnected.device = err.device.clone();
+    disconnected.operation = err.operation;
+
+    disconnected.with_source(err)
+}
 
 /// A trait for serial port devices.
 ///
@@ -502,6 +1411,10 @@ pub trait SerialDevice: io::Read+io::Write {
 /// represented as a boolean, where `true` indicates that the signal is asserted.
 ///
 /// The serial port will be closed when the value is dropped.
+///
+/// The trait is object safe. Methods that take generic arguments, such as `reconfigure()`, aren't
+/// available on `dyn SerialPort`, but `Box<dyn SerialPort + Send>` implements `SerialPort` itself
+/// and supports all methods. See [`open_boxed()`](fn.open_boxed.html).
 pub trait SerialPort: io::Read+io::Write {
     /// Returns the current timeout.
     fn timeout(&self) -> Duration;
@@ -509,6 +1422,44 @@ pub trait SerialPort: io::Read+io::Write {
     /// Sets the timeout for future I/O operations.
     fn set_timeout(&mut self, timeout: Duration) -> ::Result<()>;
 
+    /// Reads from the port using a one-off timeout.
+    ///
+    /// This behaves like `std::io::Read::read()`, except that `timeout` is used in place of the
+    /// port's current timeout. The timeout set with `set_timeout()` is left unchanged, which is
+    /// useful for protocols that mix slow and fast phases.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use serial::prelude::*;
+    ///
+    /// let mut port = serial::open("/dev/ttyUSB0").unwrap();
+    /// let mut buf = [0; 64];
+    ///
+    /// // wait up to five seconds for a device to boot before using the normal timeout
+    /// port.read_with_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `std::io::Read::read()`. If no data is received
+    /// before `timeout` elapses, an error of kind `std::io::ErrorKind::TimedOut` is returned.
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+
+    /// Discards any data that has been received by the port but not yet read.
+    ///
+    /// This is useful before sending a request to ensure that stale data isn't mistaken for the
+    /// response.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the input buffer could not be discarded:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn discard_input(&mut self) -> ::Result<()>;
+
     /// Configures a serial port device.
     ///
     /// ## Errors
@@ -528,6 +1479,9 @@ pub trait SerialPort: io::Read+io::Write {
     /// yielded to the provided function. After the function returns, any changes made to the
     /// settings object will be written back to the device.
     ///
+    /// The function is called exactly once, so it may capture and mutate local state or move
+    /// values out of its environment. Trait objects can use `reconfigure_dyn()` instead.
+    ///
     /// ## Errors
     ///
     /// This function returns an error if the `setup` function returns an error or if there was an
@@ -548,10 +1502,10 @@ pub trait SerialPort: io::Read+io::Write {
     /// use serial::prelude::*;
     ///
     /// fn toggle_stop_bits<T: SerialPort>(port: &mut T) -> serial::Result<()> {
-    ///     port.reconfigure(&|settings| {
+    ///     port.reconfigure(|settings| {
     ///         let stop_bits = match settings.stop_bits() {
-    ///             Some(serial::Stop1)        => serial::Stop2,
-    ///             Some(serial::Stop2) | None => serial::Stop1
+    ///             Some(serial::Stop1) => serial::Stop2,
+    ///             _                   => serial::Stop1
     ///         };
     ///
     ///         settings.set_stop_bits(stop_bits);
@@ -559,7 +1513,48 @@ pub trait SerialPort: io::Read+io::Write {
     ///     })
     /// }
     /// ```
-    fn reconfigure(&mut self, setup: &Fn (&mut SerialPortSettings) -> ::Result<()>) -> ::Result<()>;
+    ///
+    /// Values computed by the `setup` function can be passed back to the caller through captured
+    /// variables:
+    ///
+    /// ```no_run
+    /// use serial::prelude::*;
+    ///
+    /// fn read_baud_rate<T: SerialPort>(port: &mut T) -> serial::Result<Option<serial::BaudRate>> {
+    ///     let mut baud_rate = None;
+    ///
+    ///     port.reconfigure(|settings| {
+    ///         baud_rate = settings.baud_rate();
+    ///         Ok(())
+    ///     })?;
+    ///
+    ///     Ok(baud_rate)
+    /// }
+    /// ```
+    fn reconfigure<F>(&mut self, setup: F) -> ::Result<()>
+        where F: FnOnce(&mut dyn SerialPortSettings) -> ::Result<()>,
+              Self: Sized
+    {
+        let mut setup = Some(setup);
+
+        self.reconfigure_dyn(&mut |settings| {
+            match setup.take() {
+                Some(setup) => setup(settings),
+                None => Ok(())
+            }
+        })
+    }
+
+    /// Alter the serial port's configuration through a trait object.
+    ///
+    /// This is the object-safe counterpart to `reconfigure()`, which can't be called on a
+    /// `SerialPort` trait object because it's generic over the `setup` function. The `setup`
+    /// function is called exactly once.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `reconfigure()`.
+    fn reconfigure_dyn(&mut self, setup: &mut dyn FnMut(&mut dyn SerialPortSettings) -> ::Result<()>) -> ::Result<()>;
 
     /// Sets the state of the RTS (Request To Send) control signal.
     ///
@@ -638,6 +1633,154 @@ pub trait SerialPort: io::Read+io::Write {
     /// * `NoDevice` if the device was disconnected.
     /// * `Io` for any other type of I/O error.
     fn read_cd(&mut self) -> ::Result<bool>;
+
+    /// Reads the state of the RTS (Request To Send) control signal that's output by the port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the state of the RTS control signal could not be read:
+    ///
+    /// * `Io(Unsupported)` if the port can't report the state of its outputs, e.g., on Windows.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn read_rts(&mut self) -> ::Result<bool>;
+
+    /// Reads the state of the DTR (Data Terminal Ready) control signal that's output by the port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `read_rts()`.
+    fn read_dtr(&mut self) -> ::Result<bool>;
+
+    /// Holds the port's transmit line in the break condition for the given duration.
+    ///
+    /// A break is a space (logic 0) held for longer than a character frame. Consoles use it as an
+    /// out-of-band signal, e.g., Linux's magic SysRq over serial. This function blocks until the
+    /// break has ended.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the break could not be sent:
+    ///
+    /// * `Io(Unsupported)` if the port can't send breaks.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn send_break(&mut self, duration: Duration) -> ::Result<()>;
+
+    /// Tells the port whether to favor low latency or throughput when delivering received data.
+    ///
+    /// The hint is applied with the best mechanism available on each platform:
+    ///
+    /// * On Linux, it sets or clears the driver's `ASYNC_LOW_LATENCY` flag. For FTDI adapters,
+    ///   this switches the adapter's latency timer between 1 ms and the default 16 ms.
+    /// * On Windows, `LatencyHint::Low` completes reads as soon as any data has been received,
+    ///   instead of waiting for the buffer to fill or the timeout to elapse.
+    ///
+    /// Ports whose driver has no such mechanism ignore the hint.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the port couldn't be reconfigured:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn set_latency_hint(&mut self, hint: LatencyHint) -> ::Result<()>;
+
+    /// Verifies cheaply that the underlying device still exists.
+    ///
+    /// A port whose device was unplugged may keep reporting timeouts until the next write fails.
+    /// Supervisors can call `check()` periodically to detect such ports and reopen them. On POSIX
+    /// systems, this checks that the device file still exists and that the TTY hasn't been hung
+    /// up. On Windows, it queries the COM port's state.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the device couldn't be checked:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn check(&mut self) -> ::Result<()>;
+
+    /// Returns `false` if `check()` reports that the device was disconnected.
+    ///
+    /// Errors that don't indicate a disconnection are ignored.
+    fn is_connected(&mut self) -> bool {
+        match self.check() {
+            Ok(()) => true,
+            Err(err) => err.kind() != ErrorKind::NoDevice
+        }
+    }
+
+    /// Waits until one of the given modem status signals changes state.
+    ///
+    /// Returns the signal that changed. This function blocks until a change occurs, regardless of
+    /// the port's timeout. It can be used to answer incoming calls on a modem by waiting for the RI
+    /// and CD signals:
+    ///
+    /// ```no_run
+    /// use serial::prelude::*;
+    /// use serial::ModemSignal;
+    ///
+    /// let mut port = serial::open("/dev/ttyS0").unwrap();
+    ///
+    /// loop {
+    ///     match port.wait_signal_change(&[ModemSignal::Ri, ModemSignal::Cd]).unwrap() {
+    ///         ModemSignal::Ri => println!("ringing"),
+    ///         _ => println!("carrier {}", if port.read_cd().unwrap() { "detected" } else { "lost" })
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if waiting for the signals failed:
+    ///
+    /// * `InvalidInput` if `signals` is empty.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn wait_signal_change(&mut self, signals: &[ModemSignal]) -> ::Result<ModemSignal>;
+
+    /// Returns an iterator over lines that end with `delimiter`.
+    ///
+    /// Each line is read with the port's current timeout. Timeouts are yielded as errors without
+    /// ending the iteration. See the [`iter`](iter/index.html) module for details.
+    ///
+    /// ```no_run
+    /// use serial::prelude::*;
+    ///
+    /// let mut port = serial::open("/dev/ttyUSB0").unwrap();
+    ///
+    /// for line in port.lines(b'\n').filter_map(Result::ok) {
+    ///     println!("{}", String::from_utf8_lossy(&line));
+    /// }
+    /// ```
+    fn lines(&mut self, delimiter: u8) -> iter::Lines<&mut Self>
+        where Self: Sized
+    {
+        iter::Lines::new(self, delimiter)
+    }
+
+    /// Moves the serial port to a background I/O thread.
+    ///
+    /// Data read from the port is delivered through the returned channel's receiver, and data
+    /// sent to its writer is written to the port. See the [`channel`](channel/index.html) module
+    /// for details.
+    ///
+    /// ```no_run
+    /// use serial::prelude::*;
+    /// use serial::channel::{Backpressure,ChannelOptions};
+    ///
+    /// let port = serial::open("/dev/ttyUSB0").unwrap();
+    ///
+    /// let options = ChannelOptions { backpressure: Backpressure::Drop, ..Default::default() };
+    /// let channel = port.into_channel(options);
+    /// ```
+    #[cfg(feature = "channel")]
+    fn into_channel(self, options: channel::ChannelOptions) -> channel::Channel<Self>
+        where Self: Sized + Send + 'static
+    {
+        channel::spawn(self, options)
+    }
 }
 
 impl<T> SerialPort for T where T: SerialDevice {
@@ -649,10 +1792,18 @@ impl<T> SerialPort for T where T: SerialDevice {
         T::set_timeout(self, timeout)
     }
 
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        T::read_with_timeout(self, buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        T::discard_input(self)
+    }
+
     fn configure(&mut self, settings: &PortSettings) -> ::Result<()> {
-        let mut device_settings = try!(T::read_settings(self));
+        let mut device_settings = T::read_settings(self)?;
 
-        try!(device_settings.set_baud_rate(settings.baud_rate));
+        device_settings.set_baud_rate(settings.baud_rate)?;
         device_settings.set_char_size(settings.char_size);
         device_settings.set_parity(settings.parity);
         device_settings.set_stop_bits(settings.stop_bits);
@@ -661,9 +1812,9 @@ impl<T> SerialPort for T where T: SerialDevice {
         T::write_settings(self, &device_settings)
     }
 
-    fn reconfigure(&mut self, setup: &Fn (&mut SerialPortSettings) -> ::Result<()>) -> ::Result<()> {
-        let mut device_settings = try!(T::read_settings(self));
-        try!(setup(&mut device_settings));
+    fn reconfigure_dyn(&mut self, setup: &mut dyn FnMut(&mut dyn SerialPortSettings) -> ::Result<()>) -> ::Result<()> {
+        let mut device_settings = T::read_settings(self)?;
+        setup(&mut device_settings)?;
         T::write_settings(self, &device_settings)
     }
 
@@ -690,49 +1841,165 @@ impl<T> SerialPort for T where T: SerialDevice {
     fn read_cd(&mut self) -> ::Result<bool> {
         T::read_cd(self)
     }
-}
 
-/// A trait for objects that implement serial port configurations.
-pub trait SerialPortSettings {
-    /// Returns the current baud rate.
-    ///
-    /// This function returns `None` if the baud rate could not be determined. This may occur if
-    /// the hardware is in an uninitialized state. Setting a baud rate with `set_baud_rate()`
-    /// should initialize the baud rate to a supported value.
-    fn baud_rate(&self) -> Option<BaudRate>;
+    fn read_rts(&mut self) -> ::Result<bool> {
+        T::read_rts(self)
+    }
 
-    /// Returns the character size.
-    ///
-    /// This function returns `None` if the character size could not be determined. This may occur
-    /// if the hardware is in an uninitialized state or is using a non-standard character size.
-    /// Setting a baud rate with `set_char_size()` should initialize the character size to a
-    /// supported value.
-    fn char_size(&self) -> Option<CharSize>;
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        T::read_dtr(self)
+    }
 
-    /// Returns the parity-checking mode.
-    ///
-    /// This function returns `None` if the parity mode could not be determined. This may occur if
-    /// the hardware is in an uninitialized state or is using a non-standard parity mode. Setting
-    /// a parity mode with `set_parity()` should initialize the parity mode to a supported value.
-    fn parity(&self) -> Option<Parity>;
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        T::send_break(self, duration)
+    }
 
-    /// Returns the number of stop bits.
-    ///
-    /// This function returns `None` if the number of stop bits could not be determined. This may
-    /// occur if the hardware is in an uninitialized state or is using an unsupported stop bit
-    /// configuration. Setting the number of stop bits with `set_stop-bits()` should initialize the
-    /// stop bits to a supported value.
-    fn stop_bits(&self) -> Option<StopBits>;
+    fn set_latency_hint(&mut self, hint: LatencyHint) -> ::Result<()> {
+        T::set_latency_hint(self, hint)
+    }
 
-    /// Returns the flow control mode.
-    ///
-    /// This function returns `None` if the flow control mode could not be determined. This may
-    /// occur if the hardware is in an uninitialized state or is using an unsupported flow control
-    /// mode. Setting a flow control mode with `set_flow_control()` should initialize the flow
-    /// control mode to a supported value.
-    fn flow_control(&self) -> Option<FlowControl>;
+    fn check(&mut self) -> ::Result<()> {
+        T::check(self)
+    }
 
-    /// Sets the baud rate.
+    fn wait_signal_change(&mut self, signals: &[ModemSignal]) -> ::Result<ModemSignal> {
+        T::wait_signal_change(self, signals)
+    }
+}
+
+/// Boxed ports can be used wherever a `SerialPort` is expected, such as with `reconfigure()` or
+/// `into_channel()`, which aren't available on trait objects.
+impl SerialPort for Box<dyn SerialPort + Send> {
+    fn timeout(&self) -> Duration {
+        (**self).timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        (**self).set_timeout(timeout)
+    }
+
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        (**self).read_with_timeout(buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        (**self).discard_input()
+    }
+
+    fn configure(&mut self, settings: &PortSettings) -> ::Result<()> {
+        (**self).configure(settings)
+    }
+
+    fn reconfigure_dyn(&mut self, setup: &mut dyn FnMut(&mut dyn SerialPortSettings) -> ::Result<()>) -> ::Result<()> {
+        (**self).reconfigure_dyn(setup)
+    }
+
+    fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        (**self).set_rts(level)
+    }
+
+    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        (**self).set_dtr(level)
+    }
+
+    fn read_cts(&mut self) -> ::Result<bool> {
+        (**self).read_cts()
+    }
+
+    fn read_dsr(&mut self) -> ::Result<bool> {
+        (**self).read_dsr()
+    }
+
+    fn read_ri(&mut self) -> ::Result<bool> {
+        (**self).read_ri()
+    }
+
+    fn read_cd(&mut self) -> ::Result<bool> {
+        (**self).read_cd()
+    }
+
+    fn read_rts(&mut self) -> ::Result<bool> {
+        (**self).read_rts()
+    }
+
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        (**self).read_dtr()
+    }
+
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        (**self).send_break(duration)
+    }
+
+    fn set_latency_hint(&mut self, hint: LatencyHint) -> ::Result<()> {
+        (**self).set_latency_hint(hint)
+    }
+
+    fn check(&mut self) -> ::Result<()> {
+        (**self).check()
+    }
+
+    fn wait_signal_change(&mut self, signals: &[ModemSignal]) -> ::Result<ModemSignal> {
+        (**self).wait_signal_change(signals)
+    }
+}
+
+/// A trait for objects that implement serial port configurations.
+pub trait SerialPortSettings {
+    /// Returns the current baud rate.
+    ///
+    /// This function returns `None` if the baud rate could not be determined. This may occur if
+    /// the hardware is in an uninitialized state. Setting a baud rate with `set_baud_rate()`
+    /// should initialize the baud rate to a supported value.
+    fn baud_rate(&self) -> Option<BaudRate>;
+
+    /// Returns the character size.
+    ///
+    /// This function returns `None` if the character size could not be determined. This may occur
+    /// if the hardware is in an uninitialized state or is using a non-standard character size.
+    /// Setting a baud rate with `set_char_size()` should initialize the character size to a
+    /// supported value.
+    fn char_size(&self) -> Option<CharSize>;
+
+    /// Returns the parity-checking mode.
+    ///
+    /// This function returns `None` if the parity mode could not be determined. This may occur if
+    /// the hardware is in an uninitialized state or is using a non-standard parity mode. Setting
+    /// a parity mode with `set_parity()` should initialize the parity mode to a supported value.
+    fn parity(&self) -> Option<Parity>;
+
+    /// Returns the number of stop bits.
+    ///
+    /// This function returns `None` if the number of stop bits could not be determined. This may
+    /// occur if the hardware is in an uninitialized state or is using an unsupported stop bit
+    /// configuration. Setting the number of stop bits with `set_stop-bits()` should initialize the
+    /// stop bits to a supported value.
+    fn stop_bits(&self) -> Option<StopBits>;
+
+    /// Returns the flow control mode.
+    ///
+    /// This function returns `None` if the flow control mode could not be determined. This may
+    /// occur if the hardware is in an uninitialized state or is using an unsupported flow control
+    /// mode. Setting a flow control mode with `set_flow_control()` should initialize the flow
+    /// control mode to a supported value.
+    fn flow_control(&self) -> Option<FlowControl>;
+
+    /// Returns the flow control mode of received data.
+    ///
+    /// Input flow control pauses the remote device, by sending XOFF or deasserting RTS, when the
+    /// port's receive buffer is filling up. The default implementation returns `flow_control()`.
+    fn input_flow_control(&self) -> Option<FlowControl> {
+        self.flow_control()
+    }
+
+    /// Returns the flow control mode of transmitted data.
+    ///
+    /// Output flow control pauses transmission when the remote device sends XOFF or deasserts
+    /// CTS. The default implementation returns `flow_control()`.
+    fn output_flow_control(&self) -> Option<FlowControl> {
+        self.flow_control()
+    }
+
+    /// Sets the baud rate.
     ///
     /// ## Errors
     ///
@@ -752,10 +2019,29 @@ pub trait SerialPortSettings {
 
     /// Sets the flow control mode.
     fn set_flow_control(&mut self, flow_control: FlowControl);
+
+    /// Sets the flow control modes of received and transmitted data independently.
+    ///
+    /// This allows configurations that `set_flow_control()` can't express, such as honoring XOFF
+    /// from the remote device without ever sending XOFF to it. The default implementation only
+    /// accepts equal modes, which it passes to `set_flow_control()`.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if the combination of modes isn't
+    /// supported, e.g., hardware flow control in only one direction on Linux.
+    fn set_flow_control_directions(&mut self, input: FlowControl, output: FlowControl) -> ::Result<()> {
+        if input != output {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "flow control can't be set for each direction"));
+        }
+
+        self.set_flow_control(input);
+        Ok(())
+    }
 }
 
 /// A device-indepenent implementation of serial port settings.
-#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
 pub struct PortSettings {
     /// Baud rate.
     pub baud_rate: BaudRate,
@@ -785,6 +2071,88 @@ impl Default for PortSettings {
     }
 }
 
+impl PortSettings {
+    /// Returns settings for the common "8N1" configuration at the given baud rate.
+    ///
+    /// 8N1 (eight data bits, no parity, one stop bit) without flow control is the most widely
+    /// used serial configuration.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let settings = serial::PortSettings::standard(115200);
+    ///
+    /// assert_eq!(settings.baud_rate, serial::Baud115200);
+    /// assert_eq!(settings.char_size, serial::Bits8);
+    /// assert_eq!(settings.parity, serial::ParityNone);
+    /// assert_eq!(settings.stop_bits, serial::Stop1);
+    /// ```
+    pub fn standard(speed: usize) -> Self {
+        PortSettings {
+            baud_rate: BaudRate::from_speed(speed),
+            ..Default::default()
+        }
+    }
+
+    /// Returns settings for Modbus RTU at the given baud rate.
+    ///
+    /// The Modbus serial line specification requires even parity by default, giving an "8E1"
+    /// configuration.
+    pub fn modbus_rtu(speed: usize) -> Self {
+        PortSettings {
+            baud_rate: BaudRate::from_speed(speed),
+            parity: Parity::ParityEven,
+            ..Default::default()
+        }
+    }
+
+    /// Returns settings for NMEA 0183 devices, such as GPS receivers.
+    ///
+    /// NMEA 0183 specifies 4800 baud with 8N1 framing. Many modern receivers default to higher
+    /// speeds, in which case `standard()` should be used instead.
+    pub fn nmea() -> Self {
+        PortSettings::standard(4800)
+    }
+
+    /// Returns settings for DMX512 lighting control.
+    ///
+    /// DMX512 uses 250,000 baud with eight data bits, no parity, and two stop bits.
+    pub fn dmx() -> Self {
+        PortSettings {
+            baud_rate: BaudRate::BaudOther(250_000),
+            stop_bits: StopBits::Stop2,
+            ..Default::default()
+        }
+    }
+
+    /// Compares these settings with `other`.
+    ///
+    /// The returned diff describes the changes from `self` to `other` and can be formatted for
+    /// logging.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// let old = serial::PortSettings::standard(9600);
+    /// let new = serial::PortSettings::modbus_rtu(115200);
+    ///
+    /// assert_eq!(old.diff(&new).to_string(), "baud: 9600 → 115200, parity: N → E");
+    /// ```
+    pub fn diff(&self, other: &PortSettings) -> SettingsDiff {
+        fn change<T: PartialEq + Copy>(old: T, new: T) -> Option<(T, T)> {
+            if old != new { Some((old, new)) } else { None }
+        }
+
+        SettingsDiff {
+            baud_rate: change(self.baud_rate, other.baud_rate),
+            char_size: change(self.char_size, other.char_size),
+            parity: change(self.parity, other.parity),
+            stop_bits: change(self.stop_bits, other.stop_bits),
+            flow_control: change(self.flow_control, other.flow_control)
+        }
+    }
+}
+
 impl SerialPortSettings for PortSettings {
     fn baud_rate(&self) -> Option<BaudRate> {
         Some(self.baud_rate)
@@ -828,11 +2196,203 @@ impl SerialPortSettings for PortSettings {
     }
 }
 
+/// The differences between two sets of port settings, returned by `PortSettings::diff()`.
+///
+/// Each field holds the old and new values of a setting that changed. The `Display`
+/// implementation lists the changes as `baud: 9600 → 115200, parity: N → E`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash,Default)]
+pub struct SettingsDiff {
+    /// Change of baud rate.
+    pub baud_rate: Option<(BaudRate, BaudRate)>,
+
+    /// Change of character size.
+    pub char_size: Option<(CharSize, CharSize)>,
+
+    /// Change of parity checking mode.
+    pub parity: Option<(Parity, Parity)>,
+
+    /// Change of number of stop bits.
+    pub stop_bits: Option<(StopBits, StopBits)>,
+
+    /// Change of flow control mode.
+    pub flow_control: Option<(FlowControl, FlowControl)>
+}
+
+impl SettingsDiff {
+    /// Returns `true` if no setting changed.
+    pub fn is_empty(&self) -> bool {
+        *self == SettingsDiff::default()
+    }
+}
+
+impl fmt::Display for SettingsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn bits(char_size: CharSize) -> u8 {
+            match char_size {
+                CharSize::Bits5 => 5,
+                CharSize::Bits6 => 6,
+                CharSize::Bits7 => 7,
+                CharSize::Bits8 => 8
+            }
+        }
+
+        fn parity(parity: Parity) -> &'static str {
+            match parity {
+                Parity::ParityNone => "N",
+                Parity::ParityOdd => "O",
+                Parity::ParityEven => "E"
+            }
+        }
+
+        fn stop_bits(stop_bits: StopBits) -> u8 {
+            match stop_bits {
+                StopBits::Stop1 => 1,
+                StopBits::Stop2 => 2
+            }
+        }
+
+        fn flow_control(flow_control: FlowControl) -> &'static str {
+            match flow_control {
+                FlowControl::FlowNone => "none",
+                FlowControl::FlowSoftware => "software",
+                FlowControl::FlowHardware => "hardware"
+            }
+        }
+
+        let mut changes = Vec::new();
+
+        if let Some((old, new)) = self.baud_rate {
+            changes.push(format!("baud: {} → {}", old.speed(), new.speed()));
+        }
+
+        if let Some((old, new)) = self.char_size {
+            changes.push(format!("data bits: {} → {}", bits(old), bits(new)));
+        }
+
+        if let Some((old, new)) = self.parity {
+            changes.push(format!("parity: {} → {}", parity(old), parity(new)));
+        }
+
+        if let Some((old, new)) = self.stop_bits {
+            changes.push(format!("stop bits: {} → {}", stop_bits(old), stop_bits(new)));
+        }
+
+        if let Some((old, new)) = self.flow_control {
+            changes.push(format!("flow control: {} → {}", flow_control(old), flow_control(new)));
+        }
+
+        if changes.is_empty() {
+            f.write_str("no changes")
+        }
+        else {
+            f.write_str(&changes.join(", "))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::default::Default;
+    use std::io;
+    use std::time::Duration;
     use super::*;
 
+    struct TestDevice {
+        settings: PortSettings,
+        timeout: Duration,
+        last_read_timeout: Option<Duration>,
+        input: Vec<u8>,
+        fail_timeout_after_read: bool
+    }
+
+    impl TestDevice {
+        fn new() -> Self {
+            TestDevice {
+                settings: Default::default(),
+                timeout: Duration::from_millis(100),
+                last_read_timeout: None,
+                input: Vec::new(),
+                fail_timeout_after_read: false
+            }
+        }
+    }
+
+    impl io::Read for TestDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.last_read_timeout = Some(self.timeout);
+
+            if self.input.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
+            }
+
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input.drain(..len);
+
+            Ok(len)
+        }
+    }
+
+    impl io::Write for TestDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SerialDevice for TestDevice {
+        type Settings = PortSettings;
+
+        fn read_settings(&self) -> ::Result<PortSettings> {
+            Ok(self.settings)
+        }
+
+        fn write_settings(&mut self, settings: &PortSettings) -> ::Result<()> {
+            self.settings = *settings;
+            Ok(())
+        }
+
+        fn timeout(&self) -> Duration {
+            self.timeout
+        }
+
+        fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+            if self.fail_timeout_after_read && self.last_read_timeout.is_some() {
+                return Err(::Error::new(::ErrorKind::NoDevice, "device unplugged"));
+            }
+
+            self.timeout = timeout;
+            Ok(())
+        }
+
+        fn set_rts(&mut self, _: bool) -> ::Result<()> {
+            Ok(())
+        }
+
+        fn set_dtr(&mut self, _: bool) -> ::Result<()> {
+            Ok(())
+        }
+
+        fn read_cts(&mut self) -> ::Result<bool> {
+            Ok(false)
+        }
+
+        fn read_dsr(&mut self) -> ::Result<bool> {
+            Ok(false)
+        }
+
+        fn read_ri(&mut self) -> ::Result<bool> {
+            Ok(false)
+        }
+
+        fn read_cd(&mut self) -> ::Result<bool> {
+            Ok(false)
+        }
+    }
+
     #[test]
     fn port_settings_manipulates_baud_rate() {
         let mut settings: PortSettings = Default::default();
@@ -867,4 +2427,302 @@ mod tests {
         settings.set_flow_control(FlowSoftware);
         assert_eq!(settings.flow_control(), Some(FlowSoftware));
     }
+
+    #[test]
+    fn port_settings_only_accepts_equal_flow_control_directions() {
+        let mut settings: PortSettings = Default::default();
+        settings.set_flow_control_directions(FlowHardware, FlowHardware).unwrap();
+        assert_eq!(settings.input_flow_control(), Some(FlowHardware));
+        assert_eq!(settings.output_flow_control(), Some(FlowHardware));
+
+        let err = settings.set_flow_control_directions(FlowNone, FlowSoftware).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(settings.flow_control(), Some(FlowHardware));
+    }
+
+    #[test]
+    fn port_settings_standard_is_8n1() {
+        let settings = PortSettings::standard(19200);
+        assert_eq!(settings.baud_rate, Baud19200);
+        assert_eq!(settings.char_size, Bits8);
+        assert_eq!(settings.parity, ParityNone);
+        assert_eq!(settings.stop_bits, Stop1);
+        assert_eq!(settings.flow_control, FlowNone);
+    }
+
+    #[test]
+    fn port_settings_modbus_rtu_uses_even_parity() {
+        let settings = PortSettings::modbus_rtu(19200);
+        assert_eq!(settings.baud_rate, Baud19200);
+        assert_eq!(settings.char_size, Bits8);
+        assert_eq!(settings.parity, ParityEven);
+        assert_eq!(settings.stop_bits, Stop1);
+    }
+
+    #[test]
+    fn port_settings_nmea_uses_4800_baud() {
+        let settings = PortSettings::nmea();
+        assert_eq!(settings.baud_rate, Baud4800);
+        assert_eq!(settings.parity, ParityNone);
+    }
+
+    #[test]
+    fn port_settings_dmx_uses_two_stop_bits() {
+        let settings = PortSettings::dmx();
+        assert_eq!(settings.baud_rate, BaudOther(250_000));
+        assert_eq!(settings.stop_bits, Stop2);
+    }
+
+    #[test]
+    fn port_settings_diff_lists_changes() {
+        let old = PortSettings::standard(9600);
+        let new = PortSettings { char_size: Bits7, stop_bits: Stop2, flow_control: FlowHardware, ..old };
+
+        let diff = old.diff(&new);
+
+        assert_eq!(diff.char_size, Some((Bits8, Bits7)));
+        assert_eq!(diff.baud_rate, None);
+        assert_eq!(diff.to_string(), "data bits: 8 → 7, stop bits: 1 → 2, flow control: none → hardware");
+    }
+
+    #[test]
+    fn port_settings_diff_is_empty_for_equal_settings() {
+        let diff = PortSettings::nmea().diff(&PortSettings::nmea());
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no changes");
+    }
+
+    #[test]
+    fn open_options_records_initial_signal_levels() {
+        let mut options = ::OpenOptions::new();
+        assert_eq!(options.dtr_level(), None);
+        assert_eq!(options.rts_level(), None);
+
+        options.dtr(false).rts(true);
+        assert_eq!(options.dtr_level(), Some(false));
+        assert_eq!(options.rts_level(), Some(true));
+        assert!(!options.waits_for_carrier());
+
+        options.wait_for_carrier(true);
+        assert!(options.waits_for_carrier());
+    }
+
+    #[test]
+    fn reconfigure_accepts_capturing_closure() {
+        let mut device = TestDevice::new();
+        let mut previous = None;
+
+        device.reconfigure(|settings| {
+            previous = settings.baud_rate();
+            settings.set_baud_rate(Baud115200)
+        }).unwrap();
+
+        assert_eq!(previous, Some(Baud9600));
+        assert_eq!(device.settings.baud_rate, Baud115200);
+    }
+
+    #[test]
+    fn reconfigure_dyn_is_callable_on_trait_objects() {
+        let mut device = TestDevice::new();
+
+        {
+            let port: &mut dyn SerialPort = &mut device;
+            port.reconfigure_dyn(&mut |settings| {
+                settings.set_parity(ParityOdd);
+                Ok(())
+            }).unwrap();
+        }
+
+        assert_eq!(device.settings.parity, ParityOdd);
+    }
+
+    #[test]
+    fn boxed_port_implements_serial_port() {
+        fn set_parity<P: SerialPort>(port: &mut P) -> ::Result<()> {
+            port.reconfigure(|settings| {
+                settings.set_parity(ParityEven);
+                Ok(())
+            })
+        }
+
+        let mut port: Box<dyn SerialPort + Send> = Box::new(::testing::ScriptedDevice::new());
+
+        set_parity(&mut port).unwrap();
+        port.set_timeout(Duration::from_millis(5)).unwrap();
+
+        let mut parity = None;
+        port.reconfigure(|settings| {
+            parity = settings.parity();
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(parity, Some(ParityEven));
+        assert_eq!(port.timeout(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn reconfigure_does_not_write_settings_on_error() {
+        let mut device = TestDevice::new();
+
+        let result = device.reconfigure(|settings| {
+            settings.set_stop_bits(Stop2);
+            Err(Error::new(ErrorKind::InvalidInput, "rejected"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(device.settings.stop_bits, Stop1);
+    }
+
+    #[test]
+    fn error_classifies_transient_errors() {
+        assert!(::Error::new(::ErrorKind::Io(io::ErrorKind::Interrupted), "").is_transient());
+        assert!(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "").is_transient());
+        assert!(::Error::new(::ErrorKind::Io(io::ErrorKind::WouldBlock), "").is_transient());
+        assert!(!::Error::new(::ErrorKind::NoDevice, "").is_transient());
+        assert!(!::Error::new(::ErrorKind::Busy, "").is_transient());
+        assert!(!::Error::new(::ErrorKind::InvalidInput, "").is_transient());
+        assert!(!::Error::new(::ErrorKind::Io(io::ErrorKind::BrokenPipe), "").is_transient());
+        assert!(!::Error::new(::ErrorKind::Io(io::ErrorKind::Other), "").is_transient());
+    }
+
+    #[test]
+    fn error_classifies_disconnections() {
+        assert!(::Error::new(::ErrorKind::NoDevice, "").is_disconnection());
+        assert!(::Error::new(::ErrorKind::Io(io::ErrorKind::BrokenPipe), "").is_disconnection());
+        assert!(!::Error::new(::ErrorKind::InvalidInput, "").is_disconnection());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn error_classifies_eio_as_disconnection() {
+        let error = ::Error::from(io::Error::from_raw_os_error(5));
+
+        assert_eq!(error.raw_os_error(), Some(5));
+        assert!(error.is_disconnection());
+    }
+
+    #[test]
+    fn error_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn system_port_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SystemPort>();
+        assert_send_sync::<&SystemPort>();
+    }
+
+    #[test]
+    fn error_displays_device_and_operation() {
+        let error = Error::new(ErrorKind::Io(io::ErrorKind::TimedOut), "Operation timed out")
+            .with_device("/dev/ttyUSB3")
+            .with_operation("read");
+
+        assert_eq!(error.device(), Some("/dev/ttyUSB3"));
+        assert_eq!(error.operation(), Some("read"));
+        assert_eq!(error.to_string(), "/dev/ttyUSB3: read: Operation timed out");
+    }
+
+    #[test]
+    fn error_from_io_error_chains_source() {
+        use std::error::Error as StdError;
+
+        let error = Error::from(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"));
+        let source = error.source().unwrap().downcast_ref::<io::Error>().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Io(io::ErrorKind::BrokenPipe));
+        assert_eq!(source.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn error_survives_round_trip_through_io_error() {
+        let error = Error::new(ErrorKind::NoDevice, "device unplugged").with_device("COM7");
+
+        let io_error = io::Error::from(error);
+        assert_eq!(io_error.kind(), io::ErrorKind::NotFound);
+
+        let error = Error::from(io_error);
+        assert_eq!(error.kind(), ErrorKind::NoDevice);
+        assert_eq!(error.device(), Some("COM7"));
+    }
+
+    #[test]
+    fn baud_rate_parses_from_string() {
+        assert_eq!("9600".parse::<BaudRate>().unwrap(), Baud9600);
+        assert_eq!(" 250000 ".parse::<BaudRate>().unwrap(), BaudOther(250000));
+    }
+
+    #[test]
+    fn baud_rate_rejects_invalid_strings() {
+        for s in &["", "fast", "-9600", "0", "9600.0"] {
+            let error = s.parse::<BaudRate>().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn baud_rate_orders_by_speed() {
+        let mut rates = vec![Baud115200, BaudOther(50), Baud9600, BaudOther(14400), Baud110];
+        rates.sort();
+
+        assert_eq!(rates, vec![BaudOther(50), Baud110, Baud9600, BaudOther(14400), Baud115200]);
+    }
+
+    #[test]
+    fn baud_rate_orders_standard_before_other_with_same_speed() {
+        assert!(Baud9600 < BaudOther(9600));
+        assert_eq!(Baud9600.cmp(&Baud9600), ::std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn baud_rate_converts_to_and_from_usize() {
+        assert_eq!(BaudRate::from(19200), Baud19200);
+        assert_eq!(usize::from(Baud57600), 57600);
+    }
+
+    #[test]
+    fn port_settings_can_key_hash_maps() {
+        use std::collections::HashMap;
+
+        let mut ports = HashMap::new();
+        ports.insert(PortSettings::standard(115200), "console");
+        ports.insert(PortSettings::modbus_rtu(19200), "plc");
+
+        assert_eq!(ports.get(&PortSettings::modbus_rtu(19200)), Some(&"plc"));
+    }
+
+    #[test]
+    fn char_size_and_stop_bits_are_ordered() {
+        assert!(Bits5 < Bits6 && Bits6 < Bits7 && Bits7 < Bits8);
+        assert!(Stop1 < Stop2);
+    }
+
+    #[test]
+    fn read_with_timeout_restores_previous_timeout() {
+        let mut device = TestDevice::new();
+        let mut buf = [0; 8];
+
+        let result = SerialPort::read_with_timeout(&mut device, &mut buf, Duration::from_secs(5));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(device.last_read_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(SerialPort::timeout(&device), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn read_with_timeout_keeps_data_when_timeout_is_not_restored() {
+        let mut device = TestDevice::new();
+        device.input = b"data".to_vec();
+        device.fail_timeout_after_read = true;
+
+        let mut buf = [0; 8];
+        assert_eq!(SerialPort::read_with_timeout(&mut device, &mut buf, Duration::from_secs(5)).unwrap(), 4);
+        assert_eq!(&buf[..4], b"data");
+
+        let result = SerialPort::read_with_timeout(&mut device, &mut buf, Duration::from_secs(5));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
 }
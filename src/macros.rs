@@ -0,0 +1,103 @@
+//! Macros shared by the port wrappers.
+
+/// Implements the methods of `SerialDevice` that a wrapper passes through to the port in its
+/// `$port` field.
+///
+/// Settings reads, timeouts, control signals, breaks, and checks are always forwarded. The methods
+/// that wrappers commonly change, `write_settings`, `read_with_timeout`, and `discard_input`, are
+/// only forwarded if they're listed, so that the wrapper can implement them itself:
+///
+/// ```text
+/// impl<P: SerialDevice> SerialDevice for Wrapper<P> {
+///     type Settings = P::Settings;
+///
+///     forward_serial_device!(port, write_settings, discard_input);
+///
+///     fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+///         // ...
+///     }
+/// }
+/// ```
+macro_rules! forward_serial_device {
+    (@write_settings $port:ident) => {
+        fn write_settings(&mut self, settings: &Self::Settings) -> ::Result<()> {
+            ::SerialDevice::write_settings(&mut self.$port, settings)
+        }
+    };
+
+    (@read_with_timeout $port:ident) => {
+        fn read_with_timeout(&mut self, buf: &mut [u8], timeout: ::std::time::Duration) -> ::std::io::Result<usize> {
+            ::SerialDevice::read_with_timeout(&mut self.$port, buf, timeout)
+        }
+    };
+
+    (@discard_input $port:ident) => {
+        fn discard_input(&mut self) -> ::Result<()> {
+            ::SerialDevice::discard_input(&mut self.$port)
+        }
+    };
+
+    ($port:ident $(, $method:ident)*) => {
+        fn read_settings(&self) -> ::Result<Self::Settings> {
+            ::SerialDevice::read_settings(&self.$port)
+        }
+
+        fn timeout(&self) -> ::std::time::Duration {
+            ::SerialDevice::timeout(&self.$port)
+        }
+
+        fn set_timeout(&mut self, timeout: ::std::time::Duration) -> ::Result<()> {
+            ::SerialDevice::set_timeout(&mut self.$port, timeout)
+        }
+
+        fn set_rts(&mut self, level: bool) -> ::Result<()> {
+            ::SerialDevice::set_rts(&mut self.$port, level)
+        }
+
+        fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+            ::SerialDevice::set_dtr(&mut self.$port, level)
+        }
+
+        fn read_cts(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_cts(&mut self.$port)
+        }
+
+        fn read_dsr(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_dsr(&mut self.$port)
+        }
+
+        fn read_ri(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_ri(&mut self.$port)
+        }
+
+        fn read_cd(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_cd(&mut self.$port)
+        }
+
+        fn read_rts(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_rts(&mut self.$port)
+        }
+
+        fn read_dtr(&mut self) -> ::Result<bool> {
+            ::SerialDevice::read_dtr(&mut self.$port)
+        }
+
+        fn send_break(&mut self, duration: ::std::time::Duration) -> ::Result<()> {
+            ::SerialDevice::send_break(&mut self.$port, duration)
+        }
+
+        fn set_latency_hint(&mut self, hint: ::LatencyHint) -> ::Result<()> {
+            ::SerialDevice::set_latency_hint(&mut self.$port, hint)
+        }
+
+        fn check(&mut self) -> ::Result<()> {
+            ::SerialDevice::check(&mut self.$port)
+        }
+
+        fn wait_signal_change(&mut self, signals: &[::ModemSignal]) -> ::Result<::ModemSignal> {
+            ::SerialDevice::wait_signal_change(&mut self.$port, signals)
+        }
+
+        $(forward_serial_device!(@$method $port);)*
+    };
+}
@@ -0,0 +1,526 @@
+//! Link metrics over a moving window.
+//!
+//! A `MeteredPort` records the traffic and errors of a port into `Metrics`, which can be queried
+//! from another thread, e.g., to feed a dashboard:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use serial::metrics::{MeteredPort,Metrics};
+//!
+//! let metrics = Metrics::new(Duration::from_secs(60));
+//! let port = MeteredPort::new(serial::open("/dev/ttyUSB0").unwrap(), metrics.clone());
+//!
+//! // use the port, e.g., with the transaction engine
+//!
+//! let stats = metrics.stats();
+//! println!("{:.0} B/s in, {:.1}% timeouts", stats.read_throughput, stats.timeout_rate * 100.0);
+//!
+//! if let Some(latency) = stats.latency {
+//!     println!("round trip: {:?} average, {:?} worst", latency.mean, latency.max);
+//! }
+//! ```
+//!
+//! The round-trip latency is measured from a write to the first data received after it. It's
+//! meaningful for request/response protocols, such as those used with the transaction engine. A
+//! read that times out ends the measurement without recording a latency.
+//!
+//! With the `prometheus` feature, the statistics of many ports can be exported in the Prometheus
+//! text format with the [`prometheus`](prometheus/index.html) module.
+
+use std::io;
+use std::collections::VecDeque;
+use std::sync::{Arc,Mutex};
+use std::time::{Duration,Instant};
+
+use ::SerialDevice;
+
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+
+/// Number of buckets that a window is divided into.
+const BUCKETS: u32 = 20;
+
+/// Statistics of round-trip latency.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Latency {
+    /// Number of round trips measured.
+    pub count: u64,
+
+    /// Shortest round trip.
+    pub min: Duration,
+
+    /// Average round trip.
+    pub mean: Duration,
+
+    /// Longest round trip.
+    pub max: Duration
+}
+
+/// Statistics of a link over the metrics window.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LinkStats {
+    /// The time span covered by the statistics, which is shorter than the window until the
+    /// metrics have been recorded for a full window.
+    pub span: Duration,
+
+    /// Number of bytes read.
+    pub bytes_read: u64,
+
+    /// Number of bytes written.
+    pub bytes_written: u64,
+
+    /// Bytes read per second.
+    pub read_throughput: f64,
+
+    /// Bytes written per second.
+    pub write_throughput: f64,
+
+    /// Number of read operations, including those that timed out or failed.
+    pub reads: u64,
+
+    /// Number of write operations, including those that timed out or failed.
+    pub writes: u64,
+
+    /// Number of operations that timed out.
+    pub timeouts: u64,
+
+    /// Number of operations that failed with an error other than a timeout.
+    pub errors: u64,
+
+    /// Fraction of reads that timed out, from 0 to 1.
+    pub timeout_rate: f64,
+
+    /// Fraction of all operations that failed with an error other than a timeout, from 0 to 1.
+    pub error_rate: f64,
+
+    /// Round-trip latency, if any round trips were measured.
+    pub latency: Option<Latency>
+}
+
+#[derive(Debug,Clone)]
+struct Bucket {
+    start: Instant,
+    bytes_read: u64,
+    bytes_written: u64,
+    reads: u64,
+    writes: u64,
+    timeouts: u64,
+    read_timeouts: u64,
+    errors: u64,
+    latency_count: u64,
+    latency_sum: Duration,
+    latency_min: Duration,
+    latency_max: Duration
+}
+
+impl Bucket {
+    fn new(start: Instant) -> Self {
+        Bucket {
+            start,
+            bytes_read: 0,
+            bytes_written: 0,
+            reads: 0,
+            writes: 0,
+            timeouts: 0,
+            read_timeouts: 0,
+            errors: 0,
+            latency_count: 0,
+            latency_sum: Duration::ZERO,
+            latency_min: Duration::MAX,
+            latency_max: Duration::ZERO
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    length: Duration,
+    started: Instant,
+    buckets: VecDeque<Bucket>
+}
+
+impl Window {
+    fn bucket_length(&self) -> Duration {
+        (self.length / BUCKETS).max(Duration::from_millis(1))
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let length = self.length;
+
+        while self.buckets.front().is_some_and(|bucket| now.saturating_duration_since(bucket.start) >= length) {
+            self.buckets.pop_front();
+        }
+    }
+
+    fn bucket(&mut self, now: Instant) -> &mut Bucket {
+        self.prune(now);
+
+        let bucket_length = self.bucket_length();
+        let current = match self.buckets.back() {
+            Some(bucket) => now.saturating_duration_since(bucket.start) < bucket_length,
+            None => false
+        };
+
+        if !current {
+            self.buckets.push_back(Bucket::new(now));
+        }
+
+        self.buckets.back_mut().unwrap()
+    }
+}
+
+/// A recorder of link metrics over a moving window.
+///
+/// `Metrics` is cheap to clone. Clones share the same recorded data, so that statistics can be
+/// queried from a different thread than the one that uses the port.
+#[derive(Debug,Clone)]
+pub struct Metrics {
+    window: Arc<Mutex<Window>>
+}
+
+impl Metrics {
+    /// Creates metrics that are computed over the given window.
+    pub fn new(window: Duration) -> Self {
+        Metrics {
+            window: Arc::new(Mutex::new(Window {
+                length: window,
+                started: Instant::now(),
+                buckets: VecDeque::new()
+            }))
+        }
+    }
+
+    /// Returns the length of the window.
+    pub fn window(&self) -> Duration {
+        self.lock().length
+    }
+
+    /// Records a read of `len` bytes.
+    pub fn record_read(&self, len: usize) {
+        self.record(Instant::now(), |bucket| {
+            bucket.reads += 1;
+            bucket.bytes_read += len as u64;
+        });
+    }
+
+    /// Records a write of `len` bytes.
+    pub fn record_write(&self, len: usize) {
+        self.record(Instant::now(), |bucket| {
+            bucket.writes += 1;
+            bucket.bytes_written += len as u64;
+        });
+    }
+
+    /// Records a read that timed out.
+    pub fn record_read_timeout(&self) {
+        self.record(Instant::now(), |bucket| {
+            bucket.reads += 1;
+            bucket.timeouts += 1;
+            bucket.read_timeouts += 1;
+        });
+    }
+
+    /// Records a read that failed with an error other than a timeout.
+    pub fn record_read_error(&self) {
+        self.record(Instant::now(), |bucket| {
+            bucket.reads += 1;
+            bucket.errors += 1;
+        });
+    }
+
+    /// Records a write that failed. Timeouts are counted separately from other errors.
+    pub fn record_write_error(&self, kind: io::ErrorKind) {
+        self.record(Instant::now(), |bucket| {
+            bucket.writes += 1;
+
+            if kind == io::ErrorKind::TimedOut {
+                bucket.timeouts += 1;
+            }
+            else {
+                bucket.errors += 1;
+            }
+        });
+    }
+
+    /// Records the latency of a round trip.
+    pub fn record_round_trip(&self, latency: Duration) {
+        self.record(Instant::now(), |bucket| {
+            bucket.latency_count += 1;
+            bucket.latency_sum += latency;
+            bucket.latency_min = bucket.latency_min.min(latency);
+            bucket.latency_max = bucket.latency_max.max(latency);
+        });
+    }
+
+    /// Returns the statistics over the window that ends now.
+    pub fn stats(&self) -> LinkStats {
+        self.stats_at(Instant::now())
+    }
+
+    /// Discards all recorded data.
+    pub fn reset(&self) {
+        let mut window = self.lock();
+        window.buckets.clear();
+        window.started = Instant::now();
+    }
+
+    fn record<F: FnOnce(&mut Bucket)>(&self, now: Instant, update: F) {
+        update(self.lock().bucket(now));
+    }
+
+    fn stats_at(&self, now: Instant) -> LinkStats {
+        let mut window = self.lock();
+        window.prune(now);
+
+        let span = now.saturating_duration_since(window.started).min(window.length);
+        let seconds = span.as_secs_f64();
+
+        let mut stats = LinkStats {
+            span,
+            bytes_read: 0,
+            bytes_written: 0,
+            read_throughput: 0.0,
+            write_throughput: 0.0,
+            reads: 0,
+            writes: 0,
+            timeouts: 0,
+            errors: 0,
+            timeout_rate: 0.0,
+            error_rate: 0.0,
+            latency: None
+        };
+
+        let mut read_timeouts = 0;
+        let mut latency = Bucket::new(now);
+
+        for bucket in &window.buckets {
+            stats.bytes_read += bucket.bytes_read;
+            stats.bytes_written += bucket.bytes_written;
+            stats.reads += bucket.reads;
+            stats.writes += bucket.writes;
+            stats.timeouts += bucket.timeouts;
+            stats.errors += bucket.errors;
+            read_timeouts += bucket.read_timeouts;
+
+            latency.latency_count += bucket.latency_count;
+            latency.latency_sum += bucket.latency_sum;
+            latency.latency_min = latency.latency_min.min(bucket.latency_min);
+            latency.latency_max = latency.latency_max.max(bucket.latency_max);
+        }
+
+        if seconds > 0.0 {
+            stats.read_throughput = stats.bytes_read as f64 / seconds;
+            stats.write_throughput = stats.bytes_written as f64 / seconds;
+        }
+
+        if stats.reads > 0 {
+            stats.timeout_rate = read_timeouts as f64 / stats.reads as f64;
+        }
+
+        if stats.reads + stats.writes > 0 {
+            stats.error_rate = stats.errors as f64 / (stats.reads + stats.writes) as f64;
+        }
+
+        if latency.latency_count > 0 {
+            stats.latency = Some(Latency {
+                count: latency.latency_count,
+                min: latency.latency_min,
+                mean: latency.latency_sum / latency.latency_count as u32,
+                max: latency.latency_max
+            });
+        }
+
+        stats
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Window> {
+        self.window.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// A port wrapper that records its traffic into `Metrics`.
+///
+/// `MeteredPort` implements `SerialDevice` if the wrapped port does, so it can be used wherever a
+/// `SerialPort` is expected.
+pub struct MeteredPort<P> {
+    port: P,
+    metrics: Metrics,
+    request: Option<Instant>
+}
+
+impl<P> MeteredPort<P> {
+    /// Wraps a port to record its metrics.
+    pub fn new(port: P, metrics: Metrics) -> Self {
+        MeteredPort {
+            port,
+            metrics,
+            request: None
+        }
+    }
+
+    /// Returns the metrics that the port records into.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    ///
+    /// I/O on the underlying port isn't recorded.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn record_read(&mut self, result: &io::Result<usize>) {
+        match *result {
+            Ok(len) => {
+                self.metrics.record_read(len);
+
+                if len > 0 {
+                    if let Some(request) = self.request.take() {
+                        self.metrics.record_round_trip(request.elapsed());
+                    }
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => {
+                self.metrics.record_read_timeout();
+                self.request = None;
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => self.metrics.record_read_error()
+        }
+    }
+}
+
+impl<P: io::Read> io::Read for MeteredPort<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.port.read(buf);
+        self.record_read(&result);
+        result
+    }
+}
+
+impl<P: io::Write> io::Write for MeteredPort<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.port.write(buf);
+
+        match result {
+            Ok(len) => {
+                self.metrics.record_write(len);
+
+                if len > 0 && self.request.is_none() {
+                    self.request = Some(Instant::now());
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(ref err) => self.metrics.record_write_error(err.kind())
+        }
+
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: SerialDevice> SerialDevice for MeteredPort<P> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, write_settings, discard_input);
+
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let result = SerialDevice::read_with_timeout(&mut self.port, buf, timeout);
+        self.record_read(&result);
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::{Duration,Instant};
+
+    use super::{MeteredPort,Metrics};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn metered_port_counts_traffic_and_timeouts() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"pong");
+
+        let metrics = Metrics::new(Duration::from_secs(60));
+        let mut port = MeteredPort::new(device, metrics.clone());
+        let mut buf = [0; 8];
+
+        port.write_all(b"ping").unwrap();
+        assert_eq!(port.read(&mut buf).unwrap(), 4);
+        assert!(port.read(&mut buf).is_err());
+
+        let stats = metrics.stats();
+
+        assert_eq!(stats.bytes_written, 4);
+        assert_eq!(stats.bytes_read, 4);
+        assert_eq!(stats.reads, 2);
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.timeout_rate, 0.5);
+        assert_eq!(stats.errors, 0);
+        assert_eq!(stats.latency.unwrap().count, 1);
+    }
+
+    #[test]
+    fn metered_port_counts_errors() {
+        let mut device = ScriptedDevice::new();
+        device.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let metrics = Metrics::new(Duration::from_secs(60));
+        let mut port = MeteredPort::new(device, metrics.clone());
+
+        port.write_all(b"x").unwrap();
+        assert!(port.read(&mut [0; 1]).is_err());
+
+        let stats = metrics.stats();
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.error_rate, 0.5);
+        assert_eq!(stats.latency, None);
+    }
+
+    #[test]
+    fn metrics_expire_after_window() {
+        let metrics = Metrics::new(Duration::from_millis(100));
+        let start = Instant::now();
+
+        metrics.record(start, |bucket| bucket.bytes_read += 10);
+        metrics.record(start + Duration::from_millis(60), |bucket| bucket.bytes_read += 5);
+
+        assert_eq!(metrics.stats_at(start + Duration::from_millis(90)).bytes_read, 15);
+        assert_eq!(metrics.stats_at(start + Duration::from_millis(120)).bytes_read, 5);
+        assert_eq!(metrics.stats_at(start + Duration::from_millis(200)).bytes_read, 0);
+    }
+
+    #[test]
+    fn metrics_aggregate_latency() {
+        let metrics = Metrics::new(Duration::from_secs(1));
+
+        metrics.record_round_trip(Duration::from_millis(10));
+        metrics.record_round_trip(Duration::from_millis(30));
+
+        let latency = metrics.stats().latency.unwrap();
+
+        assert_eq!(latency.min, Duration::from_millis(10));
+        assert_eq!(latency.mean, Duration::from_millis(20));
+        assert_eq!(latency.max, Duration::from_millis(30));
+    }
+}
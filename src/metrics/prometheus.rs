@@ -0,0 +1,139 @@
+//! Export of link metrics in the Prometheus text format.
+//!
+//! An `Exporter` renders the statistics of many ports, each identified by its labels, as gauges
+//! that a Prometheus server can scrape from an HTTP endpoint served by the application:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use serial::metrics::{MeteredPort,Metrics};
+//! use serial::metrics::prometheus::Exporter;
+//!
+//! let mut exporter = Exporter::new();
+//!
+//! for path in &["/dev/ttyUSB0", "/dev/ttyUSB1"] {
+//!     let metrics = Metrics::new(Duration::from_secs(60));
+//!     exporter.add(&[("port", path)], metrics.clone());
+//!
+//!     let port = MeteredPort::new(serial::open(path).unwrap(), metrics);
+//!     // move the port to its worker thread
+//! }
+//!
+//! // respond to a scrape
+//! let body = exporter.render();
+//! ```
+//!
+//! The gauges describe the metrics window. Their names start with `serial_`, e.g.,
+//! `serial_read_bytes_per_second{port="/dev/ttyUSB0"}`.
+
+use std::fmt::Write;
+
+use super::{LinkStats,Metrics};
+
+type Gauge = (&'static str, &'static str, fn(&LinkStats) -> Option<f64>);
+
+const GAUGES: &[Gauge] = &[
+    ("serial_read_bytes_per_second", "Bytes read per second over the metrics window.", |stats| Some(stats.read_throughput)),
+    ("serial_write_bytes_per_second", "Bytes written per second over the metrics window.", |stats| Some(stats.write_throughput)),
+    ("serial_window_reads", "Read operations in the metrics window.", |stats| Some(stats.reads as f64)),
+    ("serial_window_writes", "Write operations in the metrics window.", |stats| Some(stats.writes as f64)),
+    ("serial_timeout_ratio", "Fraction of reads that timed out in the metrics window.", |stats| Some(stats.timeout_rate)),
+    ("serial_error_ratio", "Fraction of operations that failed in the metrics window.", |stats| Some(stats.error_rate)),
+    ("serial_round_trip_mean_seconds", "Average round-trip latency in the metrics window.", |stats| stats.latency.map(|latency| latency.mean.as_secs_f64())),
+    ("serial_round_trip_max_seconds", "Longest round-trip latency in the metrics window.", |stats| stats.latency.map(|latency| latency.max.as_secs_f64()))
+];
+
+/// A collection of port metrics that are exported together.
+#[derive(Debug,Clone,Default)]
+pub struct Exporter {
+    ports: Vec<(Vec<(String, String)>, Metrics)>
+}
+
+impl Exporter {
+    /// Creates an empty exporter.
+    pub fn new() -> Self {
+        Exporter::default()
+    }
+
+    /// Adds a port's metrics with the labels that identify it, e.g., `[("port", "/dev/ttyUSB0")]`.
+    pub fn add(&mut self, labels: &[(&str, &str)], metrics: Metrics) {
+        let labels = labels.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+        self.ports.push((labels, metrics));
+    }
+
+    /// Removes the metrics of all ports whose labels include `name="value"`.
+    pub fn remove(&mut self, name: &str, value: &str) {
+        self.ports.retain(|(labels, _)| !labels.iter().any(|(n, v)| n == name && v == value));
+    }
+
+    /// Renders the current statistics of all ports in the Prometheus text format.
+    ///
+    /// Latency gauges are left out for ports without measured round trips.
+    pub fn render(&self) -> String {
+        let stats: Vec<LinkStats> = self.ports.iter().map(|(_, metrics)| metrics.stats()).collect();
+        let mut out = String::new();
+
+        for &(name, help, value) in GAUGES {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+
+            for ((labels, _), stats) in self.ports.iter().zip(&stats) {
+                if let Some(value) = value(stats) {
+                    let _ = writeln!(out, "{}{} {}", name, format_labels(labels), value);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn format_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels.iter().map(|(name, value)| {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("{}=\"{}\"", name, value)
+    }).collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Exporter;
+    use super::super::Metrics;
+
+    #[test]
+    fn render_labels_each_port() {
+        let a = Metrics::new(Duration::from_secs(10));
+        let b = Metrics::new(Duration::from_secs(10));
+        a.record_read_timeout();
+        b.record_round_trip(Duration::from_millis(250));
+
+        let mut exporter = Exporter::new();
+        exporter.add(&[("port", "/dev/ttyUSB0")], a);
+        exporter.add(&[("port", "/dev/ttyUSB1"), ("site", "a\"b")], b);
+
+        let text = exporter.render();
+
+        assert!(text.contains("# TYPE serial_timeout_ratio gauge\n"));
+        assert!(text.contains("serial_timeout_ratio{port=\"/dev/ttyUSB0\"} 1\n"));
+        assert!(text.contains("serial_round_trip_mean_seconds{port=\"/dev/ttyUSB1\",site=\"a\\\"b\"} 0.25\n"));
+        assert!(!text.contains("serial_round_trip_mean_seconds{port=\"/dev/ttyUSB0\"}"));
+    }
+
+    #[test]
+    fn remove_drops_port() {
+        let mut exporter = Exporter::new();
+        exporter.add(&[("port", "/dev/ttyS0")], Metrics::new(Duration::from_secs(1)));
+        exporter.remove("port", "/dev/ttyS0");
+
+        assert!(!exporter.render().contains("ttyS0"));
+    }
+}
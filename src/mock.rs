@@ -0,0 +1,635 @@
+//! Virtual serial ports for testing without hardware.
+//!
+//! `pair()` creates two `MockPort`s connected by a virtual null-modem cable: data written to one
+//! port is received by the other, RTS is wired to the other port's CTS, and DTR is wired to the
+//! other port's DSR and CD.
+//!
+//! By default, written data is available to the other port immediately. With `set_timing()`, a
+//! port instead simulates the time it takes to transmit each character at its configured baud
+//! rate, character size, parity, and stop bits, optionally with random jitter. This allows
+//! timing-sensitive protocol logic, such as inter-frame gaps and timeouts, to be tested.
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::time::Duration;
+//! use serial::prelude::*;
+//!
+//! let (mut a, mut b) = serial::mock::pair();
+//!
+//! a.set_timing(Some(Default::default()));
+//! a.write_all(b"hello").unwrap();
+//!
+//! let mut buf = [0; 5];
+//! b.set_timeout(Duration::from_secs(1)).unwrap();
+//! b.read_exact(&mut buf).unwrap();
+//! assert_eq!(&buf, b"hello");
+//! ```
+//!
+//! Ports created by `pair_with_clock()` measure time with a `Clock`. A simulated clock only moves
+//! when it's advanced, and a port that would wait for data, a timeout, or transmission to finish
+//! advances it to the end of the wait instead of sleeping. Tests of timeouts and of simulated
+//! transmission time then run instantly and deterministically:
+//!
+//! ```
+//! use std::io::prelude::*;
+//! use std::time::Duration;
+//! use serial::prelude::*;
+//! use serial::mock::Clock;
+//!
+//! let clock = Clock::simulated();
+//! let (_a, mut b) = serial::mock::pair_with_clock(&clock);
+//!
+//! let start = clock.now();
+//! b.set_timeout(Duration::from_secs(30)).unwrap();
+//!
+//! assert!(b.read(&mut [0; 8]).is_err());
+//! assert_eq!(clock.now() - start, Duration::from_secs(30));
+//! ```
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc,Condvar,Mutex,MutexGuard};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::{PortSettings,SerialDevice};
+
+/// Parameters for simulating transmission time.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Timing {
+    /// Maximum random delay added to the arrival of each character.
+    ///
+    /// Characters still arrive in the order they were sent.
+    pub jitter: Duration,
+
+    /// Seed for the jitter's pseudo-random generator, which makes the jitter reproducible.
+    pub seed: u32
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            jitter: Duration::from_secs(0),
+            seed: 1
+        }
+    }
+}
+
+/// The source of time for mock ports.
+///
+/// Clones of a clock share the same time.
+#[derive(Debug,Clone)]
+pub struct Clock {
+    base: Instant,
+    simulated: Option<Arc<Mutex<Duration>>>
+}
+
+impl Clock {
+    /// Returns the system clock, which is used by `pair()`.
+    pub fn system() -> Self {
+        Clock {
+            base: Instant::now(),
+            simulated: None
+        }
+    }
+
+    /// Creates a simulated clock, which starts at the current time and only moves when it's
+    /// advanced.
+    ///
+    /// Waits are completed instantly by advancing the clock. A port that waits for data therefore
+    /// doesn't see data that another thread writes later, which makes a simulated clock suitable
+    /// for tests that drive both ends of a pair from one thread.
+    pub fn simulated() -> Self {
+        Clock {
+            base: Instant::now(),
+            simulated: Some(Arc::new(Mutex::new(Duration::from_secs(0))))
+        }
+    }
+
+    /// Returns `true` if this is a simulated clock.
+    pub fn is_simulated(&self) -> bool {
+        self.simulated.is_some()
+    }
+
+    /// Returns the current time.
+    pub fn now(&self) -> Instant {
+        match self.simulated {
+            Some(ref elapsed) => self.base + *elapsed.lock().unwrap_or_else(|p| p.into_inner()),
+            None => Instant::now()
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    ///
+    /// The system clock can't be moved, so this sleeps instead.
+    pub fn advance(&self, duration: Duration) {
+        match self.simulated {
+            Some(ref elapsed) => *elapsed.lock().unwrap_or_else(|p| p.into_inner()) += duration,
+            None => thread::sleep(duration)
+        }
+    }
+
+    /// Waits until the clock reaches `time`.
+    fn sleep_until(&self, time: Instant) {
+        let now = self.now();
+
+        if time > now {
+            self.advance(time - now);
+        }
+    }
+
+    /// Waits on `condvar` until it's notified or the clock reaches `time`.
+    fn wait_until<'a, T>(&self, condvar: &Condvar, guard: MutexGuard<'a, T>, time: Instant) -> MutexGuard<'a, T> {
+        if self.is_simulated() {
+            self.sleep_until(time);
+            return guard;
+        }
+
+        match condvar.wait_timeout(guard, time.saturating_duration_since(Instant::now())) {
+            Ok((guard, _)) => guard,
+            Err(poisoned) => poisoned.into_inner().0
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::system()
+    }
+}
+
+/// One direction of a virtual cable.
+struct Line {
+    state: Mutex<LineState>,
+    arrived: Condvar
+}
+
+struct LineState {
+    /// Bytes in transit or waiting to be read, with the time at which they arrive.
+    data: VecDeque<(Instant, u8)>,
+
+    /// Time at which the transmitter finishes sending the queued data.
+    busy_until: Option<Instant>,
+
+    /// Level of the RTS signal driven by the transmitter.
+    rts: bool,
+
+    /// Level of the DTR signal driven by the transmitter.
+    dtr: bool
+}
+
+impl Line {
+    fn new() -> Self {
+        Line {
+            state: Mutex::new(LineState {
+                data: VecDeque::new(),
+                busy_until: None,
+                rts: false,
+                dtr: false
+            }),
+            arrived: Condvar::new()
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LineState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A virtual serial port created by `pair()`.
+pub struct MockPort {
+    settings: PortSettings,
+    timeout: Duration,
+    timing: Option<Timing>,
+    jitter_state: u32,
+    clock: Clock,
+    tx: Arc<Line>,
+    rx: Arc<Line>
+}
+
+/// Creates two virtual serial ports connected to each other.
+pub fn pair() -> (MockPort, MockPort) {
+    pair_with_clock(&Clock::system())
+}
+
+/// Creates two virtual serial ports connected to each other that measure time with `clock`.
+pub fn pair_with_clock(clock: &Clock) -> (MockPort, MockPort) {
+    let a_to_b = Arc::new(Line::new());
+    let b_to_a = Arc::new(Line::new());
+
+    let a = MockPort::new(a_to_b.clone(), b_to_a.clone(), clock.clone());
+    let b = MockPort::new(b_to_a, a_to_b, clock.clone());
+
+    (a, b)
+}
+
+impl MockPort {
+    fn new(tx: Arc<Line>, rx: Arc<Line>, clock: Clock) -> Self {
+        MockPort {
+            settings: PortSettings::default(),
+            timeout: Duration::from_millis(100),
+            timing: None,
+            jitter_state: 1,
+            clock,
+            tx,
+            rx
+        }
+    }
+
+    /// Returns the clock that the port measures time with.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Enables or disables simulation of transmission time for data written to this port.
+    ///
+    /// With timing enabled, each character written to the port arrives at the other port after
+    /// the time it takes to transmit at this port's settings, and `flush()` blocks until all
+    /// written data has been transmitted.
+    pub fn set_timing(&mut self, timing: Option<Timing>) {
+        if let Some(ref timing) = timing {
+            self.jitter_state = if timing.seed == 0 { 1 } else { timing.seed };
+        }
+
+        self.timing = timing;
+    }
+
+    /// Returns the time it takes to transmit one character at the port's settings.
+    ///
+    /// This includes the start bit, data bits, parity bit, and stop bits.
+    pub fn char_time(&self) -> Duration {
+        let data_bits = match self.settings.char_size {
+            ::Bits5 => 5,
+            ::Bits6 => 6,
+            ::Bits7 => 7,
+            ::Bits8 => 8
+        };
+
+        let parity_bits = match self.settings.parity {
+            ::ParityNone => 0,
+            ::ParityOdd | ::ParityEven => 1
+        };
+
+        let stop_bits = match self.settings.stop_bits {
+            ::Stop1 => 1,
+            ::Stop2 => 2
+        };
+
+        let bits = (1 + data_bits + parity_bits + stop_bits) as u64;
+        let speed = cmp::max(self.settings.baud_rate.speed(), 1) as u64;
+
+        Duration::from_nanos(bits * 1_000_000_000 / speed)
+    }
+
+    fn next_jitter(&mut self, max: Duration) -> Duration {
+        if max == Duration::from_secs(0) {
+            return max;
+        }
+
+        self.jitter_state ^= self.jitter_state << 13;
+        self.jitter_state ^= self.jitter_state >> 17;
+        self.jitter_state ^= self.jitter_state << 5;
+
+        let nanos = max.as_nanos() as u64;
+        Duration::from_nanos(u64::from(self.jitter_state) % nanos)
+    }
+}
+
+impl io::Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let deadline = self.clock.now() + self.timeout;
+        let mut state = self.rx.lock();
+
+        loop {
+            let now = self.clock.now();
+
+            let wait = match state.data.front() {
+                Some(&(arrival, _)) if arrival <= now => break,
+                Some(&(arrival, _)) => cmp::min(arrival, deadline),
+                None => deadline
+            };
+
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
+            }
+
+            state = self.clock.wait_until(&self.rx.arrived, state, wait);
+        }
+
+        let now = self.clock.now();
+        let mut len = 0;
+
+        while len < buf.len() {
+            match state.data.front() {
+                Some(&(arrival, byte)) if arrival <= now => {
+                    buf[len] = byte;
+                    len += 1;
+                    state.data.pop_front();
+                },
+                _ => break
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl io::Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = self.clock.now();
+        let char_time = self.char_time();
+        let timing = self.timing;
+
+        let jitter: Vec<Duration> = match timing {
+            Some(timing) => buf.iter().map(|_| self.next_jitter(timing.jitter)).collect(),
+            None => Vec::new()
+        };
+
+        let mut state = self.tx.lock();
+
+        match timing {
+            Some(_) => {
+                let mut sent = match state.busy_until {
+                    Some(busy_until) if busy_until > now => busy_until,
+                    _ => now
+                };
+
+                let mut last_arrival = state.data.back().map_or(now, |&(arrival, _)| arrival);
+
+                for (&byte, &jitter) in buf.iter().zip(&jitter) {
+                    sent += char_time;
+                    last_arrival = cmp::max(last_arrival, sent + jitter);
+                    state.data.push_back((last_arrival, byte));
+                }
+
+                state.busy_until = Some(sent);
+            },
+            None => {
+                state.data.extend(buf.iter().map(|&byte| (now, byte)));
+            }
+        }
+
+        self.tx.arrived.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let busy_until = self.tx.lock().busy_until;
+
+        if let Some(busy_until) = busy_until {
+            self.clock.sleep_until(busy_until);
+        }
+
+        Ok(())
+    }
+}
+
+impl SerialDevice for MockPort {
+    type Settings = PortSettings;
+
+    fn read_settings(&self) -> ::Result<PortSettings> {
+        Ok(self.settings)
+    }
+
+    fn write_settings(&mut self, settings: &PortSettings) -> ::Result<()> {
+        self.settings = *settings;
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        let now = self.clock.now();
+        let mut state = self.rx.lock();
+
+        while state.data.front().is_some_and(|&(arrival, _)| arrival <= now) {
+            state.data.pop_front();
+        }
+
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        self.tx.lock().rts = level;
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        self.tx.lock().dtr = level;
+        Ok(())
+    }
+
+    fn read_cts(&mut self) -> ::Result<bool> {
+        Ok(self.rx.lock().rts)
+    }
+
+    fn read_dsr(&mut self) -> ::Result<bool> {
+        Ok(self.rx.lock().dtr)
+    }
+
+    fn read_ri(&mut self) -> ::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_cd(&mut self) -> ::Result<bool> {
+        Ok(self.rx.lock().dtr)
+    }
+
+    fn read_rts(&mut self) -> ::Result<bool> {
+        Ok(self.tx.lock().rts)
+    }
+
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        Ok(self.tx.lock().dtr)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::time::{Duration,Instant};
+
+    use super::{pair,pair_with_clock,Clock,MockPort,Timing};
+    use ::prelude::*;
+
+    #[test]
+    fn mock_pair_transfers_data_both_ways() {
+        let (mut a, mut b) = pair();
+        let mut buf = [0; 8];
+
+        a.write_all(b"ping").unwrap();
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+
+        b.write_all(b"pong").unwrap();
+        assert_eq!(a.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"pong");
+    }
+
+    #[test]
+    fn mock_port_times_out_without_data() {
+        let (_a, mut b) = pair();
+        let mut buf = [0; 8];
+
+        b.set_timeout(Duration::from_millis(1)).unwrap();
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), ::std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn mock_port_computes_char_time() {
+        let (mut a, _b) = pair();
+
+        a.configure(&::PortSettings::standard(9600)).unwrap();
+        assert_eq!(a.char_time(), Duration::from_nanos(10 * 1_000_000_000 / 9600));
+
+        a.configure(&::PortSettings::modbus_rtu(9600)).unwrap();
+        assert_eq!(a.char_time(), Duration::from_nanos(11 * 1_000_000_000 / 9600));
+    }
+
+    #[test]
+    fn mock_port_simulates_transmission_time() {
+        let (mut a, mut b) = pair();
+        let mut buf = [0; 96];
+
+        a.configure(&::PortSettings::standard(9600)).unwrap();
+        a.set_timing(Some(Default::default()));
+        b.set_timeout(Duration::from_secs(1)).unwrap();
+
+        let start = Instant::now();
+        a.write_all(&buf).unwrap();
+
+        // nothing arrives before the first character has been transmitted
+        b.set_timeout(Duration::from_millis(0)).unwrap();
+        assert!(b.read(&mut buf).is_err());
+
+        a.flush().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        b.set_timeout(Duration::from_secs(1)).unwrap();
+        b.read_exact(&mut buf).unwrap();
+    }
+
+    #[test]
+    fn simulated_clock_fast_forwards_transmission_time() {
+        let clock = Clock::simulated();
+        let (mut a, mut b) = pair_with_clock(&clock);
+        let mut buf = [0; 960];
+
+        a.configure(&::PortSettings::standard(9600)).unwrap();
+        a.set_timing(Some(Default::default()));
+        b.set_timeout(Duration::from_secs(5)).unwrap();
+
+        let start = Instant::now();
+        let virtual_start = clock.now();
+
+        a.write_all(&buf).unwrap();
+        b.read_exact(&mut buf).unwrap();
+
+        assert_eq!(clock.now() - virtual_start, a.char_time() * 960);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn simulated_clock_fast_forwards_timeouts() {
+        let clock = Clock::simulated();
+        let (mut a, mut b) = pair_with_clock(&clock);
+
+        let start = clock.now();
+        b.set_timeout(Duration::from_secs(60)).unwrap();
+
+        assert!(b.read(&mut [0; 8]).is_err());
+        assert_eq!(clock.now() - start, Duration::from_secs(60));
+
+        a.configure(&::PortSettings::standard(9600)).unwrap();
+        a.set_timing(Some(Default::default()));
+        a.write_all(b"x").unwrap();
+
+        b.set_timeout(Duration::from_millis(0)).unwrap();
+        assert!(b.read(&mut [0; 8]).is_err());
+
+        clock.advance(a.char_time());
+        assert_eq!(b.read(&mut [0; 8]).unwrap(), 1);
+    }
+
+    #[test]
+    fn mock_port_jitter_preserves_order() {
+        let (mut a, mut b) = pair();
+        let data: Vec<u8> = (0..64).collect();
+        let mut buf = [0; 64];
+
+        a.configure(&::PortSettings::standard(115200)).unwrap();
+        a.set_timing(Some(Timing { jitter: Duration::from_millis(2), seed: 42 }));
+        b.set_timeout(Duration::from_secs(1)).unwrap();
+
+        a.write_all(&data).unwrap();
+        b.read_exact(&mut buf).unwrap();
+
+        assert_eq!(&buf[..], &data[..]);
+    }
+
+    #[test]
+    fn mock_port_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MockPort>();
+    }
+
+    #[test]
+    fn mock_pair_crosses_control_signals() {
+        let (mut a, mut b) = pair();
+
+        a.set_rts(true).unwrap();
+        a.set_dtr(true).unwrap();
+
+        assert!(b.read_cts().unwrap());
+        assert!(b.read_dsr().unwrap());
+        assert!(b.read_cd().unwrap());
+        assert!(!a.read_cts().unwrap());
+    }
+
+    #[test]
+    fn mock_port_restores_snapshot() {
+        let (mut a, mut b) = pair();
+
+        a.set_rts(true).unwrap();
+        let snapshot = ::SerialDevice::snapshot(&mut a).unwrap();
+
+        a.set_rts(false).unwrap();
+        a.set_dtr(true).unwrap();
+        a.set_timeout(Duration::from_secs(5)).unwrap();
+
+        ::SerialDevice::restore(&mut a, &snapshot).unwrap();
+
+        assert_eq!(snapshot.rts(), Some(true));
+        assert!(a.read_rts().unwrap());
+        assert!(!a.read_dtr().unwrap());
+        assert!(b.read_cts().unwrap());
+        assert_eq!(a.timeout(), snapshot.timeout());
+    }
+
+    #[test]
+    fn mock_port_waits_for_signal_change() {
+        let (mut a, mut b) = pair();
+
+        let thread = ::std::thread::spawn(move || {
+            ::std::thread::sleep(Duration::from_millis(20));
+            a.set_dtr(true).unwrap();
+            a
+        });
+
+        assert_eq!(b.wait_signal_change(&[::ModemSignal::Ri, ::ModemSignal::Cd]).unwrap(), ::ModemSignal::Cd);
+        thread.join().unwrap();
+    }
+}
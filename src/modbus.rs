@@ -0,0 +1,577 @@
+//! Modbus serial line framing, with RTU and ASCII modes.
+//!
+//! A Modbus message consists of a unit address and a protocol data unit (PDU), which starts with a
+//! function code. On a serial line, messages are framed in one of two modes:
+//!
+//! * RTU: binary frames delimited by silence on the line and protected by a CRC-16.
+//! * ASCII: frames that start with `:` and end with CR LF, with each byte encoded as two
+//!   hexadecimal digits and protected by an LRC checksum. Many legacy PLCs only support this mode.
+//!
+//! The framing mode is selected per `Client` or `Server`:
+//!
+//! ```no_run
+//! use serial::modbus::{Client,Framing};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let mut client = Client::new(port, Framing::Ascii);
+//!
+//! let registers = client.read_holding_registers(1, 0, 10).unwrap();
+//! ```
+
+use std::io;
+use std::mem;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+
+/// Maximum length of an RTU frame.
+const MAX_RTU_FRAME: usize = 256;
+
+/// Maximum length of an ASCII frame.
+const MAX_ASCII_FRAME: usize = 513;
+
+/// Framing mode of a Modbus serial line.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Framing {
+    /// Binary frames delimited by silence and protected by a CRC-16.
+    Rtu,
+
+    /// Hexadecimal frames delimited by `:` and CR LF and protected by an LRC checksum.
+    Ascii
+}
+
+/// Computes the Modbus CRC-16 of `data`.
+///
+/// The CRC is transmitted with its low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff;
+
+    for &byte in data {
+        crc ^= byte as u16;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xa001 } else { crc >> 1 };
+        }
+    }
+
+    crc
+}
+
+/// Computes the Modbus longitudinal redundancy check of `data`.
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)).wrapping_neg()
+}
+
+/// Encodes a message for unit `unit` into a frame.
+pub fn encode(framing: Framing, unit: u8, pdu: &[u8]) -> Vec<u8> {
+    match framing {
+        Framing::Rtu => {
+            let mut frame = Vec::with_capacity(pdu.len() + 3);
+            frame.push(unit);
+            frame.extend_from_slice(pdu);
+
+            let crc = crc16(&frame);
+            frame.push(crc as u8);
+            frame.push((crc >> 8) as u8);
+            frame
+        },
+        Framing::Ascii => {
+            let mut message = Vec::with_capacity(pdu.len() + 2);
+            message.push(unit);
+            message.extend_from_slice(pdu);
+            message.push(lrc(&message));
+
+            let mut frame = Vec::with_capacity(message.len() * 2 + 3);
+            frame.push(b':');
+
+            for byte in message {
+                frame.push(hex_digit(byte >> 4));
+                frame.push(hex_digit(byte & 0x0f));
+            }
+
+            frame.extend_from_slice(b"\r\n");
+            frame
+        }
+    }
+}
+
+/// Decodes a frame into its unit address and PDU.
+///
+/// ## Errors
+///
+/// This function returns an error of kind `Io(InvalidData)` if the frame is malformed or its
+/// checksum doesn't match.
+pub fn decode(framing: Framing, frame: &[u8]) -> ::Result<(u8, Vec<u8>)> {
+    let message = match framing {
+        Framing::Rtu => {
+            if frame.len() < 4 {
+                return Err(invalid_frame("frame is too short"));
+            }
+
+            let (message, crc) = frame.split_at(frame.len() - 2);
+
+            if crc16(message) != (crc[0] as u16 | (crc[1] as u16) << 8) {
+                return Err(invalid_frame("CRC mismatch"));
+            }
+
+            message.to_vec()
+        },
+        Framing::Ascii => {
+            if frame.first() != Some(&b':') || !frame.ends_with(b"\r\n") {
+                return Err(invalid_frame("missing frame delimiters"));
+            }
+
+            let digits = &frame[1..frame.len() - 2];
+
+            if digits.len() < 6 || digits.len() % 2 != 0 {
+                return Err(invalid_frame("invalid frame length"));
+            }
+
+            let mut message = Vec::with_capacity(digits.len() / 2);
+
+            for pair in digits.chunks(2) {
+                match (hex_value(pair[0]), hex_value(pair[1])) {
+                    (Some(high), Some(low)) => message.push(high << 4 | low),
+                    _ => return Err(invalid_frame("invalid hexadecimal digit"))
+                }
+            }
+
+            let checksum = message.pop().unwrap();
+
+            if lrc(&message) != checksum {
+                return Err(invalid_frame("LRC mismatch"));
+            }
+
+            message
+        }
+    };
+
+    Ok((message[0], message[1..].to_vec()))
+}
+
+/// A Modbus client (master) on a serial line.
+pub struct Client<P> {
+    port: P,
+    framing: Framing,
+    timeout: Duration,
+    inter_frame_timeout: Duration,
+    pending: Vec<u8>
+}
+
+impl<P: SerialPort> Client<P> {
+    /// Creates a client that communicates through `port` with the given framing mode.
+    ///
+    /// The response timeout defaults to one second. The inter-frame timeout defaults to 20
+    /// milliseconds in RTU mode and one second in ASCII mode.
+    pub fn new(port: P, framing: Framing) -> Self {
+        Client {
+            port,
+            framing,
+            timeout: Duration::from_secs(1),
+            inter_frame_timeout: default_inter_frame_timeout(framing),
+            pending: Vec::new()
+        }
+    }
+
+    /// Returns the framing mode.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Returns the maximum time to wait for the start of a response.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the maximum time to wait for the start of a response.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the maximum gap between bytes of a frame.
+    pub fn inter_frame_timeout(&self) -> Duration {
+        self.inter_frame_timeout
+    }
+
+    /// Sets the maximum gap between bytes of a frame.
+    ///
+    /// In RTU mode, a frame ends when the line has been idle for this long. The Modbus
+    /// specification requires at least 3.5 character times. In ASCII mode, a frame is abandoned
+    /// if the line is idle for this long before the end of the frame.
+    pub fn set_inter_frame_timeout(&mut self, timeout: Duration) {
+        self.inter_frame_timeout = timeout;
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Sends a request PDU to unit `unit` and returns the response PDU.
+    ///
+    /// Requests to unit 0 are broadcast to all units, which don't respond, so an empty PDU is
+    /// returned without waiting.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no response was received.
+    /// * `Io(InvalidData)` if the response was malformed or came from another unit.
+    /// * `Io(Other)` if the unit responded with an exception.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn request(&mut self, unit: u8, pdu: &[u8]) -> ::Result<Vec<u8>> {
+        let response = self.exchange(unit, pdu)?;
+
+        if unit == 0 {
+            return Ok(response);
+        }
+
+        match (pdu.first(), response.first()) {
+            (Some(&function), Some(&code)) if code == function | 0x80 => {
+                let exception = response.get(1).cloned().unwrap_or(0);
+                Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), format!("Modbus exception code {}", exception)).with_operation("modbus"))
+            },
+            (Some(&function), Some(&code)) if code == function => Ok(response),
+            _ => Err(invalid_frame("response to unexpected function"))
+        }
+    }
+
+    /// Sends a request PDU to unit `unit` and returns the response PDU as received, including
+    /// exception responses.
+    pub(crate) fn exchange(&mut self, unit: u8, pdu: &[u8]) -> ::Result<Vec<u8>> {
+        self.port.discard_input()?;
+        self.pending.clear();
+        self.port.write_all(&encode(self.framing, unit, pdu))?;
+        self.port.flush()?;
+
+        if unit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let frame = read_frame(&mut self.port, self.framing, &mut self.pending, self.timeout, self.inter_frame_timeout)?;
+        let (address, response) = decode(self.framing, &frame)?;
+
+        if address != unit {
+            return Err(invalid_frame("response from unexpected unit"));
+        }
+
+        Ok(response)
+    }
+
+    /// Reads `count` holding registers starting at `address` (function code 3).
+    pub fn read_holding_registers(&mut self, unit: u8, address: u16, count: u16) -> ::Result<Vec<u16>> {
+        let request = [0x03, (address >> 8) as u8, address as u8, (count >> 8) as u8, count as u8];
+        let response = self.request(unit, &request)?;
+
+        if response.len() < 2 || response[1] as usize != count as usize * 2 || response.len() != 2 + count as usize * 2 {
+            return Err(invalid_frame("unexpected register count"));
+        }
+
+        Ok(response[2..].chunks(2).map(|pair| (pair[0] as u16) << 8 | pair[1] as u16).collect())
+    }
+
+    /// Writes `value` to the holding register at `address` (function code 6).
+    pub fn write_single_register(&mut self, unit: u8, address: u16, value: u16) -> ::Result<()> {
+        let request = [0x06, (address >> 8) as u8, address as u8, (value >> 8) as u8, value as u8];
+        self.request(unit, &request)?;
+        Ok(())
+    }
+}
+
+/// A Modbus server (slave) on a serial line.
+pub struct Server<P> {
+    port: P,
+    framing: Framing,
+    inter_frame_timeout: Duration,
+    pending: Vec<u8>
+}
+
+impl<P: SerialPort> Server<P> {
+    /// Creates a server that communicates through `port` with the given framing mode.
+    pub fn new(port: P, framing: Framing) -> Self {
+        Server {
+            port,
+            framing,
+            inter_frame_timeout: default_inter_frame_timeout(framing),
+            pending: Vec::new()
+        }
+    }
+
+    /// Returns the framing mode.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Sets the maximum gap between bytes of a frame. See `Client::set_inter_frame_timeout()`.
+    pub fn set_inter_frame_timeout(&mut self, timeout: Duration) {
+        self.inter_frame_timeout = timeout;
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Receives a request and returns its unit address and PDU.
+    ///
+    /// Waits for the start of a request for up to the port's timeout. Requests addressed to other
+    /// units are returned as well, so that the caller can ignore them.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no request was received.
+    /// * `Io(InvalidData)` if the request was malformed.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn receive(&mut self) -> ::Result<(u8, Vec<u8>)> {
+        let timeout = self.port.timeout();
+        let frame = read_frame(&mut self.port, self.framing, &mut self.pending, timeout, self.inter_frame_timeout)?;
+        decode(self.framing, &frame)
+    }
+
+    /// Sends a response PDU from unit `unit`.
+    pub fn respond(&mut self, unit: u8, pdu: &[u8]) -> ::Result<()> {
+        self.port.write_all(&encode(self.framing, unit, pdu))?;
+        self.port.flush()?;
+        Ok(())
+    }
+}
+
+fn default_inter_frame_timeout(framing: Framing) -> Duration {
+    match framing {
+        Framing::Rtu => Duration::from_millis(20),
+        Framing::Ascii => Duration::from_secs(1)
+    }
+}
+
+/// Reads one frame, waiting up to `timeout` for it to start.
+///
+/// Bytes in `pending` are used before reading from the port. In ASCII mode, bytes that were read
+/// after the end of the frame are left in `pending` for the next frame.
+fn read_frame<P: SerialPort + ?Sized>(port: &mut P, framing: Framing, pending: &mut Vec<u8>, timeout: Duration, inter_frame_timeout: Duration) -> ::Result<Vec<u8>> {
+    let max_len = match framing {
+        Framing::Rtu => MAX_RTU_FRAME,
+        Framing::Ascii => MAX_ASCII_FRAME
+    };
+
+    let mut frame = Vec::new();
+    let mut chunk = mem::take(pending);
+    let mut buf = [0; 64];
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        for (i, &byte) in chunk.iter().enumerate() {
+            // in ASCII mode, a colon always starts a new frame
+            if framing == Framing::Ascii {
+                if byte == b':' {
+                    frame.clear();
+                }
+                else if frame.is_empty() {
+                    continue;
+                }
+            }
+
+            frame.push(byte);
+
+            if framing == Framing::Ascii && byte == b'\n' {
+                pending.extend_from_slice(&chunk[i + 1..]);
+                return Ok(frame);
+            }
+        }
+
+        if frame.len() > max_len {
+            return Err(invalid_frame("frame is too long"));
+        }
+
+        let wait = if frame.is_empty() {
+            deadline.saturating_duration_since(Instant::now())
+        }
+        else {
+            inter_frame_timeout
+        };
+
+        let len = match port.read_with_timeout(&mut buf, wait) {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => 0,
+            Err(err) => return Err(::Error::from(err))
+        };
+
+        if len == 0 {
+            if framing == Framing::Rtu && !frame.is_empty() {
+                return Ok(frame);
+            }
+
+            if frame.is_empty() && Instant::now() < deadline {
+                continue;
+            }
+
+            let description = if frame.is_empty() { "no frame received" } else { "incomplete frame" };
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), description).with_operation("modbus"));
+        }
+
+        chunk.clear();
+        chunk.extend_from_slice(&buf[..len]);
+    }
+}
+
+fn invalid_frame(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("modbus")
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789ABCDEF"[value as usize]
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{crc16,decode,encode,lrc,Client,Framing,Server};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn crc16_matches_reference() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]), 0xcdc5);
+    }
+
+    #[test]
+    fn lrc_matches_reference() {
+        assert_eq!(lrc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]), 0xf2);
+    }
+
+    #[test]
+    fn encode_rtu_appends_crc() {
+        assert_eq!(encode(Framing::Rtu, 1, &[0x03, 0x00, 0x00, 0x00, 0x0a]), vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]);
+    }
+
+    #[test]
+    fn encode_ascii_uses_hex_and_lrc() {
+        assert_eq!(encode(Framing::Ascii, 1, &[0x03, 0x00, 0x00, 0x00, 0x0a]), b":01030000000AF2\r\n".to_vec());
+    }
+
+    #[test]
+    fn decode_round_trips_both_framings() {
+        for &framing in &[Framing::Rtu, Framing::Ascii] {
+            let frame = encode(framing, 17, &[0x06, 0x00, 0x01, 0x00, 0x03]);
+            assert_eq!(decode(framing, &frame).unwrap(), (17, vec![0x06, 0x00, 0x01, 0x00, 0x03]));
+        }
+    }
+
+    #[test]
+    fn decode_accepts_lowercase_ascii() {
+        assert_eq!(decode(Framing::Ascii, b":01030000000af2\r\n").unwrap(), (1, vec![0x03, 0x00, 0x00, 0x00, 0x0a]));
+    }
+
+    #[test]
+    fn decode_rejects_bad_checksums() {
+        let err = decode(Framing::Rtu, &[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xce]).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+
+        let err = decode(Framing::Ascii, b":01030000000AF3\r\n").unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn client_reads_holding_registers_in_ascii_mode() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"noise:0103040001");
+        device.push_input(b"0002F5\r\n");
+
+        let mut client = Client::new(device, Framing::Ascii);
+
+        assert_eq!(client.read_holding_registers(1, 0, 2).unwrap(), vec![1, 2]);
+        assert_eq!(client.get_ref().output(), encode(Framing::Ascii, 1, &[0x03, 0x00, 0x00, 0x00, 0x02]));
+    }
+
+    #[test]
+    fn client_reads_holding_registers_in_rtu_mode() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode(Framing::Rtu, 1, &[0x03, 0x02, 0x12, 0x34]));
+
+        let mut client = Client::new(device, Framing::Rtu);
+
+        assert_eq!(client.read_holding_registers(1, 0x10, 1).unwrap(), vec![0x1234]);
+    }
+
+    #[test]
+    fn client_reports_exceptions() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode(Framing::Rtu, 1, &[0x86, 0x02]));
+
+        let mut client = Client::new(device, Framing::Rtu);
+        let err = client.write_single_register(1, 0xffff, 0).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::Other));
+        assert!(err.to_string().contains("exception code 2"));
+    }
+
+    #[test]
+    fn client_does_not_wait_for_broadcast_response() {
+        let mut client = Client::new(ScriptedDevice::new(), Framing::Rtu);
+
+        assert_eq!(client.request(0, &[0x06, 0x00, 0x01, 0x00, 0x03]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn client_times_out_without_response() {
+        let mut client = Client::new(ScriptedDevice::new(), Framing::Ascii);
+        client.set_timeout(Duration::from_millis(10));
+
+        let err = client.read_holding_registers(1, 0, 1).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn server_receives_and_responds() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b":01030000000AF2\r\n");
+
+        let mut server = Server::new(device, Framing::Ascii);
+
+        assert_eq!(server.receive().unwrap(), (1, vec![0x03, 0x00, 0x00, 0x00, 0x0a]));
+
+        server.respond(1, &[0x83, 0x02]).unwrap();
+        assert_eq!(server.get_ref().output(), encode(Framing::Ascii, 1, &[0x83, 0x02]));
+    }
+
+    #[test]
+    fn server_receives_two_ascii_frames_read_together() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b":01030000000AF2\r\n:020600010003F4\r\n");
+        device.timeout = Duration::from_millis(10);
+
+        let mut server = Server::new(device, Framing::Ascii);
+
+        assert_eq!(server.receive().unwrap(), (1, vec![0x03, 0x00, 0x00, 0x00, 0x0a]));
+        assert_eq!(server.receive().unwrap(), (2, vec![0x06, 0x00, 0x01, 0x00, 0x03]));
+        assert_eq!(server.receive().unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+}
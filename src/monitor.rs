@@ -0,0 +1,263 @@
+//! Passive monitoring of an existing serial link.
+//!
+//! A `Monitor` is a read-only view of a port that's wired into a link between two other devices,
+//! such as an RS-485 bus or an RS-232 line tapped with a Y-cable. It never transmits, never
+//! asserts DTR or RTS, and leaves the line settings alone unless they're changed explicitly:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::monitor::Monitor;
+//!
+//! let mut monitor = Monitor::open("/dev/ttyUSB0").unwrap();
+//! monitor.reconfigure(|settings| settings.set_baud_rate(serial::Baud9600)).unwrap();
+//!
+//! let mut buf = [0; 256];
+//!
+//! loop {
+//!     if let Ok(n) = monitor.read(&mut buf) {
+//!         println!("{:02x?}", &buf[..n]);
+//!     }
+//! }
+//! ```
+//!
+//! `Monitor` doesn't implement `Write` and has no methods for setting control signals. Flow
+//! control is disabled when a port is wrapped and can't be enabled again, because both software
+//! and hardware flow control transmit on the line: the driver sends XOFF or lowers RTS when its
+//! input buffer fills. Data that isn't read quickly enough is therefore lost when the operating
+//! system's buffer overflows, rather than throttling the devices being monitored.
+//!
+//! ## Platform Guarantees
+//!
+//! `Monitor::open()` opens the port read-only, so the operating system rejects writes through it,
+//! and deasserts DTR and RTS. What happens before that point is up to the operating system and
+//! driver:
+//!
+//! * **POSIX:** The port is opened with `O_RDONLY`. The TTY is put into raw mode, which only
+//!   affects how received data is processed locally; the baud rate, character size, parity, and
+//!   stop bits are kept as they were found. Linux and some other systems assert DTR and RTS when a
+//!   TTY is opened, so the signals may pulse briefly unless they were left deasserted when the
+//!   port was last closed (`stty -hupcl`). See `OpenOptions::dtr()`. Carrier Detect is ignored, so
+//!   the port isn't hung up when the monitored link drops it.
+//! * **Windows:** The port is opened with `GENERIC_READ` access and `fDtrControl` and
+//!   `fRtsControl` are set to disabled. The line settings in the DCB are kept as the driver
+//!   reports them. Whether DTR or RTS change when the port is opened depends on the driver; the
+//!   standard `serial.sys` driver and most USB adapters keep their previous state until the DCB is
+//!   written.
+//!
+//! On either platform the guarantees only hold for a monitor that's opened by `Monitor::open()`.
+//! A port wrapped with `Monitor::new()` may have been opened for writing or may have already
+//! asserted its control signals.
+
+use std::ffi::OsStr;
+use std::io;
+use std::time::Duration;
+
+use ::{Access,FlowNone,ModemSignal,OpenOptions,PortSettings,SerialPort,SerialPortSettings};
+
+/// A read-only wrapper around a port that never transmits.
+pub struct Monitor<P> {
+    port: P
+}
+
+impl Monitor<::SystemPort> {
+    /// Opens a native serial port for monitoring.
+    ///
+    /// The argument is interpreted as by [`serial::open()`](../fn.open.html). The port is opened
+    /// read-only with DTR and RTS deasserted, and flow control is disabled. Other settings are
+    /// left as they were found.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `OpenOptions::open()`.
+    pub fn open<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.access(Access::ReadOnly).dtr(false).rts(false);
+
+        Monitor::new(options.open(port)?)
+    }
+}
+
+impl<P: SerialPort> Monitor<P> {
+    /// Wraps a port for monitoring and disables its flow control.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the port's settings can't be changed.
+    pub fn new(mut port: P) -> ::Result<Self> {
+        port.reconfigure(|settings| {
+            settings.set_flow_control(FlowNone);
+            Ok(())
+        })?;
+
+        Ok(Monitor { port })
+    }
+
+    /// Applies all settings to the port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if `settings` enables flow control.
+    /// Otherwise, it returns the same errors as `SerialPort::configure()`.
+    pub fn configure(&mut self, settings: &PortSettings) -> ::Result<()> {
+        if settings.flow_control != FlowNone {
+            return Err(flow_control_error());
+        }
+
+        self.port.configure(settings)
+    }
+
+    /// Changes some of the port's settings, leaving the others as they were found.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if `setup` enables flow control, in
+    /// which case the settings aren't changed. Otherwise, it returns the same errors as
+    /// `SerialPort::reconfigure()`.
+    pub fn reconfigure<F>(&mut self, setup: F) -> ::Result<()>
+        where F: FnOnce(&mut dyn SerialPortSettings) -> ::Result<()>
+    {
+        self.port.reconfigure(|settings| {
+            setup(settings)?;
+
+            match settings.flow_control() {
+                Some(FlowNone) | None => Ok(()),
+                Some(_) => Err(flow_control_error())
+            }
+        })
+    }
+
+    /// Returns the timeout for reads.
+    pub fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    /// Sets the timeout for reads.
+    pub fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.port.set_timeout(timeout)
+    }
+
+    /// Reads with a timeout for this call only. See `SerialPort::read_with_timeout()`.
+    pub fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.port.read_with_timeout(buf, timeout)
+    }
+
+    /// Discards data that was received but not yet read.
+    pub fn discard_input(&mut self) -> ::Result<()> {
+        self.port.discard_input()
+    }
+
+    /// Reads the state of the CTS (Clear To Send) control signal.
+    pub fn read_cts(&mut self) -> ::Result<bool> {
+        self.port.read_cts()
+    }
+
+    /// Reads the state of the DSR (Data Set Ready) control signal.
+    pub fn read_dsr(&mut self) -> ::Result<bool> {
+        self.port.read_dsr()
+    }
+
+    /// Reads the state of the RI (Ring Indicator) control signal.
+    pub fn read_ri(&mut self) -> ::Result<bool> {
+        self.port.read_ri()
+    }
+
+    /// Reads the state of the CD (Carrier Detect) control signal.
+    pub fn read_cd(&mut self) -> ::Result<bool> {
+        self.port.read_cd()
+    }
+
+    /// Waits for one of the given input signals to change. See `SerialPort::wait_signal_change()`.
+    pub fn wait_signal_change(&mut self, signals: &[ModemSignal]) -> ::Result<ModemSignal> {
+        self.port.wait_signal_change(signals)
+    }
+
+    /// Checks whether the port is still connected. See `SerialPort::check()`.
+    pub fn check(&mut self) -> ::Result<()> {
+        self.port.check()
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns the underlying port.
+    ///
+    /// The port's flow control stays disabled.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+}
+
+impl<P: io::Read> io::Read for Monitor<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+fn flow_control_error() -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, "flow control transmits on the monitored line").with_operation("configure")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use super::Monitor;
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn monitor_disables_flow_control() {
+        let mut device = ScriptedDevice::new();
+        device.settings.flow_control = ::FlowHardware;
+
+        let monitor = Monitor::new(device).unwrap();
+        assert_eq!(monitor.get_ref().settings.flow_control, ::FlowNone);
+    }
+
+    #[test]
+    fn monitor_rejects_flow_control() {
+        let mut monitor = Monitor::new(ScriptedDevice::new()).unwrap();
+
+        let mut settings = ::PortSettings::standard(9600);
+        settings.flow_control = ::FlowSoftware;
+
+        assert_eq!(monitor.configure(&settings).err().unwrap().kind(), ::ErrorKind::InvalidInput);
+
+        let result = monitor.reconfigure(|settings| {
+            settings.set_baud_rate(::Baud9600)?;
+            settings.set_flow_control(::FlowHardware);
+            Ok(())
+        });
+
+        assert_eq!(result.err().unwrap().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(monitor.get_ref().settings, ::PortSettings::default());
+    }
+
+    #[test]
+    fn monitor_keeps_settings_that_are_not_changed() {
+        let mut device = ScriptedDevice::new();
+        device.settings = ::PortSettings::modbus_rtu(19200);
+
+        let mut monitor = Monitor::new(device).unwrap();
+        monitor.reconfigure(|settings| settings.set_baud_rate(::Baud9600)).unwrap();
+
+        let settings = monitor.get_ref().settings;
+        assert_eq!(settings.baud_rate, ::Baud9600);
+        assert_eq!(settings.parity, ::ParityEven);
+    }
+
+    #[test]
+    fn monitor_reads_without_touching_the_line() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"traffic");
+
+        let mut monitor = Monitor::new(device).unwrap();
+        let mut buf = [0; 16];
+
+        assert_eq!(monitor.read(&mut buf).unwrap(), 7);
+        assert_eq!(&buf[..7], b"traffic");
+        assert!(monitor.into_inner().events.is_empty());
+    }
+}
@@ -0,0 +1,606 @@
+//! NMEA 0183 sentences and a multiplexer that repeats them between ports.
+//!
+//! `NmeaCodec` decodes sentences such as `$GPGGA,...*47` from a port with `Framed`, verifying
+//! their checksums. A `Multiplexer` reads sentences from several instruments, filters them, and
+//! repeats them to output ports and TCP clients, e.g., to feed a chart plotter and navigation
+//! software on the network from one GPS receiver and one AIS receiver:
+//!
+//! ```no_run
+//! use std::net::TcpListener;
+//! use std::time::Duration;
+//! use serial::nmea::Multiplexer;
+//!
+//! let mut mux = Multiplexer::new();
+//! mux.add_input(serial::open("/dev/ttyUSB0").unwrap());
+//! mux.add_input(serial::open("/dev/ttyUSB1").unwrap());
+//! mux.add_output(serial::open("/dev/ttyUSB2").unwrap());
+//! mux.listen(TcpListener::bind("0.0.0.0:10110").unwrap());
+//!
+//! // drop satellite details and sentences repeated by both receivers
+//! mux.filter_mut().reject("GSV").set_dedupe_window(Some(Duration::from_secs(1)));
+//!
+//! let worker = mux.spawn(Default::default()).unwrap();
+//! ```
+//!
+//! An output port that fails is skipped by later sentences, as with `Broadcast`, and a TCP client
+//! that fails or falls behind by more than the write timeout is disconnected. Data sent by TCP
+//! clients is ignored.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self,Write};
+use std::net::{TcpListener,TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use std::sync::mpsc::{self,Receiver,RecvTimeoutError,Sender};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::broadcast::Broadcast;
+use ::codec::{Decoder,Encoder,Framed};
+use ::codec::lines::LineCodec;
+use ::shutdown;
+
+/// An NMEA 0183 sentence.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Sentence {
+    text: String,
+    address_end: usize,
+    data_end: usize
+}
+
+impl Sentence {
+    /// Parses a sentence, without its line terminator.
+    ///
+    /// A sentence starts with `$`, or `!` for encapsulated sentences such as AIS messages. The
+    /// checksum is optional, but it must match if it's present.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(InvalidData)` if `text` isn't a sentence or its
+    /// checksum doesn't match.
+    pub fn parse(text: &str) -> ::Result<Sentence> {
+        if !text.starts_with(['$', '!']) {
+            return Err(invalid_sentence("sentence doesn't start with '$' or '!'"));
+        }
+
+        if !text.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+            return Err(invalid_sentence("sentence contains non-printable characters"));
+        }
+
+        let data_end = match text.rfind('*') {
+            Some(star) => {
+                let checksum = u8::from_str_radix(&text[star + 1..], 16).ok().filter(|_| text.len() == star + 3);
+
+                match checksum {
+                    Some(checksum) if checksum == text[1..star].bytes().fold(0, |sum, b| sum ^ b) => star,
+                    Some(_) => return Err(invalid_sentence("checksum mismatch")),
+                    None => return Err(invalid_sentence("invalid checksum"))
+                }
+            },
+            None => text.len()
+        };
+
+        let address_end = text[..data_end].find(',').unwrap_or(data_end);
+
+        if address_end == 1 || !text[1..address_end].bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(invalid_sentence("invalid address field"));
+        }
+
+        Ok(Sentence { text: text.to_string(), address_end, data_end })
+    }
+
+    /// Returns the text of the sentence, without its line terminator.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the address field, e.g., `GPGGA`.
+    pub fn address(&self) -> &str {
+        &self.text[1..self.address_end]
+    }
+
+    /// Returns the talker ID, e.g., `GP`, or `P` for proprietary sentences.
+    pub fn talker(&self) -> &str {
+        let address = self.address();
+        let len = if address.starts_with('P') { 1 } else { address.len().min(2) };
+
+        &address[..len]
+    }
+
+    /// Returns the sentence type, e.g., `GGA`.
+    pub fn kind(&self) -> &str {
+        &self.address()[self.talker().len()..]
+    }
+
+    /// Returns the data fields that follow the address field.
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.text[self.address_end..self.data_end].split(',').skip(1)
+    }
+}
+
+impl fmt::Display for Sentence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn invalid_sentence(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("decode sentence")
+}
+
+/// A codec for NMEA 0183 sentences terminated by `\r\n`.
+///
+/// Empty lines are skipped.
+#[derive(Debug,Clone,Default)]
+pub struct NmeaCodec {
+    lines: LineCodec
+}
+
+impl NmeaCodec {
+    /// Creates an NMEA codec.
+    pub fn new() -> Self {
+        NmeaCodec { lines: LineCodec::new() }
+    }
+}
+
+impl Decoder for NmeaCodec {
+    type Item = Sentence;
+
+    /// Decodes the next sentence.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` if a line isn't a valid sentence. Decoding
+    /// continues with the next line.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Sentence>> {
+        loop {
+            match self.lines.decode(buf)? {
+                Some(ref line) if line.is_empty() => continue,
+                Some(line) => return Sentence::parse(&line).map(Some),
+                None => return Ok(None)
+            }
+        }
+    }
+}
+
+impl Encoder for NmeaCodec {
+    type Item = Sentence;
+
+    fn encode(&mut self, sentence: &Sentence, buf: &mut Vec<u8>) -> ::Result<()> {
+        buf.extend_from_slice(sentence.as_str().as_bytes());
+        buf.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+}
+
+/// Selects the sentences that a `Multiplexer` repeats.
+///
+/// Sentences are identified by their address, e.g., `GPGGA`, or by their type, e.g., `GGA`, to
+/// match any talker.
+#[derive(Debug,Clone,Default)]
+pub struct Filter {
+    accept: Vec<String>,
+    reject: Vec<String>,
+    dedupe_window: Option<Duration>,
+    recent: VecDeque<(Instant, String)>
+}
+
+impl Filter {
+    /// Creates a filter that passes every sentence.
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    /// Passes sentences matching `id`.
+    ///
+    /// Once a sentence has been accepted, sentences that don't match an accepted ID are dropped.
+    pub fn accept(&mut self, id: &str) -> &mut Self {
+        self.accept.push(id.to_string());
+        self
+    }
+
+    /// Drops sentences matching `id`, even if they're accepted.
+    pub fn reject(&mut self, id: &str) -> &mut Self {
+        self.reject.push(id.to_string());
+        self
+    }
+
+    /// Drops sentences that are identical to a sentence passed within `window`.
+    ///
+    /// This removes duplicates when several inputs receive the same sentences, e.g., redundant
+    /// receivers or instruments that repeat each other's data. Duplicates aren't dropped by default.
+    pub fn set_dedupe_window(&mut self, window: Option<Duration>) -> &mut Self {
+        self.dedupe_window = window;
+        self.recent.clear();
+        self
+    }
+
+    /// Returns `true` if `sentence`, received at `now`, should be repeated.
+    pub fn pass(&mut self, sentence: &Sentence, now: Instant) -> bool {
+        let matches = |id: &String| id == sentence.address() || id == sentence.kind();
+
+        if (!self.accept.is_empty() && !self.accept.iter().any(matches)) || self.reject.iter().any(matches) {
+            return false;
+        }
+
+        if let Some(window) = self.dedupe_window {
+            while self.recent.front().is_some_and(|&(passed, _)| now.saturating_duration_since(passed) >= window) {
+                self.recent.pop_front();
+            }
+
+            if self.recent.iter().any(|(_, text)| text == sentence.as_str()) {
+                return false;
+            }
+
+            self.recent.push_back((now, sentence.as_str().to_string()));
+        }
+
+        true
+    }
+}
+
+/// Options for a multiplexer's background threads.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct MultiplexOptions {
+    /// Maximum time to wait for data or a sentence before checking whether to stop.
+    pub poll_interval: Duration,
+
+    /// Maximum time to wait for a TCP client to accept a sentence before disconnecting it.
+    pub write_timeout: Duration
+}
+
+impl Default for MultiplexOptions {
+    fn default() -> Self {
+        MultiplexOptions {
+            poll_interval: Duration::from_millis(10),
+            write_timeout: Duration::from_secs(1)
+        }
+    }
+}
+
+/// Repeats NMEA sentences from input ports to output ports and TCP clients.
+pub struct Multiplexer<P> {
+    inputs: Vec<P>,
+    outputs: Broadcast<P>,
+    listener: Option<TcpListener>,
+    filter: Filter
+}
+
+impl<P: SerialPort> Multiplexer<P> {
+    /// Creates a multiplexer with no ports.
+    pub fn new() -> Self {
+        Multiplexer {
+            inputs: Vec::new(),
+            outputs: Broadcast::new(),
+            listener: None,
+            filter: Filter::new()
+        }
+    }
+
+    /// Adds a port that sentences are read from and returns its index among the inputs.
+    pub fn add_input(&mut self, port: P) -> usize {
+        self.inputs.push(port);
+        self.inputs.len() - 1
+    }
+
+    /// Adds a port that sentences are written to and returns its index among the outputs.
+    pub fn add_output(&mut self, port: P) -> usize {
+        self.outputs.push(port)
+    }
+
+    /// Repeats sentences to TCP clients that connect to `listener`.
+    pub fn listen(&mut self, listener: TcpListener) {
+        self.listener = Some(listener);
+    }
+
+    /// Returns the input ports.
+    pub fn inputs(&self) -> &[P] {
+        &self.inputs
+    }
+
+    /// Returns the output ports, with the errors of the outputs that have failed.
+    pub fn outputs(&self) -> &Broadcast<P> {
+        &self.outputs
+    }
+
+    /// Returns a reference to the filter.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns a mutable reference to the filter.
+    pub fn filter_mut(&mut self) -> &mut Filter {
+        &mut self.filter
+    }
+
+    /// Returns the input and output ports.
+    pub fn into_inner(self) -> (Vec<P>, Vec<P>) {
+        (self.inputs, self.outputs.into_inner())
+    }
+
+    /// Moves the ports to background threads that repeat sentences.
+    ///
+    /// Each input is read by its own thread, and sentences are filtered and written by a thread
+    /// that owns the outputs.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the listener couldn't be made non-blocking.
+    pub fn spawn(mut self, options: MultiplexOptions) -> ::Result<Worker<P>>
+        where P: Send + 'static
+    {
+        if let Some(ref listener) = self.listener {
+            listener.set_nonblocking(true)?;
+        }
+
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            invalid: AtomicUsize::new(0),
+            clients: AtomicUsize::new(0)
+        });
+
+        let (sentence_tx, sentence_rx) = mpsc::channel();
+
+        let inputs = self.inputs.drain(..).map(|port| {
+            let shared = shared.clone();
+            let sentences = sentence_tx.clone();
+            thread::spawn(move || read_input(port, &sentences, &shared, &options))
+        }).collect();
+
+        let router = {
+            let shared = shared.clone();
+            thread::spawn(move || route(self, &sentence_rx, &shared, &options))
+        };
+
+        Ok(Worker { shared, router: Some(router), inputs })
+    }
+}
+
+impl<P: SerialPort> Default for Multiplexer<P> {
+    fn default() -> Self {
+        Multiplexer::new()
+    }
+}
+
+/// A handle to a multiplexer's background threads.
+///
+/// The threads are stopped when the `Worker` is closed, shut down, or dropped.
+pub struct Worker<P> {
+    shared: Arc<Shared>,
+    router: Option<JoinHandle<Multiplexer<P>>>,
+    inputs: Vec<JoinHandle<::Result<P>>>
+}
+
+struct Shared {
+    stop: AtomicBool,
+    invalid: AtomicUsize,
+    clients: AtomicUsize
+}
+
+impl<P> Worker<P> {
+    /// Returns the number of received lines that weren't valid sentences.
+    pub fn invalid(&self) -> usize {
+        self.shared.invalid.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connected TCP clients.
+    pub fn clients(&self) -> usize {
+        self.shared.clients.load(Ordering::Relaxed)
+    }
+
+    /// Stops the threads and returns the multiplexer with its ports.
+    ///
+    /// TCP clients are disconnected.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped an input thread, if one stopped because of an
+    /// I/O error, or an error of kind `Io(Other)` if a thread panicked.
+    pub fn close(mut self) -> ::Result<Multiplexer<P>> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        let router = shutdown::join_until(self.router.take().unwrap(), None, "router thread");
+        let inputs = self.inputs.drain(..).map(|thread| shutdown::join_until(thread, None, "input thread").and_then(|result| result)).collect();
+
+        reassemble(router, inputs)
+    }
+
+    /// Stops the threads like `close()`, waiting at most `timeout` for them to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the threads didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<Multiplexer<P>> {
+        let deadline = Instant::now() + timeout;
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        let router = shutdown::join_until(self.router.take().unwrap(), Some(deadline), "router thread");
+        let inputs = self.inputs.drain(..).map(|thread| shutdown::join_until(thread, Some(deadline), "input thread").and_then(|result| result)).collect();
+
+        reassemble(router, inputs)
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn reassemble<P>(router: ::Result<Multiplexer<P>>, inputs: Vec<::Result<P>>) -> ::Result<Multiplexer<P>> {
+    let mut mux = router?;
+
+    for input in inputs {
+        mux.inputs.push(input?);
+    }
+
+    Ok(mux)
+}
+
+fn read_input<P: SerialPort>(port: P, sentences: &Sender<Sentence>, shared: &Shared, options: &MultiplexOptions) -> ::Result<P> {
+    let mut framed = Framed::new(port, NmeaCodec::new());
+    let mut buf = [0; 256];
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        let len = match framed.get_mut().read_with_timeout(&mut buf, options.poll_interval) {
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(::Error::from(err))
+        };
+
+        framed.feed(&buf[..len]);
+
+        loop {
+            match framed.decode_buffered() {
+                Ok(Some(sentence)) => {
+                    if sentences.send(sentence).is_err() {
+                        return Ok(framed.into_parts().0);
+                    }
+                },
+                Ok(None) => break,
+                Err(_) => {
+                    shared.invalid.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    Ok(framed.into_parts().0)
+}
+
+fn route<P: SerialPort>(mut mux: Multiplexer<P>, sentences: &Receiver<Sentence>, shared: &Shared, options: &MultiplexOptions) -> Multiplexer<P> {
+    let mut clients = Vec::new();
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        if let Some(ref listener) = mux.listener {
+            accept_clients(listener, &mut clients, options);
+            shared.clients.store(clients.len(), Ordering::Relaxed);
+        }
+
+        let sentence = match sentences.recv_timeout(options.poll_interval) {
+            Ok(sentence) => sentence,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+
+        if !mux.filter.pass(&sentence, Instant::now()) {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        let _ = NmeaCodec::new().encode(&sentence, &mut data);
+
+        // failed outputs are recorded by the broadcast
+        if !mux.outputs.is_empty() {
+            let _ = mux.outputs.write_all(&data).and_then(|()| mux.outputs.flush());
+        }
+
+        clients.retain_mut(|client: &mut TcpStream| client.write_all(&data).is_ok());
+        shared.clients.store(clients.len(), Ordering::Relaxed);
+    }
+
+    mux
+}
+
+fn accept_clients(listener: &TcpListener, clients: &mut Vec<TcpStream>, options: &MultiplexOptions) {
+    while let Ok((stream, _)) = listener.accept() {
+        let configured = stream.set_nonblocking(false)
+            .and_then(|()| stream.set_write_timeout(Some(options.write_timeout)))
+            .and_then(|()| stream.set_nodelay(true));
+
+        if configured.is_ok() {
+            clients.push(stream);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead,BufReader};
+    use std::net::{TcpListener,TcpStream};
+    use std::time::{Duration,Instant};
+
+    use super::{Filter,Multiplexer,NmeaCodec,Sentence};
+    use ::codec::Framed;
+    use ::testing::ScriptedDevice;
+
+    const GGA: &str = "$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76";
+    const RMC: &str = "$GPRMC,092750.000,A,5321.6802,N,00630.3372,W,0.02,31.66,280511,,,A*43";
+
+    #[test]
+    fn sentence_parses_fields_and_verifies_checksum() {
+        let sentence = Sentence::parse(GGA).unwrap();
+        assert_eq!(sentence.address(), "GPGGA");
+        assert_eq!(sentence.talker(), "GP");
+        assert_eq!(sentence.kind(), "GGA");
+        assert_eq!(sentence.fields().take(3).collect::<Vec<_>>(), vec!["092750.000", "5321.6802", "N"]);
+        assert_eq!(sentence.fields().last(), Some(""));
+
+        let sentence = Sentence::parse("$PGRME,15.0,M,45.0,M,25.0,M").unwrap();
+        assert_eq!(sentence.talker(), "P");
+        assert_eq!(sentence.kind(), "GRME");
+
+        for text in &["GPGGA,1*00", "$GPGGA,1*00", "$GPGGA,1*7", "$,1", "$GP GGA,1", "$GPGGA,\u{e9}"] {
+            assert_eq!(Sentence::parse(text).unwrap_err().kind(), ::ErrorKind::Io(::std::io::ErrorKind::InvalidData), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn codec_skips_empty_lines_and_reports_invalid_sentences() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(format!("\r\n{}\r\n$GPGGA,1*00\r\n{}\r\n", GGA, RMC).as_bytes());
+
+        let mut framed = Framed::new(device, NmeaCodec::new());
+        assert_eq!(framed.read_frame().unwrap().as_str(), GGA);
+        assert!(framed.read_frame().is_err());
+        assert_eq!(framed.read_frame().unwrap().as_str(), RMC);
+    }
+
+    #[test]
+    fn filter_selects_and_dedupes_sentences() {
+        let gga = Sentence::parse(GGA).unwrap();
+        let rmc = Sentence::parse(RMC).unwrap();
+        let start = Instant::now();
+
+        let mut filter = Filter::new();
+        filter.accept("GGA").accept("GPRMC").reject("RMC");
+        assert!(filter.pass(&gga, start));
+        assert!(!filter.pass(&rmc, start));
+
+        let mut filter = Filter::new();
+        filter.set_dedupe_window(Some(Duration::from_secs(1)));
+        assert!(filter.pass(&gga, start));
+        assert!(filter.pass(&rmc, start));
+        assert!(!filter.pass(&gga, start + Duration::from_millis(500)));
+        assert!(filter.pass(&gga, start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn multiplexer_repeats_sentences_to_outputs_and_clients() {
+        let mut input = ScriptedDevice::new();
+        input.push_input(format!("{}\r\n{}\r\n", GGA, RMC).as_bytes());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let mut mux = Multiplexer::new();
+        mux.add_input(input);
+        mux.add_output(ScriptedDevice::new());
+        mux.listen(listener);
+
+        let worker = mux.spawn(Default::default()).unwrap();
+
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), GGA);
+        assert_eq!(lines.next().unwrap().unwrap(), RMC);
+
+        let mux = worker.close().unwrap();
+        assert_eq!(mux.inputs().len(), 1);
+        assert_eq!(mux.outputs().get_ref()[0].output(), format!("{}\r\n{}\r\n", GGA, RMC).as_bytes());
+    }
+}
@@ -0,0 +1,236 @@
+//! Writing with delays between bytes or frames.
+//!
+//! Some old devices, and links through slow optocouplers, lose data when bytes arrive
+//! back-to-back. A `PacedPort` inserts a minimum gap after each byte, after each frame, or both:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use std::time::Duration;
+//! use serial::paced::{PacedPort,Pacing};
+//!
+//! let pacing = Pacing {
+//!     inter_byte: Duration::from_millis(2),
+//!     inter_frame: Duration::from_millis(50),
+//!     delimiter: Some(b'\r')
+//! };
+//!
+//! let mut port = PacedPort::new(serial::open("/dev/ttyUSB0").unwrap(), pacing);
+//! port.write_all(b"RESET\rSTATUS\r").unwrap();
+//! ```
+//!
+//! The gaps are measured from the time the preceding data has been transmitted. Each paced write
+//! is flushed, which waits until the port's output buffer has drained, so that the operating
+//! system can't send the data back-to-back from its buffer. Writes that need no gap are passed to
+//! the port in a single call.
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::SerialDevice;
+
+/// The gaps inserted by a `PacedPort`.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct Pacing {
+    /// Minimum gap after each byte.
+    pub inter_byte: Duration,
+
+    /// Minimum gap after each frame.
+    ///
+    /// This replaces `inter_byte` after the last byte of a frame. A frame ends with `delimiter` or
+    /// at the end of a call to `PacedPort::write_frame()`.
+    pub inter_frame: Duration,
+
+    /// The byte that ends a frame, if frames are delimited.
+    pub delimiter: Option<u8>
+}
+
+/// A port wrapper that inserts delays between the bytes or frames that are written.
+pub struct PacedPort<P> {
+    port: P,
+    pacing: Pacing,
+    next: Option<Instant>
+}
+
+impl<P: io::Write> PacedPort<P> {
+    /// Wraps a port to pace the data written to it.
+    pub fn new(port: P, pacing: Pacing) -> Self {
+        PacedPort {
+            port,
+            pacing,
+            next: None
+        }
+    }
+
+    /// Returns the gaps that are inserted.
+    pub fn pacing(&self) -> &Pacing {
+        &self.pacing
+    }
+
+    /// Changes the gaps that are inserted. A gap that has already started isn't shortened.
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    /// Writes a complete frame, which is followed by the inter-frame gap.
+    ///
+    /// Bytes within the frame are separated by the inter-byte gap. Delimiters within `frame` also
+    /// end a frame.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        io::Write::write_all(self, frame)?;
+
+        if !frame.is_empty() && Some(frame[frame.len() - 1]) != self.pacing.delimiter {
+            self.port.flush()?;
+            self.next = Some(Instant::now() + self.pacing.inter_frame);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    ///
+    /// Data written directly to the port isn't paced.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Waits for the gap after previously written data to elapse.
+    fn wait(&mut self) {
+        if let Some(next) = self.next.take() {
+            let now = Instant::now();
+
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+    }
+
+    /// Writes `data` after the current gap, then starts a gap of `gap` once it's transmitted.
+    fn write_paced(&mut self, data: &[u8], gap: Duration) -> io::Result<()> {
+        self.wait();
+        self.port.write_all(data)?;
+
+        if gap > Duration::from_secs(0) {
+            self.port.flush()?;
+            self.next = Some(Instant::now() + gap);
+        }
+
+        Ok(())
+    }
+}
+
+/// A write transmits all of `buf` with the configured gaps before returning, unless it fails.
+impl<P: io::Write> io::Write for PacedPort<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Pacing { inter_byte, inter_frame, delimiter } = self.pacing;
+
+        if inter_byte > Duration::from_secs(0) {
+            for &byte in buf {
+                let gap = if Some(byte) == delimiter { inter_frame } else { inter_byte };
+                self.write_paced(&[byte], gap)?;
+            }
+        }
+        else if let Some(delimiter) = delimiter {
+            for frame in buf.split_inclusive(|&byte| byte == delimiter) {
+                let gap = if frame.ends_with(&[delimiter]) { inter_frame } else { Duration::from_secs(0) };
+                self.write_paced(frame, gap)?;
+            }
+        }
+        else {
+            self.write_paced(buf, Duration::from_secs(0))?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: io::Read> io::Read for PacedPort<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl<P: SerialDevice> SerialDevice for PacedPort<P> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, write_settings, read_with_timeout, discard_input);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::time::{Duration,Instant};
+
+    use super::{PacedPort,Pacing};
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn paced_port_writes_bytes_separately() {
+        let pacing = Pacing { inter_byte: Duration::from_millis(5), ..Default::default() };
+        let mut port = PacedPort::new(ScriptedDevice::new(), pacing);
+
+        let start = Instant::now();
+        port.write_all(b"abc").unwrap();
+        port.write_all(b"d").unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert_eq!(port.get_ref().events, vec![
+            Event::Write(b"a".to_vec()), Event::Flush,
+            Event::Write(b"b".to_vec()), Event::Flush,
+            Event::Write(b"c".to_vec()), Event::Flush,
+            Event::Write(b"d".to_vec()), Event::Flush
+        ]);
+    }
+
+    #[test]
+    fn paced_port_writes_delimited_frames_whole() {
+        let pacing = Pacing { inter_frame: Duration::from_millis(10), delimiter: Some(b'\r'), ..Default::default() };
+        let mut port = PacedPort::new(ScriptedDevice::new(), pacing);
+
+        let start = Instant::now();
+        port.write_all(b"one\rtwo\rthr").unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(port.get_ref().events, vec![
+            Event::Write(b"one\r".to_vec()), Event::Flush,
+            Event::Write(b"two\r".to_vec()), Event::Flush,
+            Event::Write(b"thr".to_vec())
+        ]);
+    }
+
+    #[test]
+    fn paced_port_waits_after_written_frame() {
+        let pacing = Pacing { inter_frame: Duration::from_millis(20), ..Default::default() };
+        let mut port = PacedPort::new(ScriptedDevice::new(), pacing);
+
+        let start = Instant::now();
+        port.write_frame(b"one").unwrap();
+        port.write_frame(b"two").unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(port.get_ref().output(), b"onetwo");
+    }
+
+    #[test]
+    fn unpaced_port_writes_in_one_call() {
+        let mut port = PacedPort::new(ScriptedDevice::new(), Pacing::default());
+        port.write_all(b"data").unwrap();
+
+        assert_eq!(port.get_ref().events, vec![Event::Write(b"data".to_vec())]);
+    }
+}
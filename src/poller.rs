@@ -0,0 +1,382 @@
+//! Waiting for input on many ports from a single thread.
+//!
+//! A `Poller` reports which of a set of registered ports have received data, so that a program
+//! serving many ports, such as a gateway for dozens of RS-485 adapters, doesn't need a thread per
+//! port.
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use std::time::Duration;
+//! use serial::poller::{Poller,Token};
+//!
+//! let mut ports = vec![serial::open("/dev/ttyUSB0").unwrap(), serial::open("/dev/ttyUSB1").unwrap()];
+//!
+//! let mut poller = Poller::new();
+//! for (i, port) in ports.iter().enumerate() {
+//!     poller.register(port, Token(i));
+//! }
+//!
+//! let mut buf = [0; 1024];
+//! loop {
+//!     for Token(i) in poller.wait(Duration::from_secs(1)).unwrap() {
+//!         let len = ports[i].read(&mut buf).unwrap();
+//!         println!("port {}: {:?}", i, &buf[..len]);
+//!     }
+//! }
+//! ```
+//!
+//! Applications built around another event source, such as a `crossbeam-channel` `select!` loop,
+//! can hand the poller to a background thread with `Poller::watch()`. The thread calls a function
+//! when a port becomes readable, which typically sends the port's token on a channel that the
+//! event loop selects on:
+//!
+//! ```no_run
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//! use serial::poller::{Poller,Token};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let mut poller = Poller::new();
+//! poller.register(&port, Token(0));
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let watcher = poller.watch(Duration::from_millis(10), move |token| { let _ = sender.send(token); });
+//!
+//! for token in receiver.iter() {
+//!     // read from the port until it would block, then
+//!     watcher.rearm(token);
+//! }
+//! ```
+//!
+//! On POSIX systems, ports are waited on with `poll()`. On Windows, the receive queue of each port
+//! is checked with `ClearCommError()` until one of them has data, since waiting on the handles
+//! directly would interfere with reads on ports that aren't opened for overlapped I/O.
+
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::shutdown;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd,RawFd};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle,RawHandle};
+
+#[cfg(unix)]
+type Source = RawFd;
+
+#[cfg(windows)]
+type Source = RawHandle;
+
+/// Identifies a port registered with a `Poller`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Token(pub usize);
+
+/// Waits for input on multiple ports at once.
+///
+/// The poller refers to ports by their underlying file descriptor or handle, so a port must stay
+/// open for as long as it's registered. Deregister a port before closing it.
+#[derive(Debug,Default)]
+pub struct Poller {
+    entries: Vec<(Token, Source)>
+}
+
+// A handle is only used to identify the port, so it may be sent to a watcher thread.
+#[cfg(windows)]
+unsafe impl Send for Poller {}
+
+impl Poller {
+    /// Creates a poller with no registered ports.
+    pub fn new() -> Self {
+        Poller { entries: Vec::new() }
+    }
+
+    /// Registers a port to be reported by `token` when it's readable.
+    ///
+    /// Registering a token that's already registered replaces the port it refers to.
+    #[cfg(unix)]
+    pub fn register<T: AsRawFd>(&mut self, port: &T, token: Token) {
+        self.insert(token, port.as_raw_fd());
+    }
+
+    /// Registers a port to be reported by `token` when it's readable.
+    ///
+    /// Registering a token that's already registered replaces the port it refers to.
+    #[cfg(windows)]
+    pub fn register<T: AsRawHandle>(&mut self, port: &T, token: Token) {
+        self.insert(token, port.as_raw_handle());
+    }
+
+    /// Removes the port registered with `token`.
+    ///
+    /// Returns `false` if no port was registered with `token`.
+    pub fn deregister(&mut self, token: Token) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|&(t, _)| t != token);
+        self.entries.len() != len
+    }
+
+    /// Returns the number of registered ports.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no ports are registered.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Waits until at least one registered port is readable or the timeout expires.
+    ///
+    /// Returns the tokens of the readable ports in the order they were registered, or an empty
+    /// vector if the timeout expired. A port that has been disconnected or has an error is also
+    /// reported as readable, so that the error is returned by the next read.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io` if waiting fails.
+    pub fn wait(&mut self, timeout: Duration) -> ::Result<Vec<Token>> {
+        match self.wait_ready(timeout) {
+            Ok(ready) => {
+                Ok(self.entries.iter().zip(ready).filter(|&(_, ready)| ready).map(|(&(token, _), _)| token).collect())
+            },
+            Err(err) => Err(::Error::from(err).with_operation("poll ports"))
+        }
+    }
+
+    /// Moves the poller to a background thread that calls `notify` when a port becomes readable.
+    ///
+    /// After a port has been reported, it isn't reported again until it's rearmed with
+    /// `Watcher::rearm()`, which should be called once the data has been read. `poll_interval`
+    /// bounds the time taken to notice that a port was rearmed or that the watcher was closed.
+    pub fn watch<F>(self, poll_interval: Duration, notify: F) -> Watcher
+        where F: FnMut(Token) + Send + 'static
+    {
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            disarmed: Mutex::new(Vec::new())
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || watch(self, &shared, poll_interval, notify))
+        };
+
+        Watcher { shared, thread: Some(thread) }
+    }
+
+    fn insert(&mut self, token: Token, source: Source) {
+        match self.entries.iter_mut().find(|entry| entry.0 == token) {
+            Some(entry) => entry.1 = source,
+            None => self.entries.push((token, source))
+        }
+    }
+
+    #[cfg(unix)]
+    fn wait_ready(&self, timeout: Duration) -> ::std::io::Result<Vec<bool>> {
+        let fds: Vec<RawFd> = self.entries.iter().map(|&(_, fd)| fd).collect();
+        ::posix::poll::wait_read_fds(&fds, timeout)
+    }
+
+    #[cfg(windows)]
+    fn wait_ready(&self, timeout: Duration) -> ::std::io::Result<Vec<bool>> {
+        use std::ptr;
+        use std::thread;
+        use std::time::Instant;
+
+        use ::windows::ffi::{ClearCommError,COMSTAT};
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let ready: Vec<bool> = self.entries.iter().map(|&(_, handle)| {
+                let mut stat = COMSTAT { fBits: 0, cbInQue: 0, cbOutQue: 0 };
+
+                // a failure means the port has an error, which the next read will report
+                unsafe { ClearCommError(handle as _, ptr::null_mut(), &mut stat) == 0 || stat.cbInQue > 0 }
+            }).collect();
+
+            if ready.iter().any(|&ready| ready) || Instant::now() >= deadline {
+                return Ok(ready);
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// A handle to a thread that watches a poller's ports, created by `Poller::watch()`.
+///
+/// The thread is stopped when the watcher is closed or dropped.
+pub struct Watcher {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<::Result<Poller>>>
+}
+
+struct Shared {
+    stop: AtomicBool,
+    disarmed: Mutex<Vec<Token>>
+}
+
+impl Watcher {
+    /// Reports the port registered with `token` again the next time it's readable.
+    pub fn rearm(&self, token: Token) {
+        self.shared.disarmed.lock().unwrap().retain(|&t| t != token);
+    }
+
+    /// Stops the thread and returns the poller.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if waiting on the ports failed.
+    pub fn close(mut self) -> ::Result<Poller> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "watcher thread").and_then(|result| result)
+    }
+
+    /// Stops the thread like `close()`, waiting at most `timeout` for it to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the thread didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<Poller> {
+        let deadline = Instant::now() + timeout;
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "watcher thread").and_then(|result| result)
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn watch<F: FnMut(Token)>(poller: Poller, shared: &Shared, poll_interval: Duration, mut notify: F) -> ::Result<Poller> {
+    while !shared.stop.load(Ordering::Relaxed) {
+        let armed = {
+            let disarmed = shared.disarmed.lock().unwrap();
+            Poller { entries: poller.entries.iter().filter(|&&(token, _)| !disarmed.contains(&token)).cloned().collect() }
+        };
+
+        if armed.is_empty() {
+            thread::sleep(poll_interval);
+            continue;
+        }
+
+        let ready = armed.wait_ready(poll_interval).map_err(|err| ::Error::from(err).with_operation("poll ports"))?;
+
+        for (&(token, _), ready) in armed.entries.iter().zip(ready) {
+            if ready {
+                shared.disarmed.lock().unwrap().push(token);
+                notify(token);
+            }
+        }
+    }
+
+    Ok(poller)
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::prelude::*;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc;
+    use std::time::{Duration,Instant};
+
+    use super::{Poller,Token};
+
+    #[test]
+    fn wait_reports_readable_ports() {
+        let (mut a, a_peer) = UnixStream::pair().unwrap();
+        let (mut b, b_peer) = UnixStream::pair().unwrap();
+        let (mut c, c_peer) = UnixStream::pair().unwrap();
+
+        let mut poller = Poller::new();
+        poller.register(&a_peer, Token(0));
+        poller.register(&b_peer, Token(1));
+        poller.register(&c_peer, Token(2));
+
+        a.write_all(b"a").unwrap();
+        c.write_all(b"c").unwrap();
+
+        assert_eq!(poller.wait(Duration::from_secs(1)).unwrap(), vec![Token(0), Token(2)]);
+
+        poller.deregister(Token(2));
+        b.write_all(b"b").unwrap();
+
+        assert_eq!(poller.wait(Duration::from_secs(1)).unwrap(), vec![Token(0), Token(1)]);
+    }
+
+    #[test]
+    fn wait_returns_nothing_on_timeout() {
+        let (_a, a_peer) = UnixStream::pair().unwrap();
+
+        let mut poller = Poller::new();
+        poller.register(&a_peer, Token(7));
+
+        let start = Instant::now();
+        assert_eq!(poller.wait(Duration::from_millis(50)).unwrap(), vec![]);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn wait_reports_hangup_as_readable() {
+        let (a, a_peer) = UnixStream::pair().unwrap();
+
+        let mut poller = Poller::new();
+        poller.register(&a_peer, Token(3));
+        drop(a);
+
+        assert_eq!(poller.wait(Duration::from_secs(1)).unwrap(), vec![Token(3)]);
+    }
+
+    #[test]
+    fn watcher_notifies_once_until_rearmed() {
+        let (mut a, mut a_peer) = UnixStream::pair().unwrap();
+
+        let mut poller = Poller::new();
+        poller.register(&a_peer, Token(5));
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = poller.watch(Duration::from_millis(5), move |token| { let _ = sender.send(token); });
+
+        a.write_all(b"a").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), Token(5));
+
+        // still readable, but not reported again until rearmed
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+
+        let mut buf = [0; 1];
+        a_peer.read_exact(&mut buf).unwrap();
+        a.write_all(b"b").unwrap();
+        watcher.rearm(Token(5));
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), Token(5));
+        assert_eq!(watcher.close().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn register_replaces_existing_token() {
+        let (_a, a_peer) = UnixStream::pair().unwrap();
+        let (mut b, b_peer) = UnixStream::pair().unwrap();
+
+        let mut poller = Poller::new();
+        poller.register(&a_peer, Token(0));
+        poller.register(&b_peer, Token(0));
+        b.write_all(b"b").unwrap();
+
+        assert_eq!(poller.len(), 1);
+        assert_eq!(poller.wait(Duration::from_secs(1)).unwrap(), vec![Token(0)]);
+        assert!(poller.deregister(Token(0)));
+        assert!(!poller.deregister(Token(0)));
+    }
+}
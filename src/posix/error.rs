@@ -1,6 +1,5 @@
 extern crate libc;
 
-use std::error::Error;
 use std::ffi::CStr;
 use std::io;
 use std::str;
@@ -12,31 +11,41 @@ pub fn last_os_error() -> ::Error {
 }
 
 pub fn from_raw_os_error(errno: i32) -> ::Error {
-    use self::libc::{EBUSY,EISDIR,ELOOP,ENOTDIR,ENOENT,ENODEV,ENXIO,EACCES,EINVAL,ENAMETOOLONG,EINTR,EWOULDBLOCK};
+    use self::libc::{EBUSY,EISDIR,ELOOP,ENOTDIR,ENOENT,ENODEV,ENXIO,EACCES,EPERM,EINVAL,ENAMETOOLONG,EINTR,EPIPE,EWOULDBLOCK};
 
     let kind = match errno {
-        EBUSY | EISDIR | ELOOP | ENOTDIR | ENOENT | ENODEV | ENXIO | EACCES => ::ErrorKind::NoDevice,
+        EISDIR | ELOOP | ENOTDIR | ENOENT | ENODEV | ENXIO => ::ErrorKind::NoDevice,
+        EBUSY => ::ErrorKind::Busy,
+        EACCES | EPERM => ::ErrorKind::PermissionDenied,
         EINVAL | ENAMETOOLONG => ::ErrorKind::InvalidInput,
 
         EINTR => ::ErrorKind::Io(io::ErrorKind::Interrupted),
+        EPIPE => ::ErrorKind::Io(io::ErrorKind::BrokenPipe),
         EWOULDBLOCK => ::ErrorKind::Io(io::ErrorKind::WouldBlock),
         _ => ::ErrorKind::Io(io::ErrorKind::Other)
     };
 
-    ::Error::new(kind, error_string(errno))
+    ::Error::new(kind, error_string(errno)).with_source(io::Error::from_raw_os_error(errno))
 }
 
 pub fn from_io_error(io_error: io::Error) -> ::Error {
     match io_error.raw_os_error() {
         Some(errno) => from_raw_os_error(errno),
         None => {
-            let description = io_error.description().to_string();
+            let description = io_error.to_string();
 
-            ::Error::new(::ErrorKind::Io(io_error.kind()), description)
+            ::Error::new(::ErrorKind::Io(io_error.kind()), description).with_source(io_error)
         }
     }
 }
 
+/// Returns `true` if `errno` indicates that the device was disconnected.
+pub fn is_disconnection(errno: i32) -> bool {
+    use self::libc::{EIO,ENODEV,ENXIO};
+
+    errno == EIO || errno == ENODEV || errno == ENXIO
+}
+
 // the rest of this module is borrowed from libstd
 
 const TMPBUF_SZ: usize = 128;
@@ -46,52 +55,42 @@ pub fn errno() -> i32 {
               target_os = "ios",
               target_os = "freebsd"))]
     unsafe fn errno_location() -> *const c_int {
-        extern { fn __error() -> *const c_int; }
+        extern "C" { fn __error() -> *const c_int; }
         __error()
     }
 
-    #[cfg(target_os = "bitrig")]
-    fn errno_location() -> *const c_int {
-        extern {
-            fn __errno() -> *const c_int;
-        }
-        unsafe {
-            __errno()
-        }
-    }
-
     #[cfg(target_os = "dragonfly")]
     unsafe fn errno_location() -> *const c_int {
-        extern { fn __dfly_error() -> *const c_int; }
+        extern "C" { fn __dfly_error() -> *const c_int; }
         __dfly_error()
     }
 
     #[cfg(target_os = "openbsd")]
     unsafe fn errno_location() -> *const c_int {
-        extern { fn __errno() -> *const c_int; }
+        extern "C" { fn __errno() -> *const c_int; }
         __errno()
     }
 
     #[cfg(any(target_os = "linux", target_os = "android"))]
     unsafe fn errno_location() -> *const c_int {
-        extern { fn __errno_location() -> *const c_int; }
+        extern "C" { fn __errno_location() -> *const c_int; }
         __errno_location()
     }
 
     unsafe {
-        (*errno_location()) as i32
+        *errno_location()
     }
 }
 
 pub fn error_string(errno: i32) -> String {
     #[cfg(target_os = "linux")]
-    extern {
+    extern "C" {
         #[link_name = "__xpg_strerror_r"]
         fn strerror_r(errnum: c_int, buf: *mut c_char,
                       buflen: size_t) -> c_int;
     }
     #[cfg(not(target_os = "linux"))]
-    extern {
+    extern "C" {
         fn strerror_r(errnum: c_int, buf: *mut c_char,
                       buflen: size_t) -> c_int;
     }
@@ -108,3 +107,42 @@ pub fn error_string(errno: i32) -> String {
         str::from_utf8(CStr::from_ptr(p).to_bytes()).unwrap().to_string()
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::libc::{EBUSY,EACCES,EPERM,ENOENT,EINVAL,EIO,EPIPE,ENXIO};
+    use super::from_raw_os_error;
+
+    #[test]
+    fn maps_ebusy_to_busy() {
+        assert_eq!(from_raw_os_error(EBUSY).kind(), ::ErrorKind::Busy);
+    }
+
+    #[test]
+    fn maps_eacces_and_eperm_to_permission_denied() {
+        assert_eq!(from_raw_os_error(EACCES).kind(), ::ErrorKind::PermissionDenied);
+        assert_eq!(from_raw_os_error(EPERM).kind(), ::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn maps_enoent_to_no_device() {
+        assert_eq!(from_raw_os_error(ENOENT).kind(), ::ErrorKind::NoDevice);
+    }
+
+    #[test]
+    fn maps_einval_to_invalid_input() {
+        assert_eq!(from_raw_os_error(EINVAL).kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn maps_epipe_to_broken_pipe() {
+        assert_eq!(from_raw_os_error(EPIPE).kind(), ::ErrorKind::Io(::std::io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn eio_and_enxio_are_disconnections() {
+        assert!(from_raw_os_error(EIO).is_disconnection());
+        assert_eq!(from_raw_os_error(ENXIO).kind(), ::ErrorKind::NoDevice);
+    }
+}
@@ -2,6 +2,6 @@
 
 pub use self::tty::*;
 
-mod error;
-mod poll;
+pub(crate) mod error;
+pub(crate) mod poll;
 mod tty;
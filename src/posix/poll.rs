@@ -3,7 +3,7 @@
 extern crate libc;
 
 use std::io;
-use std::time::Duration;
+use std::time::{Duration,Instant};
 
 use self::libc::{c_int,c_short};
 
@@ -37,23 +37,63 @@ pub fn wait_write_fd(fd: c_int, timeout: Duration) -> io::Result<()> {
     wait_fd(fd, POLLOUT, timeout)
 }
 
+/// Returns `true` if `fd` has been hung up, e.g., because the device was unplugged.
+pub fn is_hung_up(fd: c_int) -> bool {
+    let mut fds = [PollFd { fd, events: 0, revents: 0 }];
+
+    do_poll(&mut fds, Duration::from_secs(0)) > 0 && fds[0].revents & (POLLHUP | POLLNVAL) != 0
+}
+
+/// Waits until at least one of `fds` is readable or the timeout expires.
+///
+/// Returns whether each descriptor is ready. A descriptor is also ready if it has been hung up or
+/// has an error, so that the error is reported by the next read. Returns all `false` on timeout.
+pub fn wait_read_fds(fds: &[c_int], timeout: Duration) -> io::Result<Vec<bool>> {
+    let mut pollfds: Vec<PollFd> = fds.iter().map(|&fd| PollFd { fd, events: POLLIN, revents: 0 }).collect();
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if do_poll(&mut pollfds, remaining) >= 0 {
+            break;
+        }
+
+        let errno = super::error::errno();
+        if errno != libc::EINTR {
+            return Err(io::Error::other(super::error::error_string(errno)));
+        }
+    }
+
+    Ok(pollfds.iter().map(|pollfd| pollfd.revents & (POLLIN | POLLERR | POLLHUP | POLLNVAL) != 0).collect())
+}
+
+/// Waits for `events` on `fd`.
+///
+/// Polling is restarted if it's interrupted by a signal, with the time remaining until the
+/// original deadline.
 fn wait_fd(fd: c_int, events: c_short, timeout: Duration) -> io::Result<()> {
     use self::libc::{EINTR,EPIPE,EIO};
 
-    let mut fds = vec!(PollFd { fd: fd, events: events, revents: 0 });
+    let mut fds = vec!(PollFd { fd, events, revents: 0 });
+    let deadline = Instant::now() + timeout;
 
-    let wait = do_poll(&mut fds, timeout);
+    let wait = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let wait = do_poll(&mut fds, remaining);
 
-    if wait < 0 {
-        let errno = super::error::errno();
+        if wait < 0 {
+            let errno = super::error::errno();
 
-        let kind = match errno {
-            EINTR => io::ErrorKind::Interrupted,
-            _ => io::ErrorKind::Other
-        };
+            if errno == EINTR {
+                continue;
+            }
 
-        return Err(io::Error::new(kind, super::error::error_string(errno)));
-    }
+            return Err(io::Error::other(super::error::error_string(errno)));
+        }
+
+        break wait;
+    };
 
     if wait == 0 {
         return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
@@ -64,15 +104,15 @@ fn wait_fd(fd: c_int, events: c_short, timeout: Duration) -> io::Result<()> {
     }
 
     if fds[0].revents & (POLLHUP | POLLNVAL) != 0 {
-        return Err(io::Error::new(io::ErrorKind::BrokenPipe, super::error::error_string(EPIPE)));
+        return Err(io::Error::from_raw_os_error(EPIPE));
     }
 
-    Err(io::Error::new(io::ErrorKind::Other, super::error::error_string(EIO)))
+    Err(io::Error::from_raw_os_error(EIO))
 }
 
 #[cfg(target_os = "linux")]
 #[inline]
-fn do_poll(fds: &mut Vec<PollFd>, timeout: Duration) -> c_int {
+fn do_poll(fds: &mut [PollFd], timeout: Duration) -> c_int {
     use std::ptr;
 
     use self::libc::{c_void};
@@ -92,7 +132,7 @@ fn do_poll(fds: &mut Vec<PollFd>, timeout: Duration) -> c_int {
     };
 
     unsafe {
-        ppoll((&mut fds[..]).as_mut_ptr(),
+        ppoll(fds.as_mut_ptr(),
               fds.len() as nfds_t,
               &mut timeout_ts,
               ptr::null())
@@ -101,7 +141,7 @@ fn do_poll(fds: &mut Vec<PollFd>, timeout: Duration) -> c_int {
 
 #[cfg(not(target_os = "linux"))]
 #[inline]
-fn do_poll(fds: &mut Vec<PollFd>, timeout: Duration) -> c_int {
+fn do_poll(fds: &mut [PollFd], timeout: Duration) -> c_int {
     extern "C" {
         fn poll(fds: *mut PollFd, nfds: nfds_t, timeout: c_int) -> c_int;
     }
@@ -109,7 +149,7 @@ fn do_poll(fds: &mut Vec<PollFd>, timeout: Duration) -> c_int {
     let milliseconds = timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000;
 
     unsafe {
-        poll((&mut fds[..]).as_mut_ptr(),
+        poll(fds.as_mut_ptr(),
              fds.len() as nfds_t,
              milliseconds as c_int)
     }
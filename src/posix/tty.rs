@@ -2,10 +2,16 @@ extern crate libc;
 extern crate termios;
 extern crate ioctl_rs as ioctl;
 
+use std::cmp;
 use std::ffi::CString;
 use std::io;
+use std::mem;
 use std::path::Path;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration,Instant};
+
+#[cfg(target_os = "linux")]
+use std::time::SystemTime;
 
 use std::os::unix::prelude::*;
 
@@ -13,6 +19,8 @@ use self::libc::{c_int,c_void,size_t};
 
 use ::{SerialDevice,SerialPortSettings};
 
+pub use self::termios::Termios;
+
 
 #[cfg(target_os = "linux")]
 const O_NOCTTY: c_int = 0x00000100;
@@ -23,13 +31,41 @@ const O_NOCTTY: c_int = 0x00020000;
 #[cfg(not(any(target_os = "linux", target_os = "macos")))]
 const O_NOCTTY: c_int = 0;
 
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+use self::termios::os::target::{CCTS_OFLOW,CRTS_IFLOW};
+
+// Linux only has one flag for hardware flow control in both directions
+#[cfg(not(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd")))]
+const CCTS_OFLOW: termios::tcflag_t = termios::os::target::CRTSCTS;
+
+#[cfg(not(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd")))]
+const CRTS_IFLOW: termios::tcflag_t = termios::os::target::CRTSCTS;
+
+#[cfg(target_os = "linux")]
+const VDISABLE: termios::cc_t = 0;
+
+#[cfg(not(target_os = "linux"))]
+const VDISABLE: termios::cc_t = 0xff;
+
+/// The maximum number of bytes passed to each `write()` call.
+///
+/// The file descriptor is in blocking mode, so a write blocks until the driver has accepted the
+/// whole chunk. A TTY is reported as writable once its driver's transmit buffer is nearly empty,
+/// and a chunk this size fits in the transmit buffers of common drivers, so that writing it
+/// doesn't block past the port's timeout.
+const MAX_WRITE_CHUNK: usize = 2048;
+
 
 /// A TTY-based serial port implementation.
 ///
 /// The port will be closed when the value is dropped.
 pub struct TTYPort {
     fd: RawFd,
-    timeout: Duration
+    timeout: Duration,
+    device: String,
+    original: Option<::Snapshot<TTYSettings>>,
+    batch_size: usize,
+    batch_delay: Duration
 }
 
 impl TTYPort {
@@ -45,45 +81,286 @@ impl TTYPort {
     ///
     /// ## Errors
     ///
-    /// * `NoDevice` if the device could not be opened. This could indicate that the device is
-    ///   already in use.
+    /// * `NoDevice` if the device doesn't exist or could not be opened.
+    /// * `Busy` if the device is already in use by another process.
+    /// * `PermissionDenied` if the process lacks permission to open the device.
     /// * `InvalidInput` if `port` is not a valid device name.
     /// * `Io` for any other error while opening or initializing the device.
     pub fn open(path: &Path) -> ::Result<Self> {
-        use self::libc::{O_RDWR,O_NONBLOCK,F_SETFL,EINVAL};
+        TTYPort::open_with_options(path, &::OpenOptions::new())
+    }
+
+    /// Opens a TTY device as a serial port with the given options.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// let mut options = serial::OpenOptions::new();
+    /// options.dtr(false).rts(false);
+    ///
+    /// serial::posix::TTYPort::open_with_options(Path::new("/dev/ttyACM0"), &options).unwrap();
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `open()`. Failing to set the initial state of a
+    /// control signal is reported as an error of kind `Io`.
+    pub fn open_with_options(path: &Path, options: &::OpenOptions) -> ::Result<Self> {
+        use self::libc::{O_RDWR,O_RDONLY,O_WRONLY,O_NONBLOCK,O_CLOEXEC,F_SETFL,EINVAL};
+
+        let device = path.display().to_string();
+        let context = |err: ::Error| err.with_device(device.clone()).with_operation("open");
 
         let cstr = match CString::new(path.as_os_str().as_bytes()) {
             Ok(s) => s,
-            Err(_) => return Err(super::error::from_raw_os_error(EINVAL))
+            Err(_) => return Err(context(super::error::from_raw_os_error(EINVAL)))
+        };
+
+        let mut flags = match options.access_mode() {
+            ::Access::ReadWrite => O_RDWR,
+            ::Access::ReadOnly => O_RDONLY,
+            ::Access::WriteOnly => O_WRONLY
         };
 
-        let fd = unsafe { libc::open(cstr.as_ptr(), O_RDWR | O_NOCTTY | O_NONBLOCK, 0) };
+        flags |= O_NONBLOCK;
+
+        if !options.acquires_controlling_terminal() {
+            flags |= O_NOCTTY;
+        }
+
+        if options.closes_on_exec() {
+            flags |= O_CLOEXEC;
+        }
+
+        let fd = unsafe { libc::open(cstr.as_ptr(), flags, 0) };
         if fd < 0 {
-            return Err(super::error::last_os_error());
+            return Err(context(super::error::last_os_error()));
         }
 
         let mut port = TTYPort {
-            fd: fd,
-            timeout: Duration::from_millis(100)
+            fd,
+            timeout: Duration::from_millis(100),
+            device: device.clone(),
+            original: None,
+            batch_size: 0,
+            batch_delay: Duration::from_secs(0)
         };
 
         // get exclusive access to device
         if let Err(err) = ioctl::tiocexcl(port.fd) {
-            return Err(super::error::from_io_error(err))
+            return Err(context(super::error::from_io_error(err)))
         }
 
         // clear O_NONBLOCK flag
         if unsafe { libc::fcntl(port.fd, F_SETFL, 0) } < 0 {
-            return Err(super::error::last_os_error());
+            return Err(context(super::error::last_os_error()));
+        }
+
+        if options.restores_on_close() {
+            port.original = Some(port.snapshot().map_err(&context)?);
         }
 
-        // apply initial settings
-        let settings = try!(port.read_settings());
-        try!(port.write_settings(&settings));
+        let mut settings = port.read_settings().map_err(&context)?;
+
+        if !options.preserves_settings() || options.hangs_up_on_close().is_some() {
+            // setup TTY for binary serial port access
+            if !options.preserves_settings() {
+                settings.set_raw();
+            }
+
+            if let Some(hang_up_on_close) = options.hangs_up_on_close() {
+                settings.set_hang_up_on_close(hang_up_on_close);
+            }
+
+            port.write_settings(&settings).map_err(&context)?;
+        }
+
+        if let Some(level) = options.dtr_level() {
+            port.set_dtr(level).map_err(&context)?;
+        }
+
+        if let Some(level) = options.rts_level() {
+            port.set_rts(level).map_err(&context)?;
+        }
+
+        if options.waits_for_carrier() {
+            settings.set_modem_control(true);
+            port.write_settings(&settings).map_err(&context)?;
+
+            while !port.read_cd().map_err(&context)? {
+                port.wait_signal_change(&[::ModemSignal::Cd]).map_err(&context)?;
+            }
+        }
 
         Ok(port)
     }
 
+    /// Closes the port.
+    ///
+    /// If the port was opened with `OpenOptions::restore_on_close()`, its original settings,
+    /// timeout, and control signals are restored first. Dropping the port does the same, but
+    /// ignores errors, so a tool that must not leave a console misconfigured should call `close()`
+    /// and report its result.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the original state could not be restored:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn close(mut self) -> ::Result<()> {
+        match self.original.take() {
+            Some(original) => self.restore(&original).map_err(|err| err.with_operation("close")),
+            None => Ok(())
+        }
+    }
+
+    /// Returns the number of bytes that have been received but not yet read.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn bytes_to_read(&self) -> ::Result<usize> {
+        let mut available: c_int = 0;
+
+        if unsafe { libc::ioctl(self.fd, libc::FIONREAD, &mut available) } < 0 {
+            return Err(self.error(::disconnected(super::error::last_os_error()), "read input queue"));
+        }
+
+        Ok(available as usize)
+    }
+
+    /// Batches reads of high-rate streams into fewer system calls.
+    ///
+    /// A read normally returns as soon as any data has been received, so a reader that keeps up
+    /// with a fast stream consumes it a few bytes per system call. With batching, a read that
+    /// finds fewer than `size` bytes waiting (or fewer than fit in the read's buffer) first sleeps
+    /// for `delay` so that more data can accumulate. At 3 Mbaud, a delay of one millisecond
+    /// collects up to 300 bytes per read.
+    ///
+    /// This trades latency for CPU time: a read of a lone byte can be delayed by `delay`. A `size`
+    /// of 0 or 1 disables batching, which is the default.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let mut port = serial::posix::TTYPort::open(Path::new("/dev/ttyUSB0")).unwrap();
+    /// port.set_read_batching(4096, Duration::from_millis(1));
+    /// ```
+    pub fn set_read_batching(&mut self, size: usize, delay: Duration) {
+        self.batch_size = size;
+        self.batch_delay = delay;
+    }
+
+    /// Waits until the port is readable or the timeout expires.
+    ///
+    /// Returns `false` if the timeout expired. Waiting doesn't consume any data, so it can be
+    /// abandoned at any point, e.g., by an event loop that's waiting on something else as well.
+    /// Together with `try_read()`, this is the building block for reads that can be cancelled
+    /// without losing data:
+    ///
+    /// ```no_run
+    /// use std::io;
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let port = serial::posix::TTYPort::open(Path::new("/dev/ttyUSB0")).unwrap();
+    /// let mut buf = [0; 1024];
+    ///
+    /// while port.readable(Duration::from_secs(1)).unwrap() {
+    ///     match port.try_read(&mut buf) {
+    ///         Ok(len) => println!("{:?}", &buf[..len]),
+    ///         Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+    ///         Err(err) => panic!("{}", err)
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// A port that has been disconnected is reported as readable, so that the error is returned
+    /// by the next read.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io` if waiting fails.
+    pub fn readable(&self, timeout: Duration) -> ::Result<bool> {
+        match super::poll::wait_read_fd(self.fd, timeout) {
+            Ok(()) => Ok(true),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => Ok(false),
+            // a hang-up or error condition on the port, which the next read will report
+            Err(ref err) if err.raw_os_error().is_some() => Ok(true),
+            Err(err) => Err(self.error(super::error::from_io_error(err), "wait for input"))
+        }
+    }
+
+    /// Reads data that has already been received, without waiting.
+    ///
+    /// Data is only consumed when it's returned, so abandoning a caller that was about to call
+    /// `try_read()` doesn't lose data. Read batching isn't applied.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(WouldBlock)` if no data is available.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match super::poll::wait_read_fd(self.fd, Duration::from_secs(0)) {
+            Ok(()) => self.read_ready(buf),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+            Err(err) => Err(self.io_error(err, "read"))
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if let Err(err) = super::poll::wait_read_fd(self.fd, timeout) {
+            return Err(self.io_error(err, "read"));
+        }
+
+        let batch_size = cmp::min(self.batch_size, buf.len());
+
+        if batch_size > 1 && self.bytes_to_read().is_ok_and(|available| available < batch_size) {
+            thread::sleep(self.batch_delay);
+        }
+
+        self.read_ready(buf)
+    }
+
+    /// Reads from the port once `poll()` has reported it readable.
+    fn read_ready(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = unsafe { libc::read(self.fd, buf.as_ptr() as *mut c_void, buf.len() as size_t) };
+
+            // A TTY that has been hung up reads as end of file, which would otherwise look like a
+            // read that timed out with no data.
+            if len == 0 && !buf.is_empty() && super::poll::is_hung_up(self.fd) {
+                return Err(self.io_error(io::Error::from_raw_os_error(libc::EIO), "read"));
+            }
+
+            if len >= 0 {
+                return Ok(len as usize);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(self.io_error(err, "read"));
+            }
+        }
+    }
+
+    /// Attaches the device name and failed operation to an error.
+    fn error(&self, err: ::Error, operation: &'static str) -> ::Error {
+        err.with_device(self.device.clone()).with_operation(operation)
+    }
+
+    /// Attaches the device name and failed operation to an I/O error.
+    ///
+    /// Errors that indicate the device was unplugged, such as `EIO` or `ENXIO`, are reported as
+    /// `NoDevice`.
+    fn io_error(&self, err: io::Error, operation: &'static str) -> io::Error {
+        self.error(::disconnected(super::error::from_io_error(err)), operation).into()
+    }
+
     fn set_pin(&mut self, pin: c_int, level: bool) -> ::Result<()> {
         let retval = if level {
             ioctl::tiocmbis(self.fd, pin)
@@ -94,157 +371,910 @@ impl TTYPort {
 
         match retval {
             Ok(()) => Ok(()),
-            Err(err) => Err(super::error::from_io_error(err))
+            Err(err) => Err(self.error(super::error::from_io_error(err), "set control signal"))
         }
     }
 
     fn read_pin(&mut self, pin: c_int) -> ::Result<bool> {
         match ioctl::tiocmget(self.fd) {
             Ok(pins) => Ok(pins & pin != 0),
-            Err(err) => Err(super::error::from_io_error(err))
+            Err(err) => Err(self.error(super::error::from_io_error(err), "read control signal"))
         }
     }
-}
-
-impl Drop for TTYPort {
-    fn drop(&mut self) {
-        #![allow(unused_must_use)]
-        ioctl::tiocnxcl(self.fd);
 
-        unsafe {
-            libc::close(self.fd);
+    /// Reads an output signal, which is unsupported by devices without modem control lines, such
+    /// as pseudo-terminals.
+    fn read_output_pin(&mut self, pin: c_int) -> ::Result<bool> {
+        match ioctl::tiocmget(self.fd) {
+            Ok(pins) => Ok(pins & pin != 0),
+            Err(ref err) if err.raw_os_error() == Some(libc::ENOTTY) => {
+                Err(self.error(::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), "device has no modem control lines"), "read control signal"))
+            },
+            Err(err) => Err(self.error(super::error::from_io_error(err), "read control signal"))
         }
     }
 }
 
-impl AsRawFd for TTYPort {
-    fn as_raw_fd(&self) -> RawFd {
-        self.fd
-    }
-}
+/// A timestamped edge of a modem status signal.
+#[cfg(target_os = "linux")]
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct SignalEdge {
+    /// The signal that changed.
+    pub signal: ::ModemSignal,
 
-impl io::Read for TTYPort {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        try!(super::poll::wait_read_fd(self.fd, self.timeout));
+    /// The level of the signal after the edge.
+    pub level: bool,
 
-        let len = unsafe { libc::read(self.fd, buf.as_ptr() as *mut c_void, buf.len() as size_t) };
+    /// The system time at which the edge was detected.
+    pub timestamp: SystemTime
+}
 
-        if len >= 0 {
-            Ok(len as usize)
+#[cfg(target_os = "linux")]
+impl TTYPort {
+    /// Waits for an edge on a modem status signal and timestamps it.
+    ///
+    /// This can be used to capture the pulse-per-second (PPS) output of a GPS receiver wired to the
+    /// CD or CTS line for time synchronization. The edge is timestamped with the system clock as
+    /// soon as the kernel wakes the calling thread, so the timestamp includes scheduling latency.
+    /// Where a PPS device (`/dev/ppsN`) is attached to the port with the kernel's PPS line
+    /// discipline, it provides more precise timestamps and should be preferred.
+    ///
+    /// This function blocks until the signal changes. It doesn't observe the port's timeout.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// let port = serial::posix::TTYPort::open(Path::new("/dev/ttyS0")).unwrap();
+    ///
+    /// loop {
+    ///     let edge = port.wait_edge(serial::ModemSignal::Cd).unwrap();
+    ///
+    ///     if edge.level {
+    ///         println!("pulse at {:?}", edge.timestamp);
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if waiting for the signal failed:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error, including if the device doesn't support waiting for
+    ///   modem status signals, as is the case for pseudo-terminals.
+    pub fn wait_edge(&self, signal: ::ModemSignal) -> ::Result<SignalEdge> {
+        let pin = signal_pin(signal);
+
+        // a signal handler interrupts the wait with EINTR even if the edge hasn't occurred
+        while unsafe { libc::ioctl(self.fd, libc::TIOCMIWAIT, pin as libc::c_ulong) } < 0 {
+            if super::error::errno() != libc::EINTR {
+                return Err(self.error(super::error::last_os_error(), "wait for signal edge"));
+            }
         }
-        else {
-            Err(io::Error::last_os_error())
+
+        let timestamp = SystemTime::now();
+
+        match ioctl::tiocmget(self.fd) {
+            Ok(pins) => Ok(SignalEdge { signal, level: pins & pin != 0, timestamp }),
+            Err(err) => Err(self.error(super::error::from_io_error(err), "wait for signal edge"))
         }
     }
-}
 
-impl io::Write for TTYPort {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        try!(super::poll::wait_write_fd(self.fd, self.timeout));
+    /// Reads the kernel's counts of modem status signal changes.
+    fn signal_counts(&self) -> ::Result<SignalCounts> {
+        let mut counts = SignalCounts::default();
 
-        let len = unsafe { libc::write(self.fd, buf.as_ptr() as *mut c_void, buf.len() as size_t) };
-
-        if len >= 0 {
-            Ok(len as usize)
+        if unsafe { libc::ioctl(self.fd, libc::TIOCGICOUNT, &mut counts as *mut SignalCounts) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "wait for signal change"));
         }
-        else {
-            Err(io::Error::last_os_error())
+
+        Ok(counts)
+    }
+
+    /// Returns the kernel's RS-485 mode for the port, or `None` if it's disabled.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error, including if the driver doesn't support RS-485 mode.
+    pub fn rs485_mode(&self) -> ::Result<Option<Rs485Mode>> {
+        let mut raw = SerialRs485::default();
+
+        if unsafe { libc::ioctl(self.fd, libc::TIOCGRS485, &mut raw as *mut SerialRs485) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "read RS-485 mode"));
         }
+
+        Ok(Rs485Mode::from_raw(&raw))
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        termios::tcdrain(self.fd)
+    /// Enables the kernel's RS-485 mode for the port, or disables it with `None`.
+    ///
+    /// In RS-485 mode, the UART driver toggles RTS to control the transceiver's direction and
+    /// applies the turnaround delays itself, which is more precise than toggling RTS from user
+    /// space. Only some UART drivers support it.
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    /// use serial::posix::{Rs485Mode,TTYPort};
+    ///
+    /// let mut port = TTYPort::open(Path::new("/dev/ttyS1")).unwrap();
+    ///
+    /// let mode = Rs485Mode { delay_rts_after_send: Duration::from_millis(2), ..Default::default() };
+    /// port.set_rs485_mode(Some(&mode)).unwrap();
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error, including if the driver doesn't support RS-485 mode.
+    pub fn set_rs485_mode(&mut self, mode: Option<&Rs485Mode>) -> ::Result<()> {
+        let mut raw = match mode {
+            Some(mode) => mode.to_raw(),
+            None => SerialRs485::default()
+        };
+
+        if unsafe { libc::ioctl(self.fd, libc::TIOCSRS485, &mut raw as *mut SerialRs485) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "write RS-485 mode"));
+        }
+
+        Ok(())
     }
 }
 
-impl SerialDevice for TTYPort {
-    type Settings = TTYSettings;
+/// Configuration of the kernel's RS-485 mode on Linux.
+///
+/// The kernel applies the turnaround delays with millisecond resolution, so delays are rounded up
+/// to whole milliseconds. For finer delays, use the software-emulated delays of
+/// `rs485::HalfDuplex`.
+#[cfg(target_os = "linux")]
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub struct Rs485Mode {
+    /// Level of RTS while transmitting. `true` asserts RTS.
+    pub rts_on_send: bool,
 
-    fn read_settings(&self) -> ::Result<TTYSettings> {
-        use self::termios::{CREAD,CLOCAL}; // cflags
-        use self::termios::{ICANON,ECHO,ECHOE,ECHOK,ECHONL,ISIG,IEXTEN}; // lflags
-        use self::termios::{OPOST}; // oflags
-        use self::termios::{INLCR,IGNCR,ICRNL,IGNBRK}; // iflags
-        use self::termios::{VMIN,VTIME}; // c_cc indexes
+    /// Level of RTS after transmitting. `true` asserts RTS.
+    pub rts_after_send: bool,
 
-        let mut termios = match termios::Termios::from_fd(self.fd) {
-            Ok(t) => t,
-            Err(e) => return Err(super::error::from_io_error(e))
-        };
+    /// Whether the receiver stays enabled while transmitting, so that the transmitted data is
+    /// echoed back.
+    pub rx_during_tx: bool,
 
-        // setup TTY for binary serial port access
-        termios.c_cflag |= CREAD | CLOCAL;
-        termios.c_lflag &= !(ICANON | ECHO | ECHOE | ECHOK | ECHONL | ISIG | IEXTEN);
-        termios.c_oflag &= !OPOST;
-        termios.c_iflag &= !(INLCR | IGNCR | ICRNL | IGNBRK);
+    /// Delay between setting RTS and starting to transmit (`delay_rts_before_send`).
+    pub delay_rts_before_send: Duration,
 
-        termios.c_cc[VMIN] = 0;
-        termios.c_cc[VTIME] = 0;
+    /// Delay between the end of transmission and resetting RTS (`delay_rts_after_send`).
+    pub delay_rts_after_send: Duration
+}
 
-        Ok(TTYSettings::new(termios))
+#[cfg(target_os = "linux")]
+impl Default for Rs485Mode {
+    fn default() -> Self {
+        Rs485Mode {
+            rts_on_send: true,
+            rts_after_send: false,
+            rx_during_tx: false,
+            delay_rts_before_send: Duration::from_secs(0),
+            delay_rts_after_send: Duration::from_secs(0)
+        }
     }
+}
 
-    fn write_settings(&mut self, settings: &TTYSettings) -> ::Result<()> {
-        use self::termios::{tcsetattr,tcflush};
-        use self::termios::{TCSANOW,TCIOFLUSH};
+#[cfg(target_os = "linux")]
+const SER_RS485_ENABLED: u32 = 1 << 0;
 
-        // write settings to TTY
-        if let Err(err) = tcsetattr(self.fd, TCSANOW, &settings.termios) {
-            return Err(super::error::from_io_error(err));
-        }
+#[cfg(target_os = "linux")]
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
 
-        if let Err(err) = tcflush(self.fd, TCIOFLUSH) {
-            return Err(super::error::from_io_error(err));
+#[cfg(target_os = "linux")]
+const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+
+#[cfg(target_os = "linux")]
+const SER_RS485_RX_DURING_TX: u32 = 1 << 4;
+
+#[cfg(target_os = "linux")]
+impl Rs485Mode {
+    fn from_raw(raw: &SerialRs485) -> Option<Self> {
+        if raw.flags & SER_RS485_ENABLED == 0 {
+            return None;
         }
 
-        Ok(())
+        Some(Rs485Mode {
+            rts_on_send: raw.flags & SER_RS485_RTS_ON_SEND != 0,
+            rts_after_send: raw.flags & SER_RS485_RTS_AFTER_SEND != 0,
+            rx_during_tx: raw.flags & SER_RS485_RX_DURING_TX != 0,
+            delay_rts_before_send: Duration::from_millis(raw.delay_rts_before_send as u64),
+            delay_rts_after_send: Duration::from_millis(raw.delay_rts_after_send as u64)
+        })
     }
 
-    fn timeout(&self) -> Duration {
-        self.timeout
-    }
+    fn to_raw(self) -> SerialRs485 {
+        let mut flags = SER_RS485_ENABLED;
 
-    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
-        self.timeout = timeout;
-        Ok(())
-    }
+        if self.rts_on_send {
+            flags |= SER_RS485_RTS_ON_SEND;
+        }
 
-    fn set_rts(&mut self, level: bool) -> ::Result<()> {
-        self.set_pin(ioctl::TIOCM_RTS, level)
-    }
+        if self.rts_after_send {
+            flags |= SER_RS485_RTS_AFTER_SEND;
+        }
 
-    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
-        self.set_pin(ioctl::TIOCM_DTR, level)
-    }
+        if self.rx_during_tx {
+            flags |= SER_RS485_RX_DURING_TX;
+        }
 
-    fn read_cts(&mut self) -> ::Result<bool> {
-        self.read_pin(ioctl::TIOCM_CTS)
-    }
+        let millis = |delay: Duration| ::std::cmp::min(delay.as_micros().div_ceil(1000), u32::MAX as u128) as u32;
 
-    fn read_dsr(&mut self) -> ::Result<bool> {
-        self.read_pin(ioctl::TIOCM_DSR)
+        SerialRs485 {
+            flags,
+            delay_rts_before_send: millis(self.delay_rts_before_send),
+            delay_rts_after_send: millis(self.delay_rts_after_send),
+            padding: [0; 5]
+        }
     }
+}
 
-    fn read_ri(&mut self) -> ::Result<bool> {
-        self.read_pin(ioctl::TIOCM_RI)
-    }
+/// RS-485 configuration used by `TIOCGRS485` and `TIOCSRS485` (`struct serial_rs485`).
+#[cfg(target_os = "linux")]
+#[derive(Debug,Default,Copy,Clone,PartialEq,Eq)]
+#[repr(C)]
+struct SerialRs485 {
+    flags: u32,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    padding: [u32; 5]
+}
 
-    fn read_cd(&mut self) -> ::Result<bool> {
-        self.read_pin(ioctl::TIOCM_CD)
+#[cfg(target_os = "linux")]
+fn signal_pin(signal: ::ModemSignal) -> c_int {
+    match signal {
+        ::ModemSignal::Cts => ioctl::TIOCM_CTS,
+        ::ModemSignal::Dsr => ioctl::TIOCM_DSR,
+        ::ModemSignal::Ri => ioctl::TIOCM_RI,
+        ::ModemSignal::Cd => ioctl::TIOCM_CD
     }
 }
 
-/// Serial port settings for TTY devices.
+#[cfg(target_os = "linux")]
+const ASYNC_LOW_LATENCY: c_int = 1 << 13;
+
+/// Driver configuration used by `TIOCGSERIAL` and `TIOCSSERIAL` (`struct serial_struct`).
+#[cfg(target_os = "linux")]
 #[derive(Debug,Copy,Clone)]
-pub struct TTYSettings {
-    termios: termios::Termios
+#[repr(C)]
+struct SerialStruct {
+    kind: c_int,
+    line: c_int,
+    port: libc::c_uint,
+    irq: c_int,
+    flags: c_int,
+    xmit_fifo_size: c_int,
+    custom_divisor: c_int,
+    baud_base: c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: [libc::c_char; 1],
+    hub6: c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong
 }
 
-impl TTYSettings {
-    fn new(termios: termios::Termios) -> Self {
-        TTYSettings {
-            termios: termios
-        }
+#[cfg(target_os = "linux")]
+impl Default for SerialStruct {
+    fn default() -> Self {
+        unsafe { mem::zeroed() }
+    }
+}
+
+/// Interrupt counters reported by `TIOCGICOUNT` (`struct serial_icounter_struct`).
+#[cfg(target_os = "linux")]
+#[derive(Debug,Default,Copy,Clone,PartialEq,Eq)]
+#[repr(C)]
+struct SignalCounts {
+    cts: c_int,
+    dsr: c_int,
+    rng: c_int,
+    dcd: c_int,
+    rx: c_int,
+    tx: c_int,
+    frame: c_int,
+    overrun: c_int,
+    parity: c_int,
+    brk: c_int,
+    buf_overrun: c_int,
+    reserved: [c_int; 9]
+}
+
+#[cfg(target_os = "linux")]
+impl SignalCounts {
+    fn count(&self, signal: ::ModemSignal) -> c_int {
+        match signal {
+            ::ModemSignal::Cts => self.cts,
+            ::ModemSignal::Dsr => self.dsr,
+            ::ModemSignal::Ri => self.rng,
+            ::ModemSignal::Cd => self.dcd
+        }
+    }
+}
+
+impl Drop for TTYPort {
+    fn drop(&mut self) {
+        #![allow(unused_must_use)]
+        if let Some(original) = self.original.take() {
+            self.restore(&original);
+        }
+
+        ioctl::tiocnxcl(self.fd);
+
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl AsRawFd for TTYPort {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl io::Read for TTYPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl io::Write for TTYPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// A shared reference can be used to read from one thread while writing from another, e.g., with
+/// `std::thread::scope()`. Both use the port's current timeout.
+impl io::Read for &TTYPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_timeout(buf, self.timeout)
+    }
+}
+
+/// Large buffers are written in chunks of at most `MAX_WRITE_CHUNK` bytes. A write continues
+/// with the next chunk until the whole buffer has been written or the port's timeout elapses, so
+/// a write returns a partial count only if the timeout elapsed or an error occurred after some
+/// data was written.
+impl io::Write for &TTYPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let mut written = 0;
+
+        while written < buf.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if let Err(err) = super::poll::wait_write_fd(self.fd, remaining) {
+                if written > 0 && err.kind() == io::ErrorKind::TimedOut {
+                    break;
+                }
+
+                return Err(self.io_error(err, "write"));
+            }
+
+            let chunk = &buf[written..cmp::min(buf.len(), written + MAX_WRITE_CHUNK)];
+            let len = unsafe { libc::write(self.fd, chunk.as_ptr() as *const c_void, chunk.len() as size_t) };
+
+            if len >= 0 {
+                written += len as usize;
+                continue;
+            }
+
+            let err = io::Error::last_os_error();
+
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            // the error is reported by the next write, once the caller knows how much was written
+            if written > 0 {
+                break;
+            }
+
+            return Err(self.io_error(err, "write"));
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match termios::tcdrain(self.fd) {
+                Ok(()) => return Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(self.io_error(err, "flush"))
+            }
+        }
+    }
+}
+
+impl SerialDevice for TTYPort {
+    type Settings = TTYSettings;
+
+    fn read_settings(&self) -> ::Result<TTYSettings> {
+        match termios::Termios::from_fd(self.fd) {
+            Ok(termios) => Ok(TTYSettings::new(termios)),
+            Err(e) => Err(self.error(super::error::from_io_error(e), "read settings"))
+        }
+    }
+
+    fn write_settings(&mut self, settings: &TTYSettings) -> ::Result<()> {
+        use self::termios::{tcsetattr,tcflush};
+        use self::termios::{TCSANOW,TCIOFLUSH};
+
+        // write settings to TTY
+        if let Err(err) = tcsetattr(self.fd, TCSANOW, &settings.termios) {
+            return Err(self.error(super::error::from_io_error(err), "write settings"));
+        }
+
+        if let Err(err) = tcflush(self.fd, TCIOFLUSH) {
+            return Err(self.error(super::error::from_io_error(err), "write settings"));
+        }
+
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn read_with_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.read_timeout(buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        use self::termios::{tcflush,TCIFLUSH};
+
+        match tcflush(self.fd, TCIFLUSH) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.error(super::error::from_io_error(err), "discard input"))
+        }
+    }
+
+    fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        self.set_pin(ioctl::TIOCM_RTS, level)
+    }
+
+    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        self.set_pin(ioctl::TIOCM_DTR, level)
+    }
+
+    fn read_cts(&mut self) -> ::Result<bool> {
+        self.read_pin(ioctl::TIOCM_CTS)
+    }
+
+    fn read_dsr(&mut self) -> ::Result<bool> {
+        self.read_pin(ioctl::TIOCM_DSR)
+    }
+
+    fn read_ri(&mut self) -> ::Result<bool> {
+        self.read_pin(ioctl::TIOCM_RI)
+    }
+
+    fn read_cd(&mut self) -> ::Result<bool> {
+        self.read_pin(ioctl::TIOCM_CD)
+    }
+
+    fn read_rts(&mut self) -> ::Result<bool> {
+        self.read_output_pin(ioctl::TIOCM_RTS)
+    }
+
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        self.read_output_pin(ioctl::TIOCM_DTR)
+    }
+
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        // tcsendbreak() only sends breaks of an implementation-defined length. The requests are
+        // cast, since the request type of ioctl() differs between platforms.
+        if unsafe { libc::ioctl(self.fd, libc::TIOCSBRK as _) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "send break"));
+        }
+
+        thread::sleep(duration);
+
+        if unsafe { libc::ioctl(self.fd, libc::TIOCCBRK as _) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "send break"));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_latency_hint(&mut self, hint: ::LatencyHint) -> ::Result<()> {
+        let mut serial = SerialStruct::default();
+
+        if unsafe { libc::ioctl(self.fd, libc::TIOCGSERIAL, &mut serial as *mut SerialStruct) } < 0 {
+            return match super::error::errno() {
+                // drivers without serial_struct, such as pseudo-terminals, have no latency setting
+                libc::ENOTTY | libc::EINVAL => Ok(()),
+                _ => Err(self.error(super::error::last_os_error(), "set latency hint"))
+            };
+        }
+
+        match hint {
+            ::LatencyHint::Low => serial.flags |= ASYNC_LOW_LATENCY,
+            ::LatencyHint::Throughput => serial.flags &= !ASYNC_LOW_LATENCY
+        }
+
+        if unsafe { libc::ioctl(self.fd, libc::TIOCSSERIAL, &serial as *const SerialStruct) } < 0 {
+            return Err(self.error(super::error::last_os_error(), "set latency hint"));
+        }
+
+        Ok(())
+    }
+
+    fn check(&mut self) -> ::Result<()> {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+        if unsafe { libc::fstat(self.fd, &mut stat) } < 0 {
+            return Err(self.error(::disconnected(super::error::last_os_error()), "check"));
+        }
+
+        // the device file is removed when a USB adapter is unplugged
+        if stat.st_nlink == 0 {
+            return Err(self.error(::Error::new(::ErrorKind::NoDevice, "device was removed"), "check"));
+        }
+
+        // a hung up TTY fails all ioctls with EIO
+        match termios::Termios::from_fd(self.fd) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(self.error(::disconnected(super::error::from_io_error(err)), "check"))
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn wait_signal_change(&mut self, signals: &[::ModemSignal]) -> ::Result<::ModemSignal> {
+        if signals.is_empty() {
+            let err = ::Error::new(::ErrorKind::InvalidInput, "no modem signals to wait for");
+            return Err(self.error(err, "wait for signal change"));
+        }
+
+        let mask = signals.iter().fold(0, |mask, &signal| mask | signal_pin(signal));
+        let before = self.signal_counts()?;
+
+        loop {
+            if unsafe { libc::ioctl(self.fd, libc::TIOCMIWAIT, mask as libc::c_ulong) } < 0 {
+                if super::error::errno() == libc::EINTR {
+                    continue;
+                }
+
+                return Err(self.error(super::error::last_os_error(), "wait for signal change"));
+            }
+
+            // the counters identify the signal even if it has returned to its previous level
+            let after = self.signal_counts()?;
+
+            if let Some(&signal) = signals.iter().find(|&&signal| after.count(signal) != before.count(signal)) {
+                return Ok(signal);
+            }
+        }
+    }
+}
+
+/// Serial port settings for TTY devices.
+#[derive(Debug,Copy,Clone)]
+pub struct TTYSettings {
+    termios: termios::Termios
+}
+
+impl TTYSettings {
+    fn new(termios: termios::Termios) -> Self {
+        TTYSettings {
+            termios
+        }
+    }
+
+    /// Configures the TTY for binary serial port access.
+    fn set_raw(&mut self) {
+        use self::termios::{CREAD,CLOCAL}; // cflags
+        use self::termios::{ICANON,ECHO,ECHOE,ECHOK,ECHONL,ISIG,IEXTEN}; // lflags
+        use self::termios::{OPOST}; // oflags
+        use self::termios::{INLCR,IGNCR,ICRNL,IGNBRK}; // iflags
+        use self::termios::{VMIN,VTIME}; // c_cc indexes
+
+        self.termios.c_cflag |= CREAD | CLOCAL;
+        self.termios.c_lflag &= !(ICANON | ECHO | ECHOE | ECHOK | ECHONL | ISIG | IEXTEN);
+        self.termios.c_oflag &= !OPOST;
+        self.termios.c_iflag &= !(INLCR | IGNCR | ICRNL | IGNBRK);
+
+        self.termios.c_cc[VMIN] = 0;
+        self.termios.c_cc[VTIME] = 0;
+    }
+
+    /// Returns a reference to the underlying `termios` structure.
+    ///
+    /// This is a platform-specific escape hatch for settings that aren't modeled by
+    /// `SerialPortSettings`. The `termios` fields can be accessed through `Termios`'s `Deref`
+    /// implementation.
+    pub fn as_raw(&self) -> &Termios {
+        &self.termios
+    }
+
+    /// Returns a mutable reference to the underlying `termios` structure.
+    ///
+    /// This is a platform-specific escape hatch for settings that aren't modeled by
+    /// `SerialPortSettings`. Changes take effect when the settings are written to a port.
+    ///
+    /// ```no_run
+    /// extern crate libc;
+    /// extern crate serial;
+    ///
+    /// use serial::SerialDevice;
+    ///
+    /// # fn main() {
+    /// let mut port = serial::posix::TTYPort::open("/dev/ttyUSB0".as_ref()).unwrap();
+    ///
+    /// let mut settings = port.read_settings().unwrap();
+    /// settings.as_raw_mut().c_iflag |= libc::PARMRK;
+    /// port.write_settings(&settings).unwrap();
+    /// # }
+    /// ```
+    pub fn as_raw_mut(&mut self) -> &mut Termios {
+        &mut self.termios
+    }
+
+    /// Returns whether the TTY is in canonical (line) mode.
+    pub fn is_canonical(&self) -> bool {
+        self.termios.c_lflag & termios::ICANON != 0
+    }
+
+    /// Enables or disables canonical (line) mode.
+    ///
+    /// In canonical mode, the kernel buffers input until a complete line has been received, and
+    /// each read returns at most one line. A line is terminated by a newline (`\n`) or by the
+    /// character configured with `set_eol()`. This is more efficient than reading byte-at-a-time
+    /// from devices that send line-based text, such as GPS receivers or consoles.
+    ///
+    /// Line editing is not performed: the erase, kill and end-of-file characters are disabled when
+    /// canonical mode is enabled. Disabling canonical mode restores binary access.
+    ///
+    /// ```no_run
+    /// use serial::SerialDevice;
+    ///
+    /// let mut port = serial::posix::TTYPort::open("/dev/ttyUSB0".as_ref()).unwrap();
+    ///
+    /// let mut settings = port.read_settings().unwrap();
+    /// settings.set_canonical(true);
+    /// settings.set_eol(Some(b'\r'));
+    /// port.write_settings(&settings).unwrap();
+    /// ```
+    pub fn set_canonical(&mut self, canonical: bool) {
+        use self::termios::{ICANON,VERASE,VKILL,VEOF};
+
+        if canonical {
+            self.termios.c_lflag |= ICANON;
+
+            self.termios.c_cc[VERASE] = VDISABLE;
+            self.termios.c_cc[VKILL] = VDISABLE;
+            self.termios.c_cc[VEOF] = VDISABLE;
+        }
+        else {
+            self.set_raw();
+        }
+    }
+
+    /// Returns the additional end-of-line character used in canonical mode, if any.
+    pub fn eol(&self) -> Option<u8> {
+        match self.termios.c_cc[termios::VEOL] {
+            VDISABLE => None,
+            c => Some(c)
+        }
+    }
+
+    /// Sets an additional character that terminates a line in canonical mode.
+    ///
+    /// A newline (`\n`) always terminates a line. Setting an end-of-line character allows lines
+    /// terminated by another character, e.g., a carriage return (`\r`), to be delivered as soon as
+    /// they're complete. The end-of-line character is included in the data that's read.
+    pub fn set_eol(&mut self, eol: Option<u8>) {
+        self.termios.c_cc[termios::VEOL] = eol.unwrap_or(VDISABLE);
+    }
+
+    /// Returns whether the port honors the modem control lines.
+    pub fn is_modem_control(&self) -> bool {
+        self.termios.c_cflag & termios::CLOCAL == 0
+    }
+
+    /// Sets whether the port honors the modem control lines.
+    ///
+    /// Enabling modem control clears `CLOCAL`, so that the loss of carrier (CD) hangs up the port,
+    /// after which reads return end of file. Ports are opened with modem control disabled, which
+    /// ignores the state of CD.
+    pub fn set_modem_control(&mut self, modem_control: bool) {
+        if modem_control {
+            self.termios.c_cflag &= !termios::CLOCAL;
+        }
+        else {
+            self.termios.c_cflag |= termios::CLOCAL;
+        }
+    }
+
+    /// Returns whether the modem control lines are lowered when the port is closed.
+    pub fn is_hang_up_on_close(&self) -> bool {
+        self.termios.c_cflag & termios::HUPCL != 0
+    }
+
+    /// Sets whether the modem control lines are lowered when the port is closed.
+    ///
+    /// When enabled (`HUPCL`), DTR and RTS are dropped when the last process closes the port, which
+    /// resets or power-cycles many attached devices. When disabled, the lines keep their state
+    /// after the port is closed. The setting persists on the device after it's closed, so it also
+    /// avoids the pulse on DTR when the port is opened again.
+    pub fn set_hang_up_on_close(&mut self, hang_up_on_close: bool) {
+        if hang_up_on_close {
+            self.termios.c_cflag |= termios::HUPCL;
+        }
+        else {
+            self.termios.c_cflag &= !termios::HUPCL;
+        }
+    }
+}
+
+/// Special characters in the `c_cc` array of `termios`.
+///
+/// Most of these characters only have an effect in canonical mode or when the corresponding
+/// input processing flag is set.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum ControlChar {
+    /// End of file (`VEOF`).
+    Eof,
+
+    /// Additional end of line (`VEOL`).
+    Eol,
+
+    /// Second additional end of line (`VEOL2`).
+    Eol2,
+
+    /// Erase the previous character (`VERASE`).
+    Erase,
+
+    /// Erase the previous word (`VWERASE`).
+    WordErase,
+
+    /// Erase the line (`VKILL`).
+    Kill,
+
+    /// Reprint the line (`VREPRINT`).
+    Reprint,
+
+    /// Quote the next character (`VLNEXT`).
+    LiteralNext,
+
+    /// Discard pending output (`VDISCARD`).
+    Discard,
+
+    /// Send `SIGINT` (`VINTR`).
+    Interrupt,
+
+    /// Send `SIGQUIT` (`VQUIT`).
+    Quit,
+
+    /// Send `SIGTSTP` (`VSUSP`).
+    Suspend,
+
+    /// Resume output with software flow control (`VSTART`).
+    Start,
+
+    /// Stop output with software flow control (`VSTOP`).
+    Stop
+}
+
+impl ControlChar {
+    fn index(self) -> usize {
+        match self {
+            ControlChar::Eof => termios::VEOF,
+            ControlChar::Eol => termios::VEOL,
+            ControlChar::Eol2 => libc::VEOL2,
+            ControlChar::Erase => termios::VERASE,
+            ControlChar::WordErase => libc::VWERASE,
+            ControlChar::Kill => termios::VKILL,
+            ControlChar::Reprint => libc::VREPRINT,
+            ControlChar::LiteralNext => libc::VLNEXT,
+            ControlChar::Discard => libc::VDISCARD,
+            ControlChar::Interrupt => termios::VINTR,
+            ControlChar::Quit => termios::VQUIT,
+            ControlChar::Suspend => termios::VSUSP,
+            ControlChar::Start => termios::VSTART,
+            ControlChar::Stop => termios::VSTOP
+        }
+    }
+}
+
+/// Fine-grained access to the control characters of POSIX serial port settings.
+///
+/// This extension trait is meant for matching the exact behavior of legacy devices. Changes take
+/// effect when the settings are written to a port:
+///
+/// ```no_run
+/// use std::path::Path;
+/// use std::time::Duration;
+/// use serial::SerialDevice;
+/// use serial::posix::{ControlChar,ControlChars,TTYPort};
+///
+/// let mut port = TTYPort::open(Path::new("/dev/ttyS0")).unwrap();
+/// let mut settings = port.read_settings().unwrap();
+///
+/// settings.set_control_char(ControlChar::Start, Some(0x11));
+/// settings.set_control_char(ControlChar::Stop, Some(0x13));
+/// settings.set_min_bytes(16);
+/// settings.set_inter_byte_timeout(Duration::from_millis(200));
+///
+/// port.write_settings(&settings).unwrap();
+/// ```
+pub trait ControlChars {
+    /// Returns the value of a control character, or `None` if it's disabled.
+    fn control_char(&self, control_char: ControlChar) -> Option<u8>;
+
+    /// Sets the value of a control character. `None` disables it.
+    fn set_control_char(&mut self, control_char: ControlChar, value: Option<u8>);
+
+    /// Returns the minimum number of bytes for a read to complete in non-canonical mode (`VMIN`).
+    fn min_bytes(&self) -> u8;
+
+    /// Sets the minimum number of bytes for a read to complete in non-canonical mode (`VMIN`).
+    ///
+    /// Reads still wait for the first byte with the port's timeout. After that, a read doesn't
+    /// return until `min_bytes` bytes are received or the inter-byte timeout expires. Ports are
+    /// opened with a value of zero, so that reads return whatever data is available.
+    fn set_min_bytes(&mut self, min_bytes: u8);
+
+    /// Returns the inter-byte timeout in non-canonical mode (`VTIME`).
+    fn inter_byte_timeout(&self) -> Duration;
+
+    /// Sets the inter-byte timeout in non-canonical mode (`VTIME`).
+    ///
+    /// The timeout has a resolution of 100 milliseconds and is rounded up to it. Timeouts longer
+    /// than 25.5 seconds are limited to 25.5 seconds. A timeout of zero disables it.
+    fn set_inter_byte_timeout(&mut self, timeout: Duration);
+}
+
+impl ControlChars for TTYSettings {
+    fn control_char(&self, control_char: ControlChar) -> Option<u8> {
+        match self.termios.c_cc[control_char.index()] {
+            VDISABLE => None,
+            c => Some(c)
+        }
+    }
+
+    fn set_control_char(&mut self, control_char: ControlChar, value: Option<u8>) {
+        self.termios.c_cc[control_char.index()] = value.unwrap_or(VDISABLE);
+    }
+
+    fn min_bytes(&self) -> u8 {
+        self.termios.c_cc[termios::VMIN]
+    }
+
+    fn set_min_bytes(&mut self, min_bytes: u8) {
+        self.termios.c_cc[termios::VMIN] = min_bytes;
+    }
+
+    fn inter_byte_timeout(&self) -> Duration {
+        Duration::from_millis(self.termios.c_cc[termios::VTIME] as u64 * 100)
+    }
+
+    fn set_inter_byte_timeout(&mut self, timeout: Duration) {
+        let deciseconds = timeout.as_millis().div_ceil(100);
+        self.termios.c_cc[termios::VTIME] = ::std::cmp::min(deciseconds, 255) as u8;
     }
 }
 
@@ -287,261 +1317,733 @@ impl SerialPortSettings for TTYSettings {
             B2400    => Some(::Baud2400),
             B4800    => Some(::Baud4800),
             #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            B7200    => Some(::BaudOther(7200)),
-            B9600    => Some(::Baud9600),
+            B7200    => Some(::BaudOther(7200)),
+            B9600    => Some(::Baud9600),
+            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+            B14400   => Some(::BaudOther(14400)),
+            B19200   => Some(::Baud19200),
+            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+            B28800   => Some(::BaudOther(28800)),
+            B38400   => Some(::Baud38400),
+            B57600   => Some(::Baud57600),
+            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+            B76800   => Some(::BaudOther(76800)),
+            B115200  => Some(::Baud115200),
+            B230400  => Some(::BaudOther(230400)),
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            B460800  => Some(::BaudOther(460800)),
+            #[cfg(target_os = "linux")]
+            B500000  => Some(::BaudOther(500000)),
+            #[cfg(target_os = "linux")]
+            B576000  => Some(::BaudOther(576000)),
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            B921600  => Some(::BaudOther(921600)),
+            #[cfg(target_os = "linux")]
+            B1000000 => Some(::BaudOther(1000000)),
+            #[cfg(target_os = "linux")]
+            B1152000 => Some(::BaudOther(1152000)),
+            #[cfg(target_os = "linux")]
+            B1500000 => Some(::BaudOther(1500000)),
+            #[cfg(target_os = "linux")]
+            B2000000 => Some(::BaudOther(2000000)),
+            #[cfg(target_os = "linux")]
+            B2500000 => Some(::BaudOther(2500000)),
+            #[cfg(target_os = "linux")]
+            B3000000 => Some(::BaudOther(3000000)),
+            #[cfg(target_os = "linux")]
+            B3500000 => Some(::BaudOther(3500000)),
+            #[cfg(target_os = "linux")]
+            B4000000 => Some(::BaudOther(4000000)),
+
+            _ => None
+        }
+    }
+
+    fn char_size(&self) -> Option<::CharSize> {
+        use self::termios::{CSIZE,CS5,CS6,CS7,CS8};
+
+        match self.termios.c_cflag & CSIZE {
+            CS8 => Some(::Bits8),
+            CS7 => Some(::Bits7),
+            CS6 => Some(::Bits6),
+            CS5 => Some(::Bits5),
+
+            _ => None
+        }
+    }
+
+    fn parity(&self) -> Option<::Parity> {
+        use self::termios::{PARENB,PARODD};
+
+        if self.termios.c_cflag & PARENB != 0 {
+            if self.termios.c_cflag & PARODD != 0 {
+                Some(::ParityOdd)
+            }
+            else {
+                Some(::ParityEven)
+            }
+        }
+        else {
+            Some(::ParityNone)
+        }
+    }
+
+    fn stop_bits(&self) -> Option<::StopBits> {
+        use self::termios::{CSTOPB};
+
+        if self.termios.c_cflag & CSTOPB != 0 {
+            Some(::Stop2)
+        }
+        else {
+            Some(::Stop1)
+        }
+    }
+
+    fn flow_control(&self) -> Option<::FlowControl> {
+        use self::termios::{IXON,IXOFF};
+        use self::termios::os::target::{CRTSCTS};
+
+        if self.termios.c_cflag & CRTSCTS != 0 {
+            Some(::FlowHardware)
+        }
+        else if self.termios.c_iflag & (IXON | IXOFF) != 0 {
+            Some(::FlowSoftware)
+        }
+        else {
+            Some(::FlowNone)
+        }
+    }
+
+    fn input_flow_control(&self) -> Option<::FlowControl> {
+        use self::termios::IXOFF;
+
+        if self.termios.c_cflag & CRTS_IFLOW != 0 {
+            Some(::FlowHardware)
+        }
+        else if self.termios.c_iflag & IXOFF != 0 {
+            Some(::FlowSoftware)
+        }
+        else {
+            Some(::FlowNone)
+        }
+    }
+
+    fn output_flow_control(&self) -> Option<::FlowControl> {
+        use self::termios::IXON;
+
+        if self.termios.c_cflag & CCTS_OFLOW != 0 {
+            Some(::FlowHardware)
+        }
+        else if self.termios.c_iflag & IXON != 0 {
+            Some(::FlowSoftware)
+        }
+        else {
+            Some(::FlowNone)
+        }
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: ::BaudRate) -> ::Result<()> {
+        use self::libc::{EINVAL};
+        use self::termios::cfsetspeed;
+        use self::termios::{B50,B75,B110,B134,B150,B200,B300,B600,B1200,B1800,B2400,B4800,B9600,B19200,B38400};
+        use self::termios::os::target::{B57600,B115200,B230400};
+
+        #[cfg(target_os = "linux")]
+        use self::termios::os::linux::{B460800,B500000,B576000,B921600,B1000000,B1152000,B1500000,B2000000,B2500000,B3000000,B3500000,B4000000};
+
+        #[cfg(target_os = "macos")]
+        use self::termios::os::macos::{B7200,B14400,B28800,B76800};
+
+        #[cfg(target_os = "freebsd")]
+        use self::termios::os::freebsd::{B7200,B14400,B28800,B76800,B460800,B921600};
+
+        #[cfg(target_os = "openbsd")]
+        use self::termios::os::openbsd::{B7200,B14400,B28800,B76800};
+
+        let baud = match baud_rate {
+            ::BaudOther(50)      => B50,
+            ::BaudOther(75)      => B75,
+            ::Baud110            => B110,
+            ::BaudOther(134)     => B134,
+            ::BaudOther(150)     => B150,
+            ::BaudOther(200)     => B200,
+            ::Baud300            => B300,
+            ::Baud600            => B600,
+            ::Baud1200           => B1200,
+            ::BaudOther(1800)    => B1800,
+            ::Baud2400           => B2400,
+            ::Baud4800           => B4800,
+            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+            ::BaudOther(7200)    => B7200,
+            ::Baud9600           => B9600,
             #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            B14400   => Some(::BaudOther(14400)),
-            B19200   => Some(::Baud19200),
+            ::BaudOther(14400)   => B14400,
+            ::Baud19200          => B19200,
             #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            B28800   => Some(::BaudOther(28800)),
-            B38400   => Some(::Baud38400),
-            B57600   => Some(::Baud57600),
+            ::BaudOther(28800)   => B28800,
+            ::Baud38400          => B38400,
+            ::Baud57600          => B57600,
             #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            B76800   => Some(::BaudOther(76800)),
-            B115200  => Some(::Baud115200),
-            B230400  => Some(::BaudOther(230400)),
+            ::BaudOther(76800)   => B76800,
+            ::Baud115200         => B115200,
+            ::BaudOther(230400)  => B230400,
             #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-            B460800  => Some(::BaudOther(460800)),
+            ::BaudOther(460800)  => B460800,
             #[cfg(target_os = "linux")]
-            B500000  => Some(::BaudOther(500000)),
+            ::BaudOther(500000)  => B500000,
             #[cfg(target_os = "linux")]
-            B576000  => Some(::BaudOther(576000)),
+            ::BaudOther(576000)  => B576000,
             #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-            B921600  => Some(::BaudOther(921600)),
+            ::BaudOther(921600)  => B921600,
             #[cfg(target_os = "linux")]
-            B1000000 => Some(::BaudOther(1000000)),
+            ::BaudOther(1000000) => B1000000,
             #[cfg(target_os = "linux")]
-            B1152000 => Some(::BaudOther(1152000)),
+            ::BaudOther(1152000) => B1152000,
             #[cfg(target_os = "linux")]
-            B1500000 => Some(::BaudOther(1500000)),
+            ::BaudOther(1500000) => B1500000,
             #[cfg(target_os = "linux")]
-            B2000000 => Some(::BaudOther(2000000)),
+            ::BaudOther(2000000) => B2000000,
             #[cfg(target_os = "linux")]
-            B2500000 => Some(::BaudOther(2500000)),
+            ::BaudOther(2500000) => B2500000,
             #[cfg(target_os = "linux")]
-            B3000000 => Some(::BaudOther(3000000)),
+            ::BaudOther(3000000) => B3000000,
             #[cfg(target_os = "linux")]
-            B3500000 => Some(::BaudOther(3500000)),
+            ::BaudOther(3500000) => B3500000,
             #[cfg(target_os = "linux")]
-            B4000000 => Some(::BaudOther(4000000)),
+            ::BaudOther(4000000) => B4000000,
 
-            _ => None
+            ::BaudOther(_) => return Err(super::error::from_raw_os_error(EINVAL))
+        };
+
+        match cfsetspeed(&mut self.termios, baud) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(super::error::from_io_error(err))
         }
     }
 
-    fn char_size(&self) -> Option<::CharSize> {
+    fn set_char_size(&mut self, char_size: ::CharSize) {
         use self::termios::{CSIZE,CS5,CS6,CS7,CS8};
 
-        match self.termios.c_cflag & CSIZE {
-            CS8 => Some(::Bits8),
-            CS7 => Some(::Bits7),
-            CS6 => Some(::Bits6),
-            CS5 => Some(::Bits5),
+        let size = match char_size {
+            ::Bits5 => CS5,
+            ::Bits6 => CS6,
+            ::Bits7 => CS7,
+            ::Bits8 => CS8
+        };
 
-            _ => None
-        }
+        self.termios.c_cflag &= !CSIZE;
+        self.termios.c_cflag |= size;
     }
 
-    fn parity(&self) -> Option<::Parity> {
-        use self::termios::{PARENB,PARODD};
+    fn set_parity(&mut self, parity: ::Parity) {
+        use self::termios::{PARENB,PARODD,INPCK,IGNPAR};
 
-        if self.termios.c_cflag & PARENB != 0 {
-            if self.termios.c_cflag & PARODD != 0 {
-                Some(::ParityOdd)
+        match parity {
+            ::ParityNone => {
+                self.termios.c_cflag &= !(PARENB | PARODD);
+                self.termios.c_iflag &= !INPCK;
+                self.termios.c_iflag |= IGNPAR;
+            },
+            ::ParityOdd => {
+                self.termios.c_cflag |= PARENB | PARODD;
+                self.termios.c_iflag |= INPCK;
+                self.termios.c_iflag &= !IGNPAR;
+            },
+            ::ParityEven => {
+                self.termios.c_cflag &= !PARODD;
+                self.termios.c_cflag |= PARENB;
+                self.termios.c_iflag |= INPCK;
+                self.termios.c_iflag &= !IGNPAR;
             }
-            else {
-                Some(::ParityEven)
+        };
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: ::StopBits) {
+        use self::termios::{CSTOPB};
+
+        match stop_bits {
+            ::Stop1 => self.termios.c_cflag &= !CSTOPB,
+            ::Stop2 => self.termios.c_cflag |= CSTOPB
+        };
+    }
+
+    fn set_flow_control(&mut self, flow_control: ::FlowControl) {
+        use self::termios::{IXON,IXOFF};
+        use self::termios::os::target::{CRTSCTS};
+
+        match flow_control {
+            ::FlowNone => {
+                self.termios.c_iflag &= !(IXON | IXOFF);
+                self.termios.c_cflag &= !CRTSCTS;
+            },
+            ::FlowSoftware => {
+                self.termios.c_iflag |= IXON | IXOFF;
+                self.termios.c_cflag &= !CRTSCTS;
+            },
+            ::FlowHardware => {
+                self.termios.c_iflag &= !(IXON | IXOFF);
+                self.termios.c_cflag |= CRTSCTS;
+            }
+        };
+    }
+
+    fn set_flow_control_directions(&mut self, input: ::FlowControl, output: ::FlowControl) -> ::Result<()> {
+        use self::termios::{IXON,IXOFF};
+
+        if CRTS_IFLOW == CCTS_OFLOW && (input == ::FlowHardware) != (output == ::FlowHardware) {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "hardware flow control can't be set for one direction"));
+        }
+
+        self.termios.c_iflag &= !(IXON | IXOFF);
+        self.termios.c_cflag &= !(CRTS_IFLOW | CCTS_OFLOW);
+
+        match input {
+            ::FlowNone => (),
+            ::FlowSoftware => self.termios.c_iflag |= IXOFF,
+            ::FlowHardware => self.termios.c_cflag |= CRTS_IFLOW
+        }
+
+        match output {
+            ::FlowNone => (),
+            ::FlowSoftware => self.termios.c_iflag |= IXON,
+            ::FlowHardware => self.termios.c_cflag |= CCTS_OFLOW
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr,CString};
+    use std::io;
+    use std::io::prelude::*;
+    use std::mem;
+    use std::path::Path;
+    use std::time::{Duration,Instant};
+
+    use std::os::unix::prelude::*;
+
+    use super::libc;
+    use super::{TTYPort,TTYSettings};
+    use ::prelude::*;
+
+    fn default_settings() -> TTYSettings {
+        TTYSettings {
+            termios: unsafe { mem::zeroed() }
+        }
+    }
+
+    /// The controlling side of a pseudo-terminal, which is closed when dropped.
+    struct Master {
+        fd: RawFd
+    }
+
+    impl Master {
+        fn write(&self, data: &[u8]) {
+            let len = unsafe { libc::write(self.fd, data.as_ptr() as *const libc::c_void, data.len()) };
+            assert_eq!(len, data.len() as isize);
+        }
+    }
+
+    impl Drop for Master {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
             }
         }
-        else {
-            Some(::ParityNone)
+    }
+
+    /// Opens a pseudo-terminal and returns its master and a `TTYPort` for its slave device.
+    fn open_pty() -> (Master, TTYPort) {
+        let (master, name) = open_master();
+        let port = TTYPort::open(Path::new(&name)).unwrap();
+
+        (master, port)
+    }
+
+    /// Opens the master side of a pseudo-terminal and returns it with the name of the slave.
+    fn open_master() -> (Master, String) {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+
+            let master = Master { fd };
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+
+            let name = CStr::from_ptr(libc::ptsname(fd)).to_str().unwrap().to_owned();
+
+            (master, name)
+        }
+    }
+
+    #[test]
+    fn tty_port_opens_with_default_options() {
+        let (_master, name) = open_master();
+
+        assert!(TTYPort::open_with_options(Path::new(&name), &::OpenOptions::new()).is_ok());
+    }
+
+    #[test]
+    fn tty_port_opens_read_only() {
+        let (master, name) = open_master();
+
+        let mut port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().access(::Access::ReadOnly)).unwrap();
+        port.set_timeout(Duration::from_secs(1)).unwrap();
+
+        master.write(b"in");
+
+        let mut buf = [0; 2];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"in");
+        assert!(port.write(b"out").is_err());
+    }
+
+    #[test]
+    fn tty_port_opens_close_on_exec() {
+        let (_master, name) = open_master();
+
+        let port = TTYPort::open(Path::new(&name)).unwrap();
+        assert_eq!(unsafe { libc::fcntl(port.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+        drop(port);
+
+        let port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().close_on_exec(true)).unwrap();
+        assert_ne!(unsafe { libc::fcntl(port.as_raw_fd(), libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+    }
+
+    #[test]
+    fn tty_port_reports_failure_to_set_initial_dtr() {
+        let (_master, name) = open_master();
+
+        // pseudo-terminals have no modem control lines
+        let err = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().dtr(false)).err().unwrap();
+        assert_eq!(err.operation(), Some("open"));
+    }
+
+    #[test]
+    fn tty_port_read_with_timeout_keeps_port_timeout() {
+        let (_master, mut port) = open_pty();
+        let mut buf = [0; 8];
+
+        port.set_timeout(Duration::from_secs(10)).unwrap();
+        let err = port.read_with_timeout(&mut buf, Duration::from_millis(10)).unwrap_err();
+
+        assert_eq!(err.kind(), ::std::io::ErrorKind::TimedOut);
+        assert_eq!(port.timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn tty_port_reads_data_from_master() {
+        let (master, mut port) = open_pty();
+        let mut buf = [0; 8];
+
+        master.write(b"ping");
+        let len = port.read_with_timeout(&mut buf, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(port.read(&mut buf).unwrap_err().kind(), ::std::io::ErrorKind::TimedOut);
+    }
+
+    static SIGNALED: ::std::sync::atomic::AtomicBool = ::std::sync::atomic::AtomicBool::new(false);
+
+    extern "C" fn record_signal(_: libc::c_int) {
+        SIGNALED.store(true, ::std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[test]
+    fn tty_port_read_retries_when_interrupted_by_signal() {
+        use std::ptr;
+        use std::sync::atomic::Ordering;
+        use std::thread;
+
+        let (_master, mut port) = open_pty();
+        let mut buf = [0; 8];
+
+        // without SA_RESTART, so that the signal interrupts the wait with EINTR
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        let mut previous: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = record_signal as *const () as libc::sighandler_t;
+        action.sa_flags = 0;
+
+        unsafe {
+            libc::sigemptyset(&mut action.sa_mask);
+            assert_eq!(libc::sigaction(libc::SIGUSR1, &action, &mut previous), 0);
+        }
+
+        SIGNALED.store(false, Ordering::SeqCst);
+
+        let reader = unsafe { libc::pthread_self() };
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            unsafe { libc::pthread_kill(reader, libc::SIGUSR1) };
+        });
+
+        let start = Instant::now();
+        let result = port.read_with_timeout(&mut buf, Duration::from_millis(100));
+        let elapsed = start.elapsed();
+        interrupter.join().unwrap();
+
+        unsafe {
+            libc::sigaction(libc::SIGUSR1, &previous, ptr::null_mut());
         }
+
+        assert!(SIGNALED.load(Ordering::SeqCst), "the read wasn't interrupted by the signal");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(elapsed >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn tty_port_reads_complete_lines_in_canonical_mode() {
+        let (master, mut port) = open_pty();
+        let mut buf = [0; 16];
+
+        let mut settings = ::SerialDevice::read_settings(&port).unwrap();
+        settings.set_canonical(true);
+        settings.set_eol(Some(b'\r'));
+        ::SerialDevice::write_settings(&mut port, &settings).unwrap();
+
+        master.write(b"$GPGGA");
+        assert_eq!(port.read_with_timeout(&mut buf, Duration::from_millis(10)).unwrap_err().kind(), ::std::io::ErrorKind::TimedOut);
+
+        master.write(b",1\r$GP");
+        let len = port.read_with_timeout(&mut buf, Duration::from_secs(1)).unwrap();
+        assert_eq!(&buf[..len], b"$GPGGA,1\r");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tty_port_wait_edge_fails_without_modem_signals() {
+        let (_master, port) = open_pty();
+
+        assert!(port.wait_edge(::ModemSignal::Cd).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tty_port_wait_signal_change_fails_without_modem_signals() {
+        let (_master, mut port) = open_pty();
+
+        assert!(port.wait_signal_change(&[::ModemSignal::Ri, ::ModemSignal::Cd]).is_err());
+    }
+
+    #[test]
+    fn tty_port_wait_signal_change_rejects_empty_signals() {
+        let (_master, mut port) = open_pty();
+
+        let err = port.wait_signal_change(&[]).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tty_port_rs485_mode_fails_on_pty() {
+        let (_master, mut port) = open_pty();
+
+        assert!(port.rs485_mode().is_err());
+        assert!(port.set_rs485_mode(Some(&Default::default())).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rs485_mode_rounds_delays_up_to_milliseconds() {
+        use super::Rs485Mode;
+
+        let mode = Rs485Mode {
+            delay_rts_before_send: Duration::from_micros(1500),
+            delay_rts_after_send: Duration::from_micros(300),
+            ..Default::default()
+        };
+
+        let round_trip = Rs485Mode::from_raw(&mode.to_raw()).unwrap();
+
+        assert!(round_trip.rts_on_send);
+        assert!(!round_trip.rts_after_send);
+        assert_eq!(round_trip.delay_rts_before_send, Duration::from_millis(2));
+        assert_eq!(round_trip.delay_rts_after_send, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn tty_settings_exposes_raw_termios() {
+        let mut settings = default_settings();
+
+        settings.as_raw_mut().c_iflag |= libc::PARMRK;
+        assert!(settings.as_raw().c_iflag & libc::PARMRK != 0);
+    }
+
+    #[test]
+    fn tty_settings_toggles_canonical_mode() {
+        let mut settings = default_settings();
+        assert!(!settings.is_canonical());
+
+        settings.set_canonical(true);
+        assert!(settings.is_canonical());
+
+        settings.set_canonical(false);
+        assert!(!settings.is_canonical());
     }
 
-    fn stop_bits(&self) -> Option<::StopBits> {
-        use self::termios::{CSTOPB};
+    #[test]
+    fn tty_settings_toggles_modem_control() {
+        let mut settings = default_settings();
+        settings.set_raw();
+        assert!(!settings.is_modem_control());
 
-        if self.termios.c_cflag & CSTOPB != 0 {
-            Some(::Stop2)
-        }
-        else {
-            Some(::Stop1)
-        }
+        settings.set_modem_control(true);
+        assert!(settings.is_modem_control());
+        assert!(settings.as_raw().c_cflag & libc::CLOCAL == 0);
+
+        settings.set_modem_control(false);
+        assert!(!settings.is_modem_control());
     }
 
-    fn flow_control(&self) -> Option<::FlowControl> {
-        use self::termios::{IXON,IXOFF};
-        use self::termios::os::target::{CRTSCTS};
+    #[test]
+    fn tty_settings_toggles_hang_up_on_close() {
+        let mut settings = default_settings();
+        assert!(!settings.is_hang_up_on_close());
 
-        if self.termios.c_cflag & CRTSCTS != 0 {
-            Some(::FlowHardware)
-        }
-        else if self.termios.c_iflag & (IXON | IXOFF) != 0 {
-            Some(::FlowSoftware)
-        }
-        else {
-            Some(::FlowNone)
-        }
+        settings.set_hang_up_on_close(true);
+        assert!(settings.is_hang_up_on_close());
+
+        settings.set_hang_up_on_close(false);
+        assert!(!settings.is_hang_up_on_close());
     }
 
-    fn set_baud_rate(&mut self, baud_rate: ::BaudRate) -> ::Result<()> {
-        use self::libc::{EINVAL};
-        use self::termios::cfsetspeed;
-        use self::termios::{B50,B75,B110,B134,B150,B200,B300,B600,B1200,B1800,B2400,B4800,B9600,B19200,B38400};
-        use self::termios::os::target::{B57600,B115200,B230400};
+    #[test]
+    fn tty_port_opens_without_hang_up_on_close() {
+        let (_master, name) = open_master();
 
-        #[cfg(target_os = "linux")]
-        use self::termios::os::linux::{B460800,B500000,B576000,B921600,B1000000,B1152000,B1500000,B2000000,B2500000,B3000000,B3500000,B4000000};
+        let port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().hang_up_on_close(false)).unwrap();
+        assert!(!::SerialDevice::read_settings(&port).unwrap().is_hang_up_on_close());
+    }
 
-        #[cfg(target_os = "macos")]
-        use self::termios::os::macos::{B7200,B14400,B28800,B76800};
+    #[test]
+    fn tty_port_close_restores_original_state() {
+        let (_master, name) = open_master();
 
-        #[cfg(target_os = "freebsd")]
-        use self::termios::os::freebsd::{B7200,B14400,B28800,B76800,B460800,B921600};
+        let mut port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().restore_on_close(true)).unwrap();
+        port.reconfigure(|settings| settings.set_baud_rate(::Baud1200)).unwrap();
+        port.close().unwrap();
 
-        #[cfg(target_os = "openbsd")]
-        use self::termios::os::openbsd::{B7200,B14400,B28800,B76800};
+        let port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().preserve_settings(true)).unwrap();
+        let settings = ::SerialDevice::read_settings(&port).unwrap();
 
-        let baud = match baud_rate {
-            ::BaudOther(50)      => B50,
-            ::BaudOther(75)      => B75,
-            ::Baud110            => B110,
-            ::BaudOther(134)     => B134,
-            ::BaudOther(150)     => B150,
-            ::BaudOther(200)     => B200,
-            ::Baud300            => B300,
-            ::Baud600            => B600,
-            ::Baud1200           => B1200,
-            ::BaudOther(1800)    => B1800,
-            ::Baud2400           => B2400,
-            ::Baud4800           => B4800,
-            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            ::BaudOther(7200)    => B7200,
-            ::Baud9600           => B9600,
-            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            ::BaudOther(14400)   => B14400,
-            ::Baud19200          => B19200,
-            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            ::BaudOther(28800)   => B28800,
-            ::Baud38400          => B38400,
-            ::Baud57600          => B57600,
-            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
-            ::BaudOther(76800)   => B76800,
-            ::Baud115200         => B115200,
-            ::BaudOther(230400)  => B230400,
-            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-            ::BaudOther(460800)  => B460800,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(500000)  => B500000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(576000)  => B576000,
-            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
-            ::BaudOther(921600)  => B921600,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(1000000) => B1000000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(1152000) => B1152000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(1500000) => B1500000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(2000000) => B2000000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(2500000) => B2500000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(3000000) => B3000000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(3500000) => B3500000,
-            #[cfg(target_os = "linux")]
-            ::BaudOther(4000000) => B4000000,
+        assert!(settings.is_canonical());
+        assert_ne!(settings.baud_rate(), Some(::Baud1200));
+    }
 
-            ::BaudOther(_) => return Err(super::error::from_raw_os_error(EINVAL))
+    #[test]
+    fn tty_port_preserves_settings_when_attaching() {
+        let (_master, name) = open_master();
+
+        let fd = unsafe { libc::open(CString::new(name.clone()).unwrap().as_ptr(), libc::O_RDWR | libc::O_NOCTTY) };
+        assert!(fd >= 0);
+
+        let original = unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            libc::close(fd);
+            termios
         };
+        assert!(original.c_lflag & libc::ICANON != 0);
 
-        match cfsetspeed(&mut self.termios, baud) {
-            Ok(()) => Ok(()),
-            Err(err) => Err(super::error::from_io_error(err))
-        }
+        let port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().preserve_settings(true)).unwrap();
+        let settings = ::SerialDevice::read_settings(&port).unwrap();
+
+        assert!(settings.is_canonical());
+        assert_eq!(settings.as_raw().c_lflag, original.c_lflag);
+        assert_eq!(settings.as_raw().c_iflag, original.c_iflag);
+        assert_eq!(settings.as_raw().c_oflag, original.c_oflag);
     }
 
-    fn set_char_size(&mut self, char_size: ::CharSize) {
-        use self::termios::{CSIZE,CS5,CS6,CS7,CS8};
+    #[test]
+    fn tty_port_restores_original_state_on_close() {
+        let (_master, name) = open_master();
 
-        let size = match char_size {
-            ::Bits5 => CS5,
-            ::Bits6 => CS6,
-            ::Bits7 => CS7,
-            ::Bits8 => CS8
-        };
+        let fd = unsafe { libc::open(CString::new(name.clone()).unwrap().as_ptr(), libc::O_RDWR | libc::O_NOCTTY) };
+        assert!(fd >= 0);
 
-        self.termios.c_cflag &= !CSIZE;
-        self.termios.c_cflag |= size;
-    }
+        let original = unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            termios
+        };
+        assert!(original.c_lflag & libc::ICANON != 0);
 
-    fn set_parity(&mut self, parity: ::Parity) {
-        use self::termios::{PARENB,PARODD,INPCK,IGNPAR};
+        {
+            let mut port = TTYPort::open_with_options(Path::new(&name), ::OpenOptions::new().restore_on_close(true)).unwrap();
+            port.set_timeout(Duration::from_millis(10)).unwrap();
+            port.reconfigure(|settings| settings.set_baud_rate(::Baud9600)).unwrap();
+        }
 
-        match parity {
-            ::ParityNone => {
-                self.termios.c_cflag &= !(PARENB | PARODD);
-                self.termios.c_iflag &= !INPCK;
-                self.termios.c_iflag |= IGNPAR;
-            },
-            ::ParityOdd => {
-                self.termios.c_cflag |= PARENB | PARODD;
-                self.termios.c_iflag |= INPCK;
-                self.termios.c_iflag &= !IGNPAR;
-            },
-            ::ParityEven => {
-                self.termios.c_cflag &= !PARODD;
-                self.termios.c_cflag |= PARENB;
-                self.termios.c_iflag |= INPCK;
-                self.termios.c_iflag &= !IGNPAR;
-            }
+        let restored = unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            assert_eq!(libc::tcgetattr(fd, &mut termios), 0);
+            libc::close(fd);
+            termios
         };
+
+        assert_eq!(restored.c_lflag, original.c_lflag);
+        assert_eq!(restored.c_iflag, original.c_iflag);
+        assert_eq!(unsafe { libc::cfgetospeed(&restored) }, unsafe { libc::cfgetospeed(&original) });
     }
 
-    fn set_stop_bits(&mut self, stop_bits: ::StopBits) {
-        use self::termios::{CSTOPB};
+    #[test]
+    fn snapshot_round_trips_port_state() {
+        let (_master, mut port) = open_pty();
 
-        match stop_bits {
-            ::Stop1 => self.termios.c_cflag &= !CSTOPB,
-            ::Stop2 => self.termios.c_cflag |= CSTOPB
-        };
+        let snapshot = ::SerialDevice::snapshot(&mut port).unwrap();
+        assert_eq!(snapshot.rts(), None);
+
+        port.set_timeout(Duration::from_secs(3)).unwrap();
+        port.reconfigure(|settings| settings.set_baud_rate(::Baud9600)).unwrap();
+
+        ::SerialDevice::restore(&mut port, &snapshot).unwrap();
+
+        assert_eq!(port.timeout(), snapshot.timeout());
+        assert_eq!(::SerialDevice::read_settings(&port).unwrap().baud_rate(), snapshot.settings().baud_rate());
     }
 
-    fn set_flow_control(&mut self, flow_control: ::FlowControl) {
-        use self::termios::{IXON,IXOFF};
-        use self::termios::os::target::{CRTSCTS};
+    #[test]
+    fn tty_settings_sets_control_chars() {
+        use super::{ControlChar,ControlChars};
 
-        match flow_control {
-            ::FlowNone => {
-                self.termios.c_iflag &= !(IXON | IXOFF);
-                self.termios.c_cflag &= !CRTSCTS;
-            },
-            ::FlowSoftware => {
-                self.termios.c_iflag |= IXON | IXOFF;
-                self.termios.c_cflag &= !CRTSCTS;
-            },
-            ::FlowHardware => {
-                self.termios.c_iflag &= !(IXON | IXOFF);
-                self.termios.c_cflag |= CRTSCTS;
-            }
-        };
+        let mut settings = default_settings();
+
+        settings.set_control_char(ControlChar::Stop, Some(0x13));
+        settings.set_control_char(ControlChar::Eol2, Some(b'\r'));
+        settings.set_control_char(ControlChar::Interrupt, None);
+
+        assert_eq!(settings.control_char(ControlChar::Stop), Some(0x13));
+        assert_eq!(settings.control_char(ControlChar::Eol2), Some(b'\r'));
+        assert_eq!(settings.control_char(ControlChar::Interrupt), None);
     }
-}
 
+    #[test]
+    fn tty_settings_rounds_inter_byte_timeout() {
+        use super::ControlChars;
 
-#[cfg(test)]
-mod tests {
-    use std::mem;
+        let mut settings = default_settings();
 
-    use super::TTYSettings;
-    use ::prelude::*;
+        settings.set_min_bytes(4);
+        settings.set_inter_byte_timeout(Duration::from_millis(250));
+        assert_eq!(settings.min_bytes(), 4);
+        assert_eq!(settings.inter_byte_timeout(), Duration::from_millis(300));
 
-    fn default_settings() -> TTYSettings {
-        TTYSettings {
-            termios: unsafe { mem::uninitialized() }
-        }
+        settings.set_inter_byte_timeout(Duration::from_secs(60));
+        assert_eq!(settings.inter_byte_timeout(), Duration::from_millis(25500));
+    }
+
+    #[test]
+    fn tty_settings_sets_eol() {
+        let mut settings = default_settings();
+
+        settings.set_eol(Some(b'\r'));
+        assert_eq!(settings.eol(), Some(b'\r'));
+
+        settings.set_eol(None);
+        assert_eq!(settings.eol(), None);
     }
 
     #[test]
@@ -645,4 +2147,201 @@ mod tests {
         settings.set_flow_control(::FlowNone);
         assert_eq!(settings.flow_control(), Some(::FlowNone));
     }
+
+    #[test]
+    fn tty_settings_sets_flow_control_directions() {
+        let mut settings = default_settings();
+
+        settings.set_flow_control_directions(::FlowNone, ::FlowSoftware).unwrap();
+        assert_eq!(settings.input_flow_control(), Some(::FlowNone));
+        assert_eq!(settings.output_flow_control(), Some(::FlowSoftware));
+
+        settings.set_flow_control_directions(::FlowSoftware, ::FlowNone).unwrap();
+        assert_eq!(settings.input_flow_control(), Some(::FlowSoftware));
+        assert_eq!(settings.output_flow_control(), Some(::FlowNone));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tty_settings_rejects_one_way_hardware_flow_control() {
+        let mut settings = default_settings();
+
+        let err = settings.set_flow_control_directions(::FlowSoftware, ::FlowHardware).err().unwrap();
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(settings.flow_control(), Some(::FlowNone));
+    }
+
+    #[test]
+    fn tty_port_ignores_latency_hint_without_driver_support() {
+        let (_master, mut port) = open_pty();
+
+        port.set_latency_hint(::LatencyHint::Low).unwrap();
+        port.set_latency_hint(::LatencyHint::Throughput).unwrap();
+    }
+
+    #[test]
+    fn tty_port_discards_unread_input() {
+        let (master, mut port) = open_pty();
+        let mut buf = [0; 8];
+
+        master.write(b"stale");
+        ::std::thread::sleep(Duration::from_millis(50));
+        port.discard_input().unwrap();
+
+        let err = port.read_with_timeout(&mut buf, Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn tty_port_check_detects_hang_up() {
+        let (master, mut port) = open_pty();
+
+        port.check().unwrap();
+        assert!(port.is_connected());
+
+        drop(master);
+
+        assert_eq!(port.check().unwrap_err().kind(), ::ErrorKind::NoDevice);
+        assert!(!port.is_connected());
+    }
+
+    #[test]
+    fn tty_port_read_reports_hang_up_as_no_device() {
+        let (master, mut port) = open_pty();
+        port.set_timeout(Duration::from_secs(5)).unwrap();
+
+        drop(master);
+
+        let start = Instant::now();
+        let err = ::Error::from(port.read(&mut [0; 8]).unwrap_err());
+
+        assert_eq!(err.kind(), ::ErrorKind::NoDevice);
+        assert_eq!(err.operation(), Some("read"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn tty_port_reports_bytes_to_read() {
+        let (master, port) = open_pty();
+        assert_eq!(port.bytes_to_read().unwrap(), 0);
+
+        master.write(b"hello");
+        ::std::thread::sleep(Duration::from_millis(10));
+
+        assert_eq!(port.bytes_to_read().unwrap(), 5);
+    }
+
+    #[test]
+    fn tty_port_batches_reads() {
+        let (master, mut port) = open_pty();
+        port.set_timeout(Duration::from_secs(1)).unwrap();
+        port.set_read_batching(8, Duration::from_millis(200));
+
+        let mut buf = [0; 16];
+
+        ::std::thread::scope(|scope| {
+            master.write(b"a");
+
+            scope.spawn(|| {
+                ::std::thread::sleep(Duration::from_millis(20));
+                master.write(b"bcdefgh");
+            });
+
+            assert_eq!(port.read(&mut buf).unwrap(), 8);
+        });
+
+        assert_eq!(&buf[..8], b"abcdefgh");
+    }
+
+    #[test]
+    fn tty_port_try_read_does_not_wait() {
+        let (master, port) = open_pty();
+        let mut buf = [0; 16];
+
+        assert!(!port.readable(Duration::from_millis(10)).unwrap());
+        assert_eq!(port.try_read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        master.write(b"hello");
+
+        assert!(port.readable(Duration::from_secs(1)).unwrap());
+        assert_eq!(port.try_read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(port.try_read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn tty_port_readable_keeps_data_when_abandoned() {
+        let (master, port) = open_pty();
+        master.write(b"data");
+
+        // a wait that's abandoned after it succeeds consumes nothing
+        assert!(port.readable(Duration::from_secs(1)).unwrap());
+        assert!(port.readable(Duration::from_secs(1)).unwrap());
+
+        let mut buf = [0; 16];
+        assert_eq!(port.try_read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"data");
+    }
+
+    #[test]
+    fn tty_port_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TTYPort>();
+        assert_send_sync::<TTYSettings>();
+    }
+
+    #[test]
+    fn tty_port_writes_large_buffers() {
+        let (master, mut port) = open_pty();
+        port.set_timeout(Duration::from_secs(5)).unwrap();
+
+        let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let received = ::std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut received = Vec::with_capacity(data.len());
+                let mut buf = [0; 8192];
+
+                while received.len() < data.len() {
+                    let len = unsafe { libc::read(master.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                    assert!(len > 0);
+                    received.extend_from_slice(&buf[..len as usize]);
+                }
+
+                received
+            });
+
+            port.write_all(&data).unwrap();
+            reader.join().unwrap()
+        });
+
+        assert!(received == data);
+    }
+
+    #[test]
+    fn tty_port_reads_and_writes_through_shared_references() {
+        let (master, mut port) = open_pty();
+        port.set_timeout(Duration::from_secs(1)).unwrap();
+
+        let port = &port;
+
+        ::std::thread::scope(|scope| {
+            let reader = scope.spawn(move || {
+                let mut buf = [0; 4];
+                let mut reader = port;
+                reader.read_exact(&mut buf).unwrap();
+                buf
+            });
+
+            let mut writer = port;
+            writer.write_all(b"ping").unwrap();
+
+            master.write(b"pong");
+            assert_eq!(&reader.join().unwrap(), b"pong");
+        });
+
+        let mut buf = [0; 4];
+        assert_eq!(unsafe { libc::read(master.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }, 4);
+        assert_eq!(&buf, b"ping");
+    }
 }
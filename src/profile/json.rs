@@ -0,0 +1,248 @@
+//! A parser for the subset of JSON used by profiles.
+
+use super::{invalid,Value};
+
+/// Parses a JSON document of objects, strings, integers, booleans, and nulls.
+///
+/// Members with a null value are omitted, as if they weren't given.
+pub fn parse(text: &str) -> ::Result<Value> {
+    let mut parser = Parser { text, pos: 0 };
+
+    let value = parser.value()?;
+    parser.skip_whitespace();
+
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected data after the document"));
+    }
+
+    value.ok_or_else(|| parser.error("document is null"))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, description: &str) -> ::Error {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        invalid(format!("line {}: {}", line, description))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> ::Result<()> {
+        self.skip_whitespace();
+
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Parses a value, returning `None` for null.
+    fn value(&mut self) -> ::Result<Option<Value>> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => self.object().map(Some),
+            Some(b'"') => self.string().map(|s| Some(Value::String(s))),
+            Some(b'[') => Err(self.error("arrays are not supported")),
+            Some(b'-') | Some(b'0'..=b'9') => self.number().map(|n| Some(Value::Integer(n))),
+            Some(_) => self.literal(),
+            None => Err(self.error("unexpected end of document"))
+        }
+    }
+
+    fn object(&mut self) -> ::Result<Value> {
+        let mut members: Vec<(String, Value)> = Vec::new();
+
+        self.expect(b'{')?;
+        self.skip_whitespace();
+
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Table(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a member name"));
+            }
+
+            let name = self.string()?;
+
+            if members.iter().any(|(existing, _)| *existing == name) {
+                return Err(self.error(&format!("duplicate member: {:?}", name)));
+            }
+
+            self.expect(b':')?;
+
+            if let Some(value) = self.value()? {
+                members.push((name, value));
+            }
+
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(members));
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> ::Result<String> {
+        let mut s = String::new();
+        self.pos += 1;
+
+        loop {
+            let c = match self.text[self.pos..].chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string"))
+            };
+
+            self.pos += c.len_utf8();
+
+            match c {
+                '"' => return Ok(s),
+                '\\' => s.push(self.escape()?),
+                '\u{0}'..='\u{1f}' => return Err(self.error("control character in string")),
+                c => s.push(c)
+            }
+        }
+    }
+
+    fn escape(&mut self) -> ::Result<char> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("unterminated string"))
+        };
+
+        self.pos += 1;
+
+        match c {
+            b'"' => Ok('"'),
+            b'\\' => Ok('\\'),
+            b'/' => Ok('/'),
+            b'b' => Ok('\u{8}'),
+            b'f' => Ok('\u{c}'),
+            b'n' => Ok('\n'),
+            b'r' => Ok('\r'),
+            b't' => Ok('\t'),
+            b'u' => {
+                let high = self.hex4()?;
+
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    if !self.text[self.pos..].starts_with("\\u") {
+                        return Err(self.error("unpaired surrogate in string"));
+                    }
+
+                    self.pos += 2;
+                    let low = self.hex4()?;
+
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(self.error("unpaired surrogate in string"));
+                    }
+
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                }
+                else {
+                    high
+                };
+
+                ::std::char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate in string"))
+            },
+            _ => Err(self.error("invalid escape in string"))
+        }
+    }
+
+    fn hex4(&mut self) -> ::Result<u32> {
+        let code = self.text.get(self.pos..self.pos + 4).and_then(|hex| u32::from_str_radix(hex, 16).ok());
+
+        match code {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            },
+            None => Err(self.error("invalid escape in string"))
+        }
+    }
+
+    fn number(&mut self) -> ::Result<i64> {
+        let start = self.pos;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+
+        if let Some(b'.') | Some(b'e') | Some(b'E') = self.peek() {
+            return Err(self.error("fractional numbers are not supported"));
+        }
+
+        let digits = &self.text[start..self.pos];
+
+        if digits.len() > 1 && digits.trim_start_matches('-').starts_with('0') {
+            return Err(self.error("invalid number"));
+        }
+
+        digits.parse().map_err(|_| self.error("invalid number"))
+    }
+
+    fn literal(&mut self) -> ::Result<Option<Value>> {
+        let rest = &self.text[self.pos..];
+
+        for &(literal, ref value) in &[("true", Some(Value::Boolean(true))), ("false", Some(Value::Boolean(false))), ("null", None)] {
+            if rest.starts_with(literal) {
+                self.pos += literal.len();
+                return Ok(value.clone());
+            }
+        }
+
+        Err(self.error("expected a value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::super::Value;
+
+    #[test]
+    fn parse_objects_and_scalars() {
+        let value = parse(r#" { "a": "x\"\u00e9\ud83d\ude00", "b": -12, "c": { "d": true, "e": null } } "#).unwrap();
+
+        assert_eq!(value, Value::Table(vec![
+            ("a".to_string(), Value::String("x\"\u{e9}\u{1f600}".to_string())),
+            ("b".to_string(), Value::Integer(-12)),
+            ("c".to_string(), Value::Table(vec![("d".to_string(), Value::Boolean(true))]))
+        ]));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_documents() {
+        for text in &["", "{", "{\"a\" 1}", "{\"a\": 1,}", "{\"a\": [1]}", "{\"a\": 1.5}", "{\"a\": 01}", "{\"a\": 1, \"a\": 2}", "{} x"] {
+            assert_eq!(parse(text).unwrap_err().kind(), ::ErrorKind::InvalidInput, "{:?}", text);
+        }
+    }
+}
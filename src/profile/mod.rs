@@ -0,0 +1,585 @@
+//! Named port configurations loaded from TOML or JSON files.
+//!
+//! A profile names a device together with its settings, timeout, RS-485 direction control, and
+//! framing, so that deployments can configure their serial links from files rather than code:
+//!
+//! ```toml
+//! [gps]
+//! path = "/dev/ttyUSB0"
+//! baud = 9600
+//! timeout = "500ms"
+//! framing = "lines"
+//!
+//! [meter]
+//! usb_serial_number = "A6008isP"
+//! baud = 19200
+//! parity = "even"
+//! rs485 = { direction = "rts", post_delay = "1ms" }
+//! ```
+//!
+//! The same profiles in JSON are an object with a member for each profile:
+//!
+//! ```json
+//! {
+//!     "gps": { "path": "/dev/ttyUSB0", "baud": 9600, "timeout": "500ms", "framing": "lines" }
+//! }
+//! ```
+//!
+//! A profile is opened and configured with one call:
+//!
+//! ```no_run
+//! use serial::profile::Profiles;
+//!
+//! let profiles = Profiles::load("/etc/myapp/ports.toml").unwrap();
+//! let mut gps = profiles.open("gps").unwrap();
+//! gps.write_all(b"$PMTK220,1000*1F\r\n").unwrap();
+//! ```
+//!
+//! A profile identifies its device with exactly one of these keys:
+//!
+//! * `path`: the device's path, e.g., `/dev/ttyUSB0` or `COM3`.
+//! * `usb_serial_number`: the serial number of a USB serial adapter, which is looked up when the
+//!   profile is opened.
+//!
+//! The settings use the names and values of the [port URL](../url/index.html) parameters:
+//! `baud`, `data_bits`, `parity`, `stop_bits`, `flow_control`, `timeout`, `dtr`, and `rts`.
+//! Numbers can be given as integers or strings. Settings that aren't given are left as the device
+//! was initialized. The remaining keys are:
+//!
+//! * `framing`: `raw`, `lines`, `cobs`, or `dnp3`. The framing is recorded in `Profile::framing`
+//!   for the application to choose a codec.
+//! * `rs485`: a table with `direction` (`auto`, `rts`, or `dtr`), `invert`, `pre_delay`, and
+//!   `post_delay`. The opened port controls the transceiver as a `HalfDuplex` port.
+//!
+//! Only the parts of TOML and JSON needed for profiles are supported: tables, strings, integers,
+//! and booleans. Unknown keys are rejected, so that misspelled settings aren't silently ignored.
+
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::time::Duration;
+
+use ::{BaudRate,CharSize,FlowControl,OpenOptions,Parity,SerialPort,StopBits,SystemPort};
+use ::rs485::{DirectionControl,DtrToggle,HalfDuplex,NoControl,RtsToggle};
+use ::session::DeviceId;
+use ::url::{self,PortUrl};
+
+mod json;
+mod toml;
+
+/// The framing of messages on a link.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Framing {
+    /// An unframed byte stream.
+    Raw,
+
+    /// Lines of text, as with `codec::lines::LineCodec`.
+    Lines,
+
+    /// COBS frames, as with `codec::cobs::CobsCodec`.
+    Cobs,
+
+    /// DNP3 link-layer frames, as with `codec::dnp3::Dnp3Codec`.
+    Dnp3
+}
+
+/// The signal that enables an RS-485 transmitter.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum DirectionSignal {
+    /// The transceiver controls its direction automatically.
+    Auto,
+
+    /// The transmitter is enabled with RTS.
+    Rts,
+
+    /// The transmitter is enabled with DTR.
+    Dtr
+}
+
+/// RS-485 direction control of a profile.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Rs485Profile {
+    /// The signal that enables the transmitter.
+    pub direction: DirectionSignal,
+
+    /// Clear the signal instead of asserting it while transmitting.
+    pub invert: bool,
+
+    /// Time between enabling the transmitter and sending data.
+    pub pre_delay: Duration,
+
+    /// Time between sending the last byte and enabling the receiver.
+    pub post_delay: Duration
+}
+
+impl Default for Rs485Profile {
+    fn default() -> Self {
+        Rs485Profile {
+            direction: DirectionSignal::Auto,
+            invert: false,
+            pre_delay: Duration::from_secs(0),
+            post_delay: Duration::from_secs(0)
+        }
+    }
+}
+
+/// A named port configuration.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Profile {
+    /// The profile's name.
+    pub name: String,
+
+    /// The device that the profile configures.
+    pub device: DeviceId,
+
+    /// Baud rate, if set.
+    pub baud_rate: Option<BaudRate>,
+
+    /// Character size, if set.
+    pub char_size: Option<CharSize>,
+
+    /// Parity checking mode, if set.
+    pub parity: Option<Parity>,
+
+    /// Number of stop bits, if set.
+    pub stop_bits: Option<StopBits>,
+
+    /// Flow control mode, if set.
+    pub flow_control: Option<FlowControl>,
+
+    /// Timeout, if set.
+    pub timeout: Option<Duration>,
+
+    /// Options for opening the port.
+    pub options: OpenOptions,
+
+    /// RS-485 direction control, if the device is on an RS-485 bus.
+    pub rs485: Option<Rs485Profile>,
+
+    /// The framing of messages on the link, if set.
+    pub framing: Option<Framing>
+}
+
+impl Profile {
+    /// Creates a profile for a device without any settings.
+    pub fn new(name: &str, device: DeviceId) -> Self {
+        Profile {
+            name: name.to_string(),
+            device,
+            baud_rate: None,
+            char_size: None,
+            parity: None,
+            stop_bits: None,
+            flow_control: None,
+            timeout: None,
+            options: OpenOptions::new(),
+            rs485: None,
+            framing: None
+        }
+    }
+
+    /// Applies the profile's settings and timeout to an open port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the settings could not be applied, as by
+    /// `SerialPort::reconfigure()`.
+    pub fn configure(&self, port: &mut dyn SerialPort) -> ::Result<()> {
+        let url = PortUrl {
+            baud_rate: self.baud_rate,
+            char_size: self.char_size,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+            flow_control: self.flow_control,
+            timeout: self.timeout,
+            ..Default::default()
+        };
+
+        url.configure(port)
+    }
+
+    /// Opens and configures the profile's device.
+    ///
+    /// If the profile has RS-485 options, the returned port is a `HalfDuplex` port that enables
+    /// the transmitter around every write.
+    ///
+    /// If the profile's name is redirected by
+    /// [`SERIAL_PORT_OVERRIDES`](../url/index.html#overrides), the override's target is opened
+    /// instead of the profile's device. A target that's a URL is opened as by
+    /// `PortUrl::open()`, and the profile's settings are applied to it without RS-485 direction
+    /// control.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the errors of `DeviceId::find()`,
+    /// [`serial::open()`](../fn.open.html), `PortUrl::open()`, and `configure()`, or an
+    /// `InvalidInput` error for an invalid override.
+    pub fn open(&self) -> ::Result<Box<dyn SerialPort + Send>> {
+        let path = match url::port_override(&self.name)? {
+            Some(ref target) if target.contains("://") => {
+                let mut port = target.parse::<PortUrl>()?.open_direct()?;
+                self.configure(&mut *port)?;
+                return Ok(port);
+            },
+            Some(target) => PathBuf::from(target),
+            None => self.device.find()?
+        };
+
+        let mut port = self.options.open(&path)?;
+        self.configure(&mut port)?;
+
+        let rs485 = match self.rs485 {
+            Some(rs485) => rs485,
+            None => return Ok(Box::new(port))
+        };
+
+        let invert = rs485.invert;
+
+        match rs485.direction {
+            DirectionSignal::Auto => half_duplex(port, NoControl, &rs485),
+            DirectionSignal::Rts => half_duplex(port, RtsToggle { invert }, &rs485),
+            DirectionSignal::Dtr => half_duplex(port, DtrToggle { invert }, &rs485)
+        }
+    }
+}
+
+fn half_duplex<D>(mut port: SystemPort, mut direction: D, rs485: &Rs485Profile) -> ::Result<Box<dyn SerialPort + Send>>
+    where D: DirectionControl + Send + 'static
+{
+    // enables the receiver until the first write
+    direction.set_transmit(&mut port, false)?;
+
+    let mut port = HalfDuplex::new(port, direction);
+    port.set_pre_delay(rs485.pre_delay);
+    port.set_post_delay(rs485.post_delay);
+
+    Ok(Box::new(port))
+}
+
+/// A set of profiles, in the order they were defined.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct Profiles {
+    profiles: Vec<Profile>
+}
+
+impl Profiles {
+    /// Creates an empty set of profiles.
+    pub fn new() -> Self {
+        Profiles { profiles: Vec::new() }
+    }
+
+    /// Loads profiles from a `.toml` or `.json` file.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the file couldn't be read, an `InvalidInput` error if
+    /// the file's extension isn't `toml` or `json`, or the errors of `from_toml()` or
+    /// `from_json()`.
+    pub fn load<P: AsRef<Path>>(path: P) -> ::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        let profiles = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Profiles::from_toml(&text),
+            Some("json") => Profiles::from_json(&text),
+            _ => return Err(invalid(format!("unknown profile format: {}", path.display())))
+        };
+
+        profiles.map_err(|err| ::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    /// Parses profiles from TOML.
+    ///
+    /// Each top-level table is a profile named by the table's key.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an `InvalidInput` error if the text isn't valid TOML, uses features
+    /// of TOML that aren't supported, or doesn't describe valid profiles.
+    pub fn from_toml(text: &str) -> ::Result<Self> {
+        Profiles::from_value(toml::parse(text)?)
+    }
+
+    /// Parses profiles from JSON.
+    ///
+    /// The text must be an object with a member for each profile.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an `InvalidInput` error if the text isn't valid JSON, uses arrays or
+    /// fractional numbers, or doesn't describe valid profiles.
+    pub fn from_json(text: &str) -> ::Result<Self> {
+        Profiles::from_value(json::parse(text)?)
+    }
+
+    /// Adds a profile, replacing any profile with the same name.
+    pub fn insert(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|existing| existing.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile)
+        }
+    }
+
+    /// Returns the profile with the given name.
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Returns an iterator over the profiles.
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Profile> {
+        self.profiles.iter()
+    }
+
+    /// Opens and configures the device of the profile with the given name.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an `InvalidInput` error if there's no profile with the name, or the
+    /// errors of `Profile::open()`.
+    pub fn open(&self, name: &str) -> ::Result<Box<dyn SerialPort + Send>> {
+        match self.get(name) {
+            Some(profile) => profile.open(),
+            None => Err(invalid(format!("no profile named {:?}", name)))
+        }
+    }
+
+    fn from_value(value: Value) -> ::Result<Self> {
+        let mut profiles = Profiles::new();
+
+        for (name, value) in table(value, "profiles")? {
+            let profile = parse_profile(&name, value)?;
+            profiles.profiles.push(profile);
+        }
+
+        Ok(profiles)
+    }
+}
+
+/// A value parsed from TOML or JSON.
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Table(Vec<(String, Value)>)
+}
+
+fn invalid(description: String) -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, description)
+}
+
+fn table(value: Value, name: &str) -> ::Result<Vec<(String, Value)>> {
+    match value {
+        Value::Table(entries) => Ok(entries),
+        _ => Err(invalid(format!("{} must be a table", name)))
+    }
+}
+
+/// Returns a string or integer as text, for the parsers shared with port URLs.
+fn scalar(value: &Value, key: &str) -> ::Result<String> {
+    match *value {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Integer(n) => Ok(n.to_string()),
+        _ => Err(invalid(format!("{} must be a string or integer", key)))
+    }
+}
+
+fn string(value: &Value, key: &str) -> ::Result<String> {
+    match *value {
+        Value::String(ref s) => Ok(s.clone()),
+        _ => Err(invalid(format!("{} must be a string", key)))
+    }
+}
+
+fn boolean(value: &Value, key: &str) -> ::Result<bool> {
+    match *value {
+        Value::Boolean(b) => Ok(b),
+        _ => Err(invalid(format!("{} must be a boolean", key)))
+    }
+}
+
+fn parse_profile(name: &str, value: Value) -> ::Result<Profile> {
+    let context = |err: ::Error| ::Error::new(err.kind(), format!("profile {:?}: {}", name, err));
+
+    let mut device = None;
+    let mut profile = Profile::new(name, DeviceId::Path(PathBuf::new()));
+
+    for (key, value) in table(value, "a profile").map_err(context)? {
+        let key = &key[..];
+
+        let result = match key {
+            "path" | "usb_serial_number" if device.is_some() => Err(invalid("only one of path and usb_serial_number may be given".to_string())),
+            "path" => string(&value, key).map(|path| device = Some(DeviceId::Path(PathBuf::from(path)))),
+            "usb_serial_number" => string(&value, key).map(|serial_number| device = Some(DeviceId::UsbSerialNumber(serial_number))),
+            "baud" => scalar(&value, key).and_then(|s| s.parse()).map(|baud_rate| profile.baud_rate = Some(baud_rate)),
+            "data_bits" => scalar(&value, key).and_then(|s| url::parse_char_size(&s)).map(|char_size| profile.char_size = Some(char_size)),
+            "parity" => string(&value, key).and_then(|s| url::parse_parity(&s)).map(|parity| profile.parity = Some(parity)),
+            "stop_bits" => scalar(&value, key).and_then(|s| url::parse_stop_bits(&s)).map(|stop_bits| profile.stop_bits = Some(stop_bits)),
+            "flow_control" => string(&value, key).and_then(|s| url::parse_flow_control(&s)).map(|flow_control| profile.flow_control = Some(flow_control)),
+            "timeout" => scalar(&value, key).and_then(|s| url::parse_duration(&s)).map(|timeout| profile.timeout = Some(timeout)),
+            "dtr" => boolean(&value, key).map(|level| { profile.options.dtr(level); }),
+            "rts" => boolean(&value, key).map(|level| { profile.options.rts(level); }),
+            "framing" => string(&value, key).and_then(|s| parse_framing(&s)).map(|framing| profile.framing = Some(framing)),
+            "rs485" => parse_rs485(value).map(|rs485| profile.rs485 = Some(rs485)),
+            _ => Err(invalid(format!("unknown key: {:?}", key)))
+        };
+
+        result.map_err(context)?;
+    }
+
+    match device {
+        Some(device) => profile.device = device,
+        None => return Err(context(invalid("path or usb_serial_number is required".to_string())))
+    }
+
+    Ok(profile)
+}
+
+fn parse_rs485(value: Value) -> ::Result<Rs485Profile> {
+    let mut rs485 = Rs485Profile::default();
+
+    for (key, value) in table(value, "rs485")? {
+        let key = &key[..];
+
+        match key {
+            "direction" => rs485.direction = parse_direction(&string(&value, key)?)?,
+            "invert" => rs485.invert = boolean(&value, key)?,
+            "pre_delay" => rs485.pre_delay = url::parse_duration(&scalar(&value, key)?)?,
+            "post_delay" => rs485.post_delay = url::parse_duration(&scalar(&value, key)?)?,
+            _ => return Err(invalid(format!("unknown key: \"rs485.{}\"", key)))
+        }
+    }
+
+    Ok(rs485)
+}
+
+fn parse_framing(value: &str) -> ::Result<Framing> {
+    match value {
+        "raw" => Ok(Framing::Raw),
+        "lines" => Ok(Framing::Lines),
+        "cobs" => Ok(Framing::Cobs),
+        "dnp3" => Ok(Framing::Dnp3),
+        _ => Err(invalid(format!("invalid framing: {:?}", value)))
+    }
+}
+
+fn parse_direction(value: &str) -> ::Result<DirectionSignal> {
+    match value {
+        "auto" => Ok(DirectionSignal::Auto),
+        "rts" => Ok(DirectionSignal::Rts),
+        "dtr" => Ok(DirectionSignal::Dtr),
+        _ => Err(invalid(format!("invalid direction: {:?}", value)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{DirectionSignal,Framing,Profiles,Rs485Profile};
+    use ::session::DeviceId;
+    use ::testing::ScriptedDevice;
+    use ::url::{self,PortUrl};
+
+    const TOML: &str = r#"
+# sensors on the field bus
+[gps]
+path = "/dev/ttyUSB0"
+baud = 9600
+timeout = "500ms"
+framing = "lines"
+
+[meter]
+usb_serial_number = "A6008isP"
+baud = "19200"
+parity = "even"
+stop_bits = 2
+dtr = false
+
+[meter.rs485]
+direction = "rts"
+post_delay = "1ms"
+"#;
+
+    const JSON: &str = r#"{
+        "gps": { "path": "/dev/ttyUSB0", "baud": 9600, "timeout": "500ms", "framing": "lines" },
+        "meter": {
+            "usb_serial_number": "A6008isP",
+            "baud": "19200",
+            "parity": "even",
+            "stop_bits": 2,
+            "dtr": false,
+            "rs485": { "direction": "rts", "post_delay": "1ms" }
+        }
+    }"#;
+
+    #[test]
+    fn profiles_from_toml() {
+        let profiles = Profiles::from_toml(TOML).unwrap();
+        let names: Vec<&str> = profiles.iter().map(|profile| &profile.name[..]).collect();
+        assert_eq!(names, vec!["gps", "meter"]);
+
+        let gps = profiles.get("gps").unwrap();
+        assert_eq!(gps.device, DeviceId::Path(PathBuf::from("/dev/ttyUSB0")));
+        assert_eq!(gps.baud_rate, Some(::Baud9600));
+        assert_eq!(gps.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(gps.framing, Some(Framing::Lines));
+        assert_eq!(gps.rs485, None);
+
+        let meter = profiles.get("meter").unwrap();
+        assert_eq!(meter.device, DeviceId::UsbSerialNumber("A6008isP".to_string()));
+        assert_eq!(meter.baud_rate, Some(::Baud19200));
+        assert_eq!(meter.parity, Some(::ParityEven));
+        assert_eq!(meter.stop_bits, Some(::Stop2));
+        assert_eq!(meter.options.dtr_level(), Some(false));
+        assert_eq!(meter.rs485, Some(Rs485Profile { direction: DirectionSignal::Rts, post_delay: Duration::from_millis(1), ..Default::default() }));
+    }
+
+    #[test]
+    fn profiles_from_json_match_toml() {
+        assert_eq!(Profiles::from_json(JSON).unwrap(), Profiles::from_toml(TOML).unwrap());
+    }
+
+    #[test]
+    fn profiles_reject_invalid_profiles() {
+        for text in &[
+            "[gps]\nbaud = 9600",
+            "[gps]\npath = \"/dev/ttyS0\"\nusb_serial_number = \"A1\"",
+            "[gps]\npath = \"/dev/ttyS0\"\nspeed = 9600",
+            "[gps]\npath = \"/dev/ttyS0\"\nparity = \"mark\"",
+            "[gps]\npath = \"/dev/ttyS0\"\nrs485 = { direction = \"cts\" }",
+            "path = \"/dev/ttyS0\""
+        ] {
+            assert_eq!(Profiles::from_toml(text).unwrap_err().kind(), ::ErrorKind::InvalidInput, "{}", text);
+        }
+    }
+
+    #[test]
+    fn profiles_open_redirects_to_override() {
+        url::register("profile-test", |_: &PortUrl| Ok(Box::new(ScriptedDevice::new())));
+        env::set_var(url::OVERRIDES_VAR, "override-test=profile-test://device");
+
+        let profiles = Profiles::from_toml("[override-test]\npath = \"/dev/nonexistent\"\nbaud = 4800").unwrap();
+        let mut port = profiles.open("override-test").unwrap();
+        let mut baud_rate = None;
+
+        port.reconfigure_dyn(&mut |settings| {
+            baud_rate = settings.baud_rate();
+            Ok(())
+        }).unwrap();
+
+        env::remove_var(url::OVERRIDES_VAR);
+        url::unregister("profile-test");
+        assert_eq!(baud_rate, Some(::Baud4800));
+    }
+
+    #[test]
+    fn profiles_open_reports_unknown_profile() {
+        let err = Profiles::new().open("gps").err().unwrap();
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+    }
+}
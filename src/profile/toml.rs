@@ -0,0 +1,349 @@
+//! A parser for the subset of TOML used by profiles.
+
+use super::{invalid,Value};
+
+/// Parses a TOML document of tables, strings, integers, and booleans.
+///
+/// Arrays, floats, dates, and multi-line strings aren't supported.
+pub fn parse(text: &str) -> ::Result<Value> {
+    let mut root = Vec::new();
+    let mut current = Vec::new();
+    let mut headers: Vec<Vec<String>> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let mut cursor = Cursor { line, pos: 0, number: i + 1 };
+        cursor.skip_whitespace();
+
+        if cursor.at_end() {
+            continue;
+        }
+
+        if cursor.peek() == Some('[') {
+            if cursor.rest().starts_with("[[") {
+                return Err(cursor.error("arrays of tables are not supported"));
+            }
+
+            cursor.pos += 1;
+            let path = cursor.key()?;
+            cursor.expect(']')?;
+            cursor.end()?;
+
+            if headers.contains(&path) {
+                return Err(cursor.error(&format!("duplicate table: {:?}", path.join("."))));
+            }
+
+            table_at(&mut root, &path).map_err(|err| cursor.error(&err))?;
+            headers.push(path.clone());
+            current = path;
+        }
+        else {
+            let mut path = current.clone();
+            path.extend(cursor.key()?);
+            cursor.expect('=')?;
+            let value = cursor.value()?;
+            cursor.end()?;
+
+            insert(&mut root, &path, value).map_err(|err| cursor.error(&err))?;
+        }
+    }
+
+    Ok(Value::Table(root))
+}
+
+/// Returns the table at `path`, creating it and any enclosing tables that don't exist.
+fn table_at<'a>(mut table: &'a mut Vec<(String, Value)>, path: &[String]) -> Result<&'a mut Vec<(String, Value)>, String> {
+    for key in path {
+        let i = match table.iter().position(|(existing, _)| existing == key) {
+            Some(i) => i,
+            None => {
+                table.push((key.clone(), Value::Table(Vec::new())));
+                table.len() - 1
+            }
+        };
+
+        table = match table[i].1 {
+            Value::Table(ref mut entries) => entries,
+            _ => return Err(format!("{:?} is not a table", key))
+        };
+    }
+
+    Ok(table)
+}
+
+fn insert(root: &mut Vec<(String, Value)>, path: &[String], value: Value) -> Result<(), String> {
+    let (key, parents) = path.split_last().unwrap();
+    let table = table_at(root, parents)?;
+
+    if table.iter().any(|(existing, _)| existing == key) {
+        return Err(format!("duplicate key: {:?}", path.join(".")));
+    }
+
+    table.push((key.clone(), value));
+    Ok(())
+}
+
+struct Cursor<'a> {
+    line: &'a str,
+    pos: usize,
+    number: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn error(&self, description: &str) -> ::Error {
+        invalid(format!("line {}: {}", self.number, description))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.line[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Returns `true` at the end of the line or the start of a comment.
+    fn at_end(&self) -> bool {
+        self.peek().is_none_or(|c| c == '#')
+    }
+
+    fn end(&mut self) -> ::Result<()> {
+        self.skip_whitespace();
+
+        if !self.at_end() {
+            return Err(self.error("expected the end of the line"));
+        }
+
+        Ok(())
+    }
+
+    fn expect(&mut self, c: char) -> ::Result<()> {
+        self.skip_whitespace();
+
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)));
+        }
+
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Parses a dotted key, e.g., `meter.rs485."post_delay"`.
+    fn key(&mut self) -> ::Result<Vec<String>> {
+        let mut path = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let key = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(self.rest().len());
+
+                    if len == 0 {
+                        return Err(self.error("expected a key"));
+                    }
+
+                    self.pos += len;
+                    self.line[self.pos - len..self.pos].to_string()
+                }
+            };
+
+            path.push(key);
+            self.skip_whitespace();
+
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> ::Result<Value> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('{') => self.inline_table(),
+            Some('[') => Err(self.error("arrays are not supported")),
+            Some('+') | Some('-') | Some('0'..='9') => self.integer().map(Value::Integer),
+            _ if self.rest().starts_with("true") => {
+                self.pos += 4;
+                Ok(Value::Boolean(true))
+            },
+            _ if self.rest().starts_with("false") => {
+                self.pos += 5;
+                Ok(Value::Boolean(false))
+            },
+            _ => Err(self.error("expected a value"))
+        }
+    }
+
+    fn inline_table(&mut self) -> ::Result<Value> {
+        let mut entries = Vec::new();
+
+        self.pos += 1;
+        self.skip_whitespace();
+
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Table(entries));
+        }
+
+        loop {
+            let path = self.key()?;
+            self.expect('=')?;
+            let value = self.value()?;
+
+            insert(&mut entries, &path, value).map_err(|err| self.error(&err))?;
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(entries));
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> ::Result<String> {
+        if self.rest().starts_with("\"\"\"") {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+
+        let mut s = String::new();
+        self.pos += 1;
+
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string"))
+            };
+
+            self.pos += c.len_utf8();
+
+            match c {
+                '"' => return Ok(s),
+                '\\' => s.push(self.escape()?),
+                c => s.push(c)
+            }
+        }
+    }
+
+    fn escape(&mut self) -> ::Result<char> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("unterminated string"))
+        };
+
+        self.pos += 1;
+
+        let digits = match c {
+            '"' => return Ok('"'),
+            '\\' => return Ok('\\'),
+            'b' => return Ok('\u{8}'),
+            'f' => return Ok('\u{c}'),
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            'u' => 4,
+            'U' => 8,
+            _ => return Err(self.error("invalid escape in string"))
+        };
+
+        let c = self.line.get(self.pos..self.pos + digits)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(::std::char::from_u32);
+
+        match c {
+            Some(c) => {
+                self.pos += digits;
+                Ok(c)
+            },
+            None => Err(self.error("invalid escape in string"))
+        }
+    }
+
+    fn literal_string(&mut self) -> ::Result<String> {
+        if self.rest().starts_with("'''") {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+
+        self.pos += 1;
+
+        match self.rest().find('\'') {
+            Some(len) => {
+                let s = self.rest()[..len].to_string();
+                self.pos += len + 1;
+                Ok(s)
+            },
+            None => Err(self.error("unterminated string"))
+        }
+    }
+
+    fn integer(&mut self) -> ::Result<i64> {
+        let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-' || c == '.' || c == ':')).unwrap_or(self.rest().len());
+        let token = &self.rest()[..len];
+        let digits = token.trim_start_matches(['+', '-']);
+
+        // underscores must be between digits
+        let valid = !digits.is_empty()
+            && digits.split('_').all(|group| !group.is_empty() && group.bytes().all(|b| b.is_ascii_digit()))
+            && (digits == "0" || !digits.starts_with('0'));
+
+        if !valid {
+            return Err(self.error(&format!("unsupported value: {}", token)));
+        }
+
+        let n = token.replace('_', "").parse().map_err(|_| self.error(&format!("invalid integer: {}", token)))?;
+        self.pos += len;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::super::Value;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn parse_tables_and_keys() {
+        let value = parse("top = 1_000 # comment\n\n[a.b]\nc = \"x\\ty\\u00e9\"\nd.e = 'C:\\path'\n[f]\ng = { h = -2, i = true }\n").unwrap();
+
+        assert_eq!(value, Value::Table(vec![
+            ("top".to_string(), Value::Integer(1000)),
+            ("a".to_string(), Value::Table(vec![
+                ("b".to_string(), Value::Table(vec![
+                    ("c".to_string(), string("x\ty\u{e9}")),
+                    ("d".to_string(), Value::Table(vec![("e".to_string(), string("C:\\path"))]))
+                ]))
+            ])),
+            ("f".to_string(), Value::Table(vec![
+                ("g".to_string(), Value::Table(vec![("h".to_string(), Value::Integer(-2)), ("i".to_string(), Value::Boolean(true))]))
+            ]))
+        ]));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_documents() {
+        for text in &["a", "a = ", "a = 1 2", "a = 1\na = 2", "[a]\n[a]", "a = [1]", "a = 1.5", "a = 1979-05-27", "a = 012", "a = \"x", "[[a]]", "a = 1\n[a]"] {
+            assert_eq!(parse(text).unwrap_err().kind(), ::ErrorKind::InvalidInput, "{:?}", text);
+        }
+    }
+}
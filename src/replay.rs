@@ -0,0 +1,184 @@
+//! Replaying captured traffic to a port.
+//!
+//! `replay()` writes the records of a capture back out a port with the gaps between them
+//! reproduced, optionally scaled, so that a device can be stimulated with exactly the traffic that
+//! triggered a timing-dependent bug:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use serial::analyzer::{CaptureReader,Direction,Format};
+//! use serial::replay::{self,ReplayOptions};
+//!
+//! let capture = CaptureReader::new(BufReader::new(File::open("link.pcapng").unwrap()), Format::Pcapng).unwrap();
+//! let mut port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! // replay what the host sent, at half speed
+//! let options = ReplayOptions { direction: Some(Direction::Tx), scale: 2.0, ..Default::default() };
+//! replay::replay(&mut port, capture, &options).unwrap();
+//! ```
+//!
+//! Timing is reproduced between records, i.e., between the reads that captured them, so it's as
+//! precise as the capture's timestamps. The bytes of each record are written back to back.
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant,SystemTime};
+
+use ::SerialPort;
+use ::analyzer::{Direction,Record};
+
+/// Options for replaying a capture.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct ReplayOptions {
+    /// The direction of the records that are written, or `None` to write every record.
+    pub direction: Option<Direction>,
+
+    /// The factor by which the gaps between records are multiplied.
+    ///
+    /// `1.0` reproduces the original timing, `2.0` replays at half speed, and `0.0` writes the
+    /// records without delays.
+    pub scale: f64,
+
+    /// The longest gap between records, before scaling.
+    ///
+    /// Longer gaps, such as idle periods in a long capture, are shortened to this.
+    pub max_gap: Option<Duration>
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            direction: None,
+            scale: 1.0,
+            max_gap: None
+        }
+    }
+}
+
+/// Writes the records of a capture to `port` with their original gaps, scaled by `options.scale`.
+///
+/// Each record is written at its scheduled time relative to the first record, so delays don't
+/// accumulate when writes are slow. Gaps are measured between the records that are written, so
+/// records of the other direction don't delay the replay. Returns the number of bytes written.
+///
+/// ## Errors
+///
+/// This function returns an error of kind `InvalidInput` if `options.scale` is negative or not
+/// finite, or if a scaled gap is too long to be scheduled. Otherwise, it returns the first error
+/// reading `records` or the first error writing to the port.
+pub fn replay<P, I>(port: &mut P, records: I, options: &ReplayOptions) -> ::Result<u64>
+    where P: SerialPort + ?Sized,
+          I: IntoIterator<Item = io::Result<Record>>
+{
+    if !options.scale.is_finite() || options.scale < 0.0 {
+        return Err(::Error::new(::ErrorKind::InvalidInput, format!("invalid scale: {}", options.scale)).with_operation("replay"));
+    }
+
+    let mut start: Option<Instant> = None;
+    let mut previous: Option<SystemTime> = None;
+    let mut offset = Duration::from_secs(0);
+    let mut written = 0;
+
+    for record in records {
+        let record = record.map_err(|err| ::Error::from(err).with_operation("replay"))?;
+
+        if options.direction.is_some_and(|direction| direction != record.direction) {
+            continue;
+        }
+
+        // timestamps that go backwards, e.g., across directions, don't delay the record
+        let gap = previous.and_then(|previous| record.timestamp.duration_since(previous).ok()).unwrap_or_default();
+        let gap = options.max_gap.map_or(gap, |max_gap| gap.min(max_gap));
+
+        previous = Some(record.timestamp);
+
+        let start = *start.get_or_insert_with(Instant::now);
+        let scaled = Duration::try_from_secs_f64(gap.as_secs_f64() * options.scale).ok();
+        offset = scaled.and_then(|scaled| offset.checked_add(scaled)).ok_or_else(gap_too_long)?;
+
+        let due = start.checked_add(offset).ok_or_else(gap_too_long)?;
+        let now = Instant::now();
+
+        if due > now {
+            thread::sleep(due - now);
+        }
+
+        port.write_all(&record.data)?;
+        port.flush()?;
+        written += record.data.len() as u64;
+    }
+
+    Ok(written)
+}
+
+fn gap_too_long() -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, "scaled gap is too long").with_operation("replay")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration,Instant,UNIX_EPOCH};
+
+    use super::{replay,ReplayOptions};
+    use ::analyzer::{Direction,Record};
+    use ::testing::ScriptedDevice;
+
+    fn record(millis: u64, direction: Direction, data: &[u8]) -> Record {
+        Record {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + millis),
+            direction,
+            data: data.to_vec()
+        }
+    }
+
+    fn capture() -> Vec<::std::io::Result<Record>> {
+        vec![
+            Ok(record(0, Direction::Tx, b"AT\r")),
+            Ok(record(10, Direction::Rx, b"OK\r\n")),
+            Ok(record(60, Direction::Tx, b"ATI\r"))
+        ]
+    }
+
+    #[test]
+    fn replay_writes_records_with_scaled_gaps() {
+        let mut device = ScriptedDevice::new();
+        let options = ReplayOptions { direction: Some(Direction::Tx), scale: 2.0, ..Default::default() };
+
+        let start = Instant::now();
+        assert_eq!(replay(&mut device, capture(), &options).unwrap(), 7);
+
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert_eq!(device.output(), b"AT\rATI\r");
+    }
+
+    #[test]
+    fn replay_caps_gaps() {
+        let mut device = ScriptedDevice::new();
+        let options = ReplayOptions { max_gap: Some(Duration::from_millis(1)), ..Default::default() };
+
+        let start = Instant::now();
+        assert_eq!(replay(&mut device, capture(), &options).unwrap(), 11);
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(device.output(), b"AT\rOK\r\nATI\r");
+    }
+
+    #[test]
+    fn replay_rejects_invalid_scale() {
+        let options = ReplayOptions { scale: -1.0, ..Default::default() };
+        assert_eq!(replay(&mut ScriptedDevice::new(), capture(), &options).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replay_rejects_gaps_that_overflow() {
+        for &scale in &[1e21, 1e300] {
+            let mut device = ScriptedDevice::new();
+            let options = ReplayOptions { scale, ..Default::default() };
+
+            assert_eq!(replay(&mut device, capture(), &options).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+            assert_eq!(device.output(), b"AT\r");
+        }
+    }
+}
@@ -0,0 +1,597 @@
+//! Scripted responses for simulating devices.
+//!
+//! A `Responder` matches the data received on a port against a list of rules and replies with
+//! each matching rule's response, optionally after a delay. It can also send messages
+//! periodically without being asked. This is enough to stand in for a modem, a GPS receiver, or a
+//! PLC in integration tests of other systems, on a real port or a virtual one:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::responder::{Pattern,Responder,Response,Rule};
+//!
+//! let mut responder = Responder::new();
+//! responder.add_rule(Rule::new(Pattern::bytes(b"AT\r"), Response::bytes(b"OK\r\n")));
+//! responder.add_rule(Rule {
+//!     delay: Duration::from_millis(200),
+//!     ..Rule::new(Pattern::wildcard(b"ATD*\r"), Response::template("CONNECT {1}\r\n"))
+//! });
+//! responder.add_periodic(Duration::from_secs(1), Response::bytes(b"RING\r\n"));
+//!
+//! let worker = responder.serve(serial::open("/dev/ttyUSB0").unwrap(), Default::default());
+//! ```
+//!
+//! Patterns are literal byte strings or wildcards. Other matchers, such as regular expressions from
+//! the `regex` crate's `bytes` module, can be plugged in with `Pattern::from_fn()`.
+//!
+//! Received data is searched for the earliest match of any rule. Data before the match is
+//! discarded, as is the matched data once it has been answered. When several rules match at the
+//! same position, the one that was added first wins.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::shutdown::{self,Drain};
+
+/// The data matched by a pattern and its capture groups.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Captures {
+    groups: Vec<Vec<u8>>
+}
+
+impl Captures {
+    /// Returns the whole match for index 0, or the numbered capture group for other indices.
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.groups.get(index).map(|group| &group[..])
+    }
+
+    /// Returns the number of groups, including the whole match.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Returns `true` if there are no groups, which is never the case for a match.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+/// The result of matching a pattern: the range of the whole match and of each capture group.
+type Matched = (Range<usize>, Vec<Range<usize>>);
+
+type Matcher = Box<dyn Fn(&[u8]) -> Option<Matched> + Send>;
+
+type Generator = Box<dyn FnMut(&Captures) -> Vec<u8> + Send>;
+
+/// A pattern that received data is matched against.
+pub struct Pattern {
+    matcher: Matcher
+}
+
+impl Pattern {
+    /// Matches a literal byte string.
+    pub fn bytes(literal: &[u8]) -> Self {
+        let literal = literal.to_vec();
+
+        Pattern::from_fn(move |data| {
+            if literal.is_empty() {
+                return None;
+            }
+
+            data.windows(literal.len())
+                .position(|window| window == &literal[..])
+                .map(|start| (start..start + literal.len(), Vec::new()))
+        })
+    }
+
+    /// Matches a byte string with wildcards.
+    ///
+    /// `?` matches any single byte and `*` matches the shortest run of bytes, possibly empty, that
+    /// lets the rest of the pattern match. Each wildcard is a capture group, numbered from 1 in the
+    /// order they appear. A `\` matches the following byte literally, so `\*`, `\?`, and `\\`
+    /// match `*`, `?`, and `\`.
+    pub fn wildcard(pattern: &[u8]) -> Self {
+        let mut tokens = Vec::new();
+        let mut escaped = false;
+
+        for &byte in pattern {
+            tokens.push(match byte {
+                _ if escaped => Token::Literal(byte),
+                b'\\' => {
+                    escaped = true;
+                    continue;
+                },
+                b'?' => Token::One,
+                b'*' => Token::Any,
+                _ => Token::Literal(byte)
+            });
+
+            escaped = false;
+        }
+
+        Pattern::from_fn(move |data| {
+            if tokens.is_empty() {
+                return None;
+            }
+
+            (0..data.len()).filter_map(|start| {
+                let mut groups = Vec::new();
+                match_wildcard(&tokens, data, start, &mut groups).map(|end| (start..end, groups))
+            }).next()
+        })
+    }
+
+    /// Matches with a function.
+    ///
+    /// The function is given all unanswered data and returns the range of the earliest match
+    /// along with the ranges of its capture groups, or `None` if the data doesn't contain a match
+    /// yet. The ranges must lie within the data.
+    pub fn from_fn<F>(matcher: F) -> Self
+        where F: Fn(&[u8]) -> Option<(Range<usize>, Vec<Range<usize>>)> + Send + 'static
+    {
+        Pattern { matcher: Box::new(matcher) }
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Pattern")
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+enum Token {
+    Literal(u8),
+    One,
+    Any
+}
+
+/// Matches `tokens` against `data` at `pos`, returning the end of the match.
+fn match_wildcard(tokens: &[Token], data: &[u8], pos: usize, groups: &mut Vec<Range<usize>>) -> Option<usize> {
+    let token = match tokens.first() {
+        Some(token) => *token,
+        None => return Some(pos)
+    };
+
+    match token {
+        Token::Literal(byte) => {
+            if data.get(pos) == Some(&byte) {
+                match_wildcard(&tokens[1..], data, pos + 1, groups)
+            }
+            else {
+                None
+            }
+        },
+        Token::One => {
+            if pos < data.len() {
+                groups.push(pos..pos + 1);
+
+                let end = match_wildcard(&tokens[1..], data, pos + 1, groups);
+                if end.is_none() {
+                    groups.pop();
+                }
+
+                end
+            }
+            else {
+                None
+            }
+        },
+        Token::Any => {
+            for end in pos..data.len() + 1 {
+                groups.push(pos..end);
+
+                if let Some(end) = match_wildcard(&tokens[1..], data, end, groups) {
+                    return Some(end);
+                }
+
+                groups.pop();
+            }
+
+            None
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Part {
+    Literal(Vec<u8>),
+    Group(usize)
+}
+
+/// The data sent in reply to a match.
+pub struct Response {
+    generator: Generator
+}
+
+impl Response {
+    /// Replies with fixed data.
+    pub fn bytes(data: &[u8]) -> Self {
+        let data = data.to_vec();
+        Response::from_fn(move |_| data.clone())
+    }
+
+    /// Replies with a template in which `{0}` is replaced by the whole match and `{1}`, `{2}`, and
+    /// so on by the capture groups.
+    ///
+    /// `{{` and `}}` stand for literal braces. Placeholders for groups that don't exist are
+    /// replaced by nothing.
+    pub fn template(template: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = Vec::new();
+        let mut rest = template;
+
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push(c as u8);
+                rest = &rest[2..];
+                continue;
+            }
+
+            if c == '{' {
+                if let Some(close) = rest.find('}') {
+                    if let Ok(index) = rest[1..close].parse() {
+                        if !literal.is_empty() {
+                            parts.push(Part::Literal(literal.split_off(0)));
+                        }
+
+                        parts.push(Part::Group(index));
+                        rest = &rest[close + 1..];
+                        continue;
+                    }
+                }
+            }
+
+            literal.extend_from_slice(&rest.as_bytes()[..c.len_utf8()]);
+            rest = &rest[c.len_utf8()..];
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Response::from_fn(move |captures| {
+            let mut output = Vec::new();
+
+            for part in &parts {
+                match *part {
+                    Part::Literal(ref data) => output.extend_from_slice(data),
+                    Part::Group(index) => output.extend_from_slice(captures.get(index).unwrap_or(&[]))
+                }
+            }
+
+            output
+        })
+    }
+
+    /// Replies with the data returned by a function.
+    ///
+    /// The function is called for each match, so it can keep state, e.g. to count requests or
+    /// to generate a GPS sentence with the current time and its checksum. Periodic responses are
+    /// called with only an empty whole match.
+    pub fn from_fn<F>(generator: F) -> Self
+        where F: FnMut(&Captures) -> Vec<u8> + Send + 'static
+    {
+        Response { generator: Box::new(generator) }
+    }
+
+    fn generate(&mut self, captures: &Captures) -> Vec<u8> {
+        (self.generator)(captures)
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Response")
+    }
+}
+
+/// A pattern and the response sent when it matches.
+#[derive(Debug)]
+pub struct Rule {
+    /// The pattern that received data is matched against.
+    pub pattern: Pattern,
+
+    /// The response to a match.
+    pub response: Response,
+
+    /// Time between receiving the matched data and sending the response.
+    pub delay: Duration
+}
+
+impl Rule {
+    /// Creates a rule that responds immediately.
+    pub fn new(pattern: Pattern, response: Response) -> Self {
+        Rule {
+            pattern,
+            response,
+            delay: Duration::from_secs(0)
+        }
+    }
+}
+
+/// Options for serving a `Responder` on a port.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct ResponderOptions {
+    /// Maximum number of unanswered bytes kept for matching. Older data is discarded.
+    pub max_buffer: usize,
+
+    /// Maximum time to wait for received data before checking whether to stop.
+    pub poll_interval: Duration
+}
+
+impl Default for ResponderOptions {
+    fn default() -> Self {
+        ResponderOptions {
+            max_buffer: 4096,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// A scripted device that replies to received data.
+#[derive(Debug,Default)]
+pub struct Responder {
+    rules: Vec<Rule>,
+    periodic: Vec<(Duration, Response)>,
+    buffer: Vec<u8>
+}
+
+impl Responder {
+    /// Creates a responder without rules.
+    pub fn new() -> Self {
+        Responder::default()
+    }
+
+    /// Adds a rule. Rules are tried in the order they were added.
+    pub fn add_rule(&mut self, rule: Rule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Sends a response every `interval`, starting one interval after the responder is served.
+    pub fn add_periodic(&mut self, interval: Duration, response: Response) -> &mut Self {
+        self.periodic.push((interval, response));
+        self
+    }
+
+    /// Returns the received data that hasn't been matched yet.
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Matches received data against the rules.
+    ///
+    /// Returns each response with the delay after which it should be sent, in the order that
+    /// their matches were received.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<(Duration, Vec<u8>)> {
+        self.buffer.extend_from_slice(data);
+
+        let mut responses = Vec::new();
+
+        loop {
+            let mut best: Option<(usize, Matched)> = None;
+
+            for (i, rule) in self.rules.iter().enumerate() {
+                if let Some(matched) = (rule.pattern.matcher)(&self.buffer) {
+                    if best.as_ref().is_none_or(|best| matched.0.start < (best.1).0.start) {
+                        best = Some((i, matched));
+                    }
+                }
+            }
+
+            let (i, (whole, groups)) = match best {
+                Some(best) => best,
+                None => break
+            };
+
+            let captures = Captures {
+                groups: Some(whole.clone()).into_iter().chain(groups).map(|range| self.buffer[range].to_vec()).collect()
+            };
+
+            self.buffer.drain(..whole.end.max(whole.start + 1).min(self.buffer.len()));
+
+            let rule = &mut self.rules[i];
+            responses.push((rule.delay, rule.response.generate(&captures)));
+        }
+
+        responses
+    }
+
+    /// Discards received data that hasn't been matched yet.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Moves the responder and a port to a background thread that answers the port's data.
+    pub fn serve<P>(self, port: P, options: ResponderOptions) -> Worker<P>
+        where P: SerialPort + Send + 'static
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let drain = Arc::new(Drain::new());
+
+        let thread = {
+            let stop = stop.clone();
+            let drain = drain.clone();
+            thread::spawn(move || run(self, port, &stop, &drain, &options))
+        };
+
+        Worker { stop, drain, thread: Some(thread) }
+    }
+}
+
+/// A handle to a responder's thread.
+pub struct Worker<P> {
+    stop: Arc<AtomicBool>,
+    drain: Arc<Drain>,
+    thread: Option<JoinHandle<::Result<P>>>
+}
+
+impl<P> Worker<P> {
+    /// Stops the thread and returns the port.
+    ///
+    /// Responses that are still waiting for their delay to elapse are discarded.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if it stopped because of an I/O
+    /// error.
+    pub fn close(mut self) -> ::Result<P> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "responder thread").and_then(|result| result)
+    }
+
+    /// Sends the responses that are waiting for their delay, then stops the thread and returns
+    /// the port.
+    ///
+    /// The waiting responses are sent without the remainder of their delay, and sending stops
+    /// when `timeout` has passed. Responses that weren't sent by then are counted in
+    /// `Shutdown::unsent`. No more data is answered and no periodic responses are generated once
+    /// the thread starts sending the waiting responses.
+    ///
+    /// ## Errors
+    ///
+    /// `Shutdown::result` is an error of kind `Io(TimedOut)` if the thread didn't stop in time, or
+    /// the error that stopped the thread, if it stopped because of an I/O error.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Shutdown<P> {
+        let deadline = Instant::now() + timeout;
+        self.drain.request(deadline);
+
+        let result = shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "responder thread").and_then(|result| result);
+        ::Shutdown { result, unsent: self.drain.unsent() }
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn run<P: SerialPort>(mut responder: Responder, mut port: P, stop: &AtomicBool, drain: &Drain, options: &ResponderOptions) -> ::Result<P> {
+    let start = Instant::now();
+    let mut buf = [0; 1024];
+    let mut queue: Vec<(Instant, Vec<u8>)> = Vec::new();
+    let mut periodic: Vec<Instant> = responder.periodic.iter().map(|&(interval, _)| start + interval).collect();
+    let idle = Captures { groups: vec![Vec::new()] };
+
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        let draining = drain.deadline().is_some();
+
+        for (next, &mut (interval, ref mut response)) in periodic.iter_mut().zip(responder.periodic.iter_mut()) {
+            if *next <= now && !draining {
+                let data = response.generate(&idle);
+                drain.queued(data.len());
+                queue.push((*next, data));
+                *next += interval.max(Duration::from_millis(1));
+            }
+        }
+
+        // responses are sent in the order they're due, and in the order they were queued if due
+        // at the same time
+        queue.sort_by_key(|&(due, _)| due);
+
+        while !queue.is_empty() && (draining || queue[0].0 <= now) && !drain.expired() {
+            let (_, data) = queue.remove(0);
+            io::Write::write_all(&mut port, &data)?;
+            drain.written(data.len());
+        }
+
+        if draining {
+            break;
+        }
+
+        let next = queue.iter().map(|&(due, _)| due).chain(periodic.iter().cloned()).min();
+        let timeout = match next {
+            Some(next) => next.saturating_duration_since(now).min(options.poll_interval),
+            None => options.poll_interval
+        };
+
+        match port.read_with_timeout(&mut buf, timeout) {
+            Ok(n) => {
+                let received = Instant::now();
+
+                for (delay, data) in responder.feed(&buf[..n]) {
+                    drain.queued(data.len());
+                    queue.push((received + delay, data));
+                }
+
+                let excess = responder.buffer.len().saturating_sub(options.max_buffer);
+                responder.buffer.drain(..excess);
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(::Error::from(err))
+        }
+    }
+
+    Ok(port)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Pattern,Responder,Response,ResponderOptions,Rule};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn responder_answers_literal_patterns() {
+        let mut responder = Responder::new();
+        responder.add_rule(Rule::new(Pattern::bytes(b"AT\r"), Response::bytes(b"OK\r\n")));
+
+        assert!(responder.feed(b"A").is_empty());
+        assert_eq!(responder.feed(b"T\rnoise AT\r"), vec![(Duration::from_secs(0), b"OK\r\n".to_vec()), (Duration::from_secs(0), b"OK\r\n".to_vec())]);
+        assert!(responder.pending().is_empty());
+    }
+
+    #[test]
+    fn responder_fills_templates_from_wildcards() {
+        let mut responder = Responder::new();
+        responder.add_rule(Rule {
+            delay: Duration::from_millis(50),
+            ..Rule::new(Pattern::wildcard(b"ATD*\r"), Response::template("CONNECT {1} ({0}) {{{2}}}\r\n"))
+        });
+
+        let responses = responder.feed(b"xxATD5551234\r");
+
+        assert_eq!(responses, vec![(Duration::from_millis(50), b"CONNECT 5551234 (ATD5551234\r) {}\r\n".to_vec())]);
+    }
+
+    #[test]
+    fn responder_prefers_earliest_match() {
+        let mut responder = Responder::new();
+        responder.add_rule(Rule::new(Pattern::bytes(b"B"), Response::bytes(b"b")));
+        responder.add_rule(Rule::new(Pattern::wildcard(b"A?"), Response::template("a{1}")));
+
+        let responses: Vec<Vec<u8>> = responder.feed(b"AxB").into_iter().map(|(_, data)| data).collect();
+
+        assert_eq!(responses, vec![b"ax".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn responder_serves_port() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"PING\n");
+
+        let mut responder = Responder::new();
+        responder.add_rule(Rule::new(Pattern::bytes(b"PING\n"), Response::bytes(b"PONG\n")));
+        responder.add_periodic(Duration::from_millis(20), Response::bytes(b"TICK\n"));
+
+        let worker = responder.serve(device, ResponderOptions { poll_interval: Duration::from_millis(1), ..Default::default() });
+        thread::sleep(Duration::from_millis(100));
+
+        let output = worker.close().unwrap().output();
+
+        assert!(output.starts_with(b"PONG\nTICK\n"));
+    }
+}
@@ -0,0 +1,466 @@
+//! High-rate capture into a lock-free ring buffer.
+//!
+//! `capture()` moves a port to a dedicated thread that drains the operating system's receive
+//! buffer into a large single-producer, single-consumer ring buffer as fast as data arrives. The
+//! ring buffer absorbs bursts that would otherwise overrun the operating system's buffer when the
+//! consumer stalls. When the ring buffer itself fills up, the selected `Overflow` policy is
+//! applied.
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::ring::{self,CaptureOptions,Overflow};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let options = CaptureOptions { capacity: 16 << 20, overflow: Overflow::DropOldest, ..Default::default() };
+//! let mut capture = ring::capture(port, options);
+//!
+//! let mut buf = [0; 65536];
+//! let len = capture.consumer.read(&mut buf).unwrap();
+//! ```
+
+use std::cmp;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,AtomicU8,AtomicUsize,Ordering};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::shutdown;
+
+/// Policy applied when received data doesn't fit in the ring buffer.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Overflow {
+    /// Stop reading from the port until the consumer frees space.
+    ///
+    /// Unread data accumulates in the operating system's buffer, where it's subject to flow
+    /// control or may be lost if the buffer overflows.
+    Block,
+
+    /// Discard the oldest data in the ring buffer to make room for new data.
+    DropOldest,
+
+    /// Stop capturing and report an error once the buffered data has been consumed.
+    Error
+}
+
+/// Options for capturing into a ring buffer.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct CaptureOptions {
+    /// Size of the ring buffer in bytes.
+    pub capacity: usize,
+
+    /// Maximum number of bytes read from the port at once.
+    pub chunk_size: usize,
+
+    /// Policy applied when the ring buffer is full.
+    pub overflow: Overflow,
+
+    /// Maximum time to wait for received data before checking whether to stop.
+    pub poll_interval: Duration
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            capacity: 1 << 20,
+            chunk_size: 4096,
+            overflow: Overflow::Block,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// A port being captured by a background thread.
+pub struct Capture<P> {
+    /// Reads captured data from the ring buffer.
+    pub consumer: Consumer,
+
+    /// Controls the capture thread.
+    pub worker: Worker<P>
+}
+
+/// The reading side of a capture's ring buffer.
+///
+/// `Consumer` implements `io::Read`. Reads block until data is available and return `Ok(0)` once
+/// the capture has stopped and all captured data has been read.
+pub struct Consumer {
+    ring: Arc<Ring>
+}
+
+impl Consumer {
+    /// Returns the number of bytes available to read.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Returns `true` if no data is available to read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes that were lost because the ring buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.ring.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Reads available data without blocking.
+    ///
+    /// Returns the number of bytes read, which is zero if no data is available.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the capture stopped because the ring buffer overflowed
+    /// with `Overflow::Error`. The error is reported once all data captured before the overflow
+    /// has been read.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> ::Result<usize> {
+        let len = self.ring.pop(buf);
+
+        if len == 0 && self.ring.overflowed.load(Ordering::Acquire) {
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), "receive buffer overflowed").with_operation("capture"));
+        }
+
+        Ok(len)
+    }
+}
+
+impl io::Read for Consumer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // check before reading, so data pushed just before stopping isn't missed
+            let stopped = self.ring.stopped.load(Ordering::Acquire);
+
+            match self.try_read(buf)? {
+                0 if stopped => return Ok(0),
+                0 => thread::sleep(Duration::from_millis(1)),
+                n => return Ok(n)
+            }
+        }
+    }
+}
+
+/// A handle to a capture thread.
+pub struct Worker<P> {
+    ring: Arc<Ring>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<::Result<P>>>
+}
+
+impl<P> Worker<P> {
+    /// Stops the capture thread and returns the port.
+    ///
+    /// Data that was already captured remains available to the consumer.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if it stopped because of an I/O
+    /// error or an overflow with `Overflow::Error`.
+    pub fn close(mut self) -> ::Result<P> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "capture thread").and_then(|result| result)
+    }
+
+    /// Stops the thread like `close()`, waiting at most `timeout` for it to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the thread didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<P> {
+        let deadline = Instant::now() + timeout;
+        self.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "capture thread").and_then(|result| result)
+    }
+
+    /// Returns `true` if the capture thread has stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.ring.stopped.load(Ordering::Acquire)
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Moves a port to a thread that captures received data into a ring buffer.
+pub fn capture<P>(port: P, options: CaptureOptions) -> Capture<P>
+    where P: SerialPort + Send + 'static
+{
+    let ring = Arc::new(Ring::new(options.capacity.max(1)));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let thread = {
+        let ring = ring.clone();
+        let stop = stop.clone();
+
+        thread::spawn(move || {
+            let result = run(port, &ring, &stop, &options);
+            ring.stopped.store(true, Ordering::Release);
+            result
+        })
+    };
+
+    Capture {
+        consumer: Consumer { ring: ring.clone() },
+        worker: Worker { ring, stop, thread: Some(thread) }
+    }
+}
+
+fn run<P: SerialPort>(mut port: P, ring: &Ring, stop: &AtomicBool, options: &CaptureOptions) -> ::Result<P> {
+    let mut buf = vec![0; options.chunk_size.max(1)];
+
+    while !stop.load(Ordering::Relaxed) {
+        let len = match port.read_with_timeout(&mut buf, options.poll_interval) {
+            Ok(0) => continue,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(::Error::from(err))
+        };
+
+        let mut data = &buf[..len];
+
+        match options.overflow {
+            Overflow::Block => {
+                while !data.is_empty() {
+                    let pushed = ring.push(data);
+                    data = &data[pushed..];
+
+                    if pushed == 0 {
+                        if stop.load(Ordering::Relaxed) {
+                            ring.overruns.fetch_add(data.len(), Ordering::Relaxed);
+                            break;
+                        }
+
+                        thread::sleep(options.poll_interval);
+                    }
+                }
+            },
+            Overflow::DropOldest => {
+                ring.push_overwrite(data);
+            },
+            Overflow::Error => {
+                let pushed = ring.push(data);
+
+                if pushed < data.len() {
+                    ring.overruns.fetch_add(data.len() - pushed, Ordering::Relaxed);
+                    ring.overflowed.store(true, Ordering::Release);
+                    return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), "receive buffer overflowed").with_operation("capture"));
+                }
+            }
+        }
+    }
+
+    Ok(port)
+}
+
+/// A lock-free single-producer, single-consumer byte ring buffer.
+///
+/// `head` and `tail` are monotonically increasing byte positions, which are reduced modulo the
+/// capacity to index the buffer. Only the producer advances `head`. Both sides advance `tail`
+/// with compare-and-swap: the consumer after reading, and the producer when it discards the
+/// oldest data. A consumer whose compare-and-swap fails has raced with the producer overwriting
+/// the data it read, so it discards that data and retries.
+struct Ring {
+    buf: Box<[AtomicU8]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overruns: AtomicUsize,
+    overflowed: AtomicBool,
+    stopped: AtomicBool
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Ring {
+            buf: (0..capacity).map(|_| AtomicU8::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            overflowed: AtomicBool::new(false),
+            stopped: AtomicBool::new(false)
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+
+    /// Copies as much of `data` as fits into the ring buffer. Producer only.
+    fn push(&self, data: &[u8]) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        let free = self.capacity() - head.wrapping_sub(tail);
+        let len = cmp::min(free, data.len());
+
+        self.store(head, &data[..len]);
+        self.head.store(head.wrapping_add(len), Ordering::Release);
+
+        len
+    }
+
+    /// Copies `data` into the ring buffer, discarding the oldest data to make room. Producer only.
+    fn push_overwrite(&self, mut data: &[u8]) {
+        if data.len() > self.capacity() {
+            let excess = data.len() - self.capacity();
+            self.overruns.fetch_add(excess, Ordering::Relaxed);
+            data = &data[excess..];
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        let mut tail = self.tail.load(Ordering::Acquire);
+
+        loop {
+            let free = self.capacity() - head.wrapping_sub(tail);
+            if free >= data.len() {
+                break;
+            }
+
+            let discard = data.len() - free;
+            match self.tail.compare_exchange(tail, tail.wrapping_add(discard), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.overruns.fetch_add(discard, Ordering::Relaxed);
+                    break;
+                },
+                Err(current) => tail = current
+            }
+        }
+
+        self.store(head, data);
+        self.head.store(head.wrapping_add(data.len()), Ordering::Release);
+    }
+
+    /// Moves up to `buf.len()` bytes out of the ring buffer. Consumer only.
+    fn pop(&self, buf: &mut [u8]) -> usize {
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            let head = self.head.load(Ordering::Acquire);
+
+            let len = cmp::min(head.wrapping_sub(tail), buf.len());
+            if len == 0 {
+                return 0;
+            }
+
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = self.buf[tail.wrapping_add(i) % self.capacity()].load(Ordering::Relaxed);
+            }
+
+            if self.tail.compare_exchange(tail, tail.wrapping_add(len), Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                return len;
+            }
+        }
+    }
+
+    fn store(&self, position: usize, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.buf[position.wrapping_add(i) % self.capacity()].store(byte, Ordering::Relaxed);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+
+    use super::{capture,CaptureOptions,Overflow,Ring};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn ring_wraps_around() {
+        let ring = Ring::new(4);
+        let mut buf = [0; 4];
+
+        assert_eq!(ring.push(b"abc"), 3);
+        assert_eq!(ring.pop(&mut buf[..2]), 2);
+        assert_eq!(ring.push(b"def"), 3);
+        assert_eq!(ring.pop(&mut buf), 4);
+        assert_eq!(&buf, b"cdef");
+    }
+
+    #[test]
+    fn ring_push_stops_when_full() {
+        let ring = Ring::new(4);
+
+        assert_eq!(ring.push(b"abcdef"), 4);
+        assert_eq!(ring.push(b"g"), 0);
+        assert_eq!(ring.len(), 4);
+    }
+
+    #[test]
+    fn ring_push_overwrite_discards_oldest() {
+        let ring = Ring::new(4);
+        let mut buf = [0; 4];
+
+        ring.push(b"abc");
+        ring.push_overwrite(b"def");
+
+        assert_eq!(ring.pop(&mut buf), 4);
+        assert_eq!(&buf, b"cdef");
+        assert_eq!(ring.overruns.load(::std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn ring_push_overwrite_keeps_end_of_large_data() {
+        let ring = Ring::new(4);
+        let mut buf = [0; 4];
+
+        ring.push_overwrite(b"abcdef");
+
+        assert_eq!(ring.pop(&mut buf), 4);
+        assert_eq!(&buf, b"cdef");
+    }
+
+    #[test]
+    fn capture_delivers_received_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello ");
+        device.push_input(b"world");
+
+        let mut capture = capture(device, Default::default());
+
+        let mut data = Vec::new();
+        while data.len() < 11 {
+            let mut buf = [0; 16];
+            let len = capture.consumer.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..len]);
+        }
+
+        assert_eq!(data, b"hello world");
+        capture.worker.close().unwrap();
+    }
+
+    #[test]
+    fn capture_reports_overflow_after_buffered_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"abcdef");
+
+        let options = CaptureOptions { capacity: 4, overflow: Overflow::Error, ..Default::default() };
+        let mut capture = capture(device, options);
+
+        let mut buf = [0; 16];
+        let len = capture.consumer.read(&mut buf).unwrap();
+
+        assert_eq!(&buf[..len], b"abcd");
+        assert!(capture.consumer.read(&mut buf).is_err());
+        assert_eq!(capture.consumer.overruns(), 2);
+        assert!(capture.worker.close().is_err());
+    }
+}
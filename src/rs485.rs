@@ -0,0 +1,537 @@
+//! Helpers for half-duplex buses such as RS-485.
+//!
+//! On a half-duplex bus, a master sends a request and then listens for the response from the
+//! addressed device. The `transact()` function implements this poll/response cycle on top of any
+//! `SerialPort`.
+//!
+//! On a 2-wire RS-485 bus, a transceiver whose receiver is always enabled also receives the bytes
+//! it transmits. `transact()` can read back and verify this echo before reading the response,
+//! which also detects collisions with other devices transmitting at the same time.
+//!
+//! Transceivers whose driver is enabled by a control signal or a GPIO pin can be driven by
+//! wrapping the port in a `HalfDuplex`, which enables the driver around every write with a
+//! pluggable `DirectionControl` strategy:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use std::time::Duration;
+//! use serial::rs485::{HalfDuplex,RtsToggle};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let mut bus = HalfDuplex::new(port, RtsToggle::default());
+//! bus.set_post_delay(Duration::from_micros(500));
+//!
+//! bus.write_all(b"#01\r").unwrap();
+//! ```
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::{SerialDevice,SerialPort};
+
+/// Options for a half-duplex transaction.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct TransactOptions {
+    /// Maximum time to wait for the first byte of the response.
+    pub first_byte_timeout: Duration,
+
+    /// Maximum gap between bytes of the response.
+    ///
+    /// The response is considered complete when no byte has been received for this long.
+    pub inter_byte_timeout: Duration,
+
+    /// Whether RTS controls the direction of an RS-485 transceiver.
+    ///
+    /// When `true`, RTS is asserted while the request is transmitted and cleared once the request
+    /// has been drained from the transmit buffer.
+    pub rts_direction: bool,
+
+    /// Whether the transmitted request is echoed back by the transceiver.
+    ///
+    /// When `true`, the echo of the request is read and compared to the request before the
+    /// response is read. A mismatch indicates a bus collision.
+    pub cancel_echo: bool
+}
+
+impl Default for TransactOptions {
+    fn default() -> Self {
+        TransactOptions {
+            first_byte_timeout: Duration::from_secs(1),
+            inter_byte_timeout: Duration::from_millis(20),
+            rts_direction: false,
+            cancel_echo: false
+        }
+    }
+}
+
+/// Sends a request and reads the response on a half-duplex bus.
+///
+/// The transaction proceeds in the following steps:
+///
+/// 1. Stale data in the port's input buffer is discarded.
+/// 2. RTS is asserted if `options.rts_direction` is set.
+/// 3. The request is written and drained from the transmit buffer.
+/// 4. RTS is cleared if `options.rts_direction` is set.
+/// 5. The echo of the request is read and verified if `options.cancel_echo` is set.
+/// 6. The response is read into `response` until it's full or the line has been idle for
+///    `options.inter_byte_timeout`.
+///
+/// Returns the number of bytes of the response that were read.
+///
+/// ## Example
+///
+/// ```no_run
+/// use serial::rs485::{self,TransactOptions};
+///
+/// let mut port = serial::open("/dev/ttyUSB0").unwrap();
+/// let mut response = [0; 256];
+///
+/// let options = TransactOptions { rts_direction: true, ..Default::default() };
+/// let len = rs485::transact(&mut port, b"#01\r", &mut response, &options).unwrap();
+///
+/// println!("{:?}", &response[..len]);
+/// ```
+///
+/// ## Errors
+///
+/// This function returns an error if the request could not be sent or no response was received:
+///
+/// * `NoDevice` if the device was disconnected.
+/// * `Io(TimedOut)` if no response was received within `options.first_byte_timeout`, or if the
+///   echo of the request was incomplete.
+/// * `Io(InvalidData)` if the echo of the request didn't match the request, indicating a bus
+///   collision.
+/// * `Io` for any other type of I/O error.
+pub fn transact<T: SerialPort + ?Sized>(port: &mut T, request: &[u8], response: &mut [u8], options: &TransactOptions) -> ::Result<usize> {
+    port.discard_input()?;
+
+    if options.rts_direction {
+        port.set_rts(true)?;
+    }
+
+    let sent = send(port, request);
+
+    if options.rts_direction {
+        port.set_rts(false)?;
+    }
+
+    sent?;
+
+    if options.cancel_echo {
+        cancel_echo(port, request, options)?;
+    }
+
+    let len = read_response(port, response, options)?;
+
+    if len == 0 && !response.is_empty() {
+        return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "no response received").with_operation("transact"));
+    }
+
+    Ok(len)
+}
+
+fn send<T: SerialPort + ?Sized>(port: &mut T, request: &[u8]) -> ::Result<()> {
+    port.write_all(request)?;
+    port.flush()?;
+    Ok(())
+}
+
+/// Reads back the echo of a transmitted request and verifies that it matches.
+fn cancel_echo<T: SerialPort + ?Sized>(port: &mut T, request: &[u8], options: &TransactOptions) -> ::Result<()> {
+    let mut echo = vec![0; request.len()];
+    let len = read_response(port, &mut echo, options)?;
+
+    if len < request.len() {
+        return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "incomplete echo of request").with_operation("transact"));
+    }
+
+    if echo != request {
+        return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), "bus collision: echo did not match request").with_operation("transact"));
+    }
+
+    Ok(())
+}
+
+/// Reads a response until `buf` is full or the line is idle for the inter-byte timeout.
+fn read_response<T: SerialPort + ?Sized>(port: &mut T, buf: &mut [u8], options: &TransactOptions) -> ::Result<usize> {
+    let mut len = 0;
+    let mut timeout = options.first_byte_timeout;
+
+    while len < buf.len() {
+        match port.read_with_timeout(&mut buf[len..], timeout) {
+            Ok(0) => break,
+            Ok(n) => {
+                len += n;
+                timeout = options.inter_byte_timeout;
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(::Error::from(err))
+        }
+    }
+
+    Ok(len)
+}
+
+/// A strategy for switching a half-duplex transceiver between transmitting and receiving.
+///
+/// `HalfDuplex` calls `set_transmit(port, true)` before writing and `set_transmit(port, false)`
+/// once the written data has been transmitted. Implement this trait to support transceivers
+/// wired in other ways.
+pub trait DirectionControl {
+    /// Enables the transmitter if `transmit` is `true`, or the receiver if it's `false`.
+    fn set_transmit<P: SerialPort + ?Sized>(&mut self, port: &mut P, transmit: bool) -> ::Result<()>;
+}
+
+/// Leaves direction control to the hardware, e.g., a transceiver with automatic direction control.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct NoControl;
+
+impl DirectionControl for NoControl {
+    fn set_transmit<P: SerialPort + ?Sized>(&mut self, _: &mut P, _: bool) -> ::Result<()> {
+        Ok(())
+    }
+}
+
+/// Enables the transmitter with the RTS signal.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct RtsToggle {
+    /// Clear RTS instead of asserting it while transmitting.
+    pub invert: bool
+}
+
+impl DirectionControl for RtsToggle {
+    fn set_transmit<P: SerialPort + ?Sized>(&mut self, port: &mut P, transmit: bool) -> ::Result<()> {
+        port.set_rts(transmit != self.invert)
+    }
+}
+
+/// Enables the transmitter with the DTR signal.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct DtrToggle {
+    /// Clear DTR instead of asserting it while transmitting.
+    pub invert: bool
+}
+
+impl DirectionControl for DtrToggle {
+    fn set_transmit<P: SerialPort + ?Sized>(&mut self, port: &mut P, transmit: bool) -> ::Result<()> {
+        port.set_dtr(transmit != self.invert)
+    }
+}
+
+/// Enables the transmitter by calling a function, e.g., to drive a GPIO pin.
+///
+/// The function is called with `true` before transmitting and `false` afterwards.
+pub struct GpioCallback<F>(pub F);
+
+impl<F> DirectionControl for GpioCallback<F> where F: FnMut(bool) -> ::Result<()> {
+    fn set_transmit<P: SerialPort + ?Sized>(&mut self, _: &mut P, transmit: bool) -> ::Result<()> {
+        (self.0)(transmit)
+    }
+}
+
+/// A serial port on a half-duplex bus that enables its transmitter around every write.
+///
+/// Each call to `write()` enables the transmitter, waits for the pre-transmit delay, writes and
+/// drains the entire buffer, waits for the post-transmit delay, and enables the receiver again.
+/// Buffer a complete message before writing it, so that it's transmitted in one piece.
+///
+/// `HalfDuplex` is itself a `SerialPort`, so it can be used with `transact()` and any other code
+/// that works with serial ports.
+pub struct HalfDuplex<P, D> {
+    port: P,
+    direction: D,
+    pre_delay: Duration,
+    post_delay: Duration
+}
+
+impl<P: SerialPort, D: DirectionControl> HalfDuplex<P, D> {
+    /// Wraps a port with the given direction control strategy and no delays.
+    pub fn new(port: P, direction: D) -> Self {
+        HalfDuplex {
+            port,
+            direction,
+            pre_delay: Duration::from_secs(0),
+            post_delay: Duration::from_secs(0)
+        }
+    }
+
+    /// Returns the delay between enabling the transmitter and starting to transmit.
+    pub fn pre_delay(&self) -> Duration {
+        self.pre_delay
+    }
+
+    /// Sets the delay between enabling the transmitter and starting to transmit.
+    ///
+    /// This is the software-emulated equivalent of `delay_rts_before_send`. Delays are timed with
+    /// microsecond precision by busy-waiting for the final millisecond.
+    #[doc(alias = "delay_rts_before_send")]
+    pub fn set_pre_delay(&mut self, delay: Duration) {
+        self.pre_delay = delay;
+    }
+
+    /// Returns the delay between the end of transmission and enabling the receiver.
+    pub fn post_delay(&self) -> Duration {
+        self.post_delay
+    }
+
+    /// Sets the delay between the end of transmission and enabling the receiver.
+    ///
+    /// This is the software-emulated equivalent of `delay_rts_after_send`, which gives slow
+    /// devices on the bus a guaranteed turnaround gap. Delays are timed with microsecond precision
+    /// by busy-waiting for the final millisecond.
+    #[doc(alias = "delay_rts_after_send")]
+    pub fn set_post_delay(&mut self, delay: Duration) {
+        self.post_delay = delay;
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn transmit(&mut self, buf: &[u8]) -> ::Result<()> {
+        self.direction.set_transmit(&mut self.port, true)?;
+
+        delay(self.pre_delay);
+        let sent = send(&mut self.port, buf);
+
+        if sent.is_ok() {
+            delay(self.post_delay);
+        }
+
+        let released = self.direction.set_transmit(&mut self.port, false);
+
+        sent?;
+        released
+    }
+}
+
+/// Waits for `duration`, sleeping for most of it and busy-waiting for the final millisecond.
+fn delay(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let spin = Duration::from_millis(1);
+
+    if duration > spin {
+        thread::sleep(duration - spin);
+    }
+
+    while Instant::now() < deadline {
+        ::std::hint::spin_loop();
+    }
+}
+
+impl<P: SerialPort, D: DirectionControl> io::Read for HalfDuplex<P, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl<P: SerialPort, D: DirectionControl> io::Write for HalfDuplex<P, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transmit(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: SerialDevice, D: DirectionControl> SerialDevice for HalfDuplex<P, D> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, write_settings, read_with_timeout, discard_input);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use std::io::prelude::*;
+    use std::sync::{Arc,Mutex};
+    use std::time::{Duration,Instant};
+
+    use super::{transact,DtrToggle,GpioCallback,HalfDuplex,RtsToggle,TransactOptions};
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn transact_writes_request_and_reads_response() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"+01");
+        device.push_input(b"23.5\r");
+
+        let mut response = [0; 16];
+        let len = transact(&mut device, b"#01\r", &mut response, &Default::default()).unwrap();
+
+        assert_eq!(&response[..len], b"+0123.5\r");
+        assert_eq!(device.events, vec![Event::DiscardInput, Event::Write(b"#01\r".to_vec()), Event::Flush]);
+    }
+
+    #[test]
+    fn transact_toggles_rts_around_request() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"ok");
+
+        let options = TransactOptions { rts_direction: true, ..Default::default() };
+        let mut response = [0; 16];
+        transact(&mut device, b"go", &mut response, &options).unwrap();
+
+        assert_eq!(device.events, vec![
+            Event::DiscardInput,
+            Event::Rts(true),
+            Event::Write(b"go".to_vec()),
+            Event::Flush,
+            Event::Rts(false)
+        ]);
+    }
+
+    #[test]
+    fn transact_stops_when_response_buffer_is_full() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"0123456789");
+
+        let mut response = [0; 4];
+        let len = transact(&mut device, b"?", &mut response, &Default::default()).unwrap();
+
+        assert_eq!(&response[..len], b"0123");
+    }
+
+    #[test]
+    fn transact_times_out_without_response() {
+        let mut device = ScriptedDevice::new();
+
+        let mut response = [0; 4];
+        let err = transact(&mut device, b"?", &mut response, &Default::default()).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn transact_discards_echo_before_response() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"#0");
+        device.push_input(b"1\r>");
+        device.push_input(b"42\r");
+
+        let options = TransactOptions { cancel_echo: true, ..Default::default() };
+        let mut response = [0; 16];
+        let len = transact(&mut device, b"#01\r", &mut response, &options).unwrap();
+
+        assert_eq!(&response[..len], b">42\r");
+    }
+
+    #[test]
+    fn transact_detects_collision_in_echo() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"#0\xff\r");
+
+        let options = TransactOptions { cancel_echo: true, ..Default::default() };
+        let mut response = [0; 16];
+        let err = transact(&mut device, b"#01\r", &mut response, &options).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn transact_reports_missing_echo() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"#0");
+
+        let options = TransactOptions { cancel_echo: true, ..Default::default() };
+        let mut response = [0; 16];
+        let err = transact(&mut device, b"#01\r", &mut response, &options).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn half_duplex_toggles_rts_around_each_write() {
+        let mut bus = HalfDuplex::new(ScriptedDevice::new(), RtsToggle::default());
+
+        bus.write_all(b"ping").unwrap();
+
+        assert_eq!(bus.get_ref().events, vec![
+            Event::Rts(true),
+            Event::Write(b"ping".to_vec()),
+            Event::Flush,
+            Event::Rts(false)
+        ]);
+    }
+
+    #[test]
+    fn half_duplex_inverts_dtr() {
+        let mut bus = HalfDuplex::new(ScriptedDevice::new(), DtrToggle { invert: true });
+
+        bus.write_all(b"x").unwrap();
+
+        assert_eq!(bus.get_ref().events, vec![
+            Event::Dtr(false),
+            Event::Write(b"x".to_vec()),
+            Event::Flush,
+            Event::Dtr(true)
+        ]);
+    }
+
+    #[test]
+    fn half_duplex_calls_gpio_callback() {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let recorded = levels.clone();
+
+        let gpio = GpioCallback(move |level| {
+            recorded.lock().unwrap().push(level);
+            Ok(())
+        });
+
+        let mut bus = HalfDuplex::new(ScriptedDevice::new(), gpio);
+        bus.write_all(b"x").unwrap();
+
+        assert_eq!(*levels.lock().unwrap(), vec![true, false]);
+        assert_eq!(bus.get_ref().output(), b"x");
+    }
+
+    #[test]
+    fn half_duplex_works_with_transact() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"ok");
+
+        let mut bus = HalfDuplex::new(device, RtsToggle::default());
+        let mut response = [0; 16];
+        let len = transact(&mut bus, b"go", &mut response, &Default::default()).unwrap();
+
+        assert_eq!(&response[..len], b"ok");
+        assert_eq!(bus.get_ref().events, vec![
+            Event::DiscardInput,
+            Event::Rts(true),
+            Event::Write(b"go".to_vec()),
+            Event::Flush,
+            Event::Rts(false),
+            Event::Flush
+        ]);
+    }
+
+    #[test]
+    fn half_duplex_waits_for_turnaround_delay() {
+        let mut bus = HalfDuplex::new(ScriptedDevice::new(), RtsToggle::default());
+        bus.set_pre_delay(Duration::from_micros(300));
+        bus.set_post_delay(Duration::from_micros(1500));
+
+        let start = Instant::now();
+        bus.write_all(b"x").unwrap();
+
+        assert!(start.elapsed() >= Duration::from_micros(1800));
+    }
+}
@@ -0,0 +1,272 @@
+//! Parsers for the continuous output of industrial scales and balances.
+//!
+//! Most scales can be configured to print every weight reading as a line of text. The line
+//! formats differ between manufacturers, but carry the same information: the weight, its unit, and
+//! whether the reading is stable. `ScaleCodec` decodes these lines into `Reading`s:
+//!
+//! ```no_run
+//! use serial::codec::Framed;
+//! use serial::scale::{Format,ScaleCodec};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let mut scale = Framed::new(port, ScaleCodec::new(Format::And));
+//!
+//! loop {
+//!     let reading = scale.read_frame().unwrap();
+//!
+//!     if reading.is_stable() {
+//!         println!("{:?} {}", reading.weight, reading.unit);
+//!     }
+//! }
+//! ```
+
+use std::io;
+
+use ::codec::Decoder;
+use ::codec::lines::LineCodec;
+
+/// Output format of a scale.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Format {
+    /// Mettler Toledo MT-SICS weight responses, e.g., `S S     100.00 g`.
+    Toledo,
+
+    /// A&D header-prefixed output, e.g., `ST,+00100.00  g`.
+    And,
+
+    /// Ohaus continuous print output, e.g., `    100.00 g ? N`.
+    Ohaus
+}
+
+/// Status of a weight reading.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Status {
+    /// The weight has settled.
+    Stable,
+
+    /// The weight is still changing.
+    Unstable,
+
+    /// The load exceeds the scale's capacity.
+    Overload,
+
+    /// The load is below the scale's minimum, e.g., because the pan was removed.
+    Underload
+}
+
+/// A weight reading.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Reading {
+    /// Status of the reading.
+    pub status: Status,
+
+    /// The weight, or `None` if the scale didn't report a valid weight, e.g., while overloaded.
+    pub weight: Option<f64>,
+
+    /// The unit of the weight as printed by the scale, e.g., `g` or `kg`.
+    pub unit: String,
+
+    /// Whether the weight is a net weight, i.e., with the tare subtracted.
+    pub net: bool
+}
+
+impl Reading {
+    /// Returns `true` if the reading is stable and has a valid weight.
+    pub fn is_stable(&self) -> bool {
+        self.status == Status::Stable && self.weight.is_some()
+    }
+}
+
+/// Parses a single line of scale output.
+///
+/// ## Errors
+///
+/// Returns an error of kind `Io(InvalidData)` if the line isn't a weight reading in the given
+/// format.
+pub fn parse(format: Format, line: &str) -> ::Result<Reading> {
+    let reading = match format {
+        Format::Toledo => parse_toledo(line),
+        Format::And => parse_and(line),
+        Format::Ohaus => parse_ohaus(line)
+    };
+
+    reading.ok_or_else(|| ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), format!("invalid weight reading: {:?}", line)).with_operation("parse weight"))
+}
+
+fn parse_toledo(line: &str) -> Option<Reading> {
+    let mut tokens = line.split_whitespace();
+
+    if tokens.next() != Some("S") {
+        return None;
+    }
+
+    let status = match tokens.next()? {
+        "S" => Status::Stable,
+        "D" => Status::Unstable,
+        "+" => return Some(invalid_reading(Status::Overload)),
+        "-" => return Some(invalid_reading(Status::Underload)),
+        _ => return None
+    };
+
+    let weight = tokens.next()?.parse().ok()?;
+    let unit = tokens.next()?.to_string();
+
+    Some(Reading { status, weight: Some(weight), unit, net: false })
+}
+
+fn parse_and(line: &str) -> Option<Reading> {
+    let line = line.trim();
+    let comma = line.find(',')?;
+    let header = &line[..comma];
+
+    let status = match header {
+        "ST" | "QT" => Status::Stable,
+        "US" => Status::Unstable,
+        "OL" => return Some(invalid_reading(Status::Overload)),
+        _ => return None
+    };
+
+    let (weight, unit) = split_weight(&line[comma + 1..])?;
+
+    Some(Reading { status, weight: Some(weight), unit: unit.trim().to_string(), net: false })
+}
+
+fn parse_ohaus(line: &str) -> Option<Reading> {
+    let trimmed = line.trim();
+
+    if trimmed.contains("OL") {
+        return Some(invalid_reading(Status::Overload));
+    }
+
+    let (weight, rest) = split_weight(trimmed)?;
+    let unit = rest.split_whitespace().next().filter(|unit| *unit != "?").unwrap_or("").to_string();
+
+    let mut status = Status::Stable;
+    let mut net = false;
+
+    for token in rest.split_whitespace() {
+        match token {
+            "?" => status = Status::Unstable,
+            "N" | "NET" => net = true,
+            _ => ()
+        }
+    }
+
+    Some(Reading { status, weight: Some(weight), unit, net })
+}
+
+fn invalid_reading(status: Status) -> Reading {
+    Reading { status, weight: None, unit: String::new(), net: false }
+}
+
+/// Splits a number, whose sign may be separated from its digits by spaces, from the text after it.
+fn split_weight(text: &str) -> Option<(f64, &str)> {
+    let end = text.find(|c: char| !(c.is_ascii_digit() || c == '+' || c == '-' || c == '.' || c == ' ')).unwrap_or(text.len());
+    let number: String = text[..end].chars().filter(|&c| c != ' ').collect();
+
+    Some((number.parse().ok()?, &text[end..]))
+}
+
+/// A codec that decodes lines of scale output into weight readings.
+///
+/// Empty lines are skipped. A line that isn't a valid reading is removed from the buffer and
+/// reported as an error of kind `Io(InvalidData)`.
+#[derive(Debug,Clone)]
+pub struct ScaleCodec {
+    format: Format,
+    lines: LineCodec
+}
+
+impl ScaleCodec {
+    /// Creates a codec for the given output format.
+    pub fn new(format: Format) -> Self {
+        ScaleCodec {
+            format,
+            lines: LineCodec::with_max_length(256)
+        }
+    }
+
+    /// Returns the output format.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl Decoder for ScaleCodec {
+    type Item = Reading;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> ::Result<Option<Reading>> {
+        while let Some(line) = self.lines.decode(buf)? {
+            if !line.trim().is_empty() {
+                return parse(self.format, &line).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{parse,Format,Reading,ScaleCodec,Status};
+    use ::codec::{Decoder,Framed};
+    use ::testing::ScriptedDevice;
+
+    fn reading(status: Status, weight: f64, unit: &str, net: bool) -> Reading {
+        Reading { status, weight: Some(weight), unit: unit.to_string(), net }
+    }
+
+    #[test]
+    fn parse_toledo_readings() {
+        assert_eq!(parse(Format::Toledo, "S S     100.00 g").unwrap(), reading(Status::Stable, 100.0, "g", false));
+        assert_eq!(parse(Format::Toledo, "S D      -2.5 kg").unwrap(), reading(Status::Unstable, -2.5, "kg", false));
+        assert_eq!(parse(Format::Toledo, "S +").unwrap().status, Status::Overload);
+        assert!(parse(Format::Toledo, "ES").is_err());
+    }
+
+    #[test]
+    fn parse_and_readings() {
+        assert_eq!(parse(Format::And, "ST,+00123.45  g").unwrap(), reading(Status::Stable, 123.45, "g", false));
+        assert_eq!(parse(Format::And, "US,-00001.20 kg").unwrap(), reading(Status::Unstable, -1.2, "kg", false));
+        assert_eq!(parse(Format::And, "QT,+0000012 PC").unwrap(), reading(Status::Stable, 12.0, "PC", false));
+
+        let overload = parse(Format::And, "OL,+9999999 E").unwrap();
+        assert_eq!(overload.status, Status::Overload);
+        assert!(!overload.is_stable());
+    }
+
+    #[test]
+    fn parse_ohaus_readings() {
+        assert_eq!(parse(Format::Ohaus, "    100.00 g").unwrap(), reading(Status::Stable, 100.0, "g", false));
+        assert_eq!(parse(Format::Ohaus, "-    0.52 kg ? N").unwrap(), reading(Status::Unstable, -0.52, "kg", true));
+        assert_eq!(parse(Format::Ohaus, "  -OL-").unwrap().status, Status::Overload);
+
+        let err = parse(Format::Ohaus, "hello").unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn codec_skips_empty_lines() {
+        let mut codec = ScaleCodec::new(Format::And);
+        let mut buf = b"\r\nST,+00001.00  g\r\nUS,+0".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(reading(Status::Stable, 1.0, "g", false)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn framed_reads_readings() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"S D      99.90 g\r\nS S");
+        device.push_input(b"     100.00 g\r\n");
+
+        let mut scale = Framed::new(device, ScaleCodec::new(Format::Toledo));
+
+        assert!(!scale.read_frame().unwrap().is_stable());
+        assert!(scale.read_frame().unwrap().is_stable());
+    }
+}
@@ -0,0 +1,353 @@
+//! Periodic polling of request/response devices.
+//!
+//! Data-logging applications typically send the same requests at fixed intervals. A `Scheduler`
+//! runs these polls on a background thread and delivers the responses to callbacks or channels:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::sync::mpsc;
+//! use std::time::Duration;
+//!
+//! use serial::codec::lines::LineCodec;
+//! use serial::scheduler::{Poll,Scheduler};
+//! use serial::transaction::{Engine,Priority,Queue};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let queue = Arc::new(Queue::new(Engine::new(port, LineCodec::new())));
+//!
+//! let (tx, rx) = mpsc::channel();
+//!
+//! let handle = Scheduler::new(queue.clone())
+//!     .poll(Poll::new("MEAS:TEMP?".to_string(), Duration::from_secs(1)).deliver_to(tx))
+//!     .start();
+//!
+//! // other threads can still send commands through the queue
+//! queue.transact(Priority::Urgent, &"OUTP OFF".to_string(), |_| true).unwrap();
+//!
+//! for response in rx.iter().take(10) {
+//!     println!("{:?}", response);
+//! }
+//!
+//! handle.close().unwrap();
+//! ```
+//!
+//! Polls are queued with `Priority::Low` by default, so that commands from other threads sharing
+//! the queue are sent first.
+
+use std::sync::Arc;
+use std::sync::mpsc::{self,RecvTimeoutError,Sender};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
+
+use ::SerialPort;
+use ::codec::{Decoder,Encoder};
+use ::shutdown;
+use ::transaction::{Priority,Queue,RetryPolicy};
+
+type Matcher<T> = Box<dyn FnMut(&T) -> bool + Send>;
+type Handler<T> = Box<dyn FnMut(::Result<T>) + Send>;
+
+/// Policy for a poll that's started later than scheduled.
+///
+/// A poll is late if the previous transaction took longer than the interval, or if the port was
+/// busy with other transactions.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Overrun {
+    /// Skip the missed polls and continue at the next scheduled time.
+    Skip,
+
+    /// Start the late poll immediately and shift the following polls by the delay.
+    Delay,
+
+    /// Start the missed polls back to back until the schedule has caught up.
+    Burst
+}
+
+/// A request that's sent periodically.
+pub struct Poll<T> {
+    request: T,
+    interval: Duration,
+    jitter: Duration,
+    overrun: Overrun,
+    priority: Priority,
+    policy: Option<RetryPolicy>,
+    matcher: Matcher<T>,
+    handler: Handler<T>
+}
+
+impl<T: Send + 'static> Poll<T> {
+    /// Creates a poll that sends `request` every `interval`.
+    ///
+    /// By default, the first response is accepted and discarded.
+    pub fn new(request: T, interval: Duration) -> Self {
+        Poll {
+            request,
+            interval,
+            jitter: Duration::ZERO,
+            overrun: Overrun::Skip,
+            priority: Priority::Low,
+            policy: None,
+            matcher: Box::new(|_| true),
+            handler: Box::new(|_| ())
+        }
+    }
+
+    /// Delays each poll by a random amount of up to `jitter`.
+    ///
+    /// Jitter prevents many polls with the same interval from being started at the same time.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the policy for late polls.
+    ///
+    /// The default is `Overrun::Skip`.
+    pub fn overrun(mut self, overrun: Overrun) -> Self {
+        self.overrun = overrun;
+        self
+    }
+
+    /// Sets the priority of the poll's transactions.
+    ///
+    /// The default is `Priority::Low`.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the retry policy of the poll's transactions.
+    ///
+    /// The engine's default policy is used if this isn't set.
+    pub fn policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Sets the function that accepts a response to the poll's request.
+    pub fn matcher<F>(mut self, matcher: F) -> Self
+        where F: FnMut(&T) -> bool + Send + 'static
+    {
+        self.matcher = Box::new(matcher);
+        self
+    }
+
+    /// Registers a callback that's called with the result of each transaction.
+    pub fn on_response<F>(mut self, callback: F) -> Self
+        where F: FnMut(::Result<T>) + Send + 'static
+    {
+        self.handler = Box::new(callback);
+        self
+    }
+
+    /// Sends the result of each transaction to a channel.
+    ///
+    /// Results are discarded after the receiver has been dropped.
+    pub fn deliver_to(self, sender: Sender<::Result<T>>) -> Self {
+        self.on_response(move |result| {
+            let _ = sender.send(result);
+        })
+    }
+}
+
+/// A builder for a thread that runs periodic polls.
+pub struct Scheduler<P, C: Decoder> {
+    queue: Arc<Queue<P, C>>,
+    polls: Vec<Poll<C::Item>>
+}
+
+impl<P, C> Scheduler<P, C>
+    where P: SerialPort + Send + 'static,
+          C: Decoder + Encoder<Item = <C as Decoder>::Item> + Send + 'static,
+          <C as Decoder>::Item: Send + 'static
+{
+    /// Creates a scheduler that runs transactions through `queue`.
+    pub fn new(queue: Arc<Queue<P, C>>) -> Self {
+        Scheduler {
+            queue,
+            polls: Vec::new()
+        }
+    }
+
+    /// Adds a poll.
+    pub fn poll(mut self, poll: Poll<<C as Decoder>::Item>) -> Self {
+        self.polls.push(poll);
+        self
+    }
+
+    /// Starts the background thread.
+    ///
+    /// All polls are first started immediately, after their jitter. The callbacks are invoked from
+    /// the background thread. The thread runs until the returned handle is closed or dropped.
+    pub fn start(self) -> SchedulerHandle {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            let Scheduler { queue, mut polls } = self;
+            let mut random = Random::new();
+
+            // jitter is applied to each poll without shifting the schedule
+            let start = Instant::now();
+            let mut scheduled: Vec<Instant> = polls.iter().map(|_| start).collect();
+            let mut due: Vec<Instant> = polls.iter().map(|poll| start + random.duration(poll.jitter)).collect();
+
+            while let Some(next) = (0..polls.len()).min_by_key(|&i| due[i]) {
+                let delay = due[next].saturating_duration_since(Instant::now());
+
+                match stop_rx.recv_timeout(delay) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    _ => return
+                }
+
+                let poll = &mut polls[next];
+                let policy = poll.policy.unwrap_or_else(|| queue.policy());
+
+                let result = queue.transact_with(poll.priority, &poll.request, &policy, &mut poll.matcher);
+                (poll.handler)(result);
+
+                scheduled[next] = next_due(scheduled[next], poll.interval, Instant::now(), poll.overrun);
+                due[next] = scheduled[next] + random.duration(poll.jitter);
+            }
+        });
+
+        SchedulerHandle {
+            stop: Some(stop_tx),
+            thread: Some(thread)
+        }
+    }
+}
+
+/// A handle to the background thread started by `Scheduler::start()`.
+pub struct SchedulerHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl SchedulerHandle {
+    /// Stops the thread after the transaction in progress, if any, has completed.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if one of the callbacks panicked.
+    pub fn close(mut self) -> ::Result<()> {
+        self.stop.take();
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "scheduler thread")
+    }
+
+    /// Stops the thread like `close()`, waiting at most `timeout` for it to stop.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io(TimedOut)` if the thread didn't stop in time.
+    /// Otherwise, it returns the same errors as `close()`.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Result<()> {
+        let deadline = Instant::now() + timeout;
+        self.stop.take();
+
+        shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "scheduler thread")
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        self.stop.take();
+    }
+}
+
+/// Returns the time at which a poll is due next, without jitter.
+fn next_due(due: Instant, interval: Duration, now: Instant, overrun: Overrun) -> Instant {
+    let next = due + interval;
+
+    if next >= now || interval == Duration::ZERO {
+        return next;
+    }
+
+    match overrun {
+        Overrun::Skip => {
+            let missed = (now - next).as_nanos() / interval.as_nanos() + 1;
+            next + interval * missed as u32
+        },
+        Overrun::Delay => now,
+        Overrun::Burst => next
+    }
+}
+
+/// A xorshift generator for jitter, which doesn't need to be unpredictable.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos() as u64).unwrap_or(0);
+        Random(seed | 1)
+    }
+
+    fn duration(&mut self, max: Duration) -> Duration {
+        if max == Duration::ZERO {
+            return Duration::ZERO;
+        }
+
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        Duration::from_nanos(self.0 % max.as_nanos() as u64)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::time::{Duration,Instant};
+
+    use super::{next_due,Overrun,Poll,Random,Scheduler};
+    use ::codec::lines::LineCodec;
+    use ::testing::ScriptedDevice;
+    use ::transaction::{Engine,Queue};
+
+    #[test]
+    fn next_due_applies_overrun_policy() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(10);
+        let now = start + Duration::from_millis(35);
+
+        assert_eq!(next_due(start, interval, start + Duration::from_millis(3), Overrun::Skip), start + interval);
+        assert_eq!(next_due(start, interval, now, Overrun::Skip), start + Duration::from_millis(40));
+        assert_eq!(next_due(start, interval, now, Overrun::Delay), now);
+        assert_eq!(next_due(start, interval, now, Overrun::Burst), start + interval);
+    }
+
+    #[test]
+    fn random_duration_is_bounded() {
+        let mut random = Random::new();
+        let max = Duration::from_millis(5);
+
+        for _ in 0..100 {
+            assert!(random.duration(max) < max);
+        }
+
+        assert_eq!(random.duration(Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn scheduler_delivers_responses() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"1\n2\n");
+
+        let queue = Arc::new(Queue::new(Engine::new(device, LineCodec::new())));
+        let (tx, rx) = mpsc::channel();
+
+        let handle = Scheduler::new(queue.clone())
+            .poll(Poll::new("READ".to_string(), Duration::from_millis(1)).deliver_to(tx))
+            .start();
+
+        assert_eq!(rx.recv().unwrap().unwrap(), "1");
+        assert_eq!(rx.recv().unwrap().unwrap(), "2");
+        handle.close().unwrap();
+
+        let queue = Arc::try_unwrap(queue).ok().unwrap();
+        assert!(queue.into_engine().into_parts().0.output().starts_with(b"READ\r\nREAD\r\n"));
+    }
+}
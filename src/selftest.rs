@@ -0,0 +1,640 @@
+//! Self-tests for serial ports wired in loopback.
+//!
+//! These tests require the port's TX line to be connected to its RX line, either with a loopback
+//! plug or a jumper wire. They're useful for qualifying adapters and cables.
+//!
+//! `loopback()` runs a single pass of pseudo-random data and reports throughput, latency, and
+//! errors. `bert()` runs a continuous bit error rate test with a standard test pattern, which is
+//! useful for validating cables and isolation hardware over longer periods.
+//!
+//! ```no_run
+//! use serial::prelude::*;
+//! use serial::selftest;
+//!
+//! let mut port = serial::open("/dev/ttyUSB0").unwrap();
+//! port.configure(&serial::PortSettings::standard(115200)).unwrap();
+//!
+//! let report = selftest::loopback(&mut port, &Default::default()).unwrap();
+//! println!("{:.0} bytes/s, {} errors", report.throughput(), report.errors);
+//! ```
+
+use std::cmp;
+use std::io;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+
+/// Options for a loopback test.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct LoopbackOptions {
+    /// Total number of bytes to send.
+    pub length: usize,
+
+    /// Number of bytes sent before waiting for them to be received.
+    pub chunk_size: usize,
+
+    /// Seed for the pseudo-random test data.
+    pub seed: u32,
+
+    /// Maximum time to wait for each chunk to be received.
+    pub timeout: Duration
+}
+
+impl Default for LoopbackOptions {
+    fn default() -> Self {
+        LoopbackOptions {
+            length: 64 * 1024,
+            chunk_size: 256,
+            seed: 1,
+            timeout: Duration::from_secs(1)
+        }
+    }
+}
+
+/// Results of a loopback test.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct LoopbackReport {
+    /// Number of bytes sent.
+    pub bytes_sent: usize,
+
+    /// Number of bytes received.
+    pub bytes_received: usize,
+
+    /// Number of received bytes that didn't match the sent data.
+    pub errors: usize,
+
+    /// Number of sent bytes that weren't received before the timeout.
+    pub lost: usize,
+
+    /// Total duration of the test.
+    pub elapsed: Duration,
+
+    /// Shortest time between sending a chunk and receiving its first byte.
+    pub min_latency: Option<Duration>,
+
+    /// Longest time between sending a chunk and receiving its first byte.
+    pub max_latency: Option<Duration>
+}
+
+impl LoopbackReport {
+    /// Returns the number of bytes received per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+
+        if secs > 0.0 {
+            self.bytes_received as f64 / secs
+        }
+        else {
+            0.0
+        }
+    }
+
+    /// Returns `true` if all data was received without errors.
+    pub fn is_ok(&self) -> bool {
+        self.errors == 0 && self.lost == 0
+    }
+}
+
+/// Sends pseudo-random data through a port wired in loopback and checks the received data.
+///
+/// Data is sent in chunks of `options.chunk_size` bytes. After each chunk, the test waits until the
+/// whole chunk has been received or `options.timeout` has expired. Received data is compared to the
+/// sent data byte by byte. Stale input is discarded before each chunk so that lost bytes don't
+/// affect the following chunks.
+///
+/// ## Errors
+///
+/// This function returns an error if the port could not be written to or read from. Data
+/// corruption and timeouts are recorded in the report instead.
+pub fn loopback<T: SerialPort + ?Sized>(port: &mut T, options: &LoopbackOptions) -> ::Result<LoopbackReport> {
+    let mut generator = XorShift::new(options.seed);
+    let chunk_size = cmp::max(options.chunk_size, 1);
+
+    let mut report = LoopbackReport {
+        bytes_sent: 0,
+        bytes_received: 0,
+        errors: 0,
+        lost: 0,
+        elapsed: Duration::from_secs(0),
+        min_latency: None,
+        max_latency: None
+    };
+
+    let mut sent = vec![0; chunk_size];
+    let mut received = vec![0; chunk_size];
+    let start = Instant::now();
+
+    while report.bytes_sent < options.length {
+        let len = cmp::min(chunk_size, options.length - report.bytes_sent);
+        generator.fill(&mut sent[..len]);
+
+        port.discard_input()?;
+
+        let sent_at = Instant::now();
+        port.write_all(&sent[..len])?;
+        port.flush()?;
+        report.bytes_sent += len;
+
+        let mut count = 0;
+        let deadline = sent_at + options.timeout;
+
+        while count < len {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match port.read_with_timeout(&mut received[count..len], deadline - now) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if count == 0 {
+                        let latency = sent_at.elapsed();
+                        report.min_latency = Some(report.min_latency.map_or(latency, |min| cmp::min(min, latency)));
+                        report.max_latency = Some(report.max_latency.map_or(latency, |max| cmp::max(max, latency)));
+                    }
+
+                    count += n;
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(::Error::from(err))
+            }
+        }
+
+        report.bytes_received += count;
+        report.lost += len - count;
+        report.errors += sent[..count].iter().zip(&received[..count]).filter(|&(a, b)| a != b).count();
+    }
+
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Test patterns for bit error rate testing.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Pattern {
+    /// Pseudo-random binary sequence with polynomial x⁹ + x⁵ + 1 (period of 511 bits).
+    Prbs9,
+
+    /// Pseudo-random binary sequence with polynomial x¹⁵ + x¹⁴ + 1 (period of 32767 bits).
+    Prbs15,
+
+    /// Alternating bits (`0x55`), which exercises the line at its highest toggle rate.
+    Alternating
+}
+
+impl Pattern {
+    /// Returns the degree and second tap of the pattern's polynomial, if it's a PRBS.
+    fn taps(&self) -> Option<(u32, u32)> {
+        match *self {
+            Pattern::Prbs9 => Some((9, 5)),
+            Pattern::Prbs15 => Some((15, 14)),
+            Pattern::Alternating => None
+        }
+    }
+}
+
+/// Generates a test pattern.
+///
+/// Bits are packed into bytes least significant bit first, which is the order in which they're
+/// transmitted by a UART.
+#[derive(Debug,Clone)]
+pub struct PatternGenerator {
+    pattern: Pattern,
+    state: u32
+}
+
+impl PatternGenerator {
+    /// Creates a generator for the given pattern.
+    pub fn new(pattern: Pattern) -> Self {
+        PatternGenerator { pattern, state: !0 }
+    }
+
+    /// Fills `buf` with the next bytes of the pattern.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = match self.pattern.taps() {
+                Some(taps) => {
+                    let mut value = 0;
+
+                    for bit in 0..8 {
+                        value |= (lfsr_step(&mut self.state, taps) as u8) << bit;
+                    }
+
+                    value
+                },
+                None => 0x55
+            };
+        }
+    }
+}
+
+/// Advances a Fibonacci LFSR and returns the generated bit.
+fn lfsr_step(state: &mut u32, (degree, tap): (u32, u32)) -> u32 {
+    let bit = ((*state >> (degree - 1)) ^ (*state >> (tap - 1))) & 1;
+    *state = ((*state << 1) | bit) & ((1 << degree) - 1);
+    bit
+}
+
+/// Checks received data against a test pattern and counts bit errors.
+///
+/// For PRBS patterns, the checker synchronizes to the received sequence, so it doesn't need to
+/// start at the same point as the generator. It resynchronizes when the error rate indicates that
+/// synchronization was lost, e.g., because bytes were dropped.
+#[derive(Debug,Clone)]
+pub struct PatternChecker {
+    pattern: Pattern,
+    state: u32,
+    acquired: u32,
+    window_bits: u32,
+    window_errors: u32,
+    bits: u64,
+    bit_errors: u64,
+    resyncs: u64
+}
+
+impl PatternChecker {
+    /// Creates a checker for the given pattern.
+    pub fn new(pattern: Pattern) -> Self {
+        PatternChecker {
+            pattern,
+            state: 0,
+            acquired: 0,
+            window_bits: 0,
+            window_errors: 0,
+            bits: 0,
+            bit_errors: 0,
+            resyncs: 0
+        }
+    }
+
+    /// Checks received bytes against the pattern.
+    pub fn check(&mut self, data: &[u8]) {
+        for &byte in data {
+            match self.pattern.taps() {
+                Some(taps) => {
+                    for bit in 0..8 {
+                        self.check_bit(u32::from(byte >> bit) & 1, taps);
+                    }
+                },
+                None => {
+                    self.bits += 8;
+                    self.bit_errors += u64::from((byte ^ 0x55).count_ones());
+                }
+            }
+        }
+    }
+
+    /// Discards synchronization, e.g., after a gap in the received data.
+    pub fn resync(&mut self) {
+        self.acquired = 0;
+        self.window_bits = 0;
+        self.window_errors = 0;
+    }
+
+    /// Returns the number of bits checked.
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Returns the number of bit errors detected.
+    pub fn bit_errors(&self) -> u64 {
+        self.bit_errors
+    }
+
+    /// Returns the number of times the checker lost synchronization with the received data.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    fn check_bit(&mut self, bit: u32, taps: (u32, u32)) {
+        let (degree, _) = taps;
+
+        // load received bits into the LFSR until it's in the same state as the generator
+        if self.acquired < degree {
+            self.state = ((self.state << 1) | bit) & ((1 << degree) - 1);
+            self.acquired += 1;
+            return;
+        }
+
+        let expected = lfsr_step(&mut self.state, taps);
+
+        self.bits += 1;
+        self.window_bits += 1;
+
+        if bit != expected {
+            self.bit_errors += 1;
+            self.window_errors += 1;
+        }
+
+        if self.window_bits == 64 {
+            // an error rate this high indicates misalignment rather than line errors
+            if self.window_errors > 16 {
+                self.resyncs += 1;
+                self.acquired = 0;
+            }
+
+            self.window_bits = 0;
+            self.window_errors = 0;
+        }
+    }
+}
+
+/// Options for a bit error rate test.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct BertOptions {
+    /// The test pattern to transmit.
+    pub pattern: Pattern,
+
+    /// Number of bytes sent before waiting for them to be received.
+    pub chunk_size: usize,
+
+    /// Interval between reported samples.
+    pub interval: Duration,
+
+    /// Maximum time to wait for each chunk to be received.
+    pub timeout: Duration
+}
+
+impl Default for BertOptions {
+    fn default() -> Self {
+        BertOptions {
+            pattern: Pattern::Prbs15,
+            chunk_size: 256,
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(1)
+        }
+    }
+}
+
+/// Cumulative results of a bit error rate test.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct BertSample {
+    /// Time since the start of the test.
+    pub elapsed: Duration,
+
+    /// Number of bits checked.
+    pub bits: u64,
+
+    /// Number of bit errors detected.
+    pub bit_errors: u64,
+
+    /// Number of sent bytes that weren't received before the timeout.
+    pub lost: u64,
+
+    /// Number of times the checker lost synchronization with the received data.
+    pub resyncs: u64
+}
+
+impl BertSample {
+    /// Returns the ratio of bit errors to checked bits.
+    pub fn error_rate(&self) -> f64 {
+        if self.bits > 0 {
+            self.bit_errors as f64 / self.bits as f64
+        }
+        else {
+            0.0
+        }
+    }
+}
+
+/// Runs a bit error rate test on a port wired in loopback.
+///
+/// The test pattern is transmitted continuously and the received data is checked against it. Every
+/// `options.interval`, `on_sample` is called with the cumulative results. The test continues until
+/// `on_sample` returns `false`, and then the final results are returned.
+///
+/// ```no_run
+/// use serial::selftest::{self,BertOptions,Pattern};
+///
+/// let mut port = serial::open("/dev/ttyUSB0").unwrap();
+/// let options = BertOptions { pattern: Pattern::Prbs9, ..Default::default() };
+///
+/// let result = selftest::bert(&mut port, &options, |sample| {
+///     println!("{:?}: BER {:e}", sample.elapsed, sample.error_rate());
+///     sample.elapsed.as_secs() < 60
+/// }).unwrap();
+/// ```
+///
+/// ## Errors
+///
+/// This function returns an error if the port could not be written to or read from. Bit errors
+/// and timeouts are recorded in the results instead.
+pub fn bert<T, F>(port: &mut T, options: &BertOptions, mut on_sample: F) -> ::Result<BertSample>
+    where T: SerialPort + ?Sized,
+          F: FnMut(&BertSample) -> bool
+{
+    let mut generator = PatternGenerator::new(options.pattern);
+    let mut checker = PatternChecker::new(options.pattern);
+    let chunk_size = cmp::max(options.chunk_size, 1);
+
+    let mut sent = vec![0; chunk_size];
+    let mut received = vec![0; chunk_size];
+    let mut lost = 0;
+
+    let start = Instant::now();
+    let mut next_sample = start + options.interval;
+
+    port.discard_input()?;
+
+    loop {
+        generator.fill(&mut sent);
+        port.write_all(&sent)?;
+        port.flush()?;
+
+        let mut count = 0;
+        let deadline = Instant::now() + options.timeout;
+
+        while count < chunk_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match port.read_with_timeout(&mut received[count..], deadline - now) {
+                Ok(0) => break,
+                Ok(n) => {
+                    checker.check(&received[count..count + n]);
+                    count += n;
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(::Error::from(err))
+            }
+        }
+
+        if count < chunk_size {
+            lost += (chunk_size - count) as u64;
+            checker.resync();
+        }
+
+        let now = Instant::now();
+
+        if now >= next_sample {
+            next_sample = now + options.interval;
+
+            let sample = BertSample {
+                elapsed: now - start,
+                bits: checker.bits(),
+                bit_errors: checker.bit_errors(),
+                lost,
+                resyncs: checker.resyncs()
+            };
+
+            if !on_sample(&sample) {
+                return Ok(sample);
+            }
+        }
+    }
+}
+
+/// A xorshift pseudo-random number generator for test data.
+struct XorShift {
+    state: u32
+}
+
+impl XorShift {
+    fn new(seed: u32) -> Self {
+        // the state must be nonzero
+        XorShift { state: if seed == 0 { 0x9e37_79b9 } else { seed } }
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = (self.next() >> 24) as u8;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bert,loopback,BertOptions,LoopbackOptions,Pattern,PatternChecker,PatternGenerator};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn loopback_passes_with_echoing_device() {
+        let mut device = ScriptedDevice::new();
+        device.echo = true;
+
+        let options = LoopbackOptions { length: 1000, chunk_size: 64, ..Default::default() };
+        let report = loopback(&mut device, &options).unwrap();
+
+        assert!(report.is_ok());
+        assert_eq!(report.bytes_sent, 1000);
+        assert_eq!(report.bytes_received, 1000);
+        assert!(report.min_latency.is_some());
+    }
+
+    #[test]
+    fn loopback_reports_lost_bytes() {
+        let mut device = ScriptedDevice::new();
+
+        let options = LoopbackOptions { length: 100, timeout: Duration::from_millis(1), ..Default::default() };
+        let report = loopback(&mut device, &options).unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(report.lost, 100);
+        assert_eq!(report.min_latency, None);
+    }
+
+    #[test]
+    fn loopback_reports_corrupted_bytes() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&[0; 4]);
+
+        let options = LoopbackOptions { length: 4, timeout: Duration::from_millis(1), seed: 7, ..Default::default() };
+        let report = loopback(&mut device, &options).unwrap();
+
+        assert_eq!(report.bytes_received, 4);
+        assert!(report.errors > 0);
+    }
+
+    #[test]
+    fn prbs9_repeats_after_511_bits() {
+        let mut generator = PatternGenerator::new(Pattern::Prbs9);
+        let mut buf = [0; 511 * 2 / 8 + 1];
+        generator.fill(&mut buf);
+
+        let bit = |i: usize| (buf[i / 8] >> (i % 8)) & 1;
+        assert!((0..511).all(|i| bit(i) == bit(i + 511)));
+        assert!((1..511).all(|period| (0..511).any(|i| bit(i) != bit(i + period))));
+    }
+
+    #[test]
+    fn pattern_checker_accepts_clean_data() {
+        for &pattern in &[Pattern::Prbs9, Pattern::Prbs15, Pattern::Alternating] {
+            let mut generator = PatternGenerator::new(pattern);
+            let mut checker = PatternChecker::new(pattern);
+            let mut buf = [0; 1024];
+
+            // the checker doesn't need to start at the beginning of the sequence
+            generator.fill(&mut buf[..37]);
+            generator.fill(&mut buf);
+            checker.check(&buf);
+
+            assert!(checker.bits() > 8000);
+            assert_eq!(checker.bit_errors(), 0);
+        }
+    }
+
+    #[test]
+    fn pattern_checker_counts_single_bit_error_once() {
+        let mut generator = PatternGenerator::new(Pattern::Prbs15);
+        let mut checker = PatternChecker::new(Pattern::Prbs15);
+        let mut buf = [0; 1024];
+
+        generator.fill(&mut buf);
+        buf[500] ^= 0x10;
+        checker.check(&buf);
+
+        assert_eq!(checker.bit_errors(), 1);
+        assert_eq!(checker.resyncs(), 0);
+    }
+
+    #[test]
+    fn pattern_checker_resyncs_after_dropped_bytes() {
+        let mut generator = PatternGenerator::new(Pattern::Prbs9);
+        let mut checker = PatternChecker::new(Pattern::Prbs9);
+        let mut buf = [0; 1024];
+
+        generator.fill(&mut buf);
+        checker.check(&buf[..300]);
+        checker.check(&buf[303..]);
+
+        assert_eq!(checker.resyncs(), 1);
+
+        let errors = checker.bit_errors();
+        generator.fill(&mut buf);
+        checker.check(&buf);
+        assert_eq!(checker.bit_errors(), errors);
+    }
+
+    #[test]
+    fn bert_reports_samples_until_stopped() {
+        let mut device = ScriptedDevice::new();
+        device.echo = true;
+
+        let options = BertOptions { interval: Duration::from_millis(0), ..Default::default() };
+        let mut samples = 0;
+
+        let result = bert(&mut device, &options, |_| {
+            samples += 1;
+            samples < 3
+        }).unwrap();
+
+        assert_eq!(samples, 3);
+        assert_eq!(result.bits, 3 * 256 * 8 - 15);
+        assert_eq!(result.bit_errors, 0);
+    }
+}
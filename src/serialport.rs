@@ -0,0 +1,558 @@
+//! Adapters between this crate and the [`serialport`](https://docs.rs/serialport) crate.
+//!
+//! `FromSerialport` lets a port opened with `serialport` be used wherever this crate expects a
+//! `SerialPort`, and `IntoSerialport` goes the other way, so that code written against either
+//! crate can be migrated one piece at a time:
+//!
+//! ```no_run
+//! extern crate serialport;
+//! # extern crate serial;
+//!
+//! use std::time::Duration;
+//! use serial::prelude::*;
+//! use serial::serialport::{FromSerialport,IntoSerialport};
+//!
+//! # fn main() {
+//! // a port opened with serialport, used through this crate's API
+//! let mut port = FromSerialport::new(serialport::new("/dev/ttyUSB0", 9600).open().unwrap());
+//! port.reconfigure(|settings| settings.set_baud_rate(serial::Baud115200)).unwrap();
+//!
+//! // a port opened with this crate, passed to code that expects a serialport::SerialPort
+//! let port: Box<dyn serialport::SerialPort> = Box::new(IntoSerialport::new(serial::open("/dev/ttyUSB1").unwrap()));
+//! assert_eq!(port.timeout(), Duration::from_millis(100));
+//! # }
+//! ```
+//!
+//! Operations that only one crate supports report errors of kind `Io(Unsupported)` through the
+//! other crate's API. Errors are converted between the two `Error` types with `From`, which maps
+//! the kinds that don't exist in `serialport`, such as `Busy`, to the closest kind it has.
+
+extern crate serialport;
+
+use std::cell::RefCell;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use ::{BaudRate,CharSize,FlowControl,Parity,PortSettings,SerialDevice,SerialPortSettings,StopBits};
+
+impl From<serialport::Error> for ::Error {
+    fn from(err: serialport::Error) -> ::Error {
+        let kind = match err.kind() {
+            serialport::ErrorKind::NoDevice => ::ErrorKind::NoDevice,
+            serialport::ErrorKind::InvalidInput => ::ErrorKind::InvalidInput,
+            serialport::ErrorKind::Io(kind) => ::ErrorKind::Io(kind),
+            serialport::ErrorKind::Unknown => ::ErrorKind::Io(io::ErrorKind::Other)
+        };
+
+        ::Error::new(kind, err.description)
+    }
+}
+
+impl From<::Error> for serialport::Error {
+    fn from(err: ::Error) -> serialport::Error {
+        let kind = match err.kind() {
+            ::ErrorKind::NoDevice | ::ErrorKind::Busy => serialport::ErrorKind::NoDevice,
+            ::ErrorKind::PermissionDenied => serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied),
+            ::ErrorKind::InvalidInput => serialport::ErrorKind::InvalidInput,
+            ::ErrorKind::Io(kind) => serialport::ErrorKind::Io(kind)
+        };
+
+        serialport::Error::new(kind, err.to_string())
+    }
+}
+
+fn unsupported(description: &str) -> serialport::Error {
+    serialport::Error::new(serialport::ErrorKind::Io(io::ErrorKind::Unsupported), description)
+}
+
+
+/// A port opened with the `serialport` crate, used as a `SerialDevice`.
+///
+/// The port's settings are read and written as `PortSettings`. Reading the state of the RTS and
+/// DTR outputs isn't supported by `serialport`.
+pub struct FromSerialport {
+    port: Box<dyn serialport::SerialPort>
+}
+
+impl FromSerialport {
+    /// Wraps a port opened with `serialport`, e.g., by `serialport::new(path, baud).open()`.
+    pub fn new(port: Box<dyn serialport::SerialPort>) -> Self {
+        FromSerialport { port }
+    }
+
+    /// Returns a reference to the wrapped port.
+    pub fn get_ref(&self) -> &dyn serialport::SerialPort {
+        &*self.port
+    }
+
+    /// Returns a mutable reference to the wrapped port.
+    pub fn get_mut(&mut self) -> &mut dyn serialport::SerialPort {
+        &mut *self.port
+    }
+
+    /// Unwraps the port.
+    pub fn into_inner(self) -> Box<dyn serialport::SerialPort> {
+        self.port
+    }
+
+    /// Converts a `serialport` error and attaches the device name and failed operation to it.
+    fn error(&self, err: serialport::Error, operation: &'static str) -> ::Error {
+        let err = ::Error::from(err).with_operation(operation);
+
+        match self.port.name() {
+            Some(name) => err.with_device(name),
+            None => err
+        }
+    }
+}
+
+impl io::Read for FromSerialport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl io::Write for FromSerialport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialDevice for FromSerialport {
+    type Settings = PortSettings;
+
+    fn read_settings(&self) -> ::Result<PortSettings> {
+        let read = || -> serialport::Result<PortSettings> {
+            let char_size = match self.port.data_bits()? {
+                serialport::DataBits::Five => CharSize::Bits5,
+                serialport::DataBits::Six => CharSize::Bits6,
+                serialport::DataBits::Seven => CharSize::Bits7,
+                serialport::DataBits::Eight => CharSize::Bits8
+            };
+
+            let parity = match self.port.parity()? {
+                serialport::Parity::None => Parity::ParityNone,
+                serialport::Parity::Odd => Parity::ParityOdd,
+                serialport::Parity::Even => Parity::ParityEven
+            };
+
+            let stop_bits = match self.port.stop_bits()? {
+                serialport::StopBits::One => StopBits::Stop1,
+                serialport::StopBits::Two => StopBits::Stop2
+            };
+
+            let flow_control = match self.port.flow_control()? {
+                serialport::FlowControl::None => FlowControl::FlowNone,
+                serialport::FlowControl::Software => FlowControl::FlowSoftware,
+                serialport::FlowControl::Hardware => FlowControl::FlowHardware
+            };
+
+            Ok(PortSettings {
+                baud_rate: BaudRate::from_speed(self.port.baud_rate()? as usize),
+                char_size,
+                parity,
+                stop_bits,
+                flow_control
+            })
+        };
+
+        read().map_err(|err| self.error(err, "read settings"))
+    }
+
+    fn write_settings(&mut self, settings: &PortSettings) -> ::Result<()> {
+        let data_bits = match settings.char_size {
+            CharSize::Bits5 => serialport::DataBits::Five,
+            CharSize::Bits6 => serialport::DataBits::Six,
+            CharSize::Bits7 => serialport::DataBits::Seven,
+            CharSize::Bits8 => serialport::DataBits::Eight
+        };
+
+        let parity = match settings.parity {
+            Parity::ParityNone => serialport::Parity::None,
+            Parity::ParityOdd => serialport::Parity::Odd,
+            Parity::ParityEven => serialport::Parity::Even
+        };
+
+        let stop_bits = match settings.stop_bits {
+            StopBits::Stop1 => serialport::StopBits::One,
+            StopBits::Stop2 => serialport::StopBits::Two
+        };
+
+        let flow_control = match settings.flow_control {
+            FlowControl::FlowNone => serialport::FlowControl::None,
+            FlowControl::FlowSoftware => serialport::FlowControl::Software,
+            FlowControl::FlowHardware => serialport::FlowControl::Hardware
+        };
+
+        let result = self.port.set_baud_rate(settings.baud_rate.speed() as u32)
+            .and_then(|_| self.port.set_data_bits(data_bits))
+            .and_then(|_| self.port.set_parity(parity))
+            .and_then(|_| self.port.set_stop_bits(stop_bits))
+            .and_then(|_| self.port.set_flow_control(flow_control));
+
+        result.map_err(|err| self.error(err, "write settings"))
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.port.set_timeout(timeout).map_err(|err| self.error(err, "set timeout"))
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        self.port.clear(serialport::ClearBuffer::Input).map_err(|err| self.error(err, "discard input"))
+    }
+
+    fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        self.port.write_request_to_send(level).map_err(|err| self.error(err, "set control signal"))
+    }
+
+    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        self.port.write_data_terminal_ready(level).map_err(|err| self.error(err, "set control signal"))
+    }
+
+    fn read_cts(&mut self) -> ::Result<bool> {
+        self.port.read_clear_to_send().map_err(|err| self.error(err, "read control signal"))
+    }
+
+    fn read_dsr(&mut self) -> ::Result<bool> {
+        self.port.read_data_set_ready().map_err(|err| self.error(err, "read control signal"))
+    }
+
+    fn read_ri(&mut self) -> ::Result<bool> {
+        self.port.read_ring_indicator().map_err(|err| self.error(err, "read control signal"))
+    }
+
+    fn read_cd(&mut self) -> ::Result<bool> {
+        self.port.read_carrier_detect().map_err(|err| self.error(err, "read control signal"))
+    }
+
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        self.port.set_break().map_err(|err| self.error(err, "send break"))?;
+        thread::sleep(duration);
+        self.port.clear_break().map_err(|err| self.error(err, "send break"))
+    }
+}
+
+
+/// A port from this crate, used as a `serialport::SerialPort`.
+///
+/// The port is kept in a `RefCell`, since `serialport` discards buffered input through a shared
+/// reference. `bytes_to_read()`, `bytes_to_write()`, `try_clone()`, `set_break()`, and
+/// `clear_break()` aren't supported, and `clear()` only supports `ClearBuffer::Input`, since
+/// buffered output can't be discarded.
+pub struct IntoSerialport<P> {
+    port: RefCell<P>,
+    name: Option<String>
+}
+
+impl<P: SerialDevice> IntoSerialport<P> {
+    /// Wraps a port. The wrapped port has no name.
+    pub fn new(port: P) -> Self {
+        IntoSerialport {
+            port: RefCell::new(port),
+            name: None
+        }
+    }
+
+    /// Sets the name reported by `serialport::SerialPort::name()`.
+    pub fn with_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Returns a mutable reference to the wrapped port.
+    pub fn get_mut(&mut self) -> &mut P {
+        self.port.get_mut()
+    }
+
+    /// Unwraps the port.
+    pub fn into_inner(self) -> P {
+        self.port.into_inner()
+    }
+
+    /// Reads one of the port's settings, which fails if the port can't report it.
+    fn setting<T, F>(&self, name: &str, get: F) -> serialport::Result<T>
+        where F: FnOnce(&P::Settings) -> Option<T>
+    {
+        let settings = self.port.borrow().read_settings()?;
+
+        match get(&settings) {
+            Some(value) => Ok(value),
+            None => Err(serialport::Error::new(serialport::ErrorKind::Unknown, format!("{} is unknown", name)))
+        }
+    }
+
+    /// Changes the port's settings.
+    fn reconfigure<F>(&mut self, setup: F) -> serialport::Result<()>
+        where F: FnOnce(&mut P::Settings) -> ::Result<()>
+    {
+        let port = self.port.get_mut();
+        let mut settings = port.read_settings()?;
+
+        setup(&mut settings)?;
+        Ok(port.write_settings(&settings)?)
+    }
+}
+
+impl<P: io::Read> io::Read for IntoSerialport<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.get_mut().read(buf)
+    }
+}
+
+impl<P: io::Write> io::Write for IntoSerialport<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.get_mut().flush()
+    }
+}
+
+impl<P: SerialDevice + Send> serialport::SerialPort for IntoSerialport<P> {
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.setting("baud rate", |settings| settings.baud_rate()).map(|baud_rate| baud_rate.speed() as u32)
+    }
+
+    fn data_bits(&self) -> serialport::Result<serialport::DataBits> {
+        self.setting("character size", |settings| settings.char_size()).map(|char_size| match char_size {
+            CharSize::Bits5 => serialport::DataBits::Five,
+            CharSize::Bits6 => serialport::DataBits::Six,
+            CharSize::Bits7 => serialport::DataBits::Seven,
+            CharSize::Bits8 => serialport::DataBits::Eight
+        })
+    }
+
+    fn flow_control(&self) -> serialport::Result<serialport::FlowControl> {
+        self.setting("flow control", |settings| settings.flow_control()).map(|flow_control| match flow_control {
+            FlowControl::FlowNone => serialport::FlowControl::None,
+            FlowControl::FlowSoftware => serialport::FlowControl::Software,
+            FlowControl::FlowHardware => serialport::FlowControl::Hardware
+        })
+    }
+
+    fn parity(&self) -> serialport::Result<serialport::Parity> {
+        self.setting("parity", |settings| settings.parity()).map(|parity| match parity {
+            Parity::ParityNone => serialport::Parity::None,
+            Parity::ParityOdd => serialport::Parity::Odd,
+            Parity::ParityEven => serialport::Parity::Even
+        })
+    }
+
+    fn stop_bits(&self) -> serialport::Result<serialport::StopBits> {
+        self.setting("stop bits", |settings| settings.stop_bits()).map(|stop_bits| match stop_bits {
+            StopBits::Stop1 => serialport::StopBits::One,
+            StopBits::Stop2 => serialport::StopBits::Two
+        })
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.borrow().timeout()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.reconfigure(|settings| settings.set_baud_rate(BaudRate::from_speed(baud_rate as usize)))
+    }
+
+    fn set_data_bits(&mut self, data_bits: serialport::DataBits) -> serialport::Result<()> {
+        let char_size = match data_bits {
+            serialport::DataBits::Five => CharSize::Bits5,
+            serialport::DataBits::Six => CharSize::Bits6,
+            serialport::DataBits::Seven => CharSize::Bits7,
+            serialport::DataBits::Eight => CharSize::Bits8
+        };
+
+        self.reconfigure(|settings| { settings.set_char_size(char_size); Ok(()) })
+    }
+
+    fn set_flow_control(&mut self, flow_control: serialport::FlowControl) -> serialport::Result<()> {
+        let flow_control = match flow_control {
+            serialport::FlowControl::None => FlowControl::FlowNone,
+            serialport::FlowControl::Software => FlowControl::FlowSoftware,
+            serialport::FlowControl::Hardware => FlowControl::FlowHardware
+        };
+
+        self.reconfigure(|settings| { settings.set_flow_control(flow_control); Ok(()) })
+    }
+
+    fn set_parity(&mut self, parity: serialport::Parity) -> serialport::Result<()> {
+        let parity = match parity {
+            serialport::Parity::None => Parity::ParityNone,
+            serialport::Parity::Odd => Parity::ParityOdd,
+            serialport::Parity::Even => Parity::ParityEven
+        };
+
+        self.reconfigure(|settings| { settings.set_parity(parity); Ok(()) })
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: serialport::StopBits) -> serialport::Result<()> {
+        let stop_bits = match stop_bits {
+            serialport::StopBits::One => StopBits::Stop1,
+            serialport::StopBits::Two => StopBits::Stop2
+        };
+
+        self.reconfigure(|settings| { settings.set_stop_bits(stop_bits); Ok(()) })
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        Ok(self.port.get_mut().set_timeout(timeout)?)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        Ok(self.port.get_mut().set_rts(level)?)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        Ok(self.port.get_mut().set_dtr(level)?)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(self.port.get_mut().read_cts()?)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(self.port.get_mut().read_dsr()?)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(self.port.get_mut().read_ri()?)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(self.port.get_mut().read_cd()?)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Err(unsupported("counting received bytes is not supported"))
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Err(unsupported("counting unsent bytes is not supported"))
+    }
+
+    fn clear(&self, buffer_to_clear: serialport::ClearBuffer) -> serialport::Result<()> {
+        match buffer_to_clear {
+            serialport::ClearBuffer::Input => Ok(self.port.borrow_mut().discard_input()?),
+            serialport::ClearBuffer::Output | serialport::ClearBuffer::All => Err(unsupported("discarding output is not supported"))
+        }
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        Err(unsupported("cloning ports is not supported"))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Err(unsupported("holding a break is not supported; use SerialPort::send_break()"))
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Err(unsupported("holding a break is not supported; use SerialPort::send_break()"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::time::Duration;
+
+    use super::serialport::{self,SerialPort as _};
+    use super::{FromSerialport,IntoSerialport};
+    use ::prelude::*;
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn into_serialport_configures_the_wrapped_port() {
+        let mut port = IntoSerialport::new(ScriptedDevice::new()).with_name("scripted");
+
+        port.set_baud_rate(115200).unwrap();
+        port.set_data_bits(serialport::DataBits::Seven).unwrap();
+        port.set_parity(serialport::Parity::Even).unwrap();
+        port.set_stop_bits(serialport::StopBits::Two).unwrap();
+        port.set_flow_control(serialport::FlowControl::Hardware).unwrap();
+        port.write_request_to_send(true).unwrap();
+
+        assert_eq!(port.name().as_deref(), Some("scripted"));
+        assert_eq!(port.baud_rate().unwrap(), 115200);
+        assert_eq!(port.data_bits().unwrap(), serialport::DataBits::Seven);
+        assert_eq!(port.parity().unwrap(), serialport::Parity::Even);
+        assert_eq!(port.stop_bits().unwrap(), serialport::StopBits::Two);
+        assert_eq!(port.flow_control().unwrap(), serialport::FlowControl::Hardware);
+
+        let device = port.into_inner();
+        assert_eq!(device.settings.baud_rate, ::Baud115200);
+        assert!(device.rts);
+    }
+
+    #[test]
+    fn into_serialport_reports_unsupported_operations() {
+        let port = IntoSerialport::new(ScriptedDevice::new());
+
+        assert_eq!(port.bytes_to_read().unwrap_err().kind(), serialport::ErrorKind::Io(::std::io::ErrorKind::Unsupported));
+        assert_eq!(port.set_break().unwrap_err().kind(), serialport::ErrorKind::Io(::std::io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn into_serialport_only_clears_input() {
+        let port = IntoSerialport::new(ScriptedDevice::new());
+
+        assert_eq!(port.clear(serialport::ClearBuffer::Output).unwrap_err().kind(), serialport::ErrorKind::Io(::std::io::ErrorKind::Unsupported));
+        assert_eq!(port.clear(serialport::ClearBuffer::All).unwrap_err().kind(), serialport::ErrorKind::Io(::std::io::ErrorKind::Unsupported));
+        assert!(port.into_inner().events.is_empty());
+
+        let port = IntoSerialport::new(ScriptedDevice::new());
+        port.clear(serialport::ClearBuffer::Input).unwrap();
+        assert_eq!(port.into_inner().events, vec![Event::DiscardInput]);
+    }
+
+    #[test]
+    fn from_serialport_round_trips_through_into_serialport() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"OK");
+        device.cts = true;
+
+        let mut port = FromSerialport::new(Box::new(IntoSerialport::new(device)));
+
+        port.reconfigure(|settings| {
+            settings.set_baud_rate(::Baud57600)?;
+            settings.set_parity(::ParityOdd);
+            Ok(())
+        }).unwrap();
+        port.set_timeout(Duration::from_millis(10)).unwrap();
+        port.write_all(b"AT").unwrap();
+
+        let mut buf = [0; 2];
+        port.read_exact(&mut buf).unwrap();
+
+        assert_eq!(&buf, b"OK");
+        assert!(port.read_cts().unwrap());
+
+        let settings = ::SerialDevice::read_settings(&port).unwrap();
+        assert_eq!(settings.baud_rate, ::Baud57600);
+        assert_eq!(settings.parity, ::ParityOdd);
+        assert_eq!(port.timeout(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn errors_convert_between_crates() {
+        let err = serialport::Error::from(::Error::new(::ErrorKind::Busy, "in use").with_device("COM3"));
+        assert_eq!(err.kind(), serialport::ErrorKind::NoDevice);
+        assert_eq!(err.description, "COM3: in use");
+
+        let err = ::Error::from(serialport::Error::new(serialport::ErrorKind::Io(::std::io::ErrorKind::TimedOut), "timed out"));
+        assert_eq!(err.kind(), ::ErrorKind::Io(::std::io::ErrorKind::TimedOut));
+    }
+}
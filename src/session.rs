@@ -0,0 +1,442 @@
+//! Connections that survive unplugging and replugging a device.
+//!
+//! A USB serial adapter that's unplugged and plugged back in may reappear under a different path,
+//! and a port that was open when it was unplugged never recovers. A `Session` binds to a stable
+//! `DeviceId`, such as the adapter's USB serial number, and reopens the device when it reappears:
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use serial::session::{DeviceId,Session};
+//!
+//! let mut session = Session::open(DeviceId::UsbSerialNumber("A6008isP".to_string()));
+//! session.configure(&serial::PortSettings::standard(115200)).unwrap();
+//! session.on_state_change(|state| println!("link is {:?}", state));
+//!
+//! loop {
+//!     // written while disconnected, sent after reconnecting
+//!     session.write_all(b"ping\n").unwrap();
+//!     std::thread::sleep(std::time::Duration::from_secs(1));
+//! }
+//! ```
+//!
+//! The session remembers the port settings, timeout, and the levels of RTS and DTR, and applies
+//! them to the port after every reconnect. Data written while disconnected is buffered and sent
+//! when the device has been reopened.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration,Instant};
+
+use ::{PortSettings,SerialPort,SystemPort};
+use ::enumerate::{self,PortType};
+
+type Connector<P> = Box<dyn FnMut() -> ::Result<P> + Send>;
+type StateCallback = Box<dyn FnMut(LinkState) + Send>;
+
+/// A stable identity of a serial device.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub enum DeviceId {
+    /// The device at a fixed path, e.g., `/dev/ttyS0` or a udev symlink.
+    Path(PathBuf),
+
+    /// The USB serial adapter that reports the given serial number.
+    UsbSerialNumber(String)
+}
+
+impl DeviceId {
+    /// Returns the current path of the device.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if no device with this identity is present.
+    /// * Any error returned by `enumerate::available_ports()`.
+    pub fn find(&self) -> ::Result<PathBuf> {
+        match *self {
+            DeviceId::Path(ref path) => Ok(path.clone()),
+            DeviceId::UsbSerialNumber(ref serial_number) => {
+                for port in enumerate::available_ports()? {
+                    if let PortType::Usb(ref usb) = port.port_type {
+                        if usb.serial_number.as_ref() == Some(serial_number) {
+                            return Ok(port.path);
+                        }
+                    }
+                }
+
+                Err(::Error::new(::ErrorKind::NoDevice, format!("no USB device with serial number {}", serial_number)).with_operation("find device"))
+            }
+        }
+    }
+}
+
+/// State of a session's link to its device.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum LinkState {
+    /// The device is open.
+    Connected,
+
+    /// The device is disconnected or couldn't be opened.
+    Disconnected
+}
+
+/// A connection to a device that's reopened after the device is disconnected.
+///
+/// Reading from a disconnected session attempts to reconnect, and returns an error of kind
+/// `NotConnected` if the device isn't available. Writing to a disconnected session buffers the
+/// data until the device is reconnected, up to a limit set by `set_max_pending()`.
+pub struct Session<P> {
+    connector: Connector<P>,
+    port: Option<P>,
+    settings: Option<PortSettings>,
+    timeout: Option<Duration>,
+    rts: Option<bool>,
+    dtr: Option<bool>,
+    pending: Vec<u8>,
+    max_pending: usize,
+    retry_interval: Duration,
+    last_attempt: Option<Instant>,
+    on_state_change: Option<StateCallback>
+}
+
+impl Session<SystemPort> {
+    /// Creates a session for the device with the given identity and attempts to open it.
+    ///
+    /// The session is created even if the device isn't present, in which case it's opened when it
+    /// appears.
+    pub fn open(id: DeviceId) -> Self {
+        let mut session = Session::with_connector(move || {
+            let path = id.find()?;
+            ::open(&path)
+        });
+
+        let _ = session.connect();
+        session
+    }
+}
+
+impl<P: SerialPort> Session<P> {
+    /// Creates a disconnected session that opens its port with `connector`.
+    pub fn with_connector<F>(connector: F) -> Self
+        where F: FnMut() -> ::Result<P> + Send + 'static
+    {
+        Session {
+            connector: Box::new(connector),
+            port: None,
+            settings: None,
+            timeout: None,
+            rts: None,
+            dtr: None,
+            pending: Vec::new(),
+            max_pending: 64 * 1024,
+            retry_interval: Duration::from_millis(500),
+            last_attempt: None,
+            on_state_change: None
+        }
+    }
+
+    /// Returns the state of the link.
+    pub fn state(&self) -> LinkState {
+        match self.port {
+            Some(_) => LinkState::Connected,
+            None => LinkState::Disconnected
+        }
+    }
+
+    /// Registers a callback that's called when the state of the link changes.
+    pub fn on_state_change<F>(&mut self, callback: F)
+        where F: FnMut(LinkState) + Send + 'static
+    {
+        self.on_state_change = Some(Box::new(callback));
+    }
+
+    /// Sets the minimum time between attempts to reconnect.
+    ///
+    /// The default is 500 milliseconds.
+    pub fn set_retry_interval(&mut self, retry_interval: Duration) {
+        self.retry_interval = retry_interval;
+    }
+
+    /// Sets the maximum number of bytes buffered while disconnected.
+    ///
+    /// The default is 64 KiB.
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending;
+    }
+
+    /// Returns the number of bytes buffered while disconnected.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns a reference to the port, if it's connected.
+    pub fn get_ref(&self) -> Option<&P> {
+        self.port.as_ref()
+    }
+
+    /// Returns a mutable reference to the port, if it's connected.
+    pub fn get_mut(&mut self) -> Option<&mut P> {
+        self.port.as_mut()
+    }
+
+    /// Applies port settings now, if connected, and after every reconnect.
+    pub fn configure(&mut self, settings: &PortSettings) -> ::Result<()> {
+        self.settings = Some(*settings);
+        self.apply(|port| port.configure(settings))
+    }
+
+    /// Sets the timeout for I/O operations now, if connected, and after every reconnect.
+    pub fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.timeout = Some(timeout);
+        self.apply(|port| port.set_timeout(timeout))
+    }
+
+    /// Sets the RTS signal now, if connected, and after every reconnect.
+    pub fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        self.rts = Some(level);
+        self.apply(|port| port.set_rts(level))
+    }
+
+    /// Sets the DTR signal now, if connected, and after every reconnect.
+    pub fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        self.dtr = Some(level);
+        self.apply(|port| port.set_dtr(level))
+    }
+
+    /// Attempts to open the device, if disconnected, regardless of the retry interval.
+    ///
+    /// Data buffered while disconnected is written after the port has been configured.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error that prevented opening or configuring the device, e.g., `NoDevice` if it
+    /// isn't present.
+    pub fn connect(&mut self) -> ::Result<()> {
+        if self.port.is_some() {
+            return Ok(());
+        }
+
+        self.last_attempt = Some(Instant::now());
+
+        let mut port = (self.connector)()?;
+
+        if let Some(ref settings) = self.settings {
+            port.configure(settings)?;
+        }
+
+        if let Some(timeout) = self.timeout {
+            port.set_timeout(timeout)?;
+        }
+
+        if let Some(level) = self.rts {
+            port.set_rts(level)?;
+        }
+
+        if let Some(level) = self.dtr {
+            port.set_dtr(level)?;
+        }
+
+        // data the device accepted is removed from the buffer before a failed write is reported,
+        // so that it isn't sent again after the next reconnect
+        if !self.pending.is_empty() {
+            while !self.pending.is_empty() {
+                match port.write(&self.pending) {
+                    Ok(0) => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::WriteZero), "failed to write buffered data")),
+                    Ok(n) => { self.pending.drain(..n); },
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                    Err(err) => return Err(::Error::from(err))
+                }
+            }
+
+            port.flush()?;
+        }
+
+        self.port = Some(port);
+        self.notify(LinkState::Connected);
+        Ok(())
+    }
+
+    /// Attempts to reconnect if the retry interval has elapsed, and returns the connected port.
+    fn reconnect(&mut self) -> Option<&mut P> {
+        let due = self.last_attempt.is_none_or(|last| last.elapsed() >= self.retry_interval);
+
+        if self.port.is_none() && due {
+            let _ = self.connect();
+        }
+
+        self.port.as_mut()
+    }
+
+    /// Applies a setting to the connected port, dropping the port if it was disconnected.
+    fn apply<F>(&mut self, f: F) -> ::Result<()>
+        where F: FnOnce(&mut P) -> ::Result<()>
+    {
+        let result = match self.port {
+            Some(ref mut port) => f(port),
+            None => return Ok(())
+        };
+
+        match result {
+            Err(ref err) if err.is_disconnection() => {
+                self.disconnect();
+                Ok(())
+            },
+            result => result
+        }
+    }
+
+    fn check(&mut self, err: io::Error) -> io::Error {
+        let err = ::Error::from(err);
+
+        if err.is_disconnection() {
+            self.disconnect();
+        }
+
+        io::Error::from(err)
+    }
+
+    fn disconnect(&mut self) {
+        self.port = None;
+        self.last_attempt = Some(Instant::now());
+        self.notify(LinkState::Disconnected);
+    }
+
+    fn buffer(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.max_pending - self.pending.len().min(self.max_pending));
+
+        if len == 0 && !buf.is_empty() {
+            return Err(not_connected("device is disconnected and the write buffer is full"));
+        }
+
+        self.pending.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn notify(&mut self, state: LinkState) {
+        if let Some(ref mut on_state_change) = self.on_state_change {
+            on_state_change(state);
+        }
+    }
+}
+
+impl<P: SerialPort> io::Read for Session<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match self.reconnect() {
+            Some(port) => port.read(buf),
+            None => return Err(not_connected("device is disconnected"))
+        };
+
+        result.map_err(|err| self.check(err))
+    }
+}
+
+impl<P: SerialPort> io::Write for Session<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = match self.reconnect() {
+            Some(port) => port.write(buf),
+            None => return self.buffer(buf)
+        };
+
+        match result.map_err(|err| self.check(err)) {
+            Err(_) if self.port.is_none() => self.buffer(buf),
+            result => result
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = match self.reconnect() {
+            Some(port) => port.flush(),
+            None => return if self.pending.is_empty() { Ok(()) } else { Err(not_connected("device is disconnected")) }
+        };
+
+        result.map_err(|err| self.check(err))
+    }
+}
+
+fn not_connected(description: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, description)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self,Read,Write};
+    use std::sync::{Arc,Mutex};
+    use std::time::Duration;
+
+    use super::{LinkState,Session};
+    use ::testing::{Event,ScriptedDevice};
+
+    fn session(devices: Vec<::Result<ScriptedDevice>>) -> (Session<ScriptedDevice>, Arc<Mutex<Vec<LinkState>>>) {
+        let mut devices: VecDeque<_> = devices.into_iter().collect();
+
+        let mut session = Session::with_connector(move || {
+            devices.pop_front().unwrap_or_else(|| Err(::Error::new(::ErrorKind::NoDevice, "unplugged")))
+        });
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let recorded = states.clone();
+        session.on_state_change(move |state| recorded.lock().unwrap().push(state));
+        session.set_retry_interval(Duration::ZERO);
+
+        (session, states)
+    }
+
+    #[test]
+    fn session_buffers_writes_while_disconnected() {
+        let mut unplugged = ScriptedDevice::new();
+        unplugged.read_error = Some(io::ErrorKind::BrokenPipe);
+
+        let (mut session, states) = session(vec![
+            Ok(unplugged),
+            Err(::Error::new(::ErrorKind::NoDevice, "unplugged")),
+            Ok(ScriptedDevice::new())
+        ]);
+
+        session.set_rts(true).unwrap();
+        session.connect().unwrap();
+
+        assert!(session.read(&mut [0; 8]).is_err());
+        assert_eq!(session.state(), LinkState::Disconnected);
+
+        assert_eq!(session.write(b"abc").unwrap(), 3);
+        assert_eq!(session.pending(), 3);
+
+        session.write_all(b"def").unwrap();
+
+        let device = session.get_ref().unwrap();
+        assert_eq!(device.output(), b"abcdef".to_vec());
+        assert_eq!(device.events[0], Event::Rts(true));
+        assert_eq!(*states.lock().unwrap(), vec![LinkState::Connected, LinkState::Disconnected, LinkState::Connected]);
+    }
+
+    #[test]
+    fn session_keeps_only_unwritten_data_after_failed_reconnect() {
+        let mut failing = ScriptedDevice::new();
+        failing.write_limit = Some(2);
+
+        let (mut session, _) = session(vec![
+            Err(::Error::new(::ErrorKind::NoDevice, "unplugged")),
+            Ok(failing),
+            Ok(ScriptedDevice::new())
+        ]);
+
+        assert_eq!(session.write(b"abcdef").unwrap(), 6);
+        assert_eq!(session.connect().unwrap_err().kind(), ::ErrorKind::Io(io::ErrorKind::BrokenPipe));
+        assert_eq!(session.pending(), 4);
+
+        session.connect().unwrap();
+        assert_eq!(session.get_ref().unwrap().output(), b"cdef".to_vec());
+    }
+
+    #[test]
+    fn session_limits_pending_data() {
+        let (mut session, _) = session(vec![]);
+        session.set_max_pending(4);
+
+        assert_eq!(session.write(b"abcdef").unwrap(), 4);
+        assert_eq!(session.write(b"g").unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert_eq!(session.read(&mut [0; 8]).unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert!(session.flush().is_err());
+    }
+}
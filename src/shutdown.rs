@@ -0,0 +1,108 @@
+//! Stopping background threads with a deadline.
+//!
+//! `Drain` and `join_until()` are only compiled with the optional modules that run background
+//! threads.
+
+#[cfg(any(feature = "channel", feature = "responder", feature = "tools"))]
+use std::sync::Mutex;
+#[cfg(any(feature = "channel", feature = "responder", feature = "tools"))]
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+#[cfg(any(feature = "aggregate", feature = "analyzer", feature = "arena", feature = "channel", feature = "gateway", feature = "nmea", feature = "poller", feature = "responder", feature = "ring", feature = "scheduler", feature = "tools"))]
+use std::io;
+#[cfg(any(feature = "aggregate", feature = "analyzer", feature = "arena", feature = "channel", feature = "gateway", feature = "nmea", feature = "poller", feature = "responder", feature = "ring", feature = "scheduler", feature = "tools"))]
+use std::thread::{self,JoinHandle};
+#[cfg(any(feature = "aggregate", feature = "analyzer", feature = "arena", feature = "channel", feature = "gateway", feature = "nmea", feature = "poller", feature = "responder", feature = "ring", feature = "scheduler", feature = "tools"))]
+use std::time::{Duration,Instant};
+
+/// The outcome of stopping a background thread with `shutdown()`.
+#[derive(Debug)]
+pub struct Shutdown<T> {
+    /// The port returned by the thread, or the error that stopped it.
+    ///
+    /// This is an error of kind `Io(TimedOut)` if the thread didn't stop before the deadline. The
+    /// thread is left to stop on its own in that case.
+    pub result: ::Result<T>,
+
+    /// Number of bytes that were queued for writing but not written.
+    ///
+    /// Data that was being written when the deadline passed is counted as unsent.
+    pub unsent: usize
+}
+
+/// A background thread's queued data and the deadline for writing it.
+///
+/// The number of unsent bytes is kept up to date as data is queued and written, so that it can be
+/// reported even if the thread doesn't stop in time.
+#[cfg(any(feature = "channel", feature = "responder", feature = "tools"))]
+#[derive(Debug)]
+pub(crate) struct Drain {
+    deadline: Mutex<Option<Instant>>,
+    unsent: AtomicUsize
+}
+
+#[cfg(any(feature = "channel", feature = "responder", feature = "tools"))]
+impl Drain {
+    pub(crate) fn new() -> Self {
+        Drain {
+            deadline: Mutex::new(None),
+            unsent: AtomicUsize::new(0)
+        }
+    }
+
+    /// Asks the thread to write its queued data until `deadline` and then stop.
+    pub(crate) fn request(&self, deadline: Instant) {
+        *self.deadline.lock().unwrap() = Some(deadline);
+    }
+
+    /// Returns the deadline if the thread has been asked to stop.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock().unwrap()
+    }
+
+    /// Returns `true` if the thread has been asked to stop and the deadline has passed.
+    pub(crate) fn expired(&self) -> bool {
+        self.deadline().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Records that `len` bytes were queued for writing.
+    pub(crate) fn queued(&self, len: usize) {
+        self.unsent.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Records that `len` queued bytes were written or discarded.
+    pub(crate) fn written(&self, len: usize) {
+        self.unsent.fetch_sub(len, Ordering::Relaxed);
+    }
+
+    /// Returns the number of queued bytes that haven't been written.
+    pub(crate) fn unsent(&self) -> usize {
+        self.unsent.load(Ordering::Relaxed)
+    }
+}
+
+/// Waits until a thread has finished or `deadline` passes. Without a deadline, waits until the
+/// thread has finished.
+///
+/// The `description` names the thread in the errors for a thread that panicked or didn't stop in
+/// time.
+#[cfg(any(feature = "aggregate", feature = "analyzer", feature = "arena", feature = "channel", feature = "gateway", feature = "nmea", feature = "poller", feature = "responder", feature = "ring", feature = "scheduler", feature = "tools"))]
+pub(crate) fn join_until<T>(thread: JoinHandle<T>, deadline: Option<Instant>, description: &str) -> ::Result<T> {
+    if let Some(deadline) = deadline {
+        while !thread.is_finished() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::from_secs(0) {
+                let err = ::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), format!("{} didn't stop before the deadline", description));
+                return Err(err.with_operation("shutdown"));
+            }
+
+            thread::sleep(remaining.min(Duration::from_millis(1)));
+        }
+    }
+
+    match thread.join() {
+        Ok(result) => Ok(result),
+        Err(_) => Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Other), format!("{} panicked", description)))
+    }
+}
@@ -0,0 +1,458 @@
+//! Flashing AVR microcontrollers with the STK500 protocols.
+//!
+//! `Stk500v1` speaks the protocol of the Arduino bootloaders such as Optiboot, and `Stk500v2`
+//! speaks the framed protocol of the Arduino Mega bootloader and of STK500v2-compatible ISP
+//! programmers. Both implement `Programmer`, which writes and verifies flash memory page by page:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::prelude::*;
+//! use serial::stk500::{self,Programmer,Stk500v1};
+//!
+//! let image = std::fs::read("blink.bin").unwrap();
+//!
+//! let mut port = serial::open("/dev/ttyACM0").unwrap();
+//! port.configure(&serial::PortSettings::standard(115200)).unwrap();
+//! port.set_timeout(Duration::from_millis(500)).unwrap();
+//!
+//! stk500::reset(&mut port).unwrap();
+//!
+//! let mut programmer = Stk500v1::new(port);
+//! programmer.sync().unwrap();
+//! programmer.enter_programming().unwrap();
+//! programmer.write_flash(0, &image, 128).unwrap();
+//! programmer.verify_flash(0, &image, 128).unwrap();
+//! programmer.leave_programming().unwrap();
+//! ```
+//!
+//! Responses are read with the port's timeout, so the timeout should be long enough for the
+//! device to program a page.
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use ::SerialPort;
+
+/// Resets a board with an auto-reset circuit on DTR, such as an Arduino, into its bootloader.
+///
+/// DTR and RTS are deasserted for 250 ms and then asserted, which pulls the microcontroller's
+/// reset line low through the board's coupling capacitor. After waiting 50 ms for the bootloader
+/// to start, any data received from the application before the reset is discarded.
+pub fn reset<P: SerialPort + ?Sized>(port: &mut P) -> ::Result<()> {
+    port.set_dtr(false)?;
+    port.set_rts(false)?;
+    thread::sleep(Duration::from_millis(250));
+
+    port.set_dtr(true)?;
+    port.set_rts(true)?;
+    thread::sleep(Duration::from_millis(50));
+
+    port.discard_input()
+}
+
+/// Page-by-page access to a device's flash memory.
+pub trait Programmer {
+    /// Sets the byte address of the next page that's written or read.
+    ///
+    /// Flash is addressed in 16-bit words by both protocols, so the address should be even.
+    fn load_address(&mut self, address: u32) -> ::Result<()>;
+
+    /// Writes a page of flash memory at the loaded address.
+    fn write_page(&mut self, data: &[u8]) -> ::Result<()>;
+
+    /// Reads `len` bytes of flash memory at the loaded address.
+    fn read_page(&mut self, len: usize) -> ::Result<Vec<u8>>;
+
+    /// Writes `data` to flash memory starting at `address`, one page of `page_size` bytes at a
+    /// time.
+    ///
+    /// `address` should be aligned to a page. The last page is written as a partial page if
+    /// `data` doesn't fill it.
+    fn write_flash(&mut self, address: u32, data: &[u8], page_size: usize) -> ::Result<()> {
+        for (i, page) in data.chunks(page_size.max(2)).enumerate() {
+            self.load_address(address + (i * page_size.max(2)) as u32)?;
+            self.write_page(page)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads back flash memory starting at `address` and compares it to `data`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error of kind `Io(InvalidData)` that names the first address that differs if
+    /// the flash memory doesn't match `data`.
+    fn verify_flash(&mut self, address: u32, data: &[u8], page_size: usize) -> ::Result<()> {
+        for (i, page) in data.chunks(page_size.max(2)).enumerate() {
+            let page_address = address + (i * page_size.max(2)) as u32;
+
+            self.load_address(page_address)?;
+            let read = self.read_page(page.len())?;
+
+            if let Some(offset) = page.iter().zip(&read).position(|(a, b)| a != b) {
+                let description = format!("flash differs at address {:#x}", page_address as usize + offset);
+                return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("verify flash"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const CRC_EOP: u8 = 0x20;
+const STK_INSYNC: u8 = 0x14;
+const STK_OK: u8 = 0x10;
+
+const STK_GET_SYNC: u8 = 0x30;
+const STK_GET_PARAMETER: u8 = 0x41;
+const STK_ENTER_PROGMODE: u8 = 0x50;
+const STK_LEAVE_PROGMODE: u8 = 0x51;
+const STK_LOAD_ADDRESS: u8 = 0x55;
+const STK_PROG_PAGE: u8 = 0x64;
+const STK_READ_PAGE: u8 = 0x74;
+const STK_READ_SIGN: u8 = 0x75;
+
+/// A device that speaks STK500 version 1, such as an Arduino bootloader.
+pub struct Stk500v1<P> {
+    port: P
+}
+
+impl<P: SerialPort> Stk500v1<P> {
+    /// Creates a programmer that talks to the device on `port`.
+    pub fn new(port: P) -> Self {
+        Stk500v1 { port }
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Synchronizes with the device.
+    ///
+    /// The request is repeated up to ten times, discarding any other received data, since a
+    /// bootloader that has just started may miss the first attempts.
+    pub fn sync(&mut self) -> ::Result<()> {
+        let mut result = Ok(Vec::new());
+
+        for _ in 0..10 {
+            result = self.command(&[STK_GET_SYNC], 0);
+
+            if result.is_ok() {
+                break;
+            }
+
+            self.port.discard_input()?;
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Reads a parameter, e.g. `0x81` for the major software version.
+    pub fn get_parameter(&mut self, parameter: u8) -> ::Result<u8> {
+        Ok(self.command(&[STK_GET_PARAMETER, parameter], 1)?[0])
+    }
+
+    /// Reads the device's three signature bytes.
+    pub fn read_signature(&mut self) -> ::Result<[u8; 3]> {
+        let signature = self.command(&[STK_READ_SIGN], 3)?;
+        Ok([signature[0], signature[1], signature[2]])
+    }
+
+    /// Enters programming mode.
+    pub fn enter_programming(&mut self) -> ::Result<()> {
+        self.command(&[STK_ENTER_PROGMODE], 0).map(|_| ())
+    }
+
+    /// Leaves programming mode, which starts the application on Arduino bootloaders.
+    pub fn leave_programming(&mut self) -> ::Result<()> {
+        self.command(&[STK_LEAVE_PROGMODE], 0).map(|_| ())
+    }
+
+    /// Sends a command terminated by `CRC_EOP` and returns the `len` bytes of its response.
+    fn command(&mut self, command: &[u8], len: usize) -> ::Result<Vec<u8>> {
+        let mut request = command.to_vec();
+        request.push(CRC_EOP);
+
+        io::Write::write_all(&mut self.port, &request)?;
+        io::Write::flush(&mut self.port)?;
+
+        let mut response = vec![0; len + 2];
+        io::Read::read_exact(&mut self.port, &mut response)?;
+
+        if response[0] != STK_INSYNC || response[len + 1] != STK_OK {
+            return Err(protocol_error("STK500 device is not in sync"));
+        }
+
+        response.pop();
+        response.remove(0);
+        Ok(response)
+    }
+}
+
+impl<P: SerialPort> Programmer for Stk500v1<P> {
+    fn load_address(&mut self, address: u32) -> ::Result<()> {
+        let word = address / 2;
+        self.command(&[STK_LOAD_ADDRESS, word as u8, (word >> 8) as u8], 0).map(|_| ())
+    }
+
+    fn write_page(&mut self, data: &[u8]) -> ::Result<()> {
+        let mut command = vec![STK_PROG_PAGE, (data.len() >> 8) as u8, data.len() as u8, b'F'];
+        command.extend_from_slice(data);
+
+        self.command(&command, 0).map(|_| ())
+    }
+
+    fn read_page(&mut self, len: usize) -> ::Result<Vec<u8>> {
+        self.command(&[STK_READ_PAGE, (len >> 8) as u8, len as u8, b'F'], len)
+    }
+}
+
+const MESSAGE_START: u8 = 0x1b;
+const TOKEN: u8 = 0x0e;
+const STATUS_CMD_OK: u8 = 0x00;
+
+const CMD_SIGN_ON: u8 = 0x01;
+const CMD_GET_PARAMETER: u8 = 0x03;
+const CMD_LOAD_ADDRESS: u8 = 0x06;
+const CMD_ENTER_PROGMODE_ISP: u8 = 0x10;
+const CMD_LEAVE_PROGMODE_ISP: u8 = 0x11;
+const CMD_PROGRAM_FLASH_ISP: u8 = 0x13;
+const CMD_READ_FLASH_ISP: u8 = 0x14;
+
+/// A device that speaks STK500 version 2, such as the Arduino Mega bootloader.
+///
+/// The programming commands use the parameters that avrdude uses for ATmega devices, which
+/// bootloaders accept and largely ignore.
+pub struct Stk500v2<P> {
+    port: P,
+    sequence: u8
+}
+
+impl<P: SerialPort> Stk500v2<P> {
+    /// Creates a programmer that talks to the device on `port`.
+    pub fn new(port: P) -> Self {
+        Stk500v2 { port, sequence: 0 }
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Synchronizes with the device and returns its signature, e.g. `AVRISP_2`.
+    pub fn sign_on(&mut self) -> ::Result<String> {
+        let body = self.command(&[CMD_SIGN_ON])?;
+        let len = body.first().cloned().unwrap_or(0) as usize;
+
+        Ok(String::from_utf8_lossy(&body[1..body.len().min(1 + len)]).into_owned())
+    }
+
+    /// Reads a parameter, e.g. `0x91` for the major software version.
+    pub fn get_parameter(&mut self, parameter: u8) -> ::Result<u8> {
+        self.command(&[CMD_GET_PARAMETER, parameter])?.first().cloned().ok_or_else(|| protocol_error("STK500v2 response is too short"))
+    }
+
+    /// Enters programming mode.
+    pub fn enter_programming(&mut self) -> ::Result<()> {
+        self.command(&[CMD_ENTER_PROGMODE_ISP, 200, 100, 25, 32, 0, 0x53, 3, 0xac, 0x53, 0x00, 0x00]).map(|_| ())
+    }
+
+    /// Leaves programming mode, which starts the application on Arduino bootloaders.
+    pub fn leave_programming(&mut self) -> ::Result<()> {
+        self.command(&[CMD_LEAVE_PROGMODE_ISP, 1, 1]).map(|_| ())
+    }
+
+    /// Sends a command in a message and returns the body of the response after its status byte.
+    fn command(&mut self, body: &[u8]) -> ::Result<Vec<u8>> {
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let message = encode_message(self.sequence, body);
+        io::Write::write_all(&mut self.port, &message)?;
+        io::Write::flush(&mut self.port)?;
+
+        let response = self.read_message()?;
+
+        if response.len() < 2 || response[0] != body[0] {
+            return Err(protocol_error("unexpected STK500v2 response"));
+        }
+
+        if response[1] != STATUS_CMD_OK {
+            return Err(protocol_error(&format!("STK500v2 command {:#04x} failed with status {:#04x}", body[0], response[1])));
+        }
+
+        Ok(response[2..].to_vec())
+    }
+
+    /// Reads a message with the current sequence number and returns its body.
+    fn read_message(&mut self) -> ::Result<Vec<u8>> {
+        let mut byte = [0];
+
+        loop {
+            io::Read::read_exact(&mut self.port, &mut byte)?;
+
+            if byte[0] == MESSAGE_START {
+                break;
+            }
+        }
+
+        let mut header = [0; 4];
+        io::Read::read_exact(&mut self.port, &mut header)?;
+
+        let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        let mut rest = vec![0; len + 1];
+        io::Read::read_exact(&mut self.port, &mut rest)?;
+
+        let checksum = [MESSAGE_START].iter().chain(&header).chain(&rest).fold(0, |sum, &b| sum ^ b);
+
+        if header[0] != self.sequence || header[3] != TOKEN || checksum != 0 {
+            return Err(protocol_error("corrupt STK500v2 message"));
+        }
+
+        rest.pop();
+        Ok(rest)
+    }
+}
+
+impl<P: SerialPort> Programmer for Stk500v2<P> {
+    fn load_address(&mut self, address: u32) -> ::Result<()> {
+        let mut word = address / 2;
+
+        // devices with more than 128 KiB of flash need the extended address byte to be loaded
+        if word > 0xffff {
+            word |= 0x8000_0000;
+        }
+
+        let word = word.to_be_bytes();
+        self.command(&[CMD_LOAD_ADDRESS, word[0], word[1], word[2], word[3]]).map(|_| ())
+    }
+
+    fn write_page(&mut self, data: &[u8]) -> ::Result<()> {
+        let mut body = vec![CMD_PROGRAM_FLASH_ISP, (data.len() >> 8) as u8, data.len() as u8, 0xc1, 10, 0x40, 0x4c, 0x20, 0x00, 0x00];
+        body.extend_from_slice(data);
+
+        self.command(&body).map(|_| ())
+    }
+
+    fn read_page(&mut self, len: usize) -> ::Result<Vec<u8>> {
+        let mut body = self.command(&[CMD_READ_FLASH_ISP, (len >> 8) as u8, len as u8, 0x20])?;
+
+        // the data is followed by a second status byte
+        if body.len() != len + 1 || body[len] != STATUS_CMD_OK {
+            return Err(protocol_error("unexpected STK500v2 response"));
+        }
+
+        body.truncate(len);
+        Ok(body)
+    }
+}
+
+/// Encodes an STK500v2 message with its header and checksum.
+fn encode_message(sequence: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![MESSAGE_START, sequence, (body.len() >> 8) as u8, body.len() as u8, TOKEN];
+    message.extend_from_slice(body);
+
+    let checksum = message.iter().fold(0, |sum, &b| sum ^ b);
+    message.push(checksum);
+    message
+}
+
+fn protocol_error(description: &str) -> ::Error {
+    ::Error::new(::ErrorKind::Io(io::ErrorKind::InvalidData), description).with_operation("STK500")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{encode_message,Programmer,Stk500v1,Stk500v2};
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn stk500v1_retries_sync() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"\x00\x00");
+        device.push_input(b"\x14\x10");
+
+        let mut programmer = Stk500v1::new(device);
+        programmer.sync().unwrap();
+
+        let device = programmer.into_inner();
+        assert_eq!(device.output(), b"\x30\x20\x30\x20".to_vec());
+        assert!(device.events.contains(&Event::DiscardInput));
+    }
+
+    #[test]
+    fn stk500v1_writes_and_verifies_pages() {
+        let mut device = ScriptedDevice::new();
+
+        for _ in 0..4 {
+            device.push_input(b"\x14\x10");
+        }
+
+        device.push_input(b"\x14\x10");
+        device.push_input(b"\x14\x01\x02\x03\x04\x10");
+        device.push_input(b"\x14\x10");
+        device.push_input(b"\x14\x05\x10");
+
+        let mut programmer = Stk500v1::new(device);
+        programmer.write_flash(0x100, &[1, 2, 3, 4, 5], 4).unwrap();
+        programmer.verify_flash(0x100, &[1, 2, 3, 4, 5], 4).unwrap();
+
+        let output = programmer.into_inner().output();
+        assert!(output.starts_with(b"\x55\x80\x00\x20\x64\x00\x04F\x01\x02\x03\x04\x20\x55\x82\x00\x20\x64\x00\x01F\x05\x20"));
+    }
+
+    #[test]
+    fn stk500v1_reports_verification_failure() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"\x14\x10");
+        device.push_input(b"\x14\x01\xff\x10");
+
+        let mut programmer = Stk500v1::new(device);
+        let err = programmer.verify_flash(0, &[1, 2], 2).err().unwrap();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+        assert!(err.to_string().contains("0x1"));
+    }
+
+    #[test]
+    fn stk500v2_frames_commands() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode_message(1, b"\x01\x00\x08AVRISP_2"));
+        device.push_input(b"noise");
+        device.push_input(&encode_message(2, b"\x06\x00"));
+
+        let mut programmer = Stk500v2::new(device);
+        assert_eq!(programmer.sign_on().unwrap(), "AVRISP_2");
+        programmer.load_address(0x2_0000).unwrap();
+
+        let output = programmer.into_inner().output();
+        assert_eq!(&output[..7], &encode_message(1, b"\x01")[..]);
+        assert_eq!(&output[7..], &encode_message(2, b"\x06\x80\x01\x00\x00")[..]);
+    }
+
+    #[test]
+    fn stk500v2_rejects_failed_status() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&encode_message(1, b"\x13\xc0"));
+
+        let mut programmer = Stk500v2::new(device);
+        let err = programmer.write_page(&[0; 4]).err().unwrap();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,210 @@
+//! Test doubles shared by the unit tests of the helper modules.
+
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::default::Default;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use ::{PortSettings,SerialDevice};
+
+/// An operation performed on a `ScriptedDevice`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Event {
+    Write(Vec<u8>),
+    Flush,
+    DiscardInput,
+    Rts(bool),
+    Dtr(bool),
+    Break(Duration)
+}
+
+/// A serial device that replays scripted input and records everything done to it.
+///
+/// Each read consumes one chunk from `input`. Once `input` is empty, reads fail with `read_error`
+/// or time out. Writes fail with `write_error` if it's set, and take `write_delay` otherwise. If
+/// `write_limit` is set, writes accept that many bytes in total and then fail with `BrokenPipe`.
+pub struct ScriptedDevice {
+    pub settings: PortSettings,
+    pub timeout: Duration,
+    pub input: VecDeque<Vec<u8>>,
+    pub read_error: Option<io::ErrorKind>,
+    pub write_error: Option<io::ErrorKind>,
+    pub write_delay: Duration,
+    pub write_limit: Option<usize>,
+    pub events: Vec<Event>,
+    pub echo: bool,
+    pub rts: bool,
+    pub dtr: bool,
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool
+}
+
+impl ScriptedDevice {
+    pub fn new() -> Self {
+        ScriptedDevice {
+            settings: Default::default(),
+            timeout: Duration::from_millis(100),
+            input: VecDeque::new(),
+            read_error: None,
+            write_error: None,
+            write_delay: Duration::from_secs(0),
+            write_limit: None,
+            events: Vec::new(),
+            echo: false,
+            rts: false,
+            dtr: false,
+            cts: false,
+            dsr: false,
+            ri: false,
+            cd: false
+        }
+    }
+
+    /// Queues a chunk of data to be returned by a future read.
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.input.push_back(data.to_vec());
+    }
+
+    /// Returns all data written to the device.
+    pub fn output(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        for event in &self.events {
+            if let Event::Write(ref data) = *event {
+                output.extend_from_slice(data);
+            }
+        }
+
+        output
+    }
+}
+
+impl io::Read for ScriptedDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = match self.input.pop_front() {
+            Some(chunk) => chunk,
+            None => match self.read_error {
+                Some(kind) => return Err(io::Error::from(kind)),
+                None => return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"))
+            }
+        };
+
+        let len = ::std::cmp::min(buf.len(), chunk.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+
+        if len < chunk.len() {
+            self.input.push_front(chunk.split_off(len));
+        }
+
+        Ok(len)
+    }
+}
+
+impl io::Write for ScriptedDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(kind) = self.write_error {
+            return Err(io::Error::from(kind));
+        }
+
+        let buf = match self.write_limit {
+            Some(limit) => {
+                let written = self.output().len();
+
+                if written >= limit {
+                    return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+                }
+
+                &buf[..buf.len().min(limit - written)]
+            },
+            None => buf
+        };
+
+        thread::sleep(self.write_delay);
+        self.events.push(Event::Write(buf.to_vec()));
+
+        if self.echo {
+            self.input.push_back(buf.to_vec());
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.events.push(Event::Flush);
+        Ok(())
+    }
+}
+
+impl SerialDevice for ScriptedDevice {
+    type Settings = PortSettings;
+
+    fn read_settings(&self) -> ::Result<PortSettings> {
+        Ok(self.settings)
+    }
+
+    fn write_settings(&mut self, settings: &PortSettings) -> ::Result<()> {
+        self.settings = *settings;
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn discard_input(&mut self) -> ::Result<()> {
+        // queued input is kept, since tests queue responses before sending requests
+        self.events.push(Event::DiscardInput);
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> ::Result<()> {
+        self.events.push(Event::Rts(level));
+        self.rts = level;
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> ::Result<()> {
+        self.events.push(Event::Dtr(level));
+        self.dtr = level;
+        Ok(())
+    }
+
+    fn read_cts(&mut self) -> ::Result<bool> {
+        Ok(self.cts)
+    }
+
+    fn read_dsr(&mut self) -> ::Result<bool> {
+        Ok(self.dsr)
+    }
+
+    fn read_ri(&mut self) -> ::Result<bool> {
+        Ok(self.ri)
+    }
+
+    fn read_cd(&mut self) -> ::Result<bool> {
+        Ok(self.cd)
+    }
+
+    fn read_rts(&mut self) -> ::Result<bool> {
+        Ok(self.rts)
+    }
+
+    fn read_dtr(&mut self) -> ::Result<bool> {
+        Ok(self.dtr)
+    }
+
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        self.events.push(Event::Break(duration));
+        Ok(())
+    }
+}
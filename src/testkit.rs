@@ -0,0 +1,332 @@
+//! Hardware-in-the-loop tests against real serial ports.
+//!
+//! A test fixture is configured with environment variables, so that the same tests run against
+//! whatever hardware a CI runner provides and are skipped where there is none:
+//!
+//! * `SERIAL_TESTKIT_LOOPBACK`: the path of a port whose TX is wired to its RX. For the signal
+//!   checks, RTS must also be wired to CTS and DTR to DSR.
+//! * `SERIAL_TESTKIT_PAIR`: the paths of two ports, separated by a comma, that are connected by a
+//!   null-modem cable, which crosses TX and RX, RTS and CTS, and DTR and DSR.
+//! * `SERIAL_TESTKIT_BAUD`: the baud rate to test at. The default is 115200.
+//!
+//! ```no_run
+//! use serial::testkit;
+//!
+//! #[test]
+//! fn echoes_through_fixture() {
+//!     let mut link = match testkit::open_fixture() {
+//!         Some(link) => link,
+//!         None => return
+//!     };
+//!
+//!     link.assert_round_trip(b"hello");
+//!     link.assert_settings_applied(&serial::PortSettings::standard(9600));
+//!     link.assert_signal_crossover();
+//! }
+//! ```
+//!
+//! The assertions can also be used with other ports, e.g., a pair of `mock` ports, by creating a
+//! `Link` directly.
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration,Instant};
+
+use ::{BaudRate,PortSettings,SerialPort,SerialPortSettings,SystemPort};
+
+/// The environment variable that names a loopback port.
+pub const LOOPBACK_VAR: &str = "SERIAL_TESTKIT_LOOPBACK";
+
+/// The environment variable that names a pair of connected ports.
+pub const PAIR_VAR: &str = "SERIAL_TESTKIT_PAIR";
+
+/// The environment variable that sets the baud rate to test at.
+pub const BAUD_VAR: &str = "SERIAL_TESTKIT_BAUD";
+
+/// A hardware test fixture.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Fixture {
+    /// A port whose outputs are wired to its own inputs.
+    Loopback(PathBuf),
+
+    /// Two ports connected by a null-modem cable.
+    Pair(PathBuf, PathBuf)
+}
+
+impl Fixture {
+    /// Returns the fixture configured by the environment, or `None` if none is configured.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if `SERIAL_TESTKIT_PAIR` doesn't
+    /// name exactly two ports.
+    pub fn from_env() -> ::Result<Option<Fixture>> {
+        if let Some(pair) = env::var_os(PAIR_VAR) {
+            let pair = pair.to_string_lossy().into_owned();
+            let paths: Vec<&str> = pair.split(',').map(str::trim).collect();
+
+            return match paths[..] {
+                [a, b] if !a.is_empty() && !b.is_empty() => Ok(Some(Fixture::Pair(PathBuf::from(a), PathBuf::from(b)))),
+                _ => Err(::Error::new(::ErrorKind::InvalidInput, format!("{} must name two ports separated by a comma", PAIR_VAR)))
+            };
+        }
+
+        Ok(env::var_os(LOOPBACK_VAR).filter(|path| !path.is_empty()).map(|path| Fixture::Loopback(PathBuf::from(path))))
+    }
+
+    /// Opens the fixture's ports and configures them with the baud rate from the environment.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `InvalidInput` if `SERIAL_TESTKIT_BAUD` isn't a valid
+    /// baud rate, or any error that occurs opening and configuring the ports.
+    pub fn open(&self) -> ::Result<Link<SystemPort>> {
+        let baud_rate = match env::var(BAUD_VAR) {
+            Ok(baud) => baud.trim().parse::<BaudRate>().map_err(|_| {
+                ::Error::new(::ErrorKind::InvalidInput, format!("{} is not a valid baud rate", BAUD_VAR))
+            })?,
+            Err(_) => BaudRate::Baud115200
+        };
+
+        let settings = PortSettings { baud_rate, ..PortSettings::standard(115200) };
+
+        let mut link = match *self {
+            Fixture::Loopback(ref path) => Link::loopback(::open(path)?),
+            Fixture::Pair(ref a, ref b) => Link::pair(::open(a)?, ::open(b)?)
+        };
+
+        link.configure(&settings)?;
+        Ok(link)
+    }
+}
+
+/// Opens the fixture configured by the environment, or returns `None` if none is configured.
+///
+/// Tests that require hardware can return early when this returns `None`. Nothing is printed in
+/// that case; a test that wants to report the skip can name `LOOPBACK_VAR` and `PAIR_VAR` itself.
+///
+/// ## Panics
+///
+/// This function panics if the fixture is misconfigured or can't be opened, so that a broken
+/// fixture fails the test instead of skipping it.
+#[track_caller]
+pub fn open_fixture() -> Option<Link<SystemPort>> {
+    match Fixture::from_env() {
+        Ok(Some(fixture)) => match fixture.open() {
+            Ok(link) => Some(link),
+            Err(err) => panic!("failed to open test fixture {:?}: {}", fixture, err)
+        },
+        Ok(None) => None,
+        Err(err) => panic!("invalid test fixture: {}", err)
+    }
+}
+
+/// A connection between a transmitting port and a receiving port, which may be the same port.
+pub struct Link<P> {
+    tx: P,
+    rx: Option<P>,
+
+    /// Maximum time to wait for data or a signal to arrive. The default is one second.
+    pub timeout: Duration
+}
+
+impl<P: ::SerialDevice> Link<P> {
+    /// Creates a link that transmits and receives through a port wired in loopback.
+    pub fn loopback(port: P) -> Self {
+        Link { tx: port, rx: None, timeout: Duration::from_secs(1) }
+    }
+
+    /// Creates a link that transmits through `tx` and receives through `rx`.
+    pub fn pair(tx: P, rx: P) -> Self {
+        Link { tx, rx: Some(rx), timeout: Duration::from_secs(1) }
+    }
+
+    /// Returns a mutable reference to the transmitting port.
+    pub fn tx(&mut self) -> &mut P {
+        &mut self.tx
+    }
+
+    /// Returns a mutable reference to the receiving port.
+    pub fn rx(&mut self) -> &mut P {
+        match self.rx {
+            Some(ref mut rx) => rx,
+            None => &mut self.tx
+        }
+    }
+
+    /// Returns the ports.
+    pub fn into_inner(self) -> (P, Option<P>) {
+        (self.tx, self.rx)
+    }
+
+    /// Applies `settings` to every port of the link.
+    pub fn configure(&mut self, settings: &PortSettings) -> ::Result<()> {
+        self.tx.configure(settings)?;
+
+        if let Some(ref mut rx) = self.rx {
+            rx.configure(settings)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `data` and asserts that the same data is received.
+    ///
+    /// Stale input is discarded first.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if an I/O error occurs or if the received data differs.
+    #[track_caller]
+    pub fn assert_round_trip(&mut self, data: &[u8]) {
+        let timeout = self.timeout;
+
+        self.rx().discard_input().unwrap_or_else(|err| panic!("failed to discard input: {}", err));
+        self.tx.write_all(data).unwrap_or_else(|err| panic!("failed to write: {}", err));
+        self.tx.flush().unwrap_or_else(|err| panic!("failed to flush: {}", err));
+
+        let mut received = Vec::with_capacity(data.len());
+        let mut buf = [0; 256];
+        let deadline = Instant::now() + timeout;
+
+        while received.len() < data.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match self.rx().read_with_timeout(&mut buf, remaining) {
+                Ok(len) => received.extend_from_slice(&buf[..len]),
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => panic!("failed to read: {}", err)
+            }
+        }
+
+        assert!(received == data, "round trip failed: sent {} bytes {:02x?}, received {} bytes {:02x?}", data.len(), data, received.len(), received);
+    }
+
+    /// Configures the link with `settings`, asserts that the ports report them, and then asserts
+    /// that data survives a round trip with them.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if the settings can't be applied or read, if a port reports different
+    /// settings, or if the round trip fails.
+    #[track_caller]
+    pub fn assert_settings_applied(&mut self, settings: &PortSettings) {
+        self.configure(settings).unwrap_or_else(|err| panic!("failed to apply {:?}: {}", settings, err));
+
+        assert_settings(&self.tx, settings, "TX");
+
+        if let Some(ref rx) = self.rx {
+            assert_settings(rx, settings, "RX");
+        }
+
+        let mask = match settings.char_size {
+            ::Bits5 => 0x1f,
+            ::Bits6 => 0x3f,
+            ::Bits7 => 0x7f,
+            ::Bits8 => 0xff
+        };
+
+        let data: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(37) & mask).collect();
+        self.assert_round_trip(&data);
+    }
+
+    /// Asserts that RTS and DTR on the transmitting port drive CTS and DSR on the receiving port,
+    /// at both levels.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if a signal can't be set or read, or if an input doesn't follow its
+    /// output within the link's timeout.
+    #[track_caller]
+    pub fn assert_signal_crossover(&mut self) {
+        for &level in &[true, false, true] {
+            self.tx.set_rts(level).unwrap_or_else(|err| panic!("failed to set RTS: {}", err));
+            self.wait_for_input("CTS", level, |rx| rx.read_cts());
+
+            self.tx.set_dtr(level).unwrap_or_else(|err| panic!("failed to set DTR: {}", err));
+            self.wait_for_input("DSR", level, |rx| rx.read_dsr());
+        }
+    }
+
+    #[track_caller]
+    fn wait_for_input<F>(&mut self, name: &str, level: bool, mut read: F)
+        where F: FnMut(&mut P) -> ::Result<bool>
+    {
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let actual = read(self.rx()).unwrap_or_else(|err| panic!("failed to read {}: {}", name, err));
+
+            if actual == level {
+                return;
+            }
+
+            assert!(Instant::now() < deadline, "{} stayed {} instead of following its output to {}", name, actual, level);
+            ::std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[track_caller]
+fn assert_settings<P: ::SerialDevice>(port: &P, expected: &PortSettings, name: &str) {
+    let settings = ::SerialDevice::read_settings(port).unwrap_or_else(|err| panic!("failed to read {} settings: {}", name, err));
+
+    let actual = PortSettings {
+        baud_rate: settings.baud_rate().unwrap_or_else(|| panic!("{} baud rate is unknown", name)),
+        char_size: settings.char_size().unwrap_or_else(|| panic!("{} character size is unknown", name)),
+        parity: settings.parity().unwrap_or_else(|| panic!("{} parity is unknown", name)),
+        stop_bits: settings.stop_bits().unwrap_or_else(|| panic!("{} stop bits are unknown", name)),
+        flow_control: settings.flow_control().unwrap_or_else(|| panic!("{} flow control is unknown", name))
+    };
+
+    assert!(actual == *expected, "{} settings differ from those applied ({})", name, expected.diff(&actual));
+}
+
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::env;
+
+    use super::{Fixture,Link,LOOPBACK_VAR,PAIR_VAR};
+    use ::mock;
+
+    #[test]
+    fn link_asserts_round_trip_through_pair() {
+        let (a, b) = mock::pair();
+        let mut link = Link::pair(a, b);
+
+        link.assert_round_trip(b"hello");
+        link.assert_settings_applied(&::PortSettings::modbus_rtu(19200));
+        link.assert_signal_crossover();
+    }
+
+    #[test]
+    #[should_panic(expected = "round trip failed")]
+    fn link_fails_round_trip_without_connection() {
+        let (a, _b) = mock::pair();
+        let (_c, d) = mock::pair();
+
+        let mut link = Link::pair(a, d);
+        link.timeout = ::std::time::Duration::from_millis(10);
+        link.assert_round_trip(b"hello");
+    }
+
+    #[test]
+    fn fixture_reads_environment() {
+        env::remove_var(LOOPBACK_VAR);
+        env::set_var(PAIR_VAR, "/dev/ttyUSB0, /dev/ttyUSB1");
+        assert_eq!(Fixture::from_env().unwrap(), Some(Fixture::Pair("/dev/ttyUSB0".into(), "/dev/ttyUSB1".into())));
+
+        env::set_var(PAIR_VAR, "/dev/ttyUSB0");
+        assert_eq!(Fixture::from_env().unwrap_err().kind(), ::ErrorKind::InvalidInput);
+
+        env::remove_var(PAIR_VAR);
+        env::set_var(LOOPBACK_VAR, "/dev/ttyS0");
+        assert_eq!(Fixture::from_env().unwrap(), Some(Fixture::Loopback("/dev/ttyS0".into())));
+
+        env::remove_var(LOOPBACK_VAR);
+        assert_eq!(Fixture::from_env().unwrap(), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Limiting the rate at which data is written.
+//!
+//! Radio modems and satellite terminals accept data over USB far faster than they can send it over
+//! the air, and drop data once their buffers fill. A `Throttle` limits the sustained rate of
+//! writes with a token bucket, while still letting short bursts through at full speed:
+//!
+//! ```no_run
+//! use std::io::prelude::*;
+//! use serial::throttle::Throttle;
+//!
+//! // 9600 bps over the air is about 960 bytes per second
+//! let mut port = Throttle::new(serial::open("/dev/ttyUSB0").unwrap(), 960, 64);
+//!
+//! port.write_all(&[0; 4096]).unwrap();
+//! ```
+//!
+//! The bucket holds up to `burst` bytes and refills at `rate` bytes per second. A write sends as
+//! many bytes as the bucket holds, waiting first if it's empty, so `write()` may accept only part
+//! of a buffer and `write_all()` paces a large buffer in chunks. Reads aren't limited.
+
+use std::io;
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::SerialDevice;
+
+/// A port wrapper that limits the rate at which data is written to it.
+pub struct Throttle<P> {
+    port: P,
+    rate: u32,
+    burst: usize,
+    tokens: f64,
+    updated: Instant
+}
+
+impl<P: io::Write> Throttle<P> {
+    /// Wraps a port to limit writes to `rate` bytes per second, with bursts of up to `burst`
+    /// bytes.
+    ///
+    /// The bucket starts full. A burst of zero is treated as one byte.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `rate` is zero.
+    pub fn new(port: P, rate: u32, burst: usize) -> Self {
+        assert!(rate > 0, "rate must be positive");
+
+        let burst = burst.max(1);
+
+        Throttle {
+            port,
+            rate,
+            burst,
+            tokens: burst as f64,
+            updated: Instant::now()
+        }
+    }
+
+    /// Returns the sustained rate in bytes per second.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Changes the sustained rate in bytes per second.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `rate` is zero.
+    pub fn set_rate(&mut self, rate: u32) {
+        assert!(rate > 0, "rate must be positive");
+
+        self.refill();
+        self.rate = rate;
+    }
+
+    /// Returns the number of bytes that can be written at once after the port has been idle.
+    pub fn burst(&self) -> usize {
+        self.burst
+    }
+
+    /// Changes the number of bytes that can be written at once after the port has been idle.
+    ///
+    /// The bucket isn't filled by a larger burst size. A burst of zero is treated as one byte.
+    pub fn set_burst(&mut self, burst: usize) {
+        self.refill();
+        self.burst = burst.max(1);
+        self.tokens = self.tokens.min(self.burst as f64);
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    ///
+    /// Data written directly to the port isn't limited and isn't counted against the rate.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Adds the tokens earned since the last update, up to the burst size.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.updated).as_secs_f64() * self.rate as f64;
+
+        self.tokens = (self.tokens + earned).min(self.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// A write waits until the bucket holds enough tokens for `buf`, or a full burst if `buf` is
+/// larger, then writes as much of `buf` as the tokens allow.
+impl<P: io::Write> io::Write for Throttle<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.refill();
+
+        let wanted = buf.len().min(self.burst) as f64;
+
+        if self.tokens < wanted {
+            thread::sleep(Duration::from_secs_f64((wanted - self.tokens) / self.rate as f64));
+            self.refill();
+        }
+
+        let len = (self.tokens as usize).clamp(1, buf.len());
+        let n = self.port.write(&buf[..len])?;
+        self.tokens -= n as f64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl<P: io::Read> io::Read for Throttle<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl<P: SerialDevice> SerialDevice for Throttle<P> {
+    type Settings = P::Settings;
+
+    forward_serial_device!(port, write_settings, read_with_timeout, discard_input);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::time::{Duration,Instant};
+
+    use super::Throttle;
+    use ::testing::{Event,ScriptedDevice};
+
+    #[test]
+    fn throttle_writes_burst_immediately() {
+        let mut port = Throttle::new(ScriptedDevice::new(), 100, 16);
+
+        let start = Instant::now();
+        port.write_all(b"0123456789abcdef").unwrap();
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(port.get_ref().events, vec![Event::Write(b"0123456789abcdef".to_vec())]);
+    }
+
+    #[test]
+    fn throttle_limits_sustained_rate() {
+        let mut port = Throttle::new(ScriptedDevice::new(), 1000, 10);
+
+        let data: Vec<u8> = (0..60).collect();
+
+        let start = Instant::now();
+        port.write_all(&data).unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(45));
+        assert_eq!(port.get_ref().output(), data);
+
+        for event in &port.get_ref().events {
+            match *event {
+                Event::Write(ref chunk) => assert!(chunk.len() <= 10),
+                ref event => panic!("unexpected event: {:?}", event)
+            }
+        }
+    }
+
+    #[test]
+    fn throttle_follows_rate_changes() {
+        let mut port = Throttle::new(ScriptedDevice::new(), 1_000_000, 1);
+        port.set_rate(100);
+
+        let start = Instant::now();
+        port.write_all(b"abc").unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert_eq!(port.rate(), 100);
+        assert_eq!(port.get_ref().output(), b"abc");
+    }
+}
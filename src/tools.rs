@@ -0,0 +1,365 @@
+//! Remote ends for testing links.
+//!
+//! `echo()` sends back everything a port receives and `chargen()` transmits an endless pattern of
+//! text, like the classic TCP echo and character generator services. Running one on the far end
+//! of a link is a quick way to validate cabling and adapters, or to exercise timeout handling:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::tools::{self,EchoOptions};
+//!
+//! let options = EchoOptions { delay: Duration::from_millis(50), drop_rate: 0.01, ..Default::default() };
+//! let worker = tools::echo_with_options(serial::open("/dev/ttyUSB1").unwrap(), options);
+//!
+//! // ... test the other end of the link ...
+//!
+//! println!("echoed {} bytes, dropped {}", worker.transferred(), worker.dropped());
+//! worker.close().unwrap();
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use std::thread::{self,JoinHandle};
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
+
+use ::SerialPort;
+use ::shutdown::{self,Drain};
+
+/// Options for an echo server.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct EchoOptions {
+    /// Time between receiving data and sending it back.
+    pub delay: Duration,
+
+    /// Probability from 0.0 to 1.0 that each received byte is dropped instead of sent back.
+    pub drop_rate: f64,
+
+    /// Seed for choosing which bytes are dropped. Zero seeds from the current time.
+    pub seed: u64,
+
+    /// Maximum time to wait for received data before checking whether to stop.
+    pub poll_interval: Duration
+}
+
+impl Default for EchoOptions {
+    fn default() -> Self {
+        EchoOptions {
+            delay: Duration::from_secs(0),
+            drop_rate: 0.0,
+            seed: 0,
+            poll_interval: Duration::from_millis(10)
+        }
+    }
+}
+
+/// Options for a character generator.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct ChargenOptions {
+    /// Number of characters in each line, not counting the CR LF that ends it.
+    pub line_length: usize,
+
+    /// Time to wait after sending each line.
+    pub line_delay: Duration
+}
+
+impl Default for ChargenOptions {
+    fn default() -> Self {
+        ChargenOptions {
+            line_length: 72,
+            line_delay: Duration::from_secs(0)
+        }
+    }
+}
+
+/// A handle to an echo server or character generator thread.
+pub struct Worker<P> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<::Result<P>>>
+}
+
+struct Shared {
+    stop: AtomicBool,
+    transferred: AtomicUsize,
+    dropped: AtomicUsize,
+    drain: Drain
+}
+
+impl<P> Worker<P> {
+    /// Returns the number of bytes that have been sent.
+    pub fn transferred(&self) -> usize {
+        self.shared.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of received bytes that were dropped instead of echoed.
+    pub fn dropped(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stops the thread and returns the port.
+    ///
+    /// Data that's still waiting to be echoed is discarded.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the error that stopped the thread, if it stopped because of an I/O
+    /// error.
+    pub fn close(mut self) -> ::Result<P> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        shutdown::join_until(self.thread.take().unwrap(), None, "I/O thread").and_then(|result| result)
+    }
+
+    /// Sends back the data that's waiting to be echoed, then stops the thread and returns the
+    /// port.
+    ///
+    /// The waiting data is sent without the remainder of its delay, and sending stops when
+    /// `timeout` has passed. Data that wasn't sent by then is counted in `Shutdown::unsent`. A
+    /// character generator stops after the line it's sending.
+    ///
+    /// ## Errors
+    ///
+    /// `Shutdown::result` is an error of kind `Io(TimedOut)` if the thread didn't stop in time, or
+    /// the error that stopped the thread, if it stopped because of an I/O error.
+    pub fn shutdown(mut self, timeout: Duration) -> ::Shutdown<P> {
+        let deadline = Instant::now() + timeout;
+        self.shared.drain.request(deadline);
+
+        let result = shutdown::join_until(self.thread.take().unwrap(), Some(deadline), "I/O thread").and_then(|result| result);
+        ::Shutdown { result, unsent: self.shared.drain.unsent() }
+    }
+}
+
+impl<P> Drop for Worker<P> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Moves a port to a thread that sends back everything it receives.
+pub fn echo<P>(port: P) -> Worker<P>
+    where P: SerialPort + Send + 'static
+{
+    echo_with_options(port, Default::default())
+}
+
+/// Moves a port to a thread that sends back what it receives after a delay, dropping some bytes.
+pub fn echo_with_options<P>(port: P, options: EchoOptions) -> Worker<P>
+    where P: SerialPort + Send + 'static
+{
+    spawn(move |shared| run_echo(port, shared, &options))
+}
+
+/// Moves a port to a thread that transmits the character generator pattern of RFC 864.
+pub fn chargen<P>(port: P) -> Worker<P>
+    where P: SerialPort + Send + 'static
+{
+    chargen_with_options(port, Default::default())
+}
+
+/// Moves a port to a thread that transmits the character generator pattern with the given line
+/// length and delay.
+///
+/// Each line holds `line_length` consecutive printable ASCII characters, starting one character
+/// later than the previous line, and ends with CR LF.
+pub fn chargen_with_options<P>(port: P, options: ChargenOptions) -> Worker<P>
+    where P: SerialPort + Send + 'static
+{
+    spawn(move |shared| run_chargen(port, shared, &options))
+}
+
+fn spawn<P, F>(run: F) -> Worker<P>
+    where P: Send + 'static,
+          F: FnOnce(&Shared) -> ::Result<P> + Send + 'static
+{
+    let shared = Arc::new(Shared {
+        stop: AtomicBool::new(false),
+        transferred: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+        drain: Drain::new()
+    });
+
+    let thread = {
+        let shared = shared.clone();
+        thread::spawn(move || run(&shared))
+    };
+
+    Worker { shared, thread: Some(thread) }
+}
+
+fn run_echo<P: SerialPort>(mut port: P, shared: &Shared, options: &EchoOptions) -> ::Result<P> {
+    let mut random = Random::new(options.seed);
+    let mut queue: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+    let mut buf = [0; 1024];
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        let draining = shared.drain.deadline().is_some();
+
+        while queue.front().is_some_and(|&(due, _)| draining || due <= now) && !shared.drain.expired() {
+            let (_, data) = queue.pop_front().unwrap();
+            io::Write::write_all(&mut port, &data)?;
+            shared.transferred.fetch_add(data.len(), Ordering::Relaxed);
+            shared.drain.written(data.len());
+        }
+
+        if draining {
+            break;
+        }
+
+        let timeout = match queue.front() {
+            Some(&(due, _)) => due.saturating_duration_since(now).min(options.poll_interval),
+            None => options.poll_interval
+        };
+
+        match port.read_with_timeout(&mut buf, timeout) {
+            Ok(n) => {
+                let received = Instant::now();
+                let data: Vec<u8> = buf[..n].iter().cloned().filter(|_| random.next_f64() >= options.drop_rate).collect();
+
+                shared.dropped.fetch_add(n - data.len(), Ordering::Relaxed);
+
+                if !data.is_empty() {
+                    shared.drain.queued(data.len());
+                    queue.push_back((received + options.delay, data));
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(::Error::from(err))
+        }
+    }
+
+    Ok(port)
+}
+
+fn run_chargen<P: SerialPort>(mut port: P, shared: &Shared, options: &ChargenOptions) -> ::Result<P> {
+    const FIRST: u8 = b' ';
+    const COUNT: usize = 95;
+
+    let mut start = 0;
+    let mut line = Vec::with_capacity(options.line_length + 2);
+
+    while !shared.stop.load(Ordering::Relaxed) && shared.drain.deadline().is_none() {
+        line.clear();
+        line.extend((0..options.line_length).map(|i| FIRST + ((start + i) % COUNT) as u8));
+        line.extend_from_slice(b"\r\n");
+
+        let mut written = 0;
+
+        while written < line.len() && !shared.stop.load(Ordering::Relaxed) {
+            match io::Write::write(&mut port, &line[written..]) {
+                Ok(n) => written += n,
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(::Error::from(err))
+            }
+        }
+
+        shared.transferred.fetch_add(written, Ordering::Relaxed);
+        start = (start + 1) % COUNT;
+
+        if options.line_delay > Duration::from_secs(0) {
+            thread::sleep(options.line_delay);
+        }
+    }
+
+    Ok(port)
+}
+
+/// A xorshift64* generator for choosing which bytes to drop.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        let seed = if seed != 0 {
+            seed
+        }
+        else {
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64)
+        };
+
+        Random(seed | 1)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{chargen,echo,echo_with_options,EchoOptions};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    fn echo_sends_back_received_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello ");
+        device.push_input(b"world");
+
+        let worker = echo(device);
+
+        while worker.transferred() < 11 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(worker.close().unwrap().output(), b"hello world");
+    }
+
+    #[test]
+    fn echo_drops_bytes_at_drop_rate() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(&[0x55; 1000]);
+
+        let worker = echo_with_options(device, EchoOptions { drop_rate: 1.0, seed: 1, ..Default::default() });
+
+        while worker.dropped() < 1000 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(worker.transferred(), 0);
+        assert!(worker.close().unwrap().output().is_empty());
+    }
+
+    #[test]
+    fn chargen_rotates_printable_characters() {
+        let worker = chargen(ScriptedDevice::new());
+
+        while worker.transferred() < 74 * 96 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let output = worker.close().unwrap().output();
+        let lines: Vec<&[u8]> = output.split(|&b| b == b'\n').collect();
+
+        assert_eq!(lines[0], &b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefg\r"[..]);
+        assert_eq!(lines[1][..3], b"!\"#"[..]);
+        assert_eq!(lines[95], lines[0]);
+    }
+
+    #[test]
+    fn echo_shutdown_sends_delayed_data() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"hello");
+
+        let worker = echo_with_options(device, EchoOptions { delay: Duration::from_secs(60), ..Default::default() });
+
+        while worker.shared.drain.unsent() < 5 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let shutdown = worker.shutdown(Duration::from_secs(5));
+        assert_eq!(shutdown.unsent, 0);
+        assert_eq!(shutdown.result.ok().unwrap().output(), b"hello");
+    }
+}
@@ -0,0 +1,460 @@
+//! Request/response transactions with retries.
+//!
+//! Most instrument control follows the same loop: send a request, wait for the matching response,
+//! and retry if none arrives in time. `Engine` implements this loop on top of a codec, and
+//! serializes transactions from multiple threads so that responses are never delivered to the
+//! wrong caller:
+//!
+//! ```no_run
+//! use serial::codec::lines::LineCodec;
+//! use serial::transaction::Engine;
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//! let engine = Engine::new(port, LineCodec::new());
+//!
+//! let response = engine.transact(&"*IDN?".to_string(), |line| !line.is_empty()).unwrap();
+//! println!("{}", response);
+//! ```
+//!
+//! Received frames that don't match the request, such as late responses to an earlier request
+//! that timed out, are discarded.
+//!
+//! When several threads share a link, e.g., a user interface and a background poller, a `Queue`
+//! orders their transactions by `Priority`, so that urgent commands don't wait behind queued
+//! polls.
+
+use std::cmp;
+use std::io;
+use std::sync::{Condvar,Mutex,MutexGuard};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+use ::codec::{Decoder,Encoder,Framed};
+
+/// Timeout and retry policy of a transaction.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct RetryPolicy {
+    /// Maximum time to wait for a matching response after each try.
+    pub timeout: Duration,
+
+    /// Number of times the request is retried after the first try times out.
+    pub retries: u32,
+
+    /// Delay before the first retry.
+    pub backoff: Duration,
+
+    /// Factor by which the delay grows after each retry.
+    pub backoff_factor: u32
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, counting from zero.
+    pub fn backoff_delay(&self, retry: u32) -> Duration {
+        if self.backoff == Duration::ZERO {
+            return Duration::ZERO;
+        }
+
+        self.backoff_factor.checked_pow(retry).and_then(|factor| self.backoff.checked_mul(factor)).unwrap_or(Duration::MAX)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(1),
+            retries: 2,
+            backoff: Duration::from_millis(50),
+            backoff_factor: 2
+        }
+    }
+}
+
+/// Runs request/response transactions on a serial port.
+///
+/// `Engine` can be shared between threads, e.g., in an `Arc`. Each transaction has exclusive
+/// access to the port from sending its first request until it returns.
+pub struct Engine<P, C> {
+    framed: Mutex<Framed<P, C>>,
+    policy: RetryPolicy
+}
+
+impl<P, C> Engine<P, C> where P: SerialPort, C: Decoder + Encoder<Item = <C as Decoder>::Item> {
+    /// Creates a transaction engine with the default retry policy.
+    pub fn new(port: P, codec: C) -> Self {
+        Engine {
+            framed: Mutex::new(Framed::new(port, codec)),
+            policy: RetryPolicy::default()
+        }
+    }
+
+    /// Returns the default retry policy.
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Sets the default retry policy.
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the underlying port and codec.
+    pub fn into_parts(self) -> (P, C) {
+        self.framed.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()).into_parts()
+    }
+
+    /// Sends `request` and returns the first response accepted by `matcher`, using the default
+    /// retry policy.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no matching response was received after all retries.
+    /// * Any error returned by the encoder.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn transact<F>(&self, request: &<C as Decoder>::Item, matcher: F) -> ::Result<<C as Decoder>::Item> where F: FnMut(&<C as Decoder>::Item) -> bool {
+        self.transact_with(request, &self.policy, matcher)
+    }
+
+    /// Sends `request` and returns the first response accepted by `matcher`, using the given
+    /// retry policy.
+    ///
+    /// Corrupt frames reported by the decoder are skipped while waiting for a response.
+    ///
+    /// ## Errors
+    ///
+    /// See `transact()`.
+    pub fn transact_with<F>(&self, request: &<C as Decoder>::Item, policy: &RetryPolicy, mut matcher: F) -> ::Result<<C as Decoder>::Item> where F: FnMut(&<C as Decoder>::Item) -> bool {
+        let mut framed = self.lock();
+        let timeout = framed.get_ref().timeout();
+
+        let result = (|| {
+            for attempt in 0..=policy.retries {
+                if attempt > 0 {
+                    thread::sleep(policy.backoff_delay(attempt - 1));
+                }
+
+                framed.write_frame(request)?;
+
+                if let Some(response) = receive(&mut framed, policy.timeout, &mut matcher)? {
+                    return Ok(response);
+                }
+            }
+
+            Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), format!("no matching response after {} attempts", policy.retries as u64 + 1)).with_operation("transact"))
+        })();
+
+        framed.get_mut().set_timeout(timeout)?;
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Framed<P, C>> {
+        self.framed.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Priority of a queued transaction.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum Priority {
+    /// Background work such as periodic polls.
+    Low,
+
+    /// Ordinary commands.
+    Normal,
+
+    /// Commands that must be sent as soon as possible, such as an emergency stop.
+    Urgent
+}
+
+/// A transaction engine that runs queued transactions in order of priority.
+///
+/// Transactions with a higher priority are started before waiting transactions with a lower
+/// priority. A transaction that's already in progress isn't interrupted. Transactions with the
+/// same priority are started in the order they were submitted.
+///
+/// To prevent starvation, a waiting transaction can be overtaken by at most
+/// `max_overtakes()` transactions with a higher priority, after which it's started next.
+pub struct Queue<P, C> {
+    engine: Engine<P, C>,
+    state: Mutex<QueueState>,
+    ready: Condvar,
+    max_overtakes: usize
+}
+
+struct QueueState {
+    busy: bool,
+    next_ticket: u64,
+    waiting: Vec<Waiter>
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+struct Waiter {
+    ticket: u64,
+    priority: Priority,
+    overtaken: usize
+}
+
+impl<P, C> Queue<P, C> where P: SerialPort, C: Decoder + Encoder<Item = <C as Decoder>::Item> {
+    /// Creates a queue that runs transactions with `engine`.
+    pub fn new(engine: Engine<P, C>) -> Self {
+        Queue {
+            engine,
+            state: Mutex::new(QueueState {
+                busy: false,
+                next_ticket: 0,
+                waiting: Vec::new()
+            }),
+            ready: Condvar::new(),
+            max_overtakes: 8
+        }
+    }
+
+    /// Returns the maximum number of times a waiting transaction can be overtaken.
+    pub fn max_overtakes(&self) -> usize {
+        self.max_overtakes
+    }
+
+    /// Sets the maximum number of times a waiting transaction can be overtaken.
+    ///
+    /// `usize::MAX` orders transactions strictly by priority.
+    pub fn set_max_overtakes(&mut self, max_overtakes: usize) {
+        self.max_overtakes = max_overtakes;
+    }
+
+    /// Returns the engine's default retry policy.
+    pub fn policy(&self) -> RetryPolicy {
+        self.engine.policy()
+    }
+
+    /// Returns the number of transactions waiting to start.
+    pub fn pending(&self) -> usize {
+        self.lock().waiting.len()
+    }
+
+    /// Returns the underlying engine.
+    pub fn into_engine(self) -> Engine<P, C> {
+        self.engine
+    }
+
+    /// Queues a transaction with the engine's default retry policy.
+    ///
+    /// Blocks until the transaction has been started and completed.
+    ///
+    /// ## Errors
+    ///
+    /// See `Engine::transact()`.
+    pub fn transact<F>(&self, priority: Priority, request: &<C as Decoder>::Item, matcher: F) -> ::Result<<C as Decoder>::Item> where F: FnMut(&<C as Decoder>::Item) -> bool {
+        self.transact_with(priority, request, &self.engine.policy, matcher)
+    }
+
+    /// Queues a transaction with the given retry policy.
+    ///
+    /// Blocks until the transaction has been started and completed.
+    ///
+    /// ## Errors
+    ///
+    /// See `Engine::transact()`.
+    pub fn transact_with<F>(&self, priority: Priority, request: &<C as Decoder>::Item, policy: &RetryPolicy, matcher: F) -> ::Result<<C as Decoder>::Item> where F: FnMut(&<C as Decoder>::Item) -> bool {
+        let _turn = self.acquire(priority);
+        self.engine.transact_with(request, policy, matcher)
+    }
+
+    fn acquire(&self, priority: Priority) -> Turn<'_, P, C> {
+        let mut state = self.lock();
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiter { ticket, priority, overtaken: 0 });
+
+        loop {
+            if !state.busy && state.waiting[select(&state.waiting, self.max_overtakes)].ticket == ticket {
+                break;
+            }
+
+            state = self.ready.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+
+        let index = state.waiting.iter().position(|waiter| waiter.ticket == ticket).unwrap();
+        state.waiting.remove(index);
+        state.busy = true;
+
+        for waiter in state.waiting.iter_mut().filter(|waiter| waiter.ticket < ticket) {
+            waiter.overtaken += 1;
+        }
+
+        Turn { queue: self }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Releases the port to the next waiting transaction when dropped.
+struct Turn<'a, P: 'a, C: 'a> {
+    queue: &'a Queue<P, C>
+}
+
+impl<'a, P, C> Drop for Turn<'a, P, C> {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).busy = false;
+        self.queue.ready.notify_all();
+    }
+}
+
+/// Returns the index of the waiter to start next.
+fn select(waiting: &[Waiter], max_overtakes: usize) -> usize {
+    let starved = waiting.iter().enumerate().filter(|&(_, waiter)| waiter.overtaken >= max_overtakes).min_by_key(|&(_, waiter)| waiter.ticket);
+
+    if let Some((index, _)) = starved {
+        return index;
+    }
+
+    waiting.iter().enumerate().min_by_key(|&(_, waiter)| (cmp::Reverse(waiter.priority), waiter.ticket)).map(|(index, _)| index).unwrap()
+}
+
+/// Reads frames until one is accepted by `matcher` or `timeout` elapses.
+fn receive<P, C, F>(framed: &mut Framed<P, C>, timeout: Duration, matcher: &mut F) -> ::Result<Option<C::Item>> where P: SerialPort, C: Decoder, F: FnMut(&C::Item) -> bool {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining == Duration::ZERO {
+            return Ok(None);
+        }
+
+        framed.get_mut().set_timeout(remaining)?;
+
+        match framed.read_frame() {
+            Ok(response) => {
+                if matcher(&response) {
+                    return Ok(Some(response));
+                }
+            },
+            Err(ref err) if err.kind() == ::ErrorKind::Io(io::ErrorKind::TimedOut) => return Ok(None),
+            Err(ref err) if err.kind() == ::ErrorKind::Io(io::ErrorKind::InvalidData) => continue,
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{select,Engine,Priority,Queue,RetryPolicy,Waiter};
+    use ::codec::lines::LineCodec;
+    use ::testing::ScriptedDevice;
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            timeout: Duration::from_millis(20),
+            retries,
+            backoff: Duration::from_millis(1),
+            backoff_factor: 2
+        }
+    }
+
+    #[test]
+    fn backoff_delay_grows_geometrically() {
+        let policy = policy(3);
+
+        assert_eq!(policy.backoff_delay(0), Duration::from_millis(1));
+        assert_eq!(policy.backoff_delay(3), Duration::from_millis(8));
+        assert_eq!(policy.backoff_delay(40), Duration::MAX);
+    }
+
+    #[test]
+    fn transact_skips_unmatched_responses() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"LATE\r\nOK 42\r\n");
+
+        let engine = Engine::new(device, LineCodec::new());
+        let response = engine.transact(&"READ".to_string(), |line| line.starts_with("OK")).unwrap();
+
+        assert_eq!(response, "OK 42");
+
+        let (device, _) = engine.into_parts();
+        assert_eq!(device.output(), b"READ\r\n".to_vec());
+    }
+
+    #[test]
+    fn transact_retries_until_timeout() {
+        let engine = Engine::new(ScriptedDevice::new(), LineCodec::new());
+        let err = engine.transact_with(&"READ".to_string(), &policy(2), |_| true).unwrap_err();
+
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::TimedOut));
+
+        let (device, _) = engine.into_parts();
+        assert_eq!(device.output(), b"READ\r\nREAD\r\nREAD\r\n".to_vec());
+    }
+
+    #[test]
+    fn transact_restores_port_timeout() {
+        let mut device = ScriptedDevice::new();
+        device.timeout = Duration::from_secs(5);
+        device.push_input(b"OK\n");
+
+        let mut engine = Engine::new(device, LineCodec::new());
+        engine.set_policy(policy(0));
+        engine.transact(&"PING".to_string(), |_| true).unwrap();
+
+        assert_eq!(engine.into_parts().0.timeout, Duration::from_secs(5));
+    }
+
+    fn waiter(ticket: u64, priority: Priority, overtaken: usize) -> Waiter {
+        Waiter { ticket, priority, overtaken }
+    }
+
+    #[test]
+    fn select_prefers_priority_then_submission_order() {
+        let waiting = [waiter(0, Priority::Low, 0), waiter(1, Priority::Urgent, 0), waiter(2, Priority::Urgent, 0)];
+        assert_eq!(select(&waiting, 8), 1);
+
+        let waiting = [waiter(3, Priority::Normal, 0), waiter(4, Priority::Normal, 0)];
+        assert_eq!(select(&waiting, 8), 0);
+    }
+
+    #[test]
+    fn select_starts_starved_transaction() {
+        let waiting = [waiter(0, Priority::Low, 2), waiter(1, Priority::Low, 2), waiter(5, Priority::Urgent, 0)];
+
+        assert_eq!(select(&waiting, 2), 0);
+        assert_eq!(select(&waiting, 3), 2);
+    }
+
+    #[test]
+    fn queue_starts_urgent_transaction_first() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"OK\nOK\n");
+
+        let queue = Arc::new(Queue::new(Engine::new(device, LineCodec::new())));
+        queue.lock().busy = true;
+
+        let threads: Vec<_> = [(Priority::Low, "POLL"), (Priority::Urgent, "STOP")].iter().enumerate().map(|(i, &(priority, command))| {
+            let shared = queue.clone();
+            let thread = thread::spawn(move || shared.transact(priority, &command.to_string(), |_| true).unwrap());
+
+            while queue.pending() <= i {
+                thread::yield_now();
+            }
+
+            thread
+        }).collect();
+
+        queue.lock().busy = false;
+        queue.ready.notify_all();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let queue = Arc::try_unwrap(queue).ok().unwrap();
+        assert_eq!(queue.into_engine().into_parts().0.output(), b"STOP\r\nPOLL\r\n".to_vec());
+    }
+}
@@ -0,0 +1,331 @@
+//! Capturing data that follows a sync pattern.
+//!
+//! Some devices are quiet for hours and then send a burst worth looking at, or chatter constantly
+//! while only a rare message matters. Logging everything produces far more data than is useful. A
+//! `Trigger` discards received data until a sync pattern is seen, then records a fixed number of
+//! bytes, or everything up to an end pattern, and goes back to waiting:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::trigger::{End,Trigger,TriggerOptions};
+//!
+//! let port = serial::open("/dev/ttyUSB0").unwrap();
+//!
+//! let mut trigger = Trigger::new(port, TriggerOptions {
+//!     sync: b"FAULT".to_vec(),
+//!     end: End::Pattern(b"\r\n".to_vec()),
+//!     timeout: Duration::from_secs(2),
+//!     ..Default::default()
+//! });
+//!
+//! loop {
+//!     match trigger.read_capture() {
+//!         Ok(capture) => println!("{:?}: {}", capture.completion, String::from_utf8_lossy(&capture.data)),
+//!         Err(ref err) if err.kind() == serial::ErrorKind::Io(std::io::ErrorKind::TimedOut) => continue,
+//!         Err(err) => panic!("{}", err)
+//!     }
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration,Instant};
+
+use ::SerialPort;
+
+/// The condition that completes a capture, besides its timeout.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub enum End {
+    /// The capture is complete once this many bytes have been recorded after the sync pattern.
+    ///
+    /// The length must be at least one.
+    Length(usize),
+
+    /// The capture is complete once this pattern has been recorded.
+    ///
+    /// The end pattern is included in the capture.
+    Pattern(Vec<u8>)
+}
+
+/// Options for a trigger.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct TriggerOptions {
+    /// The pattern that starts a capture.
+    pub sync: Vec<u8>,
+
+    /// Whether the sync pattern is included at the start of each capture.
+    pub include_sync: bool,
+
+    /// The condition that completes a capture.
+    pub end: End,
+
+    /// Maximum time from the sync pattern to the end of a capture.
+    ///
+    /// A capture that hasn't completed by then is returned with the data recorded so far.
+    pub timeout: Duration,
+
+    /// Maximum number of bytes recorded after the sync pattern while waiting for an end pattern.
+    pub max_length: usize
+}
+
+impl Default for TriggerOptions {
+    fn default() -> Self {
+        TriggerOptions {
+            sync: Vec::new(),
+            include_sync: true,
+            end: End::Length(256),
+            timeout: Duration::from_secs(1),
+            max_length: 64 * 1024
+        }
+    }
+}
+
+/// How a capture was completed.
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Completion {
+    /// The requested number of bytes was recorded.
+    Length,
+
+    /// The end pattern was recorded.
+    Pattern,
+
+    /// The maximum length was reached without seeing the end pattern.
+    Truncated,
+
+    /// The timeout elapsed first.
+    Timeout
+}
+
+/// Data recorded after a sync pattern.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Capture {
+    /// The recorded data.
+    pub data: Vec<u8>,
+
+    /// How the capture was completed.
+    pub completion: Completion,
+
+    /// When the sync pattern was received.
+    pub started: Instant
+}
+
+/// A port whose data is only recorded after a sync pattern.
+pub struct Trigger<P> {
+    port: P,
+    options: TriggerOptions,
+    window: Vec<u8>,
+    recording: Option<(Vec<u8>, Instant)>,
+    completed: VecDeque<Capture>,
+    discarded: u64
+}
+
+impl<P: SerialPort> Trigger<P> {
+    /// Creates a trigger that waits for `options.sync` on `port`.
+    ///
+    /// An empty sync pattern starts a capture with the first byte received.
+    ///
+    /// ## Panics
+    ///
+    /// This function panics if `options.end` is `End::Length(0)`.
+    pub fn new(port: P, options: TriggerOptions) -> Self {
+        assert!(options.end != End::Length(0), "capture length must be positive");
+
+        Trigger {
+            port,
+            options,
+            window: Vec::new(),
+            recording: None,
+            completed: VecDeque::new(),
+            discarded: 0
+        }
+    }
+
+    /// Returns the number of bytes that were discarded while waiting for the sync pattern.
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Returns `true` if a capture has started but not completed.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn get_ref(&self) -> &P {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the underlying port.
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Returns the underlying port.
+    ///
+    /// A capture in progress is discarded.
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Waits for the sync pattern and returns the capture that follows it.
+    ///
+    /// Data received after a capture completes is kept for the next call.
+    ///
+    /// ## Errors
+    ///
+    /// * `Io(TimedOut)` if no capture started within the port's timeout. A capture in progress
+    ///   is continued by the next call.
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn read_capture(&mut self) -> ::Result<Capture> {
+        let deadline = Instant::now() + self.port.timeout();
+        let mut buf = [0; 256];
+
+        loop {
+            if let Some(capture) = self.completed.pop_front() {
+                return Ok(capture);
+            }
+
+            let now = Instant::now();
+
+            let timeout = match self.recording {
+                Some((_, started)) => (started + self.options.timeout).saturating_duration_since(now),
+                None => deadline.saturating_duration_since(now)
+            };
+
+            if timeout == Duration::from_secs(0) {
+                match self.recording.take() {
+                    Some((data, started)) => return Ok(Capture { data, completion: Completion::Timeout, started }),
+                    None => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::TimedOut), "sync pattern not received").with_operation("capture"))
+                }
+            }
+
+            match self.port.read_with_timeout(&mut buf, timeout) {
+                Ok(0) => return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::UnexpectedEof), "end of file").with_operation("capture")),
+                Ok(n) => self.feed(&buf[..n], Instant::now()),
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => (),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(::Error::from(err).with_operation("capture"))
+            }
+        }
+    }
+
+    fn feed(&mut self, data: &[u8], now: Instant) {
+        for &byte in data {
+            if self.recording.is_none() {
+                if !self.options.sync.is_empty() {
+                    self.window.push(byte);
+                    self.discarded += 1;
+
+                    if self.window.len() > self.options.sync.len() {
+                        self.window.remove(0);
+                    }
+
+                    if self.window == self.options.sync {
+                        let recorded = if self.options.include_sync { self.options.sync.clone() } else { Vec::new() };
+
+                        self.window.clear();
+                        self.discarded -= self.options.sync.len() as u64;
+                        self.recording = Some((recorded, now));
+                    }
+
+                    continue;
+                }
+
+                self.recording = Some((Vec::new(), now));
+            }
+
+            let (mut recorded, started) = self.recording.take().unwrap();
+            recorded.push(byte);
+
+            let offset = if self.options.include_sync { self.options.sync.len() } else { 0 };
+            let len = recorded.len() - offset;
+
+            let completion = match self.options.end {
+                End::Length(n) if len >= n => Some(Completion::Length),
+                End::Pattern(ref pattern) if !pattern.is_empty() && recorded[offset..].ends_with(pattern) => Some(Completion::Pattern),
+                End::Pattern(_) if len >= self.options.max_length => Some(Completion::Truncated),
+                _ => None
+            };
+
+            match completion {
+                Some(completion) => self.completed.push_back(Capture { data: recorded, completion, started }),
+                None => self.recording = Some((recorded, started))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Completion,End,Trigger,TriggerOptions};
+    use ::testing::ScriptedDevice;
+
+    #[test]
+    #[should_panic(expected = "capture length must be positive")]
+    fn trigger_rejects_zero_length() {
+        Trigger::new(ScriptedDevice::new(), TriggerOptions { end: End::Length(0), ..Default::default() });
+    }
+
+    #[test]
+    fn trigger_records_fixed_length_after_sync() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"noise SY");
+        device.push_input(b"NCabcdefSYNCxyz");
+
+        let options = TriggerOptions { sync: b"SYNC".to_vec(), include_sync: false, end: End::Length(3), ..Default::default() };
+        let mut trigger = Trigger::new(device, options);
+
+        let capture = trigger.read_capture().unwrap();
+        assert_eq!(capture.data, b"abc");
+        assert_eq!(capture.completion, Completion::Length);
+        assert_eq!(trigger.read_capture().unwrap().data, b"xyz");
+        assert_eq!(trigger.discarded(), 9);
+    }
+
+    #[test]
+    fn trigger_records_until_end_pattern() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"$$$$ hello\r\n$$ world and more");
+
+        let options = TriggerOptions { sync: b"$$".to_vec(), end: End::Pattern(b"\r\n".to_vec()), max_length: 10, ..Default::default() };
+        let mut trigger = Trigger::new(device, options);
+
+        let capture = trigger.read_capture().unwrap();
+        assert_eq!(capture.data, b"$$$$ hello\r\n");
+        assert_eq!(capture.completion, Completion::Pattern);
+
+        let capture = trigger.read_capture().unwrap();
+        assert_eq!(capture.data, b"$$ world and");
+        assert_eq!(capture.completion, Completion::Truncated);
+    }
+
+    #[test]
+    fn trigger_returns_partial_capture_on_timeout() {
+        let mut device = ScriptedDevice::new();
+        device.push_input(b"xxSYNCab");
+
+        let options = TriggerOptions { sync: b"SYNC".to_vec(), timeout: Duration::from_millis(20), ..Default::default() };
+        let mut trigger = Trigger::new(device, options);
+
+        let capture = trigger.read_capture().unwrap();
+        assert_eq!(capture.data, b"SYNCab");
+        assert_eq!(capture.completion, Completion::Timeout);
+        assert!(!trigger.is_recording());
+    }
+
+    #[test]
+    fn trigger_times_out_without_sync() {
+        let mut device = ScriptedDevice::new();
+        device.timeout = Duration::from_millis(10);
+        device.push_input(b"SYN");
+
+        let mut trigger = Trigger::new(device, TriggerOptions { sync: b"SYNC".to_vec(), ..Default::default() });
+
+        assert_eq!(trigger.read_capture().unwrap_err().kind(), ::ErrorKind::Io(::std::io::ErrorKind::TimedOut));
+        assert_eq!(trigger.discarded(), 3);
+    }
+}
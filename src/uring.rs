@@ -0,0 +1,574 @@
+//! Reading and writing many ports through an io_uring instance (Linux only).
+//!
+//! A gateway that multiplexes dozens of high-rate ports spends much of its time in system calls
+//! when each read and write is a separate call. A `Uring` queues reads and writes for any number
+//! of ports in a shared submission queue and reports their results through a single completion
+//! queue, so a batch of operations costs one system call. Data is transferred directly to and
+//! from buffers that are registered with the kernel once, when the ring is created:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use serial::uring::Uring;
+//!
+//! let ports = vec![serial::open("/dev/ttyUSB0").unwrap(), serial::open("/dev/ttyUSB1").unwrap()];
+//! let mut ring = Uring::new(64, vec![vec![0; 4096]; ports.len()]).unwrap();
+//!
+//! // buffer i is used for port i
+//! for (i, port) in ports.iter().enumerate() {
+//!     ring.read(port, i).unwrap();
+//! }
+//!
+//! loop {
+//!     for completion in ring.wait(Duration::from_secs(1)).unwrap() {
+//!         let len = completion.result.unwrap();
+//!         println!("port {}: {:?}", completion.buffer, &ring.buffer(completion.buffer).unwrap()[..len]);
+//!
+//!         ring.read(&ports[completion.buffer], completion.buffer).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! Each read waits until the port is readable, then reads whatever has been received, up to the
+//! size of its buffer. A buffer can't be accessed while an operation on it is in flight. Requires
+//! Linux 5.6 or later.
+
+#![allow(non_camel_case_types)]
+
+extern crate libc;
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd,RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32,Ordering};
+use std::time::{Duration,Instant};
+
+use self::libc::{c_int,c_long,c_uint,c_void};
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+const IORING_REGISTER_BUFFERS: c_uint = 0;
+
+const IORING_OP_READ_FIXED: u8 = 4;
+const IORING_OP_WRITE_FIXED: u8 = 5;
+const IORING_OP_POLL_ADD: u8 = 6;
+
+const IOSQE_IO_LINK: u8 = 1 << 2;
+
+const POLLIN: u32 = 0x0001;
+
+/// Marks the completion of the poll that precedes each read, which isn't reported.
+const POLL_TAG: u64 = 1 << 63;
+
+#[repr(C)]
+#[derive(Default)]
+struct io_sqring_offsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct io_cqring_offsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct io_uring_params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: io_sqring_offsets,
+    cq_off: io_cqring_offsets
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct io_uring_sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64
+}
+
+#[repr(C)]
+struct io_uring_cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32
+}
+
+/// The result of a read or write.
+#[derive(Debug)]
+pub struct Completion {
+    /// The index of the buffer that was read into or written from.
+    ///
+    /// The buffer can be accessed again once its completion has been returned.
+    pub buffer: usize,
+
+    /// The number of bytes read or written.
+    ///
+    /// A read from a port that has been disconnected fails with `NoDevice`.
+    pub result: ::Result<usize>
+}
+
+/// A memory mapping of one of the ring's queues.
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_POPULATE, fd, offset) };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Mapping { ptr, len })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { (self.ptr as *mut u8).add(offset as usize) as *mut T }
+    }
+
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*self.at::<AtomicU32>(offset) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// The operation in flight on a buffer.
+#[derive(Debug,Copy,Clone)]
+enum Operation {
+    Read(RawFd),
+    Write
+}
+
+/// An io_uring instance with a set of registered buffers.
+///
+/// Operations are queued by `read()` and `write()` and submitted to the kernel by the next call to
+/// `wait()`. The ports must stay open until their operations complete.
+pub struct Uring {
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    params: io_uring_params,
+    fd: RawFd,
+    buffers: Vec<Vec<u8>>,
+    in_flight: Vec<Option<Operation>>,
+    queued: u32
+}
+
+// the mappings are only accessed through `&mut self`
+unsafe impl Send for Uring {}
+
+impl Uring {
+    /// Creates a ring and registers `buffers` with the kernel.
+    ///
+    /// `entries` is the size of the submission queue. Each read takes two entries and each write
+    /// takes one. Buffers are identified by their index in `buffers`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidInput` if `entries` is zero or too large, or a buffer is empty.
+    /// * `Io(Unsupported)` if the kernel doesn't support io_uring.
+    /// * `Io` for any other type of I/O error, such as exceeding `RLIMIT_MEMLOCK` while
+    ///   registering the buffers.
+    pub fn new(entries: u32, buffers: Vec<Vec<u8>>) -> ::Result<Self> {
+        if buffers.is_empty() || buffers.iter().any(Vec::is_empty) {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "buffers must not be empty").with_operation("create io_uring"));
+        }
+
+        let mut params = io_uring_params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries as c_long, &mut params as *mut io_uring_params) } as c_int;
+
+        if fd < 0 {
+            let err = match ::posix::error::errno() {
+                libc::ENOSYS => ::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), "io_uring is not supported by the kernel"),
+                errno => ::posix::error::from_raw_os_error(errno)
+            };
+
+            return Err(err.with_operation("create io_uring"));
+        }
+
+        let ring = Self::map(fd, params, buffers).map_err(|err| {
+            unsafe { libc::close(fd) };
+            ::Error::from(err).with_operation("create io_uring")
+        })?;
+
+        let iovecs: Vec<libc::iovec> = ring.buffers.iter().map(|buffer| {
+            libc::iovec { iov_base: buffer.as_ptr() as *mut c_void, iov_len: buffer.len() }
+        }).collect();
+
+        let result = unsafe { libc::syscall(libc::SYS_io_uring_register, fd, IORING_REGISTER_BUFFERS, iovecs.as_ptr(), iovecs.len() as c_uint) };
+
+        if result < 0 {
+            return Err(::posix::error::last_os_error().with_operation("register buffers"));
+        }
+
+        Ok(ring)
+    }
+
+    fn map(fd: RawFd, params: io_uring_params, buffers: Vec<Vec<u8>>) -> io::Result<Self> {
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<io_uring_cqe>();
+        let sqes_len = params.sq_entries as usize * mem::size_of::<io_uring_sqe>();
+
+        let in_flight = vec![None; buffers.len()];
+
+        Ok(Uring {
+            sq: Mapping::new(fd, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(fd, sqes_len, IORING_OFF_SQES)?,
+            params,
+            fd,
+            buffers,
+            in_flight,
+            queued: 0
+        })
+    }
+
+    /// Returns the number of registered buffers.
+    pub fn buffers(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Returns the number of operations that haven't completed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.iter().filter(|op| op.is_some()).count()
+    }
+
+    /// Returns the buffer at `index`, or `None` if an operation on it is in flight.
+    pub fn buffer(&self, index: usize) -> Option<&[u8]> {
+        match self.in_flight.get(index) {
+            Some(None) => Some(&self.buffers[index]),
+            _ => None
+        }
+    }
+
+    /// Returns the buffer at `index` mutably, or `None` if an operation on it is in flight.
+    pub fn buffer_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        match self.in_flight.get(index) {
+            Some(None) => Some(&mut self.buffers[index]),
+            _ => None
+        }
+    }
+
+    /// Queues a read from `port` into the buffer at `index`.
+    ///
+    /// The read completes once the port has received data, with as much data as fits in the
+    /// buffer.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidInput` if there's no buffer at `index` or an operation on it is in flight.
+    /// * `Io(WouldBlock)` if the submission queue is full.
+    pub fn read<T: AsRawFd>(&mut self, port: &T, index: usize) -> ::Result<()> {
+        self.check_idle(index, 2)?;
+
+        let fd = port.as_raw_fd();
+        let len = self.buffers[index].len();
+
+        // a port's reads return immediately when nothing has been received, so wait first
+        self.push(io_uring_sqe {
+            opcode: IORING_OP_POLL_ADD,
+            flags: IOSQE_IO_LINK,
+            fd,
+            op_flags: POLLIN,
+            user_data: POLL_TAG | index as u64,
+            ..Default::default()
+        });
+
+        self.push(self.fixed(IORING_OP_READ_FIXED, fd, index, len));
+        self.in_flight[index] = Some(Operation::Read(fd));
+
+        Ok(())
+    }
+
+    /// Queues a write of the first `len` bytes of the buffer at `index` to `port`.
+    ///
+    /// The write completes once the data has been written, which may be fewer than `len` bytes.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidInput` if there's no buffer at `index`, an operation on it is in flight, or `len`
+    ///   exceeds its size.
+    /// * `Io(WouldBlock)` if the submission queue is full.
+    pub fn write<T: AsRawFd>(&mut self, port: &T, index: usize, len: usize) -> ::Result<()> {
+        self.check_idle(index, 1)?;
+
+        if len > self.buffers[index].len() {
+            return Err(::Error::new(::ErrorKind::InvalidInput, "length exceeds the buffer").with_operation("queue write"));
+        }
+
+        self.push(self.fixed(IORING_OP_WRITE_FIXED, port.as_raw_fd(), index, len));
+        self.in_flight[index] = Some(Operation::Write);
+
+        Ok(())
+    }
+
+    /// Submits the queued operations and waits until at least one has completed or the timeout
+    /// expires.
+    ///
+    /// Returns the completed operations, or an empty vector if the timeout expired.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error of kind `Io` if submitting or waiting fails. Errors of
+    /// individual operations are reported in their completions.
+    pub fn wait(&mut self, timeout: Duration) -> ::Result<Vec<Completion>> {
+        self.submit().map_err(|err| ::Error::from(err).with_operation("submit"))?;
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let completions = self.reap();
+
+            if !completions.is_empty() || self.in_flight() == 0 {
+                return Ok(completions);
+            }
+
+            // the ring is readable while its completion queue isn't empty
+            match ::posix::poll::wait_read_fd(self.fd, deadline.saturating_duration_since(Instant::now())) {
+                Ok(()) => (),
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => return Ok(completions),
+                Err(err) => return Err(::Error::from(err).with_operation("wait for completions"))
+            }
+        }
+    }
+
+    fn check_idle(&mut self, index: usize, entries: u32) -> ::Result<()> {
+        match self.in_flight.get(index) {
+            Some(None) => (),
+            Some(Some(_)) => return Err(::Error::new(::ErrorKind::InvalidInput, "buffer is in use").with_operation("queue operation")),
+            None => return Err(::Error::new(::ErrorKind::InvalidInput, "no such buffer").with_operation("queue operation"))
+        }
+
+        if self.queued + entries > self.params.sq_entries {
+            self.submit().map_err(|err| ::Error::from(err).with_operation("submit"))?;
+        }
+
+        if self.space() < entries {
+            return Err(::Error::new(::ErrorKind::Io(io::ErrorKind::WouldBlock), "submission queue is full").with_operation("queue operation"));
+        }
+
+        Ok(())
+    }
+
+    fn fixed(&self, opcode: u8, fd: RawFd, index: usize, len: usize) -> io_uring_sqe {
+        io_uring_sqe {
+            opcode,
+            fd,
+            // the current position, since ports aren't seekable
+            off: u64::MAX,
+            addr: self.buffers[index].as_ptr() as u64,
+            len: len as u32,
+            user_data: index as u64,
+            buf_index: index as u16,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of free entries in the submission queue.
+    fn space(&self) -> u32 {
+        let head = self.sq.atomic(self.params.sq_off.head).load(Ordering::Acquire);
+        let tail = self.sq.atomic(self.params.sq_off.tail).load(Ordering::Relaxed);
+
+        self.params.sq_entries - tail.wrapping_sub(head)
+    }
+
+    fn push(&mut self, sqe: io_uring_sqe) {
+        let off = &self.params.sq_off;
+
+        let tail = self.sq.atomic(off.tail).load(Ordering::Relaxed);
+        let mask = unsafe { *self.sq.at::<u32>(off.ring_mask) };
+        let slot = tail & mask;
+
+        unsafe {
+            ptr::write(self.sqes.at::<io_uring_sqe>(slot * mem::size_of::<io_uring_sqe>() as u32), sqe);
+            ptr::write(self.sq.at::<u32>(off.array + slot * mem::size_of::<u32>() as u32), slot);
+        }
+
+        self.sq.atomic(off.tail).store(tail.wrapping_add(1), Ordering::Release);
+        self.queued += 1;
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        while self.queued > 0 {
+            let submitted = unsafe { libc::syscall(libc::SYS_io_uring_enter, self.fd, self.queued, 0, 0, ptr::null::<c_void>(), 0) };
+
+            if submitted < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(err);
+            }
+
+            self.queued -= submitted as u32;
+        }
+
+        Ok(())
+    }
+
+    fn reap(&mut self) -> Vec<Completion> {
+        let off = &self.params.cq_off;
+        let mut completions = Vec::new();
+
+        let mask = unsafe { *self.cq.at::<u32>(off.ring_mask) };
+        let mut head = self.cq.atomic(off.head).load(Ordering::Relaxed);
+        let tail = self.cq.atomic(off.tail).load(Ordering::Acquire);
+
+        while head != tail {
+            let cqe = unsafe { ptr::read(self.cq.at::<io_uring_cqe>(off.cqes + (head & mask) * mem::size_of::<io_uring_cqe>() as u32)) };
+            head = head.wrapping_add(1);
+
+            if cqe.user_data & POLL_TAG != 0 {
+                continue;
+            }
+
+            let index = cqe.user_data as usize;
+
+            let (operation, fd) = match self.in_flight[index].take() {
+                Some(Operation::Read(fd)) => ("read", Some(fd)),
+                _ => ("write", None)
+            };
+
+            let result = match (cqe.res, fd) {
+                // a port that has been hung up reads as end of file
+                (0, Some(fd)) if ::posix::poll::is_hung_up(fd) => Err(io::Error::from_raw_os_error(libc::EIO)),
+                (res, _) if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+                (res, _) => Ok(res as usize)
+            };
+
+            completions.push(Completion {
+                buffer: index,
+                result: result.map_err(|err| ::disconnected(::posix::error::from_io_error(err)).with_operation(operation))
+            });
+        }
+
+        self.cq.atomic(off.head).store(head, Ordering::Release);
+
+        completions
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+
+        // The kernel may still write to buffers with reads in flight after the ring is closed, so
+        // they're leaked rather than returned to the allocator.
+        if self.in_flight() > 0 {
+            mem::forget(mem::take(&mut self.buffers));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::Uring;
+
+    #[test]
+    fn uring_reads_from_many_ports() {
+        let (mut a, a_peer) = UnixStream::pair().unwrap();
+        let (mut b, b_peer) = UnixStream::pair().unwrap();
+
+        let mut ring = Uring::new(8, vec![vec![0; 64], vec![0; 64]]).unwrap();
+        ring.read(&a_peer, 0).unwrap();
+        ring.read(&b_peer, 1).unwrap();
+
+        assert!(ring.buffer(0).is_none());
+        assert!(ring.wait(Duration::from_millis(20)).unwrap().is_empty());
+
+        b.write_all(b"bee").unwrap();
+        a.write_all(b"ay").unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            for completion in ring.wait(Duration::from_secs(5)).unwrap() {
+                let len = completion.result.unwrap();
+                received.push((completion.buffer, ring.buffer(completion.buffer).unwrap()[..len].to_vec()));
+            }
+        }
+        received.sort();
+
+        assert_eq!(received, vec![(0, b"ay".to_vec()), (1, b"bee".to_vec())]);
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    fn uring_writes_from_registered_buffer() {
+        let (port, mut peer) = UnixStream::pair().unwrap();
+
+        let mut ring = Uring::new(8, vec![vec![0; 64]]).unwrap();
+        ring.buffer_mut(0).unwrap()[..5].copy_from_slice(b"hello");
+        ring.write(&port, 0, 5).unwrap();
+
+        let completions = ring.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].result.as_ref().unwrap(), &5);
+
+        let mut buf = [0; 5];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn uring_rejects_busy_buffers() {
+        let (_a, a_peer) = UnixStream::pair().unwrap();
+
+        let mut ring = Uring::new(8, vec![vec![0; 64]]).unwrap();
+        ring.read(&a_peer, 0).unwrap();
+
+        assert_eq!(ring.read(&a_peer, 0).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(ring.write(&a_peer, 1, 0).unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+}
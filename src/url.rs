@@ -0,0 +1,575 @@
+//! Opening ports from URL-style strings.
+//!
+//! A URL names a port together with its settings, so that the transport and configuration can be
+//! chosen at deployment time, e.g., from a configuration file or command line:
+//!
+//! ```no_run
+//! let mut port = serial::open_url("serial:///dev/ttyUSB0?baud=115200&parity=even&timeout=500ms").unwrap();
+//! port.write_all(b"hello").unwrap();
+//! ```
+//!
+//! The `serial` scheme opens a native serial port. Its path is the device name: `/dev/ttyUSB0` on
+//! Unix systems, or `COM3` on Windows (`serial://COM3` or `serial:///COM3`). The following query
+//! parameters are understood:
+//!
+//! * `baud`: the baud rate, e.g., `115200`.
+//! * `data_bits`: the character size, from `5` to `8`.
+//! * `parity`: `none`, `odd`, or `even`.
+//! * `stop_bits`: `1` or `2`.
+//! * `flow_control`: `none`, `software`, or `hardware`.
+//! * `timeout`: the read and write timeout, e.g., `500ms` or `2s`. A number without a unit is in
+//!   milliseconds.
+//! * `dtr`, `rts`: the initial level of the control signal, `true` or `false`.
+//!
+//! Settings that aren't given are left as the device was initialized. The `tcp` and `rfc2217`
+//! schemes are reserved for network transports, which aren't implemented yet.
+//!
+//! ## Custom Schemes
+//!
+//! Applications can register a factory for their own schemes with `register()`, so that code
+//! calling `open_url()` can be redirected to custom transports, such as virtual ports in tests:
+//!
+//! ```no_run
+//! use serial::url::{self,PortUrl};
+//!
+//! // "usb://0" opens /dev/ttyUSB0
+//! url::register("usb", |url: &PortUrl| {
+//!     let port = serial::open(&format!("/dev/ttyUSB{}", url.address))?;
+//!     Ok(Box::new(port))
+//! });
+//!
+//! let port = serial::open_url("usb://0?baud=115200").unwrap();
+//! ```
+//!
+//! The settings and timeout in the query are applied to the port returned by the factory.
+//! Parameters that aren't listed above are collected in `PortUrl::params` for the factory to
+//! interpret. A factory registered for `serial` replaces the native ports.
+//!
+//! ## Overrides
+//!
+//! The `SERIAL_PORT_OVERRIDES` environment variable redirects ports to other devices or
+//! transports without changing the application, e.g., to run it against virtual ports in tests or
+//! against a staging device. Its value is a list of `name=target` pairs separated by semicolons:
+//!
+//! ```text
+//! SERIAL_PORT_OVERRIDES="gps=/dev/ttyUSB3;modem=usb://1?baud=9600"
+//! ```
+//!
+//! A URL whose address matches a name is opened with the target instead. A target that's a URL
+//! replaces the scheme and address, and the settings in its query take precedence over the
+//! settings of the original URL. Any other target is a device name for the `serial` scheme, and
+//! the original URL's settings are kept. Targets aren't redirected again.
+//!
+//! Overrides apply to `open_url()` and `PortUrl::open()`, and to profiles opened by name with the
+//! `profile` module. `serial::open()` opens the device it's given.
+
+use std::env;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+
+use ::{BaudRate,CharSize,FlowControl,OpenOptions,Parity,SerialPort,StopBits};
+
+/// The environment variable that redirects ports.
+///
+/// See the [module documentation](index.html#overrides) for its format.
+pub const OVERRIDES_VAR: &str = "SERIAL_PORT_OVERRIDES";
+
+/// A parsed port URL.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct PortUrl {
+    /// The URL's scheme, e.g., `serial`.
+    pub scheme: String,
+
+    /// The device name or network address, with percent-encoding decoded.
+    pub address: String,
+
+    /// Baud rate, if set.
+    pub baud_rate: Option<BaudRate>,
+
+    /// Character size, if set.
+    pub char_size: Option<CharSize>,
+
+    /// Parity checking mode, if set.
+    pub parity: Option<Parity>,
+
+    /// Number of stop bits, if set.
+    pub stop_bits: Option<StopBits>,
+
+    /// Flow control mode, if set.
+    pub flow_control: Option<FlowControl>,
+
+    /// Timeout, if set.
+    pub timeout: Option<Duration>,
+
+    /// Options for opening the port.
+    pub options: OpenOptions,
+
+    /// Query parameters that aren't understood by the `serial` scheme, in order.
+    pub params: Vec<(String, String)>
+}
+
+/// A function that opens a port for a URL.
+type Factory = Arc<dyn Fn(&PortUrl) -> ::Result<Box<dyn SerialPort + Send>> + Send + Sync>;
+
+static REGISTRY: Mutex<Vec<(String, Factory)>> = Mutex::new(Vec::new());
+
+/// Registers a factory that opens ports for URLs with the given scheme.
+///
+/// The factory replaces any factory that was previously registered for the scheme, including the
+/// built-in `serial` scheme. Schemes are case-insensitive.
+pub fn register<F>(scheme: &str, factory: F)
+    where F: Fn(&PortUrl) -> ::Result<Box<dyn SerialPort + Send>> + Send + Sync + 'static
+{
+    let scheme = scheme.to_ascii_lowercase();
+    let mut registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+
+    registry.retain(|(registered, _)| *registered != scheme);
+    registry.push((scheme, Arc::new(factory)));
+}
+
+/// Removes the factory registered for a scheme.
+///
+/// Returns `true` if a factory was registered.
+pub fn unregister(scheme: &str) -> bool {
+    let scheme = scheme.to_ascii_lowercase();
+    let mut registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    let len = registry.len();
+
+    registry.retain(|(registered, _)| *registered != scheme);
+    registry.len() != len
+}
+
+fn factory(scheme: &str) -> Option<Factory> {
+    let registry = REGISTRY.lock().unwrap_or_else(|p| p.into_inner());
+    registry.iter().find(|(registered, _)| registered == scheme).map(|(_, factory)| factory.clone())
+}
+
+impl PortUrl {
+    /// Applies the URL's settings and timeout to an open port.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the settings could not be applied, as by
+    /// `SerialPort::reconfigure()`.
+    pub fn configure(&self, port: &mut dyn SerialPort) -> ::Result<()> {
+        port.reconfigure_dyn(&mut |settings| {
+            if let Some(baud_rate) = self.baud_rate {
+                settings.set_baud_rate(baud_rate)?;
+            }
+
+            if let Some(char_size) = self.char_size {
+                settings.set_char_size(char_size);
+            }
+
+            if let Some(parity) = self.parity {
+                settings.set_parity(parity);
+            }
+
+            if let Some(stop_bits) = self.stop_bits {
+                settings.set_stop_bits(stop_bits);
+            }
+
+            if let Some(flow_control) = self.flow_control {
+                settings.set_flow_control(flow_control);
+            }
+
+            Ok(())
+        })?;
+
+        if let Some(timeout) = self.timeout {
+            port.set_timeout(timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens and configures the port named by the URL.
+    ///
+    /// The port is opened by the factory registered for the URL's scheme, if any. If the URL's
+    /// address is redirected by `SERIAL_PORT_OVERRIDES`, the override's target is opened instead.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the errors of the factory or [`serial::open()`](../fn.open.html) and
+    /// `configure()`, an `InvalidInput` error for an unknown scheme, a query parameter that the
+    /// `serial` scheme doesn't understand, or an invalid override, and an error of kind
+    /// `Io(Unsupported)` for a network scheme.
+    pub fn open(&self) -> ::Result<Box<dyn SerialPort + Send>> {
+        match port_override(&self.address)? {
+            Some(target) => self.redirect(&target)?.open_direct(),
+            None => self.open_direct()
+        }
+    }
+
+    /// Returns the URL for opening this URL's port with an override's target.
+    fn redirect(&self, target: &str) -> ::Result<PortUrl> {
+        if !target.contains("://") {
+            return Ok(PortUrl {
+                scheme: "serial".to_string(),
+                address: target.to_string(),
+                params: Vec::new(),
+                ..self.clone()
+            });
+        }
+
+        let target: PortUrl = target.parse()?;
+
+        Ok(PortUrl {
+            baud_rate: target.baud_rate.or(self.baud_rate),
+            char_size: target.char_size.or(self.char_size),
+            parity: target.parity.or(self.parity),
+            stop_bits: target.stop_bits.or(self.stop_bits),
+            flow_control: target.flow_control.or(self.flow_control),
+            timeout: target.timeout.or(self.timeout),
+            options: if target.options == OpenOptions::default() { self.options.clone() } else { target.options },
+            ..target
+        })
+    }
+
+    /// Opens the port named by the URL without consulting `SERIAL_PORT_OVERRIDES`.
+    pub(crate) fn open_direct(&self) -> ::Result<Box<dyn SerialPort + Send>> {
+        if let Some(factory) = factory(&self.scheme) {
+            let mut port = factory(self)?;
+            self.configure(&mut *port)?;
+            return Ok(port);
+        }
+
+        match &self.scheme[..] {
+            "serial" => {
+                if let Some((key, _)) = self.params.first() {
+                    return Err(invalid(format!("unknown query parameter: {:?}", key)));
+                }
+
+                let mut port = self.options.open(&self.address)?;
+                self.configure(&mut port)?;
+                Ok(Box::new(port))
+            },
+            "tcp" | "rfc2217" => {
+                Err(::Error::new(::ErrorKind::Io(io::ErrorKind::Unsupported), format!("{}:// ports are not supported yet", self.scheme)).with_device(&self.address[..]))
+            },
+            scheme => Err(::Error::new(::ErrorKind::InvalidInput, format!("unknown URL scheme: {:?}", scheme)))
+        }
+    }
+}
+
+impl FromStr for PortUrl {
+    type Err = ::Error;
+
+    /// Parses a port URL, e.g., `serial:///dev/ttyUSB0?baud=115200`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an `InvalidInput` error if the string isn't a URL or a query parameter has an
+    /// invalid value.
+    fn from_str(s: &str) -> ::Result<PortUrl> {
+        let (scheme, rest) = match s.find("://") {
+            Some(i) if i > 0 => (&s[..i], &s[i + 3..]),
+            _ => return Err(invalid(format!("invalid port URL: {:?}", s)))
+        };
+
+        let (address, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None)
+        };
+
+        let mut address = decode(address)?;
+
+        // on Windows, "serial:///COM3" names COM3 rather than a path
+        if cfg!(windows) && scheme == "serial" && address.starts_with('/') {
+            address.remove(0);
+        }
+
+        if address.is_empty() {
+            return Err(invalid(format!("port URL has no address: {:?}", s)));
+        }
+
+        let mut url = PortUrl {
+            scheme: scheme.to_ascii_lowercase(),
+            address,
+            ..Default::default()
+        };
+
+        for pair in query.into_iter().flat_map(|query| query.split('&')).filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], decode(&pair[i + 1..])?),
+                None => return Err(invalid(format!("query parameter has no value: {:?}", pair)))
+            };
+
+            let value = &value[..];
+
+            match key {
+                "baud" => url.baud_rate = Some(value.parse()?),
+                "data_bits" => url.char_size = Some(parse_char_size(value)?),
+                "parity" => url.parity = Some(parse_parity(value)?),
+                "stop_bits" => url.stop_bits = Some(parse_stop_bits(value)?),
+                "flow_control" => url.flow_control = Some(parse_flow_control(value)?),
+                "timeout" => url.timeout = Some(parse_duration(value)?),
+                "dtr" => { url.options.dtr(parse_bool(key, value)?); },
+                "rts" => { url.options.rts(parse_bool(key, value)?); },
+                _ => url.params.push((decode(key)?, value.to_string()))
+            }
+        }
+
+        Ok(url)
+    }
+}
+
+/// Returns the target that `SERIAL_PORT_OVERRIDES` redirects a port name to, if any.
+///
+/// ## Errors
+///
+/// This function returns an `InvalidInput` error if the variable isn't a valid list of overrides.
+pub fn port_override(name: &str) -> ::Result<Option<String>> {
+    match env::var(OVERRIDES_VAR) {
+        Ok(overrides) => find_override(&overrides, name),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(invalid(format!("{} is not valid Unicode", OVERRIDES_VAR)))
+    }
+}
+
+fn find_override(overrides: &str, name: &str) -> ::Result<Option<String>> {
+    let mut target = None;
+
+    for pair in overrides.split(';').map(str::trim).filter(|pair| !pair.is_empty()) {
+        match pair.find('=') {
+            Some(i) if i > 0 && i + 1 < pair.len() => {
+                if pair[..i].trim() == name && target.is_none() {
+                    target = Some(pair[i + 1..].trim().to_string());
+                }
+            },
+            _ => return Err(invalid(format!("invalid override in {}: {:?}", OVERRIDES_VAR, pair)))
+        }
+    }
+
+    Ok(target)
+}
+
+/// Opens and configures a port named by a URL.
+///
+/// See the [module documentation](url/index.html) for the supported schemes and parameters, and
+/// for redirecting ports with `SERIAL_PORT_OVERRIDES`.
+///
+/// ## Errors
+///
+/// This function returns an `InvalidInput` error if the URL could not be parsed, or any of the
+/// errors of `PortUrl::open()`.
+pub fn open_url(url: &str) -> ::Result<Box<dyn SerialPort + Send>> {
+    url.parse::<PortUrl>()?.open()
+}
+
+fn invalid(description: String) -> ::Error {
+    ::Error::new(::ErrorKind::InvalidInput, description)
+}
+
+/// Decodes percent-encoded bytes.
+fn decode(s: &str) -> ::Result<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = s.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            match byte {
+                Some(byte) => decoded.push(byte),
+                None => return Err(invalid(format!("invalid percent-encoding: {:?}", s)))
+            }
+
+            i += 3;
+        }
+        else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid(format!("invalid percent-encoding: {:?}", s)))
+}
+
+pub(crate) fn parse_char_size(value: &str) -> ::Result<CharSize> {
+    match value {
+        "5" => Ok(CharSize::Bits5),
+        "6" => Ok(CharSize::Bits6),
+        "7" => Ok(CharSize::Bits7),
+        "8" => Ok(CharSize::Bits8),
+        _ => Err(invalid(format!("invalid data bits: {:?}", value)))
+    }
+}
+
+pub(crate) fn parse_parity(value: &str) -> ::Result<Parity> {
+    match value {
+        "none" => Ok(Parity::ParityNone),
+        "odd" => Ok(Parity::ParityOdd),
+        "even" => Ok(Parity::ParityEven),
+        _ => Err(invalid(format!("invalid parity: {:?}", value)))
+    }
+}
+
+pub(crate) fn parse_stop_bits(value: &str) -> ::Result<StopBits> {
+    match value {
+        "1" => Ok(StopBits::Stop1),
+        "2" => Ok(StopBits::Stop2),
+        _ => Err(invalid(format!("invalid stop bits: {:?}", value)))
+    }
+}
+
+pub(crate) fn parse_flow_control(value: &str) -> ::Result<FlowControl> {
+    match value {
+        "none" => Ok(FlowControl::FlowNone),
+        "software" => Ok(FlowControl::FlowSoftware),
+        "hardware" => Ok(FlowControl::FlowHardware),
+        _ => Err(invalid(format!("invalid flow control: {:?}", value)))
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> ::Result<bool> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(invalid(format!("invalid {}: {:?}", key, value)))
+    }
+}
+
+/// Parses a duration such as `500ms`, `2s`, or `1.5s`. A number without a unit is in milliseconds.
+pub(crate) fn parse_duration(value: &str) -> ::Result<Duration> {
+    let err = || invalid(format!("invalid timeout: {:?}", value));
+
+    if let Some(us) = value.strip_suffix("us") {
+        us.parse().map(Duration::from_micros).map_err(|_| err())
+    }
+    else if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse().map(Duration::from_millis).map_err(|_| err())
+    }
+    else if let Some(s) = value.strip_suffix('s') {
+        s.parse().ok().and_then(|s| Duration::try_from_secs_f64(s).ok()).ok_or_else(err)
+    }
+    else {
+        value.parse().map(Duration::from_millis).map_err(|_| err())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::{find_override,parse_duration,register,unregister,PortUrl};
+
+    #[test]
+    fn parse_serial_url() {
+        let url: PortUrl = "serial:///dev/ttyUSB0?baud=115200&parity=even&timeout=500ms".parse().unwrap();
+
+        assert_eq!(url.scheme, "serial");
+        assert_eq!(url.address, "/dev/ttyUSB0");
+        assert_eq!(url.baud_rate, Some(::Baud115200));
+        assert_eq!(url.parity, Some(::ParityEven));
+        assert_eq!(url.char_size, None);
+        assert_eq!(url.timeout, Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn parse_framing_and_signals() {
+        let url: PortUrl = "serial:///dev/serial%2Fby-id?data_bits=7&stop_bits=2&flow_control=hardware&dtr=false".parse().unwrap();
+
+        assert_eq!(url.address, "/dev/serial/by-id");
+        assert_eq!(url.char_size, Some(::Bits7));
+        assert_eq!(url.stop_bits, Some(::Stop2));
+        assert_eq!(url.flow_control, Some(::FlowHardware));
+        assert_eq!(url.options.dtr_level(), Some(false));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_urls() {
+        for url in &["/dev/ttyUSB0", "serial://", "serial:///dev/ttyS0?parity=mark", "serial:///dev/ttyS0?baud"] {
+            assert_eq!(url.parse::<PortUrl>().unwrap_err().kind(), ::ErrorKind::InvalidInput, "{}", url);
+        }
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("250").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("100us").unwrap(), Duration::from_micros(100));
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn parse_collects_unknown_params() {
+        let url: PortUrl = "mux://gateway/3?baud=9600&channel=a%20b".parse().unwrap();
+
+        assert_eq!(url.address, "gateway/3");
+        assert_eq!(url.params, vec![("channel".to_string(), "a b".to_string())]);
+
+        let err = ::open_url("serial:///dev/ttyS0?speed=9600").err().unwrap();
+        assert_eq!(err.kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn registered_factory_opens_custom_scheme() {
+        register("Scripted", |url: &PortUrl| {
+            assert_eq!(url.address, "device");
+            Ok(Box::new(::testing::ScriptedDevice::new()))
+        });
+
+        let mut port = ::open_url("scripted://device?baud=57600").unwrap();
+        let mut baud_rate = None;
+
+        port.reconfigure_dyn(&mut |settings| {
+            baud_rate = settings.baud_rate();
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(baud_rate, Some(::Baud57600));
+        assert!(unregister("scripted"));
+        assert!(!unregister("scripted"));
+        assert_eq!(::open_url("scripted://device").err().unwrap().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn network_schemes_are_unsupported() {
+        let err = ::open_url("tcp://localhost:4000").err().unwrap();
+        assert_eq!(err.kind(), ::ErrorKind::Io(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn configure_applies_settings_and_timeout() {
+        let mut device = ::testing::ScriptedDevice::new();
+        let url: PortUrl = "serial:///dev/null?baud=19200&stop_bits=2&timeout=2s".parse().unwrap();
+
+        url.configure(&mut device).unwrap();
+
+        assert_eq!(device.settings.baud_rate, ::Baud19200);
+        assert_eq!(device.settings.stop_bits, ::Stop2);
+        assert_eq!(device.timeout, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn find_override_matches_names() {
+        let overrides = " gps = /dev/ttyUSB3 ; modem=mock://a?baud=9600;;";
+
+        assert_eq!(find_override(overrides, "gps").unwrap(), Some("/dev/ttyUSB3".to_string()));
+        assert_eq!(find_override(overrides, "modem").unwrap(), Some("mock://a?baud=9600".to_string()));
+        assert_eq!(find_override(overrides, "meter").unwrap(), None);
+        assert_eq!(find_override("gps", "gps").unwrap_err().kind(), ::ErrorKind::InvalidInput);
+        assert_eq!(find_override("=/dev/ttyS0", "gps").unwrap_err().kind(), ::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn redirect_merges_settings() {
+        let url: PortUrl = "serial://gps?baud=9600&parity=even".parse().unwrap();
+
+        let redirected = url.redirect("/dev/ttyUSB3").unwrap();
+        assert_eq!((&redirected.scheme[..], &redirected.address[..]), ("serial", "/dev/ttyUSB3"));
+        assert_eq!(redirected.baud_rate, Some(::Baud9600));
+
+        let redirected = url.redirect("mock://link?baud=115200&channel=2").unwrap();
+        assert_eq!((&redirected.scheme[..], &redirected.address[..]), ("mock", "link"));
+        assert_eq!(redirected.baud_rate, Some(::Baud115200));
+        assert_eq!(redirected.parity, Some(::ParityEven));
+        assert_eq!(redirected.params, vec![("channel".to_string(), "2".to_string())]);
+    }
+}
@@ -1,90 +1,307 @@
 extern crate libc;
 
+use std::cmp;
 use std::ffi::OsStr;
 use std::io;
 use std::mem;
 use std::ptr;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration,Instant};
 
 use std::os::windows::prelude::*;
 
-use self::libc::c_void;
+use self::libc::{c_char,c_void};
 
 use super::ffi::*;
 use ::{SerialDevice,SerialPortSettings};
 
 
+/// The largest chunk of a buffer passed to each `WriteFile()` call.
+const MAX_WRITE_CHUNK: usize = 64 * 1024;
+
+/// The smallest chunk size that's tried when a driver rejects a large write.
+const MIN_WRITE_CHUNK: usize = 256;
+
+const ERROR_INVALID_PARAMETER: i32 = 87;
+const ERROR_NOT_ENOUGH_MEMORY: i32 = 8;
+const ERROR_NO_SYSTEM_RESOURCES: i32 = 1450;
+
 /// A serial port implementation for Windows COM ports.
 ///
 /// The port will be closed when the value is dropped.
 pub struct COMPort {
     handle: HANDLE,
-    timeout: Duration
+    timeout: Duration,
+    device: String,
+    original: Option<::Snapshot<COMSettings>>,
+    low_latency: bool
 }
 
+// The handle may be used from any thread. Methods that take `&self` only call functions that are
+// safe to call concurrently on the same handle, such as `ReadFile()` and `GetCommState()`. Each
+// read and write waits on its own `OVERLAPPED` structure, so concurrent operations don't share
+// any state.
 unsafe impl Send for COMPort {}
+unsafe impl Sync for COMPort {}
 
 impl COMPort {
     /// Opens a COM port as a serial device.
     ///
-    /// `port` should be the name of a COM port, e.g., `COM1`.
+    /// `port` should be the name of a COM port, e.g., `COM1` or `COM12`, or a device path in the
+    /// Win32 device namespace, e.g., `\\.\CNCA0` for a com0com port. Names are opened in the
+    /// device namespace, so ports above `COM9` don't need the `\\.\` prefix.
     ///
     /// ```no_run
     /// serial::windows::COMPort::open("COM1").unwrap();
+    /// serial::windows::COMPort::open("COM12").unwrap();
+    /// serial::windows::COMPort::open(r"\\.\CNCA0").unwrap();
     /// ```
     ///
     /// ## Errors
     ///
-    /// * `NoDevice` if the device could not be opened. This could indicate that the device is
-    ///   already in use.
+    /// * `NoDevice` if the device doesn't exist or could not be opened.
+    /// * `Busy` if the device is already in use by another process.
+    /// * `PermissionDenied` if the process lacks permission to open the device.
     /// * `InvalidInput` if `port` is not a valid device name.
     /// * `Io` for any other I/O error while opening or initializing the device.
     pub fn open<T: AsRef<OsStr> + ?Sized>(port: &T) -> ::Result<Self> {
-        let mut name = Vec::<u16>::new();
+        COMPort::open_with_options(port, &::OpenOptions::new())
+    }
 
-        name.extend(OsStr::new("\\\\.\\").encode_wide());
-        name.extend(port.as_ref().encode_wide());
-        name.push(0);
+    /// Opens a COM port as a serial device with the given options.
+    ///
+    /// The initial states of the DTR and RTS control signals are applied through the
+    /// `fDtrControl` and `fRtsControl` fields of the port's `DCB`.
+    ///
+    /// ```no_run
+    /// let mut options = serial::OpenOptions::new();
+    /// options.dtr(false).rts(false);
+    ///
+    /// serial::windows::COMPort::open_with_options("COM3", &options).unwrap();
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// This function returns the same errors as `open()`. Failing to set the initial state of a
+    /// control signal is reported as an error of kind `Io`.
+    pub fn open_with_options<T: AsRef<OsStr> + ?Sized>(port: &T, options: &::OpenOptions) -> ::Result<Self> {
+        let device = port.as_ref().to_string_lossy().into_owned();
+
+        let name = match device_path(port.as_ref()) {
+            Some(name) => name,
+            None => {
+                return Err(::Error::new(::ErrorKind::InvalidInput, "invalid COM port name").with_device(device).with_operation("open"));
+            }
+        };
+
+        let access = match options.access_mode() {
+            ::Access::ReadWrite => GENERIC_READ | GENERIC_WRITE,
+            ::Access::ReadOnly => GENERIC_READ,
+            ::Access::WriteOnly => GENERIC_WRITE
+        };
+
+        let share_mode = if options.is_shared() { FILE_SHARE_READ | FILE_SHARE_WRITE } else { 0 };
 
         let handle = unsafe {
-            CreateFileW(name.as_ptr(), GENERIC_READ | GENERIC_WRITE, 0, ptr::null_mut(), OPEN_EXISTING, FILE_ATTRIBUTE_NORMAL, 0 as HANDLE)
+            CreateFileW(name.as_ptr(), access, share_mode, ptr::null_mut(), OPEN_EXISTING, FILE_ATTRIBUTE_NORMAL | FILE_FLAG_OVERLAPPED, 0 as HANDLE)
         };
 
         let timeout = Duration::from_millis(100);
 
         if handle != INVALID_HANDLE_VALUE {
             let mut port = COMPort {
-                handle: handle,
-                timeout: timeout
+                handle,
+                timeout,
+                device: device.clone(),
+                original: None,
+                low_latency: false
             };
 
-            try!(port.set_timeout(timeout));
+            // the DCB includes the DTR and RTS control modes, which Windows can't report otherwise
+            if options.restores_on_close() {
+                port.original = Some(port.snapshot().map_err(|err| err.with_operation("open"))?);
+            }
+
+            port.set_timeout(timeout).map_err(|err| err.with_operation("open"))?;
+
+            if options.dtr_level().is_some() || options.rts_level().is_some() {
+                let mut settings = port.read_settings().map_err(|err| err.with_operation("open"))?;
+
+                match options.dtr_level() {
+                    Some(true) => settings.inner.fBits = (settings.inner.fBits & !fDtrControl) | DTR_CONTROL_ENABLE,
+                    Some(false) => settings.inner.fBits = (settings.inner.fBits & !fDtrControl) | DTR_CONTROL_DISABLE,
+                    None => ()
+                }
+
+                match options.rts_level() {
+                    Some(true) => settings.inner.fBits = (settings.inner.fBits & !fRtsControl) | RTS_CONTROL_ENABLE,
+                    Some(false) => settings.inner.fBits = (settings.inner.fBits & !fRtsControl) | RTS_CONTROL_DISABLE,
+                    None => ()
+                }
+
+                port.write_settings(&settings).map_err(|err| err.with_operation("open"))?;
+            }
+
+            if options.waits_for_carrier() {
+                while !port.read_cd().map_err(|err| err.with_operation("open"))? {
+                    port.wait_signal_change(&[::ModemSignal::Cd]).map_err(|err| err.with_operation("open"))?;
+                }
+            }
+
             Ok(port)
         }
         else {
-            Err(super::error::last_os_error())
+            Err(super::error::last_open_error().with_device(device).with_operation("open"))
         }
     }
 
+    /// Closes the port.
+    ///
+    /// If the port was opened with `OpenOptions::restore_on_close()`, its original settings,
+    /// timeout, and control signals are restored first. Dropping the port does the same, but
+    /// ignores errors, so a tool that must not leave a console misconfigured should call `close()`
+    /// and report its result.
+    ///
+    /// ## Errors
+    ///
+    /// This function returns an error if the original state could not be restored:
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn close(mut self) -> ::Result<()> {
+        match self.original.take() {
+            Some(original) => self.restore(&original).map_err(|err| err.with_operation("close")),
+            None => Ok(())
+        }
+    }
+
+    /// Attaches the device name and failed operation to an error.
+    fn error(&self, err: ::Error, operation: &'static str) -> ::Error {
+        err.with_device(self.device.clone()).with_operation(operation)
+    }
+
+    /// Attaches the device name and failed operation to an I/O error.
+    ///
+    /// Errors that indicate the device was unplugged, such as `ERROR_DEVICE_NOT_CONNECTED` or
+    /// `ERROR_OPERATION_ABORTED`, are reported as `NoDevice`.
+    fn io_error(&self, err: io::Error, operation: &'static str) -> io::Error {
+        let err = match err.raw_os_error() {
+            Some(errno) => super::error::from_raw_os_error(errno),
+            None => ::Error::from(err)
+        };
+
+        self.error(::disconnected(err), operation).into()
+    }
+
+    /// Starts an overlapped operation and waits for it to complete.
+    ///
+    /// The handle is opened for overlapped I/O, so that a read waiting for input doesn't block a
+    /// write from another thread. Returns the number of bytes transferred.
+    fn overlapped<F>(&self, start: F) -> io::Result<DWORD>
+        where F: FnOnce(LPOVERLAPPED) -> BOOL
+    {
+        let event = unsafe { CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
+
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+        overlapped.hEvent = event;
+
+        let mut len: DWORD = 0;
+        let failed = (start(&mut overlapped) == 0 && unsafe { GetLastError() } != ERROR_IO_PENDING)
+            || unsafe { GetOverlappedResult(self.handle, &mut overlapped, &mut len, TRUE) } == 0;
+
+        let result = if failed { Err(io::Error::last_os_error()) } else { Ok(len) };
+
+        unsafe { CloseHandle(event) };
+
+        result
+    }
+
     fn escape_comm_function(&mut self, function: DWORD) -> ::Result<()> {
         match unsafe { EscapeCommFunction(self.handle, function) } {
-            0 => Err(super::error::last_os_error()),
+            0 => Err(self.error(super::error::last_os_error(), "set control signal")),
             _ => Ok(())
         }
     }
 
+    /// Acknowledges communication errors and returns the errors that occurred.
+    ///
+    /// The result is a mask of the `CE_*` flags documented for `ClearCommError()`, such as
+    /// `CE_FRAME` (`0x0008`), `CE_OVERRUN` (`0x0002`), and `CE_RXPARITY` (`0x0004`). It's zero if no
+    /// errors occurred. When `COMSettings::set_abort_on_error()` is enabled, this must be called
+    /// after an error before reads and writes can resume.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    pub fn clear_errors(&mut self) -> ::Result<u32> {
+        let mut errors: DWORD = 0;
+
+        match unsafe { ClearCommError(self.handle, &mut errors, ptr::null_mut()) } {
+            0 => Err(self.error(super::error::last_os_error(), "clear errors")),
+            _ => Ok(errors as u32)
+        }
+    }
+
     fn read_pin(&mut self, pin: DWORD) -> ::Result<bool> {
-        let mut status: DWORD = unsafe { mem::uninitialized() };
+        let mut status: DWORD = 0;
 
         match unsafe { GetCommModemStatus(self.handle, &mut status) } {
-            0 => Err(super::error::last_os_error()),
+            0 => Err(self.error(super::error::last_os_error(), "read control signal")),
             _ => Ok(status & pin != 0)
         }
     }
 }
 
+/// Returns the null-terminated path that opens `port`, or `None` if `port` isn't a valid name.
+///
+/// Paths that are already in the Win32 device namespace (`\\.\` or `\\?\`) are used as given.
+/// Other names are prefixed with `\\.\`, which is required for `COM10` and above.
+pub(crate) fn device_path(port: &OsStr) -> Option<Vec<u16>> {
+    const PREFIXES: [&str; 2] = ["\\\\.\\", "\\\\?\\"];
+
+    let name: Vec<u16> = port.encode_wide().collect();
+
+    if name.contains(&0) {
+        return None;
+    }
+
+    for prefix in &PREFIXES {
+        let prefix: Vec<u16> = OsStr::new(prefix).encode_wide().collect();
+
+        if name.starts_with(&prefix) {
+            if name.len() == prefix.len() {
+                return None;
+            }
+
+            let mut path = name;
+            path.push(0);
+            return Some(path);
+        }
+    }
+
+    let invalid = |c: u16| c < 0x20 || "\\/:*?\"<>|".encode_utf16().any(|i| i == c);
+
+    if name.is_empty() || name.iter().any(|&c| invalid(c)) {
+        return None;
+    }
+
+    let mut path: Vec<u16> = OsStr::new(PREFIXES[0]).encode_wide().collect();
+    path.extend(name);
+    path.push(0);
+    Some(path)
+}
+
 impl Drop for COMPort {
     fn drop(&mut self) {
+        if let Some(original) = self.original.take() {
+            let _ = self.restore(&original);
+        }
+
         unsafe {
             CloseHandle(self.handle);
         }
@@ -93,43 +310,92 @@ impl Drop for COMPort {
 
 impl AsRawHandle for COMPort {
     fn as_raw_handle(&self) -> RawHandle {
-        unsafe {
-            mem::transmute(self.handle)
-        }
+        self.handle as RawHandle
     }
 }
 
 impl io::Read for COMPort {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut len: DWORD = 0;
+        (&*self).read(buf)
+    }
+}
 
-        match unsafe { ReadFile(self.handle, buf.as_mut_ptr() as *mut c_void, buf.len() as DWORD, &mut len, ptr::null_mut()) } {
-            0 => Err(io::Error::last_os_error()),
-            _ => {
-                if len != 0 {
-                    Ok(len as usize)
-                }
-                else {
-                    Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"))
-                }
-            }
+impl io::Write for COMPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// A shared reference can be used for reading and writing without exclusive access to the port.
+///
+/// The handle is opened for overlapped I/O, and each call waits for its own operation to
+/// complete, so a read that's waiting for input doesn't delay a write from another thread.
+impl io::Read for &COMPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let handle = self.handle;
+
+        let result = self.overlapped(|overlapped| unsafe {
+            ReadFile(handle, buf.as_mut_ptr() as *mut c_void, buf.len() as DWORD, ptr::null_mut(), overlapped)
+        });
+
+        match result {
+            Ok(0) => Err(self.io_error(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"), "read")),
+            Ok(len) => Ok(len as usize),
+            Err(err) => Err(self.io_error(err, "read"))
         }
     }
 }
 
-impl io::Write for COMPort {
+/// Large buffers are written in chunks of at most 64 KiB, since some drivers fail larger writes
+/// with `ERROR_NO_SYSTEM_RESOURCES` or `ERROR_INVALID_PARAMETER`. If a driver rejects a chunk with
+/// one of these errors, the chunk size is halved until it's accepted. A write continues with the
+/// next chunk until the whole buffer has been written or the port's timeout elapses.
+impl io::Write for &COMPort {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let mut len: DWORD = 0;
+        let deadline = Instant::now() + self.timeout;
+        let mut chunk_size = MAX_WRITE_CHUNK;
+        let mut written = 0;
+
+        while written < buf.len() && (written == 0 || Instant::now() < deadline) {
+            let chunk = &buf[written..cmp::min(buf.len(), written + chunk_size)];
+            let handle = self.handle;
+
+            let result = self.overlapped(|overlapped| unsafe {
+                WriteFile(handle, chunk.as_ptr() as *mut c_void, chunk.len() as DWORD, ptr::null_mut(), overlapped)
+            });
+
+            let err = match result {
+                Ok(len) => {
+                    written += len as usize;
+
+                    if (len as usize) < chunk.len() {
+                        break;
+                    }
 
-        match unsafe { WriteFile(self.handle, buf.as_ptr() as *mut c_void, buf.len() as DWORD, &mut len, ptr::null_mut()) } {
-            0 => Err(io::Error::last_os_error()),
-            _ => Ok(len as usize)
+                    continue;
+                },
+                Err(err) => err
+            };
+
+            match err.raw_os_error() {
+                Some(ERROR_INVALID_PARAMETER) | Some(ERROR_NOT_ENOUGH_MEMORY) | Some(ERROR_NO_SYSTEM_RESOURCES) if chunk_size > MIN_WRITE_CHUNK => {
+                    chunk_size /= 2;
+                },
+                _ if written > 0 => break,
+                _ => return Err(self.io_error(err, "write"))
+            }
         }
+
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
         match unsafe { FlushFileBuffers(self.handle) } {
-            0 => Err(io::Error::last_os_error()),
+            0 => Err(self.io_error(io::Error::last_os_error(), "flush")),
             _ => Ok(())
         }
     }
@@ -142,7 +408,7 @@ impl SerialDevice for COMPort {
         let mut dcb = DCB::new();
 
         match unsafe { GetCommState(self.handle, &mut dcb) } {
-            0 => Err(super::error::last_os_error()),
+            0 => Err(self.error(super::error::last_os_error(), "read settings")),
             _ => Ok(COMSettings { inner: dcb })
 
         }
@@ -150,7 +416,7 @@ impl SerialDevice for COMPort {
 
     fn write_settings(&mut self, settings: &COMSettings) -> ::Result<()> {
         match unsafe { SetCommState(self.handle, &settings.inner) } {
-            0 => Err(super::error::last_os_error()),
+            0 => Err(self.error(super::error::last_os_error(), "write settings")),
             _ => Ok(())
         }
     }
@@ -162,22 +428,38 @@ impl SerialDevice for COMPort {
     fn set_timeout(&mut self, timeout: Duration) -> ::Result<()> {
         let milliseconds = timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000;
 
+        // MAXDWORD for both the interval and the multiplier completes a read as soon as any data
+        // has been received, and otherwise waits for the constant. A MAXDWORD interval alone
+        // returns immediately.
+        let (interval, multiplier) = match (self.low_latency, milliseconds) {
+            (false, _) => (0, 0),
+            (true, 0) => (MAXDWORD, 0),
+            (true, _) => (MAXDWORD, MAXDWORD)
+        };
+
         let timeouts = COMMTIMEOUTS {
-            ReadIntervalTimeout: 0,
-            ReadTotalTimeoutMultiplier: 0,
+            ReadIntervalTimeout: interval,
+            ReadTotalTimeoutMultiplier: multiplier,
             ReadTotalTimeoutConstant: milliseconds as DWORD,
             WriteTotalTimeoutMultiplier: 0,
             WriteTotalTimeoutConstant: 0
         };
 
         if unsafe { SetCommTimeouts(self.handle, &timeouts) } == 0 {
-            return Err(super::error::last_os_error());
+            return Err(self.error(super::error::last_os_error(), "set timeout"));
         }
 
         self.timeout = timeout;
         Ok(())
     }
 
+    fn discard_input(&mut self) -> ::Result<()> {
+        match unsafe { PurgeComm(self.handle, PURGE_RXCLEAR) } {
+            0 => Err(self.error(super::error::last_os_error(), "discard input")),
+            _ => Ok(())
+        }
+    }
+
     fn set_rts(&mut self, level: bool) -> ::Result<()> {
         if level {
             self.escape_comm_function(SETRTS)
@@ -211,15 +493,332 @@ impl SerialDevice for COMPort {
     fn read_cd(&mut self) -> ::Result<bool> {
         self.read_pin(MS_RLSD_ON)
     }
+
+    fn send_break(&mut self, duration: Duration) -> ::Result<()> {
+        if unsafe { SetCommBreak(self.handle) } == 0 {
+            return Err(self.error(super::error::last_os_error(), "send break"));
+        }
+
+        thread::sleep(duration);
+
+        match unsafe { ClearCommBreak(self.handle) } {
+            0 => Err(self.error(super::error::last_os_error(), "send break")),
+            _ => Ok(())
+        }
+    }
+
+    fn set_latency_hint(&mut self, hint: ::LatencyHint) -> ::Result<()> {
+        self.low_latency = hint == ::LatencyHint::Low;
+
+        let timeout = self.timeout;
+        self.set_timeout(timeout).map_err(|err| err.with_operation("set latency hint"))
+    }
+
+    fn check(&mut self) -> ::Result<()> {
+        let mut dcb = DCB::new();
+
+        // fails with ERROR_DEVICE_NOT_CONNECTED or ERROR_BAD_COMMAND once a USB adapter is unplugged
+        match unsafe { GetCommState(self.handle, &mut dcb) } {
+            0 => Err(self.error(::disconnected(super::error::last_os_error()), "check")),
+            _ => Ok(())
+        }
+    }
+
+    fn wait_signal_change(&mut self, signals: &[::ModemSignal]) -> ::Result<::ModemSignal> {
+        if signals.is_empty() {
+            let err = ::Error::new(::ErrorKind::InvalidInput, "no modem signals to wait for");
+            return Err(self.error(err, "wait for signal change"));
+        }
+
+        let mask = signals.iter().fold(0, |mask, &signal| mask | signal_event(signal));
+
+        if unsafe { SetCommMask(self.handle, mask) } == 0 {
+            return Err(self.error(super::error::last_os_error(), "wait for signal change"));
+        }
+
+        let result = loop {
+            let mut events: DWORD = 0;
+            let handle = self.handle;
+
+            if let Err(err) = self.overlapped(|overlapped| unsafe { WaitCommEvent(handle, &mut events, overlapped) }) {
+                let err = super::error::from_raw_os_error(err.raw_os_error().unwrap_or(0));
+                break Err(self.error(err, "wait for signal change"));
+            }
+
+            if let Some(&signal) = signals.iter().find(|&&signal| events & signal_event(signal) != 0) {
+                break Ok(signal);
+            }
+        };
+
+        unsafe {
+            SetCommMask(self.handle, 0);
+        }
+
+        result
+    }
+}
+
+fn signal_event(signal: ::ModemSignal) -> DWORD {
+    match signal {
+        ::ModemSignal::Cts => EV_CTS,
+        ::ModemSignal::Dsr => EV_DSR,
+        ::ModemSignal::Ri => EV_RING,
+        ::ModemSignal::Cd => EV_RLSD
+    }
 }
 
 
+/// The complete timeout model of a COM port (`COMMTIMEOUTS`), in milliseconds.
+///
+/// A read completes when the buffer is full, when `read_interval_timeout` elapses between two
+/// received bytes, or when the total timeout of `read_total_timeout_multiplier` times the number
+/// of requested bytes plus `read_total_timeout_constant` elapses. Write timeouts are computed the
+/// same way. A value of zero disables that timeout, and `u32::MAX` has the special meanings
+/// described in the documentation of `COMMTIMEOUTS`.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct CommTimeouts {
+    /// Maximum time between two received bytes (`ReadIntervalTimeout`).
+    pub read_interval_timeout: u32,
+
+    /// Time per requested byte added to the total read timeout (`ReadTotalTimeoutMultiplier`).
+    pub read_total_timeout_multiplier: u32,
+
+    /// Time added to the total read timeout (`ReadTotalTimeoutConstant`).
+    pub read_total_timeout_constant: u32,
+
+    /// Time per written byte added to the total write timeout (`WriteTotalTimeoutMultiplier`).
+    pub write_total_timeout_multiplier: u32,
+
+    /// Time added to the total write timeout (`WriteTotalTimeoutConstant`).
+    pub write_total_timeout_constant: u32
+}
+
+impl CommTimeouts {
+    fn from_raw(raw: &COMMTIMEOUTS) -> Self {
+        CommTimeouts {
+            read_interval_timeout: raw.ReadIntervalTimeout,
+            read_total_timeout_multiplier: raw.ReadTotalTimeoutMultiplier,
+            read_total_timeout_constant: raw.ReadTotalTimeoutConstant,
+            write_total_timeout_multiplier: raw.WriteTotalTimeoutMultiplier,
+            write_total_timeout_constant: raw.WriteTotalTimeoutConstant
+        }
+    }
+
+    fn to_raw(self) -> COMMTIMEOUTS {
+        COMMTIMEOUTS {
+            ReadIntervalTimeout: self.read_interval_timeout,
+            ReadTotalTimeoutMultiplier: self.read_total_timeout_multiplier,
+            ReadTotalTimeoutConstant: self.read_total_timeout_constant,
+            WriteTotalTimeoutMultiplier: self.write_total_timeout_multiplier,
+            WriteTotalTimeoutConstant: self.write_total_timeout_constant
+        }
+    }
+}
+
+/// Direct access to the timeouts of a COM port.
+///
+/// The portable model of a single timeout can't express every read behavior that Windows
+/// supports, such as completing a read once the line has been idle for an interval. This
+/// extension trait reads and writes the port's `COMMTIMEOUTS` as they are:
+///
+/// ```no_run
+/// use serial::windows::{COMPort,CommTimeouts,CommTimeoutsExt};
+///
+/// let mut port = COMPort::open("COM3").unwrap();
+///
+/// // complete reads after 20 ms of silence, or after one second in total
+/// port.set_comm_timeouts(&CommTimeouts {
+///     read_interval_timeout: 20,
+///     read_total_timeout_constant: 1000,
+///     ..Default::default()
+/// }).unwrap();
+/// ```
+///
+/// The timeouts are replaced by `set_timeout()`, `set_latency_hint()`, and by reads with
+/// `read_with_timeout()`. `timeout()` keeps returning the timeout that was set last with
+/// `set_timeout()`, which also bounds how long a write keeps retrying.
+pub trait CommTimeoutsExt {
+    /// Returns the port's current timeouts.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error.
+    fn comm_timeouts(&self) -> ::Result<CommTimeouts>;
+
+    /// Sets the port's timeouts.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device was disconnected.
+    /// * `Io` for any other type of I/O error, including if the driver rejects the timeouts.
+    fn set_comm_timeouts(&mut self, timeouts: &CommTimeouts) -> ::Result<()>;
+}
+
+impl CommTimeoutsExt for COMPort {
+    fn comm_timeouts(&self) -> ::Result<CommTimeouts> {
+        let mut raw: COMMTIMEOUTS = unsafe { mem::zeroed() };
+
+        match unsafe { GetCommTimeouts(self.handle, &mut raw) } {
+            0 => Err(self.error(super::error::last_os_error(), "read timeouts")),
+            _ => Ok(CommTimeouts::from_raw(&raw))
+        }
+    }
+
+    fn set_comm_timeouts(&mut self, timeouts: &CommTimeouts) -> ::Result<()> {
+        let raw = timeouts.to_raw();
+
+        match unsafe { SetCommTimeouts(self.handle, &raw) } {
+            0 => Err(self.error(super::error::last_os_error(), "set timeout")),
+            _ => Ok(())
+        }
+    }
+}
+
+
+/// Hardware handshaking lines used for flow control in one direction.
+///
+/// For input, the handshaking lines are outputs that are deasserted to ask the other device to
+/// pause when the receive buffer is nearly full. For output, they're inputs that must be asserted
+/// by the other device before data is transmitted.
+#[derive(Debug,Copy,Clone,Default,PartialEq,Eq,Hash)]
+pub struct Handshake {
+    /// Use the RTS and CTS signals.
+    pub rts_cts: bool,
+
+    /// Use the DTR and DSR signals.
+    pub dtr_dsr: bool
+}
+
 /// Serial port settings for COM ports.
 #[derive(Copy,Clone,Debug)]
 pub struct COMSettings {
     inner: DCB
 }
 
+impl COMSettings {
+    pub(crate) fn from_raw(inner: DCB) -> Self {
+        COMSettings { inner }
+    }
+
+    /// Returns a reference to the underlying `DCB` structure.
+    ///
+    /// This is a platform-specific escape hatch for settings that aren't modeled by
+    /// `SerialPortSettings`.
+    pub fn as_raw(&self) -> &DCB {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying `DCB` structure.
+    ///
+    /// This is a platform-specific escape hatch for settings that aren't modeled by
+    /// `SerialPortSettings`. Changes take effect when the settings are written to a port. The
+    /// `DCBlength` field must not be modified.
+    pub fn as_raw_mut(&mut self) -> &mut DCB {
+        &mut self.inner
+    }
+
+    /// Returns the hardware handshaking used to pause input.
+    pub fn input_handshake(&self) -> Handshake {
+        Handshake {
+            rts_cts: self.inner.fBits & fRtsControl == RTS_CONTROL_HANDSHAKE,
+            dtr_dsr: self.inner.fBits & fDtrControl == DTR_CONTROL_HANDSHAKE
+        }
+    }
+
+    /// Sets the hardware handshaking used to pause input.
+    ///
+    /// This controls `fRtsControl` and `fDtrControl`. A line that's no longer used for
+    /// handshaking is left asserted.
+    pub fn set_input_handshake(&mut self, handshake: Handshake) {
+        let rts = self.inner.fBits & fRtsControl;
+        let dtr = self.inner.fBits & fDtrControl;
+
+        let rts = match (handshake.rts_cts, rts) {
+            (true, _) => RTS_CONTROL_HANDSHAKE,
+            (false, RTS_CONTROL_HANDSHAKE) => RTS_CONTROL_ENABLE,
+            (false, rts) => rts
+        };
+
+        let dtr = match (handshake.dtr_dsr, dtr) {
+            (true, _) => DTR_CONTROL_HANDSHAKE,
+            (false, DTR_CONTROL_HANDSHAKE) => DTR_CONTROL_ENABLE,
+            (false, dtr) => dtr
+        };
+
+        self.inner.fBits = (self.inner.fBits & !(fRtsControl | fDtrControl)) | rts | dtr;
+    }
+
+    /// Returns the hardware handshaking that must allow output before data is transmitted.
+    pub fn output_handshake(&self) -> Handshake {
+        Handshake {
+            rts_cts: self.inner.fBits & fOutxCtsFlow != 0,
+            dtr_dsr: self.inner.fBits & fOutxDsrFlow != 0
+        }
+    }
+
+    /// Sets the hardware handshaking that must allow output before data is transmitted.
+    ///
+    /// This controls `fOutxCtsFlow` and `fOutxDsrFlow`. Output can be made to wait for both CTS
+    /// and DSR.
+    pub fn set_output_handshake(&mut self, handshake: Handshake) {
+        self.inner.fBits &= !(fOutxCtsFlow | fOutxDsrFlow);
+
+        if handshake.rts_cts {
+            self.inner.fBits |= fOutxCtsFlow;
+        }
+
+        if handshake.dtr_dsr {
+            self.inner.fBits |= fOutxDsrFlow;
+        }
+    }
+
+    /// Returns the byte that replaces bytes received with a parity error, if enabled.
+    pub fn error_char(&self) -> Option<u8> {
+        if self.inner.fBits & fErrorChar != 0 {
+            Some(self.inner.ErrorChar as u8)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Sets the byte that replaces bytes received with a parity error.
+    ///
+    /// This marks bad bytes in the input stream, similar to `PARMRK` on POSIX systems, except
+    /// that the received byte itself is lost. `None` passes bytes with parity errors through
+    /// unchanged. Replacement only occurs when parity checking is enabled.
+    pub fn set_error_char(&mut self, error_char: Option<u8>) {
+        match error_char {
+            Some(c) => {
+                self.inner.fBits |= fErrorChar;
+                self.inner.ErrorChar = c as c_char;
+            },
+            None => {
+                self.inner.fBits &= !fErrorChar;
+            }
+        }
+    }
+
+    /// Returns whether reads and writes are aborted when an error occurs.
+    pub fn is_abort_on_error(&self) -> bool {
+        self.inner.fBits & fAbortOnError != 0
+    }
+
+    /// Sets whether reads and writes are aborted when an error occurs.
+    ///
+    /// When enabled, a framing, parity, or overrun error aborts all pending reads and writes, and
+    /// further I/O fails until the error is acknowledged with `COMPort::clear_errors()`.
+    pub fn set_abort_on_error(&mut self, abort_on_error: bool) {
+        if abort_on_error {
+            self.inner.fBits |= fAbortOnError;
+        }
+        else {
+            self.inner.fBits &= !fAbortOnError;
+        }
+    }
+}
+
 impl SerialPortSettings for COMSettings {
     fn baud_rate(&self) -> Option<::BaudRate> {
         match self.inner.BaudRate {
@@ -270,7 +869,7 @@ impl SerialPortSettings for COMSettings {
     }
 
     fn flow_control(&self) -> Option<::FlowControl> {
-        if self.inner.fBits & (fOutxCtsFlow | fRtsControl) != 0 {
+        if self.inner.fBits & fOutxCtsFlow != 0 || self.inner.fBits & fRtsControl == RTS_CONTROL_HANDSHAKE {
             Some(::FlowHardware)
         }
         else if self.inner.fBits & (fOutX | fInX) != 0 {
@@ -281,6 +880,30 @@ impl SerialPortSettings for COMSettings {
         }
     }
 
+    fn input_flow_control(&self) -> Option<::FlowControl> {
+        if self.inner.fBits & fRtsControl == RTS_CONTROL_HANDSHAKE {
+            Some(::FlowHardware)
+        }
+        else if self.inner.fBits & fInX != 0 {
+            Some(::FlowSoftware)
+        }
+        else {
+            Some(::FlowNone)
+        }
+    }
+
+    fn output_flow_control(&self) -> Option<::FlowControl> {
+        if self.inner.fBits & fOutxCtsFlow != 0 {
+            Some(::FlowHardware)
+        }
+        else if self.inner.fBits & fOutX != 0 {
+            Some(::FlowSoftware)
+        }
+        else {
+            Some(::FlowNone)
+        }
+    }
+
     fn set_baud_rate(&mut self, baud_rate: ::BaudRate) -> ::Result<()> {
         self.inner.BaudRate = match baud_rate {
             ::Baud110      => CBR_110,
@@ -335,9 +958,75 @@ impl SerialPortSettings for COMSettings {
                 self.inner.fBits |= fOutX | fInX;
             },
             ::FlowHardware => {
-                self.inner.fBits |= fOutxCtsFlow | fRtsControl;
+                self.inner.fBits = (self.inner.fBits & !fRtsControl) | fOutxCtsFlow | RTS_CONTROL_HANDSHAKE;
                 self.inner.fBits &= !(fOutX | fInX);
             }
         }
     }
+
+    fn set_flow_control_directions(&mut self, input: ::FlowControl, output: ::FlowControl) -> ::Result<()> {
+        self.inner.fBits &= !(fOutxCtsFlow | fRtsControl | fOutX | fInX);
+
+        match input {
+            ::FlowNone => (),
+            ::FlowSoftware => self.inner.fBits |= fInX,
+            ::FlowHardware => self.inner.fBits |= RTS_CONTROL_HANDSHAKE
+        }
+
+        match output {
+            ::FlowNone => (),
+            ::FlowSoftware => self.inner.fBits |= fOutX,
+            ::FlowHardware => self.inner.fBits |= fOutxCtsFlow
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::{device_path,CommTimeouts};
+
+    fn path(port: &str) -> Option<String> {
+        device_path(OsStr::new(port)).map(|mut path| {
+            assert_eq!(path.pop(), Some(0));
+            String::from_utf16(&path).unwrap()
+        })
+    }
+
+    #[test]
+    fn device_path_prefixes_port_names() {
+        assert_eq!(path("COM1").as_deref(), Some(r"\\.\COM1"));
+        assert_eq!(path("COM12").as_deref(), Some(r"\\.\COM12"));
+        assert_eq!(path("CNCA0").as_deref(), Some(r"\\.\CNCA0"));
+    }
+
+    #[test]
+    fn device_path_keeps_device_namespace_paths() {
+        assert_eq!(path(r"\\.\COM12").as_deref(), Some(r"\\.\COM12"));
+        assert_eq!(path(r"\\?\CNCB0").as_deref(), Some(r"\\?\CNCB0"));
+    }
+
+    #[test]
+    fn device_path_rejects_invalid_names() {
+        for name in &["", r"\\.\", "COM1\0", r"C:\COM1", "COM1:", "dev/COM1"] {
+            assert_eq!(path(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn comm_timeouts_round_trip_through_raw() {
+        let timeouts = CommTimeouts {
+            read_interval_timeout: u32::MAX,
+            read_total_timeout_multiplier: 1,
+            read_total_timeout_constant: 2,
+            write_total_timeout_multiplier: 3,
+            write_total_timeout_constant: 4
+        };
+
+        assert_eq!(CommTimeouts::from_raw(&timeouts.to_raw()), timeouts);
+    }
 }
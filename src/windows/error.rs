@@ -8,16 +8,43 @@ use self::libc::{c_void,c_int};
 const ERROR_FILE_NOT_FOUND: c_int = 2;
 const ERROR_PATH_NOT_FOUND: c_int = 3;
 const ERROR_ACCESS_DENIED: c_int = 5;
+const ERROR_BAD_COMMAND: c_int = 22;
+const ERROR_GEN_FAILURE: c_int = 31;
+const ERROR_SHARING_VIOLATION: c_int = 32;
+const ERROR_OPERATION_ABORTED: c_int = 995;
+const ERROR_DEVICE_NOT_CONNECTED: c_int = 1167;
 
 pub fn last_os_error() -> ::Error {
+    from_raw_os_error(errno())
+}
+
+/// Returns the last OS error after failing to open a device.
+///
+/// Windows reports `ERROR_ACCESS_DENIED` when a COM port is already open in another process, so
+/// it's reported as `Busy` here rather than `NoDevice`.
+pub fn last_open_error() -> ::Error {
     let errno = errno();
 
+    match errno {
+        ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION => {
+            ::Error::new(::ErrorKind::Busy, error_string(errno).trim()).with_source(io::Error::from_raw_os_error(errno))
+        },
+        _ => from_raw_os_error(errno)
+    }
+}
+
+pub fn from_raw_os_error(errno: i32) -> ::Error {
     let kind = match errno {
         ERROR_FILE_NOT_FOUND | ERROR_PATH_NOT_FOUND | ERROR_ACCESS_DENIED => ::ErrorKind::NoDevice,
         _ => ::ErrorKind::Io(io::ErrorKind::Other)
     };
 
-    ::Error::new(kind, error_string(errno).trim())
+    ::Error::new(kind, error_string(errno).trim()).with_source(io::Error::from_raw_os_error(errno))
+}
+
+/// Returns `true` if `errno` indicates that the device was disconnected.
+pub fn is_disconnection(errno: i32) -> bool {
+    matches!(errno, ERROR_FILE_NOT_FOUND | ERROR_BAD_COMMAND | ERROR_GEN_FAILURE | ERROR_OPERATION_ABORTED | ERROR_DEVICE_NOT_CONNECTED)
 }
 
 // the rest of this module is borrowed from libstd
@@ -33,7 +60,7 @@ fn error_string(errnum: i32) -> String {
 
     use super::ffi::{DWORD,LPWSTR,LPVOID,WCHAR};
 
-    #[link_name = "kernel32"]
+    #[link(name = "kernel32")]
     extern "system" {
         fn FormatMessageW(flags: DWORD,
                           lpSrc: LPVOID,
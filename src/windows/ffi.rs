@@ -1,4 +1,5 @@
 #![allow(non_snake_case,non_camel_case_types,non_upper_case_globals,dead_code)]
+#![allow(clippy::upper_case_acronyms,clippy::new_without_default)]
 
 extern crate libc;
 
@@ -19,13 +20,24 @@ pub type LPCWSTR = *const WCHAR;
 pub type LPWSTR = *mut WCHAR;
 
 pub type HANDLE = *mut LPVOID;
+pub type ULONG_PTR = usize;
+
+pub const MAXDWORD: DWORD = 0xffffffff;
+
+pub const TRUE: BOOL = 1;
+pub const FALSE: BOOL = 0;
 
 pub const GENERIC_READ: DWORD = 0x80000000;
 pub const GENERIC_WRITE: DWORD = 0x40000000;
+pub const FILE_SHARE_READ: DWORD = 0x00000001;
+pub const FILE_SHARE_WRITE: DWORD = 0x00000002;
 pub const OPEN_EXISTING: DWORD = 3;
 pub const FILE_ATTRIBUTE_NORMAL: DWORD = 0x80;
+pub const FILE_FLAG_OVERLAPPED: DWORD = 0x40000000;
 pub const INVALID_HANDLE_VALUE: HANDLE = !0 as HANDLE;
 
+pub const ERROR_IO_PENDING: DWORD = 997;
+
 #[repr(C)]
 pub struct SECURITY_ATTRIBUTES {
     pub nLength: DWORD,
@@ -99,6 +111,17 @@ pub const fRtsControl:       DWORD = 0x00003000;
 pub const fAbortOnError:     DWORD = 0x00004000;
 pub const fDummy2:           DWORD = 0xFFFF8000;
 
+// fDtrControl values
+pub const DTR_CONTROL_DISABLE:   DWORD = 0x00000000;
+pub const DTR_CONTROL_ENABLE:    DWORD = 0x00000010;
+pub const DTR_CONTROL_HANDSHAKE: DWORD = 0x00000020;
+
+// fRtsControl values
+pub const RTS_CONTROL_DISABLE:   DWORD = 0x00000000;
+pub const RTS_CONTROL_ENABLE:    DWORD = 0x00001000;
+pub const RTS_CONTROL_HANDSHAKE: DWORD = 0x00002000;
+pub const RTS_CONTROL_TOGGLE:    DWORD = 0x00003000;
+
 // Parity values
 pub const NOPARITY:    BYTE = 0;
 pub const ODDPARITY:   BYTE = 1;
@@ -129,12 +152,24 @@ pub const CLRDTR:   DWORD = 6;
 pub const SETBREAK: DWORD = 8;
 pub const CLRBREAK: DWORD = 9;
 
+// PurgeComm flags
+pub const PURGE_TXABORT: DWORD = 0x0001;
+pub const PURGE_RXABORT: DWORD = 0x0002;
+pub const PURGE_TXCLEAR: DWORD = 0x0004;
+pub const PURGE_RXCLEAR: DWORD = 0x0008;
+
 // Modem status masks
 pub const MS_CTS_ON:  DWORD = 0x0010;
 pub const MS_DSR_ON:  DWORD = 0x0020;
 pub const MS_RING_ON: DWORD = 0x0040;
 pub const MS_RLSD_ON: DWORD = 0x0080;
 
+// Communication event masks
+pub const EV_CTS:  DWORD = 0x0008;
+pub const EV_DSR:  DWORD = 0x0010;
+pub const EV_RLSD: DWORD = 0x0020;
+pub const EV_RING: DWORD = 0x0100;
+
 #[derive(Copy,Clone,Debug)]
 #[repr(C)]
 pub struct COMMTIMEOUTS {
@@ -145,6 +180,14 @@ pub struct COMMTIMEOUTS {
     pub WriteTotalTimeoutConstant: DWORD
 }
 
+#[derive(Copy,Clone,Debug)]
+#[repr(C)]
+pub struct COMSTAT {
+    pub fBits: DWORD,
+    pub cbInQue: DWORD,
+    pub cbOutQue: DWORD
+}
+
 extern "system" {
     pub fn CreateFileW(lpFileName: LPCWSTR,
                        dwDesiredAccess: DWORD,
@@ -165,13 +208,38 @@ extern "system" {
                      lpNumberOfBytesWritten: LPDWORD,
                      lpOverlapped: LPOVERLAPPED) -> BOOL;
     pub fn FlushFileBuffers(hFile: HANDLE) -> BOOL;
+    pub fn GetOverlappedResult(hFile: HANDLE,
+                               lpOverlapped: LPOVERLAPPED,
+                               lpNumberOfBytesTransferred: LPDWORD,
+                               bWait: BOOL) -> BOOL;
+    pub fn CreateEventW(lpEventAttributes: LPSECURITY_ATTRIBUTES,
+                        bManualReset: BOOL,
+                        bInitialState: BOOL,
+                        lpName: LPCWSTR) -> HANDLE;
 
     pub fn GetCommState(hFile: HANDLE, lpDCB: *mut DCB) -> BOOL;
     pub fn SetCommState(hFile: HANDLE, lpDCB: *const DCB) -> BOOL;
     pub fn GetCommTimeouts(hFile: HANDLE, lpCommTimeouts: *mut COMMTIMEOUTS) -> BOOL;
     pub fn SetCommTimeouts(hFile: HANDLE, lpCommTimeouts: *const COMMTIMEOUTS) -> BOOL;
     pub fn EscapeCommFunction(hFile: HANDLE, dwFunc: DWORD) -> BOOL;
+    pub fn SetCommBreak(hFile: HANDLE) -> BOOL;
+    pub fn ClearCommBreak(hFile: HANDLE) -> BOOL;
     pub fn GetCommModemStatus(hFile: HANDLE, lpModemStat: *mut DWORD) -> BOOL;
+    pub fn PurgeComm(hFile: HANDLE, dwFlags: DWORD) -> BOOL;
+    pub fn SetCommMask(hFile: HANDLE, dwEvtMask: DWORD) -> BOOL;
+    pub fn WaitCommEvent(hFile: HANDLE, lpEvtMask: LPDWORD, lpOverlapped: LPOVERLAPPED) -> BOOL;
+    pub fn ClearCommError(hFile: HANDLE, lpErrors: LPDWORD, lpStat: *mut COMSTAT) -> BOOL;
+
+    pub fn CreateIoCompletionPort(FileHandle: HANDLE,
+                                  ExistingCompletionPort: HANDLE,
+                                  CompletionKey: ULONG_PTR,
+                                  NumberOfConcurrentThreads: DWORD) -> HANDLE;
+    pub fn GetQueuedCompletionStatus(CompletionPort: HANDLE,
+                                     lpNumberOfBytesTransferred: LPDWORD,
+                                     lpCompletionKey: *mut ULONG_PTR,
+                                     lpOverlapped: *mut LPOVERLAPPED,
+                                     dwMilliseconds: DWORD) -> BOOL;
+    pub fn CancelIoEx(hFile: HANDLE, lpOverlapped: LPOVERLAPPED) -> BOOL;
 
     pub fn GetLastError() -> DWORD;
 }
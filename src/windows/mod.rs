@@ -1,5 +1,6 @@
 pub use self::com::*;
+pub use self::ffi::DCB;
 
 mod com;
-mod error;
-mod ffi;
+pub(crate) mod error;
+pub(crate) mod ffi;